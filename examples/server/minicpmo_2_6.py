@@ -49,7 +49,7 @@ completion = client.chat.completions.create(
                 },
                 {
                     "type": "text",
-                    "text": "(<image>./</image>) What is shown in this image? Write a detailed response analyzing the scene.",
+                    "text": "What is shown in this image? Write a detailed response analyzing the scene.",
                 },
             ],
         },
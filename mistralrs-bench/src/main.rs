@@ -6,7 +6,7 @@ use mistralrs_core::{
     parse_isq_value, Constraint, DefaultSchedulerMethod, DeviceLayerMapMetadata, DeviceMapMetadata,
     DeviceMapSetting, DrySamplingParams, IsqType, Loader, LoaderBuilder, MemoryGpuConfig,
     MistralRs, MistralRsBuilder, ModelSelected, NormalRequest, PagedAttentionConfig, Request,
-    RequestMessage, Response, SamplingParams, SchedulerConfig, TokenSource, Usage,
+    RequestMessage, RequestPriority, Response, SamplingParams, SchedulerConfig, TokenSource, Usage,
 };
 use std::sync::Arc;
 use std::{fmt::Display, num::NonZeroUsize};
@@ -84,6 +84,10 @@ fn run_bench(
         tool_choice: None,
         logits_processors: None,
         return_raw_logits: false,
+        chat_template_kwargs: None,
+        continue_final_message: false,
+        conversation: None,
+        priority: RequestPriority::Normal,
     });
 
     let mut usages = Vec::new();
@@ -116,6 +120,10 @@ fn run_bench(
                     }
                     Response::CompletionChunk(_) => unreachable!(),
                     Response::ImageGeneration(_) => unreachable!(),
+                    Response::Transcription(_) => unreachable!(),
+                    Response::Embedding(_) => unreachable!(),
+                    Response::Rerank(_) => unreachable!(),
+                    Response::Classification(_) => unreachable!(),
                     Response::Raw { .. } => unreachable!(),
                 },
                 None => unreachable!("Expected a Done response, got None",),
@@ -255,6 +263,10 @@ fn warmup_run(mistralrs: Arc<MistralRs>) {
         tool_choice: None,
         logits_processors: None,
         return_raw_logits: false,
+        chat_template_kwargs: None,
+        continue_final_message: false,
+        conversation: None,
+        priority: RequestPriority::Normal,
     });
 
     sender
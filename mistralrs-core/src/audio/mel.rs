@@ -0,0 +1,247 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+/// Sample rate expected by Whisper-style audio encoders.
+pub(crate) const SAMPLE_RATE: u32 = 16000;
+pub(crate) const N_FFT: usize = 400;
+pub(crate) const HOP_LENGTH: usize = 160;
+/// Length of one 30 second window, in samples.
+pub(crate) const N_SAMPLES: usize = 30 * SAMPLE_RATE as usize;
+/// Length of one 30 second window, in spectrogram frames.
+pub(crate) const N_FRAMES: usize = N_SAMPLES / HOP_LENGTH;
+
+// Slaney mel scale: linear below 1 kHz, logarithmic above.
+const MEL_F_SP: f64 = 200. / 3.;
+const MEL_MIN_LOG_HZ: f64 = 1000.;
+const MEL_MIN_LOG_MEL: f64 = MEL_MIN_LOG_HZ / MEL_F_SP;
+
+fn mel_logstep() -> f64 {
+    6.4f64.ln() / 27.
+}
+
+fn hz_to_mel(hz: f64) -> f64 {
+    if hz >= MEL_MIN_LOG_HZ {
+        MEL_MIN_LOG_MEL + (hz / MEL_MIN_LOG_HZ).ln() / mel_logstep()
+    } else {
+        hz / MEL_F_SP
+    }
+}
+
+fn mel_to_hz(mel: f64) -> f64 {
+    if mel >= MEL_MIN_LOG_MEL {
+        MEL_MIN_LOG_HZ * (mel_logstep() * (mel - MEL_MIN_LOG_MEL)).exp()
+    } else {
+        mel * MEL_F_SP
+    }
+}
+
+fn linspace(start: f64, end: f64, n: usize) -> Vec<f64> {
+    (0..n)
+        .map(|i| start + (end - start) * i as f64 / (n - 1) as f64)
+        .collect()
+}
+
+/// Slaney-normalized triangular mel filters, as `(n_mels, N_FFT / 2 + 1)` in row-major order.
+fn mel_filters(n_mels: usize) -> Vec<f32> {
+    let n_freqs = N_FFT / 2 + 1;
+    let fft_freqs = linspace(0., SAMPLE_RATE as f64 / 2., n_freqs);
+    let filter_freqs = linspace(
+        hz_to_mel(0.),
+        hz_to_mel(SAMPLE_RATE as f64 / 2.),
+        n_mels + 2,
+    )
+    .into_iter()
+    .map(mel_to_hz)
+    .collect::<Vec<_>>();
+
+    let mut filters = vec![0f32; n_mels * n_freqs];
+    for m in 0..n_mels {
+        let (lower, center, upper) = (filter_freqs[m], filter_freqs[m + 1], filter_freqs[m + 2]);
+        let enorm = 2. / (upper - lower);
+        for (k, &freq) in fft_freqs.iter().enumerate() {
+            let down = (freq - lower) / (center - lower);
+            let up = (upper - freq) / (upper - center);
+            filters[m * n_freqs + k] = (down.min(up).max(0.) * enorm) as f32;
+        }
+    }
+    filters
+}
+
+/// Log-mel spectrogram frontend of Whisper (`WhisperFeatureExtractor`): a periodic Hann window of
+/// `N_FFT` samples every `HOP_LENGTH` samples over audio padded to 30 seconds, power spectrum, mel
+/// filters, then `log10` with the dynamic range clamped to 8 and rescaled to roughly `[-1, 1]`.
+pub(crate) struct WhisperFeatureExtractor {
+    n_mels: usize,
+    filters: Vec<f32>,
+    window: Vec<f32>,
+    cos: Vec<f32>,
+    sin: Vec<f32>,
+}
+
+impl WhisperFeatureExtractor {
+    pub(crate) fn new(n_mels: usize) -> Self {
+        let window = (0..N_FFT)
+            .map(|i| {
+                (0.5 - 0.5 * (2. * std::f64::consts::PI * i as f64 / N_FFT as f64).cos()) as f32
+            })
+            .collect();
+        let (cos, sin) = (0..N_FFT)
+            .map(|i| {
+                let phase = 2. * std::f64::consts::PI * i as f64 / N_FFT as f64;
+                (phase.cos() as f32, phase.sin() as f32)
+            })
+            .unzip();
+        Self {
+            n_mels,
+            filters: mel_filters(n_mels),
+            window,
+            cos,
+            sin,
+        }
+    }
+
+    pub(crate) fn n_mels(&self) -> usize {
+        self.n_mels
+    }
+
+    /// Number of spectrogram frames which cover `num_samples` samples of actual audio.
+    pub(crate) fn num_frames(num_samples: usize) -> usize {
+        num_samples.div_ceil(HOP_LENGTH).min(N_FRAMES)
+    }
+
+    fn power_spectrum(&self, frame: &[f32]) -> Vec<f32> {
+        (0..N_FFT / 2 + 1)
+            .map(|k| {
+                let (mut re, mut im) = (0f32, 0f32);
+                for (n, x) in frame.iter().enumerate() {
+                    let idx = (k * n) % N_FFT;
+                    re += x * self.cos[idx];
+                    im -= x * self.sin[idx];
+                }
+                re * re + im * im
+            })
+            .collect()
+    }
+
+    /// Compute the log-mel features of one 30 second window. `samples` (at `SAMPLE_RATE`) are
+    /// zero-padded or truncated to `N_SAMPLES`. Returns `(n_mels, N_FRAMES)` in row-major order.
+    pub(crate) fn log_mel_spectrogram(&self, samples: &[f32]) -> Vec<f32> {
+        let mut audio = samples[..samples.len().min(N_SAMPLES)].to_vec();
+        audio.resize(N_SAMPLES, 0.);
+
+        // Center the frames with reflect padding.
+        let pad = N_FFT / 2;
+        let mut padded = Vec::with_capacity(N_SAMPLES + 2 * pad);
+        padded.extend((1..=pad).rev().map(|i| audio[i]));
+        padded.extend_from_slice(&audio);
+        padded.extend((1..=pad).map(|i| audio[N_SAMPLES - 1 - i]));
+
+        let n_freqs = N_FFT / 2 + 1;
+        // The final frame is dropped, as in Whisper.
+        let spectra = (0..N_FRAMES)
+            .into_par_iter()
+            .map(|t| {
+                let frame = padded[t * HOP_LENGTH..t * HOP_LENGTH + N_FFT]
+                    .iter()
+                    .zip(&self.window)
+                    .map(|(x, w)| x * w)
+                    .collect::<Vec<_>>();
+                self.power_spectrum(&frame)
+            })
+            .collect::<Vec<_>>();
+
+        let mut mel = vec![0f32; self.n_mels * N_FRAMES];
+        for (t, spectrum) in spectra.iter().enumerate() {
+            for m in 0..self.n_mels {
+                let filter = &self.filters[m * n_freqs..(m + 1) * n_freqs];
+                let energy = filter.iter().zip(spectrum).map(|(f, p)| f * p).sum::<f32>();
+                mel[m * N_FRAMES + t] = energy.max(1e-10).log10();
+            }
+        }
+
+        let max = mel.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        for x in &mut mel {
+            *x = (x.max(max - 8.) + 4.) / 4.;
+        }
+        mel
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mel_filters, WhisperFeatureExtractor, N_FFT, N_FRAMES, SAMPLE_RATE};
+
+    // Reference values from `transformers.audio_utils.mel_filter_bank` and
+    // `WhisperFeatureExtractor` with 80 mel bins.
+
+    #[test]
+    fn mel_filters_match_reference() {
+        let n_freqs = N_FFT / 2 + 1;
+        let filters = mel_filters(80);
+        assert_eq!(filters.len(), 80 * n_freqs);
+        for (m, k, expected) in [
+            (0, 0, 0.),
+            (0, 1, 0.024862594),
+            (1, 2, 0.022871772),
+            (10, 10, 0.019908219),
+            (10, 11, 0.004954375),
+            (10, 12, 0.),
+            (40, 42, 0.005411105),
+            (40, 43, 0.014735566),
+            (40, 44, 0.006518190),
+            (79, 190, 0.002232055),
+            (79, 200, 0.),
+        ] {
+            let value = filters[m * n_freqs + k];
+            assert!(
+                (value - expected).abs() < 1e-7,
+                "filter {m}, bin {k}: {value} vs {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn log_mel_spectrogram_matches_reference() {
+        // 0.1 s of a 440 Hz sine.
+        let samples = (0..SAMPLE_RATE as usize / 10)
+            .map(|i| {
+                0.5 * (2. * std::f64::consts::PI * 440. * i as f64 / SAMPLE_RATE as f64).sin()
+                    as f32
+            })
+            .collect::<Vec<_>>();
+        let extractor = WhisperFeatureExtractor::new(80);
+        let mel = extractor.log_mel_spectrogram(&samples);
+        assert_eq!(mel.len(), 80 * N_FRAMES);
+
+        // The padding is clamped to 8 below the maximum.
+        let floor = -0.561_796_2;
+        for (t, m, expected) in [
+            (0, 0, 0.983_278_8),
+            (0, 10, 1.336_241),
+            (3, 10, 1.348_738),
+            (3, 11, 1.438_203_8),
+            (3, 40, floor),
+            (5, 10, 1.348_738),
+            (11, 10, 0.356_680_2),
+            (11, 79, floor),
+            (100, 10, floor),
+            (N_FRAMES - 1, 0, floor),
+        ] {
+            let value = mel[m * N_FRAMES + t];
+            assert!(
+                (value - expected).abs() < 1e-3,
+                "frame {t}, mel {m}: {value} vs {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn num_frames_is_capped_to_the_window() {
+        assert_eq!(WhisperFeatureExtractor::num_frames(0), 0);
+        assert_eq!(WhisperFeatureExtractor::num_frames(1), 1);
+        assert_eq!(WhisperFeatureExtractor::num_frames(160), 1);
+        assert_eq!(WhisperFeatureExtractor::num_frames(161), 2);
+        assert_eq!(WhisperFeatureExtractor::num_frames(10 * 480_000), N_FRAMES);
+    }
+}
@@ -0,0 +1,99 @@
+//! Audio decoding and feature extraction shared by the speech-capable models.
+
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+mod mel;
+mod wav;
+
+pub(crate) use mel::{WhisperFeatureExtractor, N_FRAMES, N_SAMPLES, SAMPLE_RATE};
+pub(crate) use wav::{decode_pcm16, decode_wav};
+
+/// Number of zero crossings of the windowed sinc kernel on each side of a resampled sample.
+const RESAMPLE_ZERO_CROSSINGS: usize = 16;
+
+/// Resample mono `samples` from `from_rate` to `to_rate` with a Hann-windowed sinc kernel. The
+/// kernel cutoff is the lower of the two Nyquist frequencies, so downsampling does not alias.
+pub(crate) fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let ratio = to_rate as f64 / from_rate as f64;
+    let cutoff = ratio.min(1.);
+    let half_width = RESAMPLE_ZERO_CROSSINGS as f64 / cutoff;
+    let out_len = (samples.len() as f64 * ratio).round() as usize;
+
+    (0..out_len)
+        .map(|i| {
+            let center = i as f64 / ratio;
+            let lo = (center - half_width).ceil().max(0.) as usize;
+            let hi = ((center + half_width).floor() as usize).min(samples.len() - 1);
+
+            let mut acc = 0.;
+            for (j, sample) in samples.iter().enumerate().take(hi + 1).skip(lo) {
+                let t = j as f64 - center;
+                let x = t * cutoff;
+                let sinc = if x.abs() < 1e-9 {
+                    1.
+                } else {
+                    (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
+                };
+                let window = 0.5 + 0.5 * (std::f64::consts::PI * t / half_width).cos();
+                acc += *sample as f64 * sinc * window * cutoff;
+            }
+            acc as f32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resample;
+
+    fn sine(freq: f64, rate: u32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2. * std::f64::consts::PI * freq * i as f64 / rate as f64).sin() as f32)
+            .collect()
+    }
+
+    #[test]
+    fn resample_identity_and_empty() {
+        let samples = sine(440., 16000, 100);
+        assert_eq!(resample(&samples, 16000, 16000), samples);
+        assert!(resample(&[], 44100, 16000).is_empty());
+    }
+
+    #[test]
+    fn resample_length() {
+        assert_eq!(resample(&vec![0.; 44100], 44100, 16000).len(), 16000);
+        assert_eq!(resample(&vec![0.; 8000], 8000, 16000).len(), 16000);
+        assert_eq!(resample(&vec![0.; 1000], 48000, 16000).len(), 333);
+    }
+
+    #[test]
+    fn resample_preserves_low_frequencies() {
+        for (from, to) in [(44100, 16000), (8000, 16000)] {
+            let out = resample(&sine(200., from, from as usize), from, to);
+            let expected = sine(200., to, to as usize);
+            // Away from the edges, where the kernel is truncated.
+            for i in 100..out.len() - 100 {
+                assert!(
+                    (out[i] - expected[i]).abs() < 1e-2,
+                    "{from} -> {to} at {i}: {} vs {}",
+                    out[i],
+                    expected[i]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn resample_removes_frequencies_above_nyquist() {
+        // 6 kHz is above the 4 kHz Nyquist frequency of the output.
+        let out = resample(&sine(6000., 44100, 44100), 44100, 8000);
+        let peak = out[100..out.len() - 100]
+            .iter()
+            .fold(0f32, |acc, x| acc.max(x.abs()));
+        assert!(peak < 5e-2, "{peak}");
+    }
+}
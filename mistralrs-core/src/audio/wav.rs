@@ -0,0 +1,338 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use anyhow::{bail, Context};
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+struct FmtChunk {
+    format: u16,
+    channels: usize,
+    sample_rate: u32,
+    block_align: usize,
+    bits_per_sample: u16,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> anyhow::Result<u16> {
+    let b = bytes
+        .get(offset..offset + 2)
+        .context("Unexpected end of WAV data.")?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> anyhow::Result<u32> {
+    let b = bytes
+        .get(offset..offset + 4)
+        .context("Unexpected end of WAV data.")?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn parse_fmt(chunk: &[u8]) -> anyhow::Result<FmtChunk> {
+    let mut format = read_u16(chunk, 0)?;
+    let channels = read_u16(chunk, 2)? as usize;
+    let sample_rate = read_u32(chunk, 4)?;
+    let block_align = read_u16(chunk, 12)? as usize;
+    let bits_per_sample = read_u16(chunk, 14)?;
+    if format == WAVE_FORMAT_EXTENSIBLE {
+        // The first two bytes of the sub-format GUID hold the actual format tag.
+        format = read_u16(chunk, 24)?;
+    }
+    if channels == 0 || sample_rate == 0 || block_align == 0 {
+        bail!("Invalid WAV format chunk.");
+    }
+    Ok(FmtChunk {
+        format,
+        channels,
+        sample_rate,
+        block_align,
+        bits_per_sample,
+    })
+}
+
+fn decode_sample(format: u16, bits_per_sample: u16, b: &[u8]) -> anyhow::Result<f32> {
+    Ok(match (format, bits_per_sample) {
+        (WAVE_FORMAT_PCM, 8) => (b[0] as f32 - 128.) / 128.,
+        (WAVE_FORMAT_PCM, 16) => i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.,
+        (WAVE_FORMAT_PCM, 24) => {
+            (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.
+        }
+        (WAVE_FORMAT_PCM, 32) => {
+            i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.
+        }
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        (WAVE_FORMAT_IEEE_FLOAT, 64) => {
+            f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32
+        }
+        (format, bits) => {
+            bail!("Unsupported WAV encoding (format tag {format:#06x}, {bits} bits per sample).")
+        }
+    })
+}
+
+/// Decode a RIFF WAV file into mono samples in `[-1, 1]` and the sample rate. Integer PCM
+/// (8, 16, 24 and 32 bit) and IEEE float (32 and 64 bit) data are supported; multi-channel audio
+/// is downmixed by averaging the channels.
+pub(crate) fn decode_wav(bytes: &[u8]) -> anyhow::Result<(Vec<f32>, u32)> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        bail!("Audio is not a RIFF WAV file.");
+    }
+
+    let mut fmt = None;
+    let mut data = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let size = read_u32(bytes, offset + 4)? as usize;
+        let start = offset + 8;
+        // Streamed files may leave the size of the final chunk unset.
+        let end = start.saturating_add(size).min(bytes.len());
+        match id {
+            b"fmt " => fmt = Some(parse_fmt(&bytes[start..end])?),
+            b"data" => data = Some(&bytes[start..end]),
+            _ => (),
+        }
+        // Chunks are padded to an even length.
+        offset = end + (size & 1);
+    }
+
+    let fmt = fmt.context("WAV file has no `fmt ` chunk.")?;
+    let data = data.context("WAV file has no `data` chunk.")?;
+
+    let bytes_per_sample = (fmt.bits_per_sample as usize).div_ceil(8);
+    if bytes_per_sample * fmt.channels > fmt.block_align {
+        bail!("Invalid WAV block alignment.");
+    }
+
+    let samples = data
+        .chunks_exact(fmt.block_align)
+        .map(|frame| {
+            let mut acc = 0.;
+            for channel in 0..fmt.channels {
+                let start = channel * bytes_per_sample;
+                acc += decode_sample(
+                    fmt.format,
+                    fmt.bits_per_sample,
+                    &frame[start..start + bytes_per_sample],
+                )?;
+            }
+            Ok(acc / fmt.channels as f32)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok((samples, fmt.sample_rate))
+}
+
+/// Decode interleaved little-endian signed 16-bit PCM into mono samples in `[-1, 1]`.
+pub(crate) fn decode_pcm16(bytes: &[u8], channels: usize) -> anyhow::Result<Vec<f32>> {
+    if channels == 0 {
+        bail!("PCM audio must have at least one channel.");
+    }
+    Ok(bytes
+        .chunks_exact(2 * channels)
+        .map(|frame| {
+            frame
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.)
+                .sum::<f32>()
+                / channels as f32
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_pcm16, decode_wav, WAVE_FORMAT_EXTENSIBLE, WAVE_FORMAT_IEEE_FLOAT, WAVE_FORMAT_PCM,
+    };
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        out.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+
+    fn fmt_body(format: u16, channels: u16, bits: u16) -> Vec<u8> {
+        let sample_rate = 16000u32;
+        let block_align = channels * bits.div_ceil(8);
+        let mut body = Vec::new();
+        body.extend_from_slice(&format.to_le_bytes());
+        body.extend_from_slice(&channels.to_le_bytes());
+        body.extend_from_slice(&sample_rate.to_le_bytes());
+        body.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        body.extend_from_slice(&block_align.to_le_bytes());
+        body.extend_from_slice(&bits.to_le_bytes());
+        body
+    }
+
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = chunks.concat();
+        let mut out = b"RIFF".to_vec();
+        out.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        out.extend_from_slice(b"WAVE");
+        out.extend_from_slice(&body);
+        out
+    }
+
+    fn wav(format: u16, channels: u16, bits: u16, data: &[u8]) -> Vec<u8> {
+        riff(&[
+            chunk(b"fmt ", &fmt_body(format, channels, bits)),
+            chunk(b"data", data),
+        ])
+    }
+
+    fn decode(bytes: &[u8]) -> Vec<f32> {
+        let (samples, sample_rate) = decode_wav(bytes).unwrap();
+        assert_eq!(sample_rate, 16000);
+        samples
+    }
+
+    #[test]
+    fn integer_pcm() {
+        assert_eq!(
+            decode(&wav(WAVE_FORMAT_PCM, 1, 8, &[0, 128, 192])),
+            vec![-1., 0., 0.5]
+        );
+
+        let data = [i16::MIN, 0, 16384]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decode(&wav(WAVE_FORMAT_PCM, 1, 16, &data)),
+            vec![-1., 0., 0.5]
+        );
+
+        // -2^23, 2^22 and -1
+        let data = [0x00, 0x00, 0x80, 0x00, 0x00, 0x40, 0xff, 0xff, 0xff];
+        assert_eq!(
+            decode(&wav(WAVE_FORMAT_PCM, 1, 24, &data)),
+            vec![-1., 0.5, -1. / 8_388_608.]
+        );
+
+        let data = [i32::MIN, 1 << 30]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(decode(&wav(WAVE_FORMAT_PCM, 1, 32, &data)), vec![-1., 0.5]);
+    }
+
+    #[test]
+    fn float_pcm() {
+        let data = [0.25f32, -0.75]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decode(&wav(WAVE_FORMAT_IEEE_FLOAT, 1, 32, &data)),
+            vec![0.25, -0.75]
+        );
+
+        let data = [0.25f64, -0.75]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decode(&wav(WAVE_FORMAT_IEEE_FLOAT, 1, 64, &data)),
+            vec![0.25, -0.75]
+        );
+    }
+
+    #[test]
+    fn extensible_format() {
+        let mut body = fmt_body(WAVE_FORMAT_EXTENSIBLE, 1, 16);
+        body.extend_from_slice(&22u16.to_le_bytes()); // cbSize
+        body.extend_from_slice(&16u16.to_le_bytes()); // wValidBitsPerSample
+        body.extend_from_slice(&4u32.to_le_bytes()); // dwChannelMask
+                                                     // KSDATAFORMAT_SUBTYPE_PCM
+        body.extend_from_slice(&[
+            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38,
+            0x9b, 0x71,
+        ]);
+        let data = [16384i16, -16384]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        let bytes = riff(&[chunk(b"fmt ", &body), chunk(b"data", &data)]);
+        assert_eq!(decode(&bytes), vec![0.5, -0.5]);
+    }
+
+    #[test]
+    fn stereo_is_downmixed() {
+        let data = [16384i16, -16384, 16384, 16384, i16::MIN, 0]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decode(&wav(WAVE_FORMAT_PCM, 2, 16, &data)),
+            vec![0., 0.5, -0.5]
+        );
+    }
+
+    #[test]
+    fn unknown_and_odd_sized_chunks_are_skipped() {
+        let bytes = riff(&[
+            chunk(b"LIST", &[1, 2, 3]),
+            chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 1, 8)),
+            chunk(b"fact", &[0; 4]),
+            chunk(b"data", &[0, 255, 128]),
+        ]);
+        assert_eq!(decode(&bytes), vec![-1., 127. / 128., 0.]);
+    }
+
+    #[test]
+    fn truncated_data_chunk_keeps_complete_frames() {
+        let mut bytes = wav(WAVE_FORMAT_PCM, 1, 16, &[0, 64, 0, 192]);
+        // Declare more data than there is, as streamed files do, and cut a frame in half.
+        let len = bytes.len();
+        bytes[len - 8..len - 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        bytes.truncate(len - 1);
+        assert_eq!(decode(&bytes), vec![0.5]);
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        let pcm16 = fmt_body(WAVE_FORMAT_PCM, 1, 16);
+        let data = chunk(b"data", &[0, 0]);
+
+        // Not RIFF WAV
+        assert!(decode_wav(b"RIFF").is_err());
+        assert!(decode_wav(b"RIFF\x04\x00\x00\x00WAVX").is_err());
+        // Missing chunks
+        assert!(decode_wav(&riff(&[data.clone()])).is_err());
+        assert!(decode_wav(&riff(&[chunk(b"fmt ", &pcm16)])).is_err());
+        // Truncated `fmt ` chunk
+        assert!(decode_wav(&riff(&[chunk(b"fmt ", &pcm16[..10]), data.clone()])).is_err());
+        // No channels
+        assert!(decode_wav(&riff(&[
+            chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 0, 16)),
+            data.clone()
+        ]))
+        .is_err());
+        // Block alignment smaller than a frame
+        let mut body = fmt_body(WAVE_FORMAT_PCM, 2, 16);
+        body[12..14].copy_from_slice(&2u16.to_le_bytes());
+        assert!(decode_wav(&riff(&[chunk(b"fmt ", &body), data.clone()])).is_err());
+        // Unsupported encodings
+        assert!(decode_wav(&wav(WAVE_FORMAT_PCM, 1, 12, &[0, 0])).is_err());
+        assert!(decode_wav(&wav(WAVE_FORMAT_IEEE_FLOAT, 1, 16, &[0, 0])).is_err());
+        assert!(decode_wav(&wav(0x0055, 1, 16, &[0, 0])).is_err());
+    }
+
+    #[test]
+    fn raw_pcm16() {
+        let data = [16384i16, -16384, 16384, 16384]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(decode_pcm16(&data, 1).unwrap(), vec![0.5, -0.5, 0.5, 0.5]);
+        assert_eq!(decode_pcm16(&data, 2).unwrap(), vec![0., 0.5]);
+        // A trailing partial frame is dropped.
+        assert_eq!(decode_pcm16(&data[..7], 2).unwrap(), vec![0.]);
+        assert!(decode_pcm16(&data, 0).is_err());
+    }
+}
@@ -12,12 +12,15 @@ use crate::layers::{self, MatMul};
 pub enum Activation {
     #[serde(rename = "quick_gelu")]
     QuickGelu,
+    #[serde(rename = "gelu")]
+    Gelu,
 }
 
 impl Module for Activation {
     fn forward(&self, xs: &Tensor) -> Result<Tensor> {
         match self {
             Activation::QuickGelu => xs * nn::ops::sigmoid(&(xs * 1.702f64)?)?,
+            Activation::Gelu => xs.gelu_erf(),
         }
     }
 }
@@ -245,6 +248,21 @@ impl ClipEncoder {
         }
         Ok(xs)
     }
+
+    /// Returns the output of the last layer and of the penultimate layer.
+    pub fn forward_with_penultimate(
+        &self,
+        xs: &Tensor,
+        causal_attention_mask: Option<&Tensor>,
+    ) -> Result<(Tensor, Tensor)> {
+        let mut xs = xs.clone();
+        let mut penultimate = xs.clone();
+        for layer in self.layers.iter() {
+            penultimate = xs;
+            xs = layer.forward(&penultimate, causal_attention_mask)?;
+        }
+        Ok((xs, penultimate))
+    }
 }
 
 /// A CLIP transformer based model.
@@ -300,11 +318,23 @@ impl ClipTextTransformer {
             .forward(&input_ids, Some(&causal_attention_mask))?;
         self.final_layer_norm.forward(&input_ids)
     }
-}
 
-impl Module for ClipTextTransformer {
-    fn forward(&self, input_ids: &Tensor) -> Result<Tensor> {
-        let output = self.forward_with_mask(input_ids, usize::MAX)?;
+    /// Returns the hidden states of the penultimate layer, which do not go through the final layer
+    /// norm, and the pooled output. This is the conditioning used by Stable Diffusion XL.
+    pub fn forward_with_penultimate(&self, input_ids: &Tensor) -> Result<(Tensor, Tensor)> {
+        let (bsz, seq_len) = input_ids.dims2()?;
+        let xs = self.embeddings.forward(input_ids)?;
+        let causal_attention_mask =
+            Self::build_causal_attention_mask(bsz, seq_len, usize::MAX, xs.device())?;
+        let (xs, penultimate) = self
+            .encoder
+            .forward_with_penultimate(&xs, Some(&causal_attention_mask))?;
+        let output = self.final_layer_norm.forward(&xs)?;
+        Ok((penultimate, Self::pool(&output, input_ids)?))
+    }
+
+    /// The hidden states at the end of text token, which has the largest id.
+    fn pool(output: &Tensor, input_ids: &Tensor) -> Result<Tensor> {
         let sequence_max_indices = input_ids.argmax(D::Minus1)?.to_dtype(DType::I64)?;
 
         let mut indices = Vec::new();
@@ -315,3 +345,10 @@ impl Module for ClipTextTransformer {
         Tensor::cat(&indices, 0)
     }
 }
+
+impl Module for ClipTextTransformer {
+    fn forward(&self, input_ids: &Tensor) -> Result<Tensor> {
+        let output = self.forward_with_mask(input_ids, usize::MAX)?;
+        Self::pool(&output, input_ids)
+    }
+}
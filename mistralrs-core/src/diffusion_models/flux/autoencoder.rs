@@ -406,6 +406,11 @@ impl AutoEncoder {
         let z = xs.apply(&self.encoder)?.apply(&self.reg)?;
         (z - self.shift_factor)? * self.scale_factor
     }
+    /// Encode to the mean of the latent distribution, so that seeded generations are reproducible.
+    pub fn encode_mean(&self, xs: &Tensor) -> Result<Tensor> {
+        let z = xs.apply(&self.encoder)?.chunk(2, 1)?[0].clone();
+        (z - self.shift_factor)? * self.scale_factor
+    }
     pub fn decode(&self, xs: &Tensor) -> Result<Tensor> {
         let xs = ((xs / self.scale_factor)? + self.shift_factor)?;
         xs.apply(&self.decoder)
@@ -2,15 +2,33 @@
 
 use candle_core::{Device, Result, Tensor};
 
+use crate::diffusion_models::seeded_randn;
+
+/// Each sample uses the same `seed`, so a sample does not depend on the others in the batch.
 pub fn get_noise(
     num_samples: usize,
     height: usize,
     width: usize,
+    seed: Option<u64>,
     device: &Device,
 ) -> Result<Tensor> {
     let height = (height + 15) / 16 * 2;
     let width = (width + 15) / 16 * 2;
-    Tensor::randn(0f32, 1., (num_samples, 16, height, width), device)
+    match seed {
+        Some(seed) => {
+            let noise = seeded_randn(seed, (1, 16, height, width), device)?;
+            noise.repeat((num_samples, 1, 1, 1))
+        }
+        None => Tensor::randn(0f32, 1., (num_samples, 16, height, width), device),
+    }
+}
+
+/// Pack latents of shape (b, c, h, w) into 2x2 patches of shape (b, h/2 * w/2, c * 4).
+pub fn pack(xs: &Tensor) -> Result<Tensor> {
+    let (bs, c, h, w) = xs.dims4()?;
+    xs.reshape((bs, c, h / 2, 2, w / 2, 2))? // (b, c, h, ph, w, pw)
+        .permute((0, 2, 4, 1, 3, 5))? // (b, h, w, c, ph, pw)
+        .reshape((bs, h / 2 * w / 2, c * 4))
 }
 
 #[derive(Debug, Clone)]
@@ -25,11 +43,9 @@ pub struct State {
 impl State {
     pub fn new(t5_emb: &Tensor, clip_emb: &Tensor, img: &Tensor) -> Result<Self> {
         let dtype = img.dtype();
-        let (bs, c, h, w) = img.dims4()?;
+        let (bs, _c, h, w) = img.dims4()?;
         let dev = img.device();
-        let img = img.reshape((bs, c, h / 2, 2, w / 2, 2))?; // (b, c, h, ph, w, pw)
-        let img = img.permute((0, 2, 4, 1, 3, 5))?; // (b, h, w, c, ph, pw)
-        let img = img.reshape((bs, h / 2 * w / 2, c * 4))?;
+        let img = pack(img)?;
         let img_ids = Tensor::stack(
             &[
                 Tensor::full(0u32, (h / 2, w / 2), dev)?,
@@ -45,9 +61,13 @@ impl State {
         .to_dtype(dtype)?;
         let img_ids = img_ids.reshape((1, h / 2 * w / 2, 3))?;
         let img_ids = img_ids.repeat((bs, 1, 1))?;
-        let txt = t5_emb.repeat(bs)?;
+        // A single prompt is shared by all the samples.
+        let (txt, vec) = if t5_emb.dim(0)? == bs {
+            (t5_emb.clone(), clip_emb.clone())
+        } else {
+            (t5_emb.repeat(bs)?, clip_emb.repeat(bs)?)
+        };
         let txt_ids = Tensor::zeros((bs, txt.dim(1)?, 3), dtype, dev)?;
-        let vec = clip_emb.repeat(bs)?;
         Ok(Self {
             img,
             img_ids,
@@ -93,16 +113,30 @@ pub fn unpack(xs: &Tensor, height: usize, width: usize) -> Result<Tensor> {
         .reshape((b, c_ph_pw / 4, height * 2, width * 2))
 }
 
-#[allow(clippy::too_many_arguments)]
-fn denoise_inner(
+/// Classifier-free guidance against a negative prompt, at `scale`.
+pub struct NegativeConditioning<'a> {
+    pub state: &'a State,
+    pub scale: f64,
+}
+
+/// Inpainting: outside of the `mask`, the latents are reset to the `image_latents` noised to the
+/// current timestep after every step. All tensors are packed.
+pub struct InpaintConditioning<'a> {
+    pub mask: &'a Tensor,
+    pub image_latents: &'a Tensor,
+    pub noise: &'a Tensor,
+}
+
+/// Denoise `img` over the `timesteps`. `guidance` is the distilled guidance scale, for models
+/// which have guidance embeddings.
+pub fn denoise(
     model: &mut super::model::Flux,
     img: &Tensor,
-    img_ids: &Tensor,
-    txt: &Tensor,
-    txt_ids: &Tensor,
-    vec_: &Tensor,
+    state: &State,
     timesteps: &[f64],
     guidance: Option<f64>,
+    negative: Option<NegativeConditioning<'_>>,
+    inpaint: Option<InpaintConditioning<'_>>,
 ) -> Result<Tensor> {
     let b_sz = img.dim(0)?;
     let dev = img.device();
@@ -118,44 +152,82 @@ fn denoise_inner(
             _ => continue,
         };
         let t_vec = Tensor::full(*t_curr as f32, b_sz, dev)?;
-        let pred = model.forward(&img, img_ids, txt, txt_ids, &t_vec, vec_, guidance.as_ref())?;
-        img = (img + pred * (t_prev - t_curr))?
+        let pred = model.forward(
+            &img,
+            &state.img_ids,
+            &state.txt,
+            &state.txt_ids,
+            &t_vec,
+            &state.vec,
+            guidance.as_ref(),
+        )?;
+        let pred = match &negative {
+            Some(NegativeConditioning { state: neg, scale }) => {
+                let neg_pred = model.forward(
+                    &img,
+                    &neg.img_ids,
+                    &neg.txt,
+                    &neg.txt_ids,
+                    &t_vec,
+                    &neg.vec,
+                    guidance.as_ref(),
+                )?;
+                (&neg_pred + ((pred - &neg_pred)? * *scale)?)?
+            }
+            None => pred,
+        };
+        img = (img + pred * (t_prev - t_curr))?;
+        if let Some(InpaintConditioning {
+            mask,
+            image_latents,
+            noise,
+        }) = &inpaint
+        {
+            let image_latents = ((*noise * *t_prev)? + (*image_latents * (1. - t_prev))?)?;
+            img = (mask.mul(&img)? + mask.affine(-1., 1.)?.mul(&image_latents)?)?;
+        }
     }
     Ok(img)
 }
 
-#[allow(clippy::too_many_arguments)]
-pub fn denoise(
-    model: &mut super::model::Flux,
-    img: &Tensor,
-    img_ids: &Tensor,
-    txt: &Tensor,
-    txt_ids: &Tensor,
-    vec_: &Tensor,
-    timesteps: &[f64],
-    guidance: f64,
-) -> Result<Tensor> {
-    denoise_inner(
-        model,
-        img,
-        img_ids,
-        txt,
-        txt_ids,
-        vec_,
-        timesteps,
-        Some(guidance),
-    )
+/// The timesteps for img2img. The start of the schedule is skipped so that the image is only
+/// noised according to `strength`.
+pub fn get_img2img_schedule(timesteps: &[f64], strength: f64) -> Vec<f64> {
+    let num_steps = timesteps.len() - 1;
+    let init_steps = ((num_steps as f64 * strength).round() as usize).min(num_steps);
+    timesteps[num_steps - init_steps..].to_vec()
 }
 
-#[allow(clippy::too_many_arguments)]
-pub fn denoise_no_guidance(
-    model: &mut super::model::Flux,
-    img: &Tensor,
-    img_ids: &Tensor,
-    txt: &Tensor,
-    txt_ids: &Tensor,
-    vec_: &Tensor,
-    timesteps: &[f64],
-) -> Result<Tensor> {
-    denoise_inner(model, img, img_ids, txt, txt_ids, vec_, timesteps, None)
+#[cfg(test)]
+mod tests {
+    use candle_core::Device;
+
+    use super::{get_img2img_schedule, get_noise, get_schedule};
+
+    #[test]
+    fn seeded_noise_is_reproducible() {
+        let a = get_noise(2, 64, 64, Some(42), &Device::Cpu).unwrap();
+        let b = get_noise(1, 64, 64, Some(42), &Device::Cpu).unwrap();
+        assert_eq!(a.dims(), &[2, 16, 8, 8]);
+
+        // Each sample uses the seed on its own.
+        let a = a.flatten_from(1).unwrap().to_vec2::<f32>().unwrap();
+        let b = b.flatten_all().unwrap().to_vec1::<f32>().unwrap();
+        assert_eq!(a[0], b);
+        assert_eq!(a[1], b);
+
+        let mean = b.iter().sum::<f32>() / b.len() as f32;
+        assert!(mean.abs() < 0.2);
+    }
+
+    #[test]
+    fn img2img_schedule_skips_start() {
+        let timesteps = get_schedule(10, None);
+        assert_eq!(get_img2img_schedule(&timesteps, 1.0), timesteps);
+        assert_eq!(get_img2img_schedule(&timesteps, 0.0), vec![0.0]);
+
+        let partial = get_img2img_schedule(&timesteps, 0.6);
+        assert_eq!(partial.len(), 7);
+        assert!((partial[0] - 0.6).abs() < 1e-9);
+    }
 }
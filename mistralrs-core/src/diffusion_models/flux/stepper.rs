@@ -3,6 +3,7 @@ use std::{cmp::Ordering, fs::File, sync::Arc};
 use candle_core::{DType, Device, Result, Tensor, D};
 use candle_nn::Module;
 use hf_hub::api::sync::{Api, ApiError};
+use image::{imageops::FilterType, DynamicImage};
 use mistralrs_quant::ShardedVarBuilder;
 use tokenizers::Tokenizer;
 use tracing::info;
@@ -161,6 +162,39 @@ fn get_tokenization(tok: &Tokenizer, prompts: Vec<String>, device: &Device) -> R
     )
 }
 
+/// The image resized to the generated size, with values in [-1, 1] and shape (1, 3, h, w).
+#[allow(clippy::cast_possible_truncation)]
+fn preprocess_image(
+    image: &DynamicImage,
+    height: usize,
+    width: usize,
+    device: &Device,
+) -> Result<Tensor> {
+    let (height, width) = ((height + 15) / 16 * 16, (width + 15) / 16 * 16);
+    let image = image
+        .resize_exact(width as u32, height as u32, FilterType::CatmullRom)
+        .to_rgb8();
+    let image = Tensor::from_vec(image.into_raw(), (height, width, 3), device)?
+        .permute((2, 0, 1))?
+        .to_dtype(DType::F32)?;
+    ((image / 127.5)? - 1.)?.unsqueeze(0)
+}
+
+/// The inpainting mask at the resolution of the latents, with values in [0, 1] and shape (1, 1, h, w).
+#[allow(clippy::cast_possible_truncation)]
+fn preprocess_mask(
+    mask: &DynamicImage,
+    height: usize,
+    width: usize,
+    device: &Device,
+) -> Result<Tensor> {
+    let (height, width) = ((height + 15) / 16 * 2, (width + 15) / 16 * 2);
+    let mask = mask
+        .resize_exact(width as u32, height as u32, FilterType::Triangle)
+        .to_luma8();
+    Tensor::from_vec(mask.into_raw(), (1, 1, height, width), device)?.to_dtype(DType::F32)? / 255.
+}
+
 impl FluxStepper {
     pub fn new(
         cfg: FluxStepperConfig,
@@ -193,6 +227,28 @@ impl FluxStepper {
             offloaded,
         })
     }
+
+    fn t5_input_ids(&self, prompts: Vec<String>) -> Result<Tensor> {
+        let t5_input_ids = get_tokenization(&self.t5_tok, prompts, &self.device)?;
+        if self.is_guidance {
+            return Ok(t5_input_ids);
+        }
+        match t5_input_ids.dim(1)?.cmp(&256) {
+            Ordering::Greater => {
+                candle_core::bail!("T5 embedding length greater than 256, please shrink the prompt or use the -dev (with guidance distillation) version.")
+            }
+            Ordering::Less | Ordering::Equal => {
+                t5_input_ids.pad_with_zeros(D::Minus1, 0, 256 - t5_input_ids.dim(1)?)
+            }
+        }
+    }
+
+    fn clip_embed(&self, prompts: Vec<String>) -> Result<Tensor> {
+        let clip_input_ids = get_tokenization(&self.clip_tok, prompts, &self.device)?;
+        self.clip_text
+            .forward(&clip_input_ids)?
+            .to_dtype(self.dtype)
+    }
 }
 
 impl DiffusionModel for FluxStepper {
@@ -201,20 +257,15 @@ impl DiffusionModel for FluxStepper {
         prompts: Vec<String>,
         params: DiffusionGenerationParams,
     ) -> Result<Tensor> {
-        let mut t5_input_ids = get_tokenization(&self.t5_tok, prompts.clone(), &self.device)?;
-        if !self.is_guidance {
-            match t5_input_ids.dim(1)?.cmp(&256) {
-                Ordering::Greater => {
-                    candle_core::bail!("T5 embedding length greater than 256, please shrink the prompt or use the -dev (with guidance distillation) version.")
-                }
-                Ordering::Less | Ordering::Equal => {
-                    t5_input_ids =
-                        t5_input_ids.pad_with_zeros(D::Minus1, 0, 256 - t5_input_ids.dim(1)?)?;
-                }
-            }
-        }
-
-        let t5_embed = {
+        let bs = prompts.len();
+        let t5_input_ids = self.t5_input_ids(prompts.clone())?;
+        let neg_t5_input_ids = params
+            .negative_prompt
+            .as_ref()
+            .map(|prompt| self.t5_input_ids(vec![prompt.clone()]))
+            .transpose()?;
+
+        let (t5_embed, neg_t5_embed) = {
             info!("Hotloading T5 XXL model.");
             let mut t5_encoder = get_t5_model(
                 &self.api,
@@ -223,53 +274,93 @@ impl DiffusionModel for FluxStepper {
                 self.silent,
                 self.offloaded,
             )?;
-            t5_encoder.forward(&t5_input_ids)?
+            let neg_t5_embed = neg_t5_input_ids
+                .map(|input_ids| t5_encoder.forward(&input_ids))
+                .transpose()?;
+            (t5_encoder.forward(&t5_input_ids)?, neg_t5_embed)
         };
 
-        let clip_input_ids = get_tokenization(&self.clip_tok, prompts, &self.device)?;
-        let clip_embed = self
-            .clip_text
-            .forward(&clip_input_ids)?
-            .to_dtype(self.dtype)?;
-
-        let img = flux::sampling::get_noise(
-            t5_embed.dim(0)?,
-            params.height,
-            params.width,
-            self.device(),
-        )?
-        .to_dtype(self.dtype)?;
+        let clip_embed = self.clip_embed(prompts)?;
+        let neg_clip_embed = params
+            .negative_prompt
+            .as_ref()
+            .map(|prompt| self.clip_embed(vec![prompt.clone()]))
+            .transpose()?;
+
+        let noise =
+            flux::sampling::get_noise(bs, params.height, params.width, params.seed, self.device())?
+                .to_dtype(self.dtype)?;
+
+        let state = flux::sampling::State::new(&t5_embed, &clip_embed, &noise)?;
+        let neg_state = match (&neg_t5_embed, &neg_clip_embed) {
+            (Some(t5_embed), Some(clip_embed)) => {
+                Some(flux::sampling::State::new(t5_embed, clip_embed, &noise)?)
+            }
+            _ => None,
+        };
 
-        let state = flux::sampling::State::new(&t5_embed, &clip_embed, &img)?;
+        let num_steps = params.num_steps.unwrap_or(self.cfg.num_steps);
         let timesteps = flux::sampling::get_schedule(
-            self.cfg.num_steps,
+            num_steps,
             self.cfg
                 .guidance_config
                 .map(|s| (state.img.dims()[1], s.base_shift, s.max_shift)),
         );
 
-        let img = if let Some(guidance_cfg) = &self.cfg.guidance_config {
-            flux::sampling::denoise(
-                &mut self.flux_model,
-                &state.img,
-                &state.img_ids,
-                &state.txt,
-                &state.txt_ids,
-                &state.vec,
-                &timesteps,
-                guidance_cfg.guidance_scale,
-            )?
-        } else {
-            flux::sampling::denoise_no_guidance(
-                &mut self.flux_model,
-                &state.img,
-                &state.img_ids,
-                &state.txt,
-                &state.txt_ids,
-                &state.vec,
-                &timesteps,
-            )?
+        let image_latents = match &params.init_image {
+            Some(image) => {
+                let image = preprocess_image(image, params.height, params.width, &self.device)?
+                    .to_dtype(self.dtype)?;
+                let latents = self.flux_vae.encode_mean(&image)?.repeat((bs, 1, 1, 1))?;
+                Some(flux::sampling::pack(&latents)?)
+            }
+            None => None,
         };
+        let mask = match &params.mask_image {
+            Some(mask) => {
+                let mask = preprocess_mask(mask, params.height, params.width, &self.device)?
+                    .to_dtype(self.dtype)?
+                    .repeat((bs, 16, 1, 1))?;
+                Some(flux::sampling::pack(&mask)?)
+            }
+            None => None,
+        };
+
+        // For img2img, start part of the way through the schedule from the noised image.
+        let (img, timesteps) = match &image_latents {
+            Some(image_latents) => {
+                let timesteps = flux::sampling::get_img2img_schedule(&timesteps, params.strength);
+                let t_start = timesteps[0];
+                let img = ((&state.img * t_start)? + (image_latents * (1. - t_start))?)?;
+                (img, timesteps)
+            }
+            None => (state.img.clone(), timesteps),
+        };
+
+        let img = flux::sampling::denoise(
+            &mut self.flux_model,
+            &img,
+            &state,
+            &timesteps,
+            self.cfg
+                .guidance_config
+                .map(|cfg| params.guidance_scale.unwrap_or(cfg.guidance_scale)),
+            neg_state
+                .as_ref()
+                .map(|state| flux::sampling::NegativeConditioning {
+                    state,
+                    scale: params.true_cfg_scale,
+                }),
+            mask.as_ref()
+                .zip(image_latents.as_ref())
+                .map(
+                    |(mask, image_latents)| flux::sampling::InpaintConditioning {
+                        mask,
+                        image_latents,
+                        noise: &state.img,
+                    },
+                ),
+        )?;
 
         let latent_img = flux::sampling::unpack(&img, params.height, params.width)?;
 
@@ -1,8 +1,16 @@
 pub(crate) mod clip;
 pub(crate) mod flux;
 pub(crate) mod processor;
+pub(crate) mod stable_diffusion;
 pub(crate) mod t5;
 
+use std::f32::consts::PI;
+
+use candle_core::{Device, Result, Shape, Tensor};
+use image::DynamicImage;
+use rand::{Rng, SeedableRng};
+use rand_isaac::Isaac64Rng;
+
 macro_rules! generate_repr {
     ($t:ident) => {
         #[cfg(feature = "pyo3_macros")]
@@ -16,21 +24,92 @@ macro_rules! generate_repr {
 }
 
 #[cfg_attr(feature = "pyo3_macros", pyo3::pyclass)]
-#[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+/// Parameters of an image generation request. Optional fields which are not set use the defaults
+/// of the model.
+/// - `num_steps`: Number of denoising steps.
+/// - `guidance_scale`: Distilled guidance scale for models with guidance distillation, such as
+///     FLUX.1-dev. For Stable Diffusion, this is the classifier-free guidance scale.
+/// - `seed`: Seed of the initial noise. The `n` images of a request use consecutive seeds.
+/// - `negative_prompt`: Steer the image away from this prompt with classifier-free guidance.
+///     This runs the model twice per step.
+/// - `true_cfg_scale`: Classifier-free guidance scale used with the `negative_prompt` by Flux models.
+/// - `init_image`: Image to start from (img2img). It is resized to `height` and `width`.
+/// - `strength`: How much the `init_image` is changed, from `0` (unchanged) to `1` (fully regenerated).
+/// - `mask_image`: Inpainting mask for the `init_image`. White areas are regenerated and black
+///     areas are kept.
 pub struct DiffusionGenerationParams {
+    #[cfg_attr(feature = "pyo3_macros", pyo3(get))]
     pub height: usize,
+    #[cfg_attr(feature = "pyo3_macros", pyo3(get))]
     pub width: usize,
+    #[cfg_attr(feature = "pyo3_macros", pyo3(get))]
+    pub num_steps: Option<usize>,
+    #[cfg_attr(feature = "pyo3_macros", pyo3(get))]
+    pub guidance_scale: Option<f64>,
+    #[cfg_attr(feature = "pyo3_macros", pyo3(get))]
+    pub seed: Option<u64>,
+    #[cfg_attr(feature = "pyo3_macros", pyo3(get))]
+    pub negative_prompt: Option<String>,
+    #[cfg_attr(feature = "pyo3_macros", pyo3(get))]
+    pub true_cfg_scale: f64,
+    pub init_image: Option<DynamicImage>,
+    #[cfg_attr(feature = "pyo3_macros", pyo3(get))]
+    pub strength: f64,
+    pub mask_image: Option<DynamicImage>,
 }
 
 generate_repr!(DiffusionGenerationParams);
 
 impl Default for DiffusionGenerationParams {
-    /// Image dimensions will be 720x1280.
+    /// Image dimensions will be 720x1280, generated from noise with the defaults of the model.
     fn default() -> Self {
         Self {
             height: 720,
             width: 1280,
+            num_steps: None,
+            guidance_scale: None,
+            seed: None,
+            negative_prompt: None,
+            true_cfg_scale: 4.0,
+            init_image: None,
+            strength: 0.6,
+            mask_image: None,
+        }
+    }
+}
+
+impl DiffusionGenerationParams {
+    /// Check the image inputs, as inpainting needs an image to inpaint.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.mask_image.is_some() && self.init_image.is_none() {
+            anyhow::bail!("An inpainting `mask_image` requires an `init_image`.");
         }
+        if !(0.0..=1.0).contains(&self.strength) {
+            anyhow::bail!("`strength` must be between 0 and 1, got {}.", self.strength);
+        }
+        if self.num_steps == Some(0) {
+            anyhow::bail!("`num_steps` must be at least 1.");
+        }
+        Ok(())
+    }
+}
+
+/// Standard normal noise from a seeded generator, so that a seed gives the same noise on any device.
+pub(crate) fn seeded_randn(seed: u64, shape: impl Into<Shape>, device: &Device) -> Result<Tensor> {
+    let shape = shape.into();
+    let n = shape.elem_count();
+    let mut rng = Isaac64Rng::seed_from_u64(seed);
+    let mut data = Vec::with_capacity(n + 1);
+    while data.len() < n {
+        // Box-Muller transform, `u1` is in (0, 1] to avoid `ln(0)`.
+        let u1 = 1. - rng.gen::<f32>();
+        let u2 = rng.gen::<f32>();
+        let r = (-2. * u1.ln()).sqrt();
+        let theta = 2. * PI * u2;
+        data.push(r * theta.cos());
+        data.push(r * theta.sin());
     }
+    data.truncate(n);
+    Tensor::from_vec(data, shape, device)
 }
@@ -1,6 +1,6 @@
-use std::{any::Any, num::NonZeroUsize, sync::Arc};
+use std::{any::Any, collections::HashMap, num::NonZeroUsize, sync::Arc};
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use candle_core::Device;
 use indexmap::IndexMap;
 use tokenizers::Tokenizer;
@@ -20,13 +20,16 @@ use super::DiffusionGenerationParams;
 pub struct DiffusionProcessor;
 
 impl Processor for DiffusionProcessor {
+    #[allow(clippy::too_many_arguments)]
     fn process(
         &self,
         _pipeline: &dyn Pipeline,
         _messages: Vec<IndexMap<String, MessageContent>>,
         _add_generation_prompt: bool,
+        _continue_final_message: bool,
         _add_special_tokens: bool,
         _tools: Vec<crate::Tool>,
+        _chat_template_kwargs: Option<HashMap<String, serde_json::Value>>,
     ) -> Result<(Vec<u32>, String)> {
         anyhow::bail!(
             "DiffusionProcessor::process should not be used. It does not expect chat messages."
@@ -72,27 +75,46 @@ impl InputsProcessor for DiffusionInputsProcessor {
         prompt_chunksize: Option<NonZeroUsize>,
         _mapper: Option<&dyn DeviceMapper>,
     ) -> Box<dyn Iterator<Item = Result<InputProcessorOutput>>> {
-        let mut make_value = if prompt_chunksize.is_some() {
+        if prompt_chunksize.is_some() {
             return Box::new(std::iter::once(Err(anyhow::Error::msg(
                 "Prompt batching is unsupported for diffusion models",
             ))));
-        } else {
-            || {
+        }
+
+        // Sequences are batched together when they have the same generation parameters.
+        let mut batches: Vec<(DiffusionGenerationParams, Vec<usize>)> = Vec::new();
+        for (i, seq) in input_seqs.iter().enumerate() {
+            let Some(mut params) = seq.get_diffusion_diffusion_params() else {
+                return Box::new(std::iter::once(Err(anyhow::Error::msg(
+                    "Diffusion model params must be present",
+                ))));
+            };
+            // Offset the seed so that the images of one request are different.
+            params.seed = params
+                .seed
+                .map(|seed| seed.wrapping_add(seq.get_response_index() as u64));
+            match batches.iter_mut().find(|(other, _)| *other == params) {
+                Some((_, seq_indices)) => seq_indices.push(i),
+                None => batches.push((params, vec![i])),
+            }
+        }
+
+        let outputs = batches
+            .into_iter()
+            .map(|(params, seq_indices)| {
                 let inputs = ModelInputs {
-                    prompts: input_seqs
-                        .iter_mut()
-                        .map(|seq| seq.get_initial_prompt().to_string())
+                    prompts: seq_indices
+                        .iter()
+                        .map(|i| input_seqs[*i].get_initial_prompt().to_string())
                         .collect::<Vec<_>>(),
-                    params: input_seqs[0]
-                        .get_diffusion_diffusion_params()
-                        .context("Diffusion model params must be present")?,
+                    params,
                 };
                 Ok(InputProcessorOutput {
                     inputs: Box::new(inputs),
-                    seq_indices: (0..input_seqs.len()).collect::<Vec<_>>(),
+                    seq_indices,
                 })
-            }
-        };
-        Box::new(std::iter::once(make_value()))
+            })
+            .collect::<Vec<_>>();
+        Box::new(outputs.into_iter())
     }
 }
@@ -0,0 +1,222 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use candle_core::{DType, Module, Result, Tensor, D};
+use candle_nn::{Conv2d, GroupNorm, LayerNorm, Linear};
+use mistralrs_quant::ShardedVarBuilder;
+
+use crate::layers::{conv2d, group_norm, layer_norm, linear, linear_no_bias, MatMul};
+
+/// Attention over (b, heads, seq_len, head_dim) tensors. The softmax is computed in f32, as it can
+/// overflow in f16 for large images.
+pub(crate) fn scaled_dot_product_attention(q: &Tensor, k: &Tensor, v: &Tensor) -> Result<Tensor> {
+    let in_dtype = q.dtype();
+    let scale_factor = 1.0 / (q.dim(D::Minus1)? as f64).sqrt();
+    let q = q.to_dtype(DType::F32)?;
+    let k = k.to_dtype(DType::F32)?;
+    let v = v.to_dtype(DType::F32)?;
+    let attn_weights = (MatMul.matmul(&q, &k.t()?)? * scale_factor)?;
+    MatMul
+        .matmul(&candle_nn::ops::softmax_last_dim(&attn_weights)?, &v)?
+        .to_dtype(in_dtype)
+}
+
+/// Self attention, or cross attention over the text embeddings when a `context_dim` is given.
+#[derive(Debug, Clone)]
+struct CrossAttention {
+    to_q: Linear,
+    to_k: Linear,
+    to_v: Linear,
+    to_out: Linear,
+    heads: usize,
+}
+
+impl CrossAttention {
+    fn new(
+        query_dim: usize,
+        context_dim: Option<usize>,
+        heads: usize,
+        dim_head: usize,
+        vb: ShardedVarBuilder,
+    ) -> Result<Self> {
+        let inner_dim = heads * dim_head;
+        let context_dim = context_dim.unwrap_or(query_dim);
+        Ok(Self {
+            to_q: linear_no_bias(query_dim, inner_dim, vb.pp("to_q"))?,
+            to_k: linear_no_bias(context_dim, inner_dim, vb.pp("to_k"))?,
+            to_v: linear_no_bias(context_dim, inner_dim, vb.pp("to_v"))?,
+            to_out: linear(inner_dim, query_dim, vb.pp("to_out.0"))?,
+            heads,
+        })
+    }
+
+    fn split_heads(&self, xs: &Tensor) -> Result<Tensor> {
+        let (b, seq_len, dim) = xs.dims3()?;
+        xs.reshape((b, seq_len, self.heads, dim / self.heads))?
+            .transpose(1, 2)?
+            .contiguous()
+    }
+
+    fn forward(&self, xs: &Tensor, context: Option<&Tensor>) -> Result<Tensor> {
+        let context = context.unwrap_or(xs);
+        let q = self.split_heads(&self.to_q.forward(xs)?)?;
+        let k = self.split_heads(&self.to_k.forward(context)?)?;
+        let v = self.split_heads(&self.to_v.forward(context)?)?;
+        let xs = scaled_dot_product_attention(&q, &k, &v)?;
+        let (b, heads, seq_len, head_dim) = xs.dims4()?;
+        let xs = xs
+            .transpose(1, 2)?
+            .reshape((b, seq_len, heads * head_dim))?;
+        self.to_out.forward(&xs)
+    }
+}
+
+/// Feed forward with a GEGLU activation.
+#[derive(Debug, Clone)]
+struct FeedForward {
+    proj_in: Linear,
+    proj_out: Linear,
+}
+
+impl FeedForward {
+    fn new(dim: usize, vb: ShardedVarBuilder) -> Result<Self> {
+        let inner_dim = dim * 4;
+        Ok(Self {
+            proj_in: linear(dim, inner_dim * 2, vb.pp("net.0.proj"))?,
+            proj_out: linear(inner_dim, dim, vb.pp("net.2"))?,
+        })
+    }
+}
+
+impl Module for FeedForward {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let xs = self.proj_in.forward(xs)?;
+        let chunks = xs.chunk(2, D::Minus1)?;
+        let xs = (&chunks[0] * chunks[1].gelu_erf()?)?;
+        self.proj_out.forward(&xs)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BasicTransformerBlock {
+    norm1: LayerNorm,
+    attn1: CrossAttention,
+    norm2: LayerNorm,
+    attn2: CrossAttention,
+    norm3: LayerNorm,
+    ff: FeedForward,
+}
+
+impl BasicTransformerBlock {
+    fn new(
+        dim: usize,
+        heads: usize,
+        dim_head: usize,
+        context_dim: usize,
+        vb: ShardedVarBuilder,
+    ) -> Result<Self> {
+        Ok(Self {
+            norm1: layer_norm(dim, 1e-5, vb.pp("norm1"))?,
+            attn1: CrossAttention::new(dim, None, heads, dim_head, vb.pp("attn1"))?,
+            norm2: layer_norm(dim, 1e-5, vb.pp("norm2"))?,
+            attn2: CrossAttention::new(dim, Some(context_dim), heads, dim_head, vb.pp("attn2"))?,
+            norm3: layer_norm(dim, 1e-5, vb.pp("norm3"))?,
+            ff: FeedForward::new(dim, vb.pp("ff"))?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor, context: &Tensor) -> Result<Tensor> {
+        let xs = (self.attn1.forward(&self.norm1.forward(xs)?, None)? + xs)?;
+        let xs = (self
+            .attn2
+            .forward(&self.norm2.forward(&xs)?, Some(context))?
+            + xs)?;
+        self.ff.forward(&self.norm3.forward(&xs)?)? + xs
+    }
+}
+
+/// Stable Diffusion 2 and SDXL project with linear layers instead of 1x1 convolutions.
+#[derive(Debug, Clone)]
+enum Projection {
+    Conv(Conv2d),
+    Linear(Linear),
+}
+
+impl Projection {
+    fn new(in_c: usize, out_c: usize, use_linear: bool, vb: ShardedVarBuilder) -> Result<Self> {
+        if use_linear {
+            Ok(Self::Linear(linear(in_c, out_c, vb)?))
+        } else {
+            Ok(Self::Conv(conv2d(in_c, out_c, 1, Default::default(), vb)?))
+        }
+    }
+}
+
+/// The spatial transformer of the UNet, attending over the pixels of the latents and to the text
+/// embeddings.
+#[derive(Debug, Clone)]
+pub struct Transformer2DModel {
+    norm: GroupNorm,
+    proj_in: Projection,
+    transformer_blocks: Vec<BasicTransformerBlock>,
+    proj_out: Projection,
+}
+
+impl Transformer2DModel {
+    pub fn new(
+        in_c: usize,
+        heads: usize,
+        num_layers: usize,
+        context_dim: usize,
+        groups: usize,
+        use_linear_projection: bool,
+        vb: ShardedVarBuilder,
+    ) -> Result<Self> {
+        let dim_head = in_c / heads;
+        let norm = group_norm(groups, in_c, 1e-6, vb.pp("norm"))?;
+        let proj_in = Projection::new(in_c, in_c, use_linear_projection, vb.pp("proj_in"))?;
+        let vb_t = vb.pp("transformer_blocks");
+        let transformer_blocks = (0..num_layers)
+            .map(|i| BasicTransformerBlock::new(in_c, heads, dim_head, context_dim, vb_t.pp(i)))
+            .collect::<Result<Vec<_>>>()?;
+        let proj_out = Projection::new(in_c, in_c, use_linear_projection, vb.pp("proj_out"))?;
+        Ok(Self {
+            norm,
+            proj_in,
+            transformer_blocks,
+            proj_out,
+        })
+    }
+
+    pub fn forward(&self, xs: &Tensor, context: &Tensor) -> Result<Tensor> {
+        let (b, c, h, w) = xs.dims4()?;
+        let residual = xs;
+        let xs = self.norm.forward(xs)?;
+        let mut xs = match &self.proj_in {
+            Projection::Conv(conv) => {
+                xs.apply(conv)?
+                    .permute((0, 2, 3, 1))?
+                    .reshape((b, h * w, c))?
+            }
+            Projection::Linear(linear) => xs
+                .permute((0, 2, 3, 1))?
+                .reshape((b, h * w, c))?
+                .apply(linear)?,
+        };
+        for block in &self.transformer_blocks {
+            xs = block.forward(&xs, context)?;
+        }
+        let xs = match &self.proj_out {
+            Projection::Conv(conv) => xs
+                .reshape((b, h, w, c))?
+                .permute((0, 3, 1, 2))?
+                .contiguous()?
+                .apply(conv)?,
+            Projection::Linear(linear) => xs
+                .apply(linear)?
+                .reshape((b, h, w, c))?
+                .permute((0, 3, 1, 2))?
+                .contiguous()?,
+        };
+        xs + residual
+    }
+}
@@ -0,0 +1,6 @@
+pub mod attention;
+pub mod resnet;
+pub mod schedulers;
+pub mod stepper;
+pub mod unet;
+pub mod vae;
@@ -0,0 +1,83 @@
+use candle_core::{Result, Tensor};
+use candle_nn::{Conv2d, GroupNorm, Linear, Module};
+use mistralrs_quant::ShardedVarBuilder;
+
+use crate::layers::{conv2d, group_norm, linear};
+
+/// The residual block of the UNet and the VAE. Only the UNet blocks have a time embedding.
+#[derive(Debug, Clone)]
+pub struct ResnetBlock2D {
+    norm1: GroupNorm,
+    conv1: Conv2d,
+    time_emb_proj: Option<Linear>,
+    norm2: GroupNorm,
+    conv2: Conv2d,
+    conv_shortcut: Option<Conv2d>,
+}
+
+impl ResnetBlock2D {
+    pub fn new(
+        in_c: usize,
+        out_c: usize,
+        temb_c: Option<usize>,
+        groups: usize,
+        eps: f64,
+        vb: ShardedVarBuilder,
+    ) -> Result<Self> {
+        let conv_cfg = candle_nn::Conv2dConfig {
+            padding: 1,
+            ..Default::default()
+        };
+        let norm1 = group_norm(groups, in_c, eps, vb.pp("norm1"))?;
+        let conv1 = conv2d(in_c, out_c, 3, conv_cfg, vb.pp("conv1"))?;
+        let time_emb_proj = temb_c
+            .map(|temb_c| linear(temb_c, out_c, vb.pp("time_emb_proj")))
+            .transpose()?;
+        let norm2 = group_norm(groups, out_c, eps, vb.pp("norm2"))?;
+        let conv2 = conv2d(out_c, out_c, 3, conv_cfg, vb.pp("conv2"))?;
+        let conv_shortcut = if in_c == out_c {
+            None
+        } else {
+            Some(conv2d(
+                in_c,
+                out_c,
+                1,
+                Default::default(),
+                vb.pp("conv_shortcut"),
+            )?)
+        };
+        Ok(Self {
+            norm1,
+            conv1,
+            time_emb_proj,
+            norm2,
+            conv2,
+            conv_shortcut,
+        })
+    }
+
+    pub fn forward(&self, xs: &Tensor, temb: Option<&Tensor>) -> Result<Tensor> {
+        let h = xs
+            .apply(&self.norm1)?
+            .apply(&candle_nn::Activation::Silu)?
+            .apply(&self.conv1)?;
+        let h = match (&self.time_emb_proj, temb) {
+            (Some(time_emb_proj), Some(temb)) => {
+                let temb = time_emb_proj
+                    .forward(&temb.apply(&candle_nn::Activation::Silu)?)?
+                    .unsqueeze(2)?
+                    .unsqueeze(3)?;
+                h.broadcast_add(&temb)?
+            }
+            _ => h,
+        };
+        let h = h
+            .apply(&self.norm2)?
+            .apply(&candle_nn::Activation::Silu)?
+            .apply(&self.conv2)?;
+        match &self.conv_shortcut {
+            None => xs + h,
+            Some(conv_shortcut) => xs.apply(conv_shortcut)? + h,
+        }
+    }
+}
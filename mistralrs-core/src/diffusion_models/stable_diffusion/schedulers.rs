@@ -0,0 +1,378 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use candle_core::{Result, Tensor};
+use serde::Deserialize;
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum BetaSchedule {
+    #[serde(rename = "linear")]
+    Linear,
+    #[serde(rename = "scaled_linear")]
+    ScaledLinear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum PredictionType {
+    #[serde(rename = "epsilon")]
+    Epsilon,
+    #[serde(rename = "v_prediction")]
+    VPrediction,
+}
+
+/// How the inference timesteps are spread over the training timesteps.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum TimestepSpacing {
+    #[serde(rename = "leading")]
+    Leading,
+    #[serde(rename = "trailing")]
+    Trailing,
+    #[serde(rename = "linspace")]
+    Linspace,
+}
+
+fn default_num_train_timesteps() -> usize {
+    1000
+}
+
+fn default_beta_start() -> f64 {
+    0.0001
+}
+
+fn default_beta_end() -> f64 {
+    0.02
+}
+
+fn default_beta_schedule() -> BetaSchedule {
+    BetaSchedule::Linear
+}
+
+fn default_prediction_type() -> PredictionType {
+    PredictionType::Epsilon
+}
+
+fn default_timestep_spacing() -> TimestepSpacing {
+    TimestepSpacing::Leading
+}
+
+fn default_set_alpha_to_one() -> bool {
+    true
+}
+
+/// The `scheduler/scheduler_config.json` of a diffusers Stable Diffusion model. Euler schedulers
+/// are run with [`EulerDiscreteScheduler`], and all others with [`DdimScheduler`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct SchedulerConfig {
+    #[serde(rename = "_class_name")]
+    pub class_name: String,
+    #[serde(default = "default_num_train_timesteps")]
+    pub num_train_timesteps: usize,
+    #[serde(default = "default_beta_start")]
+    pub beta_start: f64,
+    #[serde(default = "default_beta_end")]
+    pub beta_end: f64,
+    #[serde(default = "default_beta_schedule")]
+    pub beta_schedule: BetaSchedule,
+    #[serde(default = "default_prediction_type")]
+    pub prediction_type: PredictionType,
+    #[serde(default = "default_timestep_spacing")]
+    pub timestep_spacing: TimestepSpacing,
+    #[serde(default)]
+    pub steps_offset: usize,
+    #[serde(default = "default_set_alpha_to_one")]
+    pub set_alpha_to_one: bool,
+}
+
+impl SchedulerConfig {
+    pub fn is_euler(&self) -> bool {
+        self.class_name.starts_with("Euler")
+    }
+
+    /// Build the scheduler for a generation with `num_steps` steps.
+    pub fn build(&self, num_steps: usize) -> Box<dyn Scheduler> {
+        if self.is_euler() {
+            Box::new(EulerDiscreteScheduler::new(self, num_steps))
+        } else {
+            Box::new(DdimScheduler::new(self, num_steps))
+        }
+    }
+
+    /// Log the scheduler which will be used, as unsupported schedulers fall back to DDIM.
+    pub fn log_scheduler(&self) {
+        if self.is_euler() || self.class_name == "DDIMScheduler" {
+            info!("Using the {} scheduler.", self.class_name);
+        } else {
+            info!(
+                "The {} scheduler is not supported, using the DDIM scheduler.",
+                self.class_name
+            );
+        }
+    }
+
+    fn alphas_cumprod(&self) -> Vec<f64> {
+        let n = self.num_train_timesteps;
+        let betas: Vec<f64> = match self.beta_schedule {
+            BetaSchedule::Linear => linspace(self.beta_start, self.beta_end, n),
+            BetaSchedule::ScaledLinear => linspace(self.beta_start.sqrt(), self.beta_end.sqrt(), n)
+                .into_iter()
+                .map(|b| b * b)
+                .collect(),
+        };
+        betas
+            .iter()
+            .scan(1., |acc, beta| {
+                *acc *= 1. - beta;
+                Some(*acc)
+            })
+            .collect()
+    }
+
+    /// The training timesteps used for `num_steps` inference steps, in decreasing order.
+    fn timesteps(&self, num_steps: usize) -> Vec<usize> {
+        let n = self.num_train_timesteps;
+        match self.timestep_spacing {
+            TimestepSpacing::Leading => {
+                let step_ratio = n / num_steps;
+                (0..num_steps)
+                    .rev()
+                    .map(|i| (i * step_ratio + self.steps_offset).min(n - 1))
+                    .collect()
+            }
+            TimestepSpacing::Trailing => {
+                let step_ratio = n as f64 / num_steps as f64;
+                (0..num_steps)
+                    .map(|i| ((n as f64 - i as f64 * step_ratio).round() as usize).max(1) - 1)
+                    .collect()
+            }
+            TimestepSpacing::Linspace => linspace(0., (n - 1) as f64, num_steps)
+                .into_iter()
+                .rev()
+                .map(|t| t.round() as usize)
+                .collect(),
+        }
+    }
+}
+
+fn linspace(start: f64, end: f64, n: usize) -> Vec<f64> {
+    if n == 1 {
+        return vec![start];
+    }
+    let step = (end - start) / (n - 1) as f64;
+    (0..n).map(|i| start + step * i as f64).collect()
+}
+
+/// A sampler over the inference timesteps. Steps are referred to by their index in `timesteps`.
+pub trait Scheduler {
+    /// The timesteps given to the UNet, in decreasing order.
+    fn timesteps(&self) -> &[f64];
+    /// Scale of the initial noise.
+    fn init_noise_sigma(&self) -> f64;
+    /// Scale the latents before they are given to the UNet.
+    fn scale_model_input(&self, sample: Tensor, step: usize) -> Result<Tensor>;
+    /// Denoise `sample` with the UNet output at `step`, to the latents of the next step.
+    fn step(&self, model_output: &Tensor, step: usize, sample: &Tensor) -> Result<Tensor>;
+    /// Noise `original` to the noise level of `step`.
+    fn add_noise(&self, original: &Tensor, noise: &Tensor, step: usize) -> Result<Tensor>;
+}
+
+/// Deterministic DDIM sampling (eta = 0).
+pub struct DdimScheduler {
+    timesteps: Vec<f64>,
+    alphas_cumprod: Vec<f64>,
+    final_alpha_cumprod: f64,
+    step_ratio: usize,
+    prediction_type: PredictionType,
+}
+
+impl DdimScheduler {
+    pub fn new(cfg: &SchedulerConfig, num_steps: usize) -> Self {
+        let alphas_cumprod = cfg.alphas_cumprod();
+        let final_alpha_cumprod = if cfg.set_alpha_to_one {
+            1.
+        } else {
+            alphas_cumprod[0]
+        };
+        Self {
+            timesteps: cfg
+                .timesteps(num_steps)
+                .into_iter()
+                .map(|t| t as f64)
+                .collect(),
+            alphas_cumprod,
+            final_alpha_cumprod,
+            step_ratio: cfg.num_train_timesteps / num_steps,
+            prediction_type: cfg.prediction_type,
+        }
+    }
+}
+
+impl Scheduler for DdimScheduler {
+    fn timesteps(&self) -> &[f64] {
+        &self.timesteps
+    }
+
+    fn init_noise_sigma(&self) -> f64 {
+        1.
+    }
+
+    fn scale_model_input(&self, sample: Tensor, _step: usize) -> Result<Tensor> {
+        Ok(sample)
+    }
+
+    fn step(&self, model_output: &Tensor, step: usize, sample: &Tensor) -> Result<Tensor> {
+        let t = self.timesteps[step] as usize;
+        let alpha_prod_t = self.alphas_cumprod[t];
+        let alpha_prod_t_prev = t
+            .checked_sub(self.step_ratio)
+            .map(|t_prev| self.alphas_cumprod[t_prev])
+            .unwrap_or(self.final_alpha_cumprod);
+        let beta_prod_t = 1. - alpha_prod_t;
+
+        let (pred_original_sample, pred_epsilon) = match self.prediction_type {
+            PredictionType::Epsilon => (
+                ((sample - (model_output * beta_prod_t.sqrt())?)? / alpha_prod_t.sqrt())?,
+                model_output.clone(),
+            ),
+            PredictionType::VPrediction => (
+                ((sample * alpha_prod_t.sqrt())? - (model_output * beta_prod_t.sqrt())?)?,
+                ((model_output * alpha_prod_t.sqrt())? + (sample * beta_prod_t.sqrt())?)?,
+            ),
+        };
+        (pred_original_sample * alpha_prod_t_prev.sqrt())?
+            + (pred_epsilon * (1. - alpha_prod_t_prev).sqrt())?
+    }
+
+    fn add_noise(&self, original: &Tensor, noise: &Tensor, step: usize) -> Result<Tensor> {
+        let alpha_prod_t = self.alphas_cumprod[self.timesteps[step] as usize];
+        (original * alpha_prod_t.sqrt())? + (noise * (1. - alpha_prod_t).sqrt())?
+    }
+}
+
+/// Euler sampling of the probability flow ODE over the noise levels (sigmas).
+pub struct EulerDiscreteScheduler {
+    timesteps: Vec<f64>,
+    /// One more than the timesteps, ending at 0.
+    sigmas: Vec<f64>,
+    init_noise_sigma: f64,
+    prediction_type: PredictionType,
+}
+
+impl EulerDiscreteScheduler {
+    pub fn new(cfg: &SchedulerConfig, num_steps: usize) -> Self {
+        let alphas_cumprod = cfg.alphas_cumprod();
+        let timesteps = cfg.timesteps(num_steps);
+        let mut sigmas: Vec<f64> = timesteps
+            .iter()
+            .map(|t| ((1. - alphas_cumprod[*t]) / alphas_cumprod[*t]).sqrt())
+            .collect();
+        sigmas.push(0.);
+        let max_sigma = sigmas[0];
+        let init_noise_sigma = match cfg.timestep_spacing {
+            TimestepSpacing::Linspace | TimestepSpacing::Trailing => max_sigma,
+            TimestepSpacing::Leading => (max_sigma * max_sigma + 1.).sqrt(),
+        };
+        Self {
+            timesteps: timesteps.into_iter().map(|t| t as f64).collect(),
+            sigmas,
+            init_noise_sigma,
+            prediction_type: cfg.prediction_type,
+        }
+    }
+}
+
+impl Scheduler for EulerDiscreteScheduler {
+    fn timesteps(&self) -> &[f64] {
+        &self.timesteps
+    }
+
+    fn init_noise_sigma(&self) -> f64 {
+        self.init_noise_sigma
+    }
+
+    fn scale_model_input(&self, sample: Tensor, step: usize) -> Result<Tensor> {
+        let sigma = self.sigmas[step];
+        sample / (sigma * sigma + 1.).sqrt()
+    }
+
+    fn step(&self, model_output: &Tensor, step: usize, sample: &Tensor) -> Result<Tensor> {
+        let sigma = self.sigmas[step];
+        let pred_original_sample = match self.prediction_type {
+            PredictionType::Epsilon => (sample - (model_output * sigma)?)?,
+            PredictionType::VPrediction => {
+                let sigma_sq = sigma * sigma + 1.;
+                ((model_output * (-sigma / sigma_sq.sqrt()))? + (sample / sigma_sq)?)?
+            }
+        };
+        let derivative = ((sample - pred_original_sample)? / sigma)?;
+        let dt = self.sigmas[step + 1] - sigma;
+        sample + (derivative * dt)?
+    }
+
+    fn add_noise(&self, original: &Tensor, noise: &Tensor, step: usize) -> Result<Tensor> {
+        original + (noise * self.sigmas[step])?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{Device, Tensor};
+
+    use super::{Scheduler, SchedulerConfig};
+
+    fn config(class_name: &str) -> SchedulerConfig {
+        serde_json::from_str(&format!(
+            r#"{{
+                "_class_name": "{class_name}",
+                "beta_start": 0.00085,
+                "beta_end": 0.012,
+                "beta_schedule": "scaled_linear",
+                "steps_offset": 1,
+                "set_alpha_to_one": false
+            }}"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn leading_timesteps() {
+        let cfg = config("DDIMScheduler");
+        let timesteps = cfg.timesteps(50);
+        assert_eq!(timesteps.len(), 50);
+        assert_eq!(timesteps[0], 981);
+        assert_eq!(timesteps[49], 1);
+    }
+
+    #[test]
+    fn euler_sigmas() {
+        let scheduler = super::EulerDiscreteScheduler::new(&config("EulerDiscreteScheduler"), 30);
+        assert_eq!(scheduler.sigmas.len(), 31);
+        assert_eq!(scheduler.sigmas[30], 0.);
+        assert!(scheduler.sigmas.windows(2).all(|w| w[0] > w[1]));
+        // The first timestep is 958, and the noise is scaled for the "leading" spacing.
+        assert!((scheduler.init_noise_sigma - 11.52).abs() < 0.01);
+    }
+
+    #[test]
+    fn ddim_recovers_clean_sample() {
+        // With the exact noise as the prediction, the last step gives back the clean sample.
+        let mut cfg = config("DDIMScheduler");
+        cfg.set_alpha_to_one = true;
+        let scheduler = cfg.build(10);
+        let device = Device::Cpu;
+        let original = Tensor::new(&[0.5f32, -0.25, 1.0], &device).unwrap();
+        let noise = Tensor::new(&[1.0f32, -1.0, 0.3], &device).unwrap();
+        let last = scheduler.timesteps().len() - 1;
+        let noised = scheduler.add_noise(&original, &noise, last).unwrap();
+        let denoised = scheduler.step(&noise, last, &noised).unwrap();
+
+        let diff = (denoised - original)
+            .unwrap()
+            .abs()
+            .unwrap()
+            .max(0)
+            .unwrap()
+            .to_scalar::<f32>()
+            .unwrap();
+        assert!(diff < 1e-5);
+    }
+}
@@ -0,0 +1,420 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use candle_core::{DType, Device, Module, Result, Tensor, D};
+use candle_nn::Linear;
+use hf_hub::api::sync::Api;
+use image::{imageops::FilterType, DynamicImage};
+use mistralrs_quant::ShardedVarBuilder;
+use tokenizers::Tokenizer;
+use tracing::info;
+
+use crate::{
+    diffusion_models::{
+        clip::text::{ClipTextConfig, ClipTextTransformer},
+        seeded_randn, DiffusionGenerationParams,
+    },
+    layers::linear_no_bias,
+    pipeline::DiffusionModel,
+};
+
+use super::{
+    schedulers::SchedulerConfig,
+    unet::{self, AddedConditioning, UNet2DConditionModel},
+    vae::{self, AutoEncoderKL},
+};
+
+/// Both text encoders use the vocabulary of the OpenAI CLIP tokenizer.
+const CLIP_TOKENIZER_MODEL_ID: &str = "openai/clip-vit-large-patch14";
+
+#[derive(Clone, Copy, Debug)]
+pub struct StableDiffusionStepperConfig {
+    pub num_steps: usize,
+    pub guidance_scale: f64,
+    pub is_xl: bool,
+}
+
+impl StableDiffusionStepperConfig {
+    pub fn default_for_xl(is_xl: bool) -> Self {
+        if is_xl {
+            Self {
+                num_steps: 50,
+                guidance_scale: 5.0,
+                is_xl: true,
+            }
+        } else {
+            Self {
+                num_steps: 50,
+                guidance_scale: 7.5,
+                is_xl: false,
+            }
+        }
+    }
+}
+
+/// A CLIP text encoder with its padding token. The second text encoder of SDXL also has a
+/// projection for the pooled output.
+struct TextEncoder {
+    model: ClipTextTransformer,
+    text_projection: Option<Linear>,
+    pad_id: u32,
+    max_len: usize,
+}
+
+impl TextEncoder {
+    fn new(
+        vb: ShardedVarBuilder,
+        cfg: &ClipTextConfig,
+        tokenizer: &Tokenizer,
+        pad_token: &str,
+        with_projection: bool,
+    ) -> anyhow::Result<Self> {
+        let model = ClipTextTransformer::new(vb.pp("text_model"), cfg)?;
+        let text_projection = if with_projection {
+            Some(linear_no_bias(
+                cfg.projection_dim,
+                cfg.projection_dim,
+                vb.pp("text_projection"),
+            )?)
+        } else {
+            None
+        };
+        let pad_id = tokenizer
+            .token_to_id(pad_token)
+            .ok_or_else(|| anyhow::anyhow!("Tokenizer has no `{pad_token}` token."))?;
+        Ok(Self {
+            model,
+            text_projection,
+            pad_id,
+            max_len: cfg.max_position_embeddings,
+        })
+    }
+
+    /// Tokenize to exactly `max_len` tokens, truncating long prompts but keeping the end of text
+    /// token.
+    fn tokenize(&self, tok: &Tokenizer, prompts: Vec<String>, device: &Device) -> Result<Tensor> {
+        let ids = tok
+            .encode_batch(prompts, true)
+            .map_err(|e| candle_core::Error::Msg(e.to_string()))?
+            .into_iter()
+            .map(|encoding| {
+                let mut ids = encoding.get_ids().to_vec();
+                if ids.len() > self.max_len {
+                    let eos = ids[ids.len() - 1];
+                    ids.truncate(self.max_len - 1);
+                    ids.push(eos);
+                }
+                ids.resize(self.max_len, self.pad_id);
+                ids
+            })
+            .collect::<Vec<_>>();
+        Tensor::new(ids, device)
+    }
+}
+
+/// The text conditioning of a batch of prompts.
+struct TextEmbeddings {
+    context: Tensor,
+    /// Only for SDXL.
+    pooled: Option<Tensor>,
+}
+
+pub struct StableDiffusionStepper {
+    cfg: StableDiffusionStepperConfig,
+    tokenizer: Tokenizer,
+    text_encoders: Vec<TextEncoder>,
+    unet: UNet2DConditionModel,
+    vae: AutoEncoderKL,
+    scheduler_cfg: SchedulerConfig,
+    device: Device,
+    dtype: DType,
+}
+
+fn get_clip_tokenizer(api: &Api) -> anyhow::Result<Tokenizer> {
+    let tokenizer_filename = api
+        .model(CLIP_TOKENIZER_MODEL_ID.to_string())
+        .get("tokenizer.json")?;
+    Tokenizer::from_file(tokenizer_filename).map_err(anyhow::Error::msg)
+}
+
+/// The image resized to the generated size, with values in [-1, 1] and shape (1, 3, h, w).
+fn preprocess_image(
+    image: &DynamicImage,
+    height: usize,
+    width: usize,
+    device: &Device,
+) -> Result<Tensor> {
+    let image = image
+        .resize_exact(width as u32, height as u32, FilterType::CatmullRom)
+        .to_rgb8();
+    let image = Tensor::from_vec(image.into_raw(), (height, width, 3), device)?
+        .permute((2, 0, 1))?
+        .to_dtype(DType::F32)?;
+    ((image / 127.5)? - 1.)?.unsqueeze(0)
+}
+
+/// The inpainting mask at the resolution of the latents, with values in [0, 1] and shape (1, 1, h, w).
+fn preprocess_mask(
+    mask: &DynamicImage,
+    height: usize,
+    width: usize,
+    device: &Device,
+) -> Result<Tensor> {
+    let mask = mask
+        .resize_exact(width as u32, height as u32, FilterType::Triangle)
+        .to_luma8();
+    Tensor::from_vec(mask.into_raw(), (1, 1, height, width), device)?.to_dtype(DType::F32)? / 255.
+}
+
+/// Each sample uses the same `seed`, so a sample does not depend on the others in the batch.
+fn get_noise(
+    num_samples: usize,
+    shape: (usize, usize, usize),
+    seed: Option<u64>,
+    device: &Device,
+) -> Result<Tensor> {
+    let (c, h, w) = shape;
+    match seed {
+        Some(seed) => seeded_randn(seed, (1, c, h, w), device)?.repeat((num_samples, 1, 1, 1)),
+        None => Tensor::randn(0f32, 1., (num_samples, c, h, w), device),
+    }
+}
+
+impl StableDiffusionStepper {
+    /// `text_encoders` are the CLIP text encoders, with the OpenCLIP encoder second for SDXL.
+    pub fn new(
+        cfg: StableDiffusionStepperConfig,
+        (unet_vb, unet_cfg): (ShardedVarBuilder, &unet::Config),
+        (vae_vb, vae_cfg): (ShardedVarBuilder, &vae::Config),
+        text_encoders: Vec<(ShardedVarBuilder, ClipTextConfig)>,
+        scheduler_cfg: SchedulerConfig,
+        dtype: DType,
+        device: &Device,
+    ) -> anyhow::Result<Self> {
+        let api = Api::new()?;
+
+        info!("Loading CLIP tokenizer.");
+        let tokenizer = get_clip_tokenizer(&api)?;
+
+        let expected_encoders = if cfg.is_xl { 2 } else { 1 };
+        if text_encoders.len() != expected_encoders {
+            anyhow::bail!(
+                "Expected {expected_encoders} text encoders, got {}.",
+                text_encoders.len()
+            );
+        }
+        // The first text encoder pads with the end of text token, and the OpenCLIP one with `!`.
+        let text_encoders = text_encoders
+            .into_iter()
+            .enumerate()
+            .map(|(i, (vb, text_cfg))| {
+                let pad_token = if i == 0 { "<|endoftext|>" } else { "!" };
+                TextEncoder::new(vb, &text_cfg, &tokenizer, pad_token, i == 1)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        scheduler_cfg.log_scheduler();
+
+        Ok(Self {
+            cfg,
+            tokenizer,
+            text_encoders,
+            unet: UNet2DConditionModel::new(unet_cfg, unet_vb)?,
+            vae: AutoEncoderKL::new(vae_cfg, vae_vb)?,
+            scheduler_cfg,
+            device: device.clone(),
+            dtype,
+        })
+    }
+
+    /// SD 1.x uses the last hidden states of its text encoder. SDXL concatenates the penultimate
+    /// hidden states of both text encoders, and also uses the pooled output of the second one.
+    fn embed(&self, prompts: Vec<String>) -> Result<TextEmbeddings> {
+        if !self.cfg.is_xl {
+            let encoder = &self.text_encoders[0];
+            let input_ids = encoder.tokenize(&self.tokenizer, prompts, &self.device)?;
+            let context = encoder.model.forward_with_mask(&input_ids, usize::MAX)?;
+            return Ok(TextEmbeddings {
+                context,
+                pooled: None,
+            });
+        }
+
+        let mut contexts = Vec::with_capacity(self.text_encoders.len());
+        let mut pooled = None;
+        for encoder in &self.text_encoders {
+            let input_ids = encoder.tokenize(&self.tokenizer, prompts.clone(), &self.device)?;
+            let (hidden_states, pooled_output) =
+                encoder.model.forward_with_penultimate(&input_ids)?;
+            contexts.push(hidden_states);
+            if let Some(text_projection) = &encoder.text_projection {
+                pooled = Some(text_projection.forward(&pooled_output)?);
+            }
+        }
+        Ok(TextEmbeddings {
+            context: Tensor::cat(&contexts, D::Minus1)?,
+            pooled,
+        })
+    }
+
+    /// The unconditional embeddings for classifier-free guidance. Without a negative prompt,
+    /// SDXL uses zeros and SD 1.x uses the empty prompt.
+    fn negative_embed(&self, negative_prompt: Option<&str>, bs: usize) -> Result<TextEmbeddings> {
+        let embeds = match negative_prompt {
+            None if self.cfg.is_xl => {
+                let TextEmbeddings { context, pooled } = self.embed(vec![String::new()])?;
+                TextEmbeddings {
+                    context: context.zeros_like()?,
+                    pooled: pooled.map(|pooled| pooled.zeros_like()).transpose()?,
+                }
+            }
+            negative_prompt => self.embed(vec![negative_prompt.unwrap_or_default().to_string()])?,
+        };
+        Ok(TextEmbeddings {
+            context: embeds.context.repeat((bs, 1, 1))?,
+            pooled: embeds
+                .pooled
+                .map(|pooled| pooled.repeat((bs, 1)))
+                .transpose()?,
+        })
+    }
+}
+
+impl DiffusionModel for StableDiffusionStepper {
+    fn forward(
+        &mut self,
+        prompts: Vec<String>,
+        params: DiffusionGenerationParams,
+    ) -> Result<Tensor> {
+        let bs = prompts.len();
+        let guidance_scale = params.guidance_scale.unwrap_or(self.cfg.guidance_scale);
+        let do_cfg = guidance_scale > 1.;
+
+        // With classifier-free guidance, the unconditional and conditional batches are run together.
+        let embeds = self.embed(prompts)?;
+        let embeds = if do_cfg {
+            let negative = self.negative_embed(params.negative_prompt.as_deref(), bs)?;
+            TextEmbeddings {
+                context: Tensor::cat(&[negative.context, embeds.context], 0)?,
+                pooled: match (negative.pooled, embeds.pooled) {
+                    (Some(negative), Some(pooled)) => Some(Tensor::cat(&[negative, pooled], 0)?),
+                    _ => None,
+                },
+            }
+        } else {
+            embeds
+        };
+        let context = embeds.context.to_dtype(self.dtype)?;
+
+        let (height, width) = ((params.height + 7) / 8 * 8, (params.width + 7) / 8 * 8);
+        let added_cond = match embeds.pooled {
+            Some(pooled) => {
+                let model_bs = pooled.dim(0)?;
+                let (h, w) = (height as f32, width as f32);
+                // The original size, crop top left coordinates and target size.
+                let time_ids =
+                    Tensor::new(&[[h, w, 0., 0., h, w]], &self.device)?.repeat((model_bs, 1))?;
+                Some(AddedConditioning {
+                    text_embeds: pooled.to_dtype(self.dtype)?,
+                    time_ids,
+                })
+            }
+            None => None,
+        };
+
+        let num_steps = params.num_steps.unwrap_or(self.cfg.num_steps);
+        let scheduler = self.scheduler_cfg.build(num_steps);
+        let timesteps = scheduler.timesteps().to_vec();
+
+        // The latents are kept in f32, and only the UNet runs in the model dtype.
+        let latent_shape = (4, height / 8, width / 8);
+        let noise = get_noise(bs, latent_shape, params.seed, &self.device)?;
+
+        let image_latents = match &params.init_image {
+            Some(image) => {
+                let image =
+                    preprocess_image(image, height, width, &self.device)?.to_dtype(self.dtype)?;
+                let latents = self
+                    .vae
+                    .encode_mean(&image)?
+                    .to_dtype(DType::F32)?
+                    .repeat((bs, 1, 1, 1))?;
+                Some(latents)
+            }
+            None => None,
+        };
+        let mask = match &params.mask_image {
+            Some(mask) => Some(
+                preprocess_mask(mask, height / 8, width / 8, &self.device)?.repeat((
+                    bs,
+                    latent_shape.0,
+                    1,
+                    1,
+                ))?,
+            ),
+            None => None,
+        };
+
+        // For img2img, skip the start of the schedule and start from the noised image.
+        let (mut latents, start_step) = match &image_latents {
+            Some(image_latents) => {
+                let init_steps =
+                    ((num_steps as f64 * params.strength).round() as usize).min(num_steps);
+                let start_step = num_steps - init_steps;
+                let latents = if start_step < num_steps {
+                    scheduler.add_noise(image_latents, &noise, start_step)?
+                } else {
+                    image_latents.clone()
+                };
+                (latents, start_step)
+            }
+            None => ((&noise * scheduler.init_noise_sigma())?, 0),
+        };
+
+        for (step, timestep) in timesteps.iter().enumerate().skip(start_step) {
+            let model_input = if do_cfg {
+                Tensor::cat(&[&latents, &latents], 0)?
+            } else {
+                latents.clone()
+            };
+            let model_input = scheduler
+                .scale_model_input(model_input, step)?
+                .to_dtype(self.dtype)?;
+            let pred = self
+                .unet
+                .forward(&model_input, *timestep, &context, added_cond.as_ref())?
+                .to_dtype(DType::F32)?;
+            let pred = if do_cfg {
+                let chunks = pred.chunk(2, 0)?;
+                let (uncond, cond) = (&chunks[0], &chunks[1]);
+                (uncond + ((cond - uncond)? * guidance_scale)?)?
+            } else {
+                pred
+            };
+            latents = scheduler.step(&pred, step, &latents)?;
+
+            // For inpainting, keep the image outside of the mask, noised to the next step.
+            if let (Some(mask), Some(image_latents)) = (&mask, &image_latents) {
+                let image_latents = if step + 1 < timesteps.len() {
+                    scheduler.add_noise(image_latents, &noise, step + 1)?
+                } else {
+                    image_latents.clone()
+                };
+                latents = (mask.mul(&latents)? + mask.affine(-1., 1.)?.mul(&image_latents)?)?;
+            }
+        }
+
+        let img = self.vae.decode(&latents.to_dtype(self.dtype)?)?;
+
+        let normalized_img = ((img.clamp(-1f32, 1f32)? + 1.0)? * 127.5)?.to_dtype(DType::U8)?;
+
+        Ok(normalized_img)
+    }
+
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn max_seq_len(&self) -> usize {
+        self.text_encoders[0].max_len
+    }
+}
@@ -0,0 +1,523 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use candle_core::{DType, Module, Result, Tensor};
+use candle_nn::{Conv2d, GroupNorm, Linear};
+use mistralrs_quant::ShardedVarBuilder;
+use serde::Deserialize;
+
+use crate::layers::{conv2d, group_norm, linear};
+
+use super::{attention::Transformer2DModel, resnet::ResnetBlock2D};
+
+/// A value given either for all of the blocks, or for each block.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum PerBlock {
+    All(usize),
+    Each(Vec<usize>),
+}
+
+impl PerBlock {
+    fn get(&self, i: usize) -> usize {
+        match self {
+            Self::All(v) => *v,
+            Self::Each(vs) => vs[i],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum DownBlockType {
+    CrossAttnDownBlock2D,
+    DownBlock2D,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum UpBlockType {
+    CrossAttnUpBlock2D,
+    UpBlock2D,
+}
+
+fn default_transformer_layers_per_block() -> PerBlock {
+    PerBlock::All(1)
+}
+
+fn default_norm_num_groups() -> usize {
+    32
+}
+
+fn default_norm_eps() -> f64 {
+    1e-5
+}
+
+fn default_flip_sin_to_cos() -> bool {
+    true
+}
+
+/// The `unet/config.json` of a diffusers Stable Diffusion model.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    pub in_channels: usize,
+    pub out_channels: usize,
+    pub block_out_channels: Vec<usize>,
+    pub layers_per_block: usize,
+    pub down_block_types: Vec<DownBlockType>,
+    pub up_block_types: Vec<UpBlockType>,
+    pub cross_attention_dim: usize,
+    /// Despite the name, diffusers uses this as the number of heads when `num_attention_heads`
+    /// is not set.
+    pub attention_head_dim: PerBlock,
+    #[serde(default)]
+    pub num_attention_heads: Option<PerBlock>,
+    #[serde(default = "default_transformer_layers_per_block")]
+    pub transformer_layers_per_block: PerBlock,
+    #[serde(default)]
+    pub use_linear_projection: bool,
+    #[serde(default = "default_norm_num_groups")]
+    pub norm_num_groups: usize,
+    #[serde(default = "default_norm_eps")]
+    pub norm_eps: f64,
+    #[serde(default = "default_flip_sin_to_cos")]
+    pub flip_sin_to_cos: bool,
+    #[serde(default)]
+    pub freq_shift: f64,
+    /// `text_time` for SDXL, which is conditioned on the pooled text embeddings and image size.
+    #[serde(default)]
+    pub addition_embed_type: Option<String>,
+    #[serde(default)]
+    pub addition_time_embed_dim: Option<usize>,
+    #[serde(default)]
+    pub projection_class_embeddings_input_dim: Option<usize>,
+}
+
+impl Config {
+    fn num_heads(&self, i: usize) -> usize {
+        self.num_attention_heads
+            .as_ref()
+            .unwrap_or(&self.attention_head_dim)
+            .get(i)
+    }
+}
+
+/// Sinusoidal embedding of the timesteps.
+#[derive(Debug, Clone)]
+struct Timesteps {
+    dim: usize,
+    flip_sin_to_cos: bool,
+    freq_shift: f64,
+}
+
+impl Timesteps {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let half_dim = self.dim / 2;
+        let exponent = (Tensor::arange(0u32, half_dim as u32, xs.device())?
+            .to_dtype(DType::F32)?
+            * (-(10000f64.ln()) / (half_dim as f64 - self.freq_shift)))?
+            .exp()?;
+        let emb = xs
+            .to_dtype(DType::F32)?
+            .unsqueeze(1)?
+            .broadcast_mul(&exponent.unsqueeze(0)?)?;
+        let (sin, cos) = (emb.sin()?, emb.cos()?);
+        if self.flip_sin_to_cos {
+            Tensor::cat(&[cos, sin], 1)
+        } else {
+            Tensor::cat(&[sin, cos], 1)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TimestepEmbedding {
+    linear_1: Linear,
+    linear_2: Linear,
+}
+
+impl TimestepEmbedding {
+    fn new(in_dim: usize, time_embed_dim: usize, vb: ShardedVarBuilder) -> Result<Self> {
+        Ok(Self {
+            linear_1: linear(in_dim, time_embed_dim, vb.pp("linear_1"))?,
+            linear_2: linear(time_embed_dim, time_embed_dim, vb.pp("linear_2"))?,
+        })
+    }
+}
+
+impl Module for TimestepEmbedding {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        xs.apply(&self.linear_1)?
+            .apply(&candle_nn::Activation::Silu)?
+            .apply(&self.linear_2)
+    }
+}
+
+/// The conditioning of SDXL besides the text embeddings.
+/// - `text_embeds`: Pooled output of the second text encoder, of shape (b, dim).
+/// - `time_ids`: Original size, crop coordinates and target size of the image, of shape (b, 6).
+pub struct AddedConditioning {
+    pub text_embeds: Tensor,
+    pub time_ids: Tensor,
+}
+
+#[derive(Debug, Clone)]
+struct DownBlock {
+    resnets: Vec<ResnetBlock2D>,
+    attentions: Vec<Transformer2DModel>,
+    downsampler: Option<Conv2d>,
+}
+
+impl DownBlock {
+    fn forward(
+        &self,
+        xs: &Tensor,
+        temb: &Tensor,
+        context: &Tensor,
+        res_samples: &mut Vec<Tensor>,
+    ) -> Result<Tensor> {
+        let mut xs = xs.clone();
+        for (i, resnet) in self.resnets.iter().enumerate() {
+            xs = resnet.forward(&xs, Some(temb))?;
+            if let Some(attn) = self.attentions.get(i) {
+                xs = attn.forward(&xs, context)?;
+            }
+            res_samples.push(xs.clone());
+        }
+        if let Some(downsampler) = &self.downsampler {
+            xs = xs.apply(downsampler)?;
+            res_samples.push(xs.clone());
+        }
+        Ok(xs)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MidBlock {
+    resnet_1: ResnetBlock2D,
+    attention: Transformer2DModel,
+    resnet_2: ResnetBlock2D,
+}
+
+impl MidBlock {
+    fn forward(&self, xs: &Tensor, temb: &Tensor, context: &Tensor) -> Result<Tensor> {
+        let xs = self.resnet_1.forward(xs, Some(temb))?;
+        let xs = self.attention.forward(&xs, context)?;
+        self.resnet_2.forward(&xs, Some(temb))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct UpBlock {
+    resnets: Vec<ResnetBlock2D>,
+    attentions: Vec<Transformer2DModel>,
+    upsampler: Option<Conv2d>,
+}
+
+impl UpBlock {
+    fn forward(
+        &self,
+        xs: &Tensor,
+        temb: &Tensor,
+        context: &Tensor,
+        res_samples: &mut Vec<Tensor>,
+    ) -> Result<Tensor> {
+        let mut xs = xs.clone();
+        for (i, resnet) in self.resnets.iter().enumerate() {
+            let res_sample = res_samples
+                .pop()
+                .ok_or_else(|| candle_core::Error::msg("Missing UNet skip connection."))?;
+            xs = resnet.forward(&Tensor::cat(&[xs, res_sample], 1)?, Some(temb))?;
+            if let Some(attn) = self.attentions.get(i) {
+                xs = attn.forward(&xs, context)?;
+            }
+        }
+        if let Some(upsampler) = &self.upsampler {
+            // Upsample to the size of the next skip connection, which handles odd sizes.
+            let (h, w) = match res_samples.last() {
+                Some(res_sample) => {
+                    let (_, _, h, w) = res_sample.dims4()?;
+                    (h, w)
+                }
+                None => {
+                    let (_, _, h, w) = xs.dims4()?;
+                    (h * 2, w * 2)
+                }
+            };
+            xs = xs.upsample_nearest2d(h, w)?.apply(upsampler)?;
+        }
+        Ok(xs)
+    }
+}
+
+/// The conditional UNet of Stable Diffusion 1.x, 2.x and SDXL, with the diffusers weight names.
+#[derive(Debug, Clone)]
+pub struct UNet2DConditionModel {
+    conv_in: Conv2d,
+    time_proj: Timesteps,
+    time_embedding: TimestepEmbedding,
+    add_time_proj: Option<Timesteps>,
+    add_embedding: Option<TimestepEmbedding>,
+    down_blocks: Vec<DownBlock>,
+    mid_block: MidBlock,
+    up_blocks: Vec<UpBlock>,
+    conv_norm_out: GroupNorm,
+    conv_out: Conv2d,
+    dtype: DType,
+}
+
+impl UNet2DConditionModel {
+    pub fn new(cfg: &Config, vb: ShardedVarBuilder) -> Result<Self> {
+        let n_blocks = cfg.block_out_channels.len();
+        if cfg.down_block_types.len() != n_blocks || cfg.up_block_types.len() != n_blocks {
+            candle_core::bail!(
+                "Expected {n_blocks} down and up blocks, got {} and {}.",
+                cfg.down_block_types.len(),
+                cfg.up_block_types.len()
+            );
+        }
+        let conv_cfg = candle_nn::Conv2dConfig {
+            padding: 1,
+            ..Default::default()
+        };
+        let groups = cfg.norm_num_groups;
+        let eps = cfg.norm_eps;
+        let base_c = cfg.block_out_channels[0];
+        let time_embed_dim = base_c * 4;
+
+        let conv_in = conv2d(cfg.in_channels, base_c, 3, conv_cfg, vb.pp("conv_in"))?;
+        let time_proj = Timesteps {
+            dim: base_c,
+            flip_sin_to_cos: cfg.flip_sin_to_cos,
+            freq_shift: cfg.freq_shift,
+        };
+        let time_embedding =
+            TimestepEmbedding::new(base_c, time_embed_dim, vb.pp("time_embedding"))?;
+
+        let (add_time_proj, add_embedding) = match cfg.addition_embed_type.as_deref() {
+            Some("text_time") => {
+                let (Some(time_dim), Some(in_dim)) = (
+                    cfg.addition_time_embed_dim,
+                    cfg.projection_class_embeddings_input_dim,
+                ) else {
+                    candle_core::bail!("`text_time` additional embeddings require `addition_time_embed_dim` and `projection_class_embeddings_input_dim`.");
+                };
+                let add_time_proj = Timesteps {
+                    dim: time_dim,
+                    flip_sin_to_cos: cfg.flip_sin_to_cos,
+                    freq_shift: cfg.freq_shift,
+                };
+                let add_embedding =
+                    TimestepEmbedding::new(in_dim, time_embed_dim, vb.pp("add_embedding"))?;
+                (Some(add_time_proj), Some(add_embedding))
+            }
+            Some(other) => candle_core::bail!("Unsupported `addition_embed_type` `{other}`."),
+            None => (None, None),
+        };
+
+        let mut down_blocks = Vec::with_capacity(n_blocks);
+        let vb_d = vb.pp("down_blocks");
+        let mut out_c = base_c;
+        for (i, block_type) in cfg.down_block_types.iter().enumerate() {
+            let in_c = out_c;
+            out_c = cfg.block_out_channels[i];
+            let vb_d = vb_d.pp(i);
+            let resnets = (0..cfg.layers_per_block)
+                .map(|j| {
+                    let resnet_in_c = if j == 0 { in_c } else { out_c };
+                    ResnetBlock2D::new(
+                        resnet_in_c,
+                        out_c,
+                        Some(time_embed_dim),
+                        groups,
+                        eps,
+                        vb_d.pp("resnets").pp(j),
+                    )
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let attentions = match block_type {
+                DownBlockType::CrossAttnDownBlock2D => (0..cfg.layers_per_block)
+                    .map(|j| {
+                        Transformer2DModel::new(
+                            out_c,
+                            cfg.num_heads(i),
+                            cfg.transformer_layers_per_block.get(i),
+                            cfg.cross_attention_dim,
+                            groups,
+                            cfg.use_linear_projection,
+                            vb_d.pp("attentions").pp(j),
+                        )
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+                DownBlockType::DownBlock2D => Vec::new(),
+            };
+            let downsampler = if i != n_blocks - 1 {
+                let conv_cfg = candle_nn::Conv2dConfig {
+                    padding: 1,
+                    stride: 2,
+                    ..Default::default()
+                };
+                Some(conv2d(
+                    out_c,
+                    out_c,
+                    3,
+                    conv_cfg,
+                    vb_d.pp("downsamplers.0.conv"),
+                )?)
+            } else {
+                None
+            };
+            down_blocks.push(DownBlock {
+                resnets,
+                attentions,
+                downsampler,
+            });
+        }
+
+        let mid_c = cfg.block_out_channels[n_blocks - 1];
+        let vb_m = vb.pp("mid_block");
+        let mid_block = MidBlock {
+            resnet_1: ResnetBlock2D::new(
+                mid_c,
+                mid_c,
+                Some(time_embed_dim),
+                groups,
+                eps,
+                vb_m.pp("resnets.0"),
+            )?,
+            attention: Transformer2DModel::new(
+                mid_c,
+                cfg.num_heads(n_blocks - 1),
+                cfg.transformer_layers_per_block.get(n_blocks - 1),
+                cfg.cross_attention_dim,
+                groups,
+                cfg.use_linear_projection,
+                vb_m.pp("attentions.0"),
+            )?,
+            resnet_2: ResnetBlock2D::new(
+                mid_c,
+                mid_c,
+                Some(time_embed_dim),
+                groups,
+                eps,
+                vb_m.pp("resnets.1"),
+            )?,
+        };
+
+        // The up blocks mirror the down blocks, so the per block settings are reversed.
+        let mut up_blocks = Vec::with_capacity(n_blocks);
+        let vb_u = vb.pp("up_blocks");
+        let mut out_c = mid_c;
+        for (i, block_type) in cfg.up_block_types.iter().enumerate() {
+            let rev_i = n_blocks - 1 - i;
+            let prev_out_c = out_c;
+            out_c = cfg.block_out_channels[rev_i];
+            let in_c = cfg.block_out_channels[rev_i.saturating_sub(1)];
+            let num_layers = cfg.layers_per_block + 1;
+            let vb_u = vb_u.pp(i);
+            let resnets = (0..num_layers)
+                .map(|j| {
+                    let res_skip_c = if j == num_layers - 1 { in_c } else { out_c };
+                    let resnet_in_c = if j == 0 { prev_out_c } else { out_c };
+                    ResnetBlock2D::new(
+                        resnet_in_c + res_skip_c,
+                        out_c,
+                        Some(time_embed_dim),
+                        groups,
+                        eps,
+                        vb_u.pp("resnets").pp(j),
+                    )
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let attentions = match block_type {
+                UpBlockType::CrossAttnUpBlock2D => (0..num_layers)
+                    .map(|j| {
+                        Transformer2DModel::new(
+                            out_c,
+                            cfg.num_heads(rev_i),
+                            cfg.transformer_layers_per_block.get(rev_i),
+                            cfg.cross_attention_dim,
+                            groups,
+                            cfg.use_linear_projection,
+                            vb_u.pp("attentions").pp(j),
+                        )
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+                UpBlockType::UpBlock2D => Vec::new(),
+            };
+            let upsampler = if i != n_blocks - 1 {
+                Some(conv2d(
+                    out_c,
+                    out_c,
+                    3,
+                    conv_cfg,
+                    vb_u.pp("upsamplers.0.conv"),
+                )?)
+            } else {
+                None
+            };
+            up_blocks.push(UpBlock {
+                resnets,
+                attentions,
+                upsampler,
+            });
+        }
+
+        let conv_norm_out = group_norm(groups, base_c, eps, vb.pp("conv_norm_out"))?;
+        let conv_out = conv2d(base_c, cfg.out_channels, 3, conv_cfg, vb.pp("conv_out"))?;
+
+        Ok(Self {
+            conv_in,
+            time_proj,
+            time_embedding,
+            add_time_proj,
+            add_embedding,
+            down_blocks,
+            mid_block,
+            up_blocks,
+            conv_norm_out,
+            conv_out,
+            dtype: vb.dtype(),
+        })
+    }
+
+    /// Predict the noise (or velocity) of the latents `xs` of shape (b, c, h, w) at `timestep`,
+    /// conditioned on the text embeddings `context` of shape (b, seq_len, dim).
+    pub fn forward(
+        &self,
+        xs: &Tensor,
+        timestep: f64,
+        context: &Tensor,
+        added_cond: Option<&AddedConditioning>,
+    ) -> Result<Tensor> {
+        let bs = xs.dim(0)?;
+        let timesteps = Tensor::full(timestep as f32, bs, xs.device())?;
+        let mut temb = self
+            .time_embedding
+            .forward(&self.time_proj.forward(&timesteps)?.to_dtype(self.dtype)?)?;
+        match (&self.add_time_proj, &self.add_embedding, added_cond) {
+            (Some(add_time_proj), Some(add_embedding), Some(added_cond)) => {
+                let time_embeds = add_time_proj
+                    .forward(&added_cond.time_ids.flatten_all()?)?
+                    .reshape((bs, ()))?
+                    .to_dtype(self.dtype)?;
+                let add_embeds = Tensor::cat(&[&added_cond.text_embeds, &time_embeds], 1)?;
+                temb = (temb + add_embedding.forward(&add_embeds)?)?;
+            }
+            (Some(_), Some(_), None) => {
+                candle_core::bail!("This UNet requires the pooled text embeddings and time ids.")
+            }
+            _ => (),
+        }
+
+        let mut xs = xs.apply(&self.conv_in)?;
+        let mut res_samples = vec![xs.clone()];
+        for block in &self.down_blocks {
+            xs = block.forward(&xs, &temb, context, &mut res_samples)?;
+        }
+        xs = self.mid_block.forward(&xs, &temb, context)?;
+        for block in &self.up_blocks {
+            xs = block.forward(&xs, &temb, context, &mut res_samples)?;
+        }
+        xs.apply(&self.conv_norm_out)?
+            .apply(&candle_nn::Activation::Silu)?
+            .apply(&self.conv_out)
+    }
+}
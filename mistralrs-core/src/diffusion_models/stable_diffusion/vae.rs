@@ -0,0 +1,340 @@
+use candle_core::{Module, Result, Tensor, D};
+use candle_nn::{Conv2d, GroupNorm, Linear};
+use mistralrs_quant::ShardedVarBuilder;
+use serde::Deserialize;
+
+use crate::layers::{conv2d, group_norm, linear};
+
+use super::{attention::scaled_dot_product_attention, resnet::ResnetBlock2D};
+
+fn default_norm_num_groups() -> usize {
+    32
+}
+
+fn default_scaling_factor() -> f64 {
+    0.18215
+}
+
+/// The `vae/config.json` of a diffusers Stable Diffusion model.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    pub in_channels: usize,
+    pub out_channels: usize,
+    pub block_out_channels: Vec<usize>,
+    pub layers_per_block: usize,
+    pub latent_channels: usize,
+    #[serde(default = "default_norm_num_groups")]
+    pub norm_num_groups: usize,
+    #[serde(default = "default_scaling_factor")]
+    pub scaling_factor: f64,
+}
+
+/// Single head self attention over the pixels. Older checkpoints use the `query`, `key`, `value`
+/// and `proj_attn` names.
+#[derive(Debug, Clone)]
+struct AttentionBlock {
+    group_norm: GroupNorm,
+    to_q: Linear,
+    to_k: Linear,
+    to_v: Linear,
+    to_out: Linear,
+}
+
+impl AttentionBlock {
+    fn new(c: usize, cfg: &Config, vb: ShardedVarBuilder) -> Result<Self> {
+        let group_norm = group_norm(cfg.norm_num_groups, c, 1e-6, vb.pp("group_norm"))?;
+        let names = if vb.contains_tensor("query.weight") {
+            ["query", "key", "value", "proj_attn"]
+        } else {
+            ["to_q", "to_k", "to_v", "to_out.0"]
+        };
+        Ok(Self {
+            group_norm,
+            to_q: linear(c, c, vb.pp(names[0]))?,
+            to_k: linear(c, c, vb.pp(names[1]))?,
+            to_v: linear(c, c, vb.pp(names[2]))?,
+            to_out: linear(c, c, vb.pp(names[3]))?,
+        })
+    }
+}
+
+impl Module for AttentionBlock {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let (b, c, h, w) = xs.dims4()?;
+        let residual = xs;
+        let xs = xs
+            .apply(&self.group_norm)?
+            .flatten_from(2)?
+            .transpose(1, 2)?
+            .contiguous()?;
+        let q = xs.apply(&self.to_q)?.unsqueeze(1)?;
+        let k = xs.apply(&self.to_k)?.unsqueeze(1)?;
+        let v = xs.apply(&self.to_v)?.unsqueeze(1)?;
+        let xs = scaled_dot_product_attention(&q, &k, &v)?
+            .squeeze(1)?
+            .apply(&self.to_out)?;
+        let xs = xs.transpose(1, 2)?.reshape((b, c, h, w))?;
+        xs + residual
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MidBlock {
+    resnet_1: ResnetBlock2D,
+    attention: AttentionBlock,
+    resnet_2: ResnetBlock2D,
+}
+
+impl MidBlock {
+    fn new(c: usize, cfg: &Config, vb: ShardedVarBuilder) -> Result<Self> {
+        let groups = cfg.norm_num_groups;
+        Ok(Self {
+            resnet_1: ResnetBlock2D::new(c, c, None, groups, 1e-6, vb.pp("resnets.0"))?,
+            attention: AttentionBlock::new(c, cfg, vb.pp("attentions.0"))?,
+            resnet_2: ResnetBlock2D::new(c, c, None, groups, 1e-6, vb.pp("resnets.1"))?,
+        })
+    }
+}
+
+impl Module for MidBlock {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let xs = self.resnet_1.forward(xs, None)?;
+        let xs = self.attention.forward(&xs)?;
+        self.resnet_2.forward(&xs, None)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Block {
+    resnets: Vec<ResnetBlock2D>,
+    sampler: Option<Conv2d>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    conv_in: Conv2d,
+    down_blocks: Vec<Block>,
+    mid_block: MidBlock,
+    conv_norm_out: GroupNorm,
+    conv_out: Conv2d,
+}
+
+impl Encoder {
+    pub fn new(cfg: &Config, vb: ShardedVarBuilder) -> Result<Self> {
+        let conv_cfg = candle_nn::Conv2dConfig {
+            padding: 1,
+            ..Default::default()
+        };
+        let n_blocks = cfg.block_out_channels.len();
+        let mut out_c = cfg.block_out_channels[0];
+        let conv_in = conv2d(cfg.in_channels, out_c, 3, conv_cfg, vb.pp("conv_in"))?;
+
+        let mut down_blocks = Vec::with_capacity(n_blocks);
+        let vb_d = vb.pp("down_blocks");
+        for (i, block_out_c) in cfg.block_out_channels.iter().enumerate() {
+            let in_c = out_c;
+            out_c = *block_out_c;
+            let vb_d = vb_d.pp(i);
+            let resnets = (0..cfg.layers_per_block)
+                .map(|j| {
+                    let resnet_in_c = if j == 0 { in_c } else { out_c };
+                    ResnetBlock2D::new(
+                        resnet_in_c,
+                        out_c,
+                        None,
+                        cfg.norm_num_groups,
+                        1e-6,
+                        vb_d.pp("resnets").pp(j),
+                    )
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let sampler = if i != n_blocks - 1 {
+                let conv_cfg = candle_nn::Conv2dConfig {
+                    stride: 2,
+                    ..Default::default()
+                };
+                Some(conv2d(
+                    out_c,
+                    out_c,
+                    3,
+                    conv_cfg,
+                    vb_d.pp("downsamplers.0.conv"),
+                )?)
+            } else {
+                None
+            };
+            down_blocks.push(Block { resnets, sampler });
+        }
+
+        let mid_block = MidBlock::new(out_c, cfg, vb.pp("mid_block"))?;
+        let conv_norm_out = group_norm(cfg.norm_num_groups, out_c, 1e-6, vb.pp("conv_norm_out"))?;
+        let conv_out = conv2d(
+            out_c,
+            2 * cfg.latent_channels,
+            3,
+            conv_cfg,
+            vb.pp("conv_out"),
+        )?;
+        Ok(Self {
+            conv_in,
+            down_blocks,
+            mid_block,
+            conv_norm_out,
+            conv_out,
+        })
+    }
+}
+
+impl Module for Encoder {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let mut xs = xs.apply(&self.conv_in)?;
+        for block in &self.down_blocks {
+            for resnet in &block.resnets {
+                xs = resnet.forward(&xs, None)?;
+            }
+            if let Some(downsampler) = &block.sampler {
+                // The downsampling is padded on the bottom and right only.
+                xs = xs
+                    .pad_with_zeros(D::Minus1, 0, 1)?
+                    .pad_with_zeros(D::Minus2, 0, 1)?
+                    .apply(downsampler)?;
+            }
+        }
+        xs.apply(&self.mid_block)?
+            .apply(&self.conv_norm_out)?
+            .apply(&candle_nn::Activation::Silu)?
+            .apply(&self.conv_out)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    conv_in: Conv2d,
+    mid_block: MidBlock,
+    up_blocks: Vec<Block>,
+    conv_norm_out: GroupNorm,
+    conv_out: Conv2d,
+}
+
+impl Decoder {
+    pub fn new(cfg: &Config, vb: ShardedVarBuilder) -> Result<Self> {
+        let conv_cfg = candle_nn::Conv2dConfig {
+            padding: 1,
+            ..Default::default()
+        };
+        let n_blocks = cfg.block_out_channels.len();
+        let mut out_c = cfg.block_out_channels[n_blocks - 1];
+        let conv_in = conv2d(cfg.latent_channels, out_c, 3, conv_cfg, vb.pp("conv_in"))?;
+        let mid_block = MidBlock::new(out_c, cfg, vb.pp("mid_block"))?;
+
+        let mut up_blocks = Vec::with_capacity(n_blocks);
+        let vb_u = vb.pp("up_blocks");
+        for (i, block_out_c) in cfg.block_out_channels.iter().rev().enumerate() {
+            let prev_out_c = out_c;
+            out_c = *block_out_c;
+            let vb_u = vb_u.pp(i);
+            let resnets = (0..=cfg.layers_per_block)
+                .map(|j| {
+                    let resnet_in_c = if j == 0 { prev_out_c } else { out_c };
+                    ResnetBlock2D::new(
+                        resnet_in_c,
+                        out_c,
+                        None,
+                        cfg.norm_num_groups,
+                        1e-6,
+                        vb_u.pp("resnets").pp(j),
+                    )
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let sampler = if i != n_blocks - 1 {
+                Some(conv2d(
+                    out_c,
+                    out_c,
+                    3,
+                    conv_cfg,
+                    vb_u.pp("upsamplers.0.conv"),
+                )?)
+            } else {
+                None
+            };
+            up_blocks.push(Block { resnets, sampler });
+        }
+
+        let conv_norm_out = group_norm(cfg.norm_num_groups, out_c, 1e-6, vb.pp("conv_norm_out"))?;
+        let conv_out = conv2d(out_c, cfg.out_channels, 3, conv_cfg, vb.pp("conv_out"))?;
+        Ok(Self {
+            conv_in,
+            mid_block,
+            up_blocks,
+            conv_norm_out,
+            conv_out,
+        })
+    }
+}
+
+impl Module for Decoder {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let mut xs = xs.apply(&self.conv_in)?.apply(&self.mid_block)?;
+        for block in &self.up_blocks {
+            for resnet in &block.resnets {
+                xs = resnet.forward(&xs, None)?;
+            }
+            if let Some(upsampler) = &block.sampler {
+                let (_, _, h, w) = xs.dims4()?;
+                xs = xs.upsample_nearest2d(h * 2, w * 2)?.apply(upsampler)?;
+            }
+        }
+        xs.apply(&self.conv_norm_out)?
+            .apply(&candle_nn::Activation::Silu)?
+            .apply(&self.conv_out)
+    }
+}
+
+/// The `AutoencoderKL` of Stable Diffusion, with the diffusers weight names. Unlike the Flux
+/// autoencoder, the latents are not shifted and there are extra 1x1 convolutions around them.
+#[derive(Debug, Clone)]
+pub struct AutoEncoderKL {
+    encoder: Encoder,
+    decoder: Decoder,
+    quant_conv: Conv2d,
+    post_quant_conv: Conv2d,
+    scaling_factor: f64,
+}
+
+impl AutoEncoderKL {
+    pub fn new(cfg: &Config, vb: ShardedVarBuilder) -> Result<Self> {
+        let latent_c = cfg.latent_channels;
+        Ok(Self {
+            encoder: Encoder::new(cfg, vb.pp("encoder"))?,
+            decoder: Decoder::new(cfg, vb.pp("decoder"))?,
+            quant_conv: conv2d(
+                2 * latent_c,
+                2 * latent_c,
+                1,
+                Default::default(),
+                vb.pp("quant_conv"),
+            )?,
+            post_quant_conv: conv2d(
+                latent_c,
+                latent_c,
+                1,
+                Default::default(),
+                vb.pp("post_quant_conv"),
+            )?,
+            scaling_factor: cfg.scaling_factor,
+        })
+    }
+
+    /// Encode an image with values in [-1, 1] to the scaled mean of the latent distribution.
+    pub fn encode_mean(&self, xs: &Tensor) -> Result<Tensor> {
+        let moments = xs.apply(&self.encoder)?.apply(&self.quant_conv)?;
+        moments.chunk(2, 1)?[0].clone() * self.scaling_factor
+    }
+
+    /// Decode scaled latents to an image with values in [-1, 1].
+    pub fn decode(&self, xs: &Tensor) -> Result<Tensor> {
+        (xs / self.scaling_factor)?
+            .apply(&self.post_quant_conv)?
+            .apply(&self.decoder)
+    }
+}
@@ -5,6 +5,8 @@ use std::{
 
 use candle_core::{DType, Device, Result, Tensor};
 
+use crate::KvCacheType;
+
 use super::config::ModelConfigLike;
 
 #[derive(Clone, Debug)]
@@ -12,6 +14,7 @@ pub struct CacheConfig {
     pub block_size: usize,
     pub num_gpu_blocks: usize,
     pub num_cpu_blocks: usize,
+    pub cache_type: KvCacheType,
 }
 
 pub type KVCache = (Tensor, Tensor);
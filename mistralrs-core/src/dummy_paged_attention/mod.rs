@@ -21,6 +21,8 @@ pub use scheduler::{
     PagedAttentionScheduler, PagedAttentionSchedulerConfig, PagedAttentionSchedulerOutput,
 };
 
+use crate::KvCacheType;
+
 pub const DEFAULT_PAGED_ATTENTION_BLOCK_SIZE: usize = 32;
 
 /// All memory counts in MB. Default for block size is 32.
@@ -62,6 +64,7 @@ pub fn calculate_cache_config(
     _mem_cpu: usize,
     _block_size: Option<usize>,
     _dtype: DType,
+    _cache_type: KvCacheType,
     _config: &dyn ModelConfigLike,
     _device: &Device,
     _layer_devices: &[Option<Device>],
@@ -0,0 +1,350 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use std::collections::HashMap;
+
+use candle_core::{Device, Result, Tensor};
+use candle_nn::{Embedding, LayerNorm, Linear, Module};
+use mistralrs_quant::ShardedVarBuilder;
+
+use crate::{
+    layers::{embedding, layer_norm, linear, Activation, MatMul},
+    pipeline::EmbeddingModel,
+    serde_default_fn,
+};
+
+use super::attention_bias;
+
+serde_default_fn!(usize, type_vocab_size, 2);
+serde_default_fn!(f64, layer_norm_eps, 1e-12);
+serde_default_fn!(Activation, hidden_act, Activation::Gelu);
+
+/// BERT and (XLM-)RoBERTa `config.json`.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct BertConfig {
+    pub vocab_size: usize,
+    pub hidden_size: usize,
+    pub num_hidden_layers: usize,
+    pub num_attention_heads: usize,
+    pub intermediate_size: usize,
+    #[serde(default = "hidden_act")]
+    pub hidden_act: Activation,
+    pub max_position_embeddings: usize,
+    #[serde(default = "type_vocab_size")]
+    pub type_vocab_size: usize,
+    #[serde(default = "layer_norm_eps")]
+    pub layer_norm_eps: f64,
+    #[serde(default)]
+    pub pad_token_id: usize,
+    pub position_embedding_type: Option<String>,
+    #[serde(default)]
+    pub architectures: Vec<String>,
+    pub id2label: Option<HashMap<String, String>>,
+}
+
+impl BertConfig {
+    /// Whether the checkpoint is a cross-encoder with a sequence classification head.
+    pub(crate) fn is_classifier(&self) -> bool {
+        self.architectures
+            .iter()
+            .any(|a| a.ends_with("ForSequenceClassification"))
+    }
+
+    fn num_labels(&self) -> usize {
+        self.id2label.as_ref().map_or(2, |labels| labels.len())
+    }
+}
+
+/// BERT, or RoBERTa which offsets the positions by the padding index and has a different
+/// classification head.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BertFlavour {
+    Bert,
+    Roberta,
+}
+
+impl BertFlavour {
+    fn prefix(&self) -> &'static str {
+        match self {
+            Self::Bert => "bert",
+            Self::Roberta => "roberta",
+        }
+    }
+}
+
+/// RoBERTa positions start after the padding index.
+fn position_offset(cfg: &BertConfig, flavour: BertFlavour) -> usize {
+    match flavour {
+        BertFlavour::Bert => 0,
+        BertFlavour::Roberta => cfg.pad_token_id + 1,
+    }
+}
+
+struct BertEmbeddings {
+    word_embeddings: Embedding,
+    position_embeddings: Embedding,
+    token_type_embeddings: Embedding,
+    layer_norm: LayerNorm,
+    position_offset: usize,
+    use_token_types: bool,
+}
+
+impl BertEmbeddings {
+    fn new(cfg: &BertConfig, flavour: BertFlavour, vb: ShardedVarBuilder) -> Result<Self> {
+        Ok(Self {
+            word_embeddings: embedding(cfg.vocab_size, cfg.hidden_size, vb.pp("word_embeddings"))?,
+            position_embeddings: embedding(
+                cfg.max_position_embeddings,
+                cfg.hidden_size,
+                vb.pp("position_embeddings"),
+            )?,
+            token_type_embeddings: embedding(
+                cfg.type_vocab_size,
+                cfg.hidden_size,
+                vb.pp("token_type_embeddings"),
+            )?,
+            layer_norm: layer_norm(cfg.hidden_size, cfg.layer_norm_eps, vb.pp("LayerNorm"))?,
+            position_offset: position_offset(cfg, flavour),
+            // RoBERTa tokenizers may emit segment ids which its single token type cannot embed.
+            use_token_types: cfg.type_vocab_size > 1,
+        })
+    }
+
+    fn forward(&self, input_ids: &Tensor, token_type_ids: &Tensor) -> Result<Tensor> {
+        let seq_len = input_ids.dim(1)?;
+        // Padding is on the right, so the positions of real tokens do not depend on it.
+        let position_ids = Tensor::arange(
+            self.position_offset as u32,
+            (self.position_offset + seq_len) as u32,
+            input_ids.device(),
+        )?;
+        let token_type_ids = if self.use_token_types {
+            token_type_ids.clone()
+        } else {
+            token_type_ids.zeros_like()?
+        };
+        self.word_embeddings
+            .forward(input_ids)?
+            .broadcast_add(&self.position_embeddings.forward(&position_ids)?)?
+            .add(&self.token_type_embeddings.forward(&token_type_ids)?)?
+            .apply(&self.layer_norm)
+    }
+}
+
+struct BertAttention {
+    query: Linear,
+    key: Linear,
+    value: Linear,
+    output: Linear,
+    layer_norm: LayerNorm,
+    num_heads: usize,
+    head_dim: usize,
+}
+
+impl BertAttention {
+    fn new(cfg: &BertConfig, vb: ShardedVarBuilder) -> Result<Self> {
+        let hidden_size = cfg.hidden_size;
+        Ok(Self {
+            query: linear(hidden_size, hidden_size, vb.pp("self").pp("query"))?,
+            key: linear(hidden_size, hidden_size, vb.pp("self").pp("key"))?,
+            value: linear(hidden_size, hidden_size, vb.pp("self").pp("value"))?,
+            output: linear(hidden_size, hidden_size, vb.pp("output").pp("dense"))?,
+            layer_norm: layer_norm(
+                hidden_size,
+                cfg.layer_norm_eps,
+                vb.pp("output").pp("LayerNorm"),
+            )?,
+            num_heads: cfg.num_attention_heads,
+            head_dim: hidden_size / cfg.num_attention_heads,
+        })
+    }
+
+    fn split_heads(&self, xs: &Tensor) -> Result<Tensor> {
+        let (b_sz, seq_len, _) = xs.dims3()?;
+        xs.reshape((b_sz, seq_len, self.num_heads, self.head_dim))?
+            .transpose(1, 2)?
+            .contiguous()
+    }
+
+    fn forward(&self, xs: &Tensor, bias: &Tensor) -> Result<Tensor> {
+        let (b_sz, seq_len, hidden_size) = xs.dims3()?;
+        let q = self.split_heads(&(self.query.forward(xs)? / (self.head_dim as f64).sqrt())?)?;
+        let k = self.split_heads(&self.key.forward(xs)?)?;
+        let v = self.split_heads(&self.value.forward(xs)?)?;
+
+        let attn_weights = MatMul
+            .matmul(&q, &k.t()?.contiguous()?)?
+            .broadcast_add(bias)?;
+        let attn_weights = candle_nn::ops::softmax_last_dim(&attn_weights)?;
+        let attn_output = MatMul
+            .matmul(&attn_weights, &v)?
+            .transpose(1, 2)?
+            .reshape((b_sz, seq_len, hidden_size))?
+            .apply(&self.output)?;
+        (attn_output + xs)?.apply(&self.layer_norm)
+    }
+}
+
+struct BertLayer {
+    attention: BertAttention,
+    intermediate: Linear,
+    output: Linear,
+    layer_norm: LayerNorm,
+    act: Activation,
+}
+
+impl BertLayer {
+    fn new(cfg: &BertConfig, vb: ShardedVarBuilder) -> Result<Self> {
+        Ok(Self {
+            attention: BertAttention::new(cfg, vb.pp("attention"))?,
+            intermediate: linear(
+                cfg.hidden_size,
+                cfg.intermediate_size,
+                vb.pp("intermediate").pp("dense"),
+            )?,
+            output: linear(
+                cfg.intermediate_size,
+                cfg.hidden_size,
+                vb.pp("output").pp("dense"),
+            )?,
+            layer_norm: layer_norm(
+                cfg.hidden_size,
+                cfg.layer_norm_eps,
+                vb.pp("output").pp("LayerNorm"),
+            )?,
+            act: cfg.hidden_act,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor, bias: &Tensor) -> Result<Tensor> {
+        let xs = self.attention.forward(xs, bias)?;
+        let ys = xs
+            .apply(&self.intermediate)?
+            .apply(&self.act)?
+            .apply(&self.output)?;
+        (ys + xs)?.apply(&self.layer_norm)
+    }
+}
+
+/// Sequence classification head, applied to the hidden state of the first (`[CLS]`/`<s>`) token.
+enum ClassificationHead {
+    /// `BertForSequenceClassification`: tanh pooler, then the classifier.
+    Bert { pooler: Linear, classifier: Linear },
+    /// `RobertaForSequenceClassification`: dense + tanh, then the output projection.
+    Roberta { dense: Linear, out_proj: Linear },
+}
+
+impl ClassificationHead {
+    fn forward(&self, cls: &Tensor) -> Result<Tensor> {
+        match self {
+            Self::Bert { pooler, classifier } => cls.apply(pooler)?.tanh()?.apply(classifier),
+            Self::Roberta { dense, out_proj } => cls.apply(dense)?.tanh()?.apply(out_proj),
+        }
+    }
+}
+
+/// A BERT or (XLM-)RoBERTa encoder, with a sequence classification head for cross-encoders.
+pub struct BertModel {
+    embeddings: BertEmbeddings,
+    layers: Vec<BertLayer>,
+    head: Option<ClassificationHead>,
+    max_seq_len: usize,
+    pad_token_id: u32,
+    device: Device,
+}
+
+impl BertModel {
+    pub fn new(
+        cfg: &BertConfig,
+        flavour: BertFlavour,
+        vb: ShardedVarBuilder,
+        device: &Device,
+    ) -> Result<Self> {
+        if cfg
+            .position_embedding_type
+            .as_ref()
+            .is_some_and(|t| t != "absolute")
+        {
+            candle_core::bail!("Only absolute position embeddings are supported.");
+        }
+
+        // Task-specific checkpoints nest the encoder under the model type, bare ones do not.
+        let vb_m = if vb.contains_tensor(&format!(
+            "{}.embeddings.word_embeddings.weight",
+            flavour.prefix()
+        )) {
+            vb.pp(flavour.prefix())
+        } else {
+            vb.clone()
+        };
+
+        let embeddings = BertEmbeddings::new(cfg, flavour, vb_m.pp("embeddings"))?;
+        let vb_l = vb_m.pp("encoder").pp("layer");
+        let layers = (0..cfg.num_hidden_layers)
+            .map(|i| BertLayer::new(cfg, vb_l.pp(i)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let head = if cfg.is_classifier() {
+            let (hidden_size, num_labels) = (cfg.hidden_size, cfg.num_labels());
+            Some(match flavour {
+                BertFlavour::Bert => ClassificationHead::Bert {
+                    pooler: linear(hidden_size, hidden_size, vb_m.pp("pooler").pp("dense"))?,
+                    classifier: linear(hidden_size, num_labels, vb.pp("classifier"))?,
+                },
+                BertFlavour::Roberta => ClassificationHead::Roberta {
+                    dense: linear(hidden_size, hidden_size, vb.pp("classifier").pp("dense"))?,
+                    out_proj: linear(hidden_size, num_labels, vb.pp("classifier").pp("out_proj"))?,
+                },
+            })
+        } else {
+            None
+        };
+
+        Ok(Self {
+            embeddings,
+            layers,
+            head,
+            max_seq_len: cfg.max_position_embeddings - position_offset(cfg, flavour),
+            pad_token_id: cfg.pad_token_id as u32,
+            device: device.clone(),
+        })
+    }
+}
+
+impl EmbeddingModel for BertModel {
+    fn forward(
+        &self,
+        input_ids: &Tensor,
+        token_type_ids: &Tensor,
+        attention_mask: &Tensor,
+    ) -> Result<Tensor> {
+        let mut xs = self.embeddings.forward(input_ids, token_type_ids)?;
+        let bias = attention_bias(attention_mask, xs.dtype())?;
+        for layer in &self.layers {
+            xs = layer.forward(&xs, &bias)?;
+        }
+        Ok(xs)
+    }
+
+    fn classify(&self, hidden_states: &Tensor, _attention_mask: &Tensor) -> Result<Tensor> {
+        let Some(head) = &self.head else {
+            candle_core::bail!("This model does not have a sequence classification head.");
+        };
+        head.forward(&hidden_states.narrow(1, 0, 1)?.squeeze(1)?)
+    }
+
+    fn is_reranker(&self) -> bool {
+        self.head.is_some()
+    }
+
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn max_seq_len(&self) -> usize {
+        self.max_seq_len
+    }
+
+    fn pad_token_id(&self) -> u32 {
+        self.pad_token_id
+    }
+}
@@ -0,0 +1,385 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use std::{collections::HashMap, f32::consts::PI};
+
+use candle_core::{DType, Device, Result, Tensor};
+use candle_nn::{Embedding, Linear, Module};
+use mistralrs_quant::ShardedVarBuilder;
+
+use crate::{
+    layers::{
+        embedding, linear_b, linear_no_bias, repeat_kv, Activation, Llama3RopeConfig,
+        Llama3RopeType, MatMul, RmsNorm,
+    },
+    pipeline::EmbeddingModel,
+    serde_default_fn,
+};
+
+use super::{attention_bias, pool, EmbeddingPooling};
+
+serde_default_fn!(Activation, hidden_act, Activation::Silu);
+serde_default_fn!(f64, rms_norm_eps, 1e-6);
+serde_default_fn!(f32, rope_theta, 10000.);
+
+/// Llama, Mistral and Qwen2 `config.json`, for `*ForSequenceClassification` checkpoints such as
+/// reward models.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct DecoderConfig {
+    pub vocab_size: usize,
+    pub hidden_size: usize,
+    pub intermediate_size: usize,
+    pub num_hidden_layers: usize,
+    pub num_attention_heads: usize,
+    pub num_key_value_heads: Option<usize>,
+    pub head_dim: Option<usize>,
+    #[serde(default = "hidden_act")]
+    pub hidden_act: Activation,
+    pub max_position_embeddings: usize,
+    #[serde(default = "rms_norm_eps")]
+    pub rms_norm_eps: f64,
+    #[serde(default = "rope_theta")]
+    pub rope_theta: f32,
+    pub rope_scaling: Option<Llama3RopeConfig>,
+    #[serde(default)]
+    pub attention_bias: bool,
+    pub pad_token_id: Option<u32>,
+    #[serde(default)]
+    pub architectures: Vec<String>,
+    pub id2label: Option<HashMap<String, String>>,
+}
+
+impl DecoderConfig {
+    /// Whether the checkpoint has a sequence classification (or reward) head.
+    pub(crate) fn is_classifier(&self) -> bool {
+        self.architectures
+            .iter()
+            .any(|a| a.ends_with("ForSequenceClassification"))
+    }
+
+    fn num_labels(&self) -> usize {
+        self.id2label.as_ref().map_or(2, |labels| labels.len())
+    }
+
+    fn head_dim(&self) -> usize {
+        self.head_dim
+            .unwrap_or(self.hidden_size / self.num_attention_heads)
+    }
+}
+
+/// Llama and Mistral, or Qwen2 which always has a bias on the query, key and value projections.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecoderFlavour {
+    Llama,
+    Qwen2,
+}
+
+/// Rotary embedding tables (seq_len, head_dim / 2), with the Llama 3 frequency scaling if the
+/// checkpoint uses it.
+struct RotaryEmbedding {
+    inv_freq: Vec<f32>,
+}
+
+impl RotaryEmbedding {
+    // https://github.com/huggingface/transformers/blob/1392a6867f40a55dfabaf306745c67627598b1af/src/transformers/modeling_rope_utils.py#L298
+    fn new(cfg: &DecoderConfig) -> Self {
+        let head_dim = cfg.head_dim();
+        let inv_freq = (0..head_dim)
+            .step_by(2)
+            .map(|i| 1f32 / cfg.rope_theta.powf(i as f32 / head_dim as f32));
+        let inv_freq = match &cfg.rope_scaling {
+            Some(
+                scaling @ Llama3RopeConfig {
+                    rope_type: Llama3RopeType::Llama3,
+                    ..
+                },
+            ) => {
+                let original = scaling.original_max_position_embeddings as f32;
+                let low_freq_wavelen = original / scaling.low_freq_factor;
+                let high_freq_wavelen = original / scaling.high_freq_factor;
+                inv_freq
+                    .map(|freq| {
+                        let wavelen = 2. * PI / freq;
+                        if wavelen < high_freq_wavelen {
+                            freq
+                        } else if wavelen > low_freq_wavelen {
+                            freq / scaling.factor
+                        } else {
+                            let smooth = (original / wavelen - scaling.low_freq_factor)
+                                / (scaling.high_freq_factor - scaling.low_freq_factor);
+                            (1. - smooth) * freq / scaling.factor + smooth * freq
+                        }
+                    })
+                    .collect()
+            }
+            _ => inv_freq.collect(),
+        };
+        Self { inv_freq }
+    }
+
+    fn cos_sin(&self, seq_len: usize, dtype: DType, device: &Device) -> Result<(Tensor, Tensor)> {
+        let inv_freq = Tensor::from_slice(&self.inv_freq, (1, self.inv_freq.len()), device)?;
+        let positions = Tensor::arange(0u32, seq_len as u32, device)?
+            .to_dtype(DType::F32)?
+            .reshape((seq_len, 1))?;
+        let freqs = positions.broadcast_mul(&inv_freq)?;
+        Ok((freqs.cos()?.to_dtype(dtype)?, freqs.sin()?.to_dtype(dtype)?))
+    }
+}
+
+struct Attention {
+    q_proj: Linear,
+    k_proj: Linear,
+    v_proj: Linear,
+    o_proj: Linear,
+    num_heads: usize,
+    num_kv_heads: usize,
+    head_dim: usize,
+}
+
+impl Attention {
+    fn new(cfg: &DecoderConfig, flavour: DecoderFlavour, vb: ShardedVarBuilder) -> Result<Self> {
+        let num_heads = cfg.num_attention_heads;
+        let num_kv_heads = cfg.num_key_value_heads.unwrap_or(num_heads);
+        let head_dim = cfg.head_dim();
+        let qkv_bias = cfg.attention_bias || flavour == DecoderFlavour::Qwen2;
+        Ok(Self {
+            q_proj: linear_b(
+                cfg.hidden_size,
+                num_heads * head_dim,
+                qkv_bias,
+                vb.pp("q_proj"),
+            )?,
+            k_proj: linear_b(
+                cfg.hidden_size,
+                num_kv_heads * head_dim,
+                qkv_bias,
+                vb.pp("k_proj"),
+            )?,
+            v_proj: linear_b(
+                cfg.hidden_size,
+                num_kv_heads * head_dim,
+                qkv_bias,
+                vb.pp("v_proj"),
+            )?,
+            o_proj: linear_b(
+                num_heads * head_dim,
+                cfg.hidden_size,
+                cfg.attention_bias,
+                vb.pp("o_proj"),
+            )?,
+            num_heads,
+            num_kv_heads,
+            head_dim,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor, bias: &Tensor, cos_sin: &(Tensor, Tensor)) -> Result<Tensor> {
+        let (b_sz, seq_len, _) = xs.dims3()?;
+        let (cos, sin) = cos_sin;
+        let q = self
+            .q_proj
+            .forward(xs)?
+            .reshape((b_sz, seq_len, self.num_heads, self.head_dim))?
+            .transpose(1, 2)?
+            .contiguous()?;
+        let k = self
+            .k_proj
+            .forward(xs)?
+            .reshape((b_sz, seq_len, self.num_kv_heads, self.head_dim))?
+            .transpose(1, 2)?
+            .contiguous()?;
+        let v = self
+            .v_proj
+            .forward(xs)?
+            .reshape((b_sz, seq_len, self.num_kv_heads, self.head_dim))?
+            .transpose(1, 2)?;
+        let q = candle_nn::rotary_emb::rope(&q, cos, sin)?;
+        let k = candle_nn::rotary_emb::rope(&k, cos, sin)?;
+        let n_rep = self.num_heads / self.num_kv_heads;
+        let k = repeat_kv(k, n_rep)?.contiguous()?;
+        let v = repeat_kv(v, n_rep)?.contiguous()?;
+
+        let attn_weights = (MatMul.matmul(&q, &k.t()?.contiguous()?)?
+            / (self.head_dim as f64).sqrt())?
+        .broadcast_add(bias)?;
+        let attn_weights = candle_nn::ops::softmax_last_dim(&attn_weights)?;
+        MatMul
+            .matmul(&attn_weights, &v)?
+            .transpose(1, 2)?
+            .reshape((b_sz, seq_len, self.num_heads * self.head_dim))?
+            .apply(&self.o_proj)
+    }
+}
+
+struct Mlp {
+    gate_proj: Linear,
+    up_proj: Linear,
+    down_proj: Linear,
+    act: Activation,
+}
+
+impl Mlp {
+    fn new(cfg: &DecoderConfig, vb: ShardedVarBuilder) -> Result<Self> {
+        Ok(Self {
+            gate_proj: linear_no_bias(cfg.hidden_size, cfg.intermediate_size, vb.pp("gate_proj"))?,
+            up_proj: linear_no_bias(cfg.hidden_size, cfg.intermediate_size, vb.pp("up_proj"))?,
+            down_proj: linear_no_bias(cfg.intermediate_size, cfg.hidden_size, vb.pp("down_proj"))?,
+            act: cfg.hidden_act,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let gate = self.gate_proj.forward(xs)?.apply(&self.act)?;
+        (gate * self.up_proj.forward(xs)?)?.apply(&self.down_proj)
+    }
+}
+
+struct DecoderLayer {
+    input_layernorm: RmsNorm,
+    self_attn: Attention,
+    post_attention_layernorm: RmsNorm,
+    mlp: Mlp,
+}
+
+impl DecoderLayer {
+    fn new(cfg: &DecoderConfig, flavour: DecoderFlavour, vb: ShardedVarBuilder) -> Result<Self> {
+        Ok(Self {
+            input_layernorm: RmsNorm::new(
+                cfg.hidden_size,
+                cfg.rms_norm_eps,
+                vb.pp("input_layernorm"),
+            )?,
+            self_attn: Attention::new(cfg, flavour, vb.pp("self_attn"))?,
+            post_attention_layernorm: RmsNorm::new(
+                cfg.hidden_size,
+                cfg.rms_norm_eps,
+                vb.pp("post_attention_layernorm"),
+            )?,
+            mlp: Mlp::new(cfg, vb.pp("mlp"))?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor, bias: &Tensor, cos_sin: &(Tensor, Tensor)) -> Result<Tensor> {
+        let attn = self
+            .self_attn
+            .forward(&xs.apply(&self.input_layernorm)?, bias, cos_sin)?;
+        let xs = (xs + attn)?;
+        &xs + self
+            .mlp
+            .forward(&xs.apply(&self.post_attention_layernorm)?)?
+    }
+}
+
+/// A Llama-style decoder run over the whole input at once, with a `score` head on the last token
+/// for sequence classification and reward models. Without the head it is a last-token pooling
+/// embedding model.
+pub struct DecoderModel {
+    embed_tokens: Embedding,
+    layers: Vec<DecoderLayer>,
+    norm: RmsNorm,
+    rotary: RotaryEmbedding,
+    score: Option<Linear>,
+    max_seq_len: usize,
+    pad_token_id: u32,
+    device: Device,
+}
+
+impl DecoderModel {
+    pub fn new(
+        cfg: &DecoderConfig,
+        flavour: DecoderFlavour,
+        vb: ShardedVarBuilder,
+        device: &Device,
+    ) -> Result<Self> {
+        // Task-specific checkpoints nest the decoder under `model`, bare ones do not.
+        let vb_m = if vb.contains_tensor("model.embed_tokens.weight") {
+            vb.pp("model")
+        } else {
+            vb.clone()
+        };
+
+        let embed_tokens = embedding(cfg.vocab_size, cfg.hidden_size, vb_m.pp("embed_tokens"))?;
+        let vb_l = vb_m.pp("layers");
+        let layers = (0..cfg.num_hidden_layers)
+            .map(|i| DecoderLayer::new(cfg, flavour, vb_l.pp(i)))
+            .collect::<Result<Vec<_>>>()?;
+        let norm = RmsNorm::new(cfg.hidden_size, cfg.rms_norm_eps, vb_m.pp("norm"))?;
+
+        let score = if cfg.is_classifier() {
+            Some(linear_no_bias(
+                cfg.hidden_size,
+                cfg.num_labels(),
+                vb.pp("score"),
+            )?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            embed_tokens,
+            layers,
+            norm,
+            rotary: RotaryEmbedding::new(cfg),
+            score,
+            max_seq_len: cfg.max_position_embeddings,
+            pad_token_id: cfg.pad_token_id.unwrap_or(0),
+            device: device.clone(),
+        })
+    }
+
+    /// The padding bias with a causal mask, (batch, 1, seq_len, seq_len).
+    fn causal_bias(&self, attention_mask: &Tensor, dtype: DType) -> Result<Tensor> {
+        let seq_len = attention_mask.dim(1)?;
+        let mask: Vec<f32> = (0..seq_len)
+            .flat_map(|i| (0..seq_len).map(move |j| if j > i { f32::NEG_INFINITY } else { 0. }))
+            .collect();
+        let causal =
+            Tensor::from_slice(&mask, (seq_len, seq_len), &self.device)?.to_dtype(dtype)?;
+        attention_bias(attention_mask, dtype)?.broadcast_add(&causal)
+    }
+}
+
+impl EmbeddingModel for DecoderModel {
+    fn forward(
+        &self,
+        input_ids: &Tensor,
+        _token_type_ids: &Tensor,
+        attention_mask: &Tensor,
+    ) -> Result<Tensor> {
+        let mut xs = self.embed_tokens.forward(input_ids)?;
+        // Padding is on the right, so it never affects the tokens before it.
+        let bias = self.causal_bias(attention_mask, xs.dtype())?;
+        let cos_sin = self
+            .rotary
+            .cos_sin(input_ids.dim(1)?, xs.dtype(), &self.device)?;
+        for layer in &self.layers {
+            xs = layer.forward(&xs, &bias, &cos_sin)?;
+        }
+        xs.apply(&self.norm)
+    }
+
+    fn classify(&self, hidden_states: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+        let Some(score) = &self.score else {
+            candle_core::bail!("This model does not have a sequence classification head.");
+        };
+        pool(hidden_states, attention_mask, EmbeddingPooling::LastToken)?
+            .to_dtype(hidden_states.dtype())?
+            .apply(score)
+    }
+
+    fn is_reranker(&self) -> bool {
+        self.score.is_some()
+    }
+
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn max_seq_len(&self) -> usize {
+        self.max_seq_len
+    }
+
+    fn pad_token_id(&self) -> u32 {
+        self.pad_token_id
+    }
+}
@@ -0,0 +1,265 @@
+pub(crate) mod bert;
+pub(crate) mod decoder;
+pub(crate) mod modernbert;
+pub(crate) mod processor;
+
+use std::collections::HashMap;
+
+use candle_core::{DType, Result, Tensor, D};
+use serde::Deserialize;
+use tokenizers::Tokenizer;
+
+use crate::{ClassificationResponse, EmbeddingResponse, RerankResponse};
+
+#[cfg_attr(feature = "pyo3_macros", pyo3::pyclass)]
+#[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
+#[derive(Debug, Clone)]
+/// Parameters of an embedding request.
+/// - `normalize`: L2-normalize the embeddings, so that their dot product is the cosine similarity.
+/// - `dimensions`: Keep only the first `dimensions` values of each embedding (for Matryoshka
+///     models). The embeddings are normalized after truncation.
+pub struct EmbeddingParams {
+    pub normalize: bool,
+    pub dimensions: Option<usize>,
+}
+
+impl Default for EmbeddingParams {
+    fn default() -> Self {
+        Self {
+            normalize: true,
+            dimensions: None,
+        }
+    }
+}
+
+#[cfg_attr(feature = "pyo3_macros", pyo3::pyclass)]
+#[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
+#[derive(Debug, Clone, Default)]
+/// Parameters of a rerank request.
+/// - `top_n`: Only return the `top_n` most relevant documents.
+/// - `return_documents`: Include the text of each document in the results.
+pub struct RerankParams {
+    pub top_n: Option<usize>,
+    pub return_documents: bool,
+}
+
+/// The work of one embedding pipeline sequence.
+#[derive(Debug, Clone)]
+pub(crate) enum EmbeddingInputs {
+    Embed {
+        inputs: Vec<String>,
+        params: EmbeddingParams,
+    },
+    Rerank {
+        query: String,
+        documents: Vec<String>,
+        params: RerankParams,
+    },
+    Classify {
+        /// Each input, and whether the tokenizer should add special tokens. Conversations are
+        /// formatted with the chat template, which already contains them.
+        inputs: Vec<(String, bool)>,
+    },
+}
+
+impl EmbeddingInputs {
+    /// Fail if any input has no tokens, such as an empty string with a tokenizer which adds no
+    /// special tokens, as it cannot be pooled.
+    pub(crate) fn check_not_empty(&self, tokenizer: &Tokenizer) -> anyhow::Result<()> {
+        let lens = match self {
+            Self::Embed { inputs, .. } => tokenizer
+                .encode_batch(inputs.clone(), true)
+                .map_err(anyhow::Error::msg)?
+                .iter()
+                .map(|e| e.len())
+                .collect::<Vec<_>>(),
+            Self::Rerank {
+                query, documents, ..
+            } => {
+                let pairs = documents
+                    .iter()
+                    .map(|document| (query.clone(), document.clone()))
+                    .collect::<Vec<_>>();
+                tokenizer
+                    .encode_batch(pairs, true)
+                    .map_err(anyhow::Error::msg)?
+                    .iter()
+                    .map(|e| e.len())
+                    .collect()
+            }
+            Self::Classify { inputs } => inputs
+                .iter()
+                .map(|(text, add_special_tokens)| {
+                    tokenizer
+                        .encode(text.as_str(), *add_special_tokens)
+                        .map(|e| e.len())
+                        .map_err(anyhow::Error::msg)
+                })
+                .collect::<anyhow::Result<_>>()?,
+        };
+        if let Some(i) = lens.iter().position(|len| *len == 0) {
+            anyhow::bail!("Input {i} is empty after tokenization.");
+        }
+        Ok(())
+    }
+}
+
+/// The response to one embedding pipeline sequence.
+#[derive(Debug, Clone)]
+pub enum EmbeddingOutput {
+    Embedding(EmbeddingResponse),
+    Rerank(RerankResponse),
+    Classification(ClassificationResponse),
+}
+
+/// The labels of a sequence classification head, from `config.json`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct ClassifierConfig {
+    id2label: Option<HashMap<String, String>>,
+    problem_type: Option<String>,
+}
+
+impl ClassifierConfig {
+    /// The name of each of the `num_labels` outputs, `LABEL_{i}` if the checkpoint has none.
+    pub(crate) fn labels(&self, num_labels: usize) -> Vec<String> {
+        (0..num_labels)
+            .map(|i| {
+                self.id2label
+                    .as_ref()
+                    .and_then(|labels| labels.get(&i.to_string()))
+                    .cloned()
+                    .unwrap_or_else(|| format!("LABEL_{i}"))
+            })
+            .collect()
+    }
+
+    /// Whether the labels are independent, so each gets a sigmoid rather than a softmax over all.
+    pub(crate) fn is_multi_label(&self) -> bool {
+        self.problem_type.as_deref() == Some("multi_label_classification")
+    }
+}
+
+/// How the hidden states of a sequence are reduced to one embedding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum EmbeddingPooling {
+    Cls,
+    Mean,
+    LastToken,
+}
+
+/// The sentence-transformers pooling module configuration, `1_Pooling/config.json`.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct PoolingConfig {
+    #[serde(default)]
+    pooling_mode_cls_token: bool,
+    #[serde(default)]
+    pooling_mode_lasttoken: bool,
+}
+
+impl PoolingConfig {
+    pub(crate) fn pooling(&self) -> EmbeddingPooling {
+        if self.pooling_mode_cls_token {
+            EmbeddingPooling::Cls
+        } else if self.pooling_mode_lasttoken {
+            EmbeddingPooling::LastToken
+        } else {
+            EmbeddingPooling::Mean
+        }
+    }
+}
+
+/// The additive attention bias (batch, 1, 1, seq_len) masking out padding, from an
+/// `attention_mask` (batch, seq_len) of 1s for tokens and 0s for padding.
+pub(crate) fn attention_bias(attention_mask: &Tensor, dtype: DType) -> Result<Tensor> {
+    let (b_sz, seq_len) = attention_mask.dims2()?;
+    // Large and finite in f32 so that unmasked positions stay exactly 0; it saturates to -inf in
+    // half precision, which is fine as every row has at least one token.
+    ((attention_mask.to_dtype(DType::F32)? - 1.)? * 1e9)?
+        .reshape((b_sz, 1, 1, seq_len))?
+        .to_dtype(dtype)
+}
+
+/// Pool the hidden states (batch, seq_len, hidden_size) to (batch, hidden_size), ignoring padding.
+pub(crate) fn pool(
+    hidden_states: &Tensor,
+    attention_mask: &Tensor,
+    pooling: EmbeddingPooling,
+) -> Result<Tensor> {
+    let hidden_states = hidden_states.to_dtype(DType::F32)?;
+    let mask = attention_mask.to_dtype(DType::F32)?;
+    match pooling {
+        EmbeddingPooling::Cls => hidden_states.narrow(1, 0, 1)?.squeeze(1),
+        EmbeddingPooling::Mean => {
+            let summed = hidden_states
+                .broadcast_mul(&mask.unsqueeze(D::Minus1)?)?
+                .sum(1)?;
+            summed.broadcast_div(&mask.sum_keepdim(1)?)
+        }
+        EmbeddingPooling::LastToken => {
+            // Sequences are right padded, so the last token is at `len - 1`.
+            let lens = mask.sum(1)?.to_vec1::<f32>()?;
+            let last = lens
+                .iter()
+                .enumerate()
+                .map(|(i, len)| match (*len as usize).checked_sub(1) {
+                    Some(last) => hidden_states.get(i)?.get(last),
+                    None => candle_core::bail!("Cannot pool sequence {i}, which has no tokens."),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Tensor::stack(&last, 0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{Device, Tensor};
+
+    use super::{pool, ClassifierConfig, EmbeddingPooling};
+
+    #[test]
+    fn pooling_ignores_padding() -> candle_core::Result<()> {
+        let dev = Device::Cpu;
+        // (2, 3, 2): the second sequence has one padding token.
+        let hidden = Tensor::new(
+            &[
+                [[1f32, 2.], [3., 4.], [5., 6.]],
+                [[1., 1.], [3., 3.], [100., 100.]],
+            ],
+            &dev,
+        )?;
+        let mask = Tensor::new(&[[1u32, 1, 1], [1, 1, 0]], &dev)?;
+
+        let mean = pool(&hidden, &mask, EmbeddingPooling::Mean)?.to_vec2::<f32>()?;
+        assert_eq!(mean, vec![vec![3., 4.], vec![2., 2.]]);
+        let cls = pool(&hidden, &mask, EmbeddingPooling::Cls)?.to_vec2::<f32>()?;
+        assert_eq!(cls, vec![vec![1., 2.], vec![1., 1.]]);
+        let last = pool(&hidden, &mask, EmbeddingPooling::LastToken)?.to_vec2::<f32>()?;
+        assert_eq!(last, vec![vec![5., 6.], vec![3., 3.]]);
+        Ok(())
+    }
+
+    #[test]
+    fn last_token_pooling_rejects_empty_sequence() -> candle_core::Result<()> {
+        let dev = Device::Cpu;
+        let hidden = Tensor::new(&[[[1f32, 2.], [3., 4.]], [[5., 6.], [7., 8.]]], &dev)?;
+        let mask = Tensor::new(&[[1u32, 1], [0, 0]], &dev)?;
+
+        assert!(pool(&hidden, &mask, EmbeddingPooling::LastToken).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn classifier_labels_fall_back_to_index() {
+        let cfg: ClassifierConfig = serde_json::from_str(
+            r#"{"id2label": {"0": "safe", "1": "unsafe"}, "problem_type": "multi_label_classification"}"#,
+        )
+        .unwrap();
+        assert_eq!(cfg.labels(2), vec!["safe", "unsafe"]);
+        assert!(cfg.is_multi_label());
+
+        let cfg: ClassifierConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(cfg.labels(1), vec!["LABEL_0"]);
+        assert!(!cfg.is_multi_label());
+    }
+}
@@ -0,0 +1,383 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use std::collections::HashMap;
+
+use candle_core::{DType, Device, Result, Tensor, D};
+use candle_nn::{Embedding, LayerNorm, LayerNormConfig, Linear, Module};
+use mistralrs_quant::ShardedVarBuilder;
+
+use crate::{
+    layers::{embedding, layer_norm, linear, linear_b, Activation, MatMul},
+    pipeline::EmbeddingModel,
+    serde_default_fn,
+};
+
+use super::{attention_bias, pool, EmbeddingPooling};
+
+serde_default_fn!(Activation, hidden_activation, Activation::Gelu);
+serde_default_fn!(f64, norm_eps, 1e-5);
+serde_default_fn!(f64, global_rope_theta, 160000.);
+serde_default_fn!(f64, local_rope_theta, 10000.);
+serde_default_fn!(usize, local_attention, 128);
+serde_default_fn!(usize, global_attn_every_n_layers, 3);
+serde_default_fn!(usize, pad_token_id, 50283);
+serde_default_fn!(String, classifier_pooling, "cls".to_string());
+
+/// ModernBERT `config.json`.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ModernBertConfig {
+    pub vocab_size: usize,
+    pub hidden_size: usize,
+    pub intermediate_size: usize,
+    pub num_hidden_layers: usize,
+    pub num_attention_heads: usize,
+    #[serde(default = "hidden_activation")]
+    pub hidden_activation: Activation,
+    pub max_position_embeddings: usize,
+    #[serde(default = "norm_eps")]
+    pub norm_eps: f64,
+    #[serde(default)]
+    pub norm_bias: bool,
+    #[serde(default)]
+    pub attention_bias: bool,
+    #[serde(default)]
+    pub mlp_bias: bool,
+    #[serde(default = "global_rope_theta")]
+    pub global_rope_theta: f64,
+    #[serde(default = "local_rope_theta")]
+    pub local_rope_theta: f64,
+    #[serde(default = "local_attention")]
+    pub local_attention: usize,
+    #[serde(default = "global_attn_every_n_layers")]
+    pub global_attn_every_n_layers: usize,
+    #[serde(default = "pad_token_id")]
+    pub pad_token_id: usize,
+    #[serde(default = "classifier_pooling")]
+    pub classifier_pooling: String,
+    #[serde(default)]
+    pub classifier_bias: bool,
+    #[serde(default = "hidden_activation")]
+    pub classifier_activation: Activation,
+    #[serde(default)]
+    pub architectures: Vec<String>,
+    pub id2label: Option<HashMap<String, String>>,
+}
+
+impl ModernBertConfig {
+    /// Whether the checkpoint is a cross-encoder with a sequence classification head.
+    pub(crate) fn is_classifier(&self) -> bool {
+        self.architectures
+            .iter()
+            .any(|a| a.ends_with("ForSequenceClassification"))
+    }
+
+    fn num_labels(&self) -> usize {
+        self.id2label.as_ref().map_or(2, |labels| labels.len())
+    }
+
+    fn norm(&self) -> LayerNormConfig {
+        LayerNormConfig {
+            eps: self.norm_eps,
+            remove_mean: true,
+            affine: self.norm_bias,
+        }
+    }
+}
+
+/// Rotary embedding tables (seq_len, head_dim / 2) for one base frequency.
+struct RotaryEmbedding {
+    inv_freq: Vec<f32>,
+}
+
+impl RotaryEmbedding {
+    fn new(theta: f64, head_dim: usize) -> Self {
+        let inv_freq = (0..head_dim)
+            .step_by(2)
+            .map(|i| 1f32 / theta.powf(i as f64 / head_dim as f64) as f32)
+            .collect();
+        Self { inv_freq }
+    }
+
+    fn cos_sin(&self, seq_len: usize, dtype: DType, device: &Device) -> Result<(Tensor, Tensor)> {
+        let inv_freq = Tensor::from_slice(&self.inv_freq, (1, self.inv_freq.len()), device)?;
+        let positions = Tensor::arange(0u32, seq_len as u32, device)?
+            .to_dtype(DType::F32)?
+            .reshape((seq_len, 1))?;
+        let freqs = positions.broadcast_mul(&inv_freq)?;
+        Ok((freqs.cos()?.to_dtype(dtype)?, freqs.sin()?.to_dtype(dtype)?))
+    }
+}
+
+struct Attention {
+    wqkv: Linear,
+    wo: Linear,
+    num_heads: usize,
+    head_dim: usize,
+    rotary: RotaryEmbedding,
+    /// Half of the sliding window for local attention layers, `None` for global attention.
+    local_window: Option<usize>,
+}
+
+impl Attention {
+    fn new(cfg: &ModernBertConfig, layer_idx: usize, vb: ShardedVarBuilder) -> Result<Self> {
+        let hidden_size = cfg.hidden_size;
+        let head_dim = hidden_size / cfg.num_attention_heads;
+        let is_global = layer_idx % cfg.global_attn_every_n_layers == 0;
+        let theta = if is_global {
+            cfg.global_rope_theta
+        } else {
+            cfg.local_rope_theta
+        };
+        Ok(Self {
+            wqkv: linear_b(
+                hidden_size,
+                3 * hidden_size,
+                cfg.attention_bias,
+                vb.pp("Wqkv"),
+            )?,
+            wo: linear_b(hidden_size, hidden_size, cfg.attention_bias, vb.pp("Wo"))?,
+            num_heads: cfg.num_attention_heads,
+            head_dim,
+            rotary: RotaryEmbedding::new(theta, head_dim),
+            local_window: (!is_global).then_some(cfg.local_attention / 2),
+        })
+    }
+
+    fn sliding_window_bias(&self, seq_len: usize, window: usize, xs: &Tensor) -> Result<Tensor> {
+        let mask: Vec<f32> = (0..seq_len)
+            .flat_map(|i| {
+                (0..seq_len).map(move |j| {
+                    if i.abs_diff(j) > window {
+                        f32::NEG_INFINITY
+                    } else {
+                        0.
+                    }
+                })
+            })
+            .collect();
+        Tensor::from_slice(&mask, (seq_len, seq_len), xs.device())?.to_dtype(xs.dtype())
+    }
+
+    fn forward(&self, xs: &Tensor, bias: &Tensor) -> Result<Tensor> {
+        let (b_sz, seq_len, hidden_size) = xs.dims3()?;
+        let qkv = self
+            .wqkv
+            .forward(xs)?
+            .reshape((b_sz, seq_len, 3, self.num_heads, self.head_dim))?
+            .permute((2, 0, 3, 1, 4))?;
+        let (cos, sin) = self.rotary.cos_sin(seq_len, xs.dtype(), xs.device())?;
+        let q = candle_nn::rotary_emb::rope(&qkv.get(0)?.contiguous()?, &cos, &sin)?;
+        let k = candle_nn::rotary_emb::rope(&qkv.get(1)?.contiguous()?, &cos, &sin)?;
+        let v = qkv.get(2)?.contiguous()?;
+
+        let mut attn_weights = (MatMul.matmul(&q, &k.t()?.contiguous()?)?
+            / (self.head_dim as f64).sqrt())?
+        .broadcast_add(bias)?;
+        if let Some(window) = self.local_window {
+            attn_weights =
+                attn_weights.broadcast_add(&self.sliding_window_bias(seq_len, window, xs)?)?;
+        }
+        let attn_weights = candle_nn::ops::softmax_last_dim(&attn_weights)?;
+        MatMul
+            .matmul(&attn_weights, &v)?
+            .transpose(1, 2)?
+            .reshape((b_sz, seq_len, hidden_size))?
+            .apply(&self.wo)
+    }
+}
+
+/// Gated MLP: the input projection holds both the GELU input and the gate.
+struct Mlp {
+    wi: Linear,
+    wo: Linear,
+    act: Activation,
+}
+
+impl Mlp {
+    fn new(cfg: &ModernBertConfig, vb: ShardedVarBuilder) -> Result<Self> {
+        Ok(Self {
+            wi: linear_b(
+                cfg.hidden_size,
+                2 * cfg.intermediate_size,
+                cfg.mlp_bias,
+                vb.pp("Wi"),
+            )?,
+            wo: linear_b(
+                cfg.intermediate_size,
+                cfg.hidden_size,
+                cfg.mlp_bias,
+                vb.pp("Wo"),
+            )?,
+            act: cfg.hidden_activation,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let xs = self.wi.forward(xs)?;
+        let chunks = xs.chunk(2, D::Minus1)?;
+        (chunks[0].apply(&self.act)? * &chunks[1])?.apply(&self.wo)
+    }
+}
+
+struct Layer {
+    /// The first layer has no attention norm, as the embeddings are already normalized.
+    attn_norm: Option<LayerNorm>,
+    attn: Attention,
+    mlp_norm: LayerNorm,
+    mlp: Mlp,
+}
+
+impl Layer {
+    fn new(cfg: &ModernBertConfig, layer_idx: usize, vb: ShardedVarBuilder) -> Result<Self> {
+        let attn_norm = if layer_idx == 0 {
+            None
+        } else {
+            Some(layer_norm(cfg.hidden_size, cfg.norm(), vb.pp("attn_norm"))?)
+        };
+        Ok(Self {
+            attn_norm,
+            attn: Attention::new(cfg, layer_idx, vb.pp("attn"))?,
+            mlp_norm: layer_norm(cfg.hidden_size, cfg.norm(), vb.pp("mlp_norm"))?,
+            mlp: Mlp::new(cfg, vb.pp("mlp"))?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor, bias: &Tensor) -> Result<Tensor> {
+        let attn_in = match &self.attn_norm {
+            Some(norm) => xs.apply(norm)?,
+            None => xs.clone(),
+        };
+        let xs = (xs + self.attn.forward(&attn_in, bias)?)?;
+        &xs + self.mlp.forward(&xs.apply(&self.mlp_norm)?)?
+    }
+}
+
+/// `ModernBertForSequenceClassification` head: pool, dense + activation + norm, classifier.
+struct ClassificationHead {
+    pooling: EmbeddingPooling,
+    dense: Linear,
+    act: Activation,
+    norm: LayerNorm,
+    classifier: Linear,
+}
+
+/// A ModernBERT encoder, with a sequence classification head for cross-encoders. Local attention
+/// layers attend to a sliding window, and every `global_attn_every_n_layers`-th layer attends
+/// globally.
+pub struct ModernBertModel {
+    tok_embeddings: Embedding,
+    embeddings_norm: LayerNorm,
+    layers: Vec<Layer>,
+    final_norm: LayerNorm,
+    head: Option<ClassificationHead>,
+    max_seq_len: usize,
+    pad_token_id: u32,
+    device: Device,
+}
+
+impl ModernBertModel {
+    pub fn new(cfg: &ModernBertConfig, vb: ShardedVarBuilder, device: &Device) -> Result<Self> {
+        // Task-specific checkpoints nest the encoder under `model`, bare ones do not.
+        let vb_m = if vb.contains_tensor("model.embeddings.tok_embeddings.weight") {
+            vb.pp("model")
+        } else {
+            vb.clone()
+        };
+
+        let tok_embeddings = embedding(
+            cfg.vocab_size,
+            cfg.hidden_size,
+            vb_m.pp("embeddings").pp("tok_embeddings"),
+        )?;
+        let embeddings_norm = layer_norm(
+            cfg.hidden_size,
+            cfg.norm(),
+            vb_m.pp("embeddings").pp("norm"),
+        )?;
+        let vb_l = vb_m.pp("layers");
+        let layers = (0..cfg.num_hidden_layers)
+            .map(|i| Layer::new(cfg, i, vb_l.pp(i)))
+            .collect::<Result<Vec<_>>>()?;
+        let final_norm = layer_norm(cfg.hidden_size, cfg.norm(), vb_m.pp("final_norm"))?;
+
+        let head = if cfg.is_classifier() {
+            let pooling = match cfg.classifier_pooling.as_str() {
+                "cls" => EmbeddingPooling::Cls,
+                "mean" => EmbeddingPooling::Mean,
+                other => candle_core::bail!("Unsupported classifier pooling `{other}`."),
+            };
+            Some(ClassificationHead {
+                pooling,
+                dense: linear_b(
+                    cfg.hidden_size,
+                    cfg.hidden_size,
+                    cfg.classifier_bias,
+                    vb.pp("head").pp("dense"),
+                )?,
+                act: cfg.classifier_activation,
+                norm: layer_norm(cfg.hidden_size, cfg.norm(), vb.pp("head").pp("norm"))?,
+                classifier: linear(cfg.hidden_size, cfg.num_labels(), vb.pp("classifier"))?,
+            })
+        } else {
+            None
+        };
+
+        Ok(Self {
+            tok_embeddings,
+            embeddings_norm,
+            layers,
+            final_norm,
+            head,
+            max_seq_len: cfg.max_position_embeddings,
+            pad_token_id: cfg.pad_token_id as u32,
+            device: device.clone(),
+        })
+    }
+}
+
+impl EmbeddingModel for ModernBertModel {
+    fn forward(
+        &self,
+        input_ids: &Tensor,
+        _token_type_ids: &Tensor,
+        attention_mask: &Tensor,
+    ) -> Result<Tensor> {
+        let mut xs = self
+            .tok_embeddings
+            .forward(input_ids)?
+            .apply(&self.embeddings_norm)?;
+        let bias = attention_bias(attention_mask, xs.dtype())?;
+        for layer in &self.layers {
+            xs = layer.forward(&xs, &bias)?;
+        }
+        xs.apply(&self.final_norm)
+    }
+
+    fn classify(&self, hidden_states: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+        let Some(head) = &self.head else {
+            candle_core::bail!("This model does not have a sequence classification head.");
+        };
+        pool(hidden_states, attention_mask, head.pooling)?
+            .to_dtype(hidden_states.dtype())?
+            .apply(&head.dense)?
+            .apply(&head.act)?
+            .apply(&head.norm)?
+            .apply(&head.classifier)
+    }
+
+    fn is_reranker(&self) -> bool {
+        self.head.is_some()
+    }
+
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn max_seq_len(&self) -> usize {
+        self.max_seq_len
+    }
+
+    fn pad_token_id(&self) -> u32 {
+        self.pad_token_id
+    }
+}
@@ -0,0 +1,98 @@
+use std::{any::Any, collections::HashMap, num::NonZeroUsize, sync::Arc};
+
+use anyhow::{Context, Result};
+use candle_core::Device;
+use indexmap::IndexMap;
+use tokenizers::Tokenizer;
+
+use crate::{
+    device_map::DeviceMapper,
+    pipeline::{
+        text_models_inputs_processor::PagedAttentionMeta, InputProcessorOutput, InputsProcessor,
+        InputsProcessorType, MessagesAction, Processor,
+    },
+    sequence::Sequence,
+    MessageContent, Pipeline,
+};
+
+use super::EmbeddingInputs;
+
+pub struct EmbeddingProcessor;
+
+impl Processor for EmbeddingProcessor {
+    #[allow(clippy::too_many_arguments)]
+    fn process(
+        &self,
+        _pipeline: &dyn Pipeline,
+        _messages: Vec<IndexMap<String, MessageContent>>,
+        _add_generation_prompt: bool,
+        _continue_final_message: bool,
+        _add_special_tokens: bool,
+        _tools: Vec<crate::Tool>,
+        _chat_template_kwargs: Option<HashMap<String, serde_json::Value>>,
+    ) -> Result<(Vec<u32>, String)> {
+        anyhow::bail!(
+            "EmbeddingProcessor::process should not be used. It does not expect chat messages."
+        )
+    }
+    fn inputs_processor(&self) -> Arc<dyn InputsProcessor> {
+        Arc::new(EmbeddingInputsProcessor)
+    }
+    fn get_special_tokens(&self) -> &[&'static str] {
+        &[]
+    }
+    fn template_action(&self) -> MessagesAction {
+        // Just a default
+        MessagesAction::FlattenOnlyText
+    }
+}
+
+pub struct EmbeddingInputsProcessor;
+
+#[derive(Clone)]
+pub struct ModelInputs {
+    pub(crate) inputs: Vec<EmbeddingInputs>,
+}
+
+impl InputsProcessor for EmbeddingInputsProcessor {
+    fn get_type(&self) -> InputsProcessorType {
+        InputsProcessorType::Text
+    }
+
+    fn process_inputs(
+        &self,
+        _tokenizer: Option<Arc<Tokenizer>>,
+        input_seqs: &mut [&mut Sequence],
+        _is_prompt: bool,
+        _is_xlora: bool,
+        _device: &Device,
+        _no_kv_cache: bool,
+        _last_n_context_len: Option<(usize, usize)>,
+        _return_raw_logits: bool,
+        _other_config: Option<Arc<dyn Any>>,
+        _paged_attn_metadata: Option<PagedAttentionMeta<'_>>,
+        prompt_chunksize: Option<NonZeroUsize>,
+        _mapper: Option<&dyn DeviceMapper>,
+    ) -> Box<dyn Iterator<Item = Result<InputProcessorOutput>>> {
+        let mut make_value = if prompt_chunksize.is_some() {
+            return Box::new(std::iter::once(Err(anyhow::Error::msg(
+                "Prompt batching is unsupported for embedding models",
+            ))));
+        } else {
+            || {
+                let inputs = input_seqs
+                    .iter()
+                    .map(|seq| {
+                        seq.get_embedding_inputs()
+                            .context("Embedding inputs must be present")
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(InputProcessorOutput {
+                    inputs: Box::new(ModelInputs { inputs }),
+                    seq_indices: (0..input_seqs.len()).collect::<Vec<_>>(),
+                })
+            }
+        };
+        Box::new(std::iter::once(make_value()))
+    }
+}
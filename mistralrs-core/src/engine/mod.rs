@@ -13,18 +13,21 @@ use std::{
 use tokio::sync::{mpsc::Receiver, Mutex};
 
 use crate::{
+    embedding_models::EmbeddingInputs,
     pipeline::{
+        apply_chat_template,
         llg::{constraint_from_llg_grammar, llg_grammar_from_constraint},
+        process_continued_chat,
         text_models_inputs_processor::PagedAttentionMeta,
-        AdapterInstruction, CacheBackendMetadata, CacheInstruction, NormalCache,
+        AdapterInstruction, CacheBackendMetadata, CacheInstruction, MessagesAction, NormalCache,
     },
     prefix_cacher_v2::PrefixCacheManagerV2,
-    request::{DetokenizationRequest, NormalRequest, TokenizationRequest},
+    request::{ClassificationInput, DetokenizationRequest, NormalRequest, TokenizationRequest},
     response::CompletionChoice,
     scheduler::{Scheduler, SchedulerOutput},
     sequence::{SeqStepType, StopReason},
     tools::{ToolCallingMatcher, ToolChoice},
-    CompletionResponse, RequestMessage, Response, SchedulerConfig, DEBUG,
+    CompletionResponse, ModelCategory, RequestMessage, Response, SchedulerConfig, DEBUG,
 };
 use rand::SeedableRng;
 use rand_isaac::Isaac64Rng;
@@ -89,10 +92,11 @@ impl Engine {
         //     || no_prefix_cache
         //     || no_kv_cache;
         let no_prefix_cache = true;
+        let constant_state_size = get_mut_arcmutex!(pipeline).cache().is_constant_size();
         Self {
             rx,
             pipeline,
-            scheduler: config.into_scheduler(),
+            scheduler: config.into_scheduler(constant_state_size),
             id: 0,
             truncate_sequence,
             no_kv_cache,
@@ -284,7 +288,12 @@ impl Engine {
                         for seq in scheduled.prompt.iter_mut() {
                             match seq.sequence_stepping_type() {
                                 SeqStepType::OneShot => {
-                                    seq.set_state(SequenceState::Done(StopReason::GeneratedImage))
+                                    // The response was already sent, which marks the sequence done.
+                                    if !matches!(seq.getstate(), SequenceState::Done(_)) {
+                                        seq.set_state(SequenceState::Done(
+                                            StopReason::GeneratedImage,
+                                        ))
+                                    }
                                 }
                                 SeqStepType::PromptAndDecode => {
                                     seq.set_state(SequenceState::RunningCompletion)
@@ -533,7 +542,11 @@ impl Engine {
             RequestMessage::Chat(_)
             | RequestMessage::CompletionTokens(_)
             | RequestMessage::VisionChat { .. }
-            | RequestMessage::ImageGeneration { .. } => None,
+            | RequestMessage::ImageGeneration { .. }
+            | RequestMessage::Transcription { .. }
+            | RequestMessage::Embedding { .. }
+            | RequestMessage::Rerank { .. }
+            | RequestMessage::Classification { .. } => None,
         };
         if is_chat
             && !get_mut_arcmutex!(self.pipeline)
@@ -550,10 +563,122 @@ impl Engine {
         }
 
         let images = match request.messages {
-            RequestMessage::VisionChat {
-                ref images,
-                messages: _,
-            } => Some(images.clone()),
+            RequestMessage::VisionChat { ref images, .. } => Some(images.clone()),
+            _ => None,
+        };
+
+        let videos = match request.messages {
+            RequestMessage::VisionChat { ref videos, .. } if !videos.is_empty() => {
+                if !get_mut_arcmutex!(self.pipeline)
+                    .get_processor()
+                    .supports_videos()
+                {
+                    request
+                        .response
+                        .send(Response::ValidationError(
+                            "Received a video for a model which does not support video inputs."
+                                .into(),
+                        ))
+                        .await
+                        .expect("Expected receiver.");
+                    return;
+                }
+                Some(
+                    videos
+                        .iter()
+                        .map(|video| video.sample_frames())
+                        .collect::<Vec<_>>(),
+                )
+            }
+            _ => None,
+        };
+
+        if matches!(request.messages, RequestMessage::Transcription { .. })
+            && get_mut_arcmutex!(self.pipeline).category() != ModelCategory::Speech
+        {
+            request
+                .response
+                .send(Response::ValidationError(
+                    "Received a transcription request for a model which is not a speech model."
+                        .into(),
+                ))
+                .await
+                .expect("Expected receiver.");
+            return;
+        }
+
+        if let RequestMessage::ImageGeneration {
+            ref generation_params,
+            ..
+        } = request.messages
+        {
+            if let Err(e) = generation_params.validate() {
+                request
+                    .response
+                    .send(Response::ValidationError(e.into()))
+                    .await
+                    .expect("Expected receiver.");
+                return;
+            }
+        }
+
+        let embedding_category_error = match request.messages {
+            RequestMessage::Embedding { .. }
+                if get_mut_arcmutex!(self.pipeline).category() != ModelCategory::Embedding =>
+            {
+                Some("Received an embedding request for a model which is not an embedding model.")
+            }
+            RequestMessage::Rerank { .. }
+                if get_mut_arcmutex!(self.pipeline).category() != ModelCategory::Reranker =>
+            {
+                Some("Received a rerank request for a model which is not a reranker model.")
+            }
+            RequestMessage::Classification { .. }
+                if get_mut_arcmutex!(self.pipeline).category() != ModelCategory::Reranker =>
+            {
+                Some("Received a classification request for a model which does not have a sequence classification head.")
+            }
+            RequestMessage::Classification { ref inputs }
+                if inputs
+                    .iter()
+                    .any(|input| matches!(input, ClassificationInput::Conversation(_)))
+                    && !get_mut_arcmutex!(self.pipeline)
+                        .get_chat_template()
+                        .as_ref()
+                        .is_some_and(|ch_t| ch_t.has_chat_template()) =>
+            {
+                Some("Received a conversation to classify for a model which does not have a chat template.")
+            }
+            _ => None,
+        };
+        if let Some(e) = embedding_category_error {
+            request
+                .response
+                .send(Response::ValidationError(e.into()))
+                .await
+                .expect("Expected receiver.");
+            return;
+        }
+
+        let audios = match request.messages {
+            RequestMessage::Transcription { ref audio, .. } => Some(vec![audio.clone()]),
+            RequestMessage::VisionChat { ref audios, .. } if !audios.is_empty() => {
+                if !get_mut_arcmutex!(self.pipeline)
+                    .get_processor()
+                    .supports_audios()
+                {
+                    request
+                        .response
+                        .send(Response::ValidationError(
+                            "Received audio for a model which does not support audio inputs."
+                                .into(),
+                        ))
+                        .await
+                        .expect("Expected receiver.");
+                    return;
+                }
+                Some(audios.clone())
+            }
             _ => None,
         };
 
@@ -572,7 +697,11 @@ impl Engine {
         };
 
         let seq_step_type = match &request.messages {
-            RequestMessage::ImageGeneration { .. } => SeqStepType::OneShot,
+            RequestMessage::ImageGeneration { .. }
+            | RequestMessage::Transcription { .. }
+            | RequestMessage::Embedding { .. }
+            | RequestMessage::Rerank { .. }
+            | RequestMessage::Classification { .. } => SeqStepType::OneShot,
             _ => SeqStepType::PromptAndDecode,
         };
 
@@ -583,20 +712,94 @@ impl Engine {
             _ => None,
         };
 
+        let transcription_params = match &request.messages {
+            RequestMessage::Transcription { params, .. } => Some(params.clone()),
+            _ => None,
+        };
+
+        let embedding_inputs = match &request.messages {
+            RequestMessage::Embedding { inputs, params } => Some(EmbeddingInputs::Embed {
+                inputs: inputs.clone(),
+                params: params.clone(),
+            }),
+            RequestMessage::Rerank {
+                query,
+                documents,
+                params,
+            } => Some(EmbeddingInputs::Rerank {
+                query: query.clone(),
+                documents: documents.clone(),
+                params: params.clone(),
+            }),
+            RequestMessage::Classification { inputs } => {
+                let pipeline = &*get_mut_arcmutex!(self.pipeline);
+                let inputs = inputs
+                    .iter()
+                    .map(|input| match input {
+                        ClassificationInput::Text(text) => Ok((text.clone(), true)),
+                        ClassificationInput::Conversation(messages) => apply_chat_template(
+                            pipeline,
+                            messages.clone(),
+                            false,
+                            false,
+                            MessagesAction::Keep,
+                            Vec::new(),
+                            None,
+                        )
+                        .map(|text| (text, false)),
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>();
+                Some(EmbeddingInputs::Classify {
+                    inputs: handle_seq_error!(inputs, request.response),
+                })
+            }
+            _ => None,
+        };
+        if let Some(inputs) = &embedding_inputs {
+            let tokenizer = get_mut_arcmutex!(self.pipeline).tokenizer();
+            if let Some(tokenizer) = tokenizer {
+                if let Err(e) = inputs.check_not_empty(&tokenizer) {
+                    request
+                        .response
+                        .send(Response::ValidationError(e.into()))
+                        .await
+                        .expect("Expected receiver.");
+                    return;
+                }
+            }
+        }
+
         let (mut prompt_tokens, prompt_text) = match request.messages {
-            RequestMessage::Chat(messages)
-            | RequestMessage::VisionChat {
-                images: _,
-                messages,
-            } => {
+            RequestMessage::Chat(messages) | RequestMessage::VisionChat { messages, .. } => {
                 let pipeline = &*get_mut_arcmutex!(self.pipeline);
-                let template = pipeline.get_processor().process(
-                    pipeline,
-                    messages,
-                    true,
-                    true,
-                    request.tools.unwrap_or_default(),
-                );
+                // The stored tokens of a continued conversation are only used for text models,
+                // whose prompts are tokenized directly from the chat template.
+                let continued = match &request.conversation {
+                    Some(conversation) if pipeline.category() == ModelCategory::Text => {
+                        process_continued_chat(
+                            pipeline,
+                            &messages,
+                            conversation,
+                            !request.continue_final_message,
+                            request.continue_final_message,
+                            request.tools.clone().unwrap_or_default(),
+                            request.chat_template_kwargs.clone(),
+                        )
+                    }
+                    _ => Ok(None),
+                };
+                let template = match handle_seq_error!(continued, request.response) {
+                    Some(continued) => Ok(continued),
+                    None => pipeline.get_processor().process(
+                        pipeline,
+                        messages,
+                        !request.continue_final_message,
+                        request.continue_final_message,
+                        true,
+                        request.tools.unwrap_or_default(),
+                        request.chat_template_kwargs,
+                    ),
+                };
                 handle_seq_error!(template, request.response)
             }
             RequestMessage::Completion { text, .. } => {
@@ -621,6 +824,12 @@ impl Engine {
                 )
             }
             RequestMessage::ImageGeneration { prompt, .. } => (vec![u32::MAX], prompt),
+            RequestMessage::Transcription { params, .. } => {
+                (vec![u32::MAX], params.prompt.unwrap_or_default())
+            }
+            RequestMessage::Embedding { .. }
+            | RequestMessage::Rerank { .. }
+            | RequestMessage::Classification { .. } => (vec![u32::MAX], String::new()),
             RequestMessage::CompletionTokens(it) => {
                 let Some(tokenizer) = &get_mut_arcmutex!(self.pipeline).tokenizer() else {
                     request
@@ -678,6 +887,8 @@ impl Engine {
             self.prefix_cacher.search_for_matching_cache(
                 &prompt_tokens,
                 images.as_ref().is_some_and(|x| !x.is_empty())
+                    || videos.is_some()
+                    || audios.is_some()
             ),
             request.response
         );
@@ -764,12 +975,17 @@ impl Engine {
             }
         };
 
-        let group = Arc::new(tokio::sync::Mutex::new(SequenceGroup::new(
+        let mut group = SequenceGroup::new(
             request.sampling_params.n_choices,
             request.is_streaming,
             is_chat,
             best_of,
-        )));
+        );
+        group.return_conversation_tokens = request
+            .conversation
+            .as_ref()
+            .is_some_and(|conversation| conversation.return_tokens);
+        let group = Arc::new(tokio::sync::Mutex::new(group));
 
         let tokenizer = get_mut_arcmutex!(self.pipeline).tokenizer();
 
@@ -923,14 +1139,19 @@ impl Engine {
                 },
                 request.adapters.clone(),
                 images.clone(),
+                videos.clone(),
+                audios.clone(),
                 block_size,
                 matcher.clone(),
                 image_generation_format,
                 seq_step_type,
                 diffusion_params.clone(),
+                transcription_params.clone(),
+                embedding_inputs.clone(),
                 seq_preallocated_cache,
                 request.return_raw_logits,
-            );
+            )
+            .with_priority(request.priority);
             let seq = if let Some(prefill_cache) = prefill_cache.clone() {
                 seq.prefill_v2(
                     prefill_cache.normal,
@@ -953,8 +1174,10 @@ impl Engine {
                     pipeline,
                     messages,
                     request.add_generation_prompt,
+                    false,
                     request.add_special_tokens,
                     request.tools.unwrap_or_default(),
+                    request.chat_template_kwargs,
                 );
                 let toks = match template {
                     Ok((toks, _)) => toks,
@@ -1,14 +1,16 @@
-use std::{collections::HashMap, fs};
+use std::{collections::HashMap, fs, sync::Arc};
 
 use anyhow::Context;
 use candle_core::{
     quantized::{
+        ggml_file,
         gguf_file::{self, TensorInfo, Value},
         QTensor,
     },
     Device, Result,
 };
 use indexmap::IndexMap;
+use mistralrs_quant::{GgufMatMul, QuantMethod, QuantMethodConfig};
 use tracing::info;
 
 use crate::DEBUG;
@@ -133,6 +135,41 @@ impl<'a, R: std::io::Seek + std::io::Read> Content<'a, R> {
         candle_core::bail!("Cannot find tensor info for {name}")
     }
 
+    /// Retrieve a 3D tensor holding one matrix per expert (such as `ffn_gate_exps.weight`) and split
+    /// it into one tensor per expert. Quantization blocks never span experts, so the split is done on
+    /// the raw data and the experts keep their original quantization.
+    pub fn expert_tensors(
+        &mut self,
+        name: &str,
+        n_expert: usize,
+        device: &Device,
+    ) -> Result<Vec<QTensor>> {
+        let stacked = self.tensor(name, &Device::Cpu)?;
+        split_experts(name, &stacked, n_expert, device)
+    }
+
+    /// Load `{prefix}.weight` and, if `bias` is set, `{prefix}.bias` as a quantized linear layer.
+    pub fn linear(
+        &mut self,
+        prefix: &str,
+        bias: bool,
+        device: &Device,
+    ) -> Result<Arc<dyn QuantMethod>> {
+        let q_weight = self.tensor(&format!("{prefix}.weight"), device)?;
+        let b = if bias {
+            Some(
+                self.tensor(&format!("{prefix}.bias"), device)?
+                    .dequantize(device)?,
+            )
+        } else {
+            None
+        };
+        Ok(Arc::new(GgufMatMul::new(QuantMethodConfig::Gguf {
+            q_weight: Arc::new(q_weight),
+            b,
+        })?))
+    }
+
     /// Check for a tensor, searching through each content.
     pub fn has_tensor(&self, name: &str) -> bool {
         for ct in self.contents.iter() {
@@ -198,3 +235,82 @@ impl<'a, R: std::io::Seek + std::io::Read> Content<'a, R> {
         &self.all_metadata
     }
 }
+
+/// Split a stacked `(n_expert, _, _)` tensor into one tensor per expert.
+fn split_experts(
+    name: &str,
+    stacked: &QTensor,
+    n_expert: usize,
+    device: &Device,
+) -> Result<Vec<QTensor>> {
+    let dims = stacked.shape().dims().to_vec();
+    if dims.len() != 3 || dims[0] != n_expert {
+        candle_core::bail!(
+            "Expected `{name}` to have shape ({n_expert}, _, _), got {:?}",
+            dims
+        );
+    }
+    let data = stacked.data()?;
+    let expert_bytes = data.len() / n_expert;
+    data.chunks_exact(expert_bytes)
+        .map(|raw| {
+            ggml_file::qtensor_from_ggml(stacked.dtype(), raw, vec![dims[1], dims[2]], device)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{
+        quantized::{GgmlDType, QTensor},
+        Device, Tensor,
+    };
+
+    use super::split_experts;
+
+    #[test]
+    fn split_experts_matches_dequantized_chunks() -> candle_core::Result<()> {
+        let dev = Device::Cpu;
+        let n_expert = 3;
+        for (dtype, cols) in [
+            (GgmlDType::F32, 32),
+            (GgmlDType::Q4_0, 64),
+            (GgmlDType::Q8_0, 64),
+            (GgmlDType::Q4K, 256),
+            (GgmlDType::Q6K, 256),
+        ] {
+            let xs = Tensor::randn(0f32, 1., (n_expert, 4, cols), &dev)?;
+            let stacked = QTensor::quantize(&xs, dtype)?;
+            let experts = split_experts("ffn_up_exps.weight", &stacked, n_expert, &dev)?;
+            assert_eq!(experts.len(), n_expert);
+
+            let expected = stacked.dequantize(&dev)?.chunk(n_expert, 0)?;
+            for (expert, expected) in experts.iter().zip(expected) {
+                assert_eq!(expert.dtype(), dtype);
+                assert_eq!(expert.shape().dims(), &[4, cols]);
+                assert_eq!(
+                    expert.dequantize(&dev)?.to_vec2::<f32>()?,
+                    expected.squeeze(0)?.to_vec2::<f32>()?,
+                    "{dtype:?}"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn split_experts_checks_the_shape() -> candle_core::Result<()> {
+        let dev = Device::Cpu;
+        let stacked = QTensor::quantize(
+            &Tensor::zeros((2, 4, 32), candle_core::DType::F32, &dev)?,
+            GgmlDType::F32,
+        )?;
+        assert!(split_experts("ffn_up_exps.weight", &stacked, 3, &dev).is_err());
+        let flat = QTensor::quantize(
+            &Tensor::zeros((4, 32), candle_core::DType::F32, &dev)?,
+            GgmlDType::F32,
+        )?;
+        assert!(split_experts("ffn_up_exps.weight", &flat, 4, &dev).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,624 @@
+// Metadata keys and tensor layout follow llama.cpp's `convert_hf_to_gguf.py`:
+// https://github.com/ggerganov/llama.cpp/blob/master/convert_hf_to_gguf.py
+
+use std::{collections::HashMap, fs::File, io::BufWriter, path::Path, sync::Arc};
+
+use anyhow::{Context, Result};
+use candle_core::{
+    quantized::{
+        ggml_file::qtensor_from_ggml,
+        gguf_file::{self, Value},
+        GgmlDType, QTensor,
+    },
+    DType, Device, Tensor,
+};
+use mistralrs_quant::{QuantMethod, QuantizedSerde};
+use serde_json::Value as JsonValue;
+use tokenizers::Tokenizer;
+use tracing::info;
+
+use crate::pipeline::UqffFullSer;
+
+use super::GGUFArchitecture;
+
+// https://github.com/ggerganov/llama.cpp/blob/master/gguf-py/gguf/constants.py
+const TOKEN_TYPE_NORMAL: i32 = 1;
+const TOKEN_TYPE_UNKNOWN: i32 = 2;
+const TOKEN_TYPE_CONTROL: i32 = 3;
+const TOKEN_TYPE_USER_DEFINED: i32 = 4;
+const TOKEN_TYPE_BYTE: i32 = 6;
+
+const GGUF_QUANTIZATION_VERSION: u32 = 2;
+
+/// Write the layers (with their GGUF names) and residual tensors of an ISQ model as a llama.cpp
+/// compatible GGUF file. The hyperparameters and tokenizer metadata are taken from `full_ser`.
+///
+/// All layers must be GGUF quantized or unquantized.
+pub(crate) fn write_gguf(
+    path: &Path,
+    layers: Vec<(String, Arc<dyn QuantMethod>)>,
+    residual: Vec<(String, Tensor)>,
+    full_ser: &UqffFullSer<'_>,
+) -> Result<()> {
+    let config: JsonValue = serde_json::from_str(&full_ser.config)?;
+    let arch = export_arch(&config)?;
+    let hparams = Hparams::from_config(&config)?;
+
+    let mut metadata = model_metadata(arch, &hparams);
+    let mut tensors: Vec<(String, Arc<QTensor>)> = Vec::new();
+    if let Some(rope_freqs) = rope_scaling(arch, &config, &hparams, &mut metadata)? {
+        tensors.push((
+            "rope_freqs.weight".to_string(),
+            Arc::new(QTensor::quantize(&rope_freqs, GgmlDType::F32)?),
+        ));
+    }
+    metadata.extend(tokenizer_metadata(&config, full_ser)?);
+
+    for (name, layer) in layers {
+        let (weight, bias) = if let Some((w, b)) = layer.gguf_weight_bias() {
+            (w, b)
+        } else if let Some((w, b)) = layer.unquant_weight_bias() {
+            (Arc::new(unquantized_qtensor(&w)?), b)
+        } else {
+            anyhow::bail!(
+                "Layer `{name}` is a `{}` layer, only GGUF quantized and unquantized layers can be exported to GGUF.",
+                layer.name()
+            );
+        };
+
+        // llama.cpp rotates adjacent pairs in the Llama architecture, not the two halves of each head.
+        let n_head = if !matches!(arch, GGUFArchitecture::Llama) {
+            None
+        } else if name.ends_with(".attn_q.weight") {
+            Some(hparams.n_head)
+        } else if name.ends_with(".attn_k.weight") {
+            Some(hparams.n_head_kv)
+        } else {
+            None
+        };
+        let (weight, bias) = match n_head {
+            Some(n_head) => {
+                let perm = rope_permutation(weight.shape().dims()[0], n_head);
+                let bias = match bias {
+                    Some(b) => {
+                        let idx = perm.iter().map(|&i| i as u32).collect::<Vec<_>>();
+                        let idx = Tensor::new(idx, &Device::Cpu)?;
+                        Some(b.to_device(&Device::Cpu)?.index_select(&idx, 0)?)
+                    }
+                    None => None,
+                };
+                (Arc::new(permute_rows(&weight, &perm)?), bias)
+            }
+            None => (weight, bias),
+        };
+
+        if let Some(bias) = bias {
+            let bias_name = name.replace(".weight", ".bias");
+            tensors.push((bias_name, Arc::new(residual_qtensor(&bias)?)));
+        }
+        tensors.push((name, weight));
+    }
+
+    for (name, tensor) in residual {
+        let gguf_name = residual_gguf_name(&name)?;
+        tensors.push((gguf_name, Arc::new(residual_qtensor(&tensor)?)));
+    }
+
+    info!(
+        "Writing {} tensors and {} metadata entries to GGUF file `{}`.",
+        tensors.len(),
+        metadata.len(),
+        path.display()
+    );
+
+    let metadata = metadata
+        .iter()
+        .map(|(k, v)| (k.as_str(), v))
+        .collect::<Vec<_>>();
+    let tensors = tensors
+        .iter()
+        .map(|(k, v)| (k.as_str(), &**v))
+        .collect::<Vec<_>>();
+    let mut file = BufWriter::new(File::create(path)?);
+    gguf_file::write(&mut file, &metadata, &tensors)?;
+    Ok(())
+}
+
+fn export_arch(config: &JsonValue) -> Result<GGUFArchitecture> {
+    match config["model_type"].as_str() {
+        // Like llama.cpp, Mistral models are exported to the Llama architecture.
+        Some("llama" | "mistral") => Ok(GGUFArchitecture::Llama),
+        Some("qwen2") => Ok(GGUFArchitecture::Qwen2),
+        Some(other) => anyhow::bail!(
+            "Exporting `{other}` models to GGUF is not supported, expected one of `llama`, `mistral` or `qwen2`."
+        ),
+        None => anyhow::bail!("Model config has no `model_type`."),
+    }
+}
+
+struct Hparams {
+    n_layer: u64,
+    n_embd: u64,
+    n_ff: u64,
+    n_head: usize,
+    n_head_kv: usize,
+    head_dim: u64,
+    n_ctx: u64,
+    rms_norm_eps: f64,
+    rope_theta: f64,
+    vocab_size: Option<u64>,
+}
+
+impl Hparams {
+    fn from_config(config: &JsonValue) -> Result<Self> {
+        let get = |key: &str| {
+            config[key]
+                .as_u64()
+                .with_context(|| format!("Model config is missing `{key}`."))
+        };
+        let n_embd = get("hidden_size")?;
+        let n_head = get("num_attention_heads")?;
+        Ok(Self {
+            n_layer: get("num_hidden_layers")?,
+            n_embd,
+            n_ff: get("intermediate_size")?,
+            n_head: n_head as usize,
+            n_head_kv: config["num_key_value_heads"].as_u64().unwrap_or(n_head) as usize,
+            head_dim: config["head_dim"].as_u64().unwrap_or(n_embd / n_head),
+            n_ctx: get("max_position_embeddings")?,
+            rms_norm_eps: config["rms_norm_eps"]
+                .as_f64()
+                .context("Model config is missing `rms_norm_eps`.")?,
+            rope_theta: config["rope_theta"].as_f64().unwrap_or(10_000.),
+            vocab_size: config["vocab_size"].as_u64(),
+        })
+    }
+}
+
+fn model_metadata(arch: GGUFArchitecture, hparams: &Hparams) -> Vec<(String, Value)> {
+    let mut metadata = vec![
+        (
+            "general.architecture".to_string(),
+            Value::String(arch.to_string()),
+        ),
+        (
+            "general.quantization_version".to_string(),
+            Value::U32(GGUF_QUANTIZATION_VERSION),
+        ),
+        (
+            format!("{arch}.context_length"),
+            Value::U32(hparams.n_ctx as u32),
+        ),
+        (
+            format!("{arch}.embedding_length"),
+            Value::U32(hparams.n_embd as u32),
+        ),
+        (
+            format!("{arch}.block_count"),
+            Value::U32(hparams.n_layer as u32),
+        ),
+        (
+            format!("{arch}.feed_forward_length"),
+            Value::U32(hparams.n_ff as u32),
+        ),
+        (
+            format!("{arch}.attention.head_count"),
+            Value::U32(hparams.n_head as u32),
+        ),
+        (
+            format!("{arch}.attention.head_count_kv"),
+            Value::U32(hparams.n_head_kv as u32),
+        ),
+        (
+            format!("{arch}.attention.layer_norm_rms_epsilon"),
+            Value::F32(hparams.rms_norm_eps as f32),
+        ),
+        (
+            format!("{arch}.rope.freq_base"),
+            Value::F32(hparams.rope_theta as f32),
+        ),
+        (
+            format!("{arch}.rope.dimension_count"),
+            Value::U32(hparams.head_dim as u32),
+        ),
+    ];
+    if hparams.head_dim != hparams.n_embd / hparams.n_head as u64 {
+        metadata.push((
+            format!("{arch}.attention.key_length"),
+            Value::U32(hparams.head_dim as u32),
+        ));
+        metadata.push((
+            format!("{arch}.attention.value_length"),
+            Value::U32(hparams.head_dim as u32),
+        ));
+    }
+    if let Some(vocab_size) = hparams.vocab_size {
+        metadata.push((format!("{arch}.vocab_size"), Value::U32(vocab_size as u32)));
+    }
+    metadata
+}
+
+/// Add the RoPE scaling metadata. Llama 3 scaling is stored as a `rope_freqs.weight` tensor of
+/// per-frequency factors, which is returned.
+fn rope_scaling(
+    arch: GGUFArchitecture,
+    config: &JsonValue,
+    hparams: &Hparams,
+    metadata: &mut Vec<(String, Value)>,
+) -> Result<Option<Tensor>> {
+    let scaling = &config["rope_scaling"];
+    if scaling.is_null() {
+        return Ok(None);
+    }
+    let rope_type = scaling["rope_type"]
+        .as_str()
+        .or(scaling["type"].as_str())
+        .context("`rope_scaling` has no `rope_type`.")?;
+    let factor = scaling["factor"]
+        .as_f64()
+        .context("`rope_scaling` has no `factor`.")?;
+    match rope_type {
+        "default" => Ok(None),
+        "linear" => {
+            metadata.push((
+                format!("{arch}.rope.scaling.type"),
+                Value::String("linear".to_string()),
+            ));
+            metadata.push((
+                format!("{arch}.rope.scaling.factor"),
+                Value::F32(factor as f32),
+            ));
+            Ok(None)
+        }
+        "llama3" => {
+            let get = |key: &str| {
+                scaling[key]
+                    .as_f64()
+                    .with_context(|| format!("`rope_scaling` has no `{key}`."))
+            };
+            let low_freq_factor = get("low_freq_factor")?;
+            let high_freq_factor = get("high_freq_factor")?;
+            let old_ctx = get("original_max_position_embeddings")?;
+            let low_freq_wavelen = old_ctx / low_freq_factor;
+            let high_freq_wavelen = old_ctx / high_freq_factor;
+
+            let dim = hparams.head_dim as usize;
+            let factors = (0..dim)
+                .step_by(2)
+                .map(|i| {
+                    let freq = 1. / hparams.rope_theta.powf(i as f64 / dim as f64);
+                    let wavelen = 2. * std::f64::consts::PI / freq;
+                    if wavelen < high_freq_wavelen {
+                        1.
+                    } else if wavelen > low_freq_wavelen {
+                        factor as f32
+                    } else {
+                        let smooth = (old_ctx / wavelen - low_freq_factor)
+                            / (high_freq_factor - low_freq_factor);
+                        (1. / ((1. - smooth) / factor + smooth)) as f32
+                    }
+                })
+                .collect::<Vec<_>>();
+            Ok(Some(Tensor::new(factors, &Device::Cpu)?))
+        }
+        other => anyhow::bail!("RoPE scaling type `{other}` cannot be exported to GGUF."),
+    }
+}
+
+/// For each row of the GGUF tensor, the row of the HF tensor. HF checkpoints of the Llama
+/// architecture order the rows of the Q and K projections so that RoPE rotates the two halves of
+/// each head.
+fn rope_permutation(n_rows: usize, n_head: usize) -> Vec<usize> {
+    let head_dim = n_rows / n_head;
+    let half = head_dim / 2;
+    let mut perm = Vec::with_capacity(n_rows);
+    for h in 0..n_head {
+        for j in 0..half {
+            perm.push(h * head_dim + j);
+            perm.push(h * head_dim + half + j);
+        }
+    }
+    perm
+}
+
+/// Reorder the rows of a 2D quantized tensor. Blocks never span rows, so this is done on the raw data.
+fn permute_rows(w: &QTensor, perm: &[usize]) -> Result<QTensor> {
+    let dims = w.shape().dims().to_vec();
+    let data = w.data()?;
+    let row_bytes = data.len() / dims[0];
+    let mut out = Vec::with_capacity(data.len());
+    for &row in perm {
+        out.extend_from_slice(&data[row * row_bytes..(row + 1) * row_bytes]);
+    }
+    Ok(qtensor_from_ggml(w.dtype(), &out, dims, &Device::Cpu)?)
+}
+
+fn unquantized_qtensor(w: &Tensor) -> Result<QTensor> {
+    let w = w.to_device(&Device::Cpu)?;
+    let dtype = match w.dtype() {
+        DType::F32 => GgmlDType::F32,
+        DType::BF16 => GgmlDType::BF16,
+        _ => GgmlDType::F16,
+    };
+    Ok(QTensor::quantize(&w, dtype)?)
+}
+
+/// Norms and biases are stored in F32, as llama.cpp expects.
+fn residual_qtensor(t: &Tensor) -> Result<QTensor> {
+    if t.rank() == 1 {
+        let t = t.to_device(&Device::Cpu)?.to_dtype(DType::F32)?;
+        Ok(QTensor::quantize(&t, GgmlDType::F32)?)
+    } else {
+        unquantized_qtensor(t)
+    }
+}
+
+/// Map the name of a residual tensor, as produced by `IsqModel::residual_tensors`, to GGUF.
+fn residual_gguf_name(name: &str) -> Result<String> {
+    match name {
+        "model.embed_tokens.weight" => return Ok("token_embd.weight".to_string()),
+        "model.norm.weight" => return Ok("output_norm.weight".to_string()),
+        "lm_head.weight" => return Ok("output.weight".to_string()),
+        _ => (),
+    }
+    if let Some(rest) = name.strip_prefix("model.layers.") {
+        if let Some((layer, tensor)) = rest.split_once('.') {
+            let tensor = match tensor {
+                "input_layernorm.weight" => Some("attn_norm.weight"),
+                "post_attention_layernorm.weight" => Some("ffn_norm.weight"),
+                _ => None,
+            };
+            if let (Ok(layer), Some(tensor)) = (layer.parse::<usize>(), tensor) {
+                return Ok(format!("blk.{layer}.{tensor}"));
+            }
+        }
+    }
+    anyhow::bail!("Tensor `{name}` has no GGUF counterpart.")
+}
+
+/// The id of a special token given in `tokenizer_config.json`, either as a string or as an added token.
+fn special_token_id(tokenizer: &Tokenizer, tokenizer_config: &JsonValue, key: &str) -> Option<u32> {
+    let token = &tokenizer_config[key];
+    let content = token.as_str().or(token["content"].as_str())?;
+    tokenizer.token_to_id(content)
+}
+
+fn tokenizer_metadata(
+    config: &JsonValue,
+    full_ser: &UqffFullSer<'_>,
+) -> Result<Vec<(String, Value)>> {
+    let tokenizer = full_ser.tokenizer;
+    let tokenizer_json: JsonValue =
+        serde_json::from_str(&tokenizer.to_string(false).map_err(anyhow::Error::msg)?)?;
+    let tokenizer_config: JsonValue = match full_ser.template_filename {
+        Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        None => JsonValue::Null,
+    };
+    let generation_config: JsonValue = match full_ser.generation_config {
+        Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        None => JsonValue::Null,
+    };
+
+    let model = &tokenizer_json["model"];
+    let byte_fallback = model["byte_fallback"].as_bool().unwrap_or(false);
+
+    // Token strings and scores by id. Ids missing from the vocab are padded, as done by llama.cpp.
+    let mut vocab: HashMap<u32, (String, f32)> = HashMap::new();
+    let ggml_model = match model["type"].as_str() {
+        Some("BPE") => {
+            let entries = model["vocab"]
+                .as_object()
+                .context("BPE tokenizer has no vocab.")?;
+            for (token, id) in entries {
+                let id = id.as_u64().context("Invalid token id.")? as u32;
+                vocab.insert(id, (token.clone(), -(id as f32)));
+            }
+            // SentencePiece BPE tokenizers are exported as `llama` tokenizers, where the score is
+            // the merge priority.
+            if byte_fallback {
+                "llama"
+            } else {
+                "gpt2"
+            }
+        }
+        Some("Unigram") => {
+            let entries = model["vocab"]
+                .as_array()
+                .context("Unigram tokenizer has no vocab.")?;
+            for (id, entry) in entries.iter().enumerate() {
+                let token = entry[0].as_str().context("Invalid unigram token.")?;
+                let score = entry[1].as_f64().context("Invalid unigram score.")?;
+                vocab.insert(id as u32, (token.to_string(), score as f32));
+            }
+            "llama"
+        }
+        other => anyhow::bail!("Tokenizer model `{other:?}` cannot be exported to GGUF."),
+    };
+
+    let mut added = HashMap::new();
+    for token in tokenizer_json["added_tokens"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        let id = token["id"].as_u64().context("Invalid added token id.")? as u32;
+        let content = token["content"].as_str().context("Invalid added token.")?;
+        let special = token["special"].as_bool().unwrap_or(false);
+        vocab.insert(id, (content.to_string(), 0.));
+        added.insert(id, special);
+    }
+
+    let unk = model["unk_id"].as_u64().map(|x| x as u32).or_else(|| {
+        model["unk_token"]
+            .as_str()
+            .and_then(|t| tokenizer.token_to_id(t))
+    });
+
+    let n_tokens = vocab.keys().max().map(|x| x + 1).unwrap_or(0);
+    let mut tokens = Vec::with_capacity(n_tokens as usize);
+    let mut scores = Vec::with_capacity(n_tokens as usize);
+    let mut token_types = Vec::with_capacity(n_tokens as usize);
+    for id in 0..n_tokens {
+        let (token, score) = vocab
+            .remove(&id)
+            .unwrap_or_else(|| (format!("[PAD{id}]"), 0.));
+        let token_type = if Some(id) == unk {
+            TOKEN_TYPE_UNKNOWN
+        } else if let Some(special) = added.get(&id) {
+            if *special {
+                TOKEN_TYPE_CONTROL
+            } else {
+                TOKEN_TYPE_USER_DEFINED
+            }
+        } else if byte_fallback && token.len() == 6 && token.starts_with("<0x") {
+            TOKEN_TYPE_BYTE
+        } else if token.starts_with("[PAD") {
+            TOKEN_TYPE_USER_DEFINED
+        } else {
+            TOKEN_TYPE_NORMAL
+        };
+        tokens.push(Value::String(token));
+        scores.push(Value::F32(score));
+        token_types.push(Value::I32(token_type));
+    }
+
+    let mut metadata = vec![
+        (
+            "tokenizer.ggml.model".to_string(),
+            Value::String(ggml_model.to_string()),
+        ),
+        ("tokenizer.ggml.tokens".to_string(), Value::Array(tokens)),
+        (
+            "tokenizer.ggml.token_type".to_string(),
+            Value::Array(token_types),
+        ),
+    ];
+    if ggml_model == "llama" {
+        metadata.push(("tokenizer.ggml.scores".to_string(), Value::Array(scores)));
+    } else {
+        let merges = model["merges"]
+            .as_array()
+            .context("BPE tokenizer has no merges.")?
+            .iter()
+            .map(|merge| match merge {
+                JsonValue::String(merge) => Ok(Value::String(merge.clone())),
+                JsonValue::Array(pair) => Ok(Value::String(format!(
+                    "{} {}",
+                    pair[0].as_str().context("Invalid merge.")?,
+                    pair[1].as_str().context("Invalid merge.")?
+                ))),
+                _ => anyhow::bail!("Invalid merge `{merge}`."),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        metadata.push(("tokenizer.ggml.merges".to_string(), Value::Array(merges)));
+        let pre = match config["model_type"].as_str() {
+            Some("llama") => "llama-bpe",
+            Some("qwen2") => "qwen2",
+            _ => "default",
+        };
+        metadata.push((
+            "tokenizer.ggml.pre".to_string(),
+            Value::String(pre.to_string()),
+        ));
+    }
+
+    // Special tokens from `tokenizer_config.json`, then `generation_config.json` and `config.json`.
+    let fallback_id = |key: &str| {
+        [&generation_config[key], &config[key]]
+            .into_iter()
+            .find_map(|v| v.as_u64().or(v[0].as_u64()))
+            .map(|x| x as u32)
+    };
+    let bos = special_token_id(tokenizer, &tokenizer_config, "bos_token")
+        .or_else(|| fallback_id("bos_token_id"))
+        .context("Could not find the BOS token for the GGUF tokenizer.")?;
+    let eos = special_token_id(tokenizer, &tokenizer_config, "eos_token")
+        .or_else(|| fallback_id("eos_token_id"))
+        .context("Could not find the EOS token for the GGUF tokenizer.")?;
+    metadata.push(("tokenizer.ggml.bos_token_id".to_string(), Value::U32(bos)));
+    metadata.push(("tokenizer.ggml.eos_token_id".to_string(), Value::U32(eos)));
+    if let Some(unk) = unk {
+        metadata.push((
+            "tokenizer.ggml.unknown_token_id".to_string(),
+            Value::U32(unk),
+        ));
+    }
+    if let Some(pad) = special_token_id(tokenizer, &tokenizer_config, "pad_token") {
+        metadata.push((
+            "tokenizer.ggml.padding_token_id".to_string(),
+            Value::U32(pad),
+        ));
+    }
+    for key in ["add_bos_token", "add_eos_token"] {
+        if let Some(add) = tokenizer_config[key].as_bool() {
+            metadata.push((format!("tokenizer.ggml.{key}"), Value::Bool(add)));
+        }
+    }
+
+    let chat_template = match &tokenizer_config["chat_template"] {
+        JsonValue::String(template) => Some(template.clone()),
+        // Named templates, use the default one
+        JsonValue::Array(templates) => templates
+            .iter()
+            .find(|t| t["name"] == "default")
+            .and_then(|t| t["template"].as_str())
+            .map(ToString::to_string),
+        _ => None,
+    };
+    if let Some(chat_template) = chat_template {
+        metadata.push((
+            "tokenizer.chat_template".to_string(),
+            Value::String(chat_template),
+        ));
+    }
+
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{
+        quantized::{GgmlDType, QTensor},
+        Device, Tensor,
+    };
+
+    use super::{permute_rows, residual_gguf_name, rope_permutation};
+
+    #[test]
+    fn test_rope_permutation() {
+        // 2 heads with a head dim of 4: the halves `[0, 1] [2, 3]` are interleaved.
+        assert_eq!(rope_permutation(8, 2), vec![0, 2, 1, 3, 4, 6, 5, 7]);
+    }
+
+    #[test]
+    fn test_permute_quantized_rows() -> anyhow::Result<()> {
+        let dev = Device::Cpu;
+        let w = Tensor::randn(0f32, 1., (8, 64), &dev)?;
+        let q = QTensor::quantize(&w, GgmlDType::Q8_0)?;
+        let perm = rope_permutation(8, 2);
+
+        let permuted = permute_rows(&q, &perm)?.dequantize(&dev)?;
+        let idx = Tensor::new(perm.iter().map(|&i| i as u32).collect::<Vec<_>>(), &dev)?;
+        let expected = q.dequantize(&dev)?.index_select(&idx, 0)?;
+        let diff = (permuted - expected)?
+            .abs()?
+            .sum_all()?
+            .to_scalar::<f32>()?;
+        assert_eq!(diff, 0.);
+        Ok(())
+    }
+
+    #[test]
+    fn test_residual_gguf_names() -> anyhow::Result<()> {
+        assert_eq!(
+            residual_gguf_name("model.embed_tokens.weight")?,
+            "token_embd.weight"
+        );
+        assert_eq!(
+            residual_gguf_name("model.layers.12.post_attention_layernorm.weight")?,
+            "blk.12.ffn_norm.weight"
+        );
+        assert!(residual_gguf_name("model.layers.0.self_attn.rotary_emb.inv_freq").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,736 @@
+use std::{collections::HashMap, fmt::Display, fs::File, path::Path};
+
+use anyhow::{Context, Result};
+use candle_core::{quantized::gguf_file, DType, Device, Tensor};
+use mistralrs_quant::{ShardedSafeTensors, ShardedVarBuilder};
+use tracing::{info, warn};
+
+use crate::utils::gguf_metadata::ContentMetadata;
+
+const VISION_TOWER: &str = "vision_tower.vision_model";
+const QWEN2VL_VISION: &str = "visual";
+const MINICPM_VISION: &str = "vpm";
+
+/// The multimodal projector of an mmproj file, which also decides the vision encoder. These are
+/// the llama.cpp `clip.projector_type` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectorType {
+    /// LLaVA and LLaVA-Next: a CLIP encoder and a two layer MLP (`mlp`).
+    Mlp,
+    /// Qwen2-VL: the Qwen2-VL encoder and patch merger (`qwen2vl_merger`).
+    Qwen2VLMerger,
+    /// MiniCPM-o: a SigLIP encoder and a perceiver resampler (`resampler`).
+    Resampler,
+}
+
+impl ProjectorType {
+    fn from_metadata(metadata: &ContentMetadata) -> Result<Self> {
+        let projector_type = match metadata.get_option_value::<String>("projector_type")? {
+            Some(projector_type) => Some(projector_type),
+            None => metadata.get_option_value::<String>("vision.projector_type")?,
+        };
+        match projector_type.as_deref() {
+            Some("mlp") => Ok(Self::Mlp),
+            Some("qwen2vl_merger") => Ok(Self::Qwen2VLMerger),
+            Some("resampler") => Ok(Self::Resampler),
+            Some(other) => anyhow::bail!(
+                "Projector type `{other}` is not supported, expected `mlp` (LLaVA), `qwen2vl_merger` (Qwen2-VL) or `resampler` (MiniCPM-o)."
+            ),
+            // Older files only flag the projector, and LLaVA files may not set anything.
+            None => {
+                let has_flag = |key: &str| -> Result<bool> {
+                    Ok(metadata.get_option_value::<bool>(key)?.unwrap_or(false))
+                };
+                if has_flag("has_qwen2vl_merger")? {
+                    Ok(Self::Qwen2VLMerger)
+                } else if has_flag("has_minicpmv_projector")? {
+                    Ok(Self::Resampler)
+                } else {
+                    Ok(Self::Mlp)
+                }
+            }
+        }
+    }
+}
+
+impl Display for ProjectorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mlp => write!(f, "mlp"),
+            Self::Qwen2VLMerger => write!(f, "qwen2vl_merger"),
+            Self::Resampler => write!(f, "resampler"),
+        }
+    }
+}
+
+/// The vision encoder and multimodal projector of a llama.cpp `mmproj` GGUF file. The tensors are
+/// dequantized and renamed to their Hugging Face names so the vision models can load them.
+pub struct Mmproj {
+    pub projector_type: ProjectorType,
+    /// Number of encoder layers in the file. For LLaVA, llama.cpp drops the trailing layers which
+    /// come after the feature layer.
+    pub block_count: usize,
+    pub hidden_size: usize,
+    // Not written by every converter, so only checked when present.
+    intermediate_size: Option<usize>,
+    head_count: Option<usize>,
+    patch_size: Option<usize>,
+    tensors: HashMap<String, Tensor>,
+}
+
+impl Mmproj {
+    pub fn from_path(path: &Path, device: &Device) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let ct = gguf_file::Content::read(&mut file)?;
+        let metadata = ContentMetadata {
+            path_prefix: "clip",
+            metadata: &ct.metadata,
+        };
+        metadata.verify_arch("clip")?;
+        metadata.has_required_keys(&["vision.block_count", "vision.embedding_length"])?;
+
+        let projector_type = ProjectorType::from_metadata(&metadata)?;
+        let block_count = metadata.get_value::<u32>("vision.block_count")? as usize;
+        let hidden_size = metadata.get_value::<u32>("vision.embedding_length")? as usize;
+        // The Qwen2-VL converter writes a feed forward length of 0.
+        let intermediate_size = metadata
+            .get_option_value::<u32>("vision.feed_forward_length")?
+            .map(|x| x as usize)
+            .filter(|&x| x != 0);
+        let head_count = metadata
+            .get_option_value::<u32>("vision.attention.head_count")?
+            .map(|x| x as usize);
+        let patch_size = metadata
+            .get_option_value::<u32>("vision.patch_size")?
+            .map(|x| x as usize);
+
+        let mut tensors = HashMap::new();
+        for (name, tensor_info) in &ct.tensor_infos {
+            // `fc1` is the feed forward projection which does not output the hidden size.
+            let is_fc1 = tensor_info.shape.dims()[0] != hidden_size;
+            let Some(hf_name) = hf_tensor_name(projector_type, name, is_fc1) else {
+                // The resampler computes its position embeddings itself.
+                if name != "resampler.pos_embed_k" {
+                    warn!("Skipping unknown mmproj tensor `{name}`");
+                }
+                continue;
+            };
+            let tensor = tensor_info
+                .read(&mut file, ct.tensor_data_offset, device)?
+                .dequantize(device)?;
+            tensors.insert(hf_name, tensor);
+        }
+
+        match projector_type {
+            ProjectorType::Mlp => {
+                // The final norm is not used for the image features, so llama.cpp does not always
+                // export it.
+                let post_layernorm = format!("{VISION_TOWER}.post_layernorm");
+                if !tensors.contains_key(&format!("{post_layernorm}.weight")) {
+                    tensors.insert(
+                        format!("{post_layernorm}.weight"),
+                        Tensor::ones(hidden_size, DType::F32, device)?,
+                    );
+                    tensors.insert(
+                        format!("{post_layernorm}.bias"),
+                        Tensor::zeros(hidden_size, DType::F32, device)?,
+                    );
+                }
+            }
+            ProjectorType::Qwen2VLMerger => join_qwen2vl_tensors(&mut tensors, block_count)?,
+            ProjectorType::Resampler => join_resampler_tensors(&mut tensors)?,
+        }
+
+        info!(
+            "Loaded mmproj file with a `{projector_type}` projector and {block_count} vision encoder layers."
+        );
+        Ok(Self {
+            projector_type,
+            block_count,
+            hidden_size,
+            intermediate_size,
+            head_count,
+            patch_size,
+            tensors,
+        })
+    }
+
+    /// Check the encoder against the `config.json` of the original model. The number of layers
+    /// is only checked if `block_count` is given, as llama.cpp drops the unused LLaVA layers.
+    pub fn check_encoder(
+        &self,
+        hidden_size: usize,
+        intermediate_size: usize,
+        head_count: usize,
+        patch_size: usize,
+        block_count: Option<usize>,
+    ) -> Result<()> {
+        let mut checks = vec![
+            ("hidden size", Some(self.hidden_size), hidden_size),
+            (
+                "feed forward size",
+                self.intermediate_size,
+                intermediate_size,
+            ),
+            ("head count", self.head_count, head_count),
+            ("patch size", self.patch_size, patch_size),
+        ];
+        if let Some(block_count) = block_count {
+            checks.push(("layer count", Some(self.block_count), block_count));
+        }
+        for (what, actual, expected) in checks {
+            if let Some(actual) = actual.filter(|&actual| actual != expected) {
+                anyhow::bail!(
+                    "The mmproj vision encoder has a {what} of {actual}, but the `config.json` expects {expected}. Perhaps the files do not match?"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// A var builder over the renamed tensors, which are cast to `dtype` when loaded.
+    pub fn into_var_builder(self, dtype: DType, device: &Device) -> ShardedVarBuilder<'static> {
+        ShardedSafeTensors::wrap(Box::new(self.tensors), dtype, device.clone())
+    }
+}
+
+/// Map a llama.cpp CLIP tensor name to the Hugging Face name of the model using the projector.
+fn hf_tensor_name(projector_type: ProjectorType, name: &str, is_fc1: bool) -> Option<String> {
+    match projector_type {
+        ProjectorType::Mlp => llava_tensor_name(name, is_fc1),
+        ProjectorType::Qwen2VLMerger => qwen2vl_tensor_name(name, is_fc1),
+        ProjectorType::Resampler => minicpm_tensor_name(name, is_fc1),
+    }
+}
+
+/// Split `v.blk.{layer}.{part}` into the layer and the part.
+fn encoder_block(module: &str) -> Option<(&str, &str)> {
+    module.strip_prefix("v.blk.")?.split_once('.')
+}
+
+fn llava_tensor_name(name: &str, is_fc1: bool) -> Option<String> {
+    let hf_name = match name {
+        "v.class_embd" => format!("{VISION_TOWER}.embeddings.class_embedding"),
+        "v.patch_embd.weight" => format!("{VISION_TOWER}.embeddings.patch_embedding.weight"),
+        "v.position_embd.weight" => {
+            format!("{VISION_TOWER}.embeddings.position_embedding.weight")
+        }
+        "model.image_newline" => "image_newline".to_string(),
+        _ => {
+            let (module, param) = name.rsplit_once('.')?;
+            if let Some((layer, part)) = encoder_block(module) {
+                let part = match part {
+                    "attn_q" => "self_attn.q_proj",
+                    "attn_k" => "self_attn.k_proj",
+                    "attn_v" => "self_attn.v_proj",
+                    "attn_out" => "self_attn.out_proj",
+                    "ln1" => "layer_norm1",
+                    "ln2" => "layer_norm2",
+                    // Older converters swapped `ffn_up` and `ffn_down`, so go by the shape instead.
+                    "ffn_up" | "ffn_down" if is_fc1 => "mlp.fc1",
+                    "ffn_up" | "ffn_down" => "mlp.fc2",
+                    _ => return None,
+                };
+                format!("{VISION_TOWER}.encoder.layers.{layer}.{part}.{param}")
+            } else {
+                let module = match module {
+                    "v.pre_ln" => format!("{VISION_TOWER}.pre_layrnorm"),
+                    "v.post_ln" => format!("{VISION_TOWER}.post_layernorm"),
+                    "mm.0" => "multi_modal_projector.linear_1".to_string(),
+                    "mm.2" => "multi_modal_projector.linear_2".to_string(),
+                    _ => return None,
+                };
+                format!("{module}.{param}")
+            }
+        }
+    };
+    Some(hf_name)
+}
+
+/// The attention projections and temporal patch embedding are split by llama.cpp, so they are
+/// named as parts here and joined by [`join_qwen2vl_tensors`].
+fn qwen2vl_tensor_name(name: &str, is_fc1: bool) -> Option<String> {
+    let hf_name = match name {
+        "v.patch_embd.weight" => format!("{QWEN2VL_VISION}.patch_embed.proj.weight.0"),
+        "v.patch_embd.weight.1" => format!("{QWEN2VL_VISION}.patch_embed.proj.weight.1"),
+        _ => {
+            let (module, param) = name.rsplit_once('.')?;
+            if let Some((layer, part)) = encoder_block(module) {
+                let part = match part {
+                    "attn_q" => "attn.q",
+                    "attn_k" => "attn.k",
+                    "attn_v" => "attn.v",
+                    "attn_out" => "attn.proj",
+                    "ln1" => "norm1",
+                    "ln2" => "norm2",
+                    "ffn_up" | "ffn_down" if is_fc1 => "mlp.fc1",
+                    "ffn_up" | "ffn_down" => "mlp.fc2",
+                    _ => return None,
+                };
+                format!("{QWEN2VL_VISION}.blocks.{layer}.{part}.{param}")
+            } else {
+                let module = match module {
+                    "v.post_ln" => "merger.ln_q",
+                    "mm.0" => "merger.mlp.0",
+                    "mm.2" => "merger.mlp.2",
+                    _ => return None,
+                };
+                format!("{QWEN2VL_VISION}.{module}.{param}")
+            }
+        }
+    };
+    Some(hf_name)
+}
+
+/// The resampler attention projections and output projection are split and transposed by
+/// llama.cpp, they are joined by [`join_resampler_tensors`].
+fn minicpm_tensor_name(name: &str, is_fc1: bool) -> Option<String> {
+    if name == "resampler.query" {
+        return Some(name.to_string());
+    }
+    let (module, param) = name.rsplit_once('.')?;
+    let hf_name = if let Some((layer, part)) = encoder_block(module) {
+        let part = match part {
+            "attn_q" => "self_attn.q_proj",
+            "attn_k" => "self_attn.k_proj",
+            "attn_v" => "self_attn.v_proj",
+            "attn_out" => "self_attn.out_proj",
+            "ln1" => "layer_norm1",
+            "ln2" => "layer_norm2",
+            "ffn_up" | "ffn_down" if is_fc1 => "mlp.fc1",
+            "ffn_up" | "ffn_down" => "mlp.fc2",
+            _ => return None,
+        };
+        format!("{MINICPM_VISION}.encoder.layers.{layer}.{part}.{param}")
+    } else {
+        let module = match module {
+            "v.patch_embd" => format!("{MINICPM_VISION}.embeddings.patch_embedding"),
+            "v.position_embd" => format!("{MINICPM_VISION}.embeddings.position_embedding"),
+            "v.post_ln" => format!("{MINICPM_VISION}.post_layernorm"),
+            "resampler.kv" => "resampler.kv_proj".to_string(),
+            "resampler.attn.out" => "resampler.attn.out_proj".to_string(),
+            "resampler.ln_q" | "resampler.ln_kv" | "resampler.ln_post" | "resampler.attn.q"
+            | "resampler.attn.k" | "resampler.attn.v" | "resampler.proj" => module.to_string(),
+            _ => return None,
+        };
+        format!("{module}.{param}")
+    };
+    Some(hf_name)
+}
+
+fn take_tensor(tensors: &mut HashMap<String, Tensor>, name: &str) -> Result<Tensor> {
+    tensors
+        .remove(name)
+        .with_context(|| format!("Expected `{name}` in the mmproj file."))
+}
+
+/// Concatenate the `q`, `k` and `v` parts `{prefix}.{q,k,v}.{param}` into `fused`.
+fn join_qkv(
+    tensors: &mut HashMap<String, Tensor>,
+    prefix: &str,
+    param: &str,
+    fused: String,
+) -> Result<()> {
+    let parts = ["q", "k", "v"]
+        .iter()
+        .map(|part| take_tensor(tensors, &format!("{prefix}.{part}.{param}")))
+        .collect::<Result<Vec<_>>>()?;
+    tensors.insert(fused, Tensor::cat(&parts, 0)?);
+    Ok(())
+}
+
+/// Stack the two temporal kernels of the patch embedding and fuse the attention projections.
+fn join_qwen2vl_tensors(tensors: &mut HashMap<String, Tensor>, block_count: usize) -> Result<()> {
+    let proj = format!("{QWEN2VL_VISION}.patch_embed.proj");
+    let frames = [
+        take_tensor(tensors, &format!("{proj}.weight.0"))?,
+        take_tensor(tensors, &format!("{proj}.weight.1"))?,
+    ];
+    tensors.insert(format!("{proj}.weight"), Tensor::stack(&frames, 2)?);
+
+    for layer in 0..block_count {
+        let attn = format!("{QWEN2VL_VISION}.blocks.{layer}.attn");
+        for param in ["weight", "bias"] {
+            join_qkv(tensors, &attn, param, format!("{attn}.qkv.{param}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Fuse the attention projections into the `nn.MultiheadAttention` input projection and undo the
+/// transpose of the output projection.
+fn join_resampler_tensors(tensors: &mut HashMap<String, Tensor>) -> Result<()> {
+    join_qkv(
+        tensors,
+        "resampler.attn",
+        "weight",
+        "resampler.attn.in_proj_weight".to_string(),
+    )?;
+    join_qkv(
+        tensors,
+        "resampler.attn",
+        "bias",
+        "resampler.attn.in_proj_bias".to_string(),
+    )?;
+    let proj = take_tensor(tensors, "resampler.proj.weight")?;
+    tensors.insert("resampler.proj".to_string(), proj.t()?.contiguous()?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use candle_core::{quantized::gguf_file::Value, DType, Device, Tensor};
+
+    use super::{hf_tensor_name, join_qwen2vl_tensors, join_resampler_tensors, ProjectorType};
+    use crate::utils::gguf_metadata::ContentMetadata;
+
+    #[test]
+    fn tensor_names() {
+        for (name, is_fc1, expected) in [
+            (
+                "v.class_embd",
+                false,
+                Some("vision_tower.vision_model.embeddings.class_embedding"),
+            ),
+            (
+                "v.patch_embd.weight",
+                false,
+                Some("vision_tower.vision_model.embeddings.patch_embedding.weight"),
+            ),
+            (
+                "v.position_embd.weight",
+                false,
+                Some("vision_tower.vision_model.embeddings.position_embedding.weight"),
+            ),
+            ("model.image_newline", false, Some("image_newline")),
+            (
+                "v.blk.0.attn_q.weight",
+                false,
+                Some("vision_tower.vision_model.encoder.layers.0.self_attn.q_proj.weight"),
+            ),
+            (
+                "v.blk.3.attn_k.bias",
+                false,
+                Some("vision_tower.vision_model.encoder.layers.3.self_attn.k_proj.bias"),
+            ),
+            (
+                "v.blk.12.attn_v.weight",
+                false,
+                Some("vision_tower.vision_model.encoder.layers.12.self_attn.v_proj.weight"),
+            ),
+            (
+                "v.blk.22.attn_out.weight",
+                false,
+                Some("vision_tower.vision_model.encoder.layers.22.self_attn.out_proj.weight"),
+            ),
+            (
+                "v.blk.1.ln1.weight",
+                false,
+                Some("vision_tower.vision_model.encoder.layers.1.layer_norm1.weight"),
+            ),
+            (
+                "v.blk.1.ln2.bias",
+                false,
+                Some("vision_tower.vision_model.encoder.layers.1.layer_norm2.bias"),
+            ),
+            // `fc1` and `fc2` are told apart by shape, not by name.
+            (
+                "v.blk.2.ffn_up.weight",
+                true,
+                Some("vision_tower.vision_model.encoder.layers.2.mlp.fc1.weight"),
+            ),
+            (
+                "v.blk.2.ffn_down.weight",
+                true,
+                Some("vision_tower.vision_model.encoder.layers.2.mlp.fc1.weight"),
+            ),
+            (
+                "v.blk.2.ffn_up.bias",
+                false,
+                Some("vision_tower.vision_model.encoder.layers.2.mlp.fc2.bias"),
+            ),
+            (
+                "v.blk.2.ffn_down.bias",
+                false,
+                Some("vision_tower.vision_model.encoder.layers.2.mlp.fc2.bias"),
+            ),
+            (
+                "v.pre_ln.weight",
+                false,
+                Some("vision_tower.vision_model.pre_layrnorm.weight"),
+            ),
+            (
+                "v.post_ln.bias",
+                false,
+                Some("vision_tower.vision_model.post_layernorm.bias"),
+            ),
+            (
+                "mm.0.weight",
+                false,
+                Some("multi_modal_projector.linear_1.weight"),
+            ),
+            (
+                "mm.2.bias",
+                false,
+                Some("multi_modal_projector.linear_2.bias"),
+            ),
+            // Unknown tensors are skipped.
+            ("mm.1.weight", false, None),
+            ("v.blk.0.attn_qkv.weight", false, None),
+            ("v.blk.0", false, None),
+            ("resampler.query", false, None),
+            ("token_embd.weight", false, None),
+        ] {
+            assert_eq!(
+                hf_tensor_name(ProjectorType::Mlp, name, is_fc1).as_deref(),
+                expected,
+                "{name} (is_fc1 = {is_fc1})"
+            );
+        }
+    }
+
+    #[test]
+    fn qwen2vl_tensor_names() {
+        for (name, is_fc1, expected) in [
+            (
+                "v.patch_embd.weight",
+                false,
+                Some("visual.patch_embed.proj.weight.0"),
+            ),
+            (
+                "v.patch_embd.weight.1",
+                false,
+                Some("visual.patch_embed.proj.weight.1"),
+            ),
+            (
+                "v.blk.0.attn_q.weight",
+                false,
+                Some("visual.blocks.0.attn.q.weight"),
+            ),
+            (
+                "v.blk.31.attn_v.bias",
+                false,
+                Some("visual.blocks.31.attn.v.bias"),
+            ),
+            (
+                "v.blk.4.attn_out.weight",
+                false,
+                Some("visual.blocks.4.attn.proj.weight"),
+            ),
+            (
+                "v.blk.4.ln1.bias",
+                false,
+                Some("visual.blocks.4.norm1.bias"),
+            ),
+            (
+                "v.blk.4.ffn_down.weight",
+                true,
+                Some("visual.blocks.4.mlp.fc1.weight"),
+            ),
+            (
+                "v.blk.4.ffn_up.weight",
+                false,
+                Some("visual.blocks.4.mlp.fc2.weight"),
+            ),
+            ("v.post_ln.weight", false, Some("visual.merger.ln_q.weight")),
+            ("mm.0.bias", false, Some("visual.merger.mlp.0.bias")),
+            ("mm.2.weight", false, Some("visual.merger.mlp.2.weight")),
+            ("v.class_embd", false, None),
+            ("v.position_embd.weight", false, None),
+        ] {
+            assert_eq!(
+                hf_tensor_name(ProjectorType::Qwen2VLMerger, name, is_fc1).as_deref(),
+                expected,
+                "{name} (is_fc1 = {is_fc1})"
+            );
+        }
+    }
+
+    #[test]
+    fn minicpm_tensor_names() {
+        for (name, is_fc1, expected) in [
+            (
+                "v.patch_embd.bias",
+                false,
+                Some("vpm.embeddings.patch_embedding.bias"),
+            ),
+            (
+                "v.position_embd.weight",
+                false,
+                Some("vpm.embeddings.position_embedding.weight"),
+            ),
+            (
+                "v.blk.26.attn_out.bias",
+                false,
+                Some("vpm.encoder.layers.26.self_attn.out_proj.bias"),
+            ),
+            (
+                "v.blk.0.ln2.weight",
+                false,
+                Some("vpm.encoder.layers.0.layer_norm2.weight"),
+            ),
+            (
+                "v.blk.0.ffn_up.weight",
+                true,
+                Some("vpm.encoder.layers.0.mlp.fc1.weight"),
+            ),
+            ("v.post_ln.bias", false, Some("vpm.post_layernorm.bias")),
+            ("resampler.query", false, Some("resampler.query")),
+            (
+                "resampler.kv.weight",
+                false,
+                Some("resampler.kv_proj.weight"),
+            ),
+            ("resampler.ln_kv.bias", false, Some("resampler.ln_kv.bias")),
+            (
+                "resampler.attn.k.weight",
+                false,
+                Some("resampler.attn.k.weight"),
+            ),
+            (
+                "resampler.attn.out.bias",
+                false,
+                Some("resampler.attn.out_proj.bias"),
+            ),
+            (
+                "resampler.proj.weight",
+                false,
+                Some("resampler.proj.weight"),
+            ),
+            // Recomputed by the resampler.
+            ("resampler.pos_embed_k", false, None),
+            ("v.class_embd", false, None),
+            ("mm.0.weight", false, None),
+        ] {
+            assert_eq!(
+                hf_tensor_name(ProjectorType::Resampler, name, is_fc1).as_deref(),
+                expected,
+                "{name} (is_fc1 = {is_fc1})"
+            );
+        }
+    }
+
+    #[test]
+    fn joins_qwen2vl_tensors() -> anyhow::Result<()> {
+        let dev = Device::Cpu;
+        let mut tensors = HashMap::new();
+        // Two output and one input channel with 1x1 kernels per frame.
+        tensors.insert(
+            "visual.patch_embed.proj.weight.0".to_string(),
+            Tensor::new(&[1f32, 2.], &dev)?.reshape((2, 1, 1, 1))?,
+        );
+        tensors.insert(
+            "visual.patch_embed.proj.weight.1".to_string(),
+            Tensor::new(&[3f32, 4.], &dev)?.reshape((2, 1, 1, 1))?,
+        );
+        for (part, value) in [("q", 1f32), ("k", 2.), ("v", 3.)] {
+            tensors.insert(
+                format!("visual.blocks.0.attn.{part}.weight"),
+                Tensor::full(value, (1, 2), &dev)?,
+            );
+            tensors.insert(
+                format!("visual.blocks.0.attn.{part}.bias"),
+                Tensor::full(value, 1, &dev)?,
+            );
+        }
+
+        join_qwen2vl_tensors(&mut tensors, 1)?;
+        assert_eq!(tensors.len(), 3);
+        let proj = &tensors["visual.patch_embed.proj.weight"];
+        assert_eq!(proj.dims(), &[2, 1, 2, 1, 1]);
+        assert_eq!(proj.flatten_all()?.to_vec1::<f32>()?, vec![1., 3., 2., 4.]);
+        assert_eq!(
+            tensors["visual.blocks.0.attn.qkv.weight"].to_vec2::<f32>()?,
+            vec![vec![1., 1.], vec![2., 2.], vec![3., 3.]]
+        );
+        assert_eq!(
+            tensors["visual.blocks.0.attn.qkv.bias"].to_vec1::<f32>()?,
+            vec![1., 2., 3.]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn joins_resampler_tensors() -> anyhow::Result<()> {
+        let dev = Device::Cpu;
+        let mut tensors = HashMap::new();
+        for (part, value) in [("q", 1f32), ("k", 2.), ("v", 3.)] {
+            tensors.insert(
+                format!("resampler.attn.{part}.weight"),
+                Tensor::full(value, (1, 1), &dev)?,
+            );
+            tensors.insert(
+                format!("resampler.attn.{part}.bias"),
+                Tensor::full(value, 1, &dev)?,
+            );
+        }
+        tensors.insert(
+            "resampler.proj.weight".to_string(),
+            Tensor::new(&[[1f32, 2.], [3., 4.]], &dev)?,
+        );
+
+        join_resampler_tensors(&mut tensors)?;
+        assert_eq!(tensors.len(), 3);
+        assert_eq!(
+            tensors["resampler.attn.in_proj_weight"].to_vec2::<f32>()?,
+            vec![vec![1.], vec![2.], vec![3.]]
+        );
+        assert_eq!(
+            tensors["resampler.attn.in_proj_bias"].to_vec1::<f32>()?,
+            vec![1., 2., 3.]
+        );
+        assert_eq!(
+            tensors["resampler.proj"].to_vec2::<f32>()?,
+            vec![vec![1., 3.], vec![2., 4.]]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn missing_split_tensors_are_errors() -> anyhow::Result<()> {
+        let mut tensors = HashMap::new();
+        tensors.insert(
+            "visual.patch_embed.proj.weight.0".to_string(),
+            Tensor::zeros((1, 1, 1, 1), DType::F32, &Device::Cpu)?,
+        );
+        assert!(join_qwen2vl_tensors(&mut tensors, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn projector_types() -> anyhow::Result<()> {
+        let projector_type = |entries: &[(&str, Value)]| {
+            let metadata = entries
+                .iter()
+                .map(|(key, value)| (format!("clip.{key}"), value.clone()))
+                .collect::<HashMap<_, _>>();
+            ProjectorType::from_metadata(&ContentMetadata {
+                path_prefix: "clip",
+                metadata: &metadata,
+            })
+        };
+        let string = |s: &str| Value::String(s.to_string());
+
+        assert_eq!(projector_type(&[])?, ProjectorType::Mlp);
+        assert_eq!(
+            projector_type(&[("projector_type", string("qwen2vl_merger"))])?,
+            ProjectorType::Qwen2VLMerger
+        );
+        assert_eq!(
+            projector_type(&[("vision.projector_type", string("resampler"))])?,
+            ProjectorType::Resampler
+        );
+        assert_eq!(
+            projector_type(&[("has_minicpmv_projector", Value::Bool(true))])?,
+            ProjectorType::Resampler
+        );
+        assert_eq!(
+            projector_type(&[("has_qwen2vl_merger", Value::Bool(true))])?,
+            ProjectorType::Qwen2VLMerger
+        );
+        assert!(projector_type(&[("projector_type", string("ldp"))]).is_err());
+        Ok(())
+    }
+}
@@ -1,17 +1,21 @@
 mod chat_template;
 mod content;
+mod export;
 mod gguf_tokenizer;
+mod mmproj;
 use strum::EnumString;
 
 use anyhow::{Context, Result};
 pub(crate) use chat_template::get_gguf_chat_template;
 pub(crate) use content::Content;
+pub(crate) use export::write_gguf;
 pub(crate) use gguf_tokenizer::{convert_gguf_to_hf_tokenizer, GgufTokenizerConversion};
+pub(crate) use mmproj::{Mmproj, ProjectorType};
 use std::str::FromStr;
 
 pub const GGUF_MULTI_FILE_DELIMITER: &str = " ";
 
-#[derive(Debug, EnumString, Clone, Copy, strum::Display)]
+#[derive(Debug, EnumString, Clone, Copy, PartialEq, Eq, strum::Display)]
 #[strum(serialize_all = "lowercase")]
 pub enum GGUFArchitecture {
     Llama,
@@ -27,6 +31,10 @@ pub enum GGUFArchitecture {
     Phi3,
     Starcoder2,
     Qwen2,
+    Qwen2vl,
+    Gemma,
+    Gemma2,
+    Deepseek2,
 }
 
 // Wraps from_str() for some convenience:
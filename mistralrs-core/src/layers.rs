@@ -7,7 +7,8 @@ use candle_core::{
     Context, DType, Device, IndexOp, Result, Tensor, D,
 };
 use candle_nn::{
-    Conv2d, Conv2dConfig, Embedding, GroupNorm, LayerNorm, LayerNormConfig, Linear, Module,
+    Conv1d, Conv1dConfig, Conv2d, Conv2dConfig, Embedding, GroupNorm, LayerNorm, LayerNormConfig,
+    Linear, Module,
 };
 use float8::F8E4M3;
 use half::{bf16, f16};
@@ -61,6 +62,21 @@ pub fn group_norm(
     GroupNorm::new(weight, bias, num_channels, num_groups, eps)
 }
 
+pub fn conv1d(
+    in_channels: usize,
+    out_channels: usize,
+    kernel_size: usize,
+    cfg: Conv1dConfig,
+    vb: ShardedVarBuilder,
+) -> Result<Conv1d> {
+    let ws = vb.get(
+        (out_channels, in_channels / cfg.groups, kernel_size),
+        "weight",
+    )?;
+    let bs = vb.get(out_channels, "bias")?;
+    Ok(Conv1d::new(ws, Some(bs), cfg))
+}
+
 pub fn conv2d(
     in_channels: usize,
     out_channels: usize,
@@ -180,6 +196,10 @@ impl F32RmsNorm {
         })
     }
 
+    pub fn from_w(w: Tensor, eps: f64) -> Result<Self> {
+        Ok(Self { w, eps })
+    }
+
     pub fn weight(&self) -> &Tensor {
         &self.w
     }
@@ -1476,6 +1496,24 @@ impl Mlp {
         })
     }
 
+    /// Build the MLP from already loaded layers, such as GGUF weights.
+    pub fn from_layers(
+        gate: Arc<dyn QuantMethod>,
+        up: Arc<dyn QuantMethod>,
+        down: Arc<dyn QuantMethod>,
+        hidden_size: usize,
+        intermediate_size: usize,
+        hidden_act: Activation,
+    ) -> Self {
+        Self {
+            gate,
+            up,
+            down,
+            act: hidden_act,
+            params: vec![hidden_size, intermediate_size],
+        }
+    }
+
     pub fn replicate(
         params: &[usize],
         vb: ShardedVarBuilder,
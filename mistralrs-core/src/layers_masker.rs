@@ -167,8 +167,8 @@ impl CausalMasker {
             let diagonal = past_kv_len as isize - sliding_window as isize - 1;
             let context_mask = apply_tril(&mask.ones_like()?, diagonal)?;
 
-            masked_fill(&mask.to_dtype(DType::F32)?, &context_mask, f32::MIN)?
-                .to_dtype(DType::U8)?
+            // Tokens before the window are masked out like future tokens.
+            masked_fill(&mask, &context_mask, 1u8)?
         };
 
         let zero = Tensor::new(0.0f32, input_ids.device())?;
@@ -264,7 +264,7 @@ impl CausalMasker {
             let mut mask = self.make_mask(tgt_len, past_kv_len, input_ids.device())?;
             let diagonal = past_kv_len as isize - sliding_window as isize - 1;
             let context_mask = apply_tril(&mask.ones_like()?, diagonal)?;
-            mask = masked_fill(&mask.to_dtype(DType::F32)?, &context_mask, f32::MIN)?;
+            mask = masked_fill(&mask, &context_mask, 1u8)?;
             mask = mask
                 .expand((b_sz, 1, tgt_len, tgt_len + past_kv_len))?
                 .to_dtype(DType::U8)?;
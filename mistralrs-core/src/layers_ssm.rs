@@ -0,0 +1,283 @@
+//! Building blocks of state-space (Mamba) layers: the causal depthwise convolution and the
+//! selective scan, both of which continue from a per-sequence recurrent state.
+
+use candle_core::{DType, IndexOp, Result, Tensor};
+use rayon::prelude::*;
+use serde::Deserialize;
+
+/// The rank of the time step projection, `"auto"` meaning `ceil(hidden_size / 16)`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum TimeStepRank {
+    Rank(usize),
+    Auto(String),
+}
+
+impl TimeStepRank {
+    pub fn rank(&self, hidden_size: usize) -> usize {
+        match self {
+            Self::Rank(rank) => *rank,
+            Self::Auto(_) => hidden_size.div_ceil(16),
+        }
+    }
+}
+
+impl Default for TimeStepRank {
+    fn default() -> Self {
+        Self::Auto("auto".to_string())
+    }
+}
+
+/// `log(1 + exp(xs))`, linear above 20 like PyTorch to avoid overflowing.
+pub fn softplus(xs: &Tensor) -> Result<Tensor> {
+    let soft = (xs.exp()? + 1.)?.log()?;
+    xs.gt(20.)?.where_cond(xs, &soft)
+}
+
+/// Depthwise causal convolution of `xs` (batch, channels, seq_len) with `weight` (channels, kernel),
+/// continuing from `conv_state` (batch, channels, kernel - 1), the inputs of the previous tokens.
+///
+/// Returns the output (batch, channels, seq_len) and the new convolution state.
+pub fn causal_conv1d(
+    xs: &Tensor,
+    weight: &Tensor,
+    bias: Option<&Tensor>,
+    conv_state: Option<&Tensor>,
+) -> Result<(Tensor, Tensor)> {
+    let (b_sz, channels, seq_len) = xs.dims3()?;
+    let kernel = weight.dim(1)?;
+    let state = match conv_state {
+        Some(state) => state.clone(),
+        None => Tensor::zeros((b_sz, channels, kernel - 1), xs.dtype(), xs.device())?,
+    };
+    let padded = Tensor::cat(&[&state, xs], 2)?;
+    let tap = |j: usize| -> Result<Tensor> {
+        padded
+            .narrow(2, j, seq_len)?
+            .broadcast_mul(&weight.narrow(1, j, 1)?.unsqueeze(0)?)
+    };
+    let mut ys = tap(0)?;
+    for j in 1..kernel {
+        ys = (ys + tap(j)?)?;
+    }
+    if let Some(bias) = bias {
+        ys = ys.broadcast_add(&bias.reshape((1, channels, 1))?)?;
+    }
+    let new_state = padded.narrow(2, seq_len, kernel - 1)?.contiguous()?;
+    Ok((ys, new_state))
+}
+
+/// The selective scan of Mamba over `dim` channels, which are split into `groups` groups sharing
+/// the input and output projections `b` and `c`:
+///
+/// ```text
+/// h_t = exp(dt_t * a) * h_{t-1} + dt_t * b_t * x_t
+/// y_t = h_t · c_t + d * x_t
+/// ```
+///
+/// - `x`, `dt`: (batch, seq_len, dim), `dt` after the softplus.
+/// - `a`: (dim, state_size), already negative.
+/// - `b`, `c`: (batch, seq_len, groups, state_size).
+/// - `d`: (dim), the skip connection.
+/// - `state`: (batch, dim, state_size), the state after the previous tokens.
+///
+/// Returns `y` (batch, seq_len, dim) in the dtype of `x` and the final state in f32. The scan is
+/// sequential in the sequence length, and runs as a parallel loop over the channels on the CPU.
+pub fn selective_scan(
+    x: &Tensor,
+    dt: &Tensor,
+    a: &Tensor,
+    b: &Tensor,
+    c: &Tensor,
+    d: Option<&Tensor>,
+    state: Option<&Tensor>,
+) -> Result<(Tensor, Tensor)> {
+    if x.device().is_cpu() {
+        selective_scan_cpu(x, dt, a, b, c, d, state)
+    } else {
+        selective_scan_tensor(x, dt, a, b, c, d, state)
+    }
+}
+
+fn to_f32_vec(xs: &Tensor) -> Result<Vec<f32>> {
+    xs.to_dtype(DType::F32)?.flatten_all()?.to_vec1::<f32>()
+}
+
+fn selective_scan_cpu(
+    x: &Tensor,
+    dt: &Tensor,
+    a: &Tensor,
+    b: &Tensor,
+    c: &Tensor,
+    d: Option<&Tensor>,
+    state: Option<&Tensor>,
+) -> Result<(Tensor, Tensor)> {
+    let (b_sz, seq_len, dim) = x.dims3()?;
+    let (_, _, groups, state_size) = b.dims4()?;
+    let channels_per_group = dim / groups;
+
+    let xs = to_f32_vec(x)?;
+    let dts = to_f32_vec(dt)?;
+    let a_vals = to_f32_vec(a)?;
+    let bs = to_f32_vec(b)?;
+    let cs = to_f32_vec(c)?;
+    let d_vals = match d {
+        Some(d) => to_f32_vec(d)?,
+        None => vec![0.; dim],
+    };
+    let mut h = match state {
+        Some(state) => to_f32_vec(state)?,
+        None => vec![0.; b_sz * dim * state_size],
+    };
+    // Written per channel, as (batch, dim, seq_len).
+    let mut ys = vec![0f32; b_sz * dim * seq_len];
+
+    h.par_chunks_mut(state_size)
+        .zip(ys.par_chunks_mut(seq_len))
+        .enumerate()
+        .for_each(|(i, (h, ys))| {
+            let (bi, ch) = (i / dim, i % dim);
+            let g = ch / channels_per_group;
+            let a_row = &a_vals[ch * state_size..(ch + 1) * state_size];
+            for (t, y) in ys.iter_mut().enumerate() {
+                let x_off = (bi * seq_len + t) * dim + ch;
+                let bc_off = ((bi * seq_len + t) * groups + g) * state_size;
+                let (x_t, dt_t) = (xs[x_off], dts[x_off]);
+                let b_t = &bs[bc_off..bc_off + state_size];
+                let c_t = &cs[bc_off..bc_off + state_size];
+                let mut acc = 0f32;
+                for k in 0..state_size {
+                    h[k] = (dt_t * a_row[k]).exp() * h[k] + dt_t * b_t[k] * x_t;
+                    acc += h[k] * c_t[k];
+                }
+                *y = acc + d_vals[ch] * x_t;
+            }
+        });
+
+    let y = Tensor::from_vec(ys, (b_sz, dim, seq_len), x.device())?
+        .transpose(1, 2)?
+        .contiguous()?
+        .to_dtype(x.dtype())?;
+    let h = Tensor::from_vec(h, (b_sz, dim, state_size), x.device())?;
+    Ok((y, h))
+}
+
+/// Expand (batch, groups, state_size) to (batch, dim, state_size).
+fn expand_groups(xs: &Tensor, dim: usize) -> Result<Tensor> {
+    let (b_sz, groups, state_size) = xs.dims3()?;
+    xs.unsqueeze(2)?
+        .broadcast_as((b_sz, groups, dim / groups, state_size))?
+        .reshape((b_sz, dim, state_size))
+}
+
+fn selective_scan_tensor(
+    x: &Tensor,
+    dt: &Tensor,
+    a: &Tensor,
+    b: &Tensor,
+    c: &Tensor,
+    d: Option<&Tensor>,
+    state: Option<&Tensor>,
+) -> Result<(Tensor, Tensor)> {
+    let (b_sz, seq_len, dim) = x.dims3()?;
+    let state_size = b.dim(3)?;
+    let xs = x.to_dtype(DType::F32)?;
+    let dt = dt.to_dtype(DType::F32)?;
+    let a = a.to_dtype(DType::F32)?;
+    let b = b.to_dtype(DType::F32)?;
+    let c = c.to_dtype(DType::F32)?;
+
+    let mut h = match state {
+        Some(state) => state.to_dtype(DType::F32)?,
+        None => Tensor::zeros((b_sz, dim, state_size), DType::F32, x.device())?,
+    };
+    let mut ys = Vec::with_capacity(seq_len);
+    for t in 0..seq_len {
+        let x_t = xs.i((.., t))?;
+        let dt_t = dt.i((.., t))?;
+        let b_t = expand_groups(&b.i((.., t))?, dim)?;
+        let c_t = expand_groups(&c.i((.., t))?, dim)?;
+        let decay = dt_t.unsqueeze(2)?.broadcast_mul(&a)?.exp()?;
+        let input = b_t.broadcast_mul(&(dt_t * &x_t)?.unsqueeze(2)?)?;
+        h = ((decay * h)? + input)?;
+        ys.push((&h * c_t)?.sum(2)?);
+    }
+    let mut y = Tensor::stack(&ys, 1)?;
+    if let Some(d) = d {
+        y = (y + xs.broadcast_mul(&d.to_dtype(DType::F32)?)?)?;
+    }
+    Ok((y.to_dtype(x.dtype())?, h))
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{Device, Tensor};
+
+    use super::{causal_conv1d, selective_scan_cpu, selective_scan_tensor};
+
+    #[test]
+    fn selective_scan_cpu_matches_reference() -> candle_core::Result<()> {
+        let dev = Device::Cpu;
+        let (b_sz, seq_len, dim, groups, state_size) = (2, 5, 6, 2, 3);
+        let x = Tensor::randn(0f32, 1., (b_sz, seq_len, dim), &dev)?;
+        let dt = Tensor::rand(0f32, 1., (b_sz, seq_len, dim), &dev)?;
+        let a = Tensor::rand(0f32, 1., (dim, state_size), &dev)?.neg()?;
+        let b = Tensor::randn(0f32, 1., (b_sz, seq_len, groups, state_size), &dev)?;
+        let c = Tensor::randn(0f32, 1., (b_sz, seq_len, groups, state_size), &dev)?;
+        let d = Tensor::randn(0f32, 1., dim, &dev)?;
+        let state = Tensor::randn(0f32, 1., (b_sz, dim, state_size), &dev)?;
+
+        let (y, h) = selective_scan_cpu(&x, &dt, &a, &b, &c, Some(&d), Some(&state))?;
+        let (y_ref, h_ref) = selective_scan_tensor(&x, &dt, &a, &b, &c, Some(&d), Some(&state))?;
+        let max_diff = |a: &Tensor, b: &Tensor| -> candle_core::Result<f32> {
+            (a - b)?.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()
+        };
+        assert!(max_diff(&y, &y_ref)? < 1e-5);
+        assert!(max_diff(&h, &h_ref)? < 1e-5);
+
+        // Scanning the sequence in two parts through the state gives the same result.
+        let (y1, h1) = selective_scan_cpu(
+            &x.narrow(1, 0, 2)?,
+            &dt.narrow(1, 0, 2)?,
+            &a,
+            &b.narrow(1, 0, 2)?,
+            &c.narrow(1, 0, 2)?,
+            Some(&d),
+            Some(&state),
+        )?;
+        let (y2, h2) = selective_scan_cpu(
+            &x.narrow(1, 2, 3)?,
+            &dt.narrow(1, 2, 3)?,
+            &a,
+            &b.narrow(1, 2, 3)?,
+            &c.narrow(1, 2, 3)?,
+            Some(&d),
+            Some(&h1),
+        )?;
+        assert!(max_diff(&Tensor::cat(&[y1, y2], 1)?, &y)? < 1e-5);
+        assert!(max_diff(&h2, &h)? < 1e-5);
+        Ok(())
+    }
+
+    #[test]
+    fn causal_conv1d_continues_from_state() -> candle_core::Result<()> {
+        let dev = Device::Cpu;
+        // One channel, kernel 2: y_t = 2 * x_{t-1} + x_t + 1.
+        let weight = Tensor::new(&[[2f32, 1.]], &dev)?;
+        let bias = Tensor::new(&[1f32], &dev)?;
+        let xs = Tensor::new(&[[[1f32, 2., 3.]]], &dev)?;
+        let (ys, state) = causal_conv1d(&xs, &weight, Some(&bias), None)?;
+        assert_eq!(ys.to_vec3::<f32>()?, vec![vec![vec![2., 5., 8.]]]);
+        assert_eq!(state.to_vec3::<f32>()?, vec![vec![vec![3.]]]);
+
+        let (ys, state) = causal_conv1d(
+            &Tensor::new(&[[[4f32]]], &dev)?,
+            &weight,
+            Some(&bias),
+            Some(&state),
+        )?;
+        assert_eq!(ys.to_vec3::<f32>()?, vec![vec![vec![11.]]]);
+        assert_eq!(state.to_vec3::<f32>()?, vec![vec![vec![4.]]]);
+        Ok(())
+    }
+}
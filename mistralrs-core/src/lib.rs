@@ -46,6 +46,7 @@ mod dummy_paged_attention;
 mod gguf;
 pub mod layers;
 mod layers_masker;
+mod layers_ssm;
 mod layers_utils;
 mod models;
 #[cfg(any(all(feature = "cuda", target_family = "unix"), feature = "metal"))]
@@ -53,7 +54,9 @@ mod paged_attention;
 #[cfg(not(any(all(feature = "cuda", target_family = "unix"), feature = "metal")))]
 use dummy_paged_attention as paged_attention;
 mod attention;
+mod audio;
 mod diffusion_models;
+mod embedding_models;
 mod pipeline;
 mod prefix_cacher;
 mod prefix_cacher_v2;
@@ -62,6 +65,7 @@ mod response;
 mod sampler;
 mod scheduler;
 mod sequence;
+mod speech_models;
 mod toml_selector;
 mod tools;
 mod topology;
@@ -79,18 +83,23 @@ pub use paged_attention::{MemoryGpuConfig, PagedAttentionConfig};
 pub use pipeline::{
     chat_template::ChatTemplate, parse_isq_value, AnyMoeLoader, AnyMoePipeline,
     AutoDeviceMapParams, DiffusionGenerationParams, DiffusionLoader, DiffusionLoaderBuilder,
-    DiffusionLoaderType, DiffusionSpecificConfig, GGMLLoader, GGMLLoaderBuilder,
-    GGMLSpecificConfig, GGUFLoader, GGUFLoaderBuilder, GGUFSpecificConfig, GemmaLoader,
-    Idefics2Loader, IsqOrganization, LLaVALoader, LLaVANextLoader, LlamaLoader, Loader,
-    LocalModelPaths, MistralLoader, MixtralLoader, ModelKind, ModelPaths, NormalLoader,
-    NormalLoaderBuilder, NormalLoaderType, NormalSpecificConfig, Phi2Loader, Phi3Loader,
-    Phi3VLoader, Qwen2Loader, SpeculativeConfig, SpeculativeLoader, SpeculativePipeline,
-    Starcoder2Loader, TokenSource, VisionLoader, VisionLoaderBuilder, VisionLoaderType,
-    VisionPromptPrefixer, VisionSpecificConfig,
+    DiffusionLoaderType, DiffusionSpecificConfig, EmbeddingLoader, EmbeddingLoaderBuilder,
+    EmbeddingLoaderType, EmbeddingParams, GGMLLoader, GGMLLoaderBuilder, GGMLSpecificConfig,
+    GGUFLoader, GGUFLoaderBuilder, GGUFSpecificConfig, GGUFVisionLoader, GGUFVisionLoaderBuilder,
+    GemmaLoader, Idefics2Loader, IsqOrganization, KvCacheType, LLaVALoader, LLaVANextLoader,
+    LlamaLoader, Loader, LocalModelPaths, MistralLoader, MixtralLoader, ModelKind, ModelPaths,
+    NormalLoader, NormalLoaderBuilder, NormalLoaderType, NormalSpecificConfig, Phi2Loader,
+    Phi3Loader, Phi3VLoader, Qwen2Loader, RerankParams, SpeculativeConfig, SpeculativeLoader,
+    SpeculativePipeline, SpeechLoader, SpeechLoaderBuilder, SpeechLoaderType, Starcoder2Loader,
+    TokenSource, TranscriptionParams, TranscriptionTask, VisionLoader, VisionLoaderBuilder,
+    VisionLoaderType, VisionPromptPrefixer, VisionSpecificConfig,
 };
 pub use request::{
-    Constraint, DetokenizationRequest, ImageGenerationResponseFormat, LlguidanceGrammar,
-    MessageContent, NormalRequest, Request, RequestMessage, TokenizationRequest,
+    has_image_placeholders, join_text_parts, parse_content_parts, prefix_media, AudioInput,
+    AudioPart, ClassificationInput, Constraint, ContentPart, ConversationTokens,
+    DetokenizationRequest, ImageGenerationResponseFormat, LlguidanceGrammar, MessageContent,
+    NormalRequest, PendingMedia, Request, RequestMessage, RequestPriority, TokenizationRequest,
+    VideoInput, VideoPart, VideoSampling, DEFAULT_PCM16_SAMPLE_RATE,
 };
 pub use response::*;
 pub use sampler::{
@@ -103,7 +112,7 @@ use toml_selector::{TomlLoaderArgs, TomlSelector};
 pub use tools::{
     CalledFunction, Function, Tool, ToolCallResponse, ToolCallType, ToolChoice, ToolType,
 };
-pub use topology::{LayerTopology, Topology};
+pub use topology::{LayerTopology, Topology, TopologySearch};
 pub use utils::debug::initialize_logging;
 pub use utils::memory_usage::MemoryUsage;
 pub use utils::normal::{ModelDType, TryIntoDType};
@@ -311,7 +320,10 @@ impl MistralRs {
         let model_supports_reduced_gemm = match category {
             ModelCategory::Text => true,
             ModelCategory::Vision { has_conv2d, .. } => !has_conv2d,
-            ModelCategory::Diffusion => true,
+            ModelCategory::Diffusion
+            | ModelCategory::Speech
+            | ModelCategory::Embedding
+            | ModelCategory::Reranker => true,
         };
         if !gemm_full_precision_f16.unwrap_or(false) && model_supports_reduced_gemm {
             set_gemm_reduced_precision_f16();
@@ -401,6 +413,10 @@ impl MistralRs {
                     tools: None,
                     logits_processors: None,
                     return_raw_logits: false,
+                    chat_template_kwargs: None,
+                    continue_final_message: false,
+                    conversation: None,
+                    priority: RequestPriority::Normal,
                 });
                 info!("Beginning dummy run.");
                 let start = Instant::now();
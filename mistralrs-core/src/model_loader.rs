@@ -7,9 +7,10 @@ use crate::{
     get_toml_selected_model_dtype,
     pipeline::{GGMLLoaderBuilder, GGMLSpecificConfig, GGUFLoaderBuilder, NormalSpecificConfig},
     toml_selector::get_toml_selected_model_device_map_params,
-    AutoDeviceMapParams, DiffusionLoaderBuilder, DiffusionSpecificConfig, GGUFSpecificConfig,
-    Loader, ModelDType, ModelSelected, NormalLoaderBuilder, TomlLoaderArgs, TomlSelector, Topology,
-    VisionLoaderBuilder, VisionSpecificConfig, GGUF_MULTI_FILE_DELIMITER,
+    AutoDeviceMapParams, DiffusionLoaderBuilder, DiffusionSpecificConfig, EmbeddingLoaderBuilder,
+    GGUFSpecificConfig, GGUFVisionLoaderBuilder, KvCacheType, Loader, ModelDType, ModelSelected,
+    NormalLoaderBuilder, SpeechLoaderBuilder, TomlLoaderArgs, TomlSelector, Topology,
+    TopologySearch, VisionLoaderBuilder, VisionSpecificConfig, GGUF_MULTI_FILE_DELIMITER,
 };
 
 /// A builder for a loader using the selected model.
@@ -19,6 +20,9 @@ pub struct LoaderBuilder {
     chat_template: Option<String>,
     use_flash_attn: bool,
     prompt_chunksize: Option<NonZeroUsize>,
+    kv_cache_type: KvCacheType,
+    topology_search: Option<TopologySearch>,
+    self_contained_uqff: bool,
 }
 
 impl LoaderBuilder {
@@ -29,6 +33,9 @@ impl LoaderBuilder {
             chat_template: None,
             use_flash_attn: false,
             prompt_chunksize: None,
+            kv_cache_type: KvCacheType::Auto,
+            topology_search: None,
+            self_contained_uqff: false,
         }
     }
 
@@ -48,6 +55,18 @@ impl LoaderBuilder {
         self.prompt_chunksize = prompt_chunksize;
         self
     }
+    pub fn with_kv_cache_type(mut self, kv_cache_type: KvCacheType) -> Self {
+        self.kv_cache_type = kv_cache_type;
+        self
+    }
+    pub fn with_topology_search(mut self, topology_search: Option<TopologySearch>) -> Self {
+        self.topology_search = topology_search;
+        self
+    }
+    pub fn with_self_contained_uqff(mut self, self_contained_uqff: bool) -> Self {
+        self.self_contained_uqff = self_contained_uqff;
+        self
+    }
 
     pub fn build(self) -> anyhow::Result<Box<dyn Loader>> {
         loader_from_model_selected(self)
@@ -64,7 +83,10 @@ pub fn get_tgt_non_granular_index(model: &ModelSelected) -> Option<usize> {
         | ModelSelected::LoraGGML { .. }
         | ModelSelected::Toml { .. }
         | ModelSelected::VisionPlain { .. }
-        | ModelSelected::DiffusionPlain { .. } => None,
+        | ModelSelected::VisionGGUF { .. }
+        | ModelSelected::DiffusionPlain { .. }
+        | ModelSelected::Speech { .. }
+        | ModelSelected::Embedding { .. } => None,
         ModelSelected::XLora {
             tgt_non_granular_index,
             ..
@@ -86,7 +108,10 @@ pub fn get_model_dtype(model: &ModelSelected) -> anyhow::Result<ModelDType> {
         | ModelSelected::Lora { dtype, .. }
         | ModelSelected::XLora { dtype, .. }
         | ModelSelected::VisionPlain { dtype, .. }
+        | ModelSelected::VisionGGUF { dtype, .. }
         | ModelSelected::DiffusionPlain { dtype, .. }
+        | ModelSelected::Speech { dtype, .. }
+        | ModelSelected::Embedding { dtype, .. }
         | ModelSelected::GGML { dtype, .. }
         | ModelSelected::GGUF { dtype, .. }
         | ModelSelected::XLoraGGUF { dtype, .. }
@@ -159,6 +184,13 @@ pub fn get_auto_device_map_params(model: &ModelSelected) -> anyhow::Result<AutoD
             max_image_length,
             max_num_images,
             ..
+        }
+        | ModelSelected::VisionGGUF {
+            max_seq_len,
+            max_batch_size,
+            max_image_length,
+            max_num_images,
+            ..
         } => Ok(AutoDeviceMapParams::Vision {
             max_seq_len: *max_seq_len,
             max_batch_size: *max_batch_size,
@@ -168,6 +200,9 @@ pub fn get_auto_device_map_params(model: &ModelSelected) -> anyhow::Result<AutoD
         ModelSelected::DiffusionPlain { .. } => {
             anyhow::bail!("diffusion model doesn't support max_seq_len")
         }
+        ModelSelected::Speech { .. } | ModelSelected::Embedding { .. } => {
+            Ok(AutoDeviceMapParams::default_text())
+        }
         ModelSelected::Toml { file } => {
             let selector: TomlSelector = toml::from_str(
                 &fs::read_to_string(file.clone())
@@ -223,6 +258,9 @@ fn loader_from_model_selected(args: LoaderBuilder) -> anyhow::Result<Box<dyn Loa
             Some(model_id),
         )
         .with_no_kv_cache(args.no_kv_cache)
+        .with_kv_cache_type(args.kv_cache_type)
+        .with_topology_search(args.topology_search.clone())
+        .with_self_contained_uqff(args.self_contained_uqff)
         .build(arch)?,
         ModelSelected::XLora {
             model_id,
@@ -262,6 +300,8 @@ fn loader_from_model_selected(args: LoaderBuilder) -> anyhow::Result<Box<dyn Loa
             args.no_kv_cache,
             tgt_non_granular_index,
         )
+        .with_kv_cache_type(args.kv_cache_type)
+        .with_topology_search(args.topology_search.clone())
         .build(arch)?,
         ModelSelected::Lora {
             model_id,
@@ -298,6 +338,8 @@ fn loader_from_model_selected(args: LoaderBuilder) -> anyhow::Result<Box<dyn Loa
                     .unwrap_or_else(|_| panic!("Could not load ordering file at {order}")),
             )?,
         )
+        .with_kv_cache_type(args.kv_cache_type)
+        .with_topology_search(args.topology_search.clone())
         .build(arch)?,
         ModelSelected::GGUF {
             tok_model_id,
@@ -318,6 +360,7 @@ fn loader_from_model_selected(args: LoaderBuilder) -> anyhow::Result<Box<dyn Loa
                 topology: Topology::from_option_path(topology)?,
             },
         )
+        .with_kv_cache_type(args.kv_cache_type)
         .build(),
         ModelSelected::XLoraGGUF {
             tok_model_id,
@@ -351,6 +394,7 @@ fn loader_from_model_selected(args: LoaderBuilder) -> anyhow::Result<Box<dyn Loa
             args.no_kv_cache,
             tgt_non_granular_index,
         )
+        .with_kv_cache_type(args.kv_cache_type)
         .build(),
         ModelSelected::LoraGGUF {
             tok_model_id,
@@ -381,6 +425,7 @@ fn loader_from_model_selected(args: LoaderBuilder) -> anyhow::Result<Box<dyn Loa
                     .unwrap_or_else(|_| panic!("Could not load ordering file at {order}")),
             )?,
         )
+        .with_kv_cache_type(args.kv_cache_type)
         .build(),
         ModelSelected::GGML {
             tok_model_id,
@@ -403,6 +448,7 @@ fn loader_from_model_selected(args: LoaderBuilder) -> anyhow::Result<Box<dyn Loa
             quantized_filename,
         )
         .with_no_kv_cache(args.no_kv_cache)
+        .with_kv_cache_type(args.kv_cache_type)
         .build(),
         ModelSelected::XLoraGGML {
             tok_model_id,
@@ -437,6 +483,7 @@ fn loader_from_model_selected(args: LoaderBuilder) -> anyhow::Result<Box<dyn Loa
             args.no_kv_cache,
             tgt_non_granular_index,
         )
+        .with_kv_cache_type(args.kv_cache_type)
         .build(),
         ModelSelected::LoraGGML {
             tok_model_id,
@@ -468,6 +515,7 @@ fn loader_from_model_selected(args: LoaderBuilder) -> anyhow::Result<Box<dyn Loa
                     .unwrap_or_else(|_| panic!("Could not load ordering file at {order}")),
             )?,
         )
+        .with_kv_cache_type(args.kv_cache_type)
         .build(),
         ModelSelected::VisionPlain {
             model_id,
@@ -499,7 +547,33 @@ fn loader_from_model_selected(args: LoaderBuilder) -> anyhow::Result<Box<dyn Loa
             tokenizer_json,
             Some(model_id),
         )
+        .with_kv_cache_type(args.kv_cache_type)
+        .with_self_contained_uqff(args.self_contained_uqff)
         .build(arch),
+        ModelSelected::VisionGGUF {
+            tok_model_id,
+            quantized_model_id,
+            quantized_filename,
+            mmproj_filename,
+            arch,
+            topology,
+            ..
+        } => GGUFVisionLoaderBuilder::new(
+            args.chat_template,
+            tok_model_id,
+            quantized_model_id,
+            quantized_filename
+                .split(GGUF_MULTI_FILE_DELIMITER)
+                .map(ToOwned::to_owned)
+                .collect::<Vec<_>>(),
+            mmproj_filename,
+            GGUFSpecificConfig {
+                prompt_chunksize: args.prompt_chunksize,
+                topology: Topology::from_option_path(topology)?,
+            },
+        )
+        .with_kv_cache_type(args.kv_cache_type)
+        .build(arch)?,
         ModelSelected::DiffusionPlain {
             model_id,
             arch,
@@ -508,6 +582,16 @@ fn loader_from_model_selected(args: LoaderBuilder) -> anyhow::Result<Box<dyn Loa
             DiffusionLoaderBuilder::new(DiffusionSpecificConfig { use_flash_attn }, Some(model_id))
                 .build(arch)
         }
+        ModelSelected::Speech {
+            model_id,
+            arch,
+            dtype: _,
+        } => SpeechLoaderBuilder::new(Some(model_id)).build(arch),
+        ModelSelected::Embedding {
+            model_id,
+            arch,
+            dtype: _,
+        } => EmbeddingLoaderBuilder::new(Some(model_id)).build(arch),
     };
     Ok(loader)
 }
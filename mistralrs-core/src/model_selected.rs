@@ -4,7 +4,7 @@ use clap::Subcommand;
 
 use crate::{
     pipeline::{AutoDeviceMapParams, IsqOrganization, NormalLoaderType, VisionLoaderType},
-    DiffusionLoaderType, ModelDType,
+    DiffusionLoaderType, EmbeddingLoaderType, ModelDType, SpeechLoaderType,
 };
 
 fn parse_arch(x: &str) -> Result<NormalLoaderType, String> {
@@ -19,6 +19,14 @@ fn parse_diffusion_arch(x: &str) -> Result<DiffusionLoaderType, String> {
     x.parse()
 }
 
+fn parse_speech_arch(x: &str) -> Result<SpeechLoaderType, String> {
+    x.parse()
+}
+
+fn parse_embedding_arch(x: &str) -> Result<EmbeddingLoaderType, String> {
+    x.parse()
+}
+
 fn parse_model_dtype(x: &str) -> Result<ModelDType, String> {
     x.parse()
 }
@@ -59,7 +67,7 @@ pub enum ModelSelected {
         #[arg(short, long)]
         organization: Option<IsqOrganization>,
 
-        /// UQFF path to write to.
+        /// UQFF path to write to. If the extension is `.gguf`, a GGUF file is written instead.
         #[arg(short, long)]
         write_uqff: Option<PathBuf>,
 
@@ -513,6 +521,57 @@ pub enum ModelSelected {
         max_image_length: usize,
     },
 
+    /// Select a GGUF vision model, with the vision encoder and projector in a llama.cpp `mmproj` file.
+    VisionGGUF {
+        /// Model ID of the original model, where the `config.json`, `preprocessor_config.json` and
+        /// tokenizer files are found. This may be a HF hub repo or a local path.
+        #[arg(short, long)]
+        tok_model_id: String,
+
+        /// Quantized model ID to find the `quantized_filename` and `mmproj_filename`.
+        /// This may be a HF hub repo or a local path.
+        #[arg(short = 'm', long)]
+        quantized_model_id: String,
+
+        /// Quantized filename(s).
+        /// May be a single filename, or use a delimiter of " " (a single space) for multiple files.
+        #[arg(short = 'f', long)]
+        quantized_filename: String,
+
+        /// Filename of the `mmproj` file with the vision encoder and multimodal projector.
+        #[arg(long)]
+        mmproj_filename: String,
+
+        /// The architecture of the model. Only `llava`, `llava_next`, `qwen2vl` and `minicpmo` are supported.
+        #[arg(short, long, value_parser = parse_vision_arch)]
+        arch: VisionLoaderType,
+
+        /// Model data type. Defaults to `auto`.
+        #[arg(short, long, default_value_t = ModelDType::Auto, value_parser = parse_model_dtype)]
+        dtype: ModelDType,
+
+        /// Path to a topology YAML file.
+        #[arg(long)]
+        topology: Option<String>,
+
+        /// Maximum prompt sequence length to expect for this model. This affects automatic device mapping but is not a hard limit.
+        #[arg(long, default_value_t = AutoDeviceMapParams::DEFAULT_MAX_SEQ_LEN)]
+        max_seq_len: usize,
+
+        /// Maximum prompt batch size to expect for this model. This affects automatic device mapping but is not a hard limit.
+        #[arg(long, default_value_t = AutoDeviceMapParams::DEFAULT_MAX_BATCH_SIZE)]
+        max_batch_size: usize,
+
+        /// Maximum prompt number of images to expect for this model. This affects automatic device mapping but is not a hard limit.
+        #[arg(long, default_value_t = AutoDeviceMapParams::DEFAULT_MAX_NUM_IMAGES)]
+        max_num_images: usize,
+
+        /// Maximum expected image size will have this edge length on both edges.
+        /// This affects automatic device mapping but is not a hard limit.
+        #[arg(long, default_value_t = AutoDeviceMapParams::DEFAULT_MAX_IMAGE_LENGTH)]
+        max_image_length: usize,
+    },
+
     /// Select a diffusion plain model, without quantization or adapters
     DiffusionPlain {
         /// Model ID to load from. This may be a HF hub repo or a local path.
@@ -527,4 +586,34 @@ pub enum ModelSelected {
         #[arg(short, long, default_value_t = ModelDType::Auto, value_parser = parse_model_dtype)]
         dtype: ModelDType,
     },
+
+    /// Select a speech to text model, without quantization or adapters
+    Speech {
+        /// Model ID to load from. This may be a HF hub repo or a local path.
+        #[arg(short, long)]
+        model_id: String,
+
+        /// The architecture of the model.
+        #[arg(short, long, value_parser = parse_speech_arch)]
+        arch: SpeechLoaderType,
+
+        /// Model data type. Defaults to `auto`.
+        #[arg(short, long, default_value_t = ModelDType::Auto, value_parser = parse_model_dtype)]
+        dtype: ModelDType,
+    },
+
+    /// Select an encoder-only embedding or reranker model, without quantization or adapters
+    Embedding {
+        /// Model ID to load from. This may be a HF hub repo or a local path.
+        #[arg(short, long)]
+        model_id: String,
+
+        /// The architecture of the model.
+        #[arg(short, long, value_parser = parse_embedding_arch)]
+        arch: EmbeddingLoaderType,
+
+        /// Model data type. Defaults to `auto`.
+        #[arg(short, long, default_value_t = ModelDType::Auto, value_parser = parse_model_dtype)]
+        dtype: ModelDType,
+    },
 }
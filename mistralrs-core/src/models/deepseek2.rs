@@ -920,7 +920,7 @@ impl DeepSeekV2 {
         flash_params: &FlashParams,
     ) -> Result<Tensor> {
         let mut xs = self.embed_tokens.forward(input_ids)?;
-        let cache = &mut self.cache.normal().0;
+        let cache = &mut self.cache.normal()?.0;
         let attention_mask = CausalMasker.make_causal_mask_matrix(
             input_ids,
             metadata
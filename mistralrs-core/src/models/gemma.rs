@@ -481,7 +481,7 @@ impl Model {
     ) -> Result<Tensor> {
         let xs = self.embed_tokens.forward(input_ids)?;
         let mut xs = (xs * (self.hidden_size as f64).sqrt())?;
-        let cache = &mut self.cache.normal().0;
+        let cache = &mut self.cache.normal()?.0;
         let attention_mask = CausalMasker.make_causal_mask_matrix(
             input_ids,
             metadata
@@ -0,0 +1,630 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+/// Jamba, a hybrid of Mamba and attention layers with mixture-of-experts feed forward layers.
+/// https://github.com/huggingface/transformers/blob/main/src/transformers/models/jamba/modeling_jamba.py
+use candle_core::{DType, Device, Module, Result, Tensor};
+use candle_nn::Embedding;
+use mistralrs_quant::{QuantMethod, QuantizedConfig, ReplicatedLayer, ShardedVarBuilder};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::{
+    amoe::{AnyMoeBaseModelMixin, MlpLayer},
+    attention::SdpaParams,
+    device_map::DeviceMapper,
+    layers::{embedding, Activation, CausalMasker, Mlp, RmsNorm, Sdpa},
+    layers_masker::PastKvLenCache,
+    layers_ssm::TimeStepRank,
+    paged_attention::ModelConfigMetadata,
+    pipeline::{
+        extract_logits,
+        text_models_inputs_processor::{FlashParams, PagedAttentionInputMetadata},
+        EitherCache, IsqModel, KvCache, NormalLoadingMetadata, NormalModel, RecurrentCache,
+        RecurrentLayerCache,
+    },
+    serde_default_fn,
+    utils::{progress::NiceProgressBar, unvarbuilder::UnVarBuilder},
+};
+
+use super::mamba::{qmethod_matmul, MambaMixer, MixerConfig};
+
+serde_default_fn!(f64, rms_norm_eps, 1e-6);
+serde_default_fn!(Activation, hidden_act, Activation::Silu);
+serde_default_fn!(usize, max_position_embeddings, 262144);
+serde_default_fn!(usize, mamba_d_state, 16);
+serde_default_fn!(usize, mamba_d_conv, 4);
+serde_default_fn!(usize, mamba_expand, 2);
+serde_default_fn!(bool, mamba_conv_bias, true);
+serde_default_fn!(usize, num_experts, 16);
+serde_default_fn!(usize, num_experts_per_tok, 2);
+serde_default_fn!(usize, attn_layer_period, 8);
+serde_default_fn!(usize, attn_layer_offset, 4);
+serde_default_fn!(usize, expert_layer_period, 2);
+serde_default_fn!(usize, expert_layer_offset, 1);
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    pub(crate) vocab_size: usize,
+    pub(crate) hidden_size: usize,
+    pub(crate) intermediate_size: usize,
+    pub(crate) num_hidden_layers: usize,
+    pub(crate) num_attention_heads: usize,
+    pub(crate) num_key_value_heads: usize,
+    #[serde(default = "hidden_act")]
+    pub(crate) hidden_act: Activation,
+    #[serde(default = "rms_norm_eps")]
+    pub(crate) rms_norm_eps: f64,
+    #[serde(default = "max_position_embeddings")]
+    pub(crate) max_position_embeddings: usize,
+    #[serde(default = "mamba_d_state")]
+    pub(crate) mamba_d_state: usize,
+    #[serde(default = "mamba_d_conv")]
+    pub(crate) mamba_d_conv: usize,
+    #[serde(default = "mamba_expand")]
+    pub(crate) mamba_expand: usize,
+    #[serde(default)]
+    pub(crate) mamba_dt_rank: TimeStepRank,
+    #[serde(default = "mamba_conv_bias")]
+    pub(crate) mamba_conv_bias: bool,
+    #[serde(default)]
+    pub(crate) mamba_proj_bias: bool,
+    #[serde(default = "num_experts")]
+    pub(crate) num_experts: usize,
+    #[serde(default = "num_experts_per_tok")]
+    pub(crate) num_experts_per_tok: usize,
+    #[serde(default = "attn_layer_period")]
+    pub(crate) attn_layer_period: usize,
+    #[serde(default = "attn_layer_offset")]
+    pub(crate) attn_layer_offset: usize,
+    #[serde(default = "expert_layer_period")]
+    pub(crate) expert_layer_period: usize,
+    #[serde(default = "expert_layer_offset")]
+    pub(crate) expert_layer_offset: usize,
+    #[serde(default)]
+    pub(crate) tie_word_embeddings: bool,
+    pub(crate) quantization_config: Option<QuantizedConfig>,
+}
+
+impl Config {
+    pub(crate) fn is_attention_layer(&self, layer_idx: usize) -> bool {
+        layer_idx % self.attn_layer_period == self.attn_layer_offset
+    }
+
+    pub(crate) fn is_expert_layer(&self, layer_idx: usize) -> bool {
+        self.num_experts > 1 && layer_idx % self.expert_layer_period == self.expert_layer_offset
+    }
+
+    pub(crate) fn attention_layers(&self) -> Vec<usize> {
+        (0..self.num_hidden_layers)
+            .filter(|i| self.is_attention_layer(*i))
+            .collect()
+    }
+
+    pub(crate) fn head_dim(&self) -> usize {
+        self.hidden_size / self.num_attention_heads
+    }
+
+    fn mixer_config(&self) -> MixerConfig {
+        MixerConfig {
+            hidden_size: self.hidden_size,
+            intermediate_size: self.mamba_expand * self.hidden_size,
+            state_size: self.mamba_d_state,
+            conv_kernel: self.mamba_d_conv,
+            dt_rank: self.mamba_dt_rank.rank(self.hidden_size),
+            use_bias: self.mamba_proj_bias,
+            use_conv_bias: self.mamba_conv_bias,
+            inner_norm_eps: Some(self.rms_norm_eps),
+        }
+    }
+}
+
+/// Attention without positional embeddings, the Mamba layers provide the positional information.
+struct Attention {
+    q_proj: Arc<dyn QuantMethod>,
+    k_proj: Arc<dyn QuantMethod>,
+    v_proj: Arc<dyn QuantMethod>,
+    o_proj: Arc<dyn QuantMethod>,
+    num_heads: usize,
+    num_kv_heads: usize,
+    head_dim: usize,
+    sdpa_params: SdpaParams,
+}
+
+impl Attention {
+    fn new(cfg: &Config, vb: ShardedVarBuilder) -> Result<Self> {
+        let hidden_sz = cfg.hidden_size;
+        let num_heads = cfg.num_attention_heads;
+        let num_kv_heads = cfg.num_key_value_heads;
+        let head_dim = cfg.head_dim();
+        let q_proj = ReplicatedLayer::new(
+            hidden_sz,
+            num_heads * head_dim,
+            &cfg.quantization_config,
+            false,
+            vb.pp("q_proj"),
+        )?;
+        let k_proj = ReplicatedLayer::new(
+            hidden_sz,
+            num_kv_heads * head_dim,
+            &cfg.quantization_config,
+            false,
+            vb.pp("k_proj"),
+        )?;
+        let v_proj = ReplicatedLayer::new(
+            hidden_sz,
+            num_kv_heads * head_dim,
+            &cfg.quantization_config,
+            false,
+            vb.pp("v_proj"),
+        )?;
+        let o_proj = ReplicatedLayer::new(
+            num_heads * head_dim,
+            hidden_sz,
+            &cfg.quantization_config,
+            false,
+            vb.pp("o_proj"),
+        )?;
+        Ok(Self {
+            q_proj,
+            k_proj,
+            v_proj,
+            o_proj,
+            num_heads,
+            num_kv_heads,
+            head_dim,
+            sdpa_params: SdpaParams {
+                n_kv_groups: num_heads / num_kv_heads,
+                use_flash_attn: false,
+                softcap: None,
+                softmax_scale: 1.0 / (head_dim as f32).sqrt(),
+                sliding_window: None,
+            },
+        })
+    }
+
+    fn forward(
+        &self,
+        xs: &Tensor,
+        attention_mask: Option<&Tensor>,
+        kv_cache: &mut KvCache,
+        flash_params: &FlashParams,
+    ) -> Result<Tensor> {
+        let (b_sz, q_len, _) = xs.dims3()?;
+
+        let q = qmethod_matmul(xs, &*self.q_proj)?
+            .reshape((b_sz, q_len, self.num_heads, self.head_dim))?
+            .transpose(1, 2)?
+            .contiguous()?;
+        let k = qmethod_matmul(xs, &*self.k_proj)?
+            .reshape((b_sz, q_len, self.num_kv_heads, self.head_dim))?
+            .transpose(1, 2)?
+            .contiguous()?;
+        let v = qmethod_matmul(xs, &*self.v_proj)?
+            .reshape((b_sz, q_len, self.num_kv_heads, self.head_dim))?
+            .transpose(1, 2)?
+            .contiguous()?;
+
+        let (k, v) = kv_cache.append(&k, &v)?;
+
+        let attn_output = Sdpa
+            .run_attention(
+                &q,
+                &k,
+                &v,
+                attention_mask,
+                Some(flash_params),
+                &self.sdpa_params,
+            )?
+            .transpose(1, 2)?
+            .reshape((b_sz, q_len, ()))?;
+        qmethod_matmul(&attn_output, &*self.o_proj)
+    }
+}
+
+/// Top-k routing over the experts. Unlike Mixtral, the selected routing weights are not
+/// renormalized.
+struct SparseMoeBlock {
+    router: Arc<dyn QuantMethod>,
+    experts: Vec<Mlp>,
+    num_experts_per_tok: usize,
+}
+
+impl SparseMoeBlock {
+    fn new(cfg: &Config, vb: ShardedVarBuilder, comm: &Arc<mistralrs_quant::Comm>) -> Result<Self> {
+        let router = mistralrs_quant::linear_no_bias(
+            cfg.hidden_size,
+            cfg.num_experts,
+            &cfg.quantization_config,
+            vb.pp("router"),
+        )?;
+        let mut experts = Vec::with_capacity(cfg.num_experts);
+        let vb_e = vb.pp("experts");
+        for idx in 0..cfg.num_experts {
+            experts.push(Mlp::new(
+                vb_e.pp(idx),
+                cfg.hidden_size,
+                cfg.intermediate_size,
+                &cfg.quantization_config,
+                cfg.hidden_act,
+                comm,
+            )?);
+        }
+        Ok(Self {
+            router,
+            experts,
+            num_experts_per_tok: cfg.num_experts_per_tok,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let (b_size, seq_len, hidden_dim) = xs.dims3()?;
+        let xs = xs.reshape(((), hidden_dim))?;
+
+        let router_logits = qmethod_matmul(&xs, &*self.router)?;
+        let routing_weights =
+            candle_nn::ops::softmax_last_dim(&router_logits.to_dtype(DType::F32)?)?;
+        let routing_weights = routing_weights.to_vec2::<f32>()?;
+
+        // top_x contains the row indexes to evaluate for each expert.
+        let mut top_x = vec![vec![]; self.experts.len()];
+        let mut selected_rws = vec![vec![]; self.experts.len()];
+        for (row_idx, rw) in routing_weights.iter().enumerate() {
+            let mut dst = (0..rw.len() as u32).collect::<Vec<u32>>();
+            dst.sort_by(|&i, &j| rw[j as usize].total_cmp(&rw[i as usize]));
+            for &expert_idx in dst.iter().take(self.num_experts_per_tok) {
+                let expert_idx = expert_idx as usize;
+                top_x[expert_idx].push(row_idx as u32);
+                selected_rws[expert_idx].push(rw[expert_idx]);
+            }
+        }
+
+        let mut ys = xs.zeros_like()?;
+        for (expert_idx, expert_layer) in self.experts.iter().enumerate() {
+            let top_x = &top_x[expert_idx];
+            if top_x.is_empty() {
+                continue;
+            }
+            let top_x = Tensor::new(top_x.as_slice(), xs.device())?;
+            let selected_rws = Tensor::new(selected_rws[expert_idx].as_slice(), xs.device())?
+                .reshape(((), 1))?
+                .to_dtype(xs.dtype())?;
+            let current_state = xs.index_select(&top_x, 0)?;
+            let current_hidden_states = expert_layer
+                .forward(&current_state)?
+                .broadcast_mul(&selected_rws)?;
+            ys = ys.index_add(&top_x, &current_hidden_states, 0)?;
+        }
+
+        ys.reshape((b_size, seq_len, hidden_dim))
+    }
+}
+
+enum Mixer {
+    Attention(Attention),
+    Mamba(MambaMixer),
+}
+
+enum FeedForward {
+    Dense(Mlp),
+    Sparse(SparseMoeBlock),
+}
+
+impl FeedForward {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        match self {
+            Self::Dense(mlp) => mlp.forward(xs),
+            Self::Sparse(moe) => moe.forward(xs),
+        }
+    }
+}
+
+struct DecoderLayer {
+    input_layernorm: RmsNorm,
+    mixer: Mixer,
+    pre_ff_layernorm: RmsNorm,
+    feed_forward: FeedForward,
+}
+
+impl DecoderLayer {
+    fn forward(
+        &self,
+        xs: &Tensor,
+        attention_mask: Option<&Tensor>,
+        cache: &mut RecurrentLayerCache,
+        flash_params: &FlashParams,
+    ) -> Result<Tensor> {
+        let residual = xs;
+        let xs = xs.apply(&self.input_layernorm)?;
+        let xs = match (&self.mixer, cache) {
+            (Mixer::Attention(attn), RecurrentLayerCache::Attention(kv_cache)) => {
+                attn.forward(&xs, attention_mask, kv_cache, flash_params)?
+            }
+            (Mixer::Mamba(mamba), RecurrentLayerCache::Ssm(state)) => mamba.forward(&xs, state)?,
+            _ => candle_core::bail!("The layer cache does not match the layer type."),
+        };
+        let xs = (xs + residual)?;
+        let residual = &xs;
+        let xs = self
+            .feed_forward
+            .forward(&xs.apply(&self.pre_ff_layernorm)?)?;
+        residual + xs
+    }
+}
+
+pub struct Model {
+    embed_tokens: Embedding,
+    layers: Vec<DecoderLayer>,
+    final_layernorm: RmsNorm,
+    lm_head: Arc<dyn QuantMethod>,
+    device: Device,
+    cache: EitherCache,
+    max_seq_len: usize,
+    mapper: Box<dyn DeviceMapper + Send + Sync>,
+    cfg: ModelConfigMetadata,
+    num_attention_heads: usize,
+}
+
+impl Model {
+    pub fn new(
+        cfg: &Config,
+        vb: ShardedVarBuilder,
+        normal_loading_metadata: NormalLoadingMetadata,
+    ) -> Result<Self> {
+        if let Some(ref quant_cfg) = &cfg.quantization_config {
+            tracing::info!(
+                "Using {} quantization: {}.",
+                quant_cfg.quant_method.to_string(),
+                quant_cfg.get_bits_name(&vb)
+            );
+        }
+        let mapper = normal_loading_metadata.mapper;
+        let vb_m = vb.pp("model");
+
+        let embed_tokens = embedding(
+            cfg.vocab_size,
+            cfg.hidden_size,
+            mapper.set_nm_device(vb_m.pp("embed_tokens"), false),
+        )?;
+
+        let mixer_cfg = cfg.mixer_config();
+        let mut layers = Vec::with_capacity(cfg.num_hidden_layers);
+        let vb_l = vb_m.pp("layers");
+        for layer_idx in NiceProgressBar::<_, 'b'>(
+            0..cfg.num_hidden_layers,
+            "Loading repeating layers",
+            &normal_loading_metadata.multi_progress,
+        ) {
+            let vb_layer = vb_l.pp(layer_idx);
+            let vb_isq = mapper.set_device(
+                layer_idx,
+                vb_layer.clone(),
+                normal_loading_metadata.loading_isq,
+            );
+            let input_layernorm = RmsNorm::new(
+                cfg.hidden_size,
+                cfg.rms_norm_eps,
+                mapper.set_device(layer_idx, vb_layer.pp("input_layernorm"), false),
+            )?;
+            let pre_ff_layernorm = RmsNorm::new(
+                cfg.hidden_size,
+                cfg.rms_norm_eps,
+                mapper.set_device(layer_idx, vb_layer.pp("pre_ff_layernorm"), false),
+            )?;
+            let mixer = if cfg.is_attention_layer(layer_idx) {
+                Mixer::Attention(Attention::new(cfg, vb_isq.pp("self_attn"))?)
+            } else {
+                Mixer::Mamba(MambaMixer::new(
+                    &mixer_cfg,
+                    &cfg.quantization_config,
+                    vb_isq.pp("mamba"),
+                )?)
+            };
+            let comm = mapper.get_comm_for(layer_idx)?;
+            let feed_forward = if cfg.is_expert_layer(layer_idx) {
+                FeedForward::Sparse(SparseMoeBlock::new(cfg, vb_isq.pp("feed_forward"), &comm)?)
+            } else {
+                FeedForward::Dense(Mlp::new(
+                    vb_isq.pp("feed_forward"),
+                    cfg.hidden_size,
+                    cfg.intermediate_size,
+                    &cfg.quantization_config,
+                    cfg.hidden_act,
+                    &comm,
+                )?)
+            };
+            layers.push(DecoderLayer {
+                input_layernorm,
+                mixer,
+                pre_ff_layernorm,
+                feed_forward,
+            });
+        }
+        let final_layernorm = RmsNorm::new(
+            cfg.hidden_size,
+            cfg.rms_norm_eps,
+            mapper.set_nm_device(vb_m.pp("final_layernorm"), false),
+        )?;
+        let lm_head = if !cfg.tie_word_embeddings {
+            ReplicatedLayer::new(
+                cfg.hidden_size,
+                cfg.vocab_size,
+                &None,
+                false,
+                mapper.set_nm_device(vb.pp("lm_head"), normal_loading_metadata.loading_isq),
+            )?
+        } else {
+            ReplicatedLayer::from_linear(candle_nn::Linear::new(
+                mapper.cast_nm_device(
+                    embed_tokens.embeddings(),
+                    normal_loading_metadata.loading_isq,
+                )?,
+                None,
+            ))?
+        };
+        Ok(Self {
+            embed_tokens,
+            layers,
+            final_layernorm,
+            lm_head,
+            device: normal_loading_metadata.real_device,
+            cache: EitherCache::Recurrent(RecurrentCache::new(
+                cfg.num_hidden_layers,
+                &cfg.attention_layers(),
+                cfg.max_position_embeddings,
+            )),
+            max_seq_len: cfg.max_position_embeddings,
+            cfg: ModelConfigMetadata {
+                max_seq_len: cfg.max_position_embeddings,
+                num_layers: cfg.num_hidden_layers,
+                hidden_size: cfg.hidden_size,
+                num_kv_heads: cfg.num_key_value_heads,
+                num_attn_heads: cfg.num_attention_heads,
+                sliding_window: None,
+                k_head_dim: cfg.head_dim(),
+                v_head_dim: cfg.head_dim(),
+            },
+            mapper,
+            num_attention_heads: cfg.num_attention_heads,
+        })
+    }
+
+    pub fn forward(
+        &self,
+        input_ids: &Tensor,
+        seqlen_offsets: &[usize],
+        context_lens: Vec<(usize, usize)>,
+        flash_params: &FlashParams,
+    ) -> Result<Tensor> {
+        let mut xs = self.embed_tokens.forward(input_ids)?;
+        let mut cache = self.cache.recurrent()?;
+        let attention_mask = CausalMasker.make_causal_mask_matrix(
+            input_ids,
+            &seqlen_offsets as &dyn PastKvLenCache,
+            xs.dtype(),
+            self.num_attention_heads,
+        )?;
+        for (i, layer) in self.layers.iter().enumerate() {
+            xs = self.mapper.map(xs, i)?;
+            xs = layer.forward(
+                &xs,
+                attention_mask
+                    .as_ref()
+                    .map(|m| m.to_device(xs.device()).unwrap())
+                    .as_ref(),
+                &mut cache.0[i],
+                flash_params,
+            )?;
+        }
+        let xs = xs.to_device(&self.device)?.apply(&self.final_layernorm)?;
+        extract_logits(&qmethod_matmul(&xs, &*self.lm_head)?, context_lens)
+    }
+}
+
+impl IsqModel for Model {
+    fn get_layers(
+        &mut self,
+    ) -> (
+        Vec<(&mut Arc<dyn QuantMethod>, Option<usize>)>,
+        &dyn DeviceMapper,
+    ) {
+        let mut tensors = Vec::new();
+        tensors.push((&mut self.lm_head, None));
+        for (i, layer) in self.layers.iter_mut().enumerate() {
+            match &mut layer.mixer {
+                Mixer::Attention(attn) => {
+                    tensors.push((&mut attn.q_proj, Some(i)));
+                    tensors.push((&mut attn.k_proj, Some(i)));
+                    tensors.push((&mut attn.v_proj, Some(i)));
+                    tensors.push((&mut attn.o_proj, Some(i)));
+                }
+                Mixer::Mamba(mamba) => {
+                    tensors.push((&mut mamba.in_proj, Some(i)));
+                    tensors.push((&mut mamba.x_proj, Some(i)));
+                    tensors.push((&mut mamba.out_proj, Some(i)));
+                }
+            }
+            match &mut layer.feed_forward {
+                FeedForward::Dense(mlp) => {
+                    tensors.extend(mlp.get_isq_layers().into_iter().map(|m| (m, Some(i))));
+                }
+                FeedForward::Sparse(moe) => {
+                    for expert in &mut moe.experts {
+                        tensors.extend(expert.get_isq_layers().into_iter().map(|m| (m, Some(i))));
+                    }
+                }
+            }
+        }
+        (tensors, &*self.mapper)
+    }
+
+    fn residual_tensors(&self) -> Vec<(String, Tensor)> {
+        let uvb = UnVarBuilder::new();
+
+        let uvb_m = uvb.pp("model");
+        uvb_m.pp("embed_tokens").add(&self.embed_tokens);
+        uvb_m.pp("final_layernorm").add(&self.final_layernorm);
+
+        for (layer_idx, layer) in self.layers.iter().enumerate() {
+            let uvb_l = uvb_m.pp("layers").pp(layer_idx);
+            uvb_l.pp("input_layernorm").add(&layer.input_layernorm);
+            uvb_l.pp("pre_ff_layernorm").add(&layer.pre_ff_layernorm);
+            if let Mixer::Mamba(mamba) = &layer.mixer {
+                mamba.residual_tensors(&uvb_l.pp("mamba"));
+            }
+            if let FeedForward::Sparse(moe) = &layer.feed_forward {
+                uvb_l.pp("feed_forward").pp("router").add(&moe.router);
+            }
+        }
+
+        uvb.to_safetensors()
+    }
+}
+
+impl NormalModel for Model {
+    fn forward(
+        &self,
+        input_ids: &Tensor,
+        seqlen_offsets: &[usize],
+        context_lens: Vec<(usize, usize)>,
+        _position_ids: Vec<usize>,
+        _metadata: Option<(Vec<(Tensor, Tensor)>, &PagedAttentionInputMetadata)>,
+        flash_params: &FlashParams,
+    ) -> Result<Tensor> {
+        self.forward(input_ids, seqlen_offsets, context_lens, flash_params)
+    }
+    fn xlora_forward(
+        &self,
+        _input_ids: &Tensor,
+        _input_ids_full: &Tensor,
+        _seqlen_offsets: &[usize],
+        _seqlen_offsets_full: &[usize],
+        _no_kv_cache: bool,
+        _non_granular_state: &Option<crate::xlora_models::NonGranularState>,
+        _context_lens: Vec<(usize, usize)>,
+        _position_ids: Vec<usize>,
+        _flash_params: &FlashParams,
+        _flash_params_full: &FlashParams,
+    ) -> Result<Tensor> {
+        candle_core::bail!("X-LoRA is not supported for Jamba models.")
+    }
+    fn cache(&self) -> &EitherCache {
+        &self.cache
+    }
+    fn cache_mut(&mut self) -> &mut EitherCache {
+        &mut self.cache
+    }
+    fn device(&self) -> &Device {
+        &self.device
+    }
+    fn is_xlora(&self) -> bool {
+        false
+    }
+    fn max_seq_len(&self) -> usize {
+        self.max_seq_len
+    }
+    fn config(&self) -> &ModelConfigMetadata {
+        &self.cfg
+    }
+}
+
+impl AnyMoeBaseModelMixin for Model {}
@@ -509,7 +509,7 @@ impl Llama {
         flash_params: &FlashParams,
     ) -> Result<Tensor> {
         let mut x = input_embeds;
-        let cache = &mut self.kv_cache.normal().0;
+        let cache = &mut self.kv_cache.normal()?.0;
         let mask = CausalMasker.make_causal_mask_matrix(
             input_ids,
             metadata
@@ -0,0 +1,478 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+/// Mamba, https://github.com/state-spaces/mamba
+use candle_core::{DType, Device, Module, Result, Tensor, D};
+use candle_nn::{Embedding, Linear};
+use mistralrs_quant::{QuantMethod, QuantizedConfig, ReplicatedLayer, ShardedVarBuilder};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::{
+    amoe::AnyMoeBaseModelMixin,
+    device_map::DeviceMapper,
+    layers::{embedding, linear, MatMul, RmsNorm},
+    layers_ssm::{causal_conv1d, selective_scan, softplus, TimeStepRank},
+    paged_attention::ModelConfigMetadata,
+    pipeline::{
+        extract_logits,
+        text_models_inputs_processor::{FlashParams, PagedAttentionInputMetadata},
+        EitherCache, IsqModel, NormalLoadingMetadata, NormalModel, RecurrentCache,
+        RecurrentLayerCache, RecurrentState,
+    },
+    serde_default_fn,
+    utils::{progress::NiceProgressBar, unvarbuilder::UnVarBuilder},
+};
+
+/// Recurrent models have no positional limit, this only bounds prompts for truncation.
+pub(crate) const MAX_SEQ_LEN: usize = 1 << 20;
+
+serde_default_fn!(usize, state_size, 16);
+serde_default_fn!(usize, expand, 2);
+serde_default_fn!(usize, conv_kernel, 4);
+serde_default_fn!(bool, use_conv_bias, true);
+serde_default_fn!(f64, layer_norm_epsilon, 1e-5);
+serde_default_fn!(bool, tie_word_embeddings, true);
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    pub(crate) vocab_size: usize,
+    pub(crate) hidden_size: usize,
+    pub(crate) num_hidden_layers: usize,
+    #[serde(default = "state_size")]
+    pub(crate) state_size: usize,
+    #[serde(default = "expand")]
+    pub(crate) expand: usize,
+    #[serde(default = "conv_kernel")]
+    pub(crate) conv_kernel: usize,
+    #[serde(default)]
+    pub(crate) time_step_rank: TimeStepRank,
+    #[serde(default)]
+    pub(crate) use_bias: bool,
+    #[serde(default = "use_conv_bias")]
+    pub(crate) use_conv_bias: bool,
+    #[serde(default = "layer_norm_epsilon")]
+    pub(crate) layer_norm_epsilon: f64,
+    #[serde(default = "tie_word_embeddings")]
+    pub(crate) tie_word_embeddings: bool,
+    pub(crate) quantization_config: Option<QuantizedConfig>,
+}
+
+impl Config {
+    pub(crate) fn intermediate_size(&self) -> usize {
+        self.expand * self.hidden_size
+    }
+
+    fn mixer_config(&self) -> MixerConfig {
+        MixerConfig {
+            hidden_size: self.hidden_size,
+            intermediate_size: self.intermediate_size(),
+            state_size: self.state_size,
+            conv_kernel: self.conv_kernel,
+            dt_rank: self.time_step_rank.rank(self.hidden_size),
+            use_bias: self.use_bias,
+            use_conv_bias: self.use_conv_bias,
+            inner_norm_eps: None,
+        }
+    }
+}
+
+/// Matmul with a layer which may need the activations in another dtype.
+pub(crate) fn qmethod_matmul(xs: &Tensor, layer: &dyn QuantMethod) -> Result<Tensor> {
+    let original_dtype = xs.dtype();
+    let mut xs = xs.clone();
+    if let Some(t) = layer.quantized_act_type() {
+        xs = xs.to_dtype(t)?;
+    }
+    let mut res = MatMul.qmethod_matmul(&xs, layer)?;
+    if layer.quantized_act_type().is_some() {
+        res = res.to_dtype(original_dtype)?;
+    }
+    Ok(res)
+}
+
+/// The shape of a Mamba (1) mixer, shared with the SSM layers of Jamba.
+pub(crate) struct MixerConfig {
+    pub(crate) hidden_size: usize,
+    pub(crate) intermediate_size: usize,
+    pub(crate) state_size: usize,
+    pub(crate) conv_kernel: usize,
+    pub(crate) dt_rank: usize,
+    pub(crate) use_bias: bool,
+    pub(crate) use_conv_bias: bool,
+    /// Jamba normalizes the time step, B and C before the scan.
+    pub(crate) inner_norm_eps: Option<f64>,
+}
+
+struct InnerNorms {
+    dt_layernorm: RmsNorm,
+    b_layernorm: RmsNorm,
+    c_layernorm: RmsNorm,
+}
+
+pub(crate) struct MambaMixer {
+    pub(crate) in_proj: Arc<dyn QuantMethod>,
+    conv1d_weight: Tensor,
+    conv1d_bias: Option<Tensor>,
+    pub(crate) x_proj: Arc<dyn QuantMethod>,
+    dt_proj: Linear,
+    a_log: Tensor,
+    d: Tensor,
+    pub(crate) out_proj: Arc<dyn QuantMethod>,
+    inner_norms: Option<InnerNorms>,
+    intermediate_size: usize,
+    state_size: usize,
+    dt_rank: usize,
+}
+
+impl MambaMixer {
+    pub(crate) fn new(
+        cfg: &MixerConfig,
+        quantization_config: &Option<QuantizedConfig>,
+        vb: ShardedVarBuilder,
+    ) -> Result<Self> {
+        let (hidden, inter, state) = (cfg.hidden_size, cfg.intermediate_size, cfg.state_size);
+        let in_proj = ReplicatedLayer::new(
+            hidden,
+            2 * inter,
+            quantization_config,
+            cfg.use_bias,
+            vb.pp("in_proj"),
+        )?;
+        let conv1d_weight = vb.get((inter, 1, cfg.conv_kernel), "conv1d.weight")?;
+        let conv1d_bias = if cfg.use_conv_bias {
+            Some(vb.get(inter, "conv1d.bias")?)
+        } else {
+            None
+        };
+        let x_proj = ReplicatedLayer::new(
+            inter,
+            cfg.dt_rank + 2 * state,
+            quantization_config,
+            false,
+            vb.pp("x_proj"),
+        )?;
+        let dt_proj = linear(cfg.dt_rank, inter, vb.pp("dt_proj"))?;
+        let a_log = vb.get((inter, state), "A_log")?;
+        let d = vb.get(inter, "D")?;
+        let out_proj = ReplicatedLayer::new(
+            inter,
+            hidden,
+            quantization_config,
+            cfg.use_bias,
+            vb.pp("out_proj"),
+        )?;
+        let inner_norms = match cfg.inner_norm_eps {
+            Some(eps) => Some(InnerNorms {
+                dt_layernorm: RmsNorm::new(cfg.dt_rank, eps, vb.pp("dt_layernorm"))?,
+                b_layernorm: RmsNorm::new(state, eps, vb.pp("b_layernorm"))?,
+                c_layernorm: RmsNorm::new(state, eps, vb.pp("c_layernorm"))?,
+            }),
+            None => None,
+        };
+        Ok(Self {
+            in_proj,
+            conv1d_weight,
+            conv1d_bias,
+            x_proj,
+            dt_proj,
+            a_log,
+            d,
+            out_proj,
+            inner_norms,
+            intermediate_size: inter,
+            state_size: state,
+            dt_rank: cfg.dt_rank,
+        })
+    }
+
+    pub(crate) fn forward(
+        &self,
+        xs: &Tensor,
+        state: &mut Option<RecurrentState>,
+    ) -> Result<Tensor> {
+        let inter = self.intermediate_size;
+        let projected = qmethod_matmul(xs, &*self.in_proj)?;
+        let hidden = projected.narrow(D::Minus1, 0, inter)?.transpose(1, 2)?;
+        let gate = projected.narrow(D::Minus1, inter, inter)?;
+
+        let (hidden, conv_state) = causal_conv1d(
+            &hidden,
+            &self.conv1d_weight.squeeze(1)?,
+            self.conv1d_bias.as_ref(),
+            state.as_ref().map(|s| &s.conv_state),
+        )?;
+        let hidden = hidden.silu()?.transpose(1, 2)?.contiguous()?;
+
+        let ssm_params = qmethod_matmul(&hidden, &*self.x_proj)?;
+        let mut dt = ssm_params.narrow(D::Minus1, 0, self.dt_rank)?;
+        let mut b = ssm_params.narrow(D::Minus1, self.dt_rank, self.state_size)?;
+        let mut c =
+            ssm_params.narrow(D::Minus1, self.dt_rank + self.state_size, self.state_size)?;
+        if let Some(norms) = &self.inner_norms {
+            dt = dt.apply(&norms.dt_layernorm)?;
+            b = b.apply(&norms.b_layernorm)?;
+            c = c.apply(&norms.c_layernorm)?;
+        }
+        let dt = softplus(&dt.apply(&self.dt_proj)?.to_dtype(DType::F32)?)?;
+        let a = self.a_log.to_dtype(DType::F32)?.exp()?.neg()?;
+
+        let (ys, ssm_state) = selective_scan(
+            &hidden,
+            &dt,
+            &a,
+            &b.unsqueeze(2)?,
+            &c.unsqueeze(2)?,
+            Some(&self.d),
+            state.as_ref().map(|s| &s.ssm_state),
+        )?;
+        *state = Some(RecurrentState {
+            conv_state,
+            ssm_state,
+        });
+
+        qmethod_matmul(&(ys * gate.silu()?)?, &*self.out_proj)
+    }
+
+    pub(crate) fn residual_tensors(&self, uvb: &UnVarBuilder) {
+        uvb.add_tensor("conv1d.weight", self.conv1d_weight.clone());
+        if let Some(bias) = &self.conv1d_bias {
+            uvb.add_tensor("conv1d.bias", bias.clone());
+        }
+        uvb.pp("dt_proj").add(&self.dt_proj);
+        uvb.add_tensor("A_log", self.a_log.clone());
+        uvb.add_tensor("D", self.d.clone());
+        if let Some(norms) = &self.inner_norms {
+            uvb.pp("dt_layernorm").add(&norms.dt_layernorm);
+            uvb.pp("b_layernorm").add(&norms.b_layernorm);
+            uvb.pp("c_layernorm").add(&norms.c_layernorm);
+        }
+    }
+}
+
+struct MambaBlock {
+    norm: RmsNorm,
+    mixer: MambaMixer,
+}
+
+impl MambaBlock {
+    fn forward(&self, xs: &Tensor, state: &mut Option<RecurrentState>) -> Result<Tensor> {
+        let residual = xs;
+        let xs = self.mixer.forward(&xs.apply(&self.norm)?, state)?;
+        xs + residual
+    }
+}
+
+pub struct Model {
+    embeddings: Embedding,
+    layers: Vec<MambaBlock>,
+    norm_f: RmsNorm,
+    lm_head: Arc<dyn QuantMethod>,
+    device: Device,
+    cache: EitherCache,
+    mapper: Box<dyn DeviceMapper + Send + Sync>,
+    cfg: ModelConfigMetadata,
+}
+
+impl Model {
+    pub fn new(
+        cfg: &Config,
+        vb: ShardedVarBuilder,
+        normal_loading_metadata: NormalLoadingMetadata,
+    ) -> Result<Self> {
+        if let Some(ref quant_cfg) = &cfg.quantization_config {
+            tracing::info!(
+                "Using {} quantization: {}.",
+                quant_cfg.quant_method.to_string(),
+                quant_cfg.get_bits_name(&vb)
+            );
+        }
+        let mapper = normal_loading_metadata.mapper;
+        let vb_m = vb.pp("backbone");
+
+        let embeddings = embedding(
+            cfg.vocab_size,
+            cfg.hidden_size,
+            mapper.set_nm_device(vb_m.pp("embeddings"), false),
+        )?;
+
+        let mixer_cfg = cfg.mixer_config();
+        let mut layers = Vec::with_capacity(cfg.num_hidden_layers);
+        let vb_l = vb_m.pp("layers");
+        for layer_idx in NiceProgressBar::<_, 'b'>(
+            0..cfg.num_hidden_layers,
+            "Loading repeating layers",
+            &normal_loading_metadata.multi_progress,
+        ) {
+            let vb_layer = vb_l.pp(layer_idx);
+            let norm = RmsNorm::new(
+                cfg.hidden_size,
+                cfg.layer_norm_epsilon,
+                mapper.set_device(layer_idx, vb_layer.pp("norm"), false),
+            )?;
+            let mixer = MambaMixer::new(
+                &mixer_cfg,
+                &cfg.quantization_config,
+                mapper.set_device(
+                    layer_idx,
+                    vb_layer.pp("mixer"),
+                    normal_loading_metadata.loading_isq,
+                ),
+            )?;
+            layers.push(MambaBlock { norm, mixer });
+        }
+        let norm_f = RmsNorm::new(
+            cfg.hidden_size,
+            cfg.layer_norm_epsilon,
+            mapper.set_nm_device(vb_m.pp("norm_f"), false),
+        )?;
+        let lm_head = if !cfg.tie_word_embeddings {
+            ReplicatedLayer::new(
+                cfg.hidden_size,
+                cfg.vocab_size,
+                &None,
+                false,
+                mapper.set_nm_device(vb.pp("lm_head"), normal_loading_metadata.loading_isq),
+            )?
+        } else {
+            ReplicatedLayer::from_linear(candle_nn::Linear::new(
+                mapper
+                    .cast_nm_device(embeddings.embeddings(), normal_loading_metadata.loading_isq)?,
+                None,
+            ))?
+        };
+        Ok(Self {
+            embeddings,
+            layers,
+            norm_f,
+            lm_head,
+            device: normal_loading_metadata.real_device,
+            cache: EitherCache::Recurrent(RecurrentCache::new(
+                cfg.num_hidden_layers,
+                &[],
+                MAX_SEQ_LEN,
+            )),
+            cfg: ModelConfigMetadata {
+                max_seq_len: MAX_SEQ_LEN,
+                num_layers: cfg.num_hidden_layers,
+                hidden_size: cfg.hidden_size,
+                // There is no KV cache.
+                num_kv_heads: 0,
+                num_attn_heads: 0,
+                sliding_window: None,
+                k_head_dim: 0,
+                v_head_dim: 0,
+            },
+            mapper,
+        })
+    }
+
+    pub fn forward(&self, input_ids: &Tensor, context_lens: Vec<(usize, usize)>) -> Result<Tensor> {
+        let mut xs = self.embeddings.forward(input_ids)?;
+        let mut cache = self.cache.recurrent()?;
+        for (i, layer) in self.layers.iter().enumerate() {
+            xs = self.mapper.map(xs, i)?;
+            let RecurrentLayerCache::Ssm(state) = &mut cache.0[i] else {
+                candle_core::bail!("Expected a state-space layer cache for layer {i}.");
+            };
+            xs = layer.forward(&xs, state)?;
+        }
+        let xs = xs.to_device(&self.device)?.apply(&self.norm_f)?;
+        extract_logits(&qmethod_matmul(&xs, &*self.lm_head)?, context_lens)
+    }
+}
+
+impl IsqModel for Model {
+    fn get_layers(
+        &mut self,
+    ) -> (
+        Vec<(&mut Arc<dyn QuantMethod>, Option<usize>)>,
+        &dyn DeviceMapper,
+    ) {
+        let mut tensors = Vec::new();
+        tensors.push((&mut self.lm_head, None));
+        for (i, layer) in self.layers.iter_mut().enumerate() {
+            tensors.push((&mut layer.mixer.in_proj, Some(i)));
+            tensors.push((&mut layer.mixer.x_proj, Some(i)));
+            tensors.push((&mut layer.mixer.out_proj, Some(i)));
+        }
+        (tensors, &*self.mapper)
+    }
+
+    fn residual_tensors(&self) -> Vec<(String, Tensor)> {
+        let uvb = UnVarBuilder::new();
+
+        let uvb_m = uvb.pp("backbone");
+        uvb_m.pp("embeddings").add(&self.embeddings);
+        uvb_m.pp("norm_f").add(&self.norm_f);
+
+        for (layer_idx, layer) in self.layers.iter().enumerate() {
+            let uvb_l = uvb_m.pp("layers").pp(layer_idx);
+            uvb_l.pp("norm").add(&layer.norm);
+            layer.mixer.residual_tensors(&uvb_l.pp("mixer"));
+        }
+
+        uvb.to_safetensors()
+    }
+
+    fn imatrix_names(&self) -> candle_core::Result<Vec<Option<String>>> {
+        // NOTE: dependant on the exact implementation in get_layers!
+        let mut names = Vec::new();
+        // lm_head
+        names.push(None);
+        for i in 0..self.layers.len() {
+            names.push(Some(format!("blk.{i}.ssm_in.weight")));
+            names.push(Some(format!("blk.{i}.ssm_x.weight")));
+            names.push(Some(format!("blk.{i}.ssm_out.weight")));
+        }
+        Ok(names)
+    }
+}
+
+impl NormalModel for Model {
+    fn forward(
+        &self,
+        input_ids: &Tensor,
+        _seqlen_offsets: &[usize],
+        context_lens: Vec<(usize, usize)>,
+        _position_ids: Vec<usize>,
+        _metadata: Option<(Vec<(Tensor, Tensor)>, &PagedAttentionInputMetadata)>,
+        _flash_params: &FlashParams,
+    ) -> Result<Tensor> {
+        self.forward(input_ids, context_lens)
+    }
+    fn xlora_forward(
+        &self,
+        _input_ids: &Tensor,
+        _input_ids_full: &Tensor,
+        _seqlen_offsets: &[usize],
+        _seqlen_offsets_full: &[usize],
+        _no_kv_cache: bool,
+        _non_granular_state: &Option<crate::xlora_models::NonGranularState>,
+        _context_lens: Vec<(usize, usize)>,
+        _position_ids: Vec<usize>,
+        _flash_params: &FlashParams,
+        _flash_params_full: &FlashParams,
+    ) -> Result<Tensor> {
+        candle_core::bail!("X-LoRA is not supported for Mamba models.")
+    }
+    fn cache(&self) -> &EitherCache {
+        &self.cache
+    }
+    fn cache_mut(&mut self) -> &mut EitherCache {
+        &mut self.cache
+    }
+    fn device(&self) -> &Device {
+        &self.device
+    }
+    fn is_xlora(&self) -> bool {
+        false
+    }
+    fn max_seq_len(&self) -> usize {
+        MAX_SEQ_LEN
+    }
+    fn config(&self) -> &ModelConfigMetadata {
+        &self.cfg
+    }
+}
+
+impl AnyMoeBaseModelMixin for Model {}
@@ -0,0 +1,468 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+/// Mamba2, https://arxiv.org/abs/2405.21060
+use candle_core::{DType, Device, Module, Result, Tensor, D};
+use candle_nn::Embedding;
+use mistralrs_quant::{QuantMethod, QuantizedConfig, ReplicatedLayer, ShardedVarBuilder};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::{
+    amoe::AnyMoeBaseModelMixin,
+    device_map::DeviceMapper,
+    layers::{embedding, RmsNorm},
+    layers_ssm::{causal_conv1d, selective_scan, softplus},
+    paged_attention::ModelConfigMetadata,
+    pipeline::{
+        extract_logits,
+        text_models_inputs_processor::{FlashParams, PagedAttentionInputMetadata},
+        EitherCache, IsqModel, NormalLoadingMetadata, NormalModel, RecurrentCache,
+        RecurrentLayerCache, RecurrentState,
+    },
+    serde_default_fn,
+    utils::{progress::NiceProgressBar, unvarbuilder::UnVarBuilder},
+};
+
+use super::mamba::{qmethod_matmul, MAX_SEQ_LEN};
+
+serde_default_fn!(usize, state_size, 128);
+serde_default_fn!(usize, n_groups, 8);
+serde_default_fn!(usize, expand, 2);
+serde_default_fn!(usize, conv_kernel, 4);
+serde_default_fn!(bool, use_conv_bias, true);
+serde_default_fn!(f64, layer_norm_epsilon, 1e-5);
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    pub(crate) vocab_size: usize,
+    pub(crate) hidden_size: usize,
+    pub(crate) num_hidden_layers: usize,
+    pub(crate) num_heads: usize,
+    pub(crate) head_dim: usize,
+    #[serde(default = "state_size")]
+    pub(crate) state_size: usize,
+    #[serde(default = "n_groups")]
+    pub(crate) n_groups: usize,
+    #[serde(default = "expand")]
+    pub(crate) expand: usize,
+    #[serde(default = "conv_kernel")]
+    pub(crate) conv_kernel: usize,
+    #[serde(default)]
+    pub(crate) use_bias: bool,
+    #[serde(default = "use_conv_bias")]
+    pub(crate) use_conv_bias: bool,
+    #[serde(default = "layer_norm_epsilon")]
+    pub(crate) layer_norm_epsilon: f64,
+    /// Clamp of the time step after the softplus, an upper bound of `None` is unbounded.
+    pub(crate) time_step_limit: Option<(f64, Option<f64>)>,
+    #[serde(default)]
+    pub(crate) tie_word_embeddings: bool,
+    pub(crate) quantization_config: Option<QuantizedConfig>,
+}
+
+impl Config {
+    /// Parse a `config.json`, which may contain a non-standard `Infinity` in `time_step_limit`.
+    pub(crate) fn from_json(config: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(&config.replace("Infinity", "null"))
+    }
+
+    pub(crate) fn intermediate_size(&self) -> usize {
+        self.expand * self.hidden_size
+    }
+
+    fn conv_dim(&self) -> usize {
+        self.intermediate_size() + 2 * self.n_groups * self.state_size
+    }
+}
+
+/// Expand per-head values (.., heads) to the channels of each head (.., heads * head_dim).
+fn expand_heads(xs: &Tensor, head_dim: usize) -> Result<Tensor> {
+    let mut dims = xs.dims().to_vec();
+    let heads = dims.pop().unwrap();
+    let mut expanded = dims.clone();
+    expanded.extend([heads, head_dim]);
+    dims.push(heads * head_dim);
+    xs.unsqueeze(D::Minus1)?
+        .broadcast_as(expanded)?
+        .reshape(dims)
+}
+
+struct Mixer {
+    in_proj: Arc<dyn QuantMethod>,
+    conv1d_weight: Tensor,
+    conv1d_bias: Option<Tensor>,
+    dt_bias: Tensor,
+    a_log: Tensor,
+    d: Tensor,
+    norm: RmsNorm,
+    out_proj: Arc<dyn QuantMethod>,
+    intermediate_size: usize,
+    conv_dim: usize,
+    num_heads: usize,
+    head_dim: usize,
+    n_groups: usize,
+    state_size: usize,
+    time_step_limit: Option<(f64, Option<f64>)>,
+    norm_eps: f64,
+}
+
+impl Mixer {
+    fn new(cfg: &Config, vb: ShardedVarBuilder) -> Result<Self> {
+        let (inter, conv_dim, heads) = (cfg.intermediate_size(), cfg.conv_dim(), cfg.num_heads);
+        let in_proj = ReplicatedLayer::new(
+            cfg.hidden_size,
+            inter + conv_dim + heads,
+            &cfg.quantization_config,
+            cfg.use_bias,
+            vb.pp("in_proj"),
+        )?;
+        let conv1d_weight = vb.get((conv_dim, 1, cfg.conv_kernel), "conv1d.weight")?;
+        let conv1d_bias = if cfg.use_conv_bias {
+            Some(vb.get(conv_dim, "conv1d.bias")?)
+        } else {
+            None
+        };
+        let out_proj = ReplicatedLayer::new(
+            inter,
+            cfg.hidden_size,
+            &cfg.quantization_config,
+            cfg.use_bias,
+            vb.pp("out_proj"),
+        )?;
+        Ok(Self {
+            in_proj,
+            conv1d_weight,
+            conv1d_bias,
+            dt_bias: vb.get(heads, "dt_bias")?,
+            a_log: vb.get(heads, "A_log")?,
+            d: vb.get(heads, "D")?,
+            norm: RmsNorm::new(inter, cfg.layer_norm_epsilon, vb.pp("norm"))?,
+            out_proj,
+            intermediate_size: inter,
+            conv_dim,
+            num_heads: heads,
+            head_dim: cfg.head_dim,
+            n_groups: cfg.n_groups,
+            state_size: cfg.state_size,
+            time_step_limit: cfg.time_step_limit,
+            norm_eps: cfg.layer_norm_epsilon,
+        })
+    }
+
+    /// `norm(ys * silu(gate))`, normalizing each group of channels separately.
+    fn gated_norm(&self, ys: &Tensor, gate: &Tensor) -> Result<Tensor> {
+        let (b_sz, seq_len, dim) = ys.dims3()?;
+        let xs = (ys * gate.silu()?)?.to_dtype(DType::F32)?.reshape((
+            b_sz,
+            seq_len,
+            self.n_groups,
+            dim / self.n_groups,
+        ))?;
+        let variance = xs.sqr()?.mean_keepdim(D::Minus1)?;
+        xs.broadcast_div(&(variance + self.norm_eps)?.sqrt()?)?
+            .reshape((b_sz, seq_len, dim))?
+            .to_dtype(ys.dtype())?
+            .broadcast_mul(self.norm.weight())
+    }
+
+    fn forward(&self, xs: &Tensor, state: &mut Option<RecurrentState>) -> Result<Tensor> {
+        let (b_sz, seq_len, _) = xs.dims3()?;
+        let (inter, groups, n) = (self.intermediate_size, self.n_groups, self.state_size);
+        let projected = qmethod_matmul(xs, &*self.in_proj)?;
+        let gate = projected.narrow(D::Minus1, 0, inter)?;
+        let xbc = projected
+            .narrow(D::Minus1, inter, self.conv_dim)?
+            .transpose(1, 2)?;
+        let dt = projected.narrow(D::Minus1, inter + self.conv_dim, self.num_heads)?;
+
+        let (xbc, conv_state) = causal_conv1d(
+            &xbc,
+            &self.conv1d_weight.squeeze(1)?,
+            self.conv1d_bias.as_ref(),
+            state.as_ref().map(|s| &s.conv_state),
+        )?;
+        let xbc = xbc.silu()?.transpose(1, 2)?.contiguous()?;
+        let hidden = xbc.narrow(D::Minus1, 0, inter)?;
+        let b = xbc
+            .narrow(D::Minus1, inter, groups * n)?
+            .reshape((b_sz, seq_len, groups, n))?;
+        let c = xbc
+            .narrow(D::Minus1, inter + groups * n, groups * n)?
+            .reshape((b_sz, seq_len, groups, n))?;
+
+        let mut dt = softplus(
+            &dt.to_dtype(DType::F32)?
+                .broadcast_add(&self.dt_bias.to_dtype(DType::F32)?)?,
+        )?;
+        if let Some((min, max)) = self.time_step_limit {
+            dt = dt.clamp(min, max.unwrap_or(f64::INFINITY))?;
+        }
+        let dt = expand_heads(&dt, self.head_dim)?;
+        let a = expand_heads(
+            &self.a_log.to_dtype(DType::F32)?.exp()?.neg()?,
+            self.head_dim,
+        )?
+        .unsqueeze(1)?
+        .broadcast_as((inter, n))?
+        .contiguous()?;
+        let d = expand_heads(&self.d, self.head_dim)?;
+
+        let (ys, ssm_state) = selective_scan(
+            &hidden,
+            &dt,
+            &a,
+            &b,
+            &c,
+            Some(&d),
+            state.as_ref().map(|s| &s.ssm_state),
+        )?;
+        *state = Some(RecurrentState {
+            conv_state,
+            ssm_state,
+        });
+
+        qmethod_matmul(&self.gated_norm(&ys, &gate)?, &*self.out_proj)
+    }
+
+    fn residual_tensors(&self, uvb: &UnVarBuilder) {
+        uvb.add_tensor("conv1d.weight", self.conv1d_weight.clone());
+        if let Some(bias) = &self.conv1d_bias {
+            uvb.add_tensor("conv1d.bias", bias.clone());
+        }
+        uvb.add_tensor("dt_bias", self.dt_bias.clone());
+        uvb.add_tensor("A_log", self.a_log.clone());
+        uvb.add_tensor("D", self.d.clone());
+        uvb.pp("norm").add(&self.norm);
+    }
+}
+
+struct Block {
+    norm: RmsNorm,
+    mixer: Mixer,
+}
+
+impl Block {
+    fn forward(&self, xs: &Tensor, state: &mut Option<RecurrentState>) -> Result<Tensor> {
+        let residual = xs;
+        let xs = self.mixer.forward(&xs.apply(&self.norm)?, state)?;
+        xs + residual
+    }
+}
+
+pub struct Model {
+    embeddings: Embedding,
+    layers: Vec<Block>,
+    norm_f: RmsNorm,
+    lm_head: Arc<dyn QuantMethod>,
+    device: Device,
+    cache: EitherCache,
+    mapper: Box<dyn DeviceMapper + Send + Sync>,
+    cfg: ModelConfigMetadata,
+}
+
+impl Model {
+    pub fn new(
+        cfg: &Config,
+        vb: ShardedVarBuilder,
+        normal_loading_metadata: NormalLoadingMetadata,
+    ) -> Result<Self> {
+        if let Some(ref quant_cfg) = &cfg.quantization_config {
+            tracing::info!(
+                "Using {} quantization: {}.",
+                quant_cfg.quant_method.to_string(),
+                quant_cfg.get_bits_name(&vb)
+            );
+        }
+        if cfg.num_heads * cfg.head_dim != cfg.intermediate_size() {
+            candle_core::bail!(
+                "Expected num_heads * head_dim ({}) to equal expand * hidden_size ({}).",
+                cfg.num_heads * cfg.head_dim,
+                cfg.intermediate_size()
+            );
+        }
+        let mapper = normal_loading_metadata.mapper;
+        let vb_m = vb.pp("backbone");
+
+        let embeddings = embedding(
+            cfg.vocab_size,
+            cfg.hidden_size,
+            mapper.set_nm_device(vb_m.pp("embeddings"), false),
+        )?;
+
+        let mut layers = Vec::with_capacity(cfg.num_hidden_layers);
+        let vb_l = vb_m.pp("layers");
+        for layer_idx in NiceProgressBar::<_, 'b'>(
+            0..cfg.num_hidden_layers,
+            "Loading repeating layers",
+            &normal_loading_metadata.multi_progress,
+        ) {
+            let vb_layer = vb_l.pp(layer_idx);
+            let norm = RmsNorm::new(
+                cfg.hidden_size,
+                cfg.layer_norm_epsilon,
+                mapper.set_device(layer_idx, vb_layer.pp("norm"), false),
+            )?;
+            let mixer = Mixer::new(
+                cfg,
+                mapper.set_device(
+                    layer_idx,
+                    vb_layer.pp("mixer"),
+                    normal_loading_metadata.loading_isq,
+                ),
+            )?;
+            layers.push(Block { norm, mixer });
+        }
+        let norm_f = RmsNorm::new(
+            cfg.hidden_size,
+            cfg.layer_norm_epsilon,
+            mapper.set_nm_device(vb_m.pp("norm_f"), false),
+        )?;
+        let lm_head = if !cfg.tie_word_embeddings {
+            ReplicatedLayer::new(
+                cfg.hidden_size,
+                cfg.vocab_size,
+                &None,
+                false,
+                mapper.set_nm_device(vb.pp("lm_head"), normal_loading_metadata.loading_isq),
+            )?
+        } else {
+            ReplicatedLayer::from_linear(candle_nn::Linear::new(
+                mapper
+                    .cast_nm_device(embeddings.embeddings(), normal_loading_metadata.loading_isq)?,
+                None,
+            ))?
+        };
+        Ok(Self {
+            embeddings,
+            layers,
+            norm_f,
+            lm_head,
+            device: normal_loading_metadata.real_device,
+            cache: EitherCache::Recurrent(RecurrentCache::new(
+                cfg.num_hidden_layers,
+                &[],
+                MAX_SEQ_LEN,
+            )),
+            cfg: ModelConfigMetadata {
+                max_seq_len: MAX_SEQ_LEN,
+                num_layers: cfg.num_hidden_layers,
+                hidden_size: cfg.hidden_size,
+                // There is no KV cache.
+                num_kv_heads: 0,
+                num_attn_heads: 0,
+                sliding_window: None,
+                k_head_dim: 0,
+                v_head_dim: 0,
+            },
+            mapper,
+        })
+    }
+
+    pub fn forward(&self, input_ids: &Tensor, context_lens: Vec<(usize, usize)>) -> Result<Tensor> {
+        let mut xs = self.embeddings.forward(input_ids)?;
+        let mut cache = self.cache.recurrent()?;
+        for (i, layer) in self.layers.iter().enumerate() {
+            xs = self.mapper.map(xs, i)?;
+            let RecurrentLayerCache::Ssm(state) = &mut cache.0[i] else {
+                candle_core::bail!("Expected a state-space layer cache for layer {i}.");
+            };
+            xs = layer.forward(&xs, state)?;
+        }
+        let xs = xs.to_device(&self.device)?.apply(&self.norm_f)?;
+        extract_logits(&qmethod_matmul(&xs, &*self.lm_head)?, context_lens)
+    }
+}
+
+impl IsqModel for Model {
+    fn get_layers(
+        &mut self,
+    ) -> (
+        Vec<(&mut Arc<dyn QuantMethod>, Option<usize>)>,
+        &dyn DeviceMapper,
+    ) {
+        let mut tensors = Vec::new();
+        tensors.push((&mut self.lm_head, None));
+        for (i, layer) in self.layers.iter_mut().enumerate() {
+            tensors.push((&mut layer.mixer.in_proj, Some(i)));
+            tensors.push((&mut layer.mixer.out_proj, Some(i)));
+        }
+        (tensors, &*self.mapper)
+    }
+
+    fn residual_tensors(&self) -> Vec<(String, Tensor)> {
+        let uvb = UnVarBuilder::new();
+
+        let uvb_m = uvb.pp("backbone");
+        uvb_m.pp("embeddings").add(&self.embeddings);
+        uvb_m.pp("norm_f").add(&self.norm_f);
+
+        for (layer_idx, layer) in self.layers.iter().enumerate() {
+            let uvb_l = uvb_m.pp("layers").pp(layer_idx);
+            uvb_l.pp("norm").add(&layer.norm);
+            layer.mixer.residual_tensors(&uvb_l.pp("mixer"));
+        }
+
+        uvb.to_safetensors()
+    }
+
+    fn imatrix_names(&self) -> candle_core::Result<Vec<Option<String>>> {
+        // NOTE: dependant on the exact implementation in get_layers!
+        let mut names = Vec::new();
+        // lm_head
+        names.push(None);
+        for i in 0..self.layers.len() {
+            names.push(Some(format!("blk.{i}.ssm_in.weight")));
+            names.push(Some(format!("blk.{i}.ssm_out.weight")));
+        }
+        Ok(names)
+    }
+}
+
+impl NormalModel for Model {
+    fn forward(
+        &self,
+        input_ids: &Tensor,
+        _seqlen_offsets: &[usize],
+        context_lens: Vec<(usize, usize)>,
+        _position_ids: Vec<usize>,
+        _metadata: Option<(Vec<(Tensor, Tensor)>, &PagedAttentionInputMetadata)>,
+        _flash_params: &FlashParams,
+    ) -> Result<Tensor> {
+        self.forward(input_ids, context_lens)
+    }
+    fn xlora_forward(
+        &self,
+        _input_ids: &Tensor,
+        _input_ids_full: &Tensor,
+        _seqlen_offsets: &[usize],
+        _seqlen_offsets_full: &[usize],
+        _no_kv_cache: bool,
+        _non_granular_state: &Option<crate::xlora_models::NonGranularState>,
+        _context_lens: Vec<(usize, usize)>,
+        _position_ids: Vec<usize>,
+        _flash_params: &FlashParams,
+        _flash_params_full: &FlashParams,
+    ) -> Result<Tensor> {
+        candle_core::bail!("X-LoRA is not supported for Mamba2 models.")
+    }
+    fn cache(&self) -> &EitherCache {
+        &self.cache
+    }
+    fn cache_mut(&mut self) -> &mut EitherCache {
+        &mut self.cache
+    }
+    fn device(&self) -> &Device {
+        &self.device
+    }
+    fn is_xlora(&self) -> bool {
+        false
+    }
+    fn max_seq_len(&self) -> usize {
+        MAX_SEQ_LEN
+    }
+    fn config(&self) -> &ModelConfigMetadata {
+        &self.cfg
+    }
+}
+
+impl AnyMoeBaseModelMixin for Model {}
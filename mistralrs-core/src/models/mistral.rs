@@ -521,7 +521,7 @@ impl Model {
         flash_params: &FlashParams,
     ) -> Result<Tensor> {
         let mut xs = input_embeds;
-        let cache = &mut self.cache.normal().0;
+        let cache = &mut self.cache.normal()?.0;
         let attention_mask = CausalMasker.make_sliding_window_causal_mask_matrix(
             input_ids,
             metadata
@@ -0,0 +1,757 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use candle_core::quantized::QTensor;
+use candle_core::{DType, Device, IndexOp, Result, Tensor, D};
+use candle_nn::{Embedding, Module};
+use indicatif::MultiProgress;
+use mistralrs_quant::{GgufMatMul, QuantMethod, QuantMethodConfig};
+
+use crate::attention::SdpaParams;
+use crate::device_map::DeviceMapper;
+use crate::gguf::Content;
+use crate::layers::{
+    CausalMasker, DeepSeekV2RopeConfig, DeepSeekV2RopeScaling, DeepSeekV2RotaryEmbedding, MatMul,
+    QRmsNorm, ScaledRopeType, Sdpa,
+};
+use crate::layers_masker::PastKvLenCache;
+use crate::ops::{BincountOp, NonZeroOp, SplitOp, TopKLastDimOp};
+use crate::paged_attention::{AttentionImplementation, PagedAttention};
+use crate::pipeline::text_models_inputs_processor::PagedAttentionInputMetadata;
+use crate::pipeline::{extract_logits, EitherCache, KvCache, NormalCache};
+use crate::utils::gguf_metadata::ContentMetadata;
+use crate::utils::model_config as ModelConfig;
+use crate::utils::progress::NiceProgressBar;
+const MAX_SEQ_LEN: u32 = 4096;
+
+fn gguf_linear(q_weight: QTensor) -> Result<Arc<dyn QuantMethod>> {
+    Ok(Arc::new(GgufMatMul::new(QuantMethodConfig::Gguf {
+        q_weight: Arc::new(q_weight),
+        b: None,
+    })?))
+}
+
+struct Mlp {
+    feed_forward_w1: Arc<dyn QuantMethod>,
+    feed_forward_w2: Arc<dyn QuantMethod>,
+    feed_forward_w3: Arc<dyn QuantMethod>,
+}
+
+impl Mlp {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let w1 = MatMul.qmethod_matmul(xs, &*self.feed_forward_w1)?;
+        let w3 = MatMul.qmethod_matmul(xs, &*self.feed_forward_w3)?;
+        let y = &(candle_nn::ops::silu(&w1)? * w3)?;
+        MatMul.qmethod_matmul(y, &*self.feed_forward_w2)
+    }
+}
+
+/// Value of `deepseek2.expert_gating_func`.
+#[derive(Clone, Copy)]
+enum GatingFunc {
+    Softmax,
+    Sigmoid,
+}
+
+struct Moe {
+    feed_forward_gate_inp: Arc<dyn QuantMethod>,
+    /// Routing bias of DeepSeek V3, only used to choose the experts.
+    exp_probs_b: Option<Tensor>,
+    experts: Vec<Mlp>,
+    shared_experts: Option<Mlp>,
+    n_expert_used: usize,
+    gating_func: GatingFunc,
+    norm_topk_prob: bool,
+    routed_scaling_factor: f64,
+    n_group: usize,
+    topk_group: usize,
+}
+
+impl Moe {
+    /// (topk_idx, topk_weight)
+    fn route(&self, xs: &Tensor) -> Result<(Tensor, Tensor)> {
+        let n_tokens = xs.dim(0)?;
+        let router_logits = MatMul
+            .qmethod_matmul(xs, &*self.feed_forward_gate_inp)?
+            .to_dtype(DType::F32)?;
+        let scores = match self.gating_func {
+            GatingFunc::Softmax => candle_nn::ops::softmax_last_dim(&router_logits)?,
+            GatingFunc::Sigmoid => candle_nn::ops::sigmoid(&router_logits)?,
+        };
+        let mut scores_for_choice = match &self.exp_probs_b {
+            Some(bias) => scores.broadcast_add(&bias.unsqueeze(0)?)?,
+            None => scores.clone(),
+        };
+
+        if self.n_group > 1 {
+            // (n, n_group)
+            let grouped = scores_for_choice.reshape((n_tokens, self.n_group, ()))?;
+            let group_scores = if self.exp_probs_b.is_some() {
+                grouped.contiguous()?.topk(2)?.values.sum(D::Minus1)?
+            } else {
+                grouped.max(D::Minus1)?
+            };
+            // (n, topk_group)
+            let group_idx = group_scores.contiguous()?.topk(self.topk_group)?.indices;
+            // (n, n_group)
+            let group_mask = group_scores.zeros_like()?.scatter_add(
+                &group_idx,
+                &group_idx.ones_like()?.to_dtype(group_scores.dtype())?,
+                1,
+            )?;
+            // (n, e)
+            let score_mask = group_mask
+                .unsqueeze(D::Minus1)?
+                .broadcast_as(grouped.shape())?
+                .reshape((n_tokens, ()))?;
+            scores_for_choice = scores_for_choice.broadcast_mul(&score_mask)?;
+        }
+
+        let topk_idx = scores_for_choice
+            .contiguous()?
+            .topk(self.n_expert_used)?
+            .indices;
+        let mut topk_weight = scores.gather(&topk_idx, D::Minus1)?;
+        if self.norm_topk_prob {
+            let denominator = (topk_weight.sum_keepdim(D::Minus1)? + 1e-20)?;
+            topk_weight = topk_weight.broadcast_div(&denominator)?;
+        }
+        topk_weight = (topk_weight * self.routed_scaling_factor)?;
+        Ok((topk_idx, topk_weight))
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let (b_size, seq_len, hidden_dim) = xs.dims3()?;
+        let identity = xs;
+        let xs = xs.reshape(((), hidden_dim))?;
+        let (topk_idx, topk_weight) = self.route(&xs)?;
+
+        let mut ys = xs.zeros_like()?;
+        let counts = topk_idx
+            .flatten_all()?
+            .bincount(self.experts.len() as u32)?;
+        for (expert_idx, count) in counts.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let idx_top = topk_idx.eq(expert_idx as f64)?.nonzero()?.t()?;
+            let idx = &idx_top.i(0)?.contiguous()?;
+            let top = &idx_top.i(1)?.contiguous()?;
+
+            let routing_weights = topk_weight
+                .index_select(idx, 0)?
+                .gather(&top.unsqueeze(1)?, 1)?
+                .to_dtype(xs.dtype())?;
+            let current_hidden_states = self.experts[expert_idx]
+                .forward(&xs.index_select(idx, 0)?)?
+                .broadcast_mul(&routing_weights)?;
+            ys = ys.index_add(idx, &current_hidden_states, 0)?;
+        }
+
+        let mut ys = ys.reshape((b_size, seq_len, hidden_dim))?;
+        if let Some(shared_experts) = &self.shared_experts {
+            ys = (ys + shared_experts.forward(identity)?)?;
+        }
+        Ok(ys)
+    }
+}
+
+enum MlpOrMoe {
+    Mlp(Mlp),
+    MoE(Moe),
+}
+
+impl MlpOrMoe {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        match self {
+            Self::Mlp(mlp) => mlp.forward(xs),
+            Self::MoE(moe) => moe.forward(xs),
+        }
+    }
+}
+
+enum QProj {
+    Plain(Arc<dyn QuantMethod>),
+    Lora {
+        a: Arc<dyn QuantMethod>,
+        norm: QRmsNorm,
+        b: Arc<dyn QuantMethod>,
+    },
+}
+
+impl QProj {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        match self {
+            Self::Plain(w) => MatMul.qmethod_matmul(xs, &**w),
+            Self::Lora { a, norm, b } => {
+                let xs = norm.forward(&MatMul.qmethod_matmul(xs, &**a)?)?;
+                MatMul.qmethod_matmul(&xs, &**b)
+            }
+        }
+    }
+}
+
+struct LayerWeights {
+    attention_q: QProj,
+    attention_kv_a_mqa: Arc<dyn QuantMethod>,
+    attention_kv_a_norm: QRmsNorm,
+    attention_kv_b: Arc<dyn QuantMethod>,
+    attention_wo: Arc<dyn QuantMethod>,
+    attention_norm: QRmsNorm,
+    mlp_or_moe: MlpOrMoe,
+    ffn_norm: QRmsNorm,
+    n_head: usize,
+    qk_nope_head_dim: usize,
+    qk_rope_head_dim: usize,
+    v_head_dim: usize,
+    kv_lora_rank: usize,
+    rotary: Arc<DeepSeekV2RotaryEmbedding>,
+    paged_attn: Option<PagedAttention>,
+    sdpa_params: SdpaParams,
+    dtype: DType,
+}
+
+impl LayerWeights {
+    fn forward_attn(
+        &self,
+        x: &Tensor,
+        mask: Option<&Tensor>,
+        start_offsets: &[usize],
+        kv_cache: &mut KvCache,
+        metadata: Option<((Tensor, Tensor), &PagedAttentionInputMetadata)>,
+    ) -> Result<Tensor> {
+        let (b_sz, seq_len, _) = x.dims3()?;
+        let q_head_dim = self.qk_nope_head_dim + self.qk_rope_head_dim;
+
+        let q = self
+            .attention_q
+            .forward(x)?
+            .to_dtype(self.dtype)?
+            .reshape((b_sz, seq_len, self.n_head, q_head_dim))?
+            .transpose(1, 2)?;
+        let q_split = q.split(&[self.qk_nope_head_dim, self.qk_rope_head_dim], D::Minus1)?;
+        let q_nope = &q_split[0];
+        let q_pe = &q_split[1];
+
+        // Latent KV and the shared RoPE key
+        let compressed_kv = MatMul.qmethod_matmul(x, &*self.attention_kv_a_mqa)?;
+        let ckv_split =
+            compressed_kv.split(&[self.kv_lora_rank, self.qk_rope_head_dim], D::Minus1)?;
+        let k_pe = ckv_split[1]
+            .to_dtype(self.dtype)?
+            .reshape((b_sz, seq_len, 1, self.qk_rope_head_dim))?
+            .transpose(1, 2)?;
+        let kv = MatMul
+            .qmethod_matmul(
+                &self.attention_kv_a_norm.forward(&ckv_split[0])?,
+                &*self.attention_kv_b,
+            )?
+            .to_dtype(self.dtype)?
+            .reshape((
+                b_sz,
+                seq_len,
+                self.n_head,
+                self.qk_nope_head_dim + self.v_head_dim,
+            ))?
+            .transpose(1, 2)?;
+        let kv_split = kv.split(&[self.qk_nope_head_dim, self.v_head_dim], D::Minus1)?;
+        let k_nope = &kv_split[0];
+        let v = kv_split[1].contiguous()?;
+
+        let (q_pe, k_pe) = self.rotary.forward(q_pe, &k_pe, start_offsets)?;
+
+        let q = Tensor::cat(&[q_nope, &q_pe], D::Minus1)?.contiguous()?;
+        let k_pe = k_pe.repeat((1, self.n_head, 1, 1))?;
+        let k = Tensor::cat(&[k_nope, &k_pe], D::Minus1)?.contiguous()?;
+
+        let y = match &self.paged_attn {
+            Some(paged_attn) => {
+                let ((key_cache, value_cache), input_metadata) = metadata.unwrap();
+                // The paged cache stores keys and values with the same head size.
+                let v = v
+                    .pad_with_zeros(D::Minus1, 0, q_head_dim - self.v_head_dim)?
+                    .contiguous()?;
+                paged_attn
+                    .forward(
+                        &q,
+                        &k,
+                        &v,
+                        mask,
+                        Some(key_cache),
+                        Some(value_cache),
+                        input_metadata,
+                        &self.sdpa_params,
+                        None,
+                    )?
+                    .narrow(D::Minus1, 0, self.v_head_dim)?
+            }
+            None => {
+                let (k, v) = kv_cache.append(&k, &v)?;
+
+                Sdpa.run_attention(&q, &k, &v, mask, None, &self.sdpa_params)?
+            }
+        };
+
+        let y = if mask.is_some() {
+            y.transpose(1, 2)?.reshape((b_sz, seq_len, ()))?
+        } else {
+            y.reshape((b_sz, seq_len, ()))?
+        };
+
+        let y = MatMul.qmethod_matmul(&y.to_dtype(x.dtype())?, &*self.attention_wo)?;
+        Ok(y)
+    }
+}
+
+pub struct ModelWeights {
+    tok_embeddings: Embedding,
+    layers: Vec<LayerWeights>,
+    norm: QRmsNorm,
+    output: Arc<dyn QuantMethod>,
+    pub device: Device,
+    pub cache: EitherCache,
+    pub max_seq_len: usize,
+    mapper: Option<Box<dyn DeviceMapper + Send + Sync>>,
+    dtype: DType,
+}
+
+// deepseek2 `llm` fields, shared by DeepSeek V2 and V3:
+// https://github.com/ggerganov/ggml/blob/master/docs/gguf.md#llm
+// NOTE: Types here do not match spec
+pub(crate) struct PropsGGUF {
+    pub head_count: usize,
+    pub block_count: usize,
+    pub embedding_length: usize,
+    pub rms_norm_eps: f32,
+    pub max_seq_len: usize,
+    pub rope_freq_base: f32,
+    pub rope_dim: usize,
+    pub key_length: usize,
+    pub value_length: usize,
+    pub q_lora_rank: Option<usize>,
+    pub kv_lora_rank: usize,
+    pub leading_dense_block_count: usize,
+    pub n_expert: usize,
+    pub n_expert_used: usize,
+    pub n_expert_shared: usize,
+    pub n_expert_groups: usize,
+    pub n_expert_groups_used: usize,
+    pub expert_weights_scale: f32,
+    pub expert_weights_norm: bool,
+    pub expert_gating_func: u32,
+    pub rope_scaling: Option<DeepSeekV2RopeScaling>,
+}
+
+impl TryFrom<ContentMetadata<'_>> for PropsGGUF {
+    type Error = anyhow::Error;
+
+    fn try_from(c: ContentMetadata) -> std::result::Result<Self, Self::Error> {
+        c.verify_arch("deepseek2")?;
+
+        let required = [
+            "attention.head_count",
+            "block_count",
+            "embedding_length",
+            "attention.layer_norm_rms_epsilon",
+            "attention.kv_lora_rank",
+            "rope.dimension_count",
+        ];
+        c.has_required_keys(&required)?;
+
+        // Files converted for llama.cpp's MLA kernels store the latent sizes in `key_length`
+        // and the per-head sizes in `key_length_mla`.
+        let key_length = match c.get_option_value::<u32>("attention.key_length_mla")? {
+            Some(x) => x,
+            None => c.get_value::<u32>("attention.key_length")?,
+        } as usize;
+        let value_length = match c.get_option_value::<u32>("attention.value_length_mla")? {
+            Some(x) => x,
+            None => c.get_value::<u32>("attention.value_length")?,
+        } as usize;
+
+        let rope_scaling = match c
+            .get_option_value::<String>("rope.scaling.type")?
+            .as_deref()
+        {
+            None | Some("none") => None,
+            Some("yarn") => {
+                // `yarn_log_multiplier` is `0.1 * mscale_all_dim`.
+                let mscale_all_dim = c
+                    .get_option_value::<f32>("rope.scaling.yarn_log_multiplier")?
+                    .unwrap_or(0.)
+                    * 10.;
+                Some(DeepSeekV2RopeScaling::Yarn {
+                    original_max_position_embeddings: c
+                        .get_value::<u32>("rope.scaling.original_context_length")?
+                        as usize,
+                    beta_fast: 32.,
+                    beta_slow: 1.,
+                    mscale: mscale_all_dim,
+                    mscale_all_dim,
+                    factor: c.get_value::<f32>("rope.scaling.factor")?,
+                    scaling_type: ScaledRopeType::Yarn,
+                })
+            }
+            Some(other) => anyhow::bail!("Unsupported DeepSeek RoPE scaling type `{other}`"),
+        };
+
+        // NOTE: Values are not aligned with GGUFv3 types
+        // TODO: Normalize value types to spec
+        let props = Self {
+            head_count: c.get_value::<u32>("attention.head_count")? as usize,
+            block_count: c.get_value::<u32>("block_count")? as usize,
+            embedding_length: c.get_value::<u32>("embedding_length")? as usize,
+            rms_norm_eps: c.get_value("attention.layer_norm_rms_epsilon")?,
+            max_seq_len: c
+                .get_value::<u64>("context_length")
+                .ok()
+                .unwrap_or(MAX_SEQ_LEN as u64) as usize,
+            rope_freq_base: c.get_value("rope.freq_base").ok().unwrap_or(10_000_f32),
+            rope_dim: c.get_value::<u32>("rope.dimension_count")? as usize,
+            key_length,
+            value_length,
+            q_lora_rank: c
+                .get_option_value::<u32>("attention.q_lora_rank")?
+                .filter(|x| *x > 0)
+                .map(|x| x as usize),
+            kv_lora_rank: c.get_value::<u32>("attention.kv_lora_rank")? as usize,
+            leading_dense_block_count: c
+                .get_option_value::<u32>("leading_dense_block_count")?
+                .unwrap_or(0) as usize,
+            n_expert: c.get_option_value::<u32>("expert_count")?.unwrap_or(0) as usize,
+            n_expert_used: c.get_option_value::<u32>("expert_used_count")?.unwrap_or(0) as usize,
+            n_expert_shared: c
+                .get_option_value::<u32>("expert_shared_count")?
+                .unwrap_or(0) as usize,
+            n_expert_groups: c
+                .get_option_value::<u32>("expert_group_count")?
+                .unwrap_or(1) as usize,
+            n_expert_groups_used: c
+                .get_option_value::<u32>("expert_group_used_count")?
+                .unwrap_or(1) as usize,
+            expert_weights_scale: c.get_option_value("expert_weights_scale")?.unwrap_or(1.),
+            expert_weights_norm: c.get_option_value("expert_weights_norm")?.unwrap_or(false),
+            // DeepSeek V2 files predate this key and always use softmax.
+            expert_gating_func: c
+                .get_option_value::<u32>("expert_gating_func")?
+                .unwrap_or(1),
+            rope_scaling,
+        };
+
+        Ok(props)
+    }
+}
+
+impl ModelConfig::FromGGUF for ModelWeights {
+    fn from_gguf<R: std::io::Seek + std::io::Read>(
+        mut ct: Content<'_, R>,
+        device: &Device,
+        mapper: Box<dyn DeviceMapper + Send + Sync>,
+        attention_mechanism: AttentionImplementation,
+        dtype: DType,
+    ) -> Result<Self> {
+        // Parameter extraction from metadata.
+        let metadata = ContentMetadata {
+            path_prefix: "deepseek2",
+            metadata: ct.get_metadata(),
+        };
+        let PropsGGUF {
+            head_count,
+            block_count,
+            embedding_length,
+            rms_norm_eps,
+            max_seq_len,
+            rope_freq_base,
+            rope_dim,
+            key_length,
+            value_length,
+            q_lora_rank,
+            kv_lora_rank,
+            leading_dense_block_count,
+            n_expert,
+            n_expert_used,
+            n_expert_shared,
+            n_expert_groups,
+            n_expert_groups_used,
+            expert_weights_scale,
+            expert_weights_norm,
+            expert_gating_func,
+            rope_scaling,
+        } = PropsGGUF::try_from(metadata).or_else(|err| candle_core::bail!("{err}"))?;
+
+        let gating_func = match expert_gating_func {
+            1 => GatingFunc::Softmax,
+            2 => GatingFunc::Sigmoid,
+            other => candle_core::bail!("Unsupported DeepSeek expert gating function `{other}`"),
+        };
+
+        let q_head_dim = key_length;
+        let qk_nope_head_dim = q_head_dim - rope_dim;
+        let v_head_dim = value_length;
+
+        let mut softmax_scale = 1.0 / (q_head_dim as f32).sqrt();
+        if let Some(DeepSeekV2RopeScaling::Yarn {
+            mscale_all_dim,
+            factor,
+            ..
+        }) = rope_scaling
+        {
+            let mscale = DeepSeekV2RotaryEmbedding::yarn_get_mscale(factor, mscale_all_dim);
+            softmax_scale = softmax_scale * mscale * mscale;
+        }
+
+        let qtok_embeddings = ct.tensor("token_embd.weight", device)?;
+        let tok_embeddings = qtok_embeddings.dequantize(device)?;
+        let norm = QRmsNorm::new(ct.tensor("output_norm.weight", device)?, rms_norm_eps)?;
+        let output = if !ct.has_tensor("output.weight") {
+            ct.tensor("token_embd.weight", device)?
+        } else {
+            ct.tensor("output.weight", device)?
+        };
+        let mut layers = Vec::with_capacity(block_count);
+
+        let rope_cfg = DeepSeekV2RopeConfig {
+            rope_scaling,
+            max_position_embeddings: max_seq_len,
+            rope_theta: rope_freq_base,
+            qk_rope_head_dim: rope_dim,
+        };
+        let mut ropes = HashMap::new();
+        for layer_idx in 0..block_count {
+            let device = mapper.device_for(layer_idx, false).unwrap_or(device);
+            ropes.insert(
+                device.location(),
+                Arc::new(DeepSeekV2RotaryEmbedding::new(&rope_cfg, dtype, device)?),
+            );
+        }
+
+        for layer_idx in NiceProgressBar::<_, 'b'>(
+            0..block_count,
+            "Loading repeating layers",
+            &MultiProgress::new(),
+        ) {
+            let prefix = format!("blk.{layer_idx}");
+            let device = mapper.device_for(layer_idx, false).unwrap_or(device);
+            let rotary = ropes
+                .get(&device.location())
+                .expect("No RoPE for device location!")
+                .clone();
+
+            let attention_q = match q_lora_rank {
+                Some(_) => QProj::Lora {
+                    a: gguf_linear(ct.tensor(&format!("{prefix}.attn_q_a.weight"), device)?)?,
+                    norm: QRmsNorm::new(
+                        ct.tensor(&format!("{prefix}.attn_q_a_norm.weight"), device)?,
+                        rms_norm_eps,
+                    )?,
+                    b: gguf_linear(ct.tensor(&format!("{prefix}.attn_q_b.weight"), device)?)?,
+                },
+                None => QProj::Plain(gguf_linear(
+                    ct.tensor(&format!("{prefix}.attn_q.weight"), device)?,
+                )?),
+            };
+            let attention_kv_a_mqa =
+                ct.tensor(&format!("{prefix}.attn_kv_a_mqa.weight"), device)?;
+            let attention_kv_a_norm =
+                ct.tensor(&format!("{prefix}.attn_kv_a_norm.weight"), device)?;
+            let attention_kv_b = if ct.has_tensor(&format!("{prefix}.attn_kv_b.weight")) {
+                ct.tensor(&format!("{prefix}.attn_kv_b.weight"), device)?
+            } else {
+                // MLA conversions split `kv_b` into a transposed `k_b` and `v_b`: rebuild it.
+                let k_b = ct.tensor(&format!("{prefix}.attn_k_b.weight"), device)?;
+                let v_b = ct.tensor(&format!("{prefix}.attn_v_b.weight"), device)?;
+                // (n_head, kv_lora_rank, qk_nope) and (n_head, v_head_dim, kv_lora_rank)
+                let kv_b = Tensor::cat(
+                    &[
+                        &k_b.dequantize(device)?.transpose(1, 2)?,
+                        &v_b.dequantize(device)?,
+                    ],
+                    1,
+                )?
+                .reshape((head_count * (qk_nope_head_dim + v_head_dim), kv_lora_rank))?;
+                QTensor::quantize(&kv_b, k_b.dtype())?
+            };
+            let attention_wo = ct.tensor(&format!("{prefix}.attn_output.weight"), device)?;
+
+            let mlp_or_moe = if layer_idx < leading_dense_block_count || n_expert <= 1 {
+                MlpOrMoe::Mlp(Mlp {
+                    feed_forward_w1: gguf_linear(
+                        ct.tensor(&format!("{prefix}.ffn_gate.weight"), device)?,
+                    )?,
+                    feed_forward_w2: gguf_linear(
+                        ct.tensor(&format!("{prefix}.ffn_down.weight"), device)?,
+                    )?,
+                    feed_forward_w3: gguf_linear(
+                        ct.tensor(&format!("{prefix}.ffn_up.weight"), device)?,
+                    )?,
+                })
+            } else {
+                let feed_forward_gate_inp =
+                    ct.tensor(&format!("{prefix}.ffn_gate_inp.weight"), device)?;
+                let exp_probs_b = if ct.has_tensor(&format!("{prefix}.exp_probs_b.bias")) {
+                    Some(
+                        ct.tensor(&format!("{prefix}.exp_probs_b.bias"), device)?
+                            .dequantize(device)?,
+                    )
+                } else {
+                    None
+                };
+
+                let ffn_gate =
+                    ct.expert_tensors(&format!("{prefix}.ffn_gate_exps.weight"), n_expert, device)?;
+                let ffn_down =
+                    ct.expert_tensors(&format!("{prefix}.ffn_down_exps.weight"), n_expert, device)?;
+                let ffn_up =
+                    ct.expert_tensors(&format!("{prefix}.ffn_up_exps.weight"), n_expert, device)?;
+                let mut experts = Vec::with_capacity(n_expert);
+                for (ff_w1, (ff_w2, ff_w3)) in
+                    ffn_gate.into_iter().zip(ffn_down.into_iter().zip(ffn_up))
+                {
+                    experts.push(Mlp {
+                        feed_forward_w1: gguf_linear(ff_w1)?,
+                        feed_forward_w2: gguf_linear(ff_w2)?,
+                        feed_forward_w3: gguf_linear(ff_w3)?,
+                    })
+                }
+
+                let shared_experts = if n_expert_shared > 0 {
+                    Some(Mlp {
+                        feed_forward_w1: gguf_linear(
+                            ct.tensor(&format!("{prefix}.ffn_gate_shexp.weight"), device)?,
+                        )?,
+                        feed_forward_w2: gguf_linear(
+                            ct.tensor(&format!("{prefix}.ffn_down_shexp.weight"), device)?,
+                        )?,
+                        feed_forward_w3: gguf_linear(
+                            ct.tensor(&format!("{prefix}.ffn_up_shexp.weight"), device)?,
+                        )?,
+                    })
+                } else {
+                    None
+                };
+
+                MlpOrMoe::MoE(Moe {
+                    feed_forward_gate_inp: gguf_linear(feed_forward_gate_inp)?,
+                    exp_probs_b,
+                    experts,
+                    shared_experts,
+                    n_expert_used,
+                    gating_func,
+                    norm_topk_prob: expert_weights_norm,
+                    routed_scaling_factor: expert_weights_scale as f64,
+                    n_group: n_expert_groups,
+                    topk_group: n_expert_groups_used,
+                })
+            };
+
+            let attention_norm = ct.tensor(&format!("{prefix}.attn_norm.weight"), device)?;
+            let ffn_norm = ct.tensor(&format!("{prefix}.ffn_norm.weight"), device)?;
+            let paged_attn = match &attention_mechanism {
+                AttentionImplementation::Eager => None,
+                AttentionImplementation::PagedAttention => {
+                    Some(PagedAttention::new(q_head_dim, device, None)?)
+                }
+            };
+            layers.push(LayerWeights {
+                attention_q,
+                attention_kv_a_mqa: gguf_linear(attention_kv_a_mqa)?,
+                attention_kv_a_norm: QRmsNorm::new(attention_kv_a_norm, rms_norm_eps)?,
+                attention_kv_b: gguf_linear(attention_kv_b)?,
+                attention_wo: gguf_linear(attention_wo)?,
+                attention_norm: QRmsNorm::new(attention_norm, rms_norm_eps)?,
+                mlp_or_moe,
+                ffn_norm: QRmsNorm::new(ffn_norm, rms_norm_eps)?,
+                n_head: head_count,
+                qk_nope_head_dim,
+                qk_rope_head_dim: rope_dim,
+                v_head_dim,
+                kv_lora_rank,
+                rotary: rotary.clone(),
+                paged_attn,
+                sdpa_params: SdpaParams {
+                    n_kv_groups: 1,
+                    use_flash_attn: false,
+                    softcap: None,
+                    softmax_scale,
+                    sliding_window: None,
+                },
+                dtype,
+            })
+        }
+        Ok(Self {
+            tok_embeddings: Embedding::new(tok_embeddings, embedding_length),
+            layers,
+            norm,
+            output: gguf_linear(output)?,
+            device: device.clone(),
+            cache: EitherCache::Normal(NormalCache::new(block_count, max_seq_len)),
+            max_seq_len,
+            mapper: Some(mapper),
+            dtype,
+        })
+    }
+}
+
+impl ModelWeights {
+    pub fn forward(
+        &self,
+        x: &Tensor,
+        start_offsets: &[usize],
+        context_lens: Vec<(usize, usize)>,
+        metadata: Option<(Vec<(Tensor, Tensor)>, &PagedAttentionInputMetadata)>,
+    ) -> Result<Tensor> {
+        let mut layer_in = self.tok_embeddings.forward(x)?;
+        let cache = &mut self.cache.normal()?.0;
+        let mask = CausalMasker.make_causal_mask_matrix(
+            x,
+            metadata
+                .as_ref()
+                .map(|(_, _)| &start_offsets as &dyn PastKvLenCache)
+                .unwrap_or(cache as &dyn PastKvLenCache),
+            self.dtype,
+            self.layers[0].n_head,
+        )?;
+        // PagedAttention prompt chunking
+        let mask = mask.filter(|_| {
+            metadata
+                .as_ref()
+                .map(|(_, meta)| meta.is_first_prompt_chunk)
+                .unwrap_or(true)
+        });
+        for (i, layer) in self.layers.iter().enumerate() {
+            if let Some(ref mapper) = self.mapper {
+                layer_in = mapper.map(layer_in, i)?;
+            }
+            let x = layer_in;
+            let residual = &x;
+            let x = layer.attention_norm.forward(&x)?;
+            let attn = layer.forward_attn(
+                &x,
+                mask.as_ref()
+                    .map(|m| m.to_device(x.device()).unwrap())
+                    .as_ref(),
+                start_offsets,
+                &mut cache[i],
+                metadata
+                    .as_ref()
+                    .map(|(kv_cache, metadata)| (kv_cache[i].clone(), *metadata)),
+            )?;
+            let x = (attn + residual)?;
+
+            // MLP or MoE
+            let residual = &x;
+            let x = layer.ffn_norm.forward(&x)?;
+            let x = layer.mlp_or_moe.forward(&x)?;
+            let x = (x + residual)?;
+            layer_in = x;
+        }
+        let layer_in = layer_in.to_device(&self.device)?;
+        let x = self.norm.forward(&layer_in)?;
+        extract_logits(
+            &MatMul.qmethod_matmul(&x.contiguous()?, &*self.output)?,
+            context_lens,
+        )
+    }
+}
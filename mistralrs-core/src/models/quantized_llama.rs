@@ -4,7 +4,6 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use candle_core::quantized::ggml_file;
-use candle_core::quantized::QTensor;
 use candle_core::{DType, Device, Result, Tensor};
 use candle_nn::{Embedding, Module};
 use indicatif::MultiProgress;
@@ -194,9 +193,14 @@ impl LayerWeights {
                 )?
             }
             None => {
-                let (k, v) = kv_cache.append(&k, &v)?;
+                let (k, v, attn_mask) = kv_cache.append_sliding_window(
+                    &k,
+                    &v,
+                    mask,
+                    self.sdpa_params.sliding_window,
+                )?;
 
-                Sdpa.run_attention(&q, &k, &v, mask, None, &self.sdpa_params)?
+                Sdpa.run_attention(&q, &k, &v, attn_mask.as_ref(), None, &self.sdpa_params)?
             }
         };
 
@@ -220,6 +224,7 @@ pub struct ModelWeights {
     pub cache: EitherCache,
     pub max_seq_len: usize,
     mapper: Option<Box<dyn DeviceMapper + Send + Sync>>,
+    sliding_window: Option<usize>,
     dtype: DType,
 }
 
@@ -321,6 +326,7 @@ impl ModelConfig::FromGGML for ModelWeights {
             )),
             max_seq_len: MAX_SEQ_LEN as usize, // Cannot determine from ggml.
             mapper: None,
+            sliding_window: None,
             dtype,
         })
     }
@@ -342,6 +348,7 @@ pub(crate) struct PropsGGUF {
     pub rope_freq_base: f32,
     pub key_length: usize,
     pub value_length: usize,
+    pub sliding_window: Option<usize>,
 }
 
 impl TryFrom<ContentMetadata<'_>> for PropsGGUF {
@@ -390,6 +397,10 @@ impl TryFrom<ContentMetadata<'_>> for PropsGGUF {
                 .ok()
                 .map(|x| x as usize)
                 .unwrap_or(embed_len / head_count),
+            // Mistral-style models store the attention window, plain Llama models do not.
+            sliding_window: c
+                .get_option_value::<u32>("attention.sliding_window")?
+                .map(|x| x as usize),
         };
 
         Ok(props)
@@ -422,6 +433,7 @@ impl ModelConfig::FromGGUF for ModelWeights {
             rope_freq_base,
             key_length,
             value_length,
+            sliding_window,
         } = PropsGGUF::try_from(metadata).or_else(|err| candle_core::bail!("{err}"))?;
 
         let qtok_embeddings = ct.tensor("token_embd.weight", device)?;
@@ -495,86 +507,63 @@ impl ModelConfig::FromGGUF for ModelWeights {
                 let feed_forward_gate_inp =
                     ct.tensor(&format!("{prefix}.ffn_gate_inp.weight"), device)?;
                 let mut experts = Vec::with_capacity(n_expert);
-                match ct.tensor(&format!("{prefix}.ffn_gate_exps.weight"), device) {
-                    Ok(feed_forward_gate_exps) => {
-                        let feed_forward_down_exps =
-                            ct.tensor(&format!("{prefix}.ffn_down_exps.weight"), device)?;
-                        let feed_forward_up_exps =
-                            ct.tensor(&format!("{prefix}.ffn_up_exps.weight"), device)?;
-
-                        let dequant_ffn_gate = feed_forward_gate_exps
-                            .dequantize(device)?
-                            .chunk(n_expert, 0)?;
-                        let dequant_ffn_down = feed_forward_down_exps
-                            .dequantize(device)?
-                            .chunk(n_expert, 0)?;
-                        let dequant_ffn_up = feed_forward_up_exps
-                            .dequantize(device)?
-                            .chunk(n_expert, 0)?;
-
-                        assert_eq!(dequant_ffn_up.len(), dequant_ffn_down.len());
-                        assert_eq!(dequant_ffn_gate.len(), dequant_ffn_down.len());
-                        assert_eq!(dequant_ffn_gate.len(), n_expert);
-
-                        let gate_type = feed_forward_gate_exps.dtype();
-                        let down_type = feed_forward_down_exps.dtype();
-                        let up_type = feed_forward_up_exps.dtype();
-
-                        for (ff_w1, (ff_w2, ff_w3)) in dequant_ffn_gate
-                            .into_iter()
-                            .zip(dequant_ffn_down.into_iter().zip(dequant_ffn_up))
-                        {
-                            experts.push(Mlp {
-                                feed_forward_w1: Arc::new(GgufMatMul::new(
-                                    QuantMethodConfig::Gguf {
-                                        q_weight: Arc::new(QTensor::quantize(&ff_w1, gate_type)?),
-                                        b: None,
-                                    },
-                                )?),
-                                feed_forward_w2: Arc::new(GgufMatMul::new(
-                                    QuantMethodConfig::Gguf {
-                                        q_weight: Arc::new(QTensor::quantize(&ff_w2, down_type)?),
-                                        b: None,
-                                    },
-                                )?),
-                                feed_forward_w3: Arc::new(GgufMatMul::new(
-                                    QuantMethodConfig::Gguf {
-                                        q_weight: Arc::new(QTensor::quantize(&ff_w3, up_type)?),
-                                        b: None,
-                                    },
-                                )?),
-                            })
-                        }
+                if ct.has_tensor(&format!("{prefix}.ffn_gate_exps.weight")) {
+                    let ffn_gate = ct.expert_tensors(
+                        &format!("{prefix}.ffn_gate_exps.weight"),
+                        n_expert,
+                        device,
+                    )?;
+                    let ffn_down = ct.expert_tensors(
+                        &format!("{prefix}.ffn_down_exps.weight"),
+                        n_expert,
+                        device,
+                    )?;
+                    let ffn_up = ct.expert_tensors(
+                        &format!("{prefix}.ffn_up_exps.weight"),
+                        n_expert,
+                        device,
+                    )?;
+
+                    for (ff_w1, (ff_w2, ff_w3)) in
+                        ffn_gate.into_iter().zip(ffn_down.into_iter().zip(ffn_up))
+                    {
+                        experts.push(Mlp {
+                            feed_forward_w1: Arc::new(GgufMatMul::new(QuantMethodConfig::Gguf {
+                                q_weight: Arc::new(ff_w1),
+                                b: None,
+                            })?),
+                            feed_forward_w2: Arc::new(GgufMatMul::new(QuantMethodConfig::Gguf {
+                                q_weight: Arc::new(ff_w2),
+                                b: None,
+                            })?),
+                            feed_forward_w3: Arc::new(GgufMatMul::new(QuantMethodConfig::Gguf {
+                                q_weight: Arc::new(ff_w3),
+                                b: None,
+                            })?),
+                        })
                     }
-                    Err(_) => {
-                        for i in 0..n_expert {
-                            let feed_forward_w1 =
-                                ct.tensor(&format!("{prefix}.ffn_gate.{i}.weight"), device)?;
-                            let feed_forward_w2 =
-                                ct.tensor(&format!("{prefix}.ffn_down.{i}.weight"), device)?;
-                            let feed_forward_w3 =
-                                ct.tensor(&format!("{prefix}.ffn_up.{i}.weight"), device)?;
-                            experts.push(Mlp {
-                                feed_forward_w1: Arc::new(GgufMatMul::new(
-                                    QuantMethodConfig::Gguf {
-                                        q_weight: Arc::new(feed_forward_w1),
-                                        b: None,
-                                    },
-                                )?),
-                                feed_forward_w2: Arc::new(GgufMatMul::new(
-                                    QuantMethodConfig::Gguf {
-                                        q_weight: Arc::new(feed_forward_w2),
-                                        b: None,
-                                    },
-                                )?),
-                                feed_forward_w3: Arc::new(GgufMatMul::new(
-                                    QuantMethodConfig::Gguf {
-                                        q_weight: Arc::new(feed_forward_w3),
-                                        b: None,
-                                    },
-                                )?),
-                            })
-                        }
+                } else {
+                    for i in 0..n_expert {
+                        let feed_forward_w1 =
+                            ct.tensor(&format!("{prefix}.ffn_gate.{i}.weight"), device)?;
+                        let feed_forward_w2 =
+                            ct.tensor(&format!("{prefix}.ffn_down.{i}.weight"), device)?;
+                        let feed_forward_w3 =
+                            ct.tensor(&format!("{prefix}.ffn_up.{i}.weight"), device)?;
+                        experts.push(Mlp {
+                            feed_forward_w1: Arc::new(GgufMatMul::new(QuantMethodConfig::Gguf {
+                                q_weight: Arc::new(feed_forward_w1),
+                                b: None,
+                            })?),
+                            feed_forward_w2: Arc::new(GgufMatMul::new(QuantMethodConfig::Gguf {
+                                q_weight: Arc::new(feed_forward_w2),
+                                b: None,
+                            })?),
+                            feed_forward_w3: Arc::new(GgufMatMul::new(QuantMethodConfig::Gguf {
+                                q_weight: Arc::new(feed_forward_w3),
+                                b: None,
+                            })?),
+                        })
                     }
                 }
                 MlpOrMoe::MoE {
@@ -624,7 +613,7 @@ impl ModelConfig::FromGGUF for ModelWeights {
                     use_flash_attn: false,
                     softcap: None,
                     softmax_scale: 1.0 / (head_dim as f32).sqrt(),
-                    sliding_window: None,
+                    sliding_window,
                 },
                 dtype,
             })
@@ -641,12 +630,17 @@ impl ModelConfig::FromGGUF for ModelWeights {
             cache: EitherCache::Normal(NormalCache::new(block_count, max_seq_len)),
             max_seq_len,
             mapper: Some(mapper),
+            sliding_window,
             dtype,
         })
     }
 }
 
 impl ModelWeights {
+    pub fn embed(&self, input_ids: &Tensor) -> Result<Tensor> {
+        self.tok_embeddings.forward(input_ids)
+    }
+
     pub fn forward(
         &self,
         x: &Tensor,
@@ -654,14 +648,29 @@ impl ModelWeights {
         context_lens: Vec<(usize, usize)>,
         metadata: Option<(Vec<(Tensor, Tensor)>, &PagedAttentionInputMetadata)>,
     ) -> Result<Tensor> {
-        let mut layer_in = self.tok_embeddings.forward(x)?;
-        let cache = &mut self.cache.normal().0;
-        let mask = CausalMasker.make_causal_mask_matrix(
-            x,
+        let input_embeds = self.embed(x)?;
+        self.forward_input_embed(x, input_embeds, start_offsets, context_lens, metadata)
+    }
+
+    /// Run the model on precomputed input embeddings, such as text embeddings with image features
+    /// spliced in. `input_ids` is only used to build the attention mask.
+    pub fn forward_input_embed(
+        &self,
+        input_ids: &Tensor,
+        input_embeds: Tensor,
+        start_offsets: &[usize],
+        context_lens: Vec<(usize, usize)>,
+        metadata: Option<(Vec<(Tensor, Tensor)>, &PagedAttentionInputMetadata)>,
+    ) -> Result<Tensor> {
+        let mut layer_in = input_embeds;
+        let cache = &mut self.cache.normal()?.0;
+        let mask = CausalMasker.make_sliding_window_causal_mask_matrix(
+            input_ids,
             metadata
                 .as_ref()
                 .map(|(_, _)| &start_offsets as &dyn PastKvLenCache)
                 .unwrap_or(cache as &dyn PastKvLenCache),
+            self.sliding_window,
             self.dtype,
             self.layers[0].n_head,
         )?;
@@ -707,3 +716,96 @@ impl ModelWeights {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use candle_core::{quantized::gguf_file::Value, DType, Device, Tensor};
+
+    use super::PropsGGUF;
+    use crate::{
+        layers::CausalMasker, layers_masker::PastKvLenCache, utils::gguf_metadata::ContentMetadata,
+    };
+
+    fn metadata(sliding_window: Option<u32>) -> HashMap<String, Value> {
+        let mut metadata = HashMap::from([
+            (
+                "general.architecture".to_string(),
+                Value::String("llama".to_string()),
+            ),
+            ("llama.attention.head_count".to_string(), Value::U32(4)),
+            ("llama.attention.head_count_kv".to_string(), Value::U32(2)),
+            ("llama.block_count".to_string(), Value::U32(1)),
+            ("llama.embedding_length".to_string(), Value::U32(64)),
+            ("llama.rope.dimension_count".to_string(), Value::U32(16)),
+            (
+                "llama.attention.layer_norm_rms_epsilon".to_string(),
+                Value::F32(1e-5),
+            ),
+        ]);
+        if let Some(sliding_window) = sliding_window {
+            metadata.insert(
+                "llama.attention.sliding_window".to_string(),
+                Value::U32(sliding_window),
+            );
+        }
+        metadata
+    }
+
+    fn props(metadata: &HashMap<String, Value>) -> PropsGGUF {
+        PropsGGUF::try_from(ContentMetadata {
+            path_prefix: "llama",
+            metadata,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn sliding_window_is_read_from_metadata() {
+        assert_eq!(props(&metadata(None)).sliding_window, None);
+        assert_eq!(props(&metadata(Some(3))).sliding_window, Some(3));
+    }
+
+    #[test]
+    fn sliding_window_mask_hides_old_tokens() -> candle_core::Result<()> {
+        let dev = Device::Cpu;
+        let sliding_window = props(&metadata(Some(3))).sliding_window;
+        let input_ids = Tensor::zeros((1, 6), DType::U32, &dev)?;
+        let past_kv_len: &[usize] = &[0];
+        let mask = CausalMasker
+            .make_sliding_window_causal_mask_matrix(
+                &input_ids,
+                &past_kv_len as &dyn PastKvLenCache,
+                sliding_window,
+                DType::F32,
+                4,
+            )?
+            .unwrap()
+            .to_vec2::<f32>()?;
+
+        for (i, row) in mask.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                // Each token sees itself and the `sliding_window` tokens before it.
+                let visible = j <= i && i - j <= 3;
+                assert_eq!(*value == 0., visible, "query {i}, key {j}: {value}");
+                if !visible {
+                    assert_eq!(*value, f32::NEG_INFINITY);
+                }
+            }
+        }
+
+        let full = CausalMasker
+            .make_sliding_window_causal_mask_matrix(
+                &input_ids,
+                &past_kv_len as &dyn PastKvLenCache,
+                props(&metadata(None)).sliding_window,
+                DType::F32,
+                4,
+            )?
+            .unwrap()
+            .to_vec2::<f32>()?;
+        assert_eq!(full[5][0], 0.);
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 #![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
 
 use candle_core::{DType, Device, Module, Result, Tensor};
+use indicatif::MultiProgress;
 use mistralrs_quant::{
     ColumnParallelLayer, QuantMethod, QuantizedConfig, ReplicatedLayer, RowParallelLayer,
     ShardedVarBuilder,
@@ -12,6 +13,7 @@ use crate::{
     attention::SdpaParams,
     device_map::DeviceMapper,
     get_delta_from_lora_ab,
+    gguf::Content,
     layers::{embedding, Activation, CausalMasker, MatMul, Mlp, RmsNorm, RotaryEmbedding, Sdpa},
     layers_masker::PastKvLenCache,
     paged_attention::{AttentionImplementation, ModelConfigMetadata, PagedAttention},
@@ -135,6 +137,36 @@ impl Attention {
         })
     }
 
+    /// Load the attention of the GGUF layer `prefix` (such as `blk.0`).
+    fn new_gguf<R: std::io::Seek + std::io::Read>(
+        rotary_emb: Arc<RotaryEmbedding>,
+        cfg: &Config,
+        ct: &mut Content<'_, R>,
+        prefix: &str,
+        device: &Device,
+        paged_attn: Option<PagedAttention>,
+    ) -> Result<Self> {
+        let head_dim = cfg.hidden_size / cfg.num_attention_heads;
+        Ok(Self {
+            q_proj: ct.linear(&format!("{prefix}.attn_q"), true, device)?,
+            k_proj: ct.linear(&format!("{prefix}.attn_k"), true, device)?,
+            v_proj: ct.linear(&format!("{prefix}.attn_v"), true, device)?,
+            o_proj: ct.linear(&format!("{prefix}.attn_output"), false, device)?,
+            num_heads: cfg.num_attention_heads,
+            num_kv_heads: cfg.num_key_value_heads,
+            head_dim,
+            rotary_emb,
+            paged_attn,
+            sdpa_params: SdpaParams {
+                n_kv_groups: cfg.num_attention_heads / cfg.num_key_value_heads,
+                use_flash_attn: cfg.use_flash_attn,
+                softcap: None,
+                softmax_scale: 1.0 / (head_dim as f32).sqrt(),
+                sliding_window: None,
+            },
+        })
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn forward(
         &self,
@@ -462,6 +494,117 @@ impl Model {
         })
     }
 
+    /// Load the language model from the llama.cpp GGUF file of a vision model, such as MiniCPM-o.
+    /// The shapes come from `cfg`, the `config.json` of the original model.
+    pub fn new_gguf<R: std::io::Seek + std::io::Read>(
+        cfg: &Config,
+        ct: &mut Content<'_, R>,
+        mapper: Box<dyn DeviceMapper + Send + Sync>,
+        device: &Device,
+        attention_mechanism: AttentionImplementation,
+        dtype: DType,
+    ) -> Result<Self> {
+        let dequantize = |ct: &mut Content<'_, R>, name: &str, device: &Device| {
+            ct.tensor(name, device)?.dequantize(device)?.to_dtype(dtype)
+        };
+        let embed_tokens = candle_nn::Embedding::new(
+            dequantize(ct, "token_embd.weight", device)?,
+            cfg.hidden_size,
+        );
+        let head_dim = cfg.hidden_size / cfg.num_attention_heads;
+
+        let mut ropes = HashMap::new();
+        for layer_idx in 0..cfg.num_hidden_layers {
+            let device = mapper.device_for(layer_idx, false).unwrap_or(device);
+            ropes.insert(
+                device.location(),
+                Arc::new(RotaryEmbedding::new(
+                    cfg.rope_theta as f32,
+                    head_dim,
+                    cfg.max_position_embeddings,
+                    device,
+                    true,
+                    dtype,
+                )?),
+            );
+        }
+
+        let mut layers = Vec::with_capacity(cfg.num_hidden_layers);
+        for layer_idx in NiceProgressBar::<_, 'b'>(
+            0..cfg.num_hidden_layers,
+            "Loading repeating layers",
+            &MultiProgress::new(),
+        ) {
+            let prefix = format!("blk.{layer_idx}");
+            let device = mapper.device_for(layer_idx, false).unwrap_or(device);
+            let rotary_emb = ropes
+                .get(&device.location())
+                .expect("No RoPE for device location!")
+                .clone();
+            let paged_attn = match &attention_mechanism {
+                AttentionImplementation::Eager => None,
+                AttentionImplementation::PagedAttention => {
+                    Some(PagedAttention::new(head_dim, device, None)?)
+                }
+            };
+            let self_attn = Attention::new_gguf(rotary_emb, cfg, ct, &prefix, device, paged_attn)?;
+            let mlp = Mlp::from_layers(
+                ct.linear(&format!("{prefix}.ffn_gate"), false, device)?,
+                ct.linear(&format!("{prefix}.ffn_up"), false, device)?,
+                ct.linear(&format!("{prefix}.ffn_down"), false, device)?,
+                cfg.hidden_size,
+                cfg.intermediate_size,
+                cfg.hidden_act,
+            );
+            layers.push(DecoderLayer {
+                self_attn,
+                mlp: Box::new(mlp),
+                input_layernorm: RmsNorm::from_w(
+                    dequantize(ct, &format!("{prefix}.attn_norm.weight"), device)?,
+                    cfg.rms_norm_eps,
+                )?,
+                post_attention_layernorm: RmsNorm::from_w(
+                    dequantize(ct, &format!("{prefix}.ffn_norm.weight"), device)?,
+                    cfg.rms_norm_eps,
+                )?,
+            });
+        }
+        let norm = RmsNorm::from_w(
+            dequantize(ct, "output_norm.weight", device)?,
+            cfg.rms_norm_eps,
+        )?;
+        // Tied embeddings are not written to the file again.
+        let lm_head = if ct.has_tensor("output.weight") {
+            ct.linear("output", false, device)?
+        } else {
+            ct.linear("token_embd", false, device)?
+        };
+        Ok(Self {
+            embed_tokens,
+            layers,
+            norm,
+            lm_head,
+            sliding_window: cfg.sliding_window,
+            device: device.clone(),
+            cache: EitherCache::Normal(NormalCache::new(
+                cfg.num_hidden_layers,
+                cfg.max_position_embeddings,
+            )),
+            max_seq_len: cfg.max_position_embeddings,
+            cfg: ModelConfigMetadata {
+                max_seq_len: cfg.max_position_embeddings,
+                num_layers: cfg.num_hidden_layers,
+                hidden_size: cfg.hidden_size,
+                num_attn_heads: cfg.num_attention_heads,
+                num_kv_heads: cfg.num_key_value_heads,
+                sliding_window: Some(cfg.sliding_window),
+                k_head_dim: head_dim,
+                v_head_dim: head_dim,
+            },
+            mapper,
+        })
+    }
+
     pub fn get_input_embeddings(&self, input_ids: &Tensor) -> Result<Tensor> {
         self.embed_tokens.forward(input_ids)
     }
@@ -495,7 +638,7 @@ impl Model {
         metadata: Option<(Vec<(Tensor, Tensor)>, &PagedAttentionInputMetadata)>,
         flash_params: &FlashParams,
     ) -> Result<Tensor> {
-        let cache = &mut self.cache.normal().0;
+        let cache = &mut self.cache.normal()?.0;
         let attention_mask = CausalMasker.make_sliding_window_causal_mask_matrix(
             input_ids,
             metadata
@@ -4,7 +4,9 @@ use std::{
 };
 
 use candle_core::{DType, Device, Result, Tensor};
-use mistralrs_paged_attn::{copy_blocks, swap_blocks};
+use mistralrs_paged_attn::{copy_blocks, swap_blocks, FP8_KV_CACHE_SCALE_ROWS};
+
+use crate::KvCacheType;
 
 use super::config::ModelConfigLike;
 
@@ -13,6 +15,7 @@ pub struct CacheConfig {
     pub block_size: usize,
     pub num_gpu_blocks: usize,
     pub num_cpu_blocks: usize,
+    pub cache_type: KvCacheType,
 }
 
 pub type KVCache = (Tensor, Tensor);
@@ -59,10 +62,11 @@ impl CacheEngine {
         device: &Device,
         layer_devices: Vec<Option<Device>>,
     ) -> Result<Vec<KVCache>> {
+        let dtype = Self::cache_dtype(cache_config, dtype);
         let key_block_shape =
             Self::calculate_key_block_shape(model_config, dtype, cache_config.block_size);
         let value_block_shape =
-            Self::calculate_value_block_shape(model_config, cache_config.block_size);
+            Self::calculate_value_block_shape(model_config, cache_config, cache_config.block_size);
         let mut gpu_cache = Vec::new();
 
         for device in layer_devices
@@ -106,10 +110,11 @@ impl CacheEngine {
         dtype: DType,
         device: &Device,
     ) -> Result<Vec<KVCache>> {
+        let dtype = Self::cache_dtype(cache_config, dtype);
         let key_block_shape =
             Self::calculate_key_block_shape(model_config, dtype, cache_config.block_size);
         let value_block_shape =
-            Self::calculate_value_block_shape(model_config, cache_config.block_size);
+            Self::calculate_value_block_shape(model_config, cache_config, cache_config.block_size);
         let mut cpu_cache = Vec::new();
         for _ in 0..model_config.num_layers() {
             let key_blocks = unsafe {
@@ -144,6 +149,14 @@ impl CacheEngine {
 }
 
 impl CacheEngine {
+    /// The FP8 cache is stored as `F8E4M3`, otherwise the cache has the activation dtype.
+    fn cache_dtype(cache_config: &CacheConfig, dtype: DType) -> DType {
+        match cache_config.cache_type {
+            KvCacheType::F8E4M3 => DType::F8E4M3,
+            KvCacheType::Auto => dtype,
+        }
+    }
+
     fn calculate_key_block_shape(
         model_config: &dyn ModelConfigLike,
         dtype: DType,
@@ -161,11 +174,17 @@ impl CacheEngine {
 
     fn calculate_value_block_shape(
         model_config: &dyn ModelConfigLike,
+        cache_config: &CacheConfig,
         block_size: usize,
     ) -> (usize, usize, usize) {
+        // The FP8 value cache also holds the scales of the keys and values.
+        let scale_rows = match cache_config.cache_type {
+            KvCacheType::F8E4M3 => FP8_KV_CACHE_SCALE_ROWS,
+            KvCacheType::Auto => 0,
+        };
         (
             model_config.num_kv_heads(),
-            model_config.v_head_dim(),
+            model_config.v_head_dim() + scale_rows,
             block_size,
         )
     }
@@ -21,7 +21,7 @@ pub use scheduler::{
     PagedAttentionScheduler, PagedAttentionSchedulerConfig, PagedAttentionSchedulerOutput,
 };
 
-use crate::MemoryUsage;
+use crate::{KvCacheType, MemoryUsage};
 use tracing::info;
 
 pub const DEFAULT_PAGED_ATTENTION_BLOCK_SIZE: usize = 32;
@@ -97,6 +97,7 @@ pub fn calculate_cache_config(
     mem_cpu: usize,
     block_size: Option<usize>,
     dtype: DType,
+    cache_type: KvCacheType,
     config: &dyn ModelConfigLike,
     device: &Device,
     layer_devices: &[Option<Device>],
@@ -106,7 +107,18 @@ pub fn calculate_cache_config(
     if !SUPPORTED_BLOCK_SIZE.contains(&block_size) {
         anyhow::bail!("Block size must be in {SUPPORTED_BLOCK_SIZE:?}, got {block_size}");
     }
-    let dtype_size = dtype.size_in_bytes();
+    let dtype_size = match cache_type {
+        KvCacheType::Auto => dtype.size_in_bytes(),
+        KvCacheType::F8E4M3 => {
+            let all_cuda = layer_devices
+                .iter()
+                .all(|dev| dev.as_ref().unwrap_or(device).is_cuda());
+            if !all_cuda {
+                anyhow::bail!("The `f8e4m3` PagedAttention KV cache is only supported on CUDA.");
+            }
+            DType::F8E4M3.size_in_bytes()
+        }
+    };
 
     let mut min_mem_gpu = usize::MAX;
     for dev in layer_devices {
@@ -141,11 +153,15 @@ pub fn calculate_cache_config(
 
     if !silent {
         info!("Allocating {mem_gpu} MB for PagedAttention KV cache per GPU");
+        if cache_type.is_quantized() {
+            info!("Using `{cache_type}` PagedAttention KV cache");
+        }
         info!("Using PagedAttention with block size {block_size} and {num_gpu_blocks} GPU blocks: available context length is {} tokens", num_gpu_blocks*block_size);
     }
     Ok(CacheConfig {
         block_size,
         num_gpu_blocks,
         num_cpu_blocks,
+        cache_type,
     })
 }
@@ -127,7 +127,8 @@ impl PagedAttentionScheduler {
         // sequences, which will be put into the waiting or swapped out state depending on
         // the preemption method (recompute or swap, respectively).
 
-        // Sorts by creation time, in descending order so that earliest are latest (first come first serve).
+        // Sorts by priority and creation time, in descending order so that low priority and earliest are
+        // latest (first come first serve).
         self.sort_running_by_priority_fcfs();
 
         let mut running = VecDeque::new();
@@ -292,7 +293,23 @@ impl PagedAttentionScheduler {
     fn _preempt_by_recompute(&mut self, seq: Arc<Mutex<Sequence>>) {
         get_mut_arcmutex!(seq).set_state(SequenceState::Waiting);
         self._free(get_mut_arcmutex!(seq).get_id());
-        self.waiting.push_front(seq);
+        // Preempted sequences are resumed first, but low priority ones only after the waiting
+        // normal priority sequences.
+        let idx = if get_mut_arcmutex!(seq).is_low_priority() {
+            self.first_low_priority_waiting()
+        } else {
+            0
+        };
+        self.waiting.insert(idx, seq);
+    }
+
+    /// Index of the first low priority sequence in the waiting queue, where normal priority
+    /// sequences are all before the low priority ones.
+    fn first_low_priority_waiting(&self) -> usize {
+        self.waiting
+            .iter()
+            .position(|seq| get_mut_arcmutex!(seq).is_low_priority())
+            .unwrap_or(self.waiting.len())
     }
 
     fn _preempt_by_swap(
@@ -322,23 +339,31 @@ impl PagedAttentionScheduler {
     }
 
     fn sort_running_by_priority_fcfs(&mut self) {
-        self.running
-            .make_contiguous()
-            .sort_by_key(|seq| get_mut_arcmutex!(seq).timestamp());
+        self.running.make_contiguous().sort_by_key(|seq| {
+            let seq = get_mut_arcmutex!(seq);
+            (!seq.is_low_priority(), seq.timestamp())
+        });
         self.running.make_contiguous().reverse();
     }
 
     fn sort_swapped_out_by_priority_fcfs(&mut self) {
-        self.swapped_out
-            .make_contiguous()
-            .sort_by_key(|seq| get_mut_arcmutex!(seq).timestamp());
+        self.swapped_out.make_contiguous().sort_by_key(|seq| {
+            let seq = get_mut_arcmutex!(seq);
+            (!seq.is_low_priority(), seq.timestamp())
+        });
         self.swapped_out.make_contiguous().reverse();
     }
 }
 
 impl Scheduler for PagedAttentionScheduler {
     fn add_seq(&mut self, seq: Sequence) {
-        self.waiting.push_back(Arc::new(Mutex::new(seq)));
+        // Normal priority sequences are queued before all low priority ones.
+        let idx = if seq.is_low_priority() {
+            self.waiting.len()
+        } else {
+            self.first_low_priority_waiting()
+        };
+        self.waiting.insert(idx, Arc::new(Mutex::new(seq)));
     }
     fn schedule(&mut self) -> SchedulerOutput<'_> {
         SchedulerOutput::PagedAttention {
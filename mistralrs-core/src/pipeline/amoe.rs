@@ -190,10 +190,10 @@ impl CacheManagerMixin for AnyMoePipeline {
     fn cache(&self) -> &EitherCache {
         unreachable!()
     }
-    fn clone_in_cache(&self, seqs: &mut [&mut Sequence]) {
+    fn clone_in_cache(&self, seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
         get_mut_arcmutex!(self.target).clone_in_cache(seqs)
     }
-    fn clone_out_cache(&self, seqs: &mut [&mut Sequence]) {
+    fn clone_out_cache(&self, seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
         get_mut_arcmutex!(self.target).clone_out_cache(seqs)
     }
     fn set_none_cache(
@@ -202,7 +202,7 @@ impl CacheManagerMixin for AnyMoePipeline {
         reset_non_granular: bool,
         modify_draft_cache: bool,
         load_preallocated_cache: bool,
-    ) {
+    ) -> candle_core::Result<()> {
         get_mut_arcmutex!(self.target).set_none_cache(
             seqs,
             reset_non_granular,
@@ -415,8 +415,10 @@ impl AnyMoePipelineMixin for AnyMoePipeline {
                                 ("content".to_string(), Either::Left(prompt.clone())),
                             ])],
                             true,
+                            false,
                             true,
                             Vec::new(),
+                            None,
                         )
                         .map_err(candle_core::Error::msg)?;
                     let images = image_urls.as_ref().map(|urls| {
@@ -461,7 +463,7 @@ impl AnyMoePipelineMixin for AnyMoePipeline {
                 let mut input_seqs = seqs.iter_mut().collect::<Vec<_>>();
 
                 // Clear KV cache in prep for training
-                target.set_none_cache(&mut input_seqs, true, true, false);
+                target.set_none_cache(&mut input_seqs, true, true, false)?;
 
                 let inputs = inputs_processor
                     .process_inputs(
@@ -487,7 +489,7 @@ impl AnyMoePipelineMixin for AnyMoePipeline {
                 let _ = target.forward_inputs(inputs.unwrap().inputs, false)?;
 
                 // Clear the KV cache
-                target.set_none_cache(&mut input_seqs, true, true, false);
+                target.set_none_cache(&mut input_seqs, true, true, false)?;
 
                 // === BACKWARD STEP ==
                 #[allow(clippy::cast_possible_truncation)]
@@ -588,12 +590,16 @@ fn new_dummy_seq(
         None,
         None,
         images,
+        None,
+        None,
         None, // TODO incorrect for PagedAttention
         None,
         None,
         SeqStepType::PromptAndDecode,
         None,
         None,
+        None,
+        None,
         false,
     )
 }
@@ -12,15 +12,20 @@ pub trait CacheManager<T: CacheManagerMixin + MetadataMixin + ?Sized> {
         pipeline: &T,
         seqs: &mut [&mut crate::sequence::Sequence],
         modify_draft_cache: bool,
-    );
-    fn clone_out_cache(&self, pipeline: &T, seqs: &mut [&mut Sequence], modify_draft_cache: bool);
+    ) -> Result<()>;
+    fn clone_out_cache(
+        &self,
+        pipeline: &T,
+        seqs: &mut [&mut Sequence],
+        modify_draft_cache: bool,
+    ) -> Result<()>;
     fn set_none_cache(
         &self,
         pipeline: &T,
         seqs: &mut [&mut Sequence],
         modify_draft_cache: bool,
         load_preallocated_cache: bool,
-    );
+    ) -> Result<()>;
 }
 
 pub type LayerCaches = Vec<Option<(Tensor, Tensor)>>;
@@ -29,21 +34,42 @@ pub type LayerCaches = Vec<Option<(Tensor, Tensor)>>;
 pub enum EitherCache {
     Normal(Arc<Mutex<NormalCache>>),
     Full(Cache),
+    Recurrent(Arc<Mutex<RecurrentCache>>),
 }
 
 impl EitherCache {
-    /// Panics otherwise!
-    pub fn full(&self) -> &Cache {
+    /// Errors otherwise!
+    pub fn full(&self) -> Result<&Cache> {
         match self {
-            Self::Full(full) => full,
-            Self::Normal(_) => panic!("Got normal cache, expected full cache."),
+            Self::Full(full) => Ok(full),
+            Self::Normal(_) => candle_core::bail!("Got normal cache, expected full cache."),
+            Self::Recurrent(_) => candle_core::bail!("Got recurrent cache, expected full cache."),
         }
     }
-    /// Panics otherwise!
-    pub fn normal(&self) -> MutexGuard<'_, NormalCache> {
+    /// Errors otherwise!
+    pub fn normal(&self) -> Result<MutexGuard<'_, NormalCache>> {
         match self {
-            Self::Normal(normal) => normal.lock().unwrap(),
-            Self::Full(_) => panic!("Got full cache, expected normal cache."),
+            Self::Normal(normal) => Ok(normal.lock().unwrap()),
+            Self::Full(_) => candle_core::bail!("Got full cache, expected normal cache."),
+            Self::Recurrent(_) => {
+                candle_core::bail!("Got recurrent cache, expected normal cache.")
+            }
+        }
+    }
+    /// Errors otherwise!
+    pub fn recurrent(&self) -> Result<MutexGuard<'_, RecurrentCache>> {
+        match self {
+            Self::Recurrent(recurrent) => Ok(recurrent.lock().unwrap()),
+            Self::Full(_) => candle_core::bail!("Got full cache, expected recurrent cache."),
+            Self::Normal(_) => candle_core::bail!("Got normal cache, expected recurrent cache."),
+        }
+    }
+    /// Whether the per-sequence cache does not grow with the sequence length, which is the case
+    /// for models made only of state-space layers.
+    pub fn is_constant_size(&self) -> bool {
+        match self {
+            Self::Recurrent(recurrent) => recurrent.lock().unwrap().is_constant_size(),
+            Self::Normal(_) | Self::Full(_) => false,
         }
     }
 }
@@ -270,7 +296,7 @@ impl<T: CacheManagerMixin + MetadataMixin + ?Sized> CacheManager<T> for NormalCa
         pipeline: &T,
         seqs: &mut [&mut crate::sequence::Sequence],
         modify_draft_cache: bool,
-    ) {
+    ) -> Result<()> {
         let mut new_k_cache = Vec::new();
         let mut new_v_cache = Vec::new();
         let seq0_cache = if modify_draft_cache {
@@ -336,10 +362,16 @@ impl<T: CacheManagerMixin + MetadataMixin + ?Sized> CacheManager<T> for NormalCa
                 },
             });
         }
-        *pipeline.cache().normal() = NormalCache(caches);
+        *pipeline.cache().normal()? = NormalCache(caches);
+        Ok(())
     }
-    fn clone_out_cache(&self, pipeline: &T, seqs: &mut [&mut Sequence], modify_draft_cache: bool) {
-        let all_cache = pipeline.cache().normal();
+    fn clone_out_cache(
+        &self,
+        pipeline: &T,
+        seqs: &mut [&mut Sequence],
+        modify_draft_cache: bool,
+    ) -> Result<()> {
+        let all_cache = pipeline.cache().normal()?;
         for layer in 0..pipeline.get_metadata().num_hidden_layers {
             let cache = all_cache.0.get(layer).unwrap();
             // This case for llama 3.2 vision cross attn
@@ -382,6 +414,7 @@ impl<T: CacheManagerMixin + MetadataMixin + ?Sized> CacheManager<T> for NormalCa
                 });
             }
         }
+        Ok(())
     }
     fn set_none_cache(
         &self,
@@ -389,17 +422,17 @@ impl<T: CacheManagerMixin + MetadataMixin + ?Sized> CacheManager<T> for NormalCa
         seqs: &mut [&mut Sequence],
         _modify_draft_cache: bool,
         load_preallocated_cache: bool,
-    ) {
+    ) -> Result<()> {
         if seqs.iter().any(|seq| seq.preallocated_cache().is_none()) {
-            for layer in pipeline.cache().normal().0.iter_mut() {
+            for layer in pipeline.cache().normal()?.0.iter_mut() {
                 layer.reset();
             }
-            return;
+            return Ok(());
         }
 
         // Use this for the various parameters. Assumes all seqs are from one model.
-        let template_cache_dim = pipeline.cache().normal().0[0].k.dim;
-        let template_cache_msl = pipeline.cache().normal().0[0].k.max_seq_len;
+        let template_cache_dim = pipeline.cache().normal()?.0[0].k.dim;
+        let template_cache_msl = pipeline.cache().normal()?.0[0].k.max_seq_len;
 
         let layer_devices = if let Some(device_mapper) = pipeline.device_mapper() {
             let mut layer_devices = Vec::new();
@@ -412,7 +445,7 @@ impl<T: CacheManagerMixin + MetadataMixin + ?Sized> CacheManager<T> for NormalCa
             None
         };
 
-        for (layer_idx, layer) in pipeline.cache().normal().0.iter_mut().enumerate() {
+        for (layer_idx, layer) in pipeline.cache().normal()?.0.iter_mut().enumerate() {
             if !load_preallocated_cache {
                 layer.reset();
                 continue;
@@ -463,6 +496,244 @@ impl<T: CacheManagerMixin + MetadataMixin + ?Sized> CacheManager<T> for NormalCa
             };
             *layer = cache;
         }
+        Ok(())
+    }
+}
+
+fn cat_batch(xs: &[Tensor]) -> Result<Tensor> {
+    if xs.len() > 1 {
+        Tensor::cat(xs, 0)
+    } else {
+        Ok(xs[0].clone())
+    }
+}
+
+/// The state of a state-space (Mamba) layer after the tokens seen so far.
+#[derive(Debug, Clone)]
+pub struct RecurrentState {
+    /// The last `conv_kernel - 1` inputs of the causal convolution, (batch, conv_dim, conv_kernel - 1).
+    pub conv_state: Tensor,
+    /// The hidden state of the selective scan, (batch, ..., state_size).
+    pub ssm_state: Tensor,
+}
+
+impl RecurrentState {
+    fn cat(states: &[&Self]) -> Result<Self> {
+        let conv = states
+            .iter()
+            .map(|s| s.conv_state.clone())
+            .collect::<Vec<_>>();
+        let ssm = states
+            .iter()
+            .map(|s| s.ssm_state.clone())
+            .collect::<Vec<_>>();
+        Ok(Self {
+            conv_state: cat_batch(&conv)?.contiguous()?,
+            ssm_state: cat_batch(&ssm)?.contiguous()?,
+        })
+    }
+
+    fn chunk(&self, n: usize) -> Result<Vec<Self>> {
+        let conv = self.conv_state.chunk(n, 0)?;
+        let ssm = self.ssm_state.chunk(n, 0)?;
+        debug_assert_eq!(conv.len(), n);
+        Ok(conv
+            .into_iter()
+            .zip(ssm)
+            .map(|(conv_state, ssm_state)| Self {
+                conv_state,
+                ssm_state,
+            })
+            .collect())
+    }
+}
+
+/// The cache of one layer of a recurrent or hybrid model.
+#[derive(Debug, Clone)]
+pub enum RecurrentLayerCache {
+    /// A state-space layer, whose state has a constant size. `None` before the first token.
+    Ssm(Option<RecurrentState>),
+    /// An attention layer of a hybrid model.
+    Attention(KvCache),
+}
+
+impl RecurrentLayerCache {
+    pub fn reset(&mut self) {
+        match self {
+            Self::Ssm(state) => *state = None,
+            Self::Attention(kv) => kv.reset(),
+        }
+    }
+}
+
+/// The cache of a recurrent (state-space) or hybrid model: a fixed size state per SSM layer and a
+/// KV cache per attention layer.
+#[derive(Debug, Clone)]
+pub struct RecurrentCache(pub Vec<RecurrentLayerCache>);
+
+impl RecurrentCache {
+    /// `attention_layers` are the indices of the attention layers, all other layers are SSM layers.
+    pub fn new(len: usize, attention_layers: &[usize], max_seq_len: usize) -> Arc<Mutex<Self>> {
+        let layers = (0..len)
+            .map(|i| {
+                if attention_layers.contains(&i) {
+                    RecurrentLayerCache::Attention(KvCache::new(
+                        2,
+                        max_seq_len,
+                        NormalCache::CACHE_GROW_SIZE,
+                    ))
+                } else {
+                    RecurrentLayerCache::Ssm(None)
+                }
+            })
+            .collect();
+        Arc::new(Mutex::new(Self(layers)))
+    }
+
+    /// Whether the cache does not grow with the sequence length (there are no attention layers).
+    pub fn is_constant_size(&self) -> bool {
+        self.0
+            .iter()
+            .all(|layer| matches!(layer, RecurrentLayerCache::Ssm(_)))
+    }
+
+    pub fn reset(&mut self) {
+        for layer in &mut self.0 {
+            layer.reset();
+        }
+    }
+}
+
+fn cat_single_caches(caches: &[&SingleCache]) -> Result<SingleCache> {
+    let data = caches
+        .iter()
+        .map(|c| {
+            c.all_data
+                .clone()
+                .ok_or_else(|| candle_core::Error::msg("Cannot batch an empty KV cache."))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(SingleCache {
+        all_data: Some(cat_batch(&data)?.contiguous()?),
+        ..caches[0].clone()
+    })
+}
+
+fn cat_kv_caches(caches: &[&KvCache]) -> Result<KvCache> {
+    Ok(KvCache {
+        k: cat_single_caches(&caches.iter().map(|c| &c.k).collect::<Vec<_>>())?,
+        v: cat_single_caches(&caches.iter().map(|c| &c.v).collect::<Vec<_>>())?,
+    })
+}
+
+fn chunk_kv_cache(cache: &KvCache, n: usize) -> Result<Vec<KvCache>> {
+    let chunk_single = |single: &SingleCache| -> Result<Vec<SingleCache>> {
+        let Some(all_data) = &single.all_data else {
+            candle_core::bail!("Cannot split an empty KV cache.");
+        };
+        let data = all_data.chunk(n, 0)?;
+        debug_assert_eq!(data.len(), n);
+        Ok(data
+            .into_iter()
+            .map(|data| SingleCache {
+                all_data: Some(data),
+                ..single.clone()
+            })
+            .collect())
+    };
+    Ok(chunk_single(&cache.k)?
+        .into_iter()
+        .zip(chunk_single(&cache.v)?)
+        .map(|(k, v)| KvCache { k, v })
+        .collect())
+}
+
+pub struct RecurrentCacheManager;
+
+impl<T: CacheManagerMixin + MetadataMixin + ?Sized> CacheManager<T> for RecurrentCacheManager {
+    fn clone_in_cache(
+        &self,
+        pipeline: &T,
+        seqs: &mut [&mut crate::sequence::Sequence],
+        _modify_draft_cache: bool,
+    ) -> Result<()> {
+        let mut caches = Vec::new();
+        for layer in 0..pipeline.get_metadata().num_hidden_layers {
+            let seq_caches = seqs
+                .iter_mut()
+                .map(|seq| {
+                    seq.recurrent_cache()[layer].clone().ok_or_else(|| {
+                        candle_core::Error::msg("Not handling completions in `clone_in_cache`.")
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let cache = match &seq_caches[0] {
+                RecurrentLayerCache::Ssm(_) => {
+                    let states = seq_caches
+                        .iter()
+                        .map(|c| match c {
+                            RecurrentLayerCache::Ssm(Some(state)) => Ok(state),
+                            _ => candle_core::bail!("Sequences have a different cache layout."),
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    RecurrentLayerCache::Ssm(Some(RecurrentState::cat(&states)?))
+                }
+                RecurrentLayerCache::Attention(_) => {
+                    let kvs = seq_caches
+                        .iter()
+                        .map(|c| match c {
+                            RecurrentLayerCache::Attention(kv) => Ok(kv),
+                            _ => candle_core::bail!("Sequences have a different cache layout."),
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    RecurrentLayerCache::Attention(cat_kv_caches(&kvs)?)
+                }
+            };
+            caches.push(cache);
+        }
+        *pipeline.cache().recurrent()? = RecurrentCache(caches);
+        Ok(())
+    }
+    fn clone_out_cache(
+        &self,
+        pipeline: &T,
+        seqs: &mut [&mut Sequence],
+        _modify_draft_cache: bool,
+    ) -> Result<()> {
+        let all_cache = pipeline.cache().recurrent()?;
+        for layer in 0..pipeline.get_metadata().num_hidden_layers {
+            let chunks = match &all_cache.0[layer] {
+                RecurrentLayerCache::Ssm(None) => continue,
+                RecurrentLayerCache::Ssm(Some(state)) => state
+                    .chunk(seqs.len())?
+                    .into_iter()
+                    .map(|s| RecurrentLayerCache::Ssm(Some(s)))
+                    .collect::<Vec<_>>(),
+                RecurrentLayerCache::Attention(kv) => {
+                    if kv.k.all_data.is_none() {
+                        continue;
+                    }
+                    chunk_kv_cache(kv, seqs.len())?
+                        .into_iter()
+                        .map(RecurrentLayerCache::Attention)
+                        .collect::<Vec<_>>()
+                }
+            };
+            for (seq, chunk) in seqs.iter_mut().zip(chunks) {
+                seq.recurrent_cache()[layer] = Some(chunk);
+            }
+        }
+        Ok(())
+    }
+    fn set_none_cache(
+        &self,
+        pipeline: &T,
+        _seqs: &mut [&mut Sequence],
+        _modify_draft_cache: bool,
+        _load_preallocated_cache: bool,
+    ) -> Result<()> {
+        pipeline.cache().recurrent()?.reset();
+        Ok(())
     }
 }
 
@@ -694,26 +965,26 @@ impl<T: CacheManagerMixin + MetadataMixin + ?Sized> CacheManager<T> for FullCach
         pipeline: &T,
         seqs: &mut [&mut crate::sequence::Sequence],
         modify_draft_cache: bool,
-    ) {
+    ) -> Result<()> {
         if modify_draft_cache {
             clone_in_cache(
                 pipeline.get_metadata().num_hidden_layers,
-                &mut pipeline.cache().full().lock(),
+                &mut pipeline.cache().full()?.lock(),
                 seqs,
                 SeqCache::Draft,
             );
-            return;
+            return Ok(());
         }
         clone_in_cache(
             pipeline.get_metadata().num_hidden_layers,
-            &mut pipeline.cache().full().lock(),
+            &mut pipeline.cache().full()?.lock(),
             seqs,
             SeqCache::Normal,
         );
         if pipeline.get_metadata().is_xlora && !pipeline.get_metadata().no_kv_cache {
             clone_in_cache(
                 pipeline.get_metadata().num_hidden_layers,
-                &mut pipeline.cache().full().xlora_lock(),
+                &mut pipeline.cache().full()?.xlora_lock(),
                 seqs,
                 SeqCache::XLora,
             );
@@ -721,10 +992,11 @@ impl<T: CacheManagerMixin + MetadataMixin + ?Sized> CacheManager<T> for FullCach
         if pipeline.get_metadata().is_xlora {
             pipeline
                 .cache()
-                .full()
+                .full()?
                 .get_scalings_cache()
                 .clone_from(seqs[0].scaling_cache());
         }
+        Ok(())
     }
 
     fn clone_out_cache(
@@ -732,26 +1004,26 @@ impl<T: CacheManagerMixin + MetadataMixin + ?Sized> CacheManager<T> for FullCach
         pipeline: &T,
         seqs: &mut [&mut crate::sequence::Sequence],
         modify_draft_cache: bool,
-    ) {
+    ) -> Result<()> {
         if modify_draft_cache {
             clone_out_cache(
                 pipeline.get_metadata().num_hidden_layers,
-                &mut pipeline.cache().full().lock(),
+                &mut pipeline.cache().full()?.lock(),
                 seqs,
                 SeqCache::Draft,
             );
-            return;
+            return Ok(());
         }
         clone_out_cache(
             pipeline.get_metadata().num_hidden_layers,
-            &mut pipeline.cache().full().lock(),
+            &mut pipeline.cache().full()?.lock(),
             seqs,
             SeqCache::Normal,
         );
         if pipeline.get_metadata().is_xlora && !pipeline.get_metadata().no_kv_cache {
             clone_out_cache(
                 pipeline.get_metadata().num_hidden_layers,
-                &mut pipeline.cache().full().xlora_lock(),
+                &mut pipeline.cache().full()?.xlora_lock(),
                 seqs,
                 SeqCache::XLora,
             );
@@ -759,8 +1031,9 @@ impl<T: CacheManagerMixin + MetadataMixin + ?Sized> CacheManager<T> for FullCach
         if pipeline.get_metadata().is_xlora {
             seqs[0]
                 .scaling_cache()
-                .clone_from(&pipeline.cache().full().get_scalings_cache());
+                .clone_from(&pipeline.cache().full()?.get_scalings_cache());
         }
+        Ok(())
     }
 
     fn set_none_cache(
@@ -769,17 +1042,18 @@ impl<T: CacheManagerMixin + MetadataMixin + ?Sized> CacheManager<T> for FullCach
         _seqs: &mut [&mut Sequence],
         modify_draft_cache: bool,
         _load_preallocated_cache: bool,
-    ) {
+    ) -> Result<()> {
         let mut new_cache = Vec::new();
         for _ in 0..pipeline.get_metadata().num_hidden_layers {
             new_cache.push(None);
         }
-        pipeline.cache().full().lock().clone_from(&new_cache);
+        pipeline.cache().full()?.lock().clone_from(&new_cache);
         if modify_draft_cache {
-            pipeline.cache().full().draft_lock().clone_from(&new_cache);
+            pipeline.cache().full()?.draft_lock().clone_from(&new_cache);
         }
-        if pipeline.cache().full().is_xlora() {
-            *pipeline.cache().full().xlora_lock() = new_cache;
+        if pipeline.cache().full()?.is_xlora() {
+            *pipeline.cache().full()?.xlora_lock() = new_cache;
         }
+        Ok(())
     }
 }
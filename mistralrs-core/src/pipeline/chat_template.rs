@@ -1,10 +1,15 @@
 use std::collections::HashMap;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use either::Either;
 use indexmap::IndexMap;
 use itertools::Itertools;
-use minijinja::{context, value::Kwargs, Environment, Error, ErrorKind, Value};
+use minijinja::{
+    value::{Kwargs, ValueKind},
+    Environment, Error, ErrorKind, Value,
+};
+use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use tokenizers::Tokenizer;
 use tracing::info;
@@ -180,38 +185,198 @@ pub struct GenerationConfig {
     eos_token_id: Either<u32, Vec<u32>>,
 }
 
+/// `json.dumps` style formatting, as used by the `tojson` filter of Hugging Face's chat template
+/// environment. Unlike serde's formatters, Python puts a space after the separators by default.
+struct PythonJsonFormatter {
+    indent: Option<Vec<u8>>,
+    item_separator: Vec<u8>,
+    key_separator: Vec<u8>,
+    current_indent: usize,
+    has_value: bool,
+}
+
+impl PythonJsonFormatter {
+    fn new(indent: Option<usize>, separators: Option<(String, String)>) -> Self {
+        let (item_separator, key_separator) = match separators {
+            Some((item, key)) => (item.into_bytes(), key.into_bytes()),
+            // With an indent, Python does not add trailing whitespace after the item separator.
+            None if indent.is_some() => (b",".to_vec(), b": ".to_vec()),
+            None => (b", ".to_vec(), b": ".to_vec()),
+        };
+        Self {
+            indent: indent.map(|n| b" ".repeat(n)),
+            item_separator,
+            key_separator,
+            current_indent: 0,
+            has_value: false,
+        }
+    }
+
+    fn write_newline<W: ?Sized + std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        if let Some(indent) = &self.indent {
+            writer.write_all(b"\n")?;
+            for _ in 0..self.current_indent {
+                writer.write_all(indent)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl serde_json::ser::Formatter for PythonJsonFormatter {
+    fn begin_array<W: ?Sized + std::io::Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.current_indent += 1;
+        self.has_value = false;
+        writer.write_all(b"[")
+    }
+
+    fn end_array<W: ?Sized + std::io::Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.current_indent -= 1;
+        if self.has_value {
+            self.write_newline(writer)?;
+        }
+        writer.write_all(b"]")
+    }
+
+    fn begin_array_value<W: ?Sized + std::io::Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> std::io::Result<()> {
+        if !first {
+            writer.write_all(&self.item_separator)?;
+        }
+        self.write_newline(writer)
+    }
+
+    fn end_array_value<W: ?Sized + std::io::Write>(
+        &mut self,
+        _writer: &mut W,
+    ) -> std::io::Result<()> {
+        self.has_value = true;
+        Ok(())
+    }
+
+    fn begin_object<W: ?Sized + std::io::Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.current_indent += 1;
+        self.has_value = false;
+        writer.write_all(b"{")
+    }
+
+    fn end_object<W: ?Sized + std::io::Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.current_indent -= 1;
+        if self.has_value {
+            self.write_newline(writer)?;
+        }
+        writer.write_all(b"}")
+    }
+
+    fn begin_object_key<W: ?Sized + std::io::Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> std::io::Result<()> {
+        if !first {
+            writer.write_all(&self.item_separator)?;
+        }
+        self.write_newline(writer)
+    }
+
+    fn begin_object_value<W: ?Sized + std::io::Write>(
+        &mut self,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        writer.write_all(&self.key_separator)
+    }
+
+    fn end_object_value<W: ?Sized + std::io::Write>(
+        &mut self,
+        _writer: &mut W,
+    ) -> std::io::Result<()> {
+        self.has_value = true;
+        Ok(())
+    }
+}
+
+fn sort_json_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries = map.into_iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k, sort_json_keys(v)))
+                    .collect(),
+            )
+        }
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(sort_json_keys).collect())
+        }
+        other => other,
+    }
+}
+
+/// Hugging Face overrides Jinja's `tojson` filter so that the output is not HTML-escaped and
+/// matches `json.dumps(value, ensure_ascii=False, indent=None, separators=None, sort_keys=False)`.
+///
+/// https://github.com/huggingface/transformers/blob/main/src/transformers/utils/chat_template_utils.py
 fn tojson(value: Value, kwargs: Kwargs) -> Result<Value, Error> {
-    if let Ok(indent) = kwargs.get("indent") {
-        let mut buf = Vec::new();
-        let repeat = b" ".repeat(indent);
-        let formatter = serde_json::ser::PrettyFormatter::with_indent(&repeat);
-        let mut ser = serde_json::Serializer::with_formatter(&mut buf, formatter);
-        value.serialize(&mut ser).unwrap();
-        String::from_utf8(buf).map_err(|err| {
+    let indent: Option<usize> = kwargs.get("indent")?;
+    let ensure_ascii: Option<bool> = kwargs.get("ensure_ascii")?;
+    let sort_keys: Option<bool> = kwargs.get("sort_keys")?;
+    let separators: Option<Vec<String>> = kwargs.get("separators")?;
+    kwargs.assert_all_used()?;
+
+    let separators = match separators.as_deref() {
+        Some([item, key]) => Some((item.clone(), key.clone())),
+        Some(_) => {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "`separators` must be a pair of (item_separator, key_separator)",
+            ))
+        }
+        None => None,
+    };
+
+    let mut buf = Vec::new();
+    let mut ser = serde_json::Serializer::with_formatter(
+        &mut buf,
+        PythonJsonFormatter::new(indent, separators),
+    );
+    if sort_keys.unwrap_or(false) {
+        let value = serde_json::to_value(&value).map_err(|err| {
             Error::new(ErrorKind::BadSerialization, "cannot serialize to JSON").with_source(err)
-        })
+        })?;
+        sort_json_keys(value).serialize(&mut ser)
     } else {
-        serde_json::to_string(&value).map_err(|err| {
-            Error::new(ErrorKind::BadSerialization, "cannot serialize to JSON").with_source(err)
-        })
+        value.serialize(&mut ser)
     }
     .map_err(|err| {
-        Error::new(ErrorKind::InvalidOperation, "cannot serialize to JSON").with_source(err)
-    })
-    .map(|s| {
-        // When this filter is used the return value is safe for both HTML and JSON
-        let mut rv = String::with_capacity(s.len());
-        for c in s.chars() {
-            match c {
-                '<' => rv.push_str("\\u003c"),
-                '>' => rv.push_str("\\u003e"),
-                '&' => rv.push_str("\\u0026"),
-                '\'' => rv.push_str("\\u0027"),
-                _ => rv.push(c),
+        Error::new(ErrorKind::BadSerialization, "cannot serialize to JSON").with_source(err)
+    })?;
+    let json = String::from_utf8(buf).map_err(|err| {
+        Error::new(ErrorKind::BadSerialization, "cannot serialize to JSON").with_source(err)
+    })?;
+
+    let json = if ensure_ascii.unwrap_or(false) {
+        // Non-ASCII characters can only occur within strings, so they may be escaped in place.
+        let mut escaped = String::with_capacity(json.len());
+        for c in json.chars() {
+            if c.is_ascii() {
+                escaped.push(c);
+            } else {
+                let mut utf16 = [0u16; 2];
+                for unit in c.encode_utf16(&mut utf16) {
+                    escaped.push_str(&format!("\\u{unit:04x}"));
+                }
             }
         }
-        Value::from_safe_string(rv)
-    })
+        escaped
+    } else {
+        json
+    };
+    Ok(Value::from_safe_string(json))
 }
 
 fn strftime_now(fmt: String) -> Result<String, minijinja::Error> {
@@ -220,19 +385,196 @@ fn strftime_now(fmt: String) -> Result<String, minijinja::Error> {
     Ok(date_string)
 }
 
+/// Python string and dict methods which `minijinja_contrib::pycompat` does not cover. Everything
+/// else is forwarded to it.
+fn unknown_method_callback(
+    state: &minijinja::State,
+    value: &Value,
+    method: &str,
+    args: &[Value],
+) -> Result<Value, Error> {
+    if let Some(s) = value.as_str() {
+        match method {
+            "removeprefix" => {
+                let (prefix,): (&str,) = minijinja::value::from_args(args)?;
+                return Ok(Value::from(s.strip_prefix(prefix).unwrap_or(s)));
+            }
+            "removesuffix" => {
+                let (suffix,): (&str,) = minijinja::value::from_args(args)?;
+                return Ok(Value::from(s.strip_suffix(suffix).unwrap_or(s)));
+            }
+            "rsplit" => {
+                let (sep, maxsplit): (Option<&str>, Option<i64>) =
+                    minijinja::value::from_args(args)?;
+                let maxsplit = maxsplit.filter(|n| *n >= 0).map(|n| n as usize);
+                let parts = match (sep, maxsplit) {
+                    (Some(sep), Some(n)) => {
+                        let mut parts = s.rsplitn(n + 1, sep).collect::<Vec<_>>();
+                        parts.reverse();
+                        parts
+                    }
+                    (Some(sep), None) => s.split(sep).collect::<Vec<_>>(),
+                    (None, maxsplit) => {
+                        // Runs of whitespace are one separator and the leftmost part keeps its
+                        // leading whitespace, as in Python.
+                        let words = s.split_whitespace().collect::<Vec<_>>();
+                        match maxsplit {
+                            Some(n) if words.len() > n + 1 => {
+                                let n_left = words.len() - n;
+                                let last_left = words[n_left - 1];
+                                let end = last_left.as_ptr() as usize - s.as_ptr() as usize
+                                    + last_left.len();
+                                let mut parts = vec![&s[..end]];
+                                parts.extend_from_slice(&words[n_left..]);
+                                parts
+                            }
+                            _ => words,
+                        }
+                    }
+                };
+                return Ok(Value::from_iter(parts.into_iter().map(str::to_string)));
+            }
+            _ => {}
+        }
+    }
+    if value.kind() == ValueKind::Map && method == "get" {
+        // `dict.get` with a default value.
+        let (key, default): (&Value, Option<Value>) = minijinja::value::from_args(args)?;
+        return Ok(value
+            .as_object()
+            .and_then(|obj| obj.get_value(key))
+            .or(default)
+            .unwrap_or(Value::from(())));
+    }
+    minijinja_contrib::pycompat::unknown_method_callback(state, value, method, args)
+}
+
+/// Hugging Face registers a `{% generation %}` block to track assistant tokens. It has no effect
+/// on the rendered output, so it is rewritten into an always-true conditional which preserves the
+/// whitespace control of the original tags.
+fn strip_generation_blocks(template: &str) -> String {
+    static GENERATION_START: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\{%(-?)\s*generation\s*(-?)%\}").unwrap());
+    static GENERATION_END: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\{%(-?)\s*endgeneration\s*(-?)%\}").unwrap());
+    let template = GENERATION_START.replace_all(template, "{%${1} if true ${2}%}");
+    GENERATION_END
+        .replace_all(&template, "{%${1} endif ${2}%}")
+        .to_string()
+}
+
+/// Select a template from a list of named templates. Both the Hugging Face format
+/// (`[{"name": "default", "template": "..."}]`) and the `[{"default": "..."}]` format are accepted.
+fn select_named_template(
+    templates: &[HashMap<String, String>],
+    has_tools: bool,
+    has_documents: bool,
+) -> Result<String> {
+    let mut named = Vec::new();
+    for t in templates {
+        match (t.get("name"), t.get("template")) {
+            (Some(name), Some(template)) => named.push((name.as_str(), template)),
+            _ => named.extend(t.iter().map(|(name, template)| (name.as_str(), template))),
+        }
+    }
+    let find = |name: &str| {
+        named
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, template)| (*template).clone())
+    };
+
+    if has_tools {
+        if let Some(template) = find("tool_use") {
+            return Ok(template);
+        }
+    }
+    if has_documents {
+        if let Some(template) = find("rag") {
+            return Ok(template);
+        }
+    }
+    if let Some(template) = find("default") {
+        return Ok(template);
+    }
+    match &named[..] {
+        [(_, template)] => Ok((*template).clone()),
+        _ => anyhow::bail!(
+            "Chat template does not contain a `default` template and has several named templates ({}). Please ensure it contains at least a `default` template, although `tool_use` should be specified for using tools.",
+            named.iter().map(|(n, _)| format!("`{n}`")).join(", ")
+        ),
+    }
+}
+
+/// Text of the final message, used to leave it open with `continue_final_message`. For content
+/// parts, this is the last text part.
+fn final_message_text(messages: &[IndexMap<String, MessageContent>]) -> Result<String> {
+    let Some(last) = messages.last() else {
+        anyhow::bail!("`continue_final_message` requires at least one message.");
+    };
+    match last.get("role") {
+        Some(Either::Left(role)) if role == "assistant" => {}
+        _ => anyhow::bail!(
+            "`continue_final_message` requires the final message to be from the assistant."
+        ),
+    }
+    match last.get("content") {
+        Some(Either::Left(content)) => Ok(content.clone()),
+        Some(Either::Right(parts)) => parts
+            .iter()
+            .rev()
+            .find_map(|part| part.get("text").and_then(|text| text.as_str()))
+            .map(ToString::to_string)
+            .context("`continue_final_message` requires the final message to contain text."),
+        None => {
+            anyhow::bail!("`continue_final_message` requires the final message to have content.")
+        }
+    }
+}
+
+/// Cut the rendered prompt off just after the final message, removing whatever the template uses
+/// to close the turn. This follows Hugging Face: templates which trim the message are handled by
+/// searching for the stripped text.
+fn truncate_after_final_message(rendered: &str, final_message: &str) -> Result<String> {
+    let stripped = final_message.trim();
+    let Some(loc) = rendered.rfind(stripped) else {
+        anyhow::bail!("`continue_final_message` is set but the final message does not appear in the prompt after applying the chat template. This can happen if the chat template deletes portions of the final message.");
+    };
+    let lstripped = final_message.trim_start();
+    let end = if rendered[loc..].starts_with(lstripped) {
+        // The template keeps trailing whitespace, or there is none.
+        loc + lstripped.len()
+    } else {
+        loc + stripped.len()
+    };
+    Ok(rendered[..end].to_string())
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn apply_chat_template_to(
     messages: Vec<IndexMap<String, MessageContent>>,
     add_generation_prompt: bool,
+    continue_final_message: bool,
     template: &ChatTemplateValue,
     bos_tok: Option<String>,
     eos_tok: Option<String>,
     unk_tok: Option<String>,
     tools: Vec<Tool>,
+    chat_template_kwargs: Option<HashMap<String, serde_json::Value>>,
 ) -> Result<String> {
+    let final_message = if continue_final_message {
+        if add_generation_prompt {
+            anyhow::bail!("`continue_final_message` and `add_generation_prompt` are mutually exclusive: the final message cannot be continued if a new turn is opened.");
+        }
+        Some(final_message_text(&messages)?)
+    } else {
+        None
+    };
+
     let mut env = Environment::new();
 
     // enable python methods such as .strip()
-    env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+    env.set_unknown_method_callback(unknown_method_callback);
 
     // https://github.com/huggingface/transformers/blob/76a33a10923ccc1074917f6b6a1e719e626b7dc9/src/transformers/tokenization_utils_base.py#L1842
     env.set_lstrip_blocks(true);
@@ -249,25 +591,16 @@ pub fn apply_chat_template_to(
         new_messages.push(new_message);
     }
 
+    let chat_template_kwargs = chat_template_kwargs.unwrap_or_default();
+    let has_documents = chat_template_kwargs
+        .get("documents")
+        .is_some_and(|documents| !documents.is_null());
+
     let template = match &template.0 {
         Either::Left(x) => x.clone(),
-        Either::Right(map) => {
-            let mut template = "".to_string();
-            for t in map {
-                if t.contains_key("tool_use") && !tools.is_empty() {
-                    template = t["tool_use"].clone();
-                    break;
-                } else if t.contains_key("default") {
-                    template = t["default"].clone();
-                    break;
-                }
-            }
-            if template.is_empty() {
-                anyhow::bail!("Chat template does not contain a `tool_use` or `default` key. Please ensure it contains at least a `default` key, although `tool_use` should be specified for using tools.");
-            }
-            template
-        }
+        Either::Right(map) => select_named_template(map, !tools.is_empty(), has_documents)?,
     };
+    let template = strip_generation_blocks(&template);
 
     env.add_template("chat_template", &template)?;
     env.add_function("raise_exception", raise_exception);
@@ -278,24 +611,34 @@ pub fn apply_chat_template_to(
     let date = chrono::Utc::now();
     let date_string = date.format("%d, %B, %Y").to_string();
 
-    if tools.is_empty() {
-        Ok(tmpl.render(context! {
-            messages => new_messages,
-            add_generation_prompt => add_generation_prompt,
-            bos_token => bos_tok,
-            eos_token => eos_tok,
-            unk_token => unk_tok,
-            date_string => date_string,
-        })?)
-    } else {
-        Ok(tmpl.render(context! {
-            messages => new_messages,
-            add_generation_prompt => add_generation_prompt,
-            bos_token => bos_tok,
-            eos_token => eos_tok,
-            unk_token => unk_tok,
-            tools => tools,
-            date_string => date_string,
-        })?)
+    // As in Hugging Face, extra kwargs may override the special tokens but not the messages,
+    // tools or generation prompt.
+    let mut ctx: IndexMap<String, Value> = IndexMap::new();
+    ctx.insert("bos_token".to_string(), Value::from(bos_tok));
+    ctx.insert("eos_token".to_string(), Value::from(eos_tok));
+    ctx.insert("unk_token".to_string(), Value::from(unk_tok));
+    ctx.insert("date_string".to_string(), Value::from(date_string));
+    ctx.insert("documents".to_string(), Value::from(()));
+    for (k, v) in chat_template_kwargs {
+        ctx.insert(k, Value::from_serialize(&v));
+    }
+    ctx.insert("messages".to_string(), Value::from_serialize(&new_messages));
+    ctx.insert(
+        "add_generation_prompt".to_string(),
+        Value::from(add_generation_prompt),
+    );
+    ctx.insert(
+        "tools".to_string(),
+        if tools.is_empty() {
+            Value::from(())
+        } else {
+            Value::from_serialize(&tools)
+        },
+    );
+
+    let rendered = tmpl.render(Value::from_iter(ctx))?;
+    match final_message {
+        Some(final_message) => truncate_after_final_message(&rendered, &final_message),
+        None => Ok(rendered),
     }
 }
@@ -3,7 +3,7 @@ use super::{
     AdapterActivationMixin, AnyMoePipelineMixin, Cache, CacheManagerMixin, DiffusionLoaderType,
     DiffusionModel, DiffusionModelLoader, EitherCache, FluxLoader, ForwardInputsResult,
     GeneralMetadata, IsqPipelineMixin, Loader, MetadataMixin, ModelCategory, ModelKind, ModelPaths,
-    PreProcessingMixin, Processor, TokenSource,
+    PreProcessingMixin, Processor, StableDiffusionLoader, TokenSource,
 };
 use crate::device_map::DeviceMapper;
 use crate::diffusion_models::processor::{DiffusionProcessor, ModelInputs};
@@ -70,6 +70,8 @@ impl DiffusionLoaderBuilder {
         let loader: Box<dyn DiffusionModelLoader> = match loader {
             DiffusionLoaderType::Flux => Box::new(FluxLoader { offload: false }),
             DiffusionLoaderType::FluxOffloaded => Box::new(FluxLoader { offload: true }),
+            DiffusionLoaderType::StableDiffusion => Box::new(StableDiffusionLoader { xl: false }),
+            DiffusionLoaderType::StableDiffusionXl => Box::new(StableDiffusionLoader { xl: true }),
         };
         Box::new(DiffusionLoader {
             inner: loader,
@@ -261,15 +263,20 @@ impl IsqPipelineMixin for DiffusionPipeline {
 }
 
 impl CacheManagerMixin for DiffusionPipeline {
-    fn clone_in_cache(&self, _seqs: &mut [&mut Sequence]) {}
-    fn clone_out_cache(&self, _seqs: &mut [&mut Sequence]) {}
+    fn clone_in_cache(&self, _seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
+        Ok(())
+    }
+    fn clone_out_cache(&self, _seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
+        Ok(())
+    }
     fn set_none_cache(
         &self,
         _seqs: &mut [&mut Sequence],
         _reset_non_granular: bool,
         _modify_draft_cache: bool,
         _load_preallocated_cache: bool,
-    ) {
+    ) -> candle_core::Result<()> {
+        Ok(())
     }
     fn cache(&self) -> &EitherCache {
         &self.dummy_cache
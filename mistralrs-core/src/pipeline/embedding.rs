@@ -0,0 +1,588 @@
+use super::loaders::{EmbeddingModelPaths, EmbeddingModelPathsInner};
+use super::{
+    AdapterActivationMixin, AnyMoePipelineMixin, BertLoader, Cache, CacheManagerMixin, EitherCache,
+    EmbeddingLoaderType, EmbeddingModel, EmbeddingModelLoader, ForwardInputsResult,
+    GeneralMetadata, IsqPipelineMixin, LlamaEmbeddingLoader, Loader, MetadataMixin,
+    MistralEmbeddingLoader, ModelCategory, ModelKind, ModelPaths, ModernBertLoader,
+    PreProcessingMixin, Processor, Qwen2EmbeddingLoader, TokenSource, XLMRobertaLoader,
+};
+use crate::device_map::DeviceMapper;
+use crate::embedding_models::processor::{EmbeddingProcessor, ModelInputs};
+use crate::embedding_models::{
+    pool, ClassifierConfig, EmbeddingInputs, EmbeddingOutput, EmbeddingParams, EmbeddingPooling,
+    PoolingConfig, RerankParams,
+};
+use crate::pipeline::ChatTemplate;
+use crate::prefix_cacher_v2::PrefixCacheManagerV2;
+use crate::sequence::Sequence;
+use crate::utils::tokenizer::get_tokenizer;
+use crate::utils::varbuilder_utils::DeviceForLoadTensor;
+use crate::utils::{tokens::get_token, varbuilder_utils::from_mmaped_safetensors};
+use crate::{
+    ClassificationResponse, ClassificationResult, DeviceMapSetting, EmbeddingData,
+    EmbeddingResponse, EmbeddingUsage, PagedAttentionConfig, Pipeline, RerankDocument,
+    RerankResponse, RerankResult, TryIntoDType,
+};
+use anyhow::Result;
+use candle_core::{DType, Device, Tensor, D};
+use hf_hub::{api::sync::ApiBuilder, Repo, RepoType};
+use indicatif::MultiProgress;
+use mistralrs_quant::IsqType;
+use rand_isaac::Isaac64Rng;
+use std::any::Any;
+use std::sync::Arc;
+use tokenizers::{Encoding, Tokenizer, TruncationParams};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Maximum number of inputs run through the encoder at once.
+const EMBEDDING_BATCH_SIZE: usize = 32;
+
+pub struct EmbeddingPipeline {
+    model: Box<dyn EmbeddingModel + Send + Sync>,
+    tokenizer: Arc<Tokenizer>,
+    pooling: EmbeddingPooling,
+    classifier: ClassifierConfig,
+    chat_template: Option<Arc<ChatTemplate>>,
+    model_id: String,
+    metadata: Arc<GeneralMetadata>,
+    dummy_cache: EitherCache,
+}
+
+/// A loader for an embedding, reranker or sequence classification (non-quantized) model.
+pub struct EmbeddingLoader {
+    inner: Box<dyn EmbeddingModelLoader>,
+    model_id: String,
+    kind: ModelKind,
+}
+
+#[derive(Default)]
+/// A builder for a loader for an embedding, reranker or sequence classification (non-quantized) model.
+pub struct EmbeddingLoaderBuilder {
+    model_id: Option<String>,
+    kind: ModelKind,
+}
+
+impl EmbeddingLoaderBuilder {
+    pub fn new(model_id: Option<String>) -> Self {
+        Self {
+            model_id,
+            kind: ModelKind::Normal,
+        }
+    }
+
+    pub fn build(self, loader: EmbeddingLoaderType) -> Box<dyn Loader> {
+        let loader: Box<dyn EmbeddingModelLoader> = match loader {
+            EmbeddingLoaderType::Bert => Box::new(BertLoader),
+            EmbeddingLoaderType::XLMRoberta => Box::new(XLMRobertaLoader),
+            EmbeddingLoaderType::ModernBert => Box::new(ModernBertLoader),
+            EmbeddingLoaderType::Llama => Box::new(LlamaEmbeddingLoader),
+            EmbeddingLoaderType::Mistral => Box::new(MistralEmbeddingLoader),
+            EmbeddingLoaderType::Qwen2 => Box::new(Qwen2EmbeddingLoader),
+        };
+        Box::new(EmbeddingLoader {
+            inner: loader,
+            model_id: self.model_id.unwrap(),
+            kind: self.kind,
+        })
+    }
+}
+
+impl Loader for EmbeddingLoader {
+    #[allow(clippy::type_complexity, clippy::too_many_arguments)]
+    fn load_model_from_hf(
+        &self,
+        revision: Option<String>,
+        token_source: TokenSource,
+        dtype: &dyn TryIntoDType,
+        device: &Device,
+        silent: bool,
+        mapper: DeviceMapSetting,
+        in_situ_quant: Option<IsqType>,
+        paged_attn_config: Option<PagedAttentionConfig>,
+    ) -> Result<Arc<Mutex<dyn Pipeline + Send + Sync>>> {
+        let paths: anyhow::Result<Box<dyn ModelPaths>> = {
+            let api = ApiBuilder::new()
+                .with_progress(!silent)
+                .with_token(get_token(&token_source)?)
+                .build()?;
+            let revision = revision.unwrap_or("main".to_string());
+            let api = api.repo(Repo::with_revision(
+                self.model_id.clone(),
+                RepoType::Model,
+                revision.clone(),
+            ));
+            let model_id = std::path::Path::new(&self.model_id);
+            Ok(Box::new(EmbeddingModelPaths(
+                self.inner.get_model_paths(&api, model_id)?,
+            )))
+        };
+        self.load_model_from_path(
+            &paths?,
+            dtype,
+            device,
+            silent,
+            mapper,
+            in_situ_quant,
+            paged_attn_config,
+        )
+    }
+
+    #[allow(clippy::type_complexity, clippy::too_many_arguments)]
+    fn load_model_from_path(
+        &self,
+        paths: &Box<dyn ModelPaths>,
+        dtype: &dyn TryIntoDType,
+        device: &Device,
+        silent: bool,
+        mapper: DeviceMapSetting,
+        in_situ_quant: Option<IsqType>,
+        paged_attn_config: Option<PagedAttentionConfig>,
+    ) -> Result<Arc<Mutex<dyn Pipeline + Send + Sync>>> {
+        let EmbeddingModelPathsInner {
+            config_filename,
+            tokenizer_filename,
+            pooling_config_filename,
+            template_filename,
+            filenames,
+        } = &paths
+            .as_ref()
+            .as_any()
+            .downcast_ref::<EmbeddingModelPaths>()
+            .expect("Path downcast failed.")
+            .0;
+
+        if matches!(mapper, DeviceMapSetting::Map(_)) {
+            anyhow::bail!("Device mapping is not supported for embedding models.")
+        }
+
+        if in_situ_quant.is_some() {
+            anyhow::bail!("ISQ is not supported for embedding models.");
+        }
+
+        if paged_attn_config.is_some() {
+            warn!("PagedAttention is not supported for embedding models, disabling it.");
+        }
+
+        let config = std::fs::read_to_string(config_filename)?;
+        let pooling = match pooling_config_filename {
+            Some(filename) => {
+                serde_json::from_str::<PoolingConfig>(&std::fs::read_to_string(filename)?)?
+                    .pooling()
+            }
+            None => EmbeddingPooling::Mean,
+        };
+        let classifier: ClassifierConfig = serde_json::from_str(&config)?;
+        let chat_template = match template_filename {
+            Some(filename) => Some(Arc::new(serde_json::from_str::<ChatTemplate>(
+                &std::fs::read_to_string(filename)?,
+            )?)),
+            None => None,
+        };
+
+        let mapper = mapper.into_mapper(usize::MAX, device, None)?;
+        let dtype = mapper.get_min_dtype(dtype)?;
+
+        let model = match self.kind {
+            ModelKind::Normal => {
+                let vb = from_mmaped_safetensors(
+                    filenames.clone(),
+                    Vec::new(),
+                    Some(dtype),
+                    device,
+                    vec![None],
+                    silent,
+                    None,
+                    |_| true,
+                    Arc::new(|_| DeviceForLoadTensor::Base),
+                )?;
+
+                self.inner.load(
+                    &config,
+                    vb,
+                    crate::pipeline::NormalLoadingMetadata {
+                        mapper,
+                        loading_isq: false,
+                        real_device: device.clone(),
+                        multi_progress: Arc::new(MultiProgress::new()),
+                    },
+                )?
+            }
+            _ => unreachable!(),
+        };
+
+        let max_seq_len = model.max_seq_len();
+        let mut tokenizer = get_tokenizer(tokenizer_filename, None)?;
+        // Inputs are padded per batch, and truncated to what the position embeddings allow.
+        tokenizer.with_padding(None);
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: max_seq_len,
+                ..Default::default()
+            }))
+            .map_err(anyhow::Error::msg)?;
+
+        info!(
+            "Loaded {} model with {pooling:?} pooling.",
+            if model.is_reranker() {
+                "reranker"
+            } else {
+                "embedding"
+            }
+        );
+
+        Ok(Arc::new(Mutex::new(EmbeddingPipeline {
+            model,
+            tokenizer: Arc::new(tokenizer),
+            pooling,
+            classifier,
+            chat_template,
+            model_id: self.model_id.clone(),
+            metadata: Arc::new(GeneralMetadata {
+                max_seq_len,
+                tok_env: None,
+                is_xlora: false,
+                no_prefix_cache: true,
+                num_hidden_layers: 1, // Only used for caching, which embedding models do not use.
+                eos_tok: vec![],
+                kind: self.kind.clone(),
+                no_kv_cache: true,
+                activation_dtype: dtype,
+                sliding_window: None,
+                cache_config: None,
+                cache_engines: None,
+                prompt_chunksize: None,
+                model_metadata: None,
+            }),
+            dummy_cache: EitherCache::Full(Cache::new(0, false)),
+        })))
+    }
+
+    fn get_id(&self) -> String {
+        self.model_id.to_string()
+    }
+
+    fn get_kind(&self) -> ModelKind {
+        self.kind.clone()
+    }
+}
+
+impl EmbeddingPipeline {
+    /// Right pad a batch of encodings to (input_ids, token_type_ids, attention_mask).
+    fn batch(&self, encodings: &[Encoding]) -> candle_core::Result<(Tensor, Tensor, Tensor)> {
+        let max_len = encodings.iter().map(|e| e.len()).max().unwrap_or(0);
+        let pad_token_id = self.model.pad_token_id();
+        let mut input_ids = Vec::with_capacity(encodings.len() * max_len);
+        let mut token_type_ids = Vec::with_capacity(encodings.len() * max_len);
+        let mut attention_mask = Vec::with_capacity(encodings.len() * max_len);
+        for encoding in encodings {
+            let n_pad = max_len - encoding.len();
+            input_ids.extend_from_slice(encoding.get_ids());
+            input_ids.extend(std::iter::repeat(pad_token_id).take(n_pad));
+            token_type_ids.extend_from_slice(encoding.get_type_ids());
+            token_type_ids.extend(std::iter::repeat(0).take(n_pad));
+            attention_mask.extend(std::iter::repeat(1u32).take(encoding.len()));
+            attention_mask.extend(std::iter::repeat(0).take(n_pad));
+        }
+        let shape = (encodings.len(), max_len);
+        let device = self.model.device();
+        Ok((
+            Tensor::from_vec(input_ids, shape, device)?,
+            Tensor::from_vec(token_type_ids, shape, device)?,
+            Tensor::from_vec(attention_mask, shape, device)?,
+        ))
+    }
+
+    fn embed(
+        &self,
+        inputs: &[String],
+        params: &EmbeddingParams,
+    ) -> candle_core::Result<EmbeddingResponse> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(inputs.to_vec(), true)
+            .map_err(candle_core::Error::msg)?;
+
+        let mut data = Vec::with_capacity(encodings.len());
+        for chunk in encodings.chunks(EMBEDDING_BATCH_SIZE) {
+            let (input_ids, token_type_ids, attention_mask) = self.batch(chunk)?;
+            let hidden_states = self
+                .model
+                .forward(&input_ids, &token_type_ids, &attention_mask)?;
+            let mut embeddings = pool(&hidden_states, &attention_mask, self.pooling)?;
+            if let Some(dimensions) = params.dimensions {
+                let hidden_size = embeddings.dim(1)?;
+                if dimensions == 0 || dimensions > hidden_size {
+                    candle_core::bail!(
+                        "`dimensions` must be between 1 and the embedding size {hidden_size}, got {dimensions}."
+                    );
+                }
+                embeddings = embeddings.narrow(1, 0, dimensions)?;
+            }
+            if params.normalize {
+                let norm = embeddings
+                    .sqr()?
+                    .sum_keepdim(D::Minus1)?
+                    .sqrt()?
+                    .clamp(1e-12, f32::MAX)?;
+                embeddings = embeddings.broadcast_div(&norm)?;
+            }
+            for embedding in embeddings.to_vec2::<f32>()? {
+                data.push(EmbeddingData {
+                    object: "embedding".to_string(),
+                    embedding,
+                    index: data.len(),
+                });
+            }
+        }
+
+        let prompt_tokens = encodings.iter().map(|e| e.len()).sum();
+        Ok(EmbeddingResponse {
+            object: "list".to_string(),
+            data,
+            model: self.model_id.clone(),
+            usage: EmbeddingUsage {
+                prompt_tokens,
+                total_tokens: prompt_tokens,
+            },
+        })
+    }
+
+    fn rerank(
+        &self,
+        query: &str,
+        documents: &[String],
+        params: &RerankParams,
+    ) -> candle_core::Result<RerankResponse> {
+        let pairs = documents
+            .iter()
+            .map(|document| (query.to_string(), document.clone()))
+            .collect::<Vec<_>>();
+        let encodings = self
+            .tokenizer
+            .encode_batch(pairs, true)
+            .map_err(candle_core::Error::msg)?;
+
+        let mut scores = Vec::with_capacity(encodings.len());
+        for chunk in encodings.chunks(EMBEDDING_BATCH_SIZE) {
+            let (input_ids, token_type_ids, attention_mask) = self.batch(chunk)?;
+            let hidden_states = self
+                .model
+                .forward(&input_ids, &token_type_ids, &attention_mask)?;
+            let logits = self
+                .model
+                .classify(&hidden_states, &attention_mask)?
+                .to_dtype(DType::F32)?;
+            // A single logit is a relevance score, otherwise the last label is "relevant".
+            let relevance = if logits.dim(1)? == 1 {
+                candle_nn::ops::sigmoid(&logits)?.squeeze(1)?
+            } else {
+                let n_labels = logits.dim(1)?;
+                candle_nn::ops::softmax_last_dim(&logits)?
+                    .narrow(1, n_labels - 1, 1)?
+                    .squeeze(1)?
+            };
+            scores.extend(relevance.to_vec1::<f32>()?);
+        }
+
+        let mut results = scores
+            .into_iter()
+            .enumerate()
+            .map(|(index, relevance_score)| RerankResult {
+                index,
+                relevance_score,
+                document: params.return_documents.then(|| RerankDocument {
+                    text: documents[index].clone(),
+                }),
+            })
+            .collect::<Vec<_>>();
+        results.sort_by(|a, b| b.relevance_score.total_cmp(&a.relevance_score));
+        if let Some(top_n) = params.top_n {
+            results.truncate(top_n);
+        }
+
+        let prompt_tokens = encodings.iter().map(|e| e.len()).sum();
+        Ok(RerankResponse {
+            model: self.model_id.clone(),
+            results,
+            usage: EmbeddingUsage {
+                prompt_tokens,
+                total_tokens: prompt_tokens,
+            },
+        })
+    }
+
+    fn classify(&self, inputs: &[(String, bool)]) -> candle_core::Result<ClassificationResponse> {
+        let encodings = inputs
+            .iter()
+            .map(|(text, add_special_tokens)| {
+                self.tokenizer
+                    .encode(text.as_str(), *add_special_tokens)
+                    .map_err(candle_core::Error::msg)
+            })
+            .collect::<candle_core::Result<Vec<_>>>()?;
+
+        let mut labels = Vec::new();
+        let mut data = Vec::with_capacity(encodings.len());
+        for chunk in encodings.chunks(EMBEDDING_BATCH_SIZE) {
+            let (input_ids, token_type_ids, attention_mask) = self.batch(chunk)?;
+            let hidden_states = self
+                .model
+                .forward(&input_ids, &token_type_ids, &attention_mask)?;
+            let logits = self
+                .model
+                .classify(&hidden_states, &attention_mask)?
+                .to_dtype(DType::F32)?;
+            let num_labels = logits.dim(1)?;
+            // A single output is a reward or a binary score, and multi-label heads score each
+            // label on its own.
+            let probs = if num_labels == 1 || self.classifier.is_multi_label() {
+                candle_nn::ops::sigmoid(&logits)?
+            } else {
+                candle_nn::ops::softmax_last_dim(&logits)?
+            };
+            labels = self.classifier.labels(num_labels);
+            for (logits, probs) in logits.to_vec2::<f32>()?.into_iter().zip(probs.to_vec2()?) {
+                let best = probs
+                    .iter()
+                    .enumerate()
+                    .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .map_or(0, |(i, _)| i);
+                data.push(ClassificationResult {
+                    index: data.len(),
+                    label: labels[best].clone(),
+                    reward: (num_labels == 1).then_some(logits[0]),
+                    probs,
+                    logits,
+                });
+            }
+        }
+
+        let prompt_tokens = encodings.iter().map(|e| e.len()).sum();
+        Ok(ClassificationResponse {
+            object: "list".to_string(),
+            model: self.model_id.clone(),
+            labels,
+            data,
+            usage: EmbeddingUsage {
+                prompt_tokens,
+                total_tokens: prompt_tokens,
+            },
+        })
+    }
+}
+
+impl PreProcessingMixin for EmbeddingPipeline {
+    fn get_processor(&self) -> Arc<dyn Processor> {
+        Arc::new(EmbeddingProcessor)
+    }
+    fn get_chat_template(&self) -> Option<Arc<ChatTemplate>> {
+        self.chat_template.clone()
+    }
+    fn get_input_processor_config(&self) -> Option<Arc<dyn Any>> {
+        None
+    }
+}
+
+impl IsqPipelineMixin for EmbeddingPipeline {
+    fn re_isq_model(&mut self, _dtype: IsqType) -> Result<()> {
+        anyhow::bail!("Embedding models do not support ISQ for now.")
+    }
+}
+
+impl CacheManagerMixin for EmbeddingPipeline {
+    fn clone_in_cache(&self, _seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
+        Ok(())
+    }
+    fn clone_out_cache(&self, _seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
+        Ok(())
+    }
+    fn set_none_cache(
+        &self,
+        _seqs: &mut [&mut Sequence],
+        _reset_non_granular: bool,
+        _modify_draft_cache: bool,
+        _load_preallocated_cache: bool,
+    ) -> candle_core::Result<()> {
+        Ok(())
+    }
+    fn cache(&self) -> &EitherCache {
+        &self.dummy_cache
+    }
+}
+
+impl AdapterActivationMixin for EmbeddingPipeline {
+    fn activate_adapters(&mut self, _adapters: Vec<String>) -> Result<usize> {
+        anyhow::bail!("Embedding models do not support adapter activation.");
+    }
+}
+
+impl MetadataMixin for EmbeddingPipeline {
+    fn device(&self) -> Device {
+        self.model.device().clone()
+    }
+    fn get_metadata(&self) -> Arc<GeneralMetadata> {
+        self.metadata.clone()
+    }
+    fn name(&self) -> String {
+        self.model_id.clone()
+    }
+    fn reset_non_granular_state(&self) {}
+    fn tokenizer(&self) -> Option<Arc<Tokenizer>> {
+        Some(self.tokenizer.clone())
+    }
+    fn device_mapper(&self) -> Option<&dyn DeviceMapper> {
+        None
+    }
+}
+
+#[async_trait::async_trait]
+impl Pipeline for EmbeddingPipeline {
+    fn forward_inputs(
+        &mut self,
+        inputs: Box<dyn Any>,
+        return_raw_logits: bool,
+    ) -> candle_core::Result<ForwardInputsResult> {
+        assert!(!return_raw_logits);
+
+        let ModelInputs { inputs } = *inputs.downcast().expect("Downcast failed.");
+        let responses = inputs
+            .iter()
+            .map(|inputs| match inputs {
+                EmbeddingInputs::Embed { inputs, params } => {
+                    self.embed(inputs, params).map(EmbeddingOutput::Embedding)
+                }
+                EmbeddingInputs::Rerank {
+                    query,
+                    documents,
+                    params,
+                } => self
+                    .rerank(query, documents, params)
+                    .map(EmbeddingOutput::Rerank),
+                EmbeddingInputs::Classify { inputs } => {
+                    self.classify(inputs).map(EmbeddingOutput::Classification)
+                }
+            })
+            .collect::<candle_core::Result<Vec<_>>>()?;
+        Ok(ForwardInputsResult::Embedding { responses })
+    }
+    async fn sample_causal_gen(
+        &self,
+        _seqs: &mut [&mut Sequence],
+        _logits: Vec<Tensor>,
+        _prefix_cacher: &mut PrefixCacheManagerV2,
+        _disable_eos_stop: bool,
+        _srng: Arc<std::sync::Mutex<Isaac64Rng>>,
+    ) -> Result<(), candle_core::Error> {
+        candle_core::bail!("`sample_causal_gen` is incompatible with `EmbeddingPipeline`");
+    }
+    fn category(&self) -> ModelCategory {
+        if self.model.is_reranker() {
+            ModelCategory::Reranker
+        } else {
+            ModelCategory::Embedding
+        }
+    }
+}
+
+impl AnyMoePipelineMixin for EmbeddingPipeline {}
@@ -7,7 +7,8 @@ use super::{
 };
 use super::{
     AdapterActivationMixin, AnyMoePipelineMixin, CacheManagerMixin, EitherCache,
-    ForwardInputsResult, IsqPipelineMixin, MetadataMixin, ModelCategory, PreProcessingMixin,
+    ForwardInputsResult, IsqPipelineMixin, KvCacheType, MetadataMixin, ModelCategory,
+    PreProcessingMixin,
 };
 use crate::device_map::DeviceMapper;
 use crate::lora::Ordering;
@@ -70,6 +71,7 @@ pub struct GGMLLoader {
     xlora_model_id: Option<String>,
     xlora_order: Option<Ordering>,
     no_kv_cache: bool,
+    kv_cache_type: KvCacheType,
     chat_template: Option<String>,
     tokenizer_json: Option<String>,
     kind: ModelKind,
@@ -95,6 +97,7 @@ pub struct GGMLLoaderBuilder {
     kind: ModelKind,
     xlora_order: Option<Ordering>,
     no_kv_cache: bool,
+    kv_cache_type: KvCacheType,
     chat_template: Option<String>,
     tokenizer_json: Option<String>,
     tgt_non_granular_index: Option<usize>,
@@ -132,6 +135,11 @@ impl GGMLLoaderBuilder {
         self
     }
 
+    pub fn with_kv_cache_type(mut self, kv_cache_type: KvCacheType) -> Self {
+        self.kv_cache_type = kv_cache_type;
+        self
+    }
+
     fn with_adapter(
         mut self,
         xlora_model_id: String,
@@ -186,6 +194,7 @@ impl GGMLLoaderBuilder {
             kind: self.kind,
             xlora_order: self.xlora_order,
             no_kv_cache: self.no_kv_cache,
+            kv_cache_type: self.kv_cache_type,
             chat_template: self.chat_template,
             tokenizer_json: self.tokenizer_json,
             tgt_non_granular_index: self.tgt_non_granular_index,
@@ -227,6 +236,7 @@ impl GGMLLoader {
             xlora_model_id,
             xlora_order,
             no_kv_cache,
+            kv_cache_type: KvCacheType::Auto,
             chat_template,
             tokenizer_json,
             kind,
@@ -263,6 +273,9 @@ impl Loader for GGMLLoader {
             paged_attn_config = None;
         }
 
+        // Quantized KV caches are only implemented in the PagedAttention kernels.
+        self.kv_cache_type.validate(false)?;
+
         // Apply default prompt size here
         let prompt_chunksize = self
             .config
@@ -359,8 +372,8 @@ impl Loader for GGMLLoader {
         };
         let tok_env = build_tok_env(tokenizer.clone());
         let num_hidden_layers = match model {
-            Model::Llama(ref model) => model.cache.normal().0.len(),
-            Model::XLoraLlama(ref model) => model.cache.full().lock().len(),
+            Model::Llama(ref model) => model.cache.normal()?.0.len(),
+            Model::XLoraLlama(ref model) => model.cache.full()?.lock().len(),
         };
         let eos = calculate_eos_tokens(&chat_template, gen_conf, &tokenizer);
         Ok(Arc::new(Mutex::new(GGMLPipeline {
@@ -457,10 +470,10 @@ impl IsqPipelineMixin for GGMLPipeline {
 }
 
 impl CacheManagerMixin for GGMLPipeline {
-    fn clone_in_cache(&self, seqs: &mut [&mut Sequence]) {
+    fn clone_in_cache(&self, seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
         FullCacheManager.clone_in_cache(self, seqs, false)
     }
-    fn clone_out_cache(&self, seqs: &mut [&mut Sequence]) {
+    fn clone_out_cache(&self, seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
         FullCacheManager.clone_out_cache(self, seqs, false)
     }
     fn set_none_cache(
@@ -470,11 +483,12 @@ impl CacheManagerMixin for GGMLPipeline {
         modify_draft_cache: bool,
 
         load_preallocated_cache: bool,
-    ) {
-        FullCacheManager.set_none_cache(self, seqs, modify_draft_cache, load_preallocated_cache);
+    ) -> candle_core::Result<()> {
+        FullCacheManager.set_none_cache(self, seqs, modify_draft_cache, load_preallocated_cache)?;
         if reset_non_granular {
             self.reset_non_granular_state()
         }
+        Ok(())
     }
     fn cache(&self) -> &EitherCache {
         match self.model {
@@ -515,7 +529,10 @@ impl MetadataMixin for GGMLPipeline {
     }
     fn reset_non_granular_state(&self) {
         if let Some(s) = self.non_granular_state.as_ref() {
-            *self.cache().full().get_scalings_cache() = None;
+            // Non granular state is only used by X-LoRA models, which have a full cache.
+            if let EitherCache::Full(full) = self.cache() {
+                *full.get_scalings_cache() = None;
+            }
             *get_mut_arcmutex!(s.non_granular_index) = 0;
         }
     }
@@ -7,7 +7,8 @@ use super::{
 };
 use super::{
     AdapterActivationMixin, AnyMoePipelineMixin, CacheManagerMixin, EitherCache,
-    ForwardInputsResult, IsqPipelineMixin, MetadataMixin, ModelCategory, PreProcessingMixin,
+    ForwardInputsResult, IsqPipelineMixin, KvCacheType, MetadataMixin, ModelCategory,
+    PreProcessingMixin,
 };
 use crate::device_map::{self, DeviceMapper};
 use crate::gguf::{
@@ -35,6 +36,9 @@ use crate::{
     Pipeline, Topology, TryIntoDType,
 };
 use crate::{
+    models::quantized_deepseek2::ModelWeights as QDeepSeek2,
+    models::quantized_gemma::ModelWeights as QGemma,
+    models::quantized_gemma2::ModelWeights as QGemma2,
     models::quantized_llama::ModelWeights as QLlama,
     models::quantized_phi2::ModelWeights as QPhi,
     models::quantized_phi3::ModelWeights as QPhi3,
@@ -67,6 +71,9 @@ enum Model {
     Phi3(QPhi3),
     Starcoder2(QStarcoder2),
     Qwen2(QQwen2),
+    Gemma(QGemma),
+    Gemma2(QGemma2),
+    DeepSeek2(QDeepSeek2),
 }
 
 pub struct GGUFPipeline {
@@ -88,6 +95,7 @@ pub struct GGUFLoader {
     xlora_model_id: Option<String>,
     xlora_order: Option<Ordering>,
     no_kv_cache: bool,
+    kv_cache_type: KvCacheType,
     chat_template: Option<String>,
     kind: ModelKind,
     tgt_non_granular_index: Option<usize>,
@@ -111,6 +119,7 @@ pub struct GGUFLoaderBuilder {
     kind: ModelKind,
     xlora_order: Option<Ordering>,
     no_kv_cache: bool,
+    kv_cache_type: KvCacheType,
     chat_template: Option<String>,
     tgt_non_granular_index: Option<usize>,
     config: GGUFSpecificConfig,
@@ -150,6 +159,11 @@ impl GGUFLoaderBuilder {
         self
     }
 
+    pub fn with_kv_cache_type(mut self, kv_cache_type: KvCacheType) -> Self {
+        self.kv_cache_type = kv_cache_type;
+        self
+    }
+
     fn with_adapter(
         mut self,
         xlora_model_id: String,
@@ -203,6 +217,7 @@ impl GGUFLoaderBuilder {
             kind: self.kind,
             xlora_order: self.xlora_order,
             no_kv_cache: self.no_kv_cache,
+            kv_cache_type: self.kv_cache_type,
             chat_template: self.chat_template,
             tgt_non_granular_index: self.tgt_non_granular_index,
             quantized_filenames: self.quantized_filenames,
@@ -244,6 +259,7 @@ impl GGUFLoader {
             xlora_model_id,
             xlora_order,
             no_kv_cache,
+            kv_cache_type: KvCacheType::Auto,
             chat_template,
             kind,
             tgt_non_granular_index,
@@ -374,6 +390,11 @@ impl Loader for GGUFLoader {
             paged_attn_config = None;
         }
 
+        // Quantized KV caches are only implemented in the PagedAttention kernels.
+        self.kv_cache_type.validate(
+            paged_attn_config.is_some() && !matches!(self.kind, ModelKind::GgufAdapter { .. }),
+        )?;
+
         let GgufTokenizerConversion {
             tokenizer,
             bos,
@@ -445,6 +466,11 @@ impl Loader for GGUFLoader {
                     Model::Starcoder2(QStarcoder2::try_from(model_config)?)
                 }
                 GGUFArchitecture::Qwen2 => Model::Qwen2(QQwen2::try_from(model_config)?),
+                GGUFArchitecture::Gemma => Model::Gemma(QGemma::try_from(model_config)?),
+                GGUFArchitecture::Gemma2 => Model::Gemma2(QGemma2::try_from(model_config)?),
+                GGUFArchitecture::Deepseek2 => {
+                    Model::DeepSeek2(QDeepSeek2::try_from(model_config)?)
+                }
                 a => bail!("Unsupported architecture `{a:?}` for GGUF"),
             },
             ModelKind::GgufAdapter { adapter, .. } => match arch {
@@ -465,6 +491,7 @@ impl Loader for GGUFLoader {
                 paged_attn_config.mem_cpu,
                 paged_attn_config.block_size,
                 internal_dtype,
+                self.kv_cache_type,
                 model_config,
                 device,
                 &layer_devices,
@@ -505,16 +532,22 @@ impl Loader for GGUFLoader {
             Model::XLoraPhi3(ref p) => p.max_seq_len,
             Model::Starcoder2(ref p) => p.max_seq_len,
             Model::Qwen2(ref p) => p.max_seq_len,
+            Model::Gemma(ref p) => p.max_seq_len,
+            Model::Gemma2(ref p) => p.max_seq_len,
+            Model::DeepSeek2(ref p) => p.max_seq_len,
         };
         let tok_env = build_tok_env(tokenizer.clone());
         let num_hidden_layers = match model {
-            Model::Llama(ref model) => model.cache.normal().0.len(),
-            Model::Phi2(ref model) => model.cache.normal().0.len(),
-            Model::XLoraLlama(ref model) => model.cache.full().lock().len(),
-            Model::Phi3(ref model) => model.cache.normal().0.len(),
-            Model::XLoraPhi3(ref model) => model.cache.full().lock().len(),
-            Model::Starcoder2(ref model) => model.cache.normal().0.len(),
-            Model::Qwen2(ref model) => model.cache.normal().0.len(),
+            Model::Llama(ref model) => model.cache.normal()?.0.len(),
+            Model::Phi2(ref model) => model.cache.normal()?.0.len(),
+            Model::XLoraLlama(ref model) => model.cache.full()?.lock().len(),
+            Model::Phi3(ref model) => model.cache.normal()?.0.len(),
+            Model::XLoraPhi3(ref model) => model.cache.full()?.lock().len(),
+            Model::Starcoder2(ref model) => model.cache.normal()?.0.len(),
+            Model::Qwen2(ref model) => model.cache.normal()?.0.len(),
+            Model::Gemma(ref model) => model.cache.normal()?.0.len(),
+            Model::Gemma2(ref model) => model.cache.normal()?.0.len(),
+            Model::DeepSeek2(ref model) => model.cache.normal()?.0.len(),
         };
 
         if chat_template.bos_token.is_none() && bos.is_some() {
@@ -593,14 +626,14 @@ impl IsqPipelineMixin for GGUFPipeline {
 }
 
 impl CacheManagerMixin for GGUFPipeline {
-    fn clone_in_cache(&self, seqs: &mut [&mut Sequence]) {
+    fn clone_in_cache(&self, seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
         if matches!(self.cache(), EitherCache::Full(_)) {
             FullCacheManager.clone_in_cache(self, seqs, false)
         } else {
             NormalCacheManager.clone_in_cache(self, seqs, false)
         }
     }
-    fn clone_out_cache(&self, seqs: &mut [&mut Sequence]) {
+    fn clone_out_cache(&self, seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
         if matches!(self.cache(), EitherCache::Full(_)) {
             FullCacheManager.clone_out_cache(self, seqs, false)
         } else {
@@ -613,20 +646,21 @@ impl CacheManagerMixin for GGUFPipeline {
         reset_non_granular: bool,
         modify_draft_cache: bool,
         load_preallocated_cache: bool,
-    ) {
+    ) -> candle_core::Result<()> {
         if matches!(self.cache(), EitherCache::Full(_)) {
-            FullCacheManager.set_none_cache(self, seqs, modify_draft_cache, false);
+            FullCacheManager.set_none_cache(self, seqs, modify_draft_cache, false)?;
         } else {
             NormalCacheManager.set_none_cache(
                 self,
                 seqs,
                 modify_draft_cache,
                 load_preallocated_cache,
-            );
+            )?;
         }
         if reset_non_granular {
             self.reset_non_granular_state()
         }
+        Ok(())
     }
     fn cache(&self) -> &EitherCache {
         match self.model {
@@ -637,6 +671,9 @@ impl CacheManagerMixin for GGUFPipeline {
             Model::XLoraPhi3(ref model) => &model.cache,
             Model::Starcoder2(ref model) => &model.cache,
             Model::Qwen2(ref model) => &model.cache,
+            Model::Gemma(ref model) => &model.cache,
+            Model::Gemma2(ref model) => &model.cache,
+            Model::DeepSeek2(ref model) => &model.cache,
         }
     }
 }
@@ -670,6 +707,9 @@ impl MetadataMixin for GGUFPipeline {
             Model::XLoraPhi3(ref model) => model.device.clone(),
             Model::Starcoder2(ref model) => model.device.clone(),
             Model::Qwen2(ref model) => model.device.clone(),
+            Model::Gemma(ref model) => model.device.clone(),
+            Model::Gemma2(ref model) => model.device.clone(),
+            Model::DeepSeek2(ref model) => model.device.clone(),
         }
     }
     fn tokenizer(&self) -> Option<Arc<Tokenizer>> {
@@ -680,7 +720,10 @@ impl MetadataMixin for GGUFPipeline {
     }
     fn reset_non_granular_state(&self) {
         if let Some(s) = self.non_granular_state.as_ref() {
-            *self.cache().full().get_scalings_cache() = None;
+            // Non granular state is only used by X-LoRA models, which have a full cache.
+            if let EitherCache::Full(full) = self.cache() {
+                *full.get_scalings_cache() = None;
+            }
             *get_mut_arcmutex!(s.non_granular_index) = 0;
         }
     }
@@ -769,6 +812,15 @@ impl Pipeline for GGUFPipeline {
             Model::Qwen2(ref model) => {
                 model.forward(&input_ids, &seqlen_offsets, context_lens, paged_attn_meta)?
             }
+            Model::Gemma(ref model) => {
+                model.forward(&input_ids, &seqlen_offsets, context_lens, paged_attn_meta)?
+            }
+            Model::Gemma2(ref model) => {
+                model.forward(&input_ids, &seqlen_offsets, context_lens, paged_attn_meta)?
+            }
+            Model::DeepSeek2(ref model) => {
+                model.forward(&input_ids, &seqlen_offsets, context_lens, paged_attn_meta)?
+            }
         };
         if return_raw_logits {
             Ok(ForwardInputsResult::RawLogits { logits })
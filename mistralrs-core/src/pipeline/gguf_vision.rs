@@ -0,0 +1,529 @@
+use super::vision::VisionPipeline;
+use super::{
+    get_model_paths, get_xlora_paths, GeneralMetadata, KvCacheType, LLaVALoader, LLaVANextLoader,
+    Loader, MiniCpmOLoader, ModelKind, ModelPaths, QuantizationKind, Qwen2VLLoader, TokenSource,
+    VisionLoaderType, VisionModel, VisionModelLoader, XLoraPaths,
+};
+use crate::device_map::{self, DeviceMapper};
+use crate::gguf::{Content, GGUFArchitecture, Mmproj, ProjectorType};
+use crate::lora::Ordering;
+use crate::paged_attention::{
+    calculate_cache_config, AttentionImplementation, CacheEngine, ModelConfigLike,
+};
+use crate::pipeline::chat_template::{calculate_eos_tokens, GenerationConfig};
+use crate::pipeline::get_chat_template;
+use crate::pipeline::llg::build_tok_env;
+use crate::pipeline::loaders::{AutoDeviceMapParams, DeviceMappedModelLoader};
+use crate::utils::gguf_metadata::{ContentConfig, GgufDeviceMapLoaderInner};
+use crate::utils::model_config as ModelConfig;
+use crate::utils::tokenizer::get_tokenizer;
+use crate::vision_models::llava::config::Config as LLaVAConfig;
+use crate::vision_models::llava::llava_llm::QLlama;
+use crate::vision_models::llava15::Model as LLaVA;
+use crate::vision_models::llava_next::Model as LLaVANext;
+use crate::vision_models::minicpmo::{MiniCpmOConfig, MiniCpmOModel};
+use crate::vision_models::preprocessor_config::PreProcessorConfig;
+use crate::vision_models::processor_config::ProcessorConfig;
+use crate::vision_models::qwen2vl::{Config as Qwen2VLConfig, Qwen2VLModel};
+use crate::{
+    get_paths_gguf, models::quantized_llama::ModelWeights as QLlamaWeights, models::qwen2,
+    utils::tokens::get_token, DeviceMapSetting, GGUFSpecificConfig, LocalModelPaths,
+    PagedAttentionConfig, Pipeline, TryIntoDType,
+};
+use anyhow::{Context, Result};
+use candle_core::Device;
+use hf_hub::{api::sync::ApiBuilder, Repo, RepoType};
+use mistralrs_quant::IsqType;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Loader for a vision model distributed as a GGUF language model and a llama.cpp `mmproj` file
+/// holding the vision encoder and projector.
+pub struct GGUFVisionLoader {
+    inner: Box<dyn VisionModelLoader>,
+    loader_type: VisionLoaderType,
+    model_id: Option<String>,
+    quantized_model_id: String,
+    quantized_filenames: Vec<String>,
+    mmproj_filename: String,
+    xlora_model_id: Option<String>,
+    xlora_order: Option<Ordering>,
+    kv_cache_type: KvCacheType,
+    chat_template: Option<String>,
+    kind: ModelKind,
+    config: GGUFSpecificConfig,
+}
+
+/// A builder for a GGUF vision loader.
+pub struct GGUFVisionLoaderBuilder {
+    tok_model_id: String,
+    quantized_model_id: String,
+    quantized_filenames: Vec<String>,
+    mmproj_filename: String,
+    kv_cache_type: KvCacheType,
+    chat_template: Option<String>,
+    config: GGUFSpecificConfig,
+}
+
+impl GGUFVisionLoaderBuilder {
+    /// Create a loader builder for a GGUF vision model. `tok_model_id` is the model ID of the original
+    /// (non-GGUF) model, where the `config.json`, `preprocessor_config.json` and tokenizer files
+    /// are found. The `mmproj_filename` is found in the `quantized_model_id` along with the
+    /// `quantized_filenames`.
+    pub fn new(
+        chat_template: Option<String>,
+        tok_model_id: String,
+        quantized_model_id: String,
+        quantized_filenames: Vec<String>,
+        mmproj_filename: String,
+        config: GGUFSpecificConfig,
+    ) -> Self {
+        Self {
+            tok_model_id,
+            quantized_model_id,
+            quantized_filenames,
+            mmproj_filename,
+            kv_cache_type: KvCacheType::Auto,
+            chat_template,
+            config,
+        }
+    }
+
+    pub fn with_kv_cache_type(mut self, kv_cache_type: KvCacheType) -> Self {
+        self.kv_cache_type = kv_cache_type;
+        self
+    }
+
+    pub fn build(self, loader: VisionLoaderType) -> Result<Box<dyn Loader>> {
+        let inner: Box<dyn VisionModelLoader> = match loader {
+            VisionLoaderType::LLaVA => Box::new(LLaVALoader),
+            VisionLoaderType::LLaVANext => Box::new(LLaVANextLoader),
+            VisionLoaderType::Qwen2VL => Box::new(Qwen2VLLoader),
+            VisionLoaderType::MiniCpmO => Box::new(MiniCpmOLoader),
+            other => anyhow::bail!(
+                "Vision architecture `{other:?}` cannot be loaded from GGUF, only `llava`, `llava-next`, `qwen2vl` and `minicpmo` are supported."
+            ),
+        };
+        Ok(Box::new(GGUFVisionLoader {
+            inner,
+            loader_type: loader,
+            model_id: Some(self.tok_model_id),
+            quantized_model_id: self.quantized_model_id,
+            quantized_filenames: self.quantized_filenames,
+            mmproj_filename: self.mmproj_filename,
+            xlora_model_id: None,
+            xlora_order: None,
+            kv_cache_type: self.kv_cache_type,
+            chat_template: self.chat_template,
+            kind: ModelKind::GgufQuantized {
+                quant: QuantizationKind::Gguf,
+            },
+            config: self.config,
+        }))
+    }
+}
+
+impl Loader for GGUFVisionLoader {
+    #[allow(clippy::type_complexity, clippy::too_many_arguments)]
+    fn load_model_from_hf(
+        &self,
+        revision: Option<String>,
+        token_source: TokenSource,
+        dtype: &dyn TryIntoDType,
+        device: &Device,
+        silent: bool,
+        mapper: DeviceMapSetting,
+        in_situ_quant: Option<IsqType>,
+        paged_attn_config: Option<PagedAttentionConfig>,
+    ) -> Result<Arc<Mutex<dyn Pipeline + Send + Sync>>> {
+        // The mmproj file is fetched with the GGUF files and is always the last weight file.
+        let mut quantized_filenames = self.quantized_filenames.clone();
+        quantized_filenames.push(self.mmproj_filename.clone());
+        let paths: anyhow::Result<Box<dyn ModelPaths>> = get_paths_gguf!(
+            LocalModelPaths,
+            &token_source,
+            revision,
+            self,
+            self.quantized_model_id.clone(),
+            quantized_filenames,
+            silent
+        );
+        self.load_model_from_path(
+            &paths?,
+            dtype,
+            device,
+            silent,
+            mapper,
+            in_situ_quant,
+            paged_attn_config,
+        )
+    }
+
+    #[allow(clippy::type_complexity, clippy::too_many_arguments)]
+    fn load_model_from_path(
+        &self,
+        paths: &Box<dyn ModelPaths>,
+        dtype: &dyn TryIntoDType,
+        device: &Device,
+        silent: bool,
+        mut mapper: DeviceMapSetting,
+        in_situ_quant: Option<IsqType>,
+        mut paged_attn_config: Option<PagedAttentionConfig>,
+    ) -> Result<Arc<Mutex<dyn Pipeline + Send + Sync>>> {
+        if in_situ_quant.is_some() {
+            anyhow::bail!(
+                "You are trying to in-situ quantize a GGUF model. This will not do anything."
+            );
+        }
+        let config = fs::read_to_string(paths.get_config_filename()).with_context(|| {
+            format!(
+                "GGUF vision models need the `config.json` of the original model at `{}`",
+                self.model_id.as_ref().unwrap()
+            )
+        })?;
+
+        if !self.inner.supports_paged_attention() {
+            paged_attn_config = None;
+        }
+
+        let (mmproj_filename, filenames) = paths
+            .get_weight_filenames()
+            .split_last()
+            .context("Expected the mmproj file after the GGUF files.")?;
+        let mut readers = Vec::new();
+        for filename in filenames {
+            readers.push(std::fs::File::open(filename)?);
+        }
+        let mut readers = readers.iter_mut().collect::<Vec<_>>();
+
+        let mut model = Content::from_readers(&mut readers)?;
+        if !silent {
+            model.print_metadata()?;
+        }
+        let (expected_arch, expected_projector) = match self.loader_type {
+            VisionLoaderType::LLaVA | VisionLoaderType::LLaVANext => {
+                (GGUFArchitecture::Llama, ProjectorType::Mlp)
+            }
+            VisionLoaderType::Qwen2VL => (GGUFArchitecture::Qwen2vl, ProjectorType::Qwen2VLMerger),
+            VisionLoaderType::MiniCpmO => (GGUFArchitecture::Qwen2, ProjectorType::Resampler),
+            _ => unreachable!(),
+        };
+        let arch = model.arch();
+        if arch != expected_arch {
+            anyhow::bail!(
+                "`{:?}` GGUF models need a `{expected_arch}` language model, got `{arch}`.",
+                self.loader_type
+            );
+        }
+
+        // If auto, convert to Map
+        let num_layers = model.get_metadata()[&format!("{arch}.block_count")].to_u32()? as usize;
+        if let DeviceMapSetting::Auto(params) = mapper.clone() {
+            let devices = device_map::get_all_similar_devices(device)?;
+            // Initial dtype
+            let dtype = dtype.try_into_dtype(&devices.iter().collect::<Vec<_>>())?;
+
+            let model = GgufDeviceMapLoaderInner {
+                model: &model,
+                arch,
+            };
+            // Only the language model is mapped, so size it like a text model.
+            let text_params = AutoDeviceMapParams::Text {
+                max_seq_len: params.max_seq_len(),
+                max_batch_size: params.max_batch_size(),
+            };
+
+            let layer_sizes_in_bytes =
+                model.layer_sizes_in_bytes("this is a dummy config!", dtype, 1)?;
+            // The vision encoder and projector stay on the main device.
+            let non_mapped_size_in_bytes =
+                model.non_mapped_size_in_bytes("this is a dummy config!", dtype, 1)?
+                    + usize::try_from(fs::metadata(mmproj_filename)?.len())?;
+            let total_model_size_in_bytes =
+                layer_sizes_in_bytes.iter().sum::<usize>() + non_mapped_size_in_bytes;
+
+            // NOTE: Vision models don't support prompt chunking yet, so just using max seq len
+            let new = model.get_device_layers(
+                "this is a dummy config!",
+                num_layers,
+                layer_sizes_in_bytes,
+                non_mapped_size_in_bytes,
+                total_model_size_in_bytes,
+                &devices,
+                dtype,
+                &text_params,
+                params.max_seq_len(),
+                paged_attn_config.as_ref(),
+            )?;
+            mapper = DeviceMapSetting::Map(new);
+        }
+
+        let pipeline_mapper =
+            mapper.into_mapper(num_layers, device, self.config.topology.as_ref())?;
+        let llm_mapper = mapper.into_mapper(num_layers, device, self.config.topology.as_ref())?;
+        let mapper = mapper.into_mapper(num_layers, device, self.config.topology.as_ref())?;
+        let mut layer_devices = Vec::new();
+        for layer in 0..num_layers {
+            let device = mapper.device_for(layer, false).cloned();
+            layer_devices.push(device);
+        }
+
+        // TODO: PagedAttention is not supported with CPU for now.
+        // This check is not really necessary because `get_device_layers` should prevent it.
+        let mapping_uses_cpu = mapper.get_unique_devices().iter().any(Device::is_cpu);
+        if mapping_uses_cpu {
+            warn!("Device mapping contains a mix of GPU and CPU. There is no CPU support for PagedAttention, disabling PagedAttention.");
+            paged_attn_config = None;
+        }
+
+        // Quantized KV caches are only implemented in the PagedAttention kernels.
+        self.kv_cache_type.validate(paged_attn_config.is_some())?;
+
+        let internal_dtype = mapper.get_min_dtype(dtype)?;
+        let attention_mechanism = if paged_attn_config.is_some() {
+            AttentionImplementation::PagedAttention
+        } else {
+            AttentionImplementation::Eager
+        };
+
+        let mmproj = Mmproj::from_path(mmproj_filename, device)?;
+        if mmproj.projector_type != expected_projector {
+            anyhow::bail!(
+                "`{:?}` GGUF models need an mmproj file with a `{expected_projector}` projector, got `{}`.",
+                self.loader_type,
+                mmproj.projector_type
+            );
+        }
+        let check_num_layers = |expected: usize| {
+            if num_layers != expected {
+                anyhow::bail!(
+                    "The GGUF language model has {num_layers} layers, but the `config.json` of `{}` expects {expected}. Perhaps the files do not match?",
+                    self.model_id.as_ref().unwrap()
+                );
+            }
+            Ok(())
+        };
+
+        let model_config_metadata: ContentConfig = (&model).into();
+        let model: Box<dyn VisionModel + Send + Sync> = match self.loader_type {
+            VisionLoaderType::LLaVA | VisionLoaderType::LLaVANext => {
+                let mut llava_config: LLaVAConfig = serde_json::from_str(&config)?;
+                let vision_config = &llava_config.vision_config;
+                mmproj.check_encoder(
+                    vision_config.hidden_size,
+                    vision_config.intermediate_size,
+                    vision_config.num_attention_heads,
+                    vision_config.patch_size,
+                    None,
+                )?;
+                // llama.cpp drops the encoder layers after the feature layer, so select the same
+                // layer counting from the end of the remaining ones.
+                llava_config.vision_feature_layer += llava_config.vision_config.num_hidden_layers
+                    as isize
+                    - mmproj.block_count as isize;
+                llava_config.vision_config.num_hidden_layers = mmproj.block_count;
+                info!("Model config: {llava_config:?}");
+
+                let sliding_window = model
+                    .get_metadata()
+                    .get(&format!("{arch}.attention.sliding_window"))
+                    .map(|x| x.to_u32())
+                    .transpose()?
+                    .map(|x| x as usize);
+                let llm = QLlamaWeights::try_from(ModelConfig::ModelParams::new(
+                    ModelConfig::ParamsGGUF(
+                        model,
+                        (device, mapper).into(),
+                        attention_mechanism,
+                        internal_dtype,
+                    ),
+                    None,
+                ))?;
+                let llm = Box::new(QLlama::new(
+                    llm,
+                    llm_mapper,
+                    &model_config_metadata,
+                    sliding_window,
+                    internal_dtype,
+                ));
+
+                let vb = mmproj.into_var_builder(internal_dtype, device);
+                if matches!(self.loader_type, VisionLoaderType::LLaVA) {
+                    Box::new(LLaVA::new_with_llm(&llava_config, vb, llm, device.clone())?)
+                } else {
+                    Box::new(LLaVANext::new_with_llm(
+                        &llava_config,
+                        vb,
+                        llm,
+                        device.clone(),
+                    )?)
+                }
+            }
+            VisionLoaderType::Qwen2VL => {
+                let cfg: Qwen2VLConfig = serde_json::from_str(&config)?;
+                let vision_config = &cfg.vision_config;
+                mmproj.check_encoder(
+                    vision_config.embed_dim,
+                    vision_config.intermediate_size(),
+                    vision_config.num_heads,
+                    vision_config.patch_size,
+                    Some(vision_config.depth),
+                )?;
+                check_num_layers(cfg.num_hidden_layers)?;
+                info!("Model config: {cfg:?}");
+
+                let vb = mmproj.into_var_builder(internal_dtype, device);
+                Box::new(Qwen2VLModel::new_gguf(
+                    &cfg,
+                    vb,
+                    &mut model,
+                    llm_mapper,
+                    device,
+                    internal_dtype,
+                )?)
+            }
+            VisionLoaderType::MiniCpmO => {
+                let cfg: MiniCpmOConfig = serde_json::from_str(&config)?;
+                let vision_config = &cfg.vision_config;
+                mmproj.check_encoder(
+                    vision_config.hidden_size,
+                    vision_config.intermediate_size,
+                    vision_config.num_attention_heads,
+                    vision_config.patch_size,
+                    Some(vision_config.num_hidden_layers),
+                )?;
+                check_num_layers(cfg.text_config.num_hidden_layers)?;
+                info!("Model config: {cfg:?}");
+
+                let llm = qwen2::Model::new_gguf(
+                    &cfg.text_config,
+                    &mut model,
+                    llm_mapper,
+                    device,
+                    attention_mechanism,
+                    internal_dtype,
+                )?;
+                let vb = mmproj.into_var_builder(internal_dtype, device);
+                Box::new(MiniCpmOModel::new_gguf(&cfg, vb, llm, device)?)
+            }
+            _ => unreachable!(),
+        };
+        let sliding_window = model.config().sliding_window;
+
+        let preprocessor_config: PreProcessorConfig = serde_json::from_str(
+            &fs::read_to_string(
+                paths
+                    .get_preprocessor_config()
+                    .as_ref()
+                    .expect("Need preprocessor config"),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let processor_config: Option<ProcessorConfig> = paths
+            .get_processor_config()
+            .as_ref()
+            .map(|f| serde_json::from_str(&fs::read_to_string(f).unwrap()).unwrap());
+
+        let processor =
+            self.inner
+                .get_processor(&config, processor_config, preprocessor_config.clone(), None);
+
+        let tokenizer = get_tokenizer(
+            paths.get_tokenizer_filename(),
+            Some(processor.get_special_tokens()),
+        )?;
+
+        let gen_conf: Option<GenerationConfig> = paths.get_gen_conf_filename().map(|f| {
+            serde_json::from_str(&fs::read_to_string(f).unwrap())
+                .expect("bos_token_id/eos_token_id missing in generation_config.json")
+        });
+        let chat_template = get_chat_template(
+            paths,
+            &paths
+                .get_chat_template_json()
+                .as_ref()
+                .map(|x| x.to_string_lossy().to_string())
+                .clone(),
+            &self.chat_template,
+            None,
+        );
+
+        let (cache_config, cache_engine) = if let Some(paged_attn_config) = paged_attn_config {
+            let model_config: &dyn ModelConfigLike = &model_config_metadata;
+            let cache_config = calculate_cache_config(
+                paged_attn_config.mem_gpu,
+                paged_attn_config.mem_cpu,
+                paged_attn_config.block_size,
+                internal_dtype,
+                self.kv_cache_type,
+                model_config,
+                device,
+                &layer_devices,
+                silent,
+            )?;
+            let cache_engine = CacheEngine::new(
+                model_config,
+                &cache_config,
+                internal_dtype,
+                device,
+                layer_devices,
+            )?;
+            (Some(cache_config), Some(cache_engine))
+        } else {
+            (None, None)
+        };
+
+        let max_seq_len = model.max_seq_len();
+        let tok_env = build_tok_env(tokenizer.clone());
+        let num_hidden_layers = model.cache().normal()?.0.len();
+        let eos = calculate_eos_tokens(&chat_template, gen_conf, &tokenizer);
+        let model_metadata = Arc::new(model.config().clone());
+        Ok(Arc::new(Mutex::new(VisionPipeline {
+            model,
+            tokenizer: tokenizer.into(),
+            chat_template: Arc::new(chat_template),
+            model_id: self.model_id.clone().unwrap(),
+            metadata: Arc::new(GeneralMetadata {
+                max_seq_len,
+                tok_env: Some(tok_env),
+                is_xlora: false,
+                num_hidden_layers,
+                eos_tok: eos,
+                kind: self.kind.clone(),
+                no_kv_cache: false,
+                no_prefix_cache: true,
+                activation_dtype: internal_dtype,
+                sliding_window,
+                cache_config,
+                cache_engines: cache_engine.map(|x| vec![x]),
+                prompt_chunksize: self.config.prompt_chunksize,
+                model_metadata: Some(model_metadata),
+            }),
+            processor,
+            prefixer: self.inner.prefixer(),
+            preprocessor_config: Arc::new(preprocessor_config),
+            topology: self.config.topology.clone(),
+            silent,
+            template_filename: paths.get_template_filename().clone(),
+            generation_config: paths.get_gen_conf_filename().cloned(),
+            config,
+            processor_filename: paths.get_processor_config().clone(),
+            preprocessor_filename: paths.get_preprocessor_config().clone(),
+            mapper: pipeline_mapper,
+            imatrix: None,
+        })))
+    }
+
+    fn get_id(&self) -> String {
+        self.model_id.clone().unwrap()
+    }
+
+    fn get_kind(&self) -> ModelKind {
+        self.kind.clone()
+    }
+}
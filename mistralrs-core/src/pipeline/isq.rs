@@ -1,5 +1,4 @@
 use std::{
-    borrow::Cow,
     collections::{HashMap, HashSet},
     fs::File,
     path::PathBuf,
@@ -9,20 +8,25 @@ use std::{
 };
 
 use anyhow::Result;
-use candle_core::{quantized, Context, Device, Tensor};
+use candle_core::{quantized, Context, DType, Device, Tensor, D};
 use indicatif::{MultiProgress, ParallelProgressIterator, ProgressBar, ProgressStyle};
 use itertools::Itertools;
 use mistralrs_quant::{
-    CollectedImatrixData, FP8Linear, GgufMatMul, HqqLayer, IsqType, QuantMethod, QuantizedSerde,
-    QuantizedSerdeType, UnquantLinear,
+    deserialize_uqff_layer, CollectedImatrixData, IsqType, QuantMethod, QuantizedSerde,
+    UqffContents, UqffTensor,
 };
 use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
 use regex::Regex;
+use safetensors::View;
 use serde::Deserialize;
 use tokenizers::Tokenizer;
 use tracing::{info, warn};
 
-use crate::{device_map::DeviceMapper, topology::LayerTopology, Topology};
+use crate::{
+    device_map::DeviceMapper,
+    topology::{IsqCandidateCost, LayerTopology},
+    Topology,
+};
 
 pub(crate) const UQFF_RESIDUAL_SAFETENSORS: &str = "residual.safetensors";
 
@@ -44,6 +48,9 @@ pub(crate) const UQFF_RESIDUAL_SAFETENSORS: &str = "residual.safetensors";
 /// - `HQQ3`
 /// - `HQQ4`
 /// - `HQQ8`
+/// - `FP8`
+/// - `GPTQ4` (requires a calibration file)
+/// - `AWQ4` (requires a calibration file or an imatrix)
 pub fn parse_isq_value(s: &str) -> Result<IsqType, String> {
     let tp = match s.to_lowercase().as_str() {
         "q4_0" => IsqType::Q4_0,
@@ -60,11 +67,13 @@ pub fn parse_isq_value(s: &str) -> Result<IsqType, String> {
         "q8k" => IsqType::Q8K,
         "hqq8" => IsqType::HQQ8,
         "hqq4" => IsqType::HQQ4,
+        "hqq3" => IsqType::HQQ3,
+        "hqq2" => IsqType::HQQ2,
+        "hqq1" => IsqType::HQQ1,
         "fp8" => IsqType::F8E4M3,
-        // "hqq3" => IsqType::HQQ3,
-        // "hqq2" => IsqType::HQQ2,
-        // "hqq1" => IsqType::HQQ1,
-        _ => return Err(format!("ISQ type {s} unknown, choose one of `Q4_0`, `Q4_1`, `Q5_0`, `Q5_1`, `Q8_0`, `Q8_1`, `Q2K`, `Q3K`, `Q4K`, `Q5K`, `Q6K`, `Q8K`, `HQQ8`, `HQQ4`, `FP8`.")),
+        "gptq4" => IsqType::GPTQ4,
+        "awq4" => IsqType::AWQ4,
+        _ => return Err(format!("ISQ type {s} unknown, choose one of `Q4_0`, `Q4_1`, `Q5_0`, `Q5_1`, `Q8_0`, `Q8_1`, `Q2K`, `Q3K`, `Q4K`, `Q5K`, `Q6K`, `Q8K`, `HQQ8`, `HQQ4`, `HQQ3`, `HQQ2`, `HQQ1`, `FP8`, `GPTQ4`, `AWQ4`.")),
     };
     #[cfg(feature = "cuda")]
     {
@@ -82,16 +91,60 @@ pub fn parse_isq_value(s: &str) -> Result<IsqType, String> {
                 | IsqType::Q6K
                 | IsqType::HQQ8
                 | IsqType::HQQ4
-                | IsqType::F8E4M3 // | IsqType::HQQ3
-                                  // | IsqType::HQQ2
-                                  // | IsqType::HQQ1
+                | IsqType::HQQ3
+                | IsqType::HQQ2
+                | IsqType::HQQ1
+                | IsqType::F8E4M3
+                | IsqType::GPTQ4
+                | IsqType::AWQ4
         ) {
-            return Err("ISQ type on CUDA must be one of `Q4_0`, `Q4_1`, `Q5_0`, `Q5_1`, `Q8_0`, `Q2K`, `Q3K`, `Q4K`, `Q5K`, `Q6K`, `HQQ8`, `HQQ4`, `FP8`".to_string());
+            return Err("ISQ type on CUDA must be one of `Q4_0`, `Q4_1`, `Q5_0`, `Q5_1`, `Q8_0`, `Q2K`, `Q3K`, `Q4K`, `Q5K`, `Q6K`, `HQQ8`, `HQQ4`, `HQQ3`, `HQQ2`, `HQQ1`, `FP8`, `GPTQ4`, `AWQ4`".to_string());
         }
     }
     Ok(tp)
 }
 
+/// Check that the calibration data needed by the ISQ types in use is given.
+pub(crate) fn check_calibration_requirements(
+    in_situ_quant: Option<IsqType>,
+    topology: Option<&Topology>,
+    has_imatrix: bool,
+    has_calibration_file: bool,
+) -> Result<()> {
+    let isq_types = topology
+        .into_iter()
+        .flat_map(|topology| topology.0.iter().flatten().filter_map(|layer| layer.isq))
+        .chain(in_situ_quant)
+        .collect::<HashSet<_>>();
+    let gptq = isq_types.contains(&IsqType::GPTQ4);
+    let awq = isq_types.contains(&IsqType::AWQ4);
+    if gptq && !has_calibration_file {
+        anyhow::bail!("GPTQ ISQ requires a calibration file (`calibration_file`).");
+    }
+    if awq && !(has_imatrix || has_calibration_file) {
+        anyhow::bail!("AWQ ISQ requires a calibration file (`calibration_file`) or an `imatrix`.");
+    }
+    Ok(())
+}
+
+/// The ISQ type which [`IsqModel::quantize`] applies to the layers of decoder layer `layer_num`
+/// (`None` for the layers outside of the decoder layers).
+fn layer_isq_type(
+    dtype: Option<IsqType>,
+    topology: Option<&Topology>,
+    layer_num: Option<usize>,
+) -> Option<IsqType> {
+    let layers = topology.map(|x| {
+        x.0.iter()
+            .filter_map(|topo| topo.as_ref().map(|x| x.isq))
+            .collect::<Vec<_>>()
+    });
+    match (layers, layer_num) {
+        (Some(layers), Some(layer)) => layers.get(layer).copied().unwrap_or(dtype),
+        _ => dtype,
+    }
+}
+
 #[derive(Clone, Debug, Copy, Default, Deserialize)]
 pub enum IsqOrganization {
     #[default]
@@ -123,12 +176,46 @@ pub struct UqffFullSer<'a> {
     pub config: String,
     pub processor_filename: &'a Option<PathBuf>,
     pub preprocessor_filename: &'a Option<PathBuf>,
+    /// Embed the residual tensors, config and tokenizer in the UQFF file instead of writing them
+    /// next to it.
+    pub self_contained: bool,
+}
+
+/// The files a self-contained UQFF file embeds, by the name they would have next to the weights.
+fn uqff_embedded_files(full_ser: &UqffFullSer<'_>) -> candle_core::Result<Vec<(String, String)>> {
+    let mut files = vec![
+        ("config.json".to_string(), full_ser.config.clone()),
+        (
+            "tokenizer.json".to_string(),
+            serde_json::to_string_pretty(full_ser.tokenizer).map_err(candle_core::Error::msg)?,
+        ),
+    ];
+    for (name, path) in [
+        ("tokenizer_config.json", full_ser.template_filename.as_ref()),
+        ("generation_config.json", full_ser.generation_config),
+        (
+            "processor_config.json",
+            full_ser.processor_filename.as_ref(),
+        ),
+        (
+            "preprocessor_config.json",
+            full_ser.preprocessor_filename.as_ref(),
+        ),
+    ] {
+        if let Some(path) = path {
+            let data = std::fs::read_to_string(path).map_err(candle_core::Error::msg)?;
+            files.push((name.to_string(), data));
+        }
+    }
+    Ok(files)
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum ImatrixDataSource<'a> {
     File(&'a PathBuf),
     Collected,
+    /// Imatrix data which was already extracted from the model, such as by topology search.
+    Data(&'a CollectedImatrixData),
 }
 
 pub trait IsqModel {
@@ -141,16 +228,41 @@ pub trait IsqModel {
         &dyn DeviceMapper,
     );
 
-    /// This is used for imatrix generation internally. Begin stats tracking.
-    fn begin_track_stats(&mut self) -> anyhow::Result<()> {
-        let layers = self
-            .get_layers()
+    /// This is used for imatrix generation internally. Begin stats tracking. The full Hessian of
+    /// the layer inputs is tracked too for the layers of the decoder layers for which
+    /// `track_hessian` is true, for GPTQ ISQ.
+    fn begin_track_stats(
+        &mut self,
+        track_hessian: &dyn Fn(Option<usize>) -> bool,
+    ) -> anyhow::Result<()> {
+        for (layer, layer_num) in self.get_layers().0 {
+            Arc::get_mut(layer)
+                .unwrap()
+                .begin_track_stats(track_hessian(layer_num))?;
+        }
+        Ok(())
+    }
+
+    /// The decoder layers (`None` for the layers outside of them) which are quantized with GPTQ
+    /// and so need the Hessian of their inputs.
+    fn gptq_layers(
+        &mut self,
+        dtype: Option<IsqType>,
+        topology: Option<&Topology>,
+    ) -> Vec<Option<usize>> {
+        self.get_layers()
             .0
             .into_iter()
-            .map(|(layer, _)| layer)
-            .collect::<Vec<_>>();
-        for layer in layers {
-            Arc::get_mut(layer).unwrap().begin_track_stats()?;
+            .map(|(_, layer_num)| layer_num)
+            .filter(|layer_num| layer_isq_type(dtype, topology, *layer_num) == Some(IsqType::GPTQ4))
+            .unique()
+            .collect()
+    }
+
+    /// Quantize the layers whose Hessian is tracked with GPTQ and free the Hessians.
+    fn quantize_tracked_hessians(&mut self) -> candle_core::Result<()> {
+        for (layer, _) in self.get_layers().0 {
+            Arc::get_mut(layer).unwrap().quantize_tracked_hessian()?;
         }
         Ok(())
     }
@@ -185,15 +297,14 @@ pub trait IsqModel {
 
     /// Corresponds to `IsqOrganization::MoeExpertsOnly`
     /// This is used for imatrix generation internally. Begin stats tracking.
-    fn begin_track_stats_moe_experts_only(&mut self) -> anyhow::Result<()> {
-        let layers = self
-            .get_layers()
-            .0
-            .into_iter()
-            .map(|(layer, _)| layer)
-            .collect::<Vec<_>>();
-        for layer in layers {
-            Arc::get_mut(layer).unwrap().begin_track_stats()?;
+    fn begin_track_stats_moe_experts_only(
+        &mut self,
+        track_hessian: &dyn Fn(Option<usize>) -> bool,
+    ) -> anyhow::Result<()> {
+        for (layer, layer_num) in self.get_layers().0 {
+            Arc::get_mut(layer)
+                .unwrap()
+                .begin_track_stats(track_hessian(layer_num))?;
         }
         Ok(())
     }
@@ -228,6 +339,95 @@ pub trait IsqModel {
         candle_core::bail!("This model does not support quantizing with an imatrix.");
     }
 
+    /// GGUF names of the layers returned by [`get_layers`], used to export a GGUF file. By default,
+    /// these are the imatrix names, where the first layer (the `lm_head`) is `output.weight`.
+    fn gguf_layer_names(&self) -> candle_core::Result<Vec<String>> {
+        self.imatrix_names()?
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| match name {
+                Some(name) => Ok(name),
+                None if i == 0 => Ok("output.weight".to_string()),
+                None => candle_core::bail!("Layer {i} has no GGUF name."),
+            })
+            .collect()
+    }
+
+    /// Measure how sensitive each decoder layer is to quantization with each of the `candidates`.
+    /// The error is the reconstruction error of the layer weights, weighted by the imatrix and
+    /// relative to the weighted norm of the weights. Returns the costs indexed by layer number.
+    fn measure_isq_sensitivity(
+        &mut self,
+        candidates: &[IsqType],
+        imatrix: &CollectedImatrixData,
+    ) -> candle_core::Result<Vec<Vec<IsqCandidateCost>>> {
+        let n_quantized = AtomicUsize::new(0);
+        // Per layer number: weighted norm of the weights, and (error, bytes) per candidate
+        let mut sensitivities: Vec<(f64, Vec<(f64, usize)>)> = Vec::new();
+        for (i, (layer, layer_num)) in self.get_layers().0.into_iter().enumerate() {
+            let Some(layer_num) = layer_num else {
+                continue;
+            };
+            if sensitivities.len() <= layer_num {
+                sensitivities.resize(layer_num + 1, (0., vec![(0., 0); candidates.len()]));
+            }
+
+            let (dtype, device) = layer.dtype_and_device();
+            let w = layer.dequantize_w()?.to_dtype(DType::F32)?;
+            let w = w.reshape(((), w.dim(D::Minus1)?))?;
+            let imatrix_weight = imatrix.0.get(&i).cloned().flatten();
+            let importance = match &imatrix_weight {
+                Some(imatrix) => Tensor::new(imatrix.as_slice(), w.device())?,
+                None => Tensor::ones(w.dim(1)?, DType::F32, w.device())?,
+            };
+            let weighted_norm = |t: &Tensor| -> candle_core::Result<f64> {
+                Ok(t.sqr()?
+                    .sum(0)?
+                    .mul(&importance)?
+                    .sum_all()?
+                    .to_scalar::<f32>()? as f64)
+            };
+
+            let (norm, costs) = &mut sensitivities[layer_num];
+            *norm += weighted_norm(&w)?;
+            for (isq, (error, bytes)) in candidates.iter().zip(costs) {
+                let quantized = layer.clone().apply_isq(
+                    Some(*isq),
+                    device.clone(),
+                    &n_quantized,
+                    imatrix_weight.clone(),
+                )?;
+                let wq = quantized
+                    .dequantize_w()?
+                    .to_dtype(DType::F32)?
+                    .reshape(w.shape())?;
+                *error += weighted_norm(&(&w - &wq)?)?;
+                // The actual storage size, as the pack factor ignores the per-block scales and
+                // mins, which differ between for example Q4_0, Q4_1 and Q4K.
+                *bytes += if quantized.isq_serde_supported() {
+                    quantized.serialize()?.len()
+                } else {
+                    w.elem_count() * dtype.size_in_bytes() / isq.pack_factor(dtype)
+                };
+            }
+        }
+
+        Ok(sensitivities
+            .into_iter()
+            .map(|(norm, costs)| {
+                candidates
+                    .iter()
+                    .zip(costs)
+                    .map(|(isq, (error, bytes))| IsqCandidateCost {
+                        isq: *isq,
+                        error: error / norm.max(f64::EPSILON),
+                        bytes,
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
     /// Residual tensors for generating a UQFF file. Counterpart to [`get_layers`].
     fn residual_tensors(&self) -> Vec<(String, Tensor)>;
 
@@ -239,7 +439,8 @@ pub trait IsqModel {
     /// Quantize the model in-situ.
     ///
     /// This function will also create a UQFF file, or, if the model supports it (residual tensors are returned),
-    /// a full serialization is created.
+    /// a full serialization is created. If the artifacts path has a `.gguf` extension, a GGUF file is
+    /// written instead.
     #[allow(clippy::too_many_arguments)]
     fn quantize(
         &mut self,
@@ -342,6 +543,11 @@ pub trait IsqModel {
                     info!("Quantizing with collected imatrix data, {count} imatrix weights");
                     Some(data.0)
                 }
+                Some(ImatrixDataSource::Data(data)) => {
+                    let count = data.0.iter().filter(|(_, x)| x.is_some()).count();
+                    info!("Quantizing with collected imatrix data, {count} imatrix weights");
+                    Some(data.0.clone())
+                }
                 None => {
                     // Dummy, just for zip
                     None
@@ -458,7 +664,10 @@ pub trait IsqModel {
             #[cfg(feature = "metal")]
             let mut minimum_max_threads = 1;
 
-            if matches!(imatrix_source, Some(ImatrixDataSource::Collected)) {
+            if matches!(
+                imatrix_source,
+                Some(ImatrixDataSource::Collected | ImatrixDataSource::Data(_))
+            ) {
                 // Collected imatrix means that the model is potentially on the gpu already
                 minimum_max_threads = 1;
             }
@@ -503,14 +712,46 @@ pub trait IsqModel {
                 }
             });
 
-            if let Some(serialized) = write_artifacts {
+            if let Some(serialized) =
+                write_artifacts.filter(|p| p.extension().is_some_and(|ext| ext == "gguf"))
+            {
+                if !matches!(organization, IsqOrganization::Default) {
+                    candle_core::bail!(
+                        "GGUF export is only supported with the default ISQ organization."
+                    );
+                }
+                let layers = tensors
+                    .iter()
+                    .map(|(layer, _)| (*layer).clone())
+                    .collect::<Vec<_>>();
+                let names = self.gguf_layer_names()?;
+                if names.len() != layers.len() {
+                    candle_core::bail!(
+                        "Expected {} GGUF layer names, got {}.",
+                        layers.len(),
+                        names.len()
+                    );
+                }
+                let residual = self.residual_tensors();
+
+                if let Some(parent) = serialized.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                crate::gguf::write_gguf(
+                    serialized,
+                    names.into_iter().zip(layers).collect(),
+                    residual,
+                    &full_ser,
+                )
+                .map_err(candle_core::Error::msg)?;
+            } else if let Some(serialized) = write_artifacts {
                 info!(
                     "Serializing {total_tensors} ISQ tensors to `{}`.",
                     serialized.display()
                 );
 
                 if serialized.extension().is_none_or(|ext| ext != "uqff") {
-                    candle_core::bail!("UQFF output path extension must be `.uqff`",);
+                    candle_core::bail!("UQFF output path extension must be `.uqff` or `.gguf`",);
                 }
 
                 let bar = ProgressBar::new(total_tensors as u64);
@@ -537,12 +778,7 @@ pub trait IsqModel {
                             .par_iter()
                             .enumerate()
                             .filter(|(_, (layer, _))| layer.isq_serde_supported())
-                            .map(|(i, (layer, _))| {
-                                Ok((
-                                    i.to_string(),
-                                    Tensor::new(Cow::into_owned(layer.serialize()?), &Device::Cpu)?,
-                                ))
-                            })
+                            .map(|(i, (layer, _))| Ok((i.to_string(), layer.serialize()?)))
                             .collect::<candle_core::Result<Vec<_>>>()
                     } else {
                         tensors
@@ -550,12 +786,7 @@ pub trait IsqModel {
                             .enumerate()
                             .progress_with(bar)
                             .filter(|(_, (layer, _))| layer.isq_serde_supported())
-                            .map(|(i, (layer, _))| {
-                                Ok((
-                                    i.to_string(),
-                                    Tensor::new(Cow::into_owned(layer.serialize()?), &Device::Cpu)?,
-                                ))
-                            })
+                            .map(|(i, (layer, _))| Ok((i.to_string(), layer.serialize()?)))
                             .collect::<candle_core::Result<Vec<_>>>()
                     }
                 });
@@ -566,8 +797,6 @@ pub trait IsqModel {
 
                 std::fs::create_dir_all(parent)?;
 
-                safetensors::serialize_to_file(quantized_values?, &None, serialized)?;
-
                 let residual = match organization {
                     IsqOrganization::Default => self.residual_tensors(),
                     IsqOrganization::MoeExpertsOnly => self
@@ -575,81 +804,117 @@ pub trait IsqModel {
                         .unwrap_or(self.residual_tensors()),
                 };
 
-                let residual_out = parent.join(UQFF_RESIDUAL_SAFETENSORS);
-                let config_out = parent.join("config.json");
-                let tokenizer_out = parent.join("tokenizer.json");
-                let tokenizer_cfg_out = parent.join("tokenizer_config.json");
-                let gen_cfg_out = parent.join("generation_config.json");
-                let processor_out = parent.join("processor_config.json");
-                let preprocessor_out = parent.join("preprocessor_config.json");
+                if full_ser.self_contained {
+                    let files = uqff_embedded_files(&full_ser)?;
+                    info!(
+                        "Embedding {} residual tensors and {} files in `{}`.",
+                        residual.len(),
+                        files.len(),
+                        serialized.display()
+                    );
+                    let residual = residual
+                        .iter()
+                        .map(|(name, tensor)| {
+                            let tensor = UqffTensor {
+                                dtype: View::dtype(tensor),
+                                shape: tensor.dims().to_vec(),
+                                data: View::data(tensor),
+                            };
+                            (name.clone(), tensor)
+                        })
+                        .collect();
+                    let contents = UqffContents {
+                        layers: quantized_values?,
+                        residual,
+                        files,
+                    };
+                    mistralrs_quant::write_uqff(&contents, serialized)?;
+                } else {
+                    let contents = UqffContents {
+                        layers: quantized_values?,
+                        ..Default::default()
+                    };
+                    mistralrs_quant::write_uqff(&contents, serialized)?;
 
-                info!(
-                    "Serializing {} residual tensors to `{}`.",
-                    residual.len(),
-                    residual_out.display()
-                );
+                    let residual_out = parent.join(UQFF_RESIDUAL_SAFETENSORS);
+                    let config_out = parent.join("config.json");
+                    let tokenizer_out = parent.join("tokenizer.json");
+                    let tokenizer_cfg_out = parent.join("tokenizer_config.json");
+                    let gen_cfg_out = parent.join("generation_config.json");
+                    let processor_out = parent.join("processor_config.json");
+                    let preprocessor_out = parent.join("preprocessor_config.json");
 
-                safetensors::serialize_to_file(residual, &None, &residual_out)?;
+                    info!(
+                        "Serializing {} residual tensors to `{}`.",
+                        residual.len(),
+                        residual_out.display()
+                    );
 
-                let UqffFullSer {
-                    tokenizer,
-                    template_filename,
-                    generation_config,
-                    config,
-                    processor_filename,
-                    preprocessor_filename,
-                } = full_ser;
+                    safetensors::serialize_to_file(residual, &None, &residual_out)?;
 
-                info!("Serializing configuration to `{}`.", config_out.display());
+                    let UqffFullSer {
+                        tokenizer,
+                        template_filename,
+                        generation_config,
+                        config,
+                        processor_filename,
+                        preprocessor_filename,
+                        self_contained: _,
+                    } = full_ser;
 
-                std::fs::write(config_out, config)?;
+                    info!("Serializing configuration to `{}`.", config_out.display());
 
-                info!("Serializing tokenizer to `{}`.", tokenizer_out.display());
+                    std::fs::write(config_out, config)?;
 
-                serde_json::to_writer_pretty(File::create(&tokenizer_out)?, tokenizer)
-                    .map_err(candle_core::Error::msg)?;
+                    info!("Serializing tokenizer to `{}`.", tokenizer_out.display());
 
-                if let Some(template_filename) = template_filename {
-                    info!(
-                        "Serializing tokenizer config to `{}`.",
-                        tokenizer_cfg_out.display()
-                    );
-
-                    let template =
-                        std::fs::read(template_filename).map_err(candle_core::Error::msg)?;
-                    std::fs::write(&tokenizer_cfg_out, template)
+                    serde_json::to_writer_pretty(File::create(&tokenizer_out)?, tokenizer)
                         .map_err(candle_core::Error::msg)?;
-                }
 
-                if let Some(generation_config) = generation_config {
-                    info!(
-                        "Serializing generation config to `{}`.",
-                        gen_cfg_out.display()
-                    );
+                    if let Some(template_filename) = template_filename {
+                        info!(
+                            "Serializing tokenizer config to `{}`.",
+                            tokenizer_cfg_out.display()
+                        );
 
-                    let cfg = std::fs::read(generation_config).map_err(candle_core::Error::msg)?;
-                    std::fs::write(&gen_cfg_out, cfg).map_err(candle_core::Error::msg)?;
-                }
+                        let template =
+                            std::fs::read(template_filename).map_err(candle_core::Error::msg)?;
+                        std::fs::write(&tokenizer_cfg_out, template)
+                            .map_err(candle_core::Error::msg)?;
+                    }
 
-                if let Some(processor_config) = processor_filename {
-                    info!(
-                        "Serializing processor config to `{}`.",
-                        processor_out.display()
-                    );
+                    if let Some(generation_config) = generation_config {
+                        info!(
+                            "Serializing generation config to `{}`.",
+                            gen_cfg_out.display()
+                        );
 
-                    let cfg = std::fs::read(processor_config).map_err(candle_core::Error::msg)?;
-                    std::fs::write(&processor_out, cfg).map_err(candle_core::Error::msg)?;
-                }
+                        let cfg =
+                            std::fs::read(generation_config).map_err(candle_core::Error::msg)?;
+                        std::fs::write(&gen_cfg_out, cfg).map_err(candle_core::Error::msg)?;
+                    }
 
-                if let Some(preprocessor_config) = preprocessor_filename {
-                    info!(
-                        "Serializing preprocessor config to `{}`.",
-                        preprocessor_out.display()
-                    );
+                    if let Some(processor_config) = processor_filename {
+                        info!(
+                            "Serializing processor config to `{}`.",
+                            processor_out.display()
+                        );
+
+                        let cfg =
+                            std::fs::read(processor_config).map_err(candle_core::Error::msg)?;
+                        std::fs::write(&processor_out, cfg).map_err(candle_core::Error::msg)?;
+                    }
 
-                    let cfg =
-                        std::fs::read(preprocessor_config).map_err(candle_core::Error::msg)?;
-                    std::fs::write(&preprocessor_out, cfg).map_err(candle_core::Error::msg)?;
+                    if let Some(preprocessor_config) = preprocessor_filename {
+                        info!(
+                            "Serializing preprocessor config to `{}`.",
+                            preprocessor_out.display()
+                        );
+
+                        let cfg =
+                            std::fs::read(preprocessor_config).map_err(candle_core::Error::msg)?;
+                        std::fs::write(&preprocessor_out, cfg).map_err(candle_core::Error::msg)?;
+                    }
                 }
             }
             let delta = Instant::now().duration_since(t_start).as_secs_f32();
@@ -703,13 +968,8 @@ pub trait IsqModel {
         let artifact_isqs = artifacts
             .tensors()
             .into_iter()
-            .map(|(name, tensor)| {
-                (
-                    name.parse::<usize>()
-                        .expect("Name should be parseable as usize"),
-                    tensor,
-                )
-            })
+            // Self-contained UQFF files also hold the residual tensors under their own names
+            .filter_map(|(name, tensor)| name.parse::<usize>().ok().map(|i| (i, tensor)))
             .collect::<HashMap<_, _>>();
 
         if artifact_isqs.len() != total_tensors {
@@ -735,24 +995,7 @@ pub trait IsqModel {
                 .zip(tensors)
                 .map(|(i, (tensor, _))| {
                     if let Some(artifact) = artifact_isqs.get(&i) {
-                        let artifact = artifact.data();
-                        // NOTE(EricLBuehler): isq type is ALWAYS byte 4 (5th) of the tensor.
-                        let isq_type = artifact[mistralrs_quant::UQFF_QUANT_TYPE_OFFSET];
-                        let deserialized = match QuantizedSerdeType::try_from(isq_type as usize)? {
-                            QuantizedSerdeType::Gguf => {
-                                GgufMatMul::deserialize(Cow::from(artifact), &devices[i])?
-                            }
-                            QuantizedSerdeType::Unquant => {
-                                UnquantLinear::deserialize(Cow::from(artifact), &devices[i])?
-                            }
-                            QuantizedSerdeType::Hqq => {
-                                HqqLayer::deserialize(Cow::from(artifact), &devices[i])?
-                            }
-                            QuantizedSerdeType::Fp8 => {
-                                FP8Linear::deserialize(Cow::from(artifact), &devices[i])?
-                            }
-                        };
-                        *tensor = deserialized;
+                        *tensor = deserialize_uqff_layer(artifact.data(), &devices[i])?;
                     }
                     Ok(())
                 })
@@ -764,24 +1007,7 @@ pub trait IsqModel {
                 .progress_with(bar)
                 .map(|(i, (tensor, _))| {
                     if let Some(artifact) = artifact_isqs.get(&i) {
-                        let artifact = artifact.data();
-                        // NOTE(EricLBuehler): isq type is ALWAYS byte 4 (5th) of the tensor.
-                        let isq_type = artifact[4];
-                        let deserialized = match QuantizedSerdeType::try_from(isq_type as usize)? {
-                            QuantizedSerdeType::Gguf => {
-                                GgufMatMul::deserialize(Cow::from(artifact), &devices[i])?
-                            }
-                            QuantizedSerdeType::Unquant => {
-                                UnquantLinear::deserialize(Cow::from(artifact), &devices[i])?
-                            }
-                            QuantizedSerdeType::Hqq => {
-                                HqqLayer::deserialize(Cow::from(artifact), &devices[i])?
-                            }
-                            QuantizedSerdeType::Fp8 => {
-                                FP8Linear::deserialize(Cow::from(artifact), &devices[i])?
-                            }
-                        };
-                        *tensor = deserialized;
+                        *tensor = deserialize_uqff_layer(artifact.data(), &devices[i])?;
                     }
                     Ok(())
                 })
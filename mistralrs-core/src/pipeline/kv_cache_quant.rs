@@ -0,0 +1,76 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::Deserialize;
+
+/// Storage type of the KV cache.
+///
+/// Quantized caches are only supported with PagedAttention on CUDA, where the attention kernel
+/// reads the quantized blocks directly. `f8e4m3` stores one absmax scale per token and head.
+#[derive(Clone, Debug, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum KvCacheType {
+    /// Store the keys and values in the activation dtype.
+    #[default]
+    #[serde(rename = "auto")]
+    Auto,
+    #[serde(rename = "f8e4m3")]
+    F8E4M3,
+}
+
+impl FromStr for KvCacheType {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "f8e4m3" | "fp8" => Ok(Self::F8E4M3),
+            other => Err(format!(
+                "Expected KV cache type `auto` or `f8e4m3` (PagedAttention on CUDA only), got `{other}`"
+            )),
+        }
+    }
+}
+
+impl Display for KvCacheType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::F8E4M3 => write!(f, "f8e4m3"),
+        }
+    }
+}
+
+impl KvCacheType {
+    pub fn is_quantized(&self) -> bool {
+        !matches!(self, Self::Auto)
+    }
+
+    /// Check that the cache type can be used. Quantized caches require PagedAttention, the
+    /// device is checked when the PagedAttention cache is configured.
+    pub fn validate(&self, paged_attn: bool) -> anyhow::Result<()> {
+        if self.is_quantized() && !paged_attn {
+            anyhow::bail!(
+                "The `{self}` KV cache requires PagedAttention on CUDA, use the `auto` KV cache type otherwise."
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KvCacheType;
+
+    #[test]
+    fn test_kv_cache_type_from_str() {
+        assert_eq!("fp8".parse::<KvCacheType>(), Ok(KvCacheType::F8E4M3));
+        assert_eq!("Auto".parse::<KvCacheType>(), Ok(KvCacheType::Auto));
+        assert!("int8".parse::<KvCacheType>().is_err());
+        assert!("q4".parse::<KvCacheType>().is_err());
+    }
+
+    #[test]
+    fn test_kv_cache_type_requires_paged_attn() {
+        assert!(KvCacheType::Auto.validate(false).is_ok());
+        assert!(KvCacheType::F8E4M3.validate(true).is_ok());
+        assert!(KvCacheType::F8E4M3.validate(false).is_err());
+    }
+}
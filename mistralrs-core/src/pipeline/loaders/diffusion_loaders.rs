@@ -22,10 +22,16 @@ use super::{ModelPaths, NormalLoadingMetadata};
 use crate::{
     api_dir_list, api_get_file,
     diffusion_models::{
+        clip::text::ClipTextConfig,
         flux::{
             self,
             stepper::{FluxStepper, FluxStepperConfig},
         },
+        stable_diffusion::{
+            self,
+            schedulers::SchedulerConfig,
+            stepper::{StableDiffusionStepper, StableDiffusionStepperConfig},
+        },
         DiffusionGenerationParams,
     },
     lora::LoraConfig,
@@ -71,6 +77,10 @@ pub enum DiffusionLoaderType {
     Flux,
     #[serde(rename = "flux-offloaded")]
     FluxOffloaded,
+    #[serde(rename = "stable-diffusion")]
+    StableDiffusion,
+    #[serde(rename = "stable-diffusion-xl")]
+    StableDiffusionXl,
 }
 
 impl FromStr for DiffusionLoaderType {
@@ -79,8 +89,10 @@ impl FromStr for DiffusionLoaderType {
         match s {
             "flux" => Ok(Self::Flux),
             "flux-offloaded" => Ok(Self::FluxOffloaded),
+            "stable-diffusion" => Ok(Self::StableDiffusion),
+            "stable-diffusion-xl" => Ok(Self::StableDiffusionXl),
             a => Err(format!(
-                "Unknown architecture `{a}`. Possible architectures: `flux`."
+                "Unknown architecture `{a}`. Possible architectures: `flux`, `flux-offloaded`, `stable-diffusion`, `stable-diffusion-xl`."
             )),
         }
     }
@@ -206,3 +218,102 @@ impl DiffusionModelLoader for FluxLoader {
         )?))
     }
 }
+
+// ======================== Stable Diffusion loader
+
+/// [`DiffusionLoader`] for a Stable Diffusion 1.x or SDXL model in the diffusers format.
+///
+/// [`DiffusionLoader`]: https://ericlbuehler.github.io/mistral.rs/mistralrs/struct.DiffusionLoader.html
+pub struct StableDiffusionLoader {
+    pub(crate) xl: bool,
+}
+
+impl StableDiffusionLoader {
+    fn subfolders(&self) -> Vec<&'static str> {
+        if self.xl {
+            vec!["unet", "vae", "text_encoder", "text_encoder_2"]
+        } else {
+            vec!["unet", "vae", "text_encoder"]
+        }
+    }
+}
+
+impl DiffusionModelLoader for StableDiffusionLoader {
+    fn get_model_paths(&self, api: &ApiRepo, model_id: &Path) -> Result<Vec<PathBuf>> {
+        let listing = api_dir_list!(api, model_id).collect::<Vec<_>>();
+        // Prefer the smaller fp16 weights when they are available.
+        Ok(self
+            .subfolders()
+            .into_iter()
+            .map(|subfolder| {
+                let name = if subfolder.starts_with("text_encoder") {
+                    "model"
+                } else {
+                    "diffusion_pytorch_model"
+                };
+                let fp16 = format!("{subfolder}/{name}.fp16.safetensors");
+                let file = if listing.contains(&fp16) || model_id.join(&fp16).exists() {
+                    fp16
+                } else {
+                    format!("{subfolder}/{name}.safetensors")
+                };
+                api_get_file!(api, &file, model_id)
+            })
+            .collect())
+    }
+    fn get_config_filenames(&self, api: &ApiRepo, model_id: &Path) -> Result<Vec<PathBuf>> {
+        // The scheduler config is last, and has no weights.
+        Ok(self
+            .subfolders()
+            .into_iter()
+            .map(|subfolder| format!("{subfolder}/config.json"))
+            .chain(std::iter::once(
+                "scheduler/scheduler_config.json".to_string(),
+            ))
+            .map(|file| api_get_file!(api, &file, model_id))
+            .collect())
+    }
+    fn force_cpu_vb(&self) -> Vec<bool> {
+        vec![false; self.subfolders().len()]
+    }
+    fn load(
+        &self,
+        mut configs: Vec<String>,
+        _use_flash_attn: bool,
+        mut vbs: Vec<ShardedVarBuilder>,
+        normal_loading_metadata: NormalLoadingMetadata,
+        _attention_mechanism: AttentionImplementation,
+        _silent: bool,
+    ) -> Result<Box<dyn DiffusionModel + Send + Sync>> {
+        let scheduler_cfg: SchedulerConfig = serde_json::from_str(
+            &configs
+                .pop()
+                .context("Expected the scheduler config to be the last config.")?,
+        )?;
+        let text_encoders = configs
+            .split_off(2)
+            .into_iter()
+            .zip(vbs.split_off(2))
+            .map(|(cfg, vb)| {
+                Ok::<_, anyhow::Error>((vb, serde_json::from_str::<ClipTextConfig>(&cfg)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (vae_cfg, vae_vb) = (configs.remove(1), vbs.remove(1));
+        let (unet_cfg, unet_vb) = (configs.remove(0), vbs.remove(0));
+
+        let vae_cfg: stable_diffusion::vae::Config = serde_json::from_str(&vae_cfg)?;
+        let unet_cfg: stable_diffusion::unet::Config = serde_json::from_str(&unet_cfg)?;
+
+        let dtype = unet_vb.dtype();
+
+        Ok(Box::new(StableDiffusionStepper::new(
+            StableDiffusionStepperConfig::default_for_xl(self.xl),
+            (unet_vb, &unet_cfg),
+            (vae_vb, &vae_cfg),
+            text_encoders,
+            scheduler_cfg,
+            dtype,
+            &normal_loading_metadata.real_device,
+        )?))
+    }
+}
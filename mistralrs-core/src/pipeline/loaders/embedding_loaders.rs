@@ -0,0 +1,337 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::Result;
+use candle_core::{Device, Tensor};
+
+use hf_hub::api::sync::ApiRepo;
+use mistralrs_quant::ShardedVarBuilder;
+#[cfg(feature = "pyo3_macros")]
+use pyo3::pyclass;
+
+use serde::Deserialize;
+
+use tracing::info;
+
+use super::{ModelPaths, NormalLoadingMetadata};
+use crate::{
+    api_dir_list, api_get_file,
+    embedding_models::{
+        bert::{BertConfig, BertFlavour, BertModel},
+        decoder::{DecoderConfig, DecoderFlavour, DecoderModel},
+        modernbert::{ModernBertConfig, ModernBertModel},
+    },
+    lora::LoraConfig,
+    xlora_models::XLoraConfig,
+    Ordering,
+};
+
+pub trait EmbeddingModel {
+    /// Final hidden states (batch, seq_len, hidden_size) of the encoder. `attention_mask`
+    /// (batch, seq_len) is 1 for tokens and 0 for padding, which is on the right.
+    fn forward(
+        &self,
+        input_ids: &Tensor,
+        token_type_ids: &Tensor,
+        attention_mask: &Tensor,
+    ) -> candle_core::Result<Tensor>;
+    /// Sequence classification logits (batch, num_labels) of a cross-encoder, classifier or
+    /// reward model, from the hidden states returned by `forward`.
+    fn classify(
+        &self,
+        hidden_states: &Tensor,
+        attention_mask: &Tensor,
+    ) -> candle_core::Result<Tensor>;
+    /// Whether the model has a sequence classification head, and so is used for reranking and
+    /// classification.
+    fn is_reranker(&self) -> bool;
+    fn device(&self) -> &Device;
+    /// Maximum number of tokens per input, longer inputs are truncated.
+    fn max_seq_len(&self) -> usize;
+    fn pad_token_id(&self) -> u32;
+}
+
+pub trait EmbeddingModelLoader: Send + Sync {
+    /// If the model is being loaded with `load_model_from_hf` (so manual paths not provided), this will be called.
+    fn get_model_paths(&self, api: &ApiRepo, model_id: &Path) -> Result<EmbeddingModelPathsInner> {
+        let files = api_dir_list!(api, model_id).collect::<Vec<_>>();
+        let filenames = files
+            .iter()
+            // Nested weights belong to other sentence-transformers modules, like `2_Dense`.
+            .filter(|x| x.ends_with(".safetensors") && !x.contains('/'))
+            .map(|x| api_get_file!(api, x, model_id))
+            .collect::<Vec<_>>();
+        if filenames.is_empty() {
+            anyhow::bail!("Expected at least 1 .safetensors file for the embedding model.");
+        }
+        // The sentence-transformers pooling module, if this is a sentence-transformers model.
+        let pooling_config_filename = if files.contains(&"1_Pooling/config.json".to_string())
+            || model_id.join("1_Pooling/config.json").exists()
+        {
+            Some(api_get_file!(api, "1_Pooling/config.json", model_id))
+        } else {
+            None
+        };
+        // Holds the chat template used to format conversations for classification.
+        let template_filename = if files.contains(&"tokenizer_config.json".to_string())
+            || model_id.join("tokenizer_config.json").exists()
+        {
+            Some(api_get_file!(api, "tokenizer_config.json", model_id))
+        } else {
+            None
+        };
+        Ok(EmbeddingModelPathsInner {
+            config_filename: api_get_file!(api, "config.json", model_id),
+            tokenizer_filename: api_get_file!(api, "tokenizer.json", model_id),
+            pooling_config_filename,
+            template_filename,
+            filenames,
+        })
+    }
+    fn load(
+        &self,
+        config: &str,
+        vb: ShardedVarBuilder,
+        normal_loading_metadata: NormalLoadingMetadata,
+    ) -> Result<Box<dyn EmbeddingModel + Send + Sync>>;
+}
+
+#[cfg_attr(feature = "pyo3_macros", pyclass(eq, eq_int))]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+/// The architecture to load the embedding, reranker or classifier model as.
+pub enum EmbeddingLoaderType {
+    #[serde(rename = "bert")]
+    Bert,
+    #[serde(rename = "xlmroberta")]
+    XLMRoberta,
+    #[serde(rename = "modernbert")]
+    ModernBert,
+    #[serde(rename = "llama")]
+    Llama,
+    #[serde(rename = "mistral")]
+    Mistral,
+    #[serde(rename = "qwen2")]
+    Qwen2,
+}
+
+impl FromStr for EmbeddingLoaderType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bert" => Ok(Self::Bert),
+            "xlmroberta" => Ok(Self::XLMRoberta),
+            "modernbert" => Ok(Self::ModernBert),
+            "llama" => Ok(Self::Llama),
+            "mistral" => Ok(Self::Mistral),
+            "qwen2" => Ok(Self::Qwen2),
+            a => Err(format!(
+                "Unknown architecture `{a}`. Possible architectures: `bert`, `xlmroberta`, `modernbert`, `llama`, `mistral`, `qwen2`."
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EmbeddingModelPathsInner {
+    pub config_filename: PathBuf,
+    pub tokenizer_filename: PathBuf,
+    pub pooling_config_filename: Option<PathBuf>,
+    pub template_filename: Option<PathBuf>,
+    pub filenames: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmbeddingModelPaths(pub EmbeddingModelPathsInner);
+
+impl ModelPaths for EmbeddingModelPaths {
+    fn get_config_filename(&self) -> &PathBuf {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_tokenizer_filename(&self) -> &PathBuf {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_weight_filenames(&self) -> &[PathBuf] {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_adapter_filenames(&self) -> &Option<Vec<(String, PathBuf)>> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_adapter_configs(&self) -> &Option<Vec<((String, String), LoraConfig)>> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_classifier_config(&self) -> &Option<XLoraConfig> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_classifier_path(&self) -> &Option<PathBuf> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_ordering(&self) -> &Option<Ordering> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_template_filename(&self) -> &Option<PathBuf> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_gen_conf_filename(&self) -> Option<&PathBuf> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_lora_preload_adapter_info(&self) -> &Option<HashMap<String, (PathBuf, LoraConfig)>> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_preprocessor_config(&self) -> &Option<PathBuf> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_processor_config(&self) -> &Option<PathBuf> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_chat_template_json(&self) -> &Option<PathBuf> {
+        unreachable!("Use `std::any::Any`.")
+    }
+}
+
+// ======================== BERT loader
+
+/// [`EmbeddingLoader`] for a BERT model.
+///
+/// [`EmbeddingLoader`]: https://ericlbuehler.github.io/mistral.rs/mistralrs/struct.EmbeddingLoader.html
+pub struct BertLoader;
+
+impl EmbeddingModelLoader for BertLoader {
+    fn load(
+        &self,
+        config: &str,
+        vb: ShardedVarBuilder,
+        normal_loading_metadata: NormalLoadingMetadata,
+    ) -> Result<Box<dyn EmbeddingModel + Send + Sync>> {
+        let cfg: BertConfig = serde_json::from_str(config)?;
+        Ok(Box::new(BertModel::new(
+            &cfg,
+            BertFlavour::Bert,
+            vb,
+            &normal_loading_metadata.real_device,
+        )?))
+    }
+}
+
+// ======================== XLM-RoBERTa loader
+
+/// [`EmbeddingLoader`] for an XLM-RoBERTa (or RoBERTa) model.
+///
+/// [`EmbeddingLoader`]: https://ericlbuehler.github.io/mistral.rs/mistralrs/struct.EmbeddingLoader.html
+pub struct XLMRobertaLoader;
+
+impl EmbeddingModelLoader for XLMRobertaLoader {
+    fn load(
+        &self,
+        config: &str,
+        vb: ShardedVarBuilder,
+        normal_loading_metadata: NormalLoadingMetadata,
+    ) -> Result<Box<dyn EmbeddingModel + Send + Sync>> {
+        let cfg: BertConfig = serde_json::from_str(config)?;
+        Ok(Box::new(BertModel::new(
+            &cfg,
+            BertFlavour::Roberta,
+            vb,
+            &normal_loading_metadata.real_device,
+        )?))
+    }
+}
+
+// ======================== ModernBERT loader
+
+/// [`EmbeddingLoader`] for a ModernBERT model.
+///
+/// [`EmbeddingLoader`]: https://ericlbuehler.github.io/mistral.rs/mistralrs/struct.EmbeddingLoader.html
+pub struct ModernBertLoader;
+
+impl EmbeddingModelLoader for ModernBertLoader {
+    fn load(
+        &self,
+        config: &str,
+        vb: ShardedVarBuilder,
+        normal_loading_metadata: NormalLoadingMetadata,
+    ) -> Result<Box<dyn EmbeddingModel + Send + Sync>> {
+        let cfg: ModernBertConfig = serde_json::from_str(config)?;
+        Ok(Box::new(ModernBertModel::new(
+            &cfg,
+            vb,
+            &normal_loading_metadata.real_device,
+        )?))
+    }
+}
+
+// ======================== Llama loader
+
+/// [`EmbeddingLoader`] for a Llama sequence classification or reward model.
+///
+/// [`EmbeddingLoader`]: https://ericlbuehler.github.io/mistral.rs/mistralrs/struct.EmbeddingLoader.html
+pub struct LlamaEmbeddingLoader;
+
+impl EmbeddingModelLoader for LlamaEmbeddingLoader {
+    fn load(
+        &self,
+        config: &str,
+        vb: ShardedVarBuilder,
+        normal_loading_metadata: NormalLoadingMetadata,
+    ) -> Result<Box<dyn EmbeddingModel + Send + Sync>> {
+        let cfg: DecoderConfig = serde_json::from_str(config)?;
+        Ok(Box::new(DecoderModel::new(
+            &cfg,
+            DecoderFlavour::Llama,
+            vb,
+            &normal_loading_metadata.real_device,
+        )?))
+    }
+}
+
+// ======================== Mistral loader
+
+/// [`EmbeddingLoader`] for a Mistral sequence classification or reward model. Inputs are attended
+/// to in full, so they should be shorter than the sliding window.
+///
+/// [`EmbeddingLoader`]: https://ericlbuehler.github.io/mistral.rs/mistralrs/struct.EmbeddingLoader.html
+pub struct MistralEmbeddingLoader;
+
+impl EmbeddingModelLoader for MistralEmbeddingLoader {
+    fn load(
+        &self,
+        config: &str,
+        vb: ShardedVarBuilder,
+        normal_loading_metadata: NormalLoadingMetadata,
+    ) -> Result<Box<dyn EmbeddingModel + Send + Sync>> {
+        let cfg: DecoderConfig = serde_json::from_str(config)?;
+        Ok(Box::new(DecoderModel::new(
+            &cfg,
+            DecoderFlavour::Llama,
+            vb,
+            &normal_loading_metadata.real_device,
+        )?))
+    }
+}
+
+// ======================== Qwen2 loader
+
+/// [`EmbeddingLoader`] for a Qwen2 sequence classification or reward model.
+///
+/// [`EmbeddingLoader`]: https://ericlbuehler.github.io/mistral.rs/mistralrs/struct.EmbeddingLoader.html
+pub struct Qwen2EmbeddingLoader;
+
+impl EmbeddingModelLoader for Qwen2EmbeddingLoader {
+    fn load(
+        &self,
+        config: &str,
+        vb: ShardedVarBuilder,
+        normal_loading_metadata: NormalLoadingMetadata,
+    ) -> Result<Box<dyn EmbeddingModel + Send + Sync>> {
+        let cfg: DecoderConfig = serde_json::from_str(config)?;
+        Ok(Box::new(DecoderModel::new(
+            &cfg,
+            DecoderFlavour::Qwen2,
+            vb,
+            &normal_loading_metadata.real_device,
+        )?))
+    }
+}
@@ -1,5 +1,7 @@
 mod diffusion_loaders;
+mod embedding_loaders;
 mod normal_loaders;
+mod speech_loaders;
 mod vision_loaders;
 
 use std::{
@@ -18,9 +20,10 @@ use mistralrs_quant::IsqType;
 use tokio::sync::Mutex;
 
 pub use normal_loaders::{
-    AutoLoader, DeepSeekV2Loader, DeepSeekV3Loader, Gemma2Loader, GemmaLoader, LlamaLoader,
-    MistralLoader, MixtralLoader, NormalLoaderType, NormalLoadingMetadata, NormalModel,
-    NormalModelLoader, Phi2Loader, Phi3Loader, Phi3_5MoELoader, Qwen2Loader, Starcoder2Loader,
+    AutoLoader, DeepSeekV2Loader, DeepSeekV3Loader, Gemma2Loader, GemmaLoader, JambaLoader,
+    LlamaLoader, Mamba2Loader, MambaLoader, MistralLoader, MixtralLoader, NormalLoaderType,
+    NormalLoadingMetadata, NormalModel, NormalModelLoader, Phi2Loader, Phi3Loader, Phi3_5MoELoader,
+    Qwen2Loader, Starcoder2Loader,
 };
 
 use tracing::{info, warn};
@@ -31,7 +34,18 @@ pub use vision_loaders::{
 
 pub use diffusion_loaders::{
     DiffusionLoaderType, DiffusionModel, DiffusionModelLoader, DiffusionModelPaths,
-    DiffusionModelPathsInner, FluxLoader,
+    DiffusionModelPathsInner, FluxLoader, StableDiffusionLoader,
+};
+
+pub use embedding_loaders::{
+    BertLoader, EmbeddingLoaderType, EmbeddingModel, EmbeddingModelLoader, EmbeddingModelPaths,
+    EmbeddingModelPathsInner, LlamaEmbeddingLoader, MistralEmbeddingLoader, ModernBertLoader,
+    Qwen2EmbeddingLoader, XLMRobertaLoader,
+};
+
+pub use speech_loaders::{
+    SpeechLoaderType, SpeechModel, SpeechModelLoader, SpeechModelPaths, SpeechModelPathsInner,
+    WhisperLoader,
 };
 
 use crate::{
@@ -41,8 +55,8 @@ use crate::{
     },
     utils::debug::DeviceRepr,
     xlora_models::XLoraConfig,
-    DeviceLayerMapMetadata, DeviceMapMetadata, DeviceMapSetting, MemoryGpuConfig, MemoryUsage,
-    Ordering, PagedAttentionConfig, TryIntoDType,
+    DeviceLayerMapMetadata, DeviceMapMetadata, DeviceMapSetting, KvCacheType, MemoryGpuConfig,
+    MemoryUsage, Ordering, PagedAttentionConfig, TryIntoDType,
 };
 
 use super::Pipeline;
@@ -391,6 +405,14 @@ impl AutoDeviceMapParams {
             Self::Text { max_seq_len, .. } | Self::Vision { max_seq_len, .. } => *max_seq_len,
         }
     }
+
+    pub fn max_batch_size(&self) -> usize {
+        match self {
+            Self::Text { max_batch_size, .. } | Self::Vision { max_batch_size, .. } => {
+                *max_batch_size
+            }
+        }
+    }
 }
 
 impl Display for AutoDeviceMapParams {
@@ -559,6 +581,8 @@ pub trait DeviceMappedModelLoader {
                             .unwrap_or(DEFAULT_PAGED_ATTENTION_BLOCK_SIZE),
                     ),
                     dtype,
+                    // Upper bound for the quantized KV caches.
+                    KvCacheType::Auto,
                     &*model_cfg,
                     &devices[0],
                     &devices.iter().map(|x| Some(x.clone())).collect::<Vec<_>>(),
@@ -114,6 +114,10 @@ pub trait NormalModelLoader: IsqModelLoader + Send + Sync + DeviceMappedModelLoa
     fn get_config_repr(&self, config: &str, use_flash_attn: bool) -> Result<Box<dyn Debug>>;
     /// Get total num_hidden_layers for the layers which will be device mapped.
     fn get_total_device_mapping_num_layers(&self, config: &str) -> Result<usize>;
+    /// Whether the model can use PagedAttention. Recurrent models keep a fixed size state instead.
+    fn supports_paged_attention(&self, _config: &str) -> Result<bool> {
+        Ok(true)
+    }
     fn get_device_for_tensor(
         &self,
         config: &str,
@@ -171,6 +175,12 @@ pub enum NormalLoaderType {
     DeepSeekV2,
     #[serde(rename = "deepseekv3")]
     DeepSeekV3,
+    #[serde(rename = "mamba")]
+    Mamba,
+    #[serde(rename = "mamba2")]
+    Mamba2,
+    #[serde(rename = "jamba")]
+    Jamba,
 }
 
 // https://github.com/huggingface/transformers/blob/cff06aac6fad28019930be03f5d467055bf62177/src/transformers/models/auto/modeling_auto.py#L448
@@ -190,6 +200,9 @@ impl NormalLoaderType {
             "PhiMoEForCausalLM" => Ok(Self::Phi3_5MoE),
             "DeepseekV2ForCausalLM" => Ok(Self::DeepSeekV2),
             "DeepseekV3ForCausalLM" => Ok(Self::DeepSeekV3),
+            "MambaForCausalLM" => Ok(Self::Mamba),
+            "Mamba2ForCausalLM" => Ok(Self::Mamba2),
+            "JambaForCausalLM" => Ok(Self::Jamba),
             other => anyhow::bail!(
                 "Unsupported Huggging Face Transformers -CausalLM model class `{other}`. Please raise an issue."
             ),
@@ -213,7 +226,10 @@ impl FromStr for NormalLoaderType {
             "phi3.5moe" => Ok(Self::Phi3_5MoE),
             "deepseekv2" => Ok(Self::DeepSeekV2),
             "deepseekv3" => Ok(Self::DeepSeekV3),
-            a => Err(format!("Unknown architecture `{a}`. Possible architectures: `mistral`, `gemma`, `mixtral`, `llama`, `phi2`, `phi3`, `qwen2`, `gemma2`, `starcoder2`, `phi3.5moe`, `deepseekv2`, `deepseekv3`.")),
+            "mamba" => Ok(Self::Mamba),
+            "mamba2" => Ok(Self::Mamba2),
+            "jamba" => Ok(Self::Jamba),
+            a => Err(format!("Unknown architecture `{a}`. Possible architectures: `mistral`, `gemma`, `mixtral`, `llama`, `phi2`, `phi3`, `qwen2`, `gemma2`, `starcoder2`, `phi3.5moe`, `deepseekv2`, `deepseekv3`, `mamba`, `mamba2`, `jamba`.")),
         }
     }
 }
@@ -233,6 +249,9 @@ impl Display for NormalLoaderType {
             Self::Starcoder2 => write!(f, "starcoder2"),
             Self::DeepSeekV2 => write!(f, "deepseekv2"),
             Self::DeepSeekV3 => write!(f, "deepseekv3"),
+            Self::Mamba => write!(f, "mamba"),
+            Self::Mamba2 => write!(f, "mamba2"),
+            Self::Jamba => write!(f, "jamba"),
         }
     }
 }
@@ -281,6 +300,9 @@ impl AutoLoader {
             NormalLoaderType::Phi3_5MoE => Ok(Box::new(Phi3_5MoELoader)),
             NormalLoaderType::DeepSeekV2 => Ok(Box::new(DeepSeekV2Loader)),
             NormalLoaderType::DeepSeekV3 => Ok(Box::new(DeepSeekV3Loader)),
+            NormalLoaderType::Mamba => Ok(Box::new(MambaLoader)),
+            NormalLoaderType::Mamba2 => Ok(Box::new(Mamba2Loader)),
+            NormalLoaderType::Jamba => Ok(Box::new(JambaLoader)),
         }
     }
 }
@@ -333,6 +355,9 @@ impl NormalModelLoader for AutoLoader {
     fn is_gptx(&self, config: &str) -> Result<bool> {
         Self::get_loader(config)?.is_gptx(config)
     }
+    fn supports_paged_attention(&self, config: &str) -> Result<bool> {
+        Self::get_loader(config)?.supports_paged_attention(config)
+    }
 }
 
 impl IsqModelLoader for AutoLoader {
@@ -3376,3 +3401,531 @@ impl DeviceMappedModelLoader for DeepSeekV3Loader {
         Ok(Box::new(cfg))
     }
 }
+
+// ======================== Mamba loader
+
+/// [`NormalLoader`] for a Mamba model.
+///
+/// [`NormalLoader`]: https://ericlbuehler.github.io/mistral.rs/mistralrs/struct.NormalLoader.html
+pub struct MambaLoader;
+
+impl NormalModelLoader for MambaLoader {
+    fn load(
+        &self,
+        config: &str,
+        _use_flash_attn: bool,
+        vb: ShardedVarBuilder,
+        normal_loading_metadata: NormalLoadingMetadata,
+        _attention_mechanism: AttentionImplementation,
+    ) -> Result<Box<dyn NormalModel + Send + Sync>> {
+        let cfg: crate::models::mamba::Config = serde_json::from_str(config)?;
+        Ok(Box::new(models::mamba::Model::new(
+            &cfg,
+            vb,
+            normal_loading_metadata,
+        )?))
+    }
+    fn load_xlora(
+        &self,
+        _config: &str,
+        _use_flash_attn: bool,
+        _vb: ShardedVarBuilder,
+        _lora_config: &[((String, String), LoraConfig)],
+        _xlora_config: Option<XLoraConfig>,
+        _xlora_ordering: Ordering,
+        _normal_loading_metadata: NormalLoadingMetadata,
+        _preload_adapters: &Option<HashMap<String, (ShardedVarBuilder, LoraConfig)>>,
+    ) -> Result<Box<dyn NormalModel + Send + Sync>> {
+        anyhow::bail!("X-LoRA is not supported for Mamba models.")
+    }
+    fn is_gptx(&self, _: &str) -> Result<bool> {
+        Ok(true)
+    }
+    fn get_config_repr(&self, config: &str, _use_flash_attn: bool) -> Result<Box<dyn Debug>> {
+        let cfg: crate::models::mamba::Config = serde_json::from_str(config)?;
+        Ok(Box::new(cfg))
+    }
+    fn get_total_device_mapping_num_layers(&self, config: &str) -> Result<usize> {
+        Ok(serde_json::from_str::<crate::models::mamba::Config>(config)?.num_hidden_layers)
+    }
+    fn supports_paged_attention(&self, _config: &str) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+impl IsqModelLoader for MambaLoader {
+    fn isq_layer_regexes(&self, _config: &str) -> Result<Vec<Regex>> {
+        Ok(vec![
+            Regex::new(r"lm_head\.(weight|bias)$")?,
+            Regex::new(r"layers\.(\d+)\.mixer\.in_proj\.(weight|bias)$")?,
+            Regex::new(r"layers\.(\d+)\.mixer\.x_proj\.(weight|bias)$")?,
+            Regex::new(r"layers\.(\d+)\.mixer\.out_proj\.(weight|bias)$")?,
+        ])
+    }
+}
+
+impl DeviceMappedModelLoader for MambaLoader {
+    fn mapped_max_act_size_elems(
+        &self,
+        config: &str,
+        params: &AutoDeviceMapParams,
+        prompt_chunksize: usize,
+    ) -> Result<usize> {
+        let AutoDeviceMapParams::Text {
+            max_seq_len: _,
+            max_batch_size,
+        } = params
+        else {
+            anyhow::bail!("Expected text AutoDeviceMapParams for this model!")
+        };
+
+        let cfg: crate::models::mamba::Config = serde_json::from_str(config)?;
+
+        // The input projection, there is no attention matrix.
+        Ok(max_batch_size * prompt_chunksize * 2 * cfg.intermediate_size())
+    }
+    fn non_mapped_max_act_size_elems(
+        &self,
+        _config: &str,
+        _params: &AutoDeviceMapParams,
+    ) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn non_mapped_size_in_bytes(
+        &self,
+        config: &str,
+        dtype: DType,
+        weight_pack_factor: usize,
+    ) -> Result<usize> {
+        let cfg: crate::models::mamba::Config = serde_json::from_str(config)?;
+        let elems = {
+            let embeddings = cfg.hidden_size * cfg.vocab_size / weight_pack_factor;
+            let lm_head = if !cfg.tie_word_embeddings {
+                cfg.hidden_size * cfg.vocab_size
+            } else {
+                0
+            };
+            let norm_f = cfg.hidden_size;
+            embeddings + lm_head + norm_f
+        };
+        Ok(elems * dtype.size_in_bytes())
+    }
+
+    fn layer_sizes_in_bytes(
+        &self,
+        config: &str,
+        dtype: DType,
+        weight_pack_factor: usize,
+    ) -> Result<Vec<usize>> {
+        let cfg: crate::models::mamba::Config = serde_json::from_str(config)?;
+        let per_layer_elems = {
+            let (h_size, i_size) = (cfg.hidden_size, cfg.intermediate_size());
+            let (n, r) = (cfg.state_size, cfg.time_step_rank.rank(h_size));
+
+            let norm = h_size;
+            let in_proj =
+                h_size * 2 * i_size / weight_pack_factor + bias_if!(cfg.use_bias, 2 * i_size);
+            let conv1d = i_size * cfg.conv_kernel + bias_if!(cfg.use_conv_bias, i_size);
+            let x_proj = i_size * (r + 2 * n) / weight_pack_factor;
+            let dt_proj = r * i_size + i_size;
+            let a_log = i_size * n;
+            let d = i_size;
+            let out_proj = i_size * h_size / weight_pack_factor + bias_if!(cfg.use_bias, h_size);
+            norm + in_proj + conv1d + x_proj + dt_proj + a_log + d + out_proj
+        };
+        Ok(vec![
+            per_layer_elems * dtype.size_in_bytes();
+            cfg.num_hidden_layers
+        ])
+    }
+
+    fn num_layers(&self, config: &str) -> Result<usize> {
+        let cfg: crate::models::mamba::Config = serde_json::from_str(config)?;
+        Ok(cfg.num_hidden_layers)
+    }
+
+    fn model_config(&self, config: &str) -> Result<Box<dyn ModelConfigLike>> {
+        let cfg: crate::models::mamba::Config = serde_json::from_str(config)?;
+
+        let cfg = ModelConfigMetadata {
+            max_seq_len: crate::models::mamba::MAX_SEQ_LEN,
+            num_layers: cfg.num_hidden_layers,
+            hidden_size: cfg.hidden_size,
+            num_kv_heads: 0,
+            num_attn_heads: 0,
+            sliding_window: None,
+            k_head_dim: 0,
+            v_head_dim: 0,
+        };
+
+        Ok(Box::new(cfg))
+    }
+}
+
+// ======================== Mamba2 loader
+
+/// [`NormalLoader`] for a Mamba2 model.
+///
+/// [`NormalLoader`]: https://ericlbuehler.github.io/mistral.rs/mistralrs/struct.NormalLoader.html
+pub struct Mamba2Loader;
+
+impl NormalModelLoader for Mamba2Loader {
+    fn load(
+        &self,
+        config: &str,
+        _use_flash_attn: bool,
+        vb: ShardedVarBuilder,
+        normal_loading_metadata: NormalLoadingMetadata,
+        _attention_mechanism: AttentionImplementation,
+    ) -> Result<Box<dyn NormalModel + Send + Sync>> {
+        let cfg = crate::models::mamba2::Config::from_json(config)?;
+        Ok(Box::new(models::mamba2::Model::new(
+            &cfg,
+            vb,
+            normal_loading_metadata,
+        )?))
+    }
+    fn load_xlora(
+        &self,
+        _config: &str,
+        _use_flash_attn: bool,
+        _vb: ShardedVarBuilder,
+        _lora_config: &[((String, String), LoraConfig)],
+        _xlora_config: Option<XLoraConfig>,
+        _xlora_ordering: Ordering,
+        _normal_loading_metadata: NormalLoadingMetadata,
+        _preload_adapters: &Option<HashMap<String, (ShardedVarBuilder, LoraConfig)>>,
+    ) -> Result<Box<dyn NormalModel + Send + Sync>> {
+        anyhow::bail!("X-LoRA is not supported for Mamba2 models.")
+    }
+    fn is_gptx(&self, _: &str) -> Result<bool> {
+        Ok(true)
+    }
+    fn get_config_repr(&self, config: &str, _use_flash_attn: bool) -> Result<Box<dyn Debug>> {
+        Ok(Box::new(crate::models::mamba2::Config::from_json(config)?))
+    }
+    fn get_total_device_mapping_num_layers(&self, config: &str) -> Result<usize> {
+        Ok(crate::models::mamba2::Config::from_json(config)?.num_hidden_layers)
+    }
+    fn supports_paged_attention(&self, _config: &str) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+impl IsqModelLoader for Mamba2Loader {
+    fn isq_layer_regexes(&self, _config: &str) -> Result<Vec<Regex>> {
+        Ok(vec![
+            Regex::new(r"lm_head\.(weight|bias)$")?,
+            Regex::new(r"layers\.(\d+)\.mixer\.in_proj\.(weight|bias)$")?,
+            Regex::new(r"layers\.(\d+)\.mixer\.out_proj\.(weight|bias)$")?,
+        ])
+    }
+}
+
+impl DeviceMappedModelLoader for Mamba2Loader {
+    fn mapped_max_act_size_elems(
+        &self,
+        config: &str,
+        params: &AutoDeviceMapParams,
+        prompt_chunksize: usize,
+    ) -> Result<usize> {
+        let AutoDeviceMapParams::Text {
+            max_seq_len: _,
+            max_batch_size,
+        } = params
+        else {
+            anyhow::bail!("Expected text AutoDeviceMapParams for this model!")
+        };
+
+        let cfg = crate::models::mamba2::Config::from_json(config)?;
+
+        // The input projection, there is no attention matrix.
+        Ok(max_batch_size
+            * prompt_chunksize
+            * (2 * cfg.intermediate_size() + 2 * cfg.n_groups * cfg.state_size + cfg.num_heads))
+    }
+    fn non_mapped_max_act_size_elems(
+        &self,
+        _config: &str,
+        _params: &AutoDeviceMapParams,
+    ) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn non_mapped_size_in_bytes(
+        &self,
+        config: &str,
+        dtype: DType,
+        weight_pack_factor: usize,
+    ) -> Result<usize> {
+        let cfg = crate::models::mamba2::Config::from_json(config)?;
+        let elems = {
+            let embeddings = cfg.hidden_size * cfg.vocab_size / weight_pack_factor;
+            let lm_head = if !cfg.tie_word_embeddings {
+                cfg.hidden_size * cfg.vocab_size
+            } else {
+                0
+            };
+            let norm_f = cfg.hidden_size;
+            embeddings + lm_head + norm_f
+        };
+        Ok(elems * dtype.size_in_bytes())
+    }
+
+    fn layer_sizes_in_bytes(
+        &self,
+        config: &str,
+        dtype: DType,
+        weight_pack_factor: usize,
+    ) -> Result<Vec<usize>> {
+        let cfg = crate::models::mamba2::Config::from_json(config)?;
+        let per_layer_elems = {
+            let (h_size, i_size) = (cfg.hidden_size, cfg.intermediate_size());
+            let conv_dim = i_size + 2 * cfg.n_groups * cfg.state_size;
+            let proj_size = i_size + conv_dim + cfg.num_heads;
+
+            let norm = h_size;
+            let in_proj =
+                h_size * proj_size / weight_pack_factor + bias_if!(cfg.use_bias, proj_size);
+            let conv1d = conv_dim * cfg.conv_kernel + bias_if!(cfg.use_conv_bias, conv_dim);
+            // dt_bias, A_log and D
+            let per_head = 3 * cfg.num_heads;
+            let gated_norm = i_size;
+            let out_proj = i_size * h_size / weight_pack_factor + bias_if!(cfg.use_bias, h_size);
+            norm + in_proj + conv1d + per_head + gated_norm + out_proj
+        };
+        Ok(vec![
+            per_layer_elems * dtype.size_in_bytes();
+            cfg.num_hidden_layers
+        ])
+    }
+
+    fn num_layers(&self, config: &str) -> Result<usize> {
+        let cfg = crate::models::mamba2::Config::from_json(config)?;
+        Ok(cfg.num_hidden_layers)
+    }
+
+    fn model_config(&self, config: &str) -> Result<Box<dyn ModelConfigLike>> {
+        let cfg = crate::models::mamba2::Config::from_json(config)?;
+
+        let cfg = ModelConfigMetadata {
+            max_seq_len: crate::models::mamba::MAX_SEQ_LEN,
+            num_layers: cfg.num_hidden_layers,
+            hidden_size: cfg.hidden_size,
+            num_kv_heads: 0,
+            num_attn_heads: 0,
+            sliding_window: None,
+            k_head_dim: 0,
+            v_head_dim: 0,
+        };
+
+        Ok(Box::new(cfg))
+    }
+}
+
+// ======================== Jamba loader
+
+/// [`NormalLoader`] for a Jamba model.
+///
+/// [`NormalLoader`]: https://ericlbuehler.github.io/mistral.rs/mistralrs/struct.NormalLoader.html
+pub struct JambaLoader;
+
+impl NormalModelLoader for JambaLoader {
+    fn load(
+        &self,
+        config: &str,
+        _use_flash_attn: bool,
+        vb: ShardedVarBuilder,
+        normal_loading_metadata: NormalLoadingMetadata,
+        _attention_mechanism: AttentionImplementation,
+    ) -> Result<Box<dyn NormalModel + Send + Sync>> {
+        let cfg: crate::models::jamba::Config = serde_json::from_str(config)?;
+        Ok(Box::new(models::jamba::Model::new(
+            &cfg,
+            vb,
+            normal_loading_metadata,
+        )?))
+    }
+    fn load_xlora(
+        &self,
+        _config: &str,
+        _use_flash_attn: bool,
+        _vb: ShardedVarBuilder,
+        _lora_config: &[((String, String), LoraConfig)],
+        _xlora_config: Option<XLoraConfig>,
+        _xlora_ordering: Ordering,
+        _normal_loading_metadata: NormalLoadingMetadata,
+        _preload_adapters: &Option<HashMap<String, (ShardedVarBuilder, LoraConfig)>>,
+    ) -> Result<Box<dyn NormalModel + Send + Sync>> {
+        anyhow::bail!("X-LoRA is not supported for Jamba models.")
+    }
+    fn is_gptx(&self, _: &str) -> Result<bool> {
+        Ok(true)
+    }
+    fn get_config_repr(&self, config: &str, _use_flash_attn: bool) -> Result<Box<dyn Debug>> {
+        let cfg: crate::models::jamba::Config = serde_json::from_str(config)?;
+        Ok(Box::new(cfg))
+    }
+    fn get_total_device_mapping_num_layers(&self, config: &str) -> Result<usize> {
+        Ok(serde_json::from_str::<crate::models::jamba::Config>(config)?.num_hidden_layers)
+    }
+    fn supports_paged_attention(&self, _config: &str) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+impl IsqModelLoader for JambaLoader {
+    fn isq_layer_regexes(&self, _config: &str) -> Result<Vec<Regex>> {
+        Ok(vec![
+            Regex::new(r"lm_head\.(weight|bias)$")?,
+            // Attention
+            Regex::new(r"layers\.(\d+)\.self_attn\.q_proj\.(weight|bias)$")?,
+            Regex::new(r"layers\.(\d+)\.self_attn\.k_proj\.(weight|bias)$")?,
+            Regex::new(r"layers\.(\d+)\.self_attn\.v_proj\.(weight|bias)$")?,
+            Regex::new(r"layers\.(\d+)\.self_attn\.o_proj\.(weight|bias)$")?,
+            // Mamba
+            Regex::new(r"layers\.(\d+)\.mamba\.in_proj\.(weight|bias)$")?,
+            Regex::new(r"layers\.(\d+)\.mamba\.x_proj\.(weight|bias)$")?,
+            Regex::new(r"layers\.(\d+)\.mamba\.out_proj\.(weight|bias)$")?,
+            // MLP
+            Regex::new(r"layers\.(\d+)\.feed_forward\.gate_proj\.(weight|bias)$")?,
+            Regex::new(r"layers\.(\d+)\.feed_forward\.up_proj\.(weight|bias)$")?,
+            Regex::new(r"layers\.(\d+)\.feed_forward\.down_proj\.(weight|bias)$")?,
+            // Experts
+            Regex::new(r"layers\.(\d+)\.feed_forward\.experts\.(\d+)\.gate_proj\.(weight|bias)$")?,
+            Regex::new(r"layers\.(\d+)\.feed_forward\.experts\.(\d+)\.up_proj\.(weight|bias)$")?,
+            Regex::new(r"layers\.(\d+)\.feed_forward\.experts\.(\d+)\.down_proj\.(weight|bias)$")?,
+        ])
+    }
+}
+
+impl DeviceMappedModelLoader for JambaLoader {
+    fn mapped_max_act_size_elems(
+        &self,
+        config: &str,
+        params: &AutoDeviceMapParams,
+        prompt_chunksize: usize,
+    ) -> Result<usize> {
+        let AutoDeviceMapParams::Text {
+            max_seq_len: _,
+            max_batch_size,
+        } = params
+        else {
+            anyhow::bail!("Expected text AutoDeviceMapParams for this model!")
+        };
+
+        let cfg: crate::models::jamba::Config = serde_json::from_str(config)?;
+
+        let attention = cfg.num_attention_heads * prompt_chunksize * prompt_chunksize;
+        let mamba = prompt_chunksize * 2 * cfg.mamba_expand * cfg.hidden_size;
+        Ok(max_batch_size * attention.max(mamba))
+    }
+    fn non_mapped_max_act_size_elems(
+        &self,
+        _config: &str,
+        _params: &AutoDeviceMapParams,
+    ) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn non_mapped_size_in_bytes(
+        &self,
+        config: &str,
+        dtype: DType,
+        weight_pack_factor: usize,
+    ) -> Result<usize> {
+        let cfg: crate::models::jamba::Config = serde_json::from_str(config)?;
+        let elems = {
+            let embed_tokens = cfg.hidden_size * cfg.vocab_size / weight_pack_factor;
+            let lm_head = if !cfg.tie_word_embeddings {
+                cfg.hidden_size * cfg.vocab_size
+            } else {
+                0
+            };
+            let norm = cfg.hidden_size;
+            embed_tokens + lm_head + norm
+        };
+        Ok(elems * dtype.size_in_bytes())
+    }
+
+    fn layer_sizes_in_bytes(
+        &self,
+        config: &str,
+        dtype: DType,
+        weight_pack_factor: usize,
+    ) -> Result<Vec<usize>> {
+        let cfg: crate::models::jamba::Config = serde_json::from_str(config)?;
+        let h_size = cfg.hidden_size;
+        let mut per_layer_elems = Vec::new();
+
+        for layer_idx in 0..cfg.num_hidden_layers {
+            let input_layernorm = h_size;
+            let pre_ff_layernorm = h_size;
+
+            let mixer = if cfg.is_attention_layer(layer_idx) {
+                let head_dim = cfg.head_dim();
+                let q_proj = h_size * cfg.num_attention_heads * head_dim / weight_pack_factor;
+                let k_proj = h_size * cfg.num_key_value_heads * head_dim / weight_pack_factor;
+                let v_proj = h_size * cfg.num_key_value_heads * head_dim / weight_pack_factor;
+                let o_proj = cfg.num_attention_heads * head_dim * h_size / weight_pack_factor;
+                q_proj + k_proj + v_proj + o_proj
+            } else {
+                let i_size = cfg.mamba_expand * h_size;
+                let (n, r) = (cfg.mamba_d_state, cfg.mamba_dt_rank.rank(h_size));
+                let in_proj = h_size * 2 * i_size / weight_pack_factor
+                    + bias_if!(cfg.mamba_proj_bias, 2 * i_size);
+                let conv1d = i_size * cfg.mamba_d_conv + bias_if!(cfg.mamba_conv_bias, i_size);
+                let x_proj = i_size * (r + 2 * n) / weight_pack_factor;
+                let dt_proj = r * i_size + i_size;
+                let a_log = i_size * n;
+                let d = i_size;
+                let out_proj =
+                    i_size * h_size / weight_pack_factor + bias_if!(cfg.mamba_proj_bias, h_size);
+                // dt, B and C norms
+                let inner_norms = r + 2 * n;
+                in_proj + conv1d + x_proj + dt_proj + a_log + d + out_proj + inner_norms
+            };
+
+            let feed_forward = {
+                let i_size = cfg.intermediate_size;
+                let mlp = 3 * h_size * i_size / weight_pack_factor;
+                if cfg.is_expert_layer(layer_idx) {
+                    let router = h_size * cfg.num_experts;
+                    mlp * cfg.num_experts + router
+                } else {
+                    mlp
+                }
+            };
+
+            per_layer_elems.push(input_layernorm + pre_ff_layernorm + mixer + feed_forward);
+        }
+
+        Ok(per_layer_elems
+            .into_iter()
+            .map(|x| x * dtype.size_in_bytes())
+            .collect())
+    }
+
+    fn num_layers(&self, config: &str) -> Result<usize> {
+        let cfg: crate::models::jamba::Config = serde_json::from_str(config)?;
+        Ok(cfg.num_hidden_layers)
+    }
+
+    fn model_config(&self, config: &str) -> Result<Box<dyn ModelConfigLike>> {
+        let cfg: crate::models::jamba::Config = serde_json::from_str(config)?;
+
+        let cfg = ModelConfigMetadata {
+            max_seq_len: cfg.max_position_embeddings,
+            num_layers: cfg.num_hidden_layers,
+            hidden_size: cfg.hidden_size,
+            num_kv_heads: cfg.num_key_value_heads,
+            num_attn_heads: cfg.num_attention_heads,
+            sliding_window: None,
+            k_head_dim: cfg.head_dim(),
+            v_head_dim: cfg.head_dim(),
+        };
+
+        Ok(Box::new(cfg))
+    }
+}
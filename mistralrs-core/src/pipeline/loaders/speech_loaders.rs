@@ -0,0 +1,190 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use candle_core::Device;
+
+use hf_hub::api::sync::ApiRepo;
+use mistralrs_quant::ShardedVarBuilder;
+#[cfg(feature = "pyo3_macros")]
+use pyo3::pyclass;
+
+use serde::Deserialize;
+use tokenizers::Tokenizer;
+
+use tracing::info;
+
+use super::{ModelPaths, NormalLoadingMetadata};
+use crate::{
+    api_dir_list, api_get_file,
+    lora::LoraConfig,
+    speech_models::{
+        whisper::{WhisperConfig, WhisperGenerationConfig, WhisperModel},
+        TranscriptionParams,
+    },
+    xlora_models::XLoraConfig,
+    AudioInput, Ordering, TranscriptionResponse,
+};
+
+pub trait SpeechModel {
+    /// Transcribe (or translate) a whole audio clip.
+    fn transcribe(
+        &mut self,
+        audio: &AudioInput,
+        params: &TranscriptionParams,
+    ) -> candle_core::Result<TranscriptionResponse>;
+    fn device(&self) -> &Device;
+    fn max_seq_len(&self) -> usize;
+}
+
+pub trait SpeechModelLoader: Send + Sync {
+    /// If the model is being loaded with `load_model_from_hf` (so manual paths not provided), this will be called.
+    fn get_model_paths(&self, api: &ApiRepo, model_id: &Path) -> Result<SpeechModelPathsInner>;
+    fn load(
+        &self,
+        config: &str,
+        generation_config: Option<&str>,
+        tokenizer: Arc<Tokenizer>,
+        vb: ShardedVarBuilder,
+        normal_loading_metadata: NormalLoadingMetadata,
+    ) -> Result<Box<dyn SpeechModel + Send + Sync>>;
+}
+
+#[cfg_attr(feature = "pyo3_macros", pyclass(eq, eq_int))]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+/// The architecture to load the speech model as.
+pub enum SpeechLoaderType {
+    #[serde(rename = "whisper")]
+    Whisper,
+}
+
+impl FromStr for SpeechLoaderType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "whisper" => Ok(Self::Whisper),
+            a => Err(format!(
+                "Unknown architecture `{a}`. Possible architectures: `whisper`."
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SpeechModelPathsInner {
+    pub config_filename: PathBuf,
+    pub generation_config_filename: Option<PathBuf>,
+    pub tokenizer_filename: PathBuf,
+    pub filenames: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SpeechModelPaths(pub SpeechModelPathsInner);
+
+impl ModelPaths for SpeechModelPaths {
+    fn get_config_filename(&self) -> &PathBuf {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_tokenizer_filename(&self) -> &PathBuf {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_weight_filenames(&self) -> &[PathBuf] {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_adapter_filenames(&self) -> &Option<Vec<(String, PathBuf)>> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_adapter_configs(&self) -> &Option<Vec<((String, String), LoraConfig)>> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_classifier_config(&self) -> &Option<XLoraConfig> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_classifier_path(&self) -> &Option<PathBuf> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_ordering(&self) -> &Option<Ordering> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_template_filename(&self) -> &Option<PathBuf> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_gen_conf_filename(&self) -> Option<&PathBuf> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_lora_preload_adapter_info(&self) -> &Option<HashMap<String, (PathBuf, LoraConfig)>> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_preprocessor_config(&self) -> &Option<PathBuf> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_processor_config(&self) -> &Option<PathBuf> {
+        unreachable!("Use `std::any::Any`.")
+    }
+    fn get_chat_template_json(&self) -> &Option<PathBuf> {
+        unreachable!("Use `std::any::Any`.")
+    }
+}
+
+// ======================== Whisper loader
+
+/// [`SpeechLoader`] for a Whisper model.
+///
+/// [`SpeechLoader`]: https://ericlbuehler.github.io/mistral.rs/mistralrs/struct.SpeechLoader.html
+pub struct WhisperLoader;
+
+impl SpeechModelLoader for WhisperLoader {
+    fn get_model_paths(&self, api: &ApiRepo, model_id: &Path) -> Result<SpeechModelPathsInner> {
+        let files = api_dir_list!(api, model_id).collect::<Vec<_>>();
+        // Some repos also ship sharded fp32 copies of `model.safetensors`.
+        let filenames = if files.contains(&"model.safetensors".to_string()) {
+            vec![api_get_file!(api, "model.safetensors", model_id)]
+        } else {
+            files
+                .iter()
+                .filter(|x| x.ends_with(".safetensors"))
+                .map(|x| api_get_file!(api, x, model_id))
+                .collect::<Vec<_>>()
+        };
+        if filenames.is_empty() {
+            anyhow::bail!("Expected at least 1 .safetensors file for the Whisper model.");
+        }
+        let generation_config_filename = if files.contains(&"generation_config.json".to_string()) {
+            Some(api_get_file!(api, "generation_config.json", model_id))
+        } else {
+            None
+        };
+        Ok(SpeechModelPathsInner {
+            config_filename: api_get_file!(api, "config.json", model_id),
+            generation_config_filename,
+            tokenizer_filename: api_get_file!(api, "tokenizer.json", model_id),
+            filenames,
+        })
+    }
+    fn load(
+        &self,
+        config: &str,
+        generation_config: Option<&str>,
+        tokenizer: Arc<Tokenizer>,
+        vb: ShardedVarBuilder,
+        normal_loading_metadata: NormalLoadingMetadata,
+    ) -> Result<Box<dyn SpeechModel + Send + Sync>> {
+        let cfg: WhisperConfig = serde_json::from_str(config)?;
+        let generation_cfg: Option<WhisperGenerationConfig> = generation_config
+            .map(serde_json::from_str)
+            .transpose()
+            .context("Failed to parse `generation_config.json`")?;
+        Ok(Box::new(WhisperModel::new(
+            &cfg,
+            generation_cfg,
+            tokenizer,
+            vb,
+            &normal_loading_metadata.real_device,
+        )?))
+    }
+}
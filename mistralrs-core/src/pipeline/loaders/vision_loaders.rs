@@ -1706,6 +1706,15 @@ impl VisionPromptPrefixer for Qwen2VLPrefixer {
             Qwen2VLProcessor::VISION_END
         )
     }
+
+    fn prefix_video(&self, _video_index: usize, prompt: &str) -> String {
+        format!(
+            "{}{}{}{prompt}",
+            Qwen2VLProcessor::VISION_START,
+            Qwen2VLProcessor::VIDEO_PAD,
+            Qwen2VLProcessor::VISION_END
+        )
+    }
 }
 
 impl VisionModelLoader for Qwen2VLLoader {
@@ -2270,6 +2279,10 @@ impl VisionPromptPrefixer for MiniCpmOPrefixer {
     fn prefix_image(&self, _image_index: usize, prompt: &str) -> String {
         format!("(<image>./</image>){prompt}")
     }
+
+    fn prefix_audio(&self, _audio_index: usize, prompt: &str) -> String {
+        format!("(<audio>./</audio>){prompt}")
+    }
 }
 
 impl VisionModelLoader for MiniCpmOLoader {
@@ -2301,12 +2314,13 @@ impl VisionModelLoader for MiniCpmOLoader {
     }
     fn get_processor(
         &self,
-        _model_config: &str,
+        model_config: &str,
         processor_config: Option<ProcessorConfig>,
         preprocessor_config: PreProcessorConfig,
         max_edge: Option<u32>,
     ) -> Arc<dyn Processor + Send + Sync> {
         Arc::new(MiniCpmOProcessor::new(
+            model_config,
             processor_config.unwrap_or_default(),
             preprocessor_config,
             max_edge,
@@ -2403,7 +2417,14 @@ impl DeviceMappedModelLoader for MiniCpmOLoader {
                 * img_seq_len
         };
 
-        Ok(max_vision_attn)
+        let max_audio_attn = cfg.audio_config().map_or(0, |audio_cfg| {
+            max_batch_size
+                * audio_cfg.encoder_attention_heads
+                * audio_cfg.max_source_positions
+                * audio_cfg.max_source_positions
+        });
+
+        Ok(max_vision_attn.max(max_audio_attn))
     }
 
     fn non_mapped_size_in_bytes(
@@ -2457,7 +2478,53 @@ impl DeviceMappedModelLoader for MiniCpmOLoader {
             post_layernorm + patch_embedding + position_embedding + layer_elems
         };
 
-        let elems = text_elems + vision_transformer;
+        let audio_tower = cfg.audio_config().map_or(0, |audio_cfg| {
+            let d_model = audio_cfg.d_model;
+            let ffn_dim = audio_cfg.encoder_ffn_dim;
+
+            let conv1 = audio_cfg.num_mel_bins * d_model * 3 + d_model;
+            let conv2 = d_model * d_model * 3 + d_model;
+            let embed_positions = audio_cfg.max_source_positions * d_model;
+            let layer_norm = 2 * d_model;
+
+            let layer_elems = {
+                let self_attn_layer_norm = 2 * d_model;
+                let final_layer_norm = 2 * d_model;
+
+                let q_proj = d_model * d_model + d_model;
+                let k_proj = d_model * d_model;
+                let v_proj = d_model * d_model + d_model;
+                let out_proj = d_model * d_model + d_model;
+
+                let fc1 = d_model * ffn_dim + ffn_dim;
+                let fc2 = ffn_dim * d_model + d_model;
+
+                self_attn_layer_norm
+                    + final_layer_norm
+                    + q_proj
+                    + k_proj
+                    + v_proj
+                    + out_proj
+                    + fc1
+                    + fc2
+            };
+
+            let projection = {
+                let hidden_size = cfg.text_config.hidden_size;
+                let linear1 = ffn_dim / 4 * hidden_size + hidden_size;
+                let linear2 = hidden_size * hidden_size + hidden_size;
+                linear1 + linear2
+            };
+
+            conv1
+                + conv2
+                + embed_positions
+                + layer_norm
+                + layer_elems * audio_cfg.encoder_layers
+                + projection
+        });
+
+        let elems = text_elems + vision_transformer + audio_tower;
 
         Ok(elems * dtype.size_in_bytes())
     }
@@ -348,6 +348,17 @@ macro_rules! get_paths_gguf {
             None
         };
 
+        // Only used by GGUF vision models, which take the processor settings from the original model.
+        let config_filename = if $crate::api_dir_list!(api, model_id)
+            .collect::<Vec<_>>()
+            .contains(&"config.json".to_string())
+        {
+            info!("Loading `config.json` at `{}`", this_model_id);
+            $crate::api_get_file!(api, "config.json", model_id)
+        } else {
+            PathBuf::from_str("")?
+        };
+
         let tokenizer_filename = if $this.model_id.is_some() {
             info!("Loading `tokenizer.json` at `{}`", this_model_id);
             $crate::api_get_file!(api, "tokenizer.json", model_id)
@@ -371,7 +382,7 @@ macro_rules! get_paths_gguf {
 
         Ok(Box::new($path_name {
             tokenizer_filename,
-            config_filename: PathBuf::from_str("")?,
+            config_filename,
             filenames,
             xlora_adapter_configs: adapter_configs,
             xlora_adapter_filenames: adapter_safetensors,
@@ -2,10 +2,13 @@ mod amoe;
 mod cache_manager;
 pub mod chat_template;
 mod diffusion;
+mod embedding;
 mod ggml;
 mod gguf;
+mod gguf_vision;
 mod inputs_processor;
 mod isq;
+mod kv_cache_quant;
 pub(crate) mod llg;
 mod loaders;
 mod macros;
@@ -15,9 +18,12 @@ mod processing;
 mod response;
 mod sampling;
 mod speculative;
+mod speech;
 mod vision;
 
 pub use super::diffusion_models::DiffusionGenerationParams;
+pub use super::embedding_models::{EmbeddingParams, RerankParams};
+pub use super::speech_models::{TranscriptionParams, TranscriptionTask};
 use crate::amoe::{AnyMoeConfig, AnyMoeExpertType, AnyMoeTrainingInputs, AnyMoeTrainingResult};
 use crate::device_map::DeviceMapper;
 use crate::paged_attention::{CacheConfig, CacheEngine, ModelConfigLike};
@@ -25,29 +31,40 @@ use crate::prefix_cacher_v2::PrefixCacheManagerV2;
 pub use amoe::{AnyMoeLoader, AnyMoePipeline};
 use chat_template::ChatTemplate;
 pub use diffusion::{DiffusionLoader, DiffusionLoaderBuilder, DiffusionSpecificConfig};
+pub use embedding::{EmbeddingLoader, EmbeddingLoaderBuilder};
 pub use ggml::{GGMLLoader, GGMLLoaderBuilder, GGMLSpecificConfig};
 pub use gguf::{GGUFLoader, GGUFLoaderBuilder, GGUFSpecificConfig};
+pub use gguf_vision::{GGUFVisionLoader, GGUFVisionLoaderBuilder};
 use image::DynamicImage;
 pub use inputs_processor::InputProcessorOutput;
+pub(crate) use isq::UqffFullSer;
 pub use isq::{parse_isq_value, IsqModel, IsqOrganization};
+pub use kv_cache_quant::KvCacheType;
 pub use loaders::{
-    AdapterKind, AutoDeviceMapParams, AutoLoader, DeepSeekV2Loader, DeepSeekV3Loader,
-    DeviceMappedModelLoader, DiffusionLoaderType, DiffusionModel, DiffusionModelLoader, FluxLoader,
-    Gemma2Loader, GemmaLoader, Idefics2Loader, Idefics3Loader, LLaVALoader, LLaVANextLoader,
-    LlamaLoader, Loader, LocalModelPaths, MiniCpmOLoader, MistralLoader, MixtralLoader, ModelKind,
-    ModelPaths, NormalLoaderType, NormalLoadingMetadata, NormalModel, NormalModelLoader,
+    AdapterKind, AutoDeviceMapParams, AutoLoader, BertLoader, DeepSeekV2Loader, DeepSeekV3Loader,
+    DeviceMappedModelLoader, DiffusionLoaderType, DiffusionModel, DiffusionModelLoader,
+    EmbeddingLoaderType, EmbeddingModel, EmbeddingModelLoader, FluxLoader, Gemma2Loader,
+    GemmaLoader, Idefics2Loader, Idefics3Loader, JambaLoader, LLaVALoader, LLaVANextLoader,
+    LlamaEmbeddingLoader, LlamaLoader, Loader, LocalModelPaths, Mamba2Loader, MambaLoader,
+    MiniCpmOLoader, MistralEmbeddingLoader, MistralLoader, MixtralLoader, ModelKind, ModelPaths,
+    ModernBertLoader, NormalLoaderType, NormalLoadingMetadata, NormalModel, NormalModelLoader,
     Phi2Loader, Phi3Loader, Phi3VLoader, Phi3_5MoELoader, PrettyName, QuantizationKind,
-    Qwen2Loader, Qwen2VLLoader, Starcoder2Loader, TokenSource, VLlamaLoader, VisionLoaderType,
-    VisionModel, VisionModelLoader,
+    Qwen2EmbeddingLoader, Qwen2Loader, Qwen2VLLoader, SpeechLoaderType, SpeechModel,
+    SpeechModelLoader, StableDiffusionLoader, Starcoder2Loader, TokenSource, VLlamaLoader,
+    VisionLoaderType, VisionModel, VisionModelLoader, WhisperLoader, XLMRobertaLoader,
 };
 use mistralrs_quant::IsqType;
 pub use normal::{NormalLoader, NormalLoaderBuilder, NormalSpecificConfig};
-pub(crate) use paths::{get_chat_template, get_model_paths, get_xlora_paths, XLoraPaths};
+pub(crate) use paths::{
+    get_chat_template, get_model_paths, get_uqff_bundle_paths, get_xlora_paths, XLoraPaths,
+};
 pub(crate) use processing::{
-    apply_chat_template, BasicProcessor, MessagesAction, Processor, ProcessorCreator,
+    apply_chat_template, process_continued_chat, BasicProcessor, MessagesAction, Processor,
+    ProcessorCreator,
 };
 use rand_isaac::Isaac64Rng;
 pub use speculative::{SpeculativeConfig, SpeculativeLoader, SpeculativePipeline};
+pub use speech::{SpeechLoader, SpeechLoaderBuilder};
 use std::any::Any;
 use std::collections::HashMap;
 use std::num::NonZeroUsize;
@@ -59,10 +76,13 @@ pub use vision::{VisionLoader, VisionLoaderBuilder, VisionSpecificConfig};
 use anyhow::Result;
 use candle_core::{DType, Device, IndexOp, Tensor, Var};
 
+use crate::embedding_models::EmbeddingOutput;
 use crate::sequence::Sequence;
+use crate::TranscriptionResponse;
 
 pub use self::cache_manager::{
-    Cache, CacheManager, EitherCache, KvCache, LayerCaches, NormalCache, SingleCache,
+    Cache, CacheManager, EitherCache, KvCache, LayerCaches, NormalCache, RecurrentCache,
+    RecurrentLayerCache, RecurrentState, SingleCache,
 };
 pub use self::inputs_processor::{
     text_models_inputs_processor, InputsProcessor, InputsProcessorType,
@@ -122,10 +142,10 @@ pub trait IsqPipelineMixin {
 pub trait CacheManagerMixin {
     /// Clone the cache FROM the sequences' cache TO the model cache. Only called for completion seqs.
     /// It is not a guarantee that this will be called for each completion step.
-    fn clone_in_cache(&self, seqs: &mut [&mut Sequence]);
+    fn clone_in_cache(&self, seqs: &mut [&mut Sequence]) -> candle_core::Result<()>;
     /// Clone the cache FROM the model cache TO the sequences. Called for prompt and completion seqs.
     /// It is not a guarantee that this will be called for each step.
-    fn clone_out_cache(&self, seqs: &mut [&mut Sequence]);
+    fn clone_out_cache(&self, seqs: &mut [&mut Sequence]) -> candle_core::Result<()>;
     /// Set the model cache to all None. Only called for prompt seqs.
     /// It is not a guarantee that this will be called for each prompt step.
     /// This may also reset the non granular state if applicable.
@@ -135,7 +155,7 @@ pub trait CacheManagerMixin {
         reset_non_granular: bool,
         modify_draft_cache: bool,
         load_preallocated_cache: bool,
-    );
+    ) -> candle_core::Result<()>;
     fn cache(&self) -> &EitherCache;
     fn do_preallocated_cache(&self) -> bool {
         matches!(self.cache(), EitherCache::Normal(_))
@@ -221,6 +241,9 @@ pub enum ModelCategory {
         prefixer: Arc<dyn VisionPromptPrefixer>,
     },
     Diffusion,
+    Speech,
+    Embedding,
+    Reranker,
 }
 
 impl PartialEq for ModelCategory {
@@ -229,9 +252,15 @@ impl PartialEq for ModelCategory {
             (Self::Text, Self::Text) => true,
             (Self::Vision { .. }, Self::Vision { .. }) => true,
             (Self::Diffusion, Self::Diffusion) => true,
+            (Self::Speech, Self::Speech) => true,
+            (Self::Embedding, Self::Embedding) => true,
+            (Self::Reranker, Self::Reranker) => true,
             (Self::Text, _) => false,
             (Self::Vision { .. }, _) => false,
             (Self::Diffusion, _) => false,
+            (Self::Speech, _) => false,
+            (Self::Embedding, _) => false,
+            (Self::Reranker, _) => false,
         }
     }
 }
@@ -240,6 +269,17 @@ impl PartialEq for ModelCategory {
 pub trait VisionPromptPrefixer: Send + Sync {
     /// Prefix for inclusion in messages (may do nothing if the chat template handles it).
     fn prefix_image(&self, image_index: usize, prompt: &str) -> String;
+
+    /// Prefix for a video in messages. Only models which accept videos need to implement this.
+    fn prefix_video(&self, _video_index: usize, prompt: &str) -> String {
+        prompt.to_string()
+    }
+
+    /// Prefix for an audio clip in messages. Only models which accept audio need to implement
+    /// this.
+    fn prefix_audio(&self, _audio_index: usize, prompt: &str) -> String {
+        prompt.to_string()
+    }
 }
 
 pub enum CacheBackendMetadata<'a> {
@@ -257,9 +297,21 @@ pub enum CacheBackendMetadata<'a> {
 
 #[derive(Clone, Debug)]
 pub enum ForwardInputsResult {
-    RawLogits { logits: Tensor },
-    CausalGeneration { logits: Tensor },
-    Image { images: Vec<DynamicImage> },
+    RawLogits {
+        logits: Tensor,
+    },
+    CausalGeneration {
+        logits: Tensor,
+    },
+    Image {
+        images: Vec<DynamicImage>,
+    },
+    Transcription {
+        transcriptions: Vec<TranscriptionResponse>,
+    },
+    Embedding {
+        responses: Vec<EmbeddingOutput>,
+    },
 }
 
 impl ForwardInputsResult {
@@ -274,6 +326,12 @@ impl ForwardInputsResult {
             Self::Image { images } => Ok(Self::Image {
                 images: vec![images[bs_idx].clone()],
             }),
+            Self::Transcription { transcriptions } => Ok(Self::Transcription {
+                transcriptions: vec![transcriptions[bs_idx].clone()],
+            }),
+            Self::Embedding { responses } => Ok(Self::Embedding {
+                responses: vec![responses[bs_idx].clone()],
+            }),
         }
     }
 
@@ -285,7 +343,9 @@ impl ForwardInputsResult {
             Self::RawLogits { logits } => Ok(Self::RawLogits {
                 logits: logits.to_device(device)?,
             }),
-            Self::Image { .. } => Ok(self.clone()),
+            Self::Image { .. } | Self::Transcription { .. } | Self::Embedding { .. } => {
+                Ok(self.clone())
+            }
         }
     }
 }
@@ -370,7 +430,7 @@ pub trait Pipeline:
                                     }
                                     AdapterInstruction::None => 0,
                                 };
-                                self.clone_in_cache(input_seqs)
+                                self.clone_in_cache(input_seqs)?
                             }
                             CacheInstruction::Nothing(ref adapter_inst) => {
                                 match adapter_inst {
@@ -408,7 +468,7 @@ pub trait Pipeline:
                                     reset_non_granular,
                                     false,
                                     load_preallocated_cache,
-                                )
+                                )?
                             }
                             _ => unreachable!("Unreachable PRE cache op."),
                         }
@@ -430,7 +490,7 @@ pub trait Pipeline:
                 }
 
                 match post_op {
-                    CacheInstruction::Out => self.clone_out_cache(input_seqs),
+                    CacheInstruction::Out => self.clone_out_cache(input_seqs)?,
                     CacheInstruction::Nothing(_) => (),
                     CacheInstruction::Reset {
                         load_preallocated_cache,
@@ -441,7 +501,7 @@ pub trait Pipeline:
                         reset_non_granular,
                         false,
                         load_preallocated_cache,
-                    ),
+                    )?,
                     _ => unreachable!("Unreachable POST cache op."),
                 }
 
@@ -516,6 +576,45 @@ pub trait Pipeline:
                         )
                         .await?;
                     }
+                    ForwardInputsResult::Transcription { .. } => {
+                        response::send_transcription_responses(
+                            input_seqs,
+                            logits
+                                .into_iter()
+                                .map(|r| {
+                                    let ForwardInputsResult::Transcription { transcriptions } = r
+                                    else {
+                                        unreachable!(
+                                            "All results must have same type, `Transcription`"
+                                        )
+                                    };
+                                    transcriptions
+                                        .into_iter()
+                                        .next()
+                                        .expect("Must have at least 1 element.")
+                                })
+                                .collect::<Vec<_>>(),
+                        )
+                        .await?;
+                    }
+                    ForwardInputsResult::Embedding { .. } => {
+                        response::send_embedding_responses(
+                            input_seqs,
+                            logits
+                                .into_iter()
+                                .map(|r| {
+                                    let ForwardInputsResult::Embedding { responses } = r else {
+                                        unreachable!("All results must have same type, `Embedding`")
+                                    };
+                                    responses
+                                        .into_iter()
+                                        .next()
+                                        .expect("Must have at least 1 element.")
+                                })
+                                .collect::<Vec<_>>(),
+                        )
+                        .await?;
+                    }
                 }
                 let end = Instant::now();
                 exec_duration += end.duration_since(start);
@@ -661,6 +760,45 @@ pub trait Pipeline:
                         )
                         .await?;
                     }
+                    ForwardInputsResult::Transcription { .. } => {
+                        response::send_transcription_responses(
+                            input_seqs,
+                            logits
+                                .into_iter()
+                                .map(|r| {
+                                    let ForwardInputsResult::Transcription { transcriptions } = r
+                                    else {
+                                        unreachable!(
+                                            "All results must have same type, `Transcription`"
+                                        )
+                                    };
+                                    transcriptions
+                                        .into_iter()
+                                        .next()
+                                        .expect("Must have at least 1 element.")
+                                })
+                                .collect::<Vec<_>>(),
+                        )
+                        .await?;
+                    }
+                    ForwardInputsResult::Embedding { .. } => {
+                        response::send_embedding_responses(
+                            input_seqs,
+                            logits
+                                .into_iter()
+                                .map(|r| {
+                                    let ForwardInputsResult::Embedding { responses } = r else {
+                                        unreachable!("All results must have same type, `Embedding`")
+                                    };
+                                    responses
+                                        .into_iter()
+                                        .next()
+                                        .expect("Must have at least 1 element.")
+                                })
+                                .collect::<Vec<_>>(),
+                        )
+                        .await?;
+                    }
                 }
                 let end = Instant::now();
                 exec_duration += end.duration_since(start);
@@ -739,11 +877,13 @@ mod tests {
                     inputs.clone()
                 },
                 true,
+                false,
                 &ChatTemplateValue(Either::Left(template.to_string())),
                 Some(bos.to_string()),
                 Some(eos.to_string()),
                 Some(unk.to_string()),
                 Vec::new(),
+                None,
             ) {
                 Ok(v) => v,
                 Err(e) => {
@@ -938,4 +1078,208 @@ mod tests {
 
         test_with_inputs(&templates, &expected_outputs, inputs);
     }
+
+    #[test]
+    /// Each case in `tests/chat_templates` holds a real-world chat template with its inputs and the
+    /// output of `transformers`' Jinja environment:
+    /// ```py
+    /// >>> t.apply_chat_template(case["messages"], tools=case["tools"], add_generation_prompt=case["add_generation_prompt"], continue_final_message=case.get("continue_final_message", False), tokenize=False, **(case["chat_template_kwargs"] or {}))
+    /// ```
+    fn test_chat_template_corpus() {
+        use std::collections::HashMap;
+
+        use super::chat_template::{apply_chat_template_to, ChatTemplateValue};
+        use crate::Tool;
+
+        #[derive(serde::Deserialize)]
+        struct ChatTemplateCase {
+            name: String,
+            chat_template: Value,
+            bos_token: Option<String>,
+            eos_token: Option<String>,
+            unk_token: Option<String>,
+            add_generation_prompt: bool,
+            #[serde(default)]
+            continue_final_message: bool,
+            chat_template_kwargs: Option<HashMap<String, Value>>,
+            tools: Option<Vec<Tool>>,
+            messages: Vec<IndexMap<String, Value>>,
+            expected: String,
+        }
+
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/chat_templates");
+        let mut paths = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        let mut failed = Vec::new();
+        for path in &paths {
+            let case: ChatTemplateCase =
+                serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+            let template = match case.chat_template {
+                Value::String(template) => ChatTemplateValue(Either::Left(template)),
+                named => ChatTemplateValue(Either::Right(serde_json::from_value(named).unwrap())),
+            };
+            let messages = case
+                .messages
+                .into_iter()
+                .map(|message| {
+                    message
+                        .into_iter()
+                        .map(|(k, v)| {
+                            let content: MessageContent = match v {
+                                Value::String(text) => Either::Left(text),
+                                parts => Either::Right(serde_json::from_value(parts).unwrap()),
+                            };
+                            (k, content)
+                        })
+                        .collect()
+                })
+                .collect();
+            match apply_chat_template_to(
+                messages,
+                case.add_generation_prompt,
+                case.continue_final_message,
+                &template,
+                case.bos_token,
+                case.eos_token,
+                case.unk_token,
+                case.tools.unwrap_or_default(),
+                case.chat_template_kwargs,
+            ) {
+                Ok(output) if output == case.expected => {}
+                Ok(output) => failed.push(format!(
+                    "{}\nExpected: `{}` \n\nGot:      `{}`",
+                    case.name,
+                    case.expected.replace('\n', "\\n"),
+                    output.replace('\n', "\\n")
+                )),
+                Err(e) => failed.push(format!("{}\nFailed with {e}.", case.name)),
+            }
+        }
+        if !failed.is_empty() {
+            for line in &failed {
+                println!("------------------------");
+                println!("{line}");
+            }
+            println!("------------------------");
+            panic!("{}/{} chat templates failed.", failed.len(), paths.len());
+        }
+    }
+
+    fn message(role: &str, content: MessageContent) -> IndexMap<String, MessageContent> {
+        IndexMap::from([
+            ("role".to_string(), Either::Left(role.to_string())),
+            ("content".to_string(), content),
+        ])
+    }
+
+    fn text_message(role: &str, content: &str) -> IndexMap<String, MessageContent> {
+        message(role, Either::Left(content.to_string()))
+    }
+
+    /// Render `messages` with `template`, leaving the final message open.
+    fn continue_final_message(
+        template: &str,
+        messages: Vec<IndexMap<String, MessageContent>>,
+        add_generation_prompt: bool,
+    ) -> anyhow::Result<String> {
+        use super::chat_template::{apply_chat_template_to, ChatTemplateValue};
+
+        apply_chat_template_to(
+            messages,
+            add_generation_prompt,
+            true,
+            &ChatTemplateValue(Either::Left(template.to_string())),
+            None,
+            None,
+            None,
+            Vec::new(),
+            None,
+        )
+    }
+
+    const CHATML_TEMPLATE: &str = "{% for message in messages %}{{ '<|im_start|>' + message['role'] + '\n' + message['content'] + '<|im_end|>\n' }}{% endfor %}";
+
+    #[test]
+    fn test_continue_final_message_trimmed_by_template() {
+        let trimming = "{% for message in messages %}{{ '<|im_start|>' + message['role'] + '\n' + (message['content'] | trim) + '<|im_end|>\n' }}{% endfor %}";
+        let messages = vec![
+            text_message("user", "What is 2 + 2?"),
+            text_message("assistant", "The answer is  \n"),
+        ];
+
+        assert_eq!(
+            continue_final_message(trimming, messages.clone(), false).unwrap(),
+            "<|im_start|>user\nWhat is 2 + 2?<|im_end|>\n<|im_start|>assistant\nThe answer is"
+        );
+        // A template which keeps the whitespace keeps it in the prompt too.
+        assert_eq!(
+            continue_final_message(CHATML_TEMPLATE, messages, false).unwrap(),
+            "<|im_start|>user\nWhat is 2 + 2?<|im_end|>\n<|im_start|>assistant\nThe answer is  \n"
+        );
+    }
+
+    #[test]
+    fn test_continue_final_message_content_parts() {
+        let template = "{% for message in messages %}<|{{ message['role'] }}|>{% if message['content'] is string %}{{ message['content'] }}{% else %}{% for part in message['content'] %}{% if part['type'] == 'text' %}{{ part['text'] }}{% endif %}{% endfor %}{% endif %}<|end|>{% endfor %}";
+        let part = |text: &str| {
+            IndexMap::from([
+                ("type".to_string(), Value::String("text".to_string())),
+                ("text".to_string(), Value::String(text.to_string())),
+            ])
+        };
+        let messages = vec![
+            text_message("user", "Write JSON."),
+            message(
+                "assistant",
+                Either::Right(vec![part("```json\n"), part("{")]),
+            ),
+        ];
+
+        assert_eq!(
+            continue_final_message(template, messages, false).unwrap(),
+            "<|user|>Write JSON.<|end|><|assistant|>```json\n{"
+        );
+    }
+
+    #[test]
+    fn test_continue_final_message_requires_assistant() {
+        let messages = vec![
+            text_message("assistant", "Hello!"),
+            text_message("user", "Continue this"),
+        ];
+        let err = continue_final_message(CHATML_TEMPLATE, messages, false).unwrap_err();
+        assert!(err.to_string().contains("from the assistant"), "{err}");
+
+        assert!(continue_final_message(CHATML_TEMPLATE, Vec::new(), false).is_err());
+    }
+
+    #[test]
+    fn test_continue_final_message_rejects_generation_prompt() {
+        let messages = vec![
+            text_message("user", "Hi"),
+            text_message("assistant", "Hello"),
+        ];
+        let err = continue_final_message(CHATML_TEMPLATE, messages, true).unwrap_err();
+        assert!(err.to_string().contains("mutually exclusive"), "{err}");
+    }
+
+    #[test]
+    fn test_continue_final_message_uses_last_occurrence() {
+        // The final message is rendered once in the conversation and once more at the end. The
+        // user message is the same text, so only the last occurrence is the right one.
+        let template = "{% for message in messages %}<|{{ message['role'] }}|>{{ message['content'] }}<|end|>{% endfor %}{% set last = messages | last %}<|recap|>{{ last['content'] }}<|end|>";
+        let messages = vec![
+            text_message("user", "Echo"),
+            text_message("assistant", "Echo"),
+        ];
+
+        assert_eq!(
+            continue_final_message(template, messages, false).unwrap(),
+            "<|user|>Echo<|end|><|assistant|>Echo<|end|><|recap|>Echo"
+        );
+    }
 }
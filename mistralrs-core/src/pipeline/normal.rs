@@ -1,21 +1,21 @@
-use super::cache_manager::{FullCacheManager, NormalCacheManager};
+use super::cache_manager::{FullCacheManager, NormalCacheManager, RecurrentCacheManager};
 use super::inputs_processor::DEFAULT_PROMPT_CHUNK_SIZE;
-use super::isq::ImatrixDataSource;
+use super::isq::{check_calibration_requirements, ImatrixDataSource};
 use super::llg::build_tok_env;
 use super::{
-    get_model_paths, get_xlora_paths, text_models_inputs_processor::ModelInputs, AdapterKind,
-    CacheManager, GeneralMetadata, Loader, ModelKind, ModelPaths, NormalModel, NormalModelLoader,
-    TokenSource, XLoraPaths,
+    get_model_paths, get_uqff_bundle_paths, get_xlora_paths,
+    text_models_inputs_processor::ModelInputs, AdapterKind, CacheManager, GeneralMetadata, Loader,
+    ModelKind, ModelPaths, NormalModel, NormalModelLoader, TokenSource, XLoraPaths,
 };
 use super::{
     AdapterActivationMixin, AnyMoePipelineMixin, CacheManagerMixin, EitherCache,
-    ForwardInputsResult, IsqOrganization, IsqPipelineMixin, MetadataMixin, ModelCategory,
-    PreProcessingMixin,
+    ForwardInputsResult, IsqOrganization, IsqPipelineMixin, KvCacheType, MetadataMixin,
+    ModelCategory, PreProcessingMixin,
 };
 use super::{
-    AutoLoader, DeepSeekV2Loader, DeepSeekV3Loader, Gemma2Loader, GemmaLoader, LlamaLoader,
-    MistralLoader, MixtralLoader, NormalLoaderType, Phi2Loader, Phi3Loader, Phi3_5MoELoader,
-    Qwen2Loader, Starcoder2Loader,
+    AutoLoader, DeepSeekV2Loader, DeepSeekV3Loader, Gemma2Loader, GemmaLoader, JambaLoader,
+    LlamaLoader, Mamba2Loader, MambaLoader, MistralLoader, MixtralLoader, NormalLoaderType,
+    Phi2Loader, Phi3Loader, Phi3_5MoELoader, Qwen2Loader, Starcoder2Loader,
 };
 use crate::amoe::AnyMoeExpertType;
 use crate::device_map::{self, DeviceMapper};
@@ -29,6 +29,7 @@ use crate::pipeline::text_models_inputs_processor::make_prompt_chunk;
 use crate::pipeline::{ChatTemplate, LocalModelPaths};
 use crate::prefix_cacher_v2::PrefixCacheManagerV2;
 use crate::sequence::Sequence;
+use crate::topology::{solve_topology, topology_yaml};
 use crate::utils::tokenizer::get_tokenizer;
 use crate::utils::varbuilder_utils::DeviceForLoadTensor;
 use crate::utils::{tokens::get_token, varbuilder_utils::from_mmaped_safetensors};
@@ -36,13 +37,15 @@ use crate::xlora_models::NonGranularState;
 use crate::{
     api_dir_list, api_get_file, get_mut_arcmutex, get_paths, get_uqff_paths, lora_model_loader,
     normal_model_loader, normal_model_loader_sharded, xlora_model_loader, DeviceMapSetting,
-    PagedAttentionConfig, Pipeline, Topology, TryIntoDType,
+    PagedAttentionConfig, Pipeline, Topology, TopologySearch, TryIntoDType,
 };
 use anyhow::{Context, Result};
 use candle_core::{Device, Tensor, Var};
 use hf_hub::{api::sync::ApiBuilder, Repo, RepoType};
 use indicatif::MultiProgress;
-use mistralrs_quant::{GgufMatMul, HqqLayer, IsqType, QuantizedSerdeType, ShardedSafeTensors};
+use mistralrs_quant::{
+    AwqLayer, GgufMatMul, HqqLayer, IsqType, QuantizedSerdeType, ShardedSafeTensors,
+};
 use rand_isaac::Isaac64Rng;
 use rayon::iter::{
     IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator,
@@ -89,6 +92,9 @@ pub struct NormalLoader {
     kind: ModelKind,
     xlora_order: Option<Ordering>,
     no_kv_cache: bool,
+    kv_cache_type: KvCacheType,
+    topology_search: Option<TopologySearch>,
+    self_contained_uqff: bool,
     chat_template: Option<String>,
     tokenizer_json: Option<String>,
     tgt_non_granular_index: Option<usize>,
@@ -106,6 +112,9 @@ pub struct NormalLoaderBuilder {
     kind: ModelKind,
     xlora_order: Option<Ordering>,
     no_kv_cache: bool,
+    kv_cache_type: KvCacheType,
+    topology_search: Option<TopologySearch>,
+    self_contained_uqff: bool,
     chat_template: Option<String>,
     tokenizer_json: Option<String>,
     tgt_non_granular_index: Option<usize>,
@@ -148,6 +157,25 @@ impl NormalLoaderBuilder {
         self
     }
 
+    pub fn with_kv_cache_type(mut self, kv_cache_type: KvCacheType) -> Self {
+        self.kv_cache_type = kv_cache_type;
+        self
+    }
+
+    /// Search for a topology meeting a memory budget using the calibration file, write it out and
+    /// quantize the model with it.
+    pub fn with_topology_search(mut self, topology_search: Option<TopologySearch>) -> Self {
+        self.topology_search = topology_search;
+        self
+    }
+
+    /// Embed the residual tensors, config and tokenizer in the UQFF file written with `write_uqff`,
+    /// so that it can be loaded from a single path.
+    pub fn with_self_contained_uqff(mut self, self_contained_uqff: bool) -> Self {
+        self.self_contained_uqff = self_contained_uqff;
+        self
+    }
+
     fn with_adapter(
         mut self,
         xlora_model_id: String,
@@ -212,6 +240,9 @@ impl NormalLoaderBuilder {
             Some(NormalLoaderType::Phi3_5MoE) => Box::new(Phi3_5MoELoader),
             Some(NormalLoaderType::DeepSeekV2) => Box::new(DeepSeekV2Loader),
             Some(NormalLoaderType::DeepSeekV3) => Box::new(DeepSeekV3Loader),
+            Some(NormalLoaderType::Mamba) => Box::new(MambaLoader),
+            Some(NormalLoaderType::Mamba2) => Box::new(Mamba2Loader),
+            Some(NormalLoaderType::Jamba) => Box::new(JambaLoader),
             None => Box::new(AutoLoader),
         };
         Ok(Box::new(NormalLoader {
@@ -222,6 +253,9 @@ impl NormalLoaderBuilder {
             kind: self.kind,
             xlora_order: self.xlora_order,
             no_kv_cache: self.no_kv_cache,
+            kv_cache_type: self.kv_cache_type,
+            topology_search: self.topology_search,
+            self_contained_uqff: self.self_contained_uqff,
             chat_template: self.chat_template,
             tokenizer_json: self.tokenizer_json,
             tgt_non_granular_index: self.tgt_non_granular_index,
@@ -245,6 +279,36 @@ impl Loader for NormalLoader {
         in_situ_quant: Option<IsqType>,
         paged_attn_config: Option<PagedAttentionConfig>,
     ) -> Result<Arc<Mutex<dyn Pipeline + Send + Sync>>> {
+        if let Some(from_uqff) = self
+            .config
+            .from_uqff
+            .clone()
+            .filter(|p| p.exists() && mistralrs_quant::is_self_contained_uqff(p))
+        {
+            anyhow::ensure!(
+                self.xlora_model_id.is_none(),
+                "Self-contained UQFF files cannot be loaded with adapters."
+            );
+            info!("Loading self-contained UQFF file `{}`", from_uqff.display());
+            let paths =
+                get_uqff_bundle_paths(&from_uqff, &self.tokenizer_json, &self.chat_template)?;
+            *self.from_uqff.write().unwrap() = Some(from_uqff);
+            *self
+                .token_source
+                .write()
+                .expect("Failed to write to token source") = Some(token_source);
+            *self.revision.write().expect("Failed to write to revision") = revision;
+            return self.load_model_from_path(
+                &paths,
+                dtype,
+                device,
+                silent,
+                mapper,
+                in_situ_quant,
+                paged_attn_config,
+            );
+        }
+
         let paths: anyhow::Result<Box<dyn ModelPaths>> = get_paths!(
             LocalModelPaths,
             &token_source,
@@ -287,6 +351,10 @@ impl Loader for NormalLoader {
     ) -> Result<Arc<Mutex<dyn Pipeline + Send + Sync>>> {
         let config = std::fs::read_to_string(paths.get_config_filename())?;
 
+        if !self.inner.supports_paged_attention(&config)? {
+            paged_attn_config = None;
+        }
+
         // Apply default prompt size here
         let prompt_chunksize = self
             .config
@@ -322,8 +390,14 @@ impl Loader for NormalLoader {
                             candle_core::safetensors::MmapedSafetensors::new(serialized)?
                         };
                         let mut total_pack_factors = 0;
-                        let total_tensors = ser_artifacts.tensors().len();
-                        for (_, artifact) in ser_artifacts.tensors() {
+                        // Self-contained UQFF files also hold the residual tensors
+                        let layers = ser_artifacts
+                            .tensors()
+                            .into_iter()
+                            .filter(|(name, _)| name.parse::<usize>().is_ok())
+                            .collect::<Vec<_>>();
+                        let total_tensors = layers.len();
+                        for (_, artifact) in layers {
                             let artifact = artifact.data();
                             // NOTE(EricLBuehler): isq type is ALWAYS byte 4 (5th) of the tensor.
                             let isq_type = artifact[mistralrs_quant::UQFF_QUANT_TYPE_OFFSET];
@@ -339,6 +413,7 @@ impl Loader for NormalLoader {
                                 }
                                 QuantizedSerdeType::Fp8 => IsqType::F8E4M3.pack_factor(dtype),
                                 QuantizedSerdeType::Unquant => 1,
+                                QuantizedSerdeType::Awq => AwqLayer::pack_factor(dtype),
                             };
                             total_pack_factors += pack_factor;
                         }
@@ -425,13 +500,20 @@ impl Loader for NormalLoader {
             paged_attn_config = None;
         }
 
+        // Quantized KV caches are only implemented in the PagedAttention kernels.
+        self.kv_cache_type.validate(
+            paged_attn_config.is_some() && !matches!(self.kind, ModelKind::Adapter { .. }),
+        )?;
+
         info!(
             "Model config: {:?}",
             self.inner
                 .get_config_repr(&config, self.config.use_flash_attn)?
         );
 
-        let mut loading_isq = in_situ_quant.is_some() || self.config.from_uqff.is_some();
+        let mut loading_isq = in_situ_quant.is_some()
+            || self.config.from_uqff.is_some()
+            || self.topology_search.is_some();
         if let Some(ref topology) = self.config.topology {
             loading_isq |= topology
                 .0
@@ -444,6 +526,26 @@ impl Loader for NormalLoader {
                 "`imatrix` and `calibration_file` were both specified, this is not allowed."
             );
         }
+        if let Some(search) = &self.topology_search {
+            search.validate()?;
+            if self.config.calibration_file.is_none() {
+                anyhow::bail!("Topology search requires a calibration file (`calibration_file`).");
+            }
+            if self.config.topology.is_some() {
+                anyhow::bail!(
+                    "`topology` and a topology search were both specified, this is not allowed."
+                );
+            }
+            if !matches!(self.config.organization, IsqOrganization::Default) {
+                anyhow::bail!("Topology search only supports the default ISQ organization.");
+            }
+        }
+        check_calibration_requirements(
+            in_situ_quant,
+            self.config.topology.as_ref(),
+            self.config.imatrix.is_some(),
+            self.config.calibration_file.is_some(),
+        )?;
 
         // Load onto the regular device if not using isq or if the calibration file is specified
         let load_device = if !loading_isq || self.config.calibration_file.is_some() {
@@ -824,66 +926,96 @@ impl Loader for NormalLoader {
                 .token_to_id(&bos_toks[0])
                 .expect("Somehow the bos token is not present.");
 
-            for model in &mut parallel_models {
-                match self.config.organization {
-                    IsqOrganization::Default => model.begin_track_stats()?,
-                    IsqOrganization::MoeExpertsOnly => {
-                        model.begin_track_stats_moe_experts_only()?
-                    }
-                }
-            }
-
             const CHUNK_SIZE: usize = 1024;
             let n_chunks = tokens.len().div_ceil(CHUNK_SIZE);
+            // GPTQ needs the full Hessian of the inputs of each of its layers. To only keep the
+            // Hessians of one decoder layer in memory, the calibration data is run once for each
+            // decoder layer quantized with GPTQ, which is quantized at the end of its pass.
+            let gptq_layers =
+                parallel_models[0].gptq_layers(in_situ_quant, self.config.topology.as_ref());
+            let passes = if gptq_layers.is_empty() {
+                vec![None]
+            } else {
+                gptq_layers.into_iter().map(Some).collect::<Vec<_>>()
+            };
+            let n_passes = passes.len();
             let start = Instant::now();
-            for (i, chunk) in tokens.chunks(CHUNK_SIZE).enumerate() {
-                let chunk = [vec![bos_tok_id], chunk.to_vec()].concat();
-                let chunk_len = chunk.len();
-
-                let start = Instant::now();
-                let inputs = make_prompt_chunk(
-                    0,
-                    vec![chunk],
-                    &[0],
-                    &load_device,
-                    None,
-                    false,
-                    None,
-                    Some(pipeline_mapper.as_ref()),
-                )?;
-                let _ = parallel_models
-                    .par_iter()
-                    .map(|model| {
-                        model.forward(
-                            &inputs.input.to_device(model.device())?,
-                            &inputs.positions,
-                            inputs.context_lens.clone(),
-                            inputs.position_ids.clone(),
-                            None,
-                            &inputs.flash_meta.clone(),
+            for (pass, hessian_layer) in passes.into_iter().enumerate() {
+                if let Some(layer_num) = hessian_layer {
+                    info!(
+                        "Calibration pass {}/{n_passes}: GPTQ Hessians of {}.",
+                        pass + 1,
+                        layer_num.map_or(
+                            "the layers outside of the decoder layers".to_string(),
+                            |layer| format!("layer {layer}")
                         )
-                    })
-                    .collect::<candle_core::Result<Vec<_>>>()?;
+                    );
+                }
+                let track_hessian = |layer_num| hessian_layer == Some(layer_num);
                 for model in &mut parallel_models {
-                    match model.cache_mut() {
-                        EitherCache::Full(full) => {
-                            for layer in &mut *full.lock() {
-                                *layer = None
-                            }
+                    match self.config.organization {
+                        IsqOrganization::Default => model.begin_track_stats(&track_hessian)?,
+                        IsqOrganization::MoeExpertsOnly => {
+                            model.begin_track_stats_moe_experts_only(&track_hessian)?
                         }
-                        EitherCache::Normal(normal) => {
-                            for layer in &mut *normal.lock().unwrap().0 {
-                                layer.set_len(0);
+                    }
+                }
+
+                for (i, chunk) in tokens.chunks(CHUNK_SIZE).enumerate() {
+                    let chunk = [vec![bos_tok_id], chunk.to_vec()].concat();
+                    let chunk_len = chunk.len();
+
+                    let start = Instant::now();
+                    let inputs = make_prompt_chunk(
+                        0,
+                        vec![chunk],
+                        &[0],
+                        &load_device,
+                        None,
+                        false,
+                        None,
+                        Some(pipeline_mapper.as_ref()),
+                    )?;
+                    let _ = parallel_models
+                        .par_iter()
+                        .map(|model| {
+                            model.forward(
+                                &inputs.input.to_device(model.device())?,
+                                &inputs.positions,
+                                inputs.context_lens.clone(),
+                                inputs.position_ids.clone(),
+                                None,
+                                &inputs.flash_meta.clone(),
+                            )
+                        })
+                        .collect::<candle_core::Result<Vec<_>>>()?;
+                    for model in &mut parallel_models {
+                        match model.cache_mut() {
+                            EitherCache::Full(full) => {
+                                for layer in &mut *full.lock() {
+                                    *layer = None
+                                }
                             }
+                            EitherCache::Normal(normal) => {
+                                for layer in &mut *normal.lock().unwrap().0 {
+                                    layer.set_len(0);
+                                }
+                            }
+                            EitherCache::Recurrent(recurrent) => recurrent.lock().unwrap().reset(),
                         }
                     }
+                    let end = Instant::now();
+                    info!(
+                        "Processed chunk {}/{n_chunks} ({chunk_len} tokens), {:.2}s",
+                        i + 1,
+                        end.duration_since(start).as_secs_f32()
+                    );
+                }
+                if hessian_layer.is_some() {
+                    for model in &mut parallel_models {
+                        model.quantize_tracked_hessians()?;
+                    }
                 }
-                let end = Instant::now();
-                info!(
-                    "Processed chunk {}/{n_chunks} ({chunk_len} tokens), {:.2}s",
-                    i + 1,
-                    end.duration_since(start).as_secs_f32()
-                );
             }
             load_device.synchronize()?;
             let end = Instant::now();
@@ -893,17 +1025,52 @@ impl Loader for NormalLoader {
             );
         }
 
-        if (in_situ_quant.is_some() || self.config.topology.is_some())
-            && self.config.from_uqff.is_none()
-        {
+        let mut topology = self.config.topology.clone();
+        let mut searched_imatrix = None;
+        if let Some(search) = &self.topology_search {
+            if parallel_models.len() > 1 {
+                anyhow::bail!("Topology search is not supported with tensor parallelism.");
+            }
+            // The collected imatrix is consumed here, so it is kept to quantize with. It is also
+            // saved next to the topology so that it can be reused with `--imatrix`.
+            let model = &mut parallel_models[0];
+            let imatrix = model.extract_imatrix_data()?;
+            let imatrix_path = search.output.with_extension("cimatrix");
+            info!(
+                "Saving collected imatrix data to `{}`",
+                imatrix_path.display()
+            );
+            imatrix.save_imatrix(&imatrix_path)?;
+
+            info!(
+                "Measuring the quantization sensitivity of each layer for {:?}.",
+                search.candidates
+            );
+            let start = Instant::now();
+            let sensitivities = model.measure_isq_sensitivity(&search.candidates, &imatrix)?;
+            let assignment = solve_topology(&sensitivities, search.budget_bytes)?;
+            let yaml = topology_yaml(&assignment);
+            std::fs::write(&search.output, &yaml)?;
+            info!(
+                "Searched topology in {:.2}s, wrote it to `{}`.",
+                Instant::now().duration_since(start).as_secs_f32(),
+                search.output.display()
+            );
+            topology = Some(Topology::from(assignment.as_slice()));
+            searched_imatrix = Some(imatrix);
+        }
+
+        if (in_situ_quant.is_some() || topology.is_some()) && self.config.from_uqff.is_none() {
             let imatrix_source = match (
                 self.config.imatrix.as_ref(),
                 self.config.calibration_file.is_some(),
+                searched_imatrix.as_ref(),
             ) {
-                (None, false) => None,
-                (Some(file), false) => Some(ImatrixDataSource::File(file)),
-                (None, true) => Some(ImatrixDataSource::Collected),
-                (Some(_), true) => unreachable!(),
+                (_, _, Some(data)) => Some(ImatrixDataSource::Data(data)),
+                (None, false, None) => None,
+                (Some(file), false, None) => Some(ImatrixDataSource::File(file)),
+                (None, true, None) => Some(ImatrixDataSource::Collected),
+                (Some(_), true, None) => unreachable!(),
             };
 
             info!("Applying ISQ to all ranks.");
@@ -915,7 +1082,7 @@ impl Loader for NormalLoader {
                     model.quantize(
                         in_situ_quant,
                         model.device().clone(),
-                        self.config.topology.as_ref(),
+                        topology.as_ref(),
                         silent,
                         imatrix_source,
                         self.config.organization,
@@ -927,6 +1094,7 @@ impl Loader for NormalLoader {
                             config: config.clone(),
                             processor_filename: &None,
                             preprocessor_filename: &None,
+                            self_contained: self.self_contained_uqff,
                         },
                         multi_progress.clone(),
                     )
@@ -937,12 +1105,7 @@ impl Loader for NormalLoader {
             for (rank, model) in parallel_models.iter_mut().enumerate() {
                 info!("Loading UFF for rank {}/{world_size}", rank + 1);
 
-                model.load_from_artifacts(
-                    device.clone(),
-                    self.config.topology.as_ref(),
-                    silent,
-                    from_uqff,
-                )?;
+                model.load_from_artifacts(device.clone(), topology.as_ref(), silent, from_uqff)?;
             }
         }
 
@@ -961,6 +1124,7 @@ impl Loader for NormalLoader {
                 paged_attn_config.mem_cpu,
                 paged_attn_config.block_size,
                 dtype,
+                self.kv_cache_type,
                 parallel_models[0].config(),
                 device,
                 &pipeline_mapper
@@ -996,6 +1160,7 @@ impl Loader for NormalLoader {
         let num_hidden_layers = match parallel_models[0].cache() {
             EitherCache::Full(full) => full.lock().len(),
             EitherCache::Normal(normal) => normal.lock().unwrap().0.len(),
+            EitherCache::Recurrent(recurrent) => recurrent.lock().unwrap().0.len(),
         };
         let eos = calculate_eos_tokens(&chat_template, gen_conf, &tokenizer);
         let sliding_window = parallel_models[0].config().sliding_window;
@@ -1033,7 +1198,7 @@ impl Loader for NormalLoader {
                 prompt_chunksize: Some(NonZero::new(prompt_chunksize).unwrap()),
                 model_metadata: Some(model_metadata),
             }),
-            topology: self.config.topology.clone(),
+            topology,
             silent,
             organization: self.config.organization,
             template_filename: paths.get_template_filename().clone(),
@@ -1087,6 +1252,7 @@ impl IsqPipelineMixin for NormalPipeline {
                         config: self.config.clone(),
                         processor_filename: &None,
                         preprocessor_filename: &None,
+                        self_contained: false,
                     },
                     multi_progress.clone(),
                 )
@@ -1097,24 +1263,24 @@ impl IsqPipelineMixin for NormalPipeline {
 }
 
 impl CacheManagerMixin for NormalPipeline {
-    fn clone_in_cache(&self, seqs: &mut [&mut Sequence]) {
+    fn clone_in_cache(&self, seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
         if self.parallel_models.len() != 1 {
             panic!("Number of parallel models is not 1.");
         }
-        if matches!(self.parallel_models[0].cache(), EitherCache::Full(_)) {
-            FullCacheManager.clone_in_cache(self, seqs, false)
-        } else {
-            NormalCacheManager.clone_in_cache(self, seqs, false)
+        match self.parallel_models[0].cache() {
+            EitherCache::Full(_) => FullCacheManager.clone_in_cache(self, seqs, false),
+            EitherCache::Normal(_) => NormalCacheManager.clone_in_cache(self, seqs, false),
+            EitherCache::Recurrent(_) => RecurrentCacheManager.clone_in_cache(self, seqs, false),
         }
     }
-    fn clone_out_cache(&self, seqs: &mut [&mut Sequence]) {
+    fn clone_out_cache(&self, seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
         if self.parallel_models.len() != 1 {
             panic!("Number of parallel models is not 1.");
         }
-        if matches!(self.parallel_models[0].cache(), EitherCache::Full(_)) {
-            FullCacheManager.clone_out_cache(self, seqs, false)
-        } else {
-            NormalCacheManager.clone_out_cache(self, seqs, false)
+        match self.parallel_models[0].cache() {
+            EitherCache::Full(_) => FullCacheManager.clone_out_cache(self, seqs, false),
+            EitherCache::Normal(_) => NormalCacheManager.clone_out_cache(self, seqs, false),
+            EitherCache::Recurrent(_) => RecurrentCacheManager.clone_out_cache(self, seqs, false),
         }
     }
     fn set_none_cache(
@@ -1124,23 +1290,28 @@ impl CacheManagerMixin for NormalPipeline {
         modify_draft_cache: bool,
 
         load_preallocated_cache: bool,
-    ) {
+    ) -> candle_core::Result<()> {
         if self.parallel_models.len() != 1 {
             panic!("Number of parallel models is not 1.");
         }
-        if matches!(self.parallel_models[0].cache(), EitherCache::Full(_)) {
-            FullCacheManager.set_none_cache(self, seqs, modify_draft_cache, false);
-        } else {
-            NormalCacheManager.set_none_cache(
+        match self.parallel_models[0].cache() {
+            EitherCache::Full(_) => {
+                FullCacheManager.set_none_cache(self, seqs, modify_draft_cache, false)?
+            }
+            EitherCache::Normal(_) => NormalCacheManager.set_none_cache(
                 self,
                 seqs,
                 modify_draft_cache,
                 load_preallocated_cache,
-            );
+            )?,
+            EitherCache::Recurrent(_) => {
+                RecurrentCacheManager.set_none_cache(self, seqs, modify_draft_cache, false)?
+            }
         }
         if reset_non_granular {
             self.reset_non_granular_state()
         }
+        Ok(())
     }
     fn cache(&self) -> &EitherCache {
         self.parallel_models[0].cache()
@@ -1178,7 +1349,10 @@ impl MetadataMixin for NormalPipeline {
     }
     fn reset_non_granular_state(&self) {
         if let Some(s) = self.non_granular_state.as_ref() {
-            *self.cache().full().get_scalings_cache() = None;
+            // Non granular state is only used by X-LoRA models, which have a full cache.
+            if let EitherCache::Full(full) = self.cache() {
+                *full.get_scalings_cache() = None;
+            }
             *get_mut_arcmutex!(s.non_granular_index) = 0;
         }
     }
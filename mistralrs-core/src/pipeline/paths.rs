@@ -1,10 +1,11 @@
 use std::{
     collections::HashMap,
     fs,
+    hash::{DefaultHasher, Hash, Hasher},
     path::{Path, PathBuf},
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use either::Either;
 use hf_hub::{
     api::sync::{ApiBuilder, ApiRepo},
@@ -20,6 +21,7 @@ use crate::{
     pipeline::{
         chat_template::{ChatTemplate, ChatTemplateValue},
         isq::UQFF_RESIDUAL_SAFETENSORS,
+        LocalModelPaths,
     },
     utils::tokens::get_token,
     xlora_models::XLoraConfig,
@@ -348,6 +350,77 @@ pub fn get_model_paths(
     }
 }
 
+/// Get the paths to load a self-contained UQFF file from. The embedded files are extracted to a
+/// temporary directory, and the UQFF file itself provides the residual weights.
+pub(crate) fn get_uqff_bundle_paths(
+    uqff: &Path,
+    tokenizer_json: &Option<String>,
+    chat_template: &Option<String>,
+) -> Result<Box<dyn ModelPaths>> {
+    let files = mistralrs_quant::read_uqff_files(uqff)?;
+
+    // Key the directory on the contents so that different bundles with the same name do not clash
+    let mut hasher = DefaultHasher::new();
+    files.hash(&mut hasher);
+    let stem = uqff
+        .file_stem()
+        .context("UQFF path must have a file name.")?
+        .to_string_lossy();
+    let dir = std::env::temp_dir().join(format!("mistralrs-uqff-{stem}-{:016x}", hasher.finish()));
+    fs::create_dir_all(&dir)?;
+
+    let mut extracted = HashMap::new();
+    for (name, data) in files {
+        let Some(file_name) = Path::new(&name).file_name() else {
+            continue;
+        };
+        let path = dir.join(file_name);
+        fs::write(&path, data)?;
+        extracted.insert(name, path);
+    }
+    info!(
+        "Extracted {} files embedded in `{}` to `{}`",
+        extracted.len(),
+        uqff.display(),
+        dir.display()
+    );
+
+    let tokenizer_filename = if let Some(p) = tokenizer_json {
+        info!("Using tokenizer.json at `{p}`");
+        PathBuf::from(p)
+    } else {
+        extracted
+            .remove("tokenizer.json")
+            .context("Self-contained UQFF file does not embed `tokenizer.json`.")?
+    };
+    let config_filename = extracted
+        .remove("config.json")
+        .context("Self-contained UQFF file does not embed `config.json`.")?;
+    let template_filename = if let Some(p) = chat_template {
+        info!("Using chat template file at `{p}`");
+        Some(PathBuf::from(p))
+    } else {
+        extracted.remove("tokenizer_config.json")
+    };
+
+    Ok(Box::new(LocalModelPaths {
+        tokenizer_filename,
+        config_filename,
+        template_filename,
+        filenames: vec![uqff.to_path_buf()],
+        xlora_adapter_filenames: None,
+        xlora_adapter_configs: None,
+        classifier_path: None,
+        classifier_config: None,
+        xlora_ordering: None,
+        gen_conf: extracted.remove("generation_config.json"),
+        lora_preload_adapter_info: None,
+        preprocessor_config: extracted.remove("preprocessor_config.json"),
+        processor_config: extracted.remove("processor_config.json"),
+        chat_template_json_filename: None,
+    }))
+}
+
 /// Find and parse the appropriate [`ChatTemplate`], and ensure is has a valid [`ChatTemplate.chat_template`].
 /// If the provided `tokenizer_config.json` from [`ModelPaths.get_template_filename`] does not
 /// have a `chat_template`, use the provided one.
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use anyhow::{Context, Result};
 use either::Either;
@@ -6,7 +6,7 @@ use indexmap::IndexMap;
 
 use crate::{
     vision_models::{preprocessor_config::PreProcessorConfig, processor_config::ProcessorConfig},
-    MessageContent, Pipeline, Tool,
+    ConversationTokens, MessageContent, Pipeline, Tool,
 };
 
 use super::{chat_template::apply_chat_template_to, text_models_inputs_processor, InputsProcessor};
@@ -31,20 +31,27 @@ pub enum MessagesAction {
 /// model.
 pub trait Processor {
     /// Get the tokens and the untokenized prompt. `add_special_tokens` should usually be true.
+    /// `chat_template_kwargs` are extra variables for the chat template, such as `enable_thinking`.
+    /// With `continue_final_message`, the prompt ends within the final assistant message.
+    #[allow(clippy::too_many_arguments)]
     fn process(
         &self,
         pipeline: &dyn Pipeline,
         messages: Vec<IndexMap<String, MessageContent>>,
         add_generation_prompt: bool,
+        continue_final_message: bool,
         add_special_tokens: bool,
         tools: Vec<Tool>,
+        chat_template_kwargs: Option<HashMap<String, serde_json::Value>>,
     ) -> Result<(Vec<u32>, String)> {
         let prompt = apply_chat_template(
             pipeline,
             messages,
             add_generation_prompt,
+            continue_final_message,
             self.template_action(),
             tools,
+            chat_template_kwargs,
         )?;
         let encoding = pipeline
             .tokenizer()
@@ -58,14 +65,24 @@ pub trait Processor {
     fn inputs_processor(&self) -> Arc<dyn InputsProcessor>;
     fn get_special_tokens(&self) -> &[&'static str];
     fn template_action(&self) -> MessagesAction;
+    /// Whether the inputs processor accepts videos in a [`crate::RequestMessage::VisionChat`].
+    fn supports_videos(&self) -> bool {
+        false
+    }
+    /// Whether the inputs processor accepts audio in a [`crate::RequestMessage::VisionChat`].
+    fn supports_audios(&self) -> bool {
+        false
+    }
 }
 
 pub(crate) fn apply_chat_template(
     pipeline: &dyn Pipeline,
     messages: Vec<IndexMap<String, MessageContent>>,
     add_generation_prompt: bool,
+    continue_final_message: bool,
     action: MessagesAction,
     tools: Vec<Tool>,
+    chat_template_kwargs: Option<HashMap<String, serde_json::Value>>,
 ) -> Result<String> {
     let messages = match action {
         MessagesAction::Keep => messages,
@@ -82,19 +99,20 @@ pub(crate) fn apply_chat_template(
                                 new_message.insert(k, Either::Left(lv));
                             }
                             Either::Right(rv) => {
-                                'outer: for content_row in rv {
+                                // Interleaved text parts are joined in order with newlines, as
+                                // the Hugging Face processors do. Any image placeholders have
+                                // already been added by the prefixer.
+                                let mut texts = Vec::new();
+                                for content_row in rv {
                                     for (content_k, content_v) in content_row {
                                         if content_k == "text" {
-                                            if let Some(content_str) = content_v.as_str() {
-                                                new_message.insert(
-                                                    k,
-                                                    Either::Left(content_str.to_string()),
-                                                );
-                                                break 'outer;
+                                            if let Some(text) = content_v.as_str() {
+                                                texts.push(text.to_string());
                                             }
                                         }
                                     }
                                 }
+                                new_message.insert(k, Either::Left(texts.join("\n")));
                             }
                         }
                     } else {
@@ -137,14 +155,72 @@ pub(crate) fn apply_chat_template(
     apply_chat_template_to(
         messages,
         add_generation_prompt,
+        continue_final_message,
         template,
         bos_tok,
         eos_tok,
         unk_tok,
         tools,
+        chat_template_kwargs,
     )
 }
 
+/// Tokenize a chat which continues a stored conversation. The rendering of the first
+/// `prefix_messages` messages, which ends just after the previous reply, is replaced by the
+/// `prefix_tokens` and only the rest of the prompt is tokenized. Returns `None` if the stored
+/// tokens cannot be used, for example if the chat template renders the earlier messages
+/// differently once there are new ones, so that the chat is tokenized as usual.
+pub(crate) fn process_continued_chat(
+    pipeline: &dyn Pipeline,
+    messages: &[IndexMap<String, MessageContent>],
+    conversation: &ConversationTokens,
+    add_generation_prompt: bool,
+    continue_final_message: bool,
+    tools: Vec<Tool>,
+    chat_template_kwargs: Option<HashMap<String, serde_json::Value>>,
+) -> Result<Option<(Vec<u32>, String)>> {
+    let ConversationTokens {
+        prefix_tokens,
+        prefix_messages,
+        ..
+    } = conversation;
+    if prefix_tokens.is_empty() || *prefix_messages == 0 || *prefix_messages > messages.len() {
+        return Ok(None);
+    }
+    // This fails if the previous messages do not end with the reply.
+    let Ok(previous) = apply_chat_template(
+        pipeline,
+        messages[..*prefix_messages].to_vec(),
+        false,
+        true,
+        pipeline.get_processor().template_action(),
+        tools.clone(),
+        chat_template_kwargs.clone(),
+    ) else {
+        return Ok(None);
+    };
+    let prompt = apply_chat_template(
+        pipeline,
+        messages.to_vec(),
+        add_generation_prompt,
+        continue_final_message,
+        pipeline.get_processor().template_action(),
+        tools,
+        chat_template_kwargs,
+    )?;
+    let Some(rest) = prompt.strip_prefix(&previous) else {
+        return Ok(None);
+    };
+    let encoding = pipeline
+        .tokenizer()
+        .with_context(|| "`process_continued_chat` requires the model to have a tokenizer.")?
+        .encode(rest, false)
+        .map_err(anyhow::Error::msg)?;
+    let mut tokens = prefix_tokens.clone();
+    tokens.extend_from_slice(encoding.get_ids());
+    Ok(Some((tokens, prompt)))
+}
+
 pub struct BasicProcessor;
 
 impl Processor for BasicProcessor {
@@ -6,8 +6,10 @@ use image::DynamicImage;
 use uuid::Uuid;
 
 use crate::{
+    embedding_models::EmbeddingOutput,
     sequence::{Sequence, SequenceState, StopReason},
-    ImageChoice, ImageGenerationResponse, ImageGenerationResponseFormat,
+    ImageChoice, ImageGenerationResponse, ImageGenerationResponseFormat, Response,
+    TranscriptionResponse,
 };
 
 pub async fn send_image_responses(
@@ -95,3 +97,58 @@ pub async fn send_raw_responses(
 
     Ok(())
 }
+
+pub async fn send_transcription_responses(
+    input_seqs: &mut [&mut Sequence],
+    transcriptions: Vec<TranscriptionResponse>,
+) -> candle_core::Result<()> {
+    if input_seqs.len() != transcriptions.len() {
+        candle_core::bail!(
+            "Input seqs len ({}) does not match transcriptions len ({})",
+            input_seqs.len(),
+            transcriptions.len()
+        );
+    }
+
+    for (seq, transcription) in input_seqs.iter_mut().zip(transcriptions) {
+        seq.responder()
+            .send(Response::Transcription(transcription))
+            .await
+            .map_err(candle_core::Error::msg)?;
+
+        seq.set_state(SequenceState::Done(StopReason::Transcribed));
+    }
+
+    Ok(())
+}
+
+pub async fn send_embedding_responses(
+    input_seqs: &mut [&mut Sequence],
+    responses: Vec<EmbeddingOutput>,
+) -> candle_core::Result<()> {
+    if input_seqs.len() != responses.len() {
+        candle_core::bail!(
+            "Input seqs len ({}) does not match embedding responses len ({})",
+            input_seqs.len(),
+            responses.len()
+        );
+    }
+
+    for (seq, response) in input_seqs.iter_mut().zip(responses) {
+        let response = match response {
+            EmbeddingOutput::Embedding(embedding) => Response::Embedding(embedding),
+            EmbeddingOutput::Rerank(rerank) => Response::Rerank(rerank),
+            EmbeddingOutput::Classification(classification) => {
+                Response::Classification(classification)
+            }
+        };
+        seq.responder()
+            .send(response)
+            .await
+            .map_err(candle_core::Error::msg)?;
+
+        seq.set_state(SequenceState::Done(StopReason::Embedded));
+    }
+
+    Ok(())
+}
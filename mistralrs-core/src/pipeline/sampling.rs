@@ -54,6 +54,9 @@ pub(crate) async fn finish_or_add_toks_to_seq(
                     if !tool_calls.is_empty() && is_done.is_none() {
                         is_done = Some(StopReason::Eos);
                     };
+                    let return_tokens = seq.get_mut_group().return_conversation_tokens;
+                    let conversation_tokens =
+                        (is_done.is_some() && return_tokens).then(|| seq.conversation_tokens());
                     seq.add_streaming_chunk_choice_to_group(crate::ChunkChoice {
                         delta: crate::Delta {
                             content: text_new.map(ToString::to_string),
@@ -72,6 +75,7 @@ pub(crate) async fn finish_or_add_toks_to_seq(
                         } else {
                             None
                         },
+                        conversation_tokens,
                     });
                 } else {
                     seq.add_streaming_completion_chunk_choice_to_group(
@@ -184,11 +188,19 @@ pub(crate) async fn finish_or_add_toks_to_seq(
                 crate::sequence::StopReason::GeneratedImage => {
                     candle_core::bail!("Stop reason was `GeneratedImage`.")
                 }
+                crate::sequence::StopReason::Transcribed => {
+                    candle_core::bail!("Stop reason was `Transcribed`.")
+                }
+                crate::sequence::StopReason::Embedded => {
+                    candle_core::bail!("Stop reason was `Embedded`.")
+                }
             };
 
             if seq.get_mut_group().is_chat {
                 let (text_new, tool_calls) = parse_text_tools(text.as_str(), seq.tools.clone())
                     .map_err(candle_core::Error::msg)?;
+                let return_tokens = seq.get_mut_group().return_conversation_tokens;
+                let conversation_tokens = return_tokens.then(|| seq.conversation_tokens());
                 let choice = crate::Choice {
                     finish_reason: reason.to_string(),
                     index: seq.get_response_index(),
@@ -198,6 +210,7 @@ pub(crate) async fn finish_or_add_toks_to_seq(
                         tool_calls,
                     },
                     logprobs: logprobs.map(|l| crate::Logprobs { content: Some(l) }),
+                    conversation_tokens,
                 };
                 seq.add_choice_to_group(choice);
             } else {
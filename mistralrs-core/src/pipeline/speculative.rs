@@ -214,6 +214,11 @@ impl SpeculativePipeline {
         {
             candle_core::bail!("Target and draft models' input processors do not match. This is required for speculative decoding.");
         }
+        if matches!(get_mut_arcmutex!(target).cache(), EitherCache::Recurrent(_))
+            || matches!(get_mut_arcmutex!(draft).cache(), EitherCache::Recurrent(_))
+        {
+            candle_core::bail!("Recurrent models cannot roll back their state to reject draft tokens. This is required for speculative decoding.");
+        }
         let metadata = get_mut_arcmutex!(target).get_metadata().clone();
         let category = get_mut_arcmutex!(target).category();
         // TODO: some checks or relaxation here?
@@ -244,13 +249,13 @@ impl IsqPipelineMixin for SpeculativePipeline {
 }
 
 impl CacheManagerMixin for SpeculativePipeline {
-    fn clone_in_cache(&self, seqs: &mut [&mut Sequence]) {
-        NormalCacheManager.clone_in_cache(&*get_mut_arcmutex!(self.draft), seqs, true);
-        NormalCacheManager.clone_in_cache(&*get_mut_arcmutex!(self.target), seqs, false);
+    fn clone_in_cache(&self, seqs: &mut [&mut Sequence]) -> Result<()> {
+        NormalCacheManager.clone_in_cache(&*get_mut_arcmutex!(self.draft), seqs, true)?;
+        NormalCacheManager.clone_in_cache(&*get_mut_arcmutex!(self.target), seqs, false)
     }
-    fn clone_out_cache(&self, seqs: &mut [&mut Sequence]) {
-        NormalCacheManager.clone_out_cache(&*get_mut_arcmutex!(self.draft), seqs, true);
-        NormalCacheManager.clone_out_cache(&*get_mut_arcmutex!(self.target), seqs, false);
+    fn clone_out_cache(&self, seqs: &mut [&mut Sequence]) -> Result<()> {
+        NormalCacheManager.clone_out_cache(&*get_mut_arcmutex!(self.draft), seqs, true)?;
+        NormalCacheManager.clone_out_cache(&*get_mut_arcmutex!(self.target), seqs, false)
     }
     fn set_none_cache(
         &self,
@@ -258,22 +263,23 @@ impl CacheManagerMixin for SpeculativePipeline {
         reset_non_granular: bool,
         modify_draft_cache: bool,
         load_preallocated_cache: bool,
-    ) {
+    ) -> Result<()> {
         NormalCacheManager.set_none_cache(
             &*get_mut_arcmutex!(self.draft),
             seqs,
             modify_draft_cache,
             load_preallocated_cache,
-        );
+        )?;
         NormalCacheManager.set_none_cache(
             &*get_mut_arcmutex!(self.target),
             seqs,
             false,
             load_preallocated_cache,
-        );
+        )?;
         if reset_non_granular {
             self.reset_non_granular_state()
         }
+        Ok(())
     }
     fn cache(&self) -> &EitherCache {
         unreachable!()
@@ -366,7 +372,7 @@ impl Pipeline for SpeculativePipeline {
                             }
                             AdapterInstruction::None => 0,
                         };
-                        self.clone_in_cache(input_seqs)
+                        self.clone_in_cache(input_seqs)?
                     }
                     CacheInstruction::Nothing(adapter_inst) => {
                         match adapter_inst {
@@ -404,7 +410,7 @@ impl Pipeline for SpeculativePipeline {
                             reset_non_granular,
                             true,
                             load_preallocated_cache,
-                        )
+                        )?
                     }
                     _ => unreachable!("Unreachable PRE cache op."),
                 }
@@ -488,6 +494,7 @@ impl Pipeline for SpeculativePipeline {
                         .map(|(k, _)| k.dims()[2])
                         .unwrap_or(0),
                     EitherCache::Normal(normal) => normal.lock().unwrap().0[0].current_seq_len(),
+                    EitherCache::Recurrent(_) => unreachable!(),
                 };
 
                 // ========= Run the model ============
@@ -562,6 +569,7 @@ impl Pipeline for SpeculativePipeline {
                             cache.set_len(cache.current_seq_len() - n_not_accepted);
                         }
                     }
+                    EitherCache::Recurrent(_) => unreachable!(),
                 }
                 if get_mut_arcmutex!(self.draft).get_metadata().is_xlora {
                     match get_mut_arcmutex!(self.draft).cache() {
@@ -571,7 +579,7 @@ impl Pipeline for SpeculativePipeline {
                                 *v = v.i((.., .., ..v.dims()[2] - n_not_accepted, ..))?;
                             }
                         }
-                        EitherCache::Normal(_) => {
+                        EitherCache::Normal(_) | EitherCache::Recurrent(_) => {
                             unreachable!()
                         }
                     }
@@ -588,6 +596,7 @@ impl Pipeline for SpeculativePipeline {
                             cache.set_len(cache.current_seq_len() - n_not_accepted);
                         }
                     }
+                    EitherCache::Recurrent(_) => unreachable!(),
                 }
                 if get_mut_arcmutex!(self.draft).get_metadata().is_xlora {
                     match get_mut_arcmutex!(self.target).cache() {
@@ -597,7 +606,7 @@ impl Pipeline for SpeculativePipeline {
                                 *v = v.i((.., .., ..v.dims()[2] - n_not_accepted, ..))?;
                             }
                         }
-                        EitherCache::Normal(_) => {
+                        EitherCache::Normal(_) | EitherCache::Recurrent(_) => {
                             unreachable!()
                         }
                     }
@@ -652,7 +661,7 @@ impl Pipeline for SpeculativePipeline {
 
                 match post_op {
                     CacheInstruction::Out => {
-                        self.clone_out_cache(input_seqs);
+                        self.clone_out_cache(input_seqs)?;
                     }
                     CacheInstruction::Nothing(_) => (),
                     CacheInstruction::Reset {
@@ -664,7 +673,7 @@ impl Pipeline for SpeculativePipeline {
                         reset_non_granular,
                         true,
                         load_preallocated_cache,
-                    ),
+                    )?,
                     _ => unreachable!("Unreachable pre cache op."),
                 }
 
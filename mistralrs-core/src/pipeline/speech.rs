@@ -0,0 +1,316 @@
+use super::loaders::{SpeechModelPaths, SpeechModelPathsInner};
+use super::{
+    AdapterActivationMixin, AnyMoePipelineMixin, Cache, CacheManagerMixin, EitherCache,
+    ForwardInputsResult, GeneralMetadata, IsqPipelineMixin, Loader, MetadataMixin, ModelCategory,
+    ModelKind, ModelPaths, PreProcessingMixin, Processor, SpeechLoaderType, SpeechModel,
+    SpeechModelLoader, TokenSource, WhisperLoader,
+};
+use crate::device_map::DeviceMapper;
+use crate::pipeline::ChatTemplate;
+use crate::prefix_cacher_v2::PrefixCacheManagerV2;
+use crate::sequence::Sequence;
+use crate::speech_models::processor::{ModelInputs, SpeechProcessor};
+use crate::utils::tokenizer::get_tokenizer;
+use crate::utils::varbuilder_utils::DeviceForLoadTensor;
+use crate::utils::{tokens::get_token, varbuilder_utils::from_mmaped_safetensors};
+use crate::{DeviceMapSetting, PagedAttentionConfig, Pipeline, TryIntoDType};
+use anyhow::Result;
+use candle_core::{Device, Tensor};
+use hf_hub::{api::sync::ApiBuilder, Repo, RepoType};
+use indicatif::MultiProgress;
+use mistralrs_quant::IsqType;
+use rand_isaac::Isaac64Rng;
+use std::any::Any;
+use std::sync::Arc;
+use tokenizers::Tokenizer;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+pub struct SpeechPipeline {
+    model: Box<dyn SpeechModel + Send + Sync>,
+    tokenizer: Arc<Tokenizer>,
+    model_id: String,
+    metadata: Arc<GeneralMetadata>,
+    dummy_cache: EitherCache,
+}
+
+/// A loader for a speech to text (non-quantized) model.
+pub struct SpeechLoader {
+    inner: Box<dyn SpeechModelLoader>,
+    model_id: String,
+    kind: ModelKind,
+}
+
+#[derive(Default)]
+/// A builder for a loader for a speech to text (non-quantized) model.
+pub struct SpeechLoaderBuilder {
+    model_id: Option<String>,
+    kind: ModelKind,
+}
+
+impl SpeechLoaderBuilder {
+    pub fn new(model_id: Option<String>) -> Self {
+        Self {
+            model_id,
+            kind: ModelKind::Normal,
+        }
+    }
+
+    pub fn build(self, loader: SpeechLoaderType) -> Box<dyn Loader> {
+        let loader: Box<dyn SpeechModelLoader> = match loader {
+            SpeechLoaderType::Whisper => Box::new(WhisperLoader),
+        };
+        Box::new(SpeechLoader {
+            inner: loader,
+            model_id: self.model_id.unwrap(),
+            kind: self.kind,
+        })
+    }
+}
+
+impl Loader for SpeechLoader {
+    #[allow(clippy::type_complexity, clippy::too_many_arguments)]
+    fn load_model_from_hf(
+        &self,
+        revision: Option<String>,
+        token_source: TokenSource,
+        dtype: &dyn TryIntoDType,
+        device: &Device,
+        silent: bool,
+        mapper: DeviceMapSetting,
+        in_situ_quant: Option<IsqType>,
+        paged_attn_config: Option<PagedAttentionConfig>,
+    ) -> Result<Arc<Mutex<dyn Pipeline + Send + Sync>>> {
+        let paths: anyhow::Result<Box<dyn ModelPaths>> = {
+            let api = ApiBuilder::new()
+                .with_progress(!silent)
+                .with_token(get_token(&token_source)?)
+                .build()?;
+            let revision = revision.unwrap_or("main".to_string());
+            let api = api.repo(Repo::with_revision(
+                self.model_id.clone(),
+                RepoType::Model,
+                revision.clone(),
+            ));
+            let model_id = std::path::Path::new(&self.model_id);
+            Ok(Box::new(SpeechModelPaths(
+                self.inner.get_model_paths(&api, model_id)?,
+            )))
+        };
+        self.load_model_from_path(
+            &paths?,
+            dtype,
+            device,
+            silent,
+            mapper,
+            in_situ_quant,
+            paged_attn_config,
+        )
+    }
+
+    #[allow(clippy::type_complexity, clippy::too_many_arguments)]
+    fn load_model_from_path(
+        &self,
+        paths: &Box<dyn ModelPaths>,
+        dtype: &dyn TryIntoDType,
+        device: &Device,
+        silent: bool,
+        mapper: DeviceMapSetting,
+        in_situ_quant: Option<IsqType>,
+        paged_attn_config: Option<PagedAttentionConfig>,
+    ) -> Result<Arc<Mutex<dyn Pipeline + Send + Sync>>> {
+        let SpeechModelPathsInner {
+            config_filename,
+            generation_config_filename,
+            tokenizer_filename,
+            filenames,
+        } = &paths
+            .as_ref()
+            .as_any()
+            .downcast_ref::<SpeechModelPaths>()
+            .expect("Path downcast failed.")
+            .0;
+
+        if matches!(mapper, DeviceMapSetting::Map(_)) {
+            anyhow::bail!("Device mapping is not supported for speech models.")
+        }
+
+        if in_situ_quant.is_some() {
+            anyhow::bail!("ISQ is not supported for speech models.");
+        }
+
+        if paged_attn_config.is_some() {
+            warn!("PagedAttention is not supported for speech models, disabling it.");
+        }
+
+        let config = std::fs::read_to_string(config_filename)?;
+        let generation_config = generation_config_filename
+            .as_ref()
+            .map(std::fs::read_to_string)
+            .transpose()?;
+        let tokenizer = Arc::new(get_tokenizer(tokenizer_filename, None)?);
+
+        let mapper = mapper.into_mapper(usize::MAX, device, None)?;
+        let dtype = mapper.get_min_dtype(dtype)?;
+
+        let model = match self.kind {
+            ModelKind::Normal => {
+                let vb = from_mmaped_safetensors(
+                    filenames.clone(),
+                    Vec::new(),
+                    Some(dtype),
+                    device,
+                    vec![None],
+                    silent,
+                    None,
+                    |_| true,
+                    Arc::new(|_| DeviceForLoadTensor::Base),
+                )?;
+
+                self.inner.load(
+                    &config,
+                    generation_config.as_deref(),
+                    tokenizer.clone(),
+                    vb,
+                    crate::pipeline::NormalLoadingMetadata {
+                        mapper,
+                        loading_isq: false,
+                        real_device: device.clone(),
+                        multi_progress: Arc::new(MultiProgress::new()),
+                    },
+                )?
+            }
+            _ => unreachable!(),
+        };
+
+        let max_seq_len = model.max_seq_len();
+        Ok(Arc::new(Mutex::new(SpeechPipeline {
+            model,
+            tokenizer,
+            model_id: self.model_id.clone(),
+            metadata: Arc::new(GeneralMetadata {
+                max_seq_len,
+                tok_env: None,
+                is_xlora: false,
+                no_prefix_cache: true,
+                num_hidden_layers: 1, // Only used for caching, which speech models manage themselves.
+                eos_tok: vec![],
+                kind: self.kind.clone(),
+                no_kv_cache: true, // NOTE: the decoder keeps its own KV cache for each window.
+                activation_dtype: dtype,
+                sliding_window: None,
+                cache_config: None,
+                cache_engines: None,
+                prompt_chunksize: None,
+                model_metadata: None,
+            }),
+            dummy_cache: EitherCache::Full(Cache::new(0, false)),
+        })))
+    }
+
+    fn get_id(&self) -> String {
+        self.model_id.to_string()
+    }
+
+    fn get_kind(&self) -> ModelKind {
+        self.kind.clone()
+    }
+}
+
+impl PreProcessingMixin for SpeechPipeline {
+    fn get_processor(&self) -> Arc<dyn Processor> {
+        Arc::new(SpeechProcessor)
+    }
+    fn get_chat_template(&self) -> Option<Arc<ChatTemplate>> {
+        None
+    }
+    fn get_input_processor_config(&self) -> Option<Arc<dyn Any>> {
+        None
+    }
+}
+
+impl IsqPipelineMixin for SpeechPipeline {
+    fn re_isq_model(&mut self, _dtype: IsqType) -> Result<()> {
+        anyhow::bail!("Speech models do not support ISQ for now.")
+    }
+}
+
+impl CacheManagerMixin for SpeechPipeline {
+    fn clone_in_cache(&self, _seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
+        Ok(())
+    }
+    fn clone_out_cache(&self, _seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
+        Ok(())
+    }
+    fn set_none_cache(
+        &self,
+        _seqs: &mut [&mut Sequence],
+        _reset_non_granular: bool,
+        _modify_draft_cache: bool,
+        _load_preallocated_cache: bool,
+    ) -> candle_core::Result<()> {
+        Ok(())
+    }
+    fn cache(&self) -> &EitherCache {
+        &self.dummy_cache
+    }
+}
+
+impl AdapterActivationMixin for SpeechPipeline {
+    fn activate_adapters(&mut self, _adapters: Vec<String>) -> Result<usize> {
+        anyhow::bail!("Speech models do not support adapter activation.");
+    }
+}
+
+impl MetadataMixin for SpeechPipeline {
+    fn device(&self) -> Device {
+        self.model.device().clone()
+    }
+    fn get_metadata(&self) -> Arc<GeneralMetadata> {
+        self.metadata.clone()
+    }
+    fn name(&self) -> String {
+        self.model_id.clone()
+    }
+    fn reset_non_granular_state(&self) {}
+    fn tokenizer(&self) -> Option<Arc<Tokenizer>> {
+        Some(self.tokenizer.clone())
+    }
+    fn device_mapper(&self) -> Option<&dyn DeviceMapper> {
+        None
+    }
+}
+
+#[async_trait::async_trait]
+impl Pipeline for SpeechPipeline {
+    fn forward_inputs(
+        &mut self,
+        inputs: Box<dyn Any>,
+        return_raw_logits: bool,
+    ) -> candle_core::Result<ForwardInputsResult> {
+        assert!(!return_raw_logits);
+
+        let ModelInputs { audios, params } = *inputs.downcast().expect("Downcast failed.");
+        let transcriptions = audios
+            .iter()
+            .zip(&params)
+            .map(|(audio, params)| self.model.transcribe(audio, params))
+            .collect::<candle_core::Result<Vec<_>>>()?;
+        Ok(ForwardInputsResult::Transcription { transcriptions })
+    }
+    async fn sample_causal_gen(
+        &self,
+        _seqs: &mut [&mut Sequence],
+        _logits: Vec<Tensor>,
+        _prefix_cacher: &mut PrefixCacheManagerV2,
+        _disable_eos_stop: bool,
+        _srng: Arc<std::sync::Mutex<Isaac64Rng>>,
+    ) -> Result<(), candle_core::Error> {
+        candle_core::bail!("`sample_causal_gen` is incompatible with `SpeechPipeline`");
+    }
+    fn category(&self) -> ModelCategory {
+        ModelCategory::Speech
+    }
+}
+
+impl AnyMoePipelineMixin for SpeechPipeline {}
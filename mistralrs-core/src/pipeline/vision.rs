@@ -1,12 +1,12 @@
 use super::cache_manager::{FullCacheManager, NormalCacheManager};
-use super::isq::ImatrixDataSource;
 use super::isq::UqffFullSer;
+use super::isq::{check_calibration_requirements, ImatrixDataSource};
 use super::{
-    get_model_paths, get_xlora_paths, AdapterActivationMixin, AnyMoePipelineMixin, CacheManager,
-    CacheManagerMixin, EitherCache, ForwardInputsResult, GeneralMetadata, IsqPipelineMixin, Loader,
-    MetadataMixin, MiniCpmOLoader, ModelCategory, ModelKind, ModelPaths, PreProcessingMixin,
-    Processor, Qwen2VLLoader, TokenSource, VLlamaLoader, VisionModel, VisionModelLoader,
-    VisionPromptPrefixer, XLoraPaths,
+    get_model_paths, get_uqff_bundle_paths, get_xlora_paths, AdapterActivationMixin,
+    AnyMoePipelineMixin, CacheManager, CacheManagerMixin, EitherCache, ForwardInputsResult,
+    GeneralMetadata, IsqPipelineMixin, KvCacheType, Loader, MetadataMixin, MiniCpmOLoader,
+    ModelCategory, ModelKind, ModelPaths, PreProcessingMixin, Processor, Qwen2VLLoader,
+    TokenSource, VLlamaLoader, VisionModel, VisionModelLoader, VisionPromptPrefixer, XLoraPaths,
 };
 use super::{
     Idefics2Loader, Idefics3Loader, LLaVALoader, LLaVANextLoader, Phi3VLoader, VisionLoaderType,
@@ -35,7 +35,7 @@ use anyhow::Result;
 use candle_core::{Device, Tensor, Var};
 use hf_hub::{api::sync::ApiBuilder, Repo, RepoType};
 use indicatif::MultiProgress;
-use mistralrs_quant::{GgufMatMul, HqqLayer, IsqType, QuantizedSerdeType};
+use mistralrs_quant::{AwqLayer, GgufMatMul, HqqLayer, IsqType, QuantizedSerdeType};
 use rand_isaac::Isaac64Rng;
 use regex_automata::meta::Regex;
 use std::any::Any;
@@ -51,25 +51,25 @@ use tokio::sync::Mutex;
 use tracing::{info, warn};
 
 pub struct VisionPipeline {
-    model: Box<dyn VisionModel + Send + Sync>,
-    tokenizer: Arc<Tokenizer>,
-    chat_template: Arc<ChatTemplate>,
-    model_id: String,
-    metadata: Arc<GeneralMetadata>,
-    processor: Arc<dyn Processor + Send + Sync>,
-    preprocessor_config: Arc<PreProcessorConfig>,
-    topology: Option<Topology>,
-    silent: bool,
-    prefixer: Arc<dyn VisionPromptPrefixer>,
-    mapper: Box<dyn DeviceMapper + Send + Sync>,
+    pub(super) model: Box<dyn VisionModel + Send + Sync>,
+    pub(super) tokenizer: Arc<Tokenizer>,
+    pub(super) chat_template: Arc<ChatTemplate>,
+    pub(super) model_id: String,
+    pub(super) metadata: Arc<GeneralMetadata>,
+    pub(super) processor: Arc<dyn Processor + Send + Sync>,
+    pub(super) preprocessor_config: Arc<PreProcessorConfig>,
+    pub(super) topology: Option<Topology>,
+    pub(super) silent: bool,
+    pub(super) prefixer: Arc<dyn VisionPromptPrefixer>,
+    pub(super) mapper: Box<dyn DeviceMapper + Send + Sync>,
 
     // For full UQFF serialization
-    template_filename: Option<PathBuf>,
-    generation_config: Option<PathBuf>,
-    config: String,
-    processor_filename: Option<PathBuf>,
-    preprocessor_filename: Option<PathBuf>,
-    imatrix: Option<PathBuf>,
+    pub(super) template_filename: Option<PathBuf>,
+    pub(super) generation_config: Option<PathBuf>,
+    pub(super) config: String,
+    pub(super) processor_filename: Option<PathBuf>,
+    pub(super) preprocessor_filename: Option<PathBuf>,
+    pub(super) imatrix: Option<PathBuf>,
 }
 
 /// A loader for a vision (non-quantized) model.
@@ -78,6 +78,8 @@ pub struct VisionLoader {
     model_id: String,
     config: VisionSpecificConfig,
     kind: ModelKind,
+    kv_cache_type: KvCacheType,
+    self_contained_uqff: bool,
     chat_template: Option<String>,
     tokenizer_json: Option<String>,
     xlora_model_id: Option<String>,
@@ -93,6 +95,8 @@ pub struct VisionLoaderBuilder {
     model_id: Option<String>,
     config: VisionSpecificConfig,
     kind: ModelKind,
+    kv_cache_type: KvCacheType,
+    self_contained_uqff: bool,
     chat_template: Option<String>,
     tokenizer_json: Option<String>,
 }
@@ -123,9 +127,23 @@ impl VisionLoaderBuilder {
             tokenizer_json,
             model_id,
             kind: ModelKind::Normal,
+            kv_cache_type: KvCacheType::Auto,
+            self_contained_uqff: false,
         }
     }
 
+    pub fn with_kv_cache_type(mut self, kv_cache_type: KvCacheType) -> Self {
+        self.kv_cache_type = kv_cache_type;
+        self
+    }
+
+    /// Embed the residual tensors, config and tokenizer in the UQFF file written with `write_uqff`,
+    /// so that it can be loaded from a single path.
+    pub fn with_self_contained_uqff(mut self, self_contained_uqff: bool) -> Self {
+        self.self_contained_uqff = self_contained_uqff;
+        self
+    }
+
     pub fn build(self, loader: VisionLoaderType) -> Box<dyn Loader> {
         let loader: Box<dyn VisionModelLoader> = match loader {
             VisionLoaderType::Phi3V => Box::new(Phi3VLoader),
@@ -142,6 +160,8 @@ impl VisionLoaderBuilder {
             model_id: self.model_id.unwrap(),
             config: self.config,
             kind: self.kind,
+            kv_cache_type: self.kv_cache_type,
+            self_contained_uqff: self.self_contained_uqff,
             chat_template: self.chat_template,
             tokenizer_json: self.tokenizer_json,
             xlora_model_id: None,
@@ -166,6 +186,32 @@ impl Loader for VisionLoader {
         in_situ_quant: Option<IsqType>,
         paged_attn_config: Option<PagedAttentionConfig>,
     ) -> Result<Arc<Mutex<dyn Pipeline + Send + Sync>>> {
+        if let Some(from_uqff) = self
+            .config
+            .from_uqff
+            .clone()
+            .filter(|p| p.exists() && mistralrs_quant::is_self_contained_uqff(p))
+        {
+            info!("Loading self-contained UQFF file `{}`", from_uqff.display());
+            let paths =
+                get_uqff_bundle_paths(&from_uqff, &self.tokenizer_json, &self.chat_template)?;
+            *self.from_uqff.write().unwrap() = Some(from_uqff);
+            *self
+                .token_source
+                .write()
+                .expect("Failed to write to token source") = Some(token_source);
+            *self.revision.write().expect("Failed to write to revision") = revision;
+            return self.load_model_from_path(
+                &paths,
+                dtype,
+                device,
+                silent,
+                mapper,
+                in_situ_quant,
+                paged_attn_config,
+            );
+        }
+
         let paths: anyhow::Result<Box<dyn ModelPaths>> = get_paths!(
             LocalModelPaths,
             &token_source,
@@ -227,8 +273,14 @@ impl Loader for VisionLoader {
                             candle_core::safetensors::MmapedSafetensors::new(serialized)?
                         };
                         let mut total_pack_factors = 0;
-                        let total_tensors = ser_artifacts.tensors().len();
-                        for (_, artifact) in ser_artifacts.tensors() {
+                        // Self-contained UQFF files also hold the residual tensors
+                        let layers = ser_artifacts
+                            .tensors()
+                            .into_iter()
+                            .filter(|(name, _)| name.parse::<usize>().is_ok())
+                            .collect::<Vec<_>>();
+                        let total_tensors = layers.len();
+                        for (_, artifact) in layers {
                             let artifact = artifact.data();
                             // NOTE(EricLBuehler): isq type is ALWAYS byte 4 (5th) of the tensor.
                             let isq_type = artifact[mistralrs_quant::UQFF_QUANT_TYPE_OFFSET];
@@ -244,6 +296,7 @@ impl Loader for VisionLoader {
                                 }
                                 QuantizedSerdeType::Fp8 => IsqType::F8E4M3.pack_factor(dtype),
                                 QuantizedSerdeType::Unquant => 1,
+                                QuantizedSerdeType::Awq => AwqLayer::pack_factor(dtype),
                             };
                             total_pack_factors += pack_factor;
                         }
@@ -331,6 +384,9 @@ impl Loader for VisionLoader {
             paged_attn_config = None;
         }
 
+        // Quantized KV caches are only implemented in the PagedAttention kernels.
+        self.kv_cache_type.validate(paged_attn_config.is_some())?;
+
         info!(
             "Model config: {:?}",
             self.inner
@@ -350,6 +406,12 @@ impl Loader for VisionLoader {
                 "`imatrix` and `calibration_file` were both specified, this is not allowed."
             );
         }
+        check_calibration_requirements(
+            in_situ_quant,
+            self.config.topology.as_ref(),
+            self.config.imatrix.is_some(),
+            self.config.calibration_file.is_some(),
+        )?;
 
         // Load onto the regular device if not using isq or if the calibration file is specified
         let load_device = if !loading_isq || self.config.calibration_file.is_some() {
@@ -445,48 +507,83 @@ impl Loader for VisionLoader {
                 .token_to_id(&bos_toks[0])
                 .expect("Somehow the bos token is not present.");
 
-            // NOTE: We ONLY calibrate the text bits of these models!!
-            // So only those should be tracked!
-            model.begin_track_stats()?;
-
             const CHUNK_SIZE: usize = 1024;
             let n_chunks: usize = tokens.len().div_ceil(CHUNK_SIZE);
+            // GPTQ needs the full Hessian of the inputs of each of its layers. To only keep the
+            // Hessians of one decoder layer in memory, the calibration data is run once for each
+            // decoder layer quantized with GPTQ, which is quantized at the end of its pass.
+            let gptq_layers = model.gptq_layers(in_situ_quant, self.config.topology.as_ref());
+            let passes = if gptq_layers.is_empty() {
+                vec![None]
+            } else {
+                gptq_layers.into_iter().map(Some).collect::<Vec<_>>()
+            };
+            let n_passes = passes.len();
             let start = Instant::now();
-            for (i, chunk) in tokens.chunks(CHUNK_SIZE).enumerate() {
-                let chunk = [vec![bos_tok_id], chunk.to_vec()].concat();
-                let chunk_len = chunk.len();
-
-                let start = Instant::now();
-                let inputs =
-                    make_prompt_chunk(0, vec![chunk], &[0], &load_device, None, false, None, None)?;
-                let _ = model.forward(
-                    &inputs.input,
-                    None, // NOTE: We ONLY calibrate the text bits of these models!!
-                    &inputs.positions,
-                    inputs.context_lens,
-                    inputs.position_ids,
-                    model.default_model_specific_args(&inputs.input),
-                    None,
-                    &inputs.flash_meta,
-                )?;
-                match model.cache_mut() {
-                    EitherCache::Full(full) => {
-                        for layer in &mut *full.lock() {
-                            *layer = None
+            for (pass, hessian_layer) in passes.into_iter().enumerate() {
+                if let Some(layer_num) = hessian_layer {
+                    info!(
+                        "Calibration pass {}/{n_passes}: GPTQ Hessians of {}.",
+                        pass + 1,
+                        layer_num.map_or(
+                            "the layers outside of the decoder layers".to_string(),
+                            |layer| format!("layer {layer}")
+                        )
+                    );
+                }
+                let track_hessian = |layer_num| hessian_layer == Some(layer_num);
+                // NOTE: We ONLY calibrate the text bits of these models!!
+                // So only those should be tracked!
+                model.begin_track_stats(&track_hessian)?;
+
+                for (i, chunk) in tokens.chunks(CHUNK_SIZE).enumerate() {
+                    let chunk = [vec![bos_tok_id], chunk.to_vec()].concat();
+                    let chunk_len = chunk.len();
+
+                    let start = Instant::now();
+                    let inputs = make_prompt_chunk(
+                        0,
+                        vec![chunk],
+                        &[0],
+                        &load_device,
+                        None,
+                        false,
+                        None,
+                        None,
+                    )?;
+                    let _ = model.forward(
+                        &inputs.input,
+                        None, // NOTE: We ONLY calibrate the text bits of these models!!
+                        &inputs.positions,
+                        inputs.context_lens,
+                        inputs.position_ids,
+                        model.default_model_specific_args(&inputs.input),
+                        None,
+                        &inputs.flash_meta,
+                    )?;
+                    match model.cache_mut() {
+                        EitherCache::Full(full) => {
+                            for layer in &mut *full.lock() {
+                                *layer = None
+                            }
                         }
-                    }
-                    EitherCache::Normal(normal) => {
-                        for layer in &mut *normal.lock().unwrap().0 {
-                            layer.set_len(0);
+                        EitherCache::Normal(normal) => {
+                            for layer in &mut *normal.lock().unwrap().0 {
+                                layer.set_len(0);
+                            }
                         }
+                        EitherCache::Recurrent(recurrent) => recurrent.lock().unwrap().reset(),
                     }
+                    let end = Instant::now();
+                    info!(
+                        "Processed chunk {}/{n_chunks} ({chunk_len} tokens), {:.2}s",
+                        i + 1,
+                        end.duration_since(start).as_secs_f32()
+                    );
+                }
+                if hessian_layer.is_some() {
+                    model.quantize_tracked_hessians()?;
                 }
-                let end = Instant::now();
-                info!(
-                    "Processed chunk {}/{n_chunks} ({chunk_len} tokens), {:.2}s",
-                    i + 1,
-                    end.duration_since(start).as_secs_f32()
-                );
             }
             load_device.synchronize()?;
             let end = Instant::now();
@@ -523,6 +620,7 @@ impl Loader for VisionLoader {
                     config: config.clone(),
                     processor_filename: paths.get_processor_config(),
                     preprocessor_filename: paths.get_preprocessor_config(),
+                    self_contained: self.self_contained_uqff,
                 },
                 Arc::new(MultiProgress::new()),
             )?;
@@ -545,6 +643,7 @@ impl Loader for VisionLoader {
                 paged_attn_config.mem_cpu,
                 paged_attn_config.block_size,
                 dtype,
+                self.kv_cache_type,
                 model.config(),
                 device,
                 &layer_devices,
@@ -562,6 +661,7 @@ impl Loader for VisionLoader {
         let num_hidden_layers = match model.cache() {
             EitherCache::Full(full) => full.lock().len(),
             EitherCache::Normal(normal) => normal.lock().unwrap().0.len(),
+            EitherCache::Recurrent(recurrent) => recurrent.lock().unwrap().0.len(),
         };
         let eos = calculate_eos_tokens(&chat_template, gen_conf, &tokenizer);
         let sliding_window = model.config().sliding_window;
@@ -642,6 +742,7 @@ impl IsqPipelineMixin for VisionPipeline {
                     config: self.config.clone(),
                     processor_filename: &self.processor_filename,
                     preprocessor_filename: &self.preprocessor_filename,
+                    self_contained: false,
                 },
                 Arc::new(MultiProgress::new()),
             )
@@ -650,14 +751,14 @@ impl IsqPipelineMixin for VisionPipeline {
 }
 
 impl CacheManagerMixin for VisionPipeline {
-    fn clone_in_cache(&self, seqs: &mut [&mut Sequence]) {
+    fn clone_in_cache(&self, seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
         if matches!(self.model.cache(), EitherCache::Full(_)) {
             FullCacheManager.clone_in_cache(self, seqs, false)
         } else {
             NormalCacheManager.clone_in_cache(self, seqs, false)
         }
     }
-    fn clone_out_cache(&self, seqs: &mut [&mut Sequence]) {
+    fn clone_out_cache(&self, seqs: &mut [&mut Sequence]) -> candle_core::Result<()> {
         if matches!(self.model.cache(), EitherCache::Full(_)) {
             FullCacheManager.clone_out_cache(self, seqs, false)
         } else {
@@ -671,20 +772,21 @@ impl CacheManagerMixin for VisionPipeline {
         modify_draft_cache: bool,
 
         load_preallocated_cache: bool,
-    ) {
+    ) -> candle_core::Result<()> {
         if matches!(self.model.cache(), EitherCache::Full(_)) {
-            FullCacheManager.set_none_cache(self, seqs, modify_draft_cache, false);
+            FullCacheManager.set_none_cache(self, seqs, modify_draft_cache, false)?;
         } else {
             NormalCacheManager.set_none_cache(
                 self,
                 seqs,
                 modify_draft_cache,
                 load_preallocated_cache,
-            );
+            )?;
         }
         if reset_non_granular {
             self.reset_non_granular_state()
         }
+        Ok(())
     }
     fn cache(&self) -> &EitherCache {
         self.model.cache()
@@ -1,3 +1,6 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use anyhow::Context;
 use either::Either;
 use indexmap::IndexMap;
 use mistralrs_quant::IsqType;
@@ -8,9 +11,10 @@ use crate::{
     response::Response,
     sampler::SamplingParams,
     tools::{Tool, ToolChoice},
-    CustomLogitsProcessor, DiffusionGenerationParams,
+    CustomLogitsProcessor, DiffusionGenerationParams, EmbeddingParams, RerankParams,
+    TranscriptionParams, VisionPromptPrefixer,
 };
-use std::{fmt::Debug, sync::Arc};
+use std::{borrow::Borrow, collections::HashMap, fmt::Debug, sync::Arc};
 use tokio::sync::mpsc::Sender;
 
 pub type LlguidanceGrammar = llguidance::api::TopLevelGrammar;
@@ -47,6 +51,8 @@ pub enum RequestMessage {
     CompletionTokens(Vec<u32>),
     VisionChat {
         images: Vec<image::DynamicImage>,
+        videos: Vec<VideoInput>,
+        audios: Vec<AudioInput>,
         messages: Vec<IndexMap<String, MessageContent>>,
     },
     ImageGeneration {
@@ -54,6 +60,448 @@ pub enum RequestMessage {
         format: ImageGenerationResponseFormat,
         generation_params: DiffusionGenerationParams,
     },
+    Transcription {
+        audio: AudioInput,
+        params: TranscriptionParams,
+    },
+    Embedding {
+        inputs: Vec<String>,
+        params: EmbeddingParams,
+    },
+    Rerank {
+        query: String,
+        documents: Vec<String>,
+        params: RerankParams,
+    },
+    Classification {
+        inputs: Vec<ClassificationInput>,
+    },
+}
+
+#[derive(Clone, Debug)]
+/// One input of a [`RequestMessage::Classification`]. Conversations are formatted with the chat
+/// template of the model, as reward models score whole exchanges.
+pub enum ClassificationInput {
+    Text(String),
+    Conversation(Vec<IndexMap<String, MessageContent>>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// How frames are sampled from a [`VideoInput`] before they are passed to the model.
+/// - `fps`: Sample frames uniformly in time at this rate.
+/// - `num_frames`: Sample exactly this many frames. Takes precedence over `fps`.
+/// - `max_frames`: Upper bound on the number of sampled frames.
+pub struct VideoSampling {
+    pub fps: Option<f64>,
+    pub num_frames: Option<usize>,
+    pub max_frames: Option<usize>,
+}
+
+impl Default for VideoSampling {
+    fn default() -> Self {
+        Self {
+            fps: Some(2.0),
+            num_frames: None,
+            max_frames: Some(768),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A video for a [`RequestMessage::VisionChat`], as its decoded frames and their frame rate.
+pub struct VideoInput {
+    pub frames: Vec<image::DynamicImage>,
+    /// Frame rate of `frames`, in frames per second.
+    pub fps: f64,
+    pub sampling: VideoSampling,
+}
+
+impl VideoInput {
+    /// Fallback frame rate for animations which do not specify frame delays.
+    const DEFAULT_ANIMATION_FPS: f64 = 10.0;
+
+    pub fn from_frames(frames: Vec<image::DynamicImage>, fps: f64) -> Self {
+        Self {
+            frames,
+            fps,
+            sampling: VideoSampling::default(),
+        }
+    }
+
+    /// Decode an animated GIF, PNG (APNG) or WebP clip. The frame rate is derived from the frame
+    /// delays.
+    pub fn from_animation_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        use image::{
+            codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+            AnimationDecoder, ImageFormat,
+        };
+
+        let cursor = std::io::Cursor::new(bytes);
+        let frames = match image::guess_format(bytes)? {
+            ImageFormat::Gif => GifDecoder::new(cursor)?.into_frames().collect_frames()?,
+            ImageFormat::Png => PngDecoder::new(cursor)?
+                .apng()?
+                .into_frames()
+                .collect_frames()?,
+            ImageFormat::WebP => WebPDecoder::new(cursor)?.into_frames().collect_frames()?,
+            other => anyhow::bail!(
+                "Unsupported video format {other:?}, expected an animated GIF, PNG or WebP."
+            ),
+        };
+        if frames.is_empty() {
+            anyhow::bail!("Video contains no frames.");
+        }
+
+        let total_ms = frames
+            .iter()
+            .map(|frame| {
+                let (numer, denom) = frame.delay().numer_denom_ms();
+                numer as f64 / denom.max(1) as f64
+            })
+            .sum::<f64>();
+        let fps = if total_ms > 0. {
+            frames.len() as f64 / (total_ms / 1000.)
+        } else {
+            Self::DEFAULT_ANIMATION_FPS
+        };
+
+        let frames = frames
+            .into_iter()
+            .map(|frame| image::DynamicImage::ImageRgba8(frame.into_buffer()))
+            .collect();
+        Ok(Self::from_frames(frames, fps))
+    }
+
+    pub fn with_sampling(mut self, sampling: VideoSampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// Sample frames uniformly in time according to `sampling`. At least one frame is returned
+    /// for a non-empty video.
+    pub fn sample_frames(&self) -> Vec<image::DynamicImage> {
+        let total = self.frames.len();
+        if total == 0 {
+            return vec![];
+        }
+        let mut n = match (self.sampling.num_frames, self.sampling.fps) {
+            (Some(n), _) => n,
+            (None, Some(fps)) if self.fps > 0. => (total as f64 / self.fps * fps).round() as usize,
+            (None, _) => total,
+        };
+        if let Some(max_frames) = self.sampling.max_frames {
+            n = n.min(max_frames);
+        }
+        let n = n.clamp(1, total);
+        if n == 1 {
+            return vec![self.frames[0].clone()];
+        }
+
+        (0..n)
+            .map(|i| {
+                let idx = (i as f64 * (total - 1) as f64 / (n - 1) as f64).round() as usize;
+                self.frames[idx].clone()
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// An audio clip for a [`RequestMessage::VisionChat`], as mono samples in `[-1, 1]` and their
+/// sample rate.
+pub struct AudioInput {
+    pub samples: Vec<f32>,
+    /// Sample rate of `samples`, in Hz.
+    pub sample_rate: u32,
+}
+
+impl AudioInput {
+    pub fn from_samples(samples: Vec<f32>, sample_rate: u32) -> Self {
+        Self {
+            samples,
+            sample_rate,
+        }
+    }
+
+    /// Decode a RIFF WAV file. Multi-channel audio is downmixed to mono.
+    pub fn from_wav_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let (samples, sample_rate) = crate::audio::decode_wav(bytes)?;
+        Ok(Self::from_samples(samples, sample_rate))
+    }
+
+    /// Decode raw interleaved little-endian signed 16-bit PCM. Multi-channel audio is downmixed
+    /// to mono.
+    pub fn from_pcm16_bytes(
+        bytes: &[u8],
+        sample_rate: u32,
+        channels: usize,
+    ) -> anyhow::Result<Self> {
+        let samples = crate::audio::decode_pcm16(bytes, channels)?;
+        Ok(Self::from_samples(samples, sample_rate))
+    }
+
+    /// Resample to `sample_rate`, which is a no-op if the rates already match.
+    pub fn resample(&self, sample_rate: u32) -> Self {
+        Self::from_samples(
+            crate::audio::resample(&self.samples, self.sample_rate, sample_rate),
+            sample_rate,
+        )
+    }
+
+    /// Duration of the clip, in seconds.
+    pub fn duration_secs(&self) -> f64 {
+        self.samples.len() as f64 / self.sample_rate as f64
+    }
+}
+
+/// Sample rate of `pcm16` audio content parts which do not specify one.
+pub const DEFAULT_PCM16_SAMPLE_RATE: u32 = 16000;
+
+#[derive(Clone, Debug, PartialEq)]
+/// One part of the content of a chat message, as sent to the OpenAI compatible APIs. Media are not
+/// loaded yet.
+pub enum ContentPart {
+    Text(String),
+    ImageUrl(String),
+    VideoUrl(VideoPart),
+    Audio(AudioPart),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A video content part: an animated clip or a list of frame URLs.
+pub struct VideoPart {
+    pub source: Either<String, Vec<String>>,
+    /// Frame rate of the source, overriding the one of a clip.
+    pub frame_rate: Option<f64>,
+    pub sampling: VideoSampling,
+}
+
+impl VideoPart {
+    /// Build the video from its loaded clip or frames. Without a frame rate, the frames are
+    /// taken as already sampled.
+    pub fn into_video(self, loaded: Either<VideoInput, Vec<image::DynamicImage>>) -> VideoInput {
+        let mut video = match loaded {
+            Either::Left(video) => video,
+            Either::Right(frames) => {
+                VideoInput::from_frames(frames, self.frame_rate.or(self.sampling.fps).unwrap_or(1.))
+            }
+        };
+        if let Some(frame_rate) = self.frame_rate {
+            video.fps = frame_rate;
+        }
+        video.with_sampling(self.sampling)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// An audio content part: a WAV file, or raw 16-bit mono PCM if `pcm16_sample_rate` is set.
+pub struct AudioPart {
+    /// A URL, file path or base64 data.
+    pub source: String,
+    pub pcm16_sample_rate: Option<u32>,
+}
+
+/// Parse `{`url`: ...}` or `{`frames`: [...]}`, with optional `frame_rate`, `fps`, `num_frames`
+/// and `max_frames`.
+fn parse_video_part(video_url: &HashMap<String, Value>) -> anyhow::Result<VideoPart> {
+    let source = match (video_url.get("url"), video_url.get("frames")) {
+        (Some(Value::String(url)), None) => Either::Left(url.clone()),
+        (None, Some(Value::Array(frames))) => Either::Right(
+            frames
+                .iter()
+                .map(|frame| {
+                    frame
+                        .as_str()
+                        .map(ToString::to_string)
+                        .context("Expected string URLs in `frames`.")
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        ),
+        _ => {
+            anyhow::bail!("Expected either a string `url` or an array of `frames` in `video_url`.")
+        }
+    };
+    let number = |key: &str| -> anyhow::Result<Option<f64>> {
+        video_url
+            .get(key)
+            .map(|value| {
+                value
+                    .as_f64()
+                    .with_context(|| format!("Expected a number in `{key}`."))
+            })
+            .transpose()
+    };
+    let defaults = VideoSampling::default();
+    Ok(VideoPart {
+        source,
+        frame_rate: number("frame_rate")?,
+        sampling: VideoSampling {
+            fps: number("fps")?.or(defaults.fps),
+            num_frames: number("num_frames")?.map(|n| n as usize),
+            max_frames: number("max_frames")?
+                .map(|n| n as usize)
+                .or(defaults.max_frames),
+        },
+    })
+}
+
+/// Parse `{`data`: ...}` (for `input_audio`) or `{`url`: ...}` (for `audio_url`), with an
+/// optional `format` of `wav` (the default) or `pcm16` and a `sample_rate` for `pcm16` audio.
+fn parse_audio_part(audio: &HashMap<String, Value>, source_key: &str) -> anyhow::Result<AudioPart> {
+    let Some(Value::String(source)) = audio.get(source_key) else {
+        anyhow::bail!("Expected a string `{source_key}` in the audio content part.");
+    };
+    let pcm16_sample_rate = match audio.get("format").map(|format| format.as_str()) {
+        None | Some(Some("wav")) => None,
+        Some(Some("pcm16")) => match audio.get("sample_rate") {
+            Some(rate) => Some(
+                rate.as_f64()
+                    .filter(|rate| *rate > 0. && rate.fract() == 0.)
+                    .context("Expected a positive integer in `sample_rate`.")?
+                    as u32,
+            ),
+            None => Some(DEFAULT_PCM16_SAMPLE_RATE),
+        },
+        Some(_) => anyhow::bail!("Unsupported audio `format`, expected `wav` or `pcm16`."),
+    };
+    Ok(AudioPart {
+        source: source.clone(),
+        pcm16_sample_rate,
+    })
+}
+
+fn content_part_value<'a, V>(
+    content_part: &'a HashMap<String, V>,
+    key: &str,
+) -> Option<&'a Either<String, HashMap<String, Value>>>
+where
+    V: Borrow<Either<String, HashMap<String, Value>>>,
+{
+    content_part.get(key).map(Borrow::borrow)
+}
+
+/// Parse the content parts of a chat message, such as
+/// `{`type`: `image_url`, `image_url`: {`url`: ...}}`, preserving their order. Each part maps a
+/// key to either a string or an object.
+pub fn parse_content_parts<V>(
+    content_parts: &[HashMap<String, V>],
+) -> anyhow::Result<Vec<ContentPart>>
+where
+    V: Borrow<Either<String, HashMap<String, Value>>>,
+{
+    let mut parts = Vec::new();
+    for content_part in content_parts {
+        let get = |key: &str| content_part_value(content_part, key);
+        let Some(tp) = get("type") else {
+            anyhow::bail!("Expected `type` key in input message.");
+        };
+        let Either::Left(tp) = tp else {
+            anyhow::bail!("Expected string value in `type`.");
+        };
+        match tp.as_str() {
+            "text" => match get("text") {
+                Some(Either::Left(text)) => parts.push(ContentPart::Text(text.clone())),
+                _ => anyhow::bail!(
+                    "Expected content of format {{`type`: `text`, `text`: ...}}, with a string value in `text`."
+                ),
+            },
+            "image_url" => match get("image_url")
+                .and_then(|image_url| image_url.as_ref().right())
+                .and_then(|image_url| image_url.get("url"))
+            {
+                Some(Value::String(url)) => parts.push(ContentPart::ImageUrl(url.clone())),
+                _ => anyhow::bail!(
+                    "Expected content of format {{`type`: `image_url`, `image_url`: {{`url`: ...}}}}"
+                ),
+            },
+            "video_url" => match get("video_url") {
+                Some(Either::Right(video_url)) => {
+                    parts.push(ContentPart::VideoUrl(parse_video_part(video_url)?))
+                }
+                _ => anyhow::bail!(
+                    "Expected content of format {{`type`: `video_url`, `video_url`: {{`url`: ...}}}} or {{`type`: `video_url`, `video_url`: {{`frames`: [...]}}}}"
+                ),
+            },
+            "input_audio" | "audio_url" => {
+                let source_key = if tp == "input_audio" { "data" } else { "url" };
+                match get(tp) {
+                    Some(Either::Right(audio)) => {
+                        parts.push(ContentPart::Audio(parse_audio_part(audio, source_key)?))
+                    }
+                    _ => anyhow::bail!(
+                        "Expected content of format {{`type`: `input_audio`, `input_audio`: {{`data`: ..., `format`: ...}}}} or {{`type`: `audio_url`, `audio_url`: {{`url`: ...}}}}"
+                    ),
+                }
+            }
+            other => anyhow::bail!(
+                "Unsupported content part type `{other}`, expected `text`, `image_url`, `video_url`, `input_audio` or `audio_url`."
+            ),
+        }
+    }
+    Ok(parts)
+}
+
+/// An image, video or audio clip of a message, by its index in the whole request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PendingMedia {
+    Image(usize),
+    Video(usize),
+    Audio(usize),
+}
+
+/// Prefix the placeholders of the images, videos and audio clips which directly precede a text
+/// part, in order.
+pub fn prefix_media(
+    prefixer: &dyn VisionPromptPrefixer,
+    pending: &[PendingMedia],
+    prompt: &str,
+) -> String {
+    pending
+        .iter()
+        .rev()
+        .fold(prompt.to_string(), |prompt, media| match media {
+            PendingMedia::Image(image_index) => prefixer.prefix_image(*image_index, &prompt),
+            PendingMedia::Video(video_index) => prefixer.prefix_video(*video_index, &prompt),
+            PendingMedia::Audio(audio_index) => prefixer.prefix_audio(*audio_index, &prompt),
+        })
+}
+
+/// The text of a message made only of text parts, which are joined with newlines as the Hugging
+/// Face processors do. Returns `None` if there is any image, video or audio part.
+pub fn join_text_parts(parts: &[ContentPart]) -> Option<String> {
+    parts
+        .iter()
+        .map(|part| match part {
+            ContentPart::Text(text) => Some(text.as_str()),
+            ContentPart::ImageUrl(_) | ContentPart::VideoUrl(_) | ContentPart::Audio(_) => None,
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(|texts| texts.join("\n"))
+}
+
+/// Whether the text parts of a message already contain the placeholder of one of its images, as
+/// clients had to write them before the placeholders were added automatically. The images of such
+/// a message are not prefixed again. `first_image` is the index in the whole request of the first
+/// image of the message.
+pub fn has_image_placeholders(
+    prefixer: &dyn VisionPromptPrefixer,
+    parts: &[ContentPart],
+    first_image: usize,
+) -> bool {
+    let n_images = parts
+        .iter()
+        .filter(|part| matches!(part, ContentPart::ImageUrl(_)))
+        .count();
+    (first_image..first_image + n_images).any(|image_index| {
+        let placeholder = prefixer.prefix_image(image_index, "");
+        !placeholder.is_empty()
+            && parts.iter().any(|part| match part {
+                ContentPart::Text(text) => text.contains(&placeholder),
+                ContentPart::ImageUrl(_) | ContentPart::VideoUrl(_) | ContentPart::Audio(_) => {
+                    false
+                }
+            })
+    })
 }
 
 #[derive(Clone)]
@@ -75,6 +523,13 @@ pub enum RequestMessage {
 ///     3) Apply temperature and softmax
 ///     4) Sample the next token (topk, topp, minp, etc)
 /// - `return_raw_logits`: Return raw logits.
+/// - `chat_template_kwargs`: Extra variables for the chat template, such as `enable_thinking`.
+/// - `continue_final_message`: Leave the final assistant message open so that the model continues
+///     it instead of starting a new turn. Only the continuation is returned.
+/// - `conversation`: Tokens of a conversation stored outside of the engine, see
+///     [`ConversationTokens`].
+/// - `priority`: Whether the scheduler serves this request before or after others, see
+///     [`RequestPriority`].
 pub struct NormalRequest {
     pub messages: RequestMessage,
     pub sampling_params: SamplingParams,
@@ -89,6 +544,36 @@ pub struct NormalRequest {
     pub tool_choice: Option<ToolChoice>,
     pub logits_processors: Option<Vec<Arc<dyn CustomLogitsProcessor>>>,
     pub return_raw_logits: bool,
+    pub chat_template_kwargs: Option<HashMap<String, Value>>,
+    pub continue_final_message: bool,
+    pub conversation: Option<ConversationTokens>,
+    pub priority: RequestPriority,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Priority of a request in the scheduler. Waiting `Normal` requests are started before waiting
+/// `Low` requests, and running `Low` requests are preempted first when the KV cache is full. Use
+/// `Low` for background work such as batch jobs so that interactive requests are served first.
+pub enum RequestPriority {
+    #[default]
+    Normal,
+    Low,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// Tokens of a chat which continues a conversation stored outside of the engine, such as by the
+/// server with `previous_response_id`.
+/// - `prefix_tokens`: The prompt and completion tokens of the previous response, as returned in
+///     [`crate::Choice::conversation_tokens`]. They are used as they are in place of the first
+///     `prefix_messages` messages, so that the prompt holds the tokens which were actually
+///     generated and the prefix cacher can reuse their KV cache.
+/// - `prefix_messages`: Number of messages of the request which `prefix_tokens` cover.
+/// - `return_tokens`: Return the tokens of this exchange in
+///     [`crate::Choice::conversation_tokens`], so that they can be stored to continue it later.
+pub struct ConversationTokens {
+    pub prefix_tokens: Vec<u32>,
+    pub prefix_messages: usize,
+    pub return_tokens: bool,
 }
 
 impl NormalRequest {
@@ -114,6 +599,10 @@ impl NormalRequest {
             adapters: None,
             logits_processors: None,
             return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
         }
     }
 }
@@ -124,6 +613,7 @@ impl NormalRequest {
 pub struct TokenizationRequest {
     pub text: Either<Vec<IndexMap<String, MessageContent>>, String>,
     pub tools: Option<Vec<Tool>>,
+    pub chat_template_kwargs: Option<HashMap<String, Value>>,
     pub add_generation_prompt: bool,
     pub add_special_tokens: bool,
     pub response: Sender<anyhow::Result<Vec<u32>>>,
@@ -183,3 +673,260 @@ impl Debug for Request {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use either::Either;
+    use serde_json::{json, Value};
+
+    use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+
+    use super::{
+        has_image_placeholders, join_text_parts, parse_content_parts, prefix_media, AudioPart,
+        ContentPart, PendingMedia, VideoInput, VideoSampling, DEFAULT_PCM16_SAMPLE_RATE,
+    };
+    use crate::VisionPromptPrefixer;
+
+    type Part = HashMap<String, Either<String, HashMap<String, Value>>>;
+
+    /// Build content parts from JSON, as the OpenAI API sends them.
+    fn parts(value: Value) -> Vec<Part> {
+        value
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|part| {
+                part.as_object()
+                    .unwrap()
+                    .iter()
+                    .map(|(key, value)| {
+                        let value = match value {
+                            Value::String(s) => Either::Left(s.clone()),
+                            Value::Object(o) => Either::Right(
+                                o.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+                            ),
+                            other => panic!("unexpected {other}"),
+                        };
+                        (key.clone(), value)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn content_parts_keep_their_order() {
+        let parsed = parse_content_parts(&parts(json!([
+            {"type": "image_url", "image_url": {"url": "a.png"}},
+            {"type": "text", "text": "Compare"},
+            {"type": "input_audio", "input_audio": {"data": "UklGRg==", "format": "pcm16"}},
+            {"type": "video_url", "video_url": {"frames": ["b.png", "c.png"], "num_frames": 2}},
+        ])))
+        .unwrap();
+
+        assert_eq!(parsed.len(), 4);
+        assert_eq!(parsed[0], ContentPart::ImageUrl("a.png".to_string()));
+        assert_eq!(parsed[1], ContentPart::Text("Compare".to_string()));
+        assert_eq!(
+            parsed[2],
+            ContentPart::Audio(AudioPart {
+                source: "UklGRg==".to_string(),
+                pcm16_sample_rate: Some(DEFAULT_PCM16_SAMPLE_RATE),
+            })
+        );
+        let ContentPart::VideoUrl(video) = &parsed[3] else {
+            panic!("expected a video part");
+        };
+        assert_eq!(
+            video.source,
+            Either::Right(vec!["b.png".to_string(), "c.png".to_string()])
+        );
+        assert_eq!(video.sampling.num_frames, Some(2));
+    }
+
+    #[test]
+    fn invalid_content_parts_are_rejected() {
+        for invalid in [
+            json!([{"text": "no type"}]),
+            json!([{"type": "image_url", "image_url": {"link": "a.png"}}]),
+            json!([{"type": "input_audio", "input_audio": {"data": "x", "format": "mp3"}}]),
+            json!([{"type": "input_audio", "input_audio": {"data": "x", "format": "pcm16", "sample_rate": 0.5}}]),
+            json!([{"type": "video_url", "video_url": {"url": "a.gif", "frames": ["b.png"]}}]),
+            json!([{"type": "file", "file": {"url": "a.pdf"}}]),
+        ] {
+            assert!(
+                parse_content_parts(&parts(invalid.clone())).is_err(),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn text_parts_are_joined_with_newlines() {
+        let text = |text: &str| ContentPart::Text(text.to_string());
+        assert_eq!(
+            join_text_parts(&[text("Hello"), text("world")]),
+            Some("Hello\nworld".to_string())
+        );
+        assert_eq!(join_text_parts(&[text("Hello")]), Some("Hello".to_string()));
+        assert_eq!(
+            join_text_parts(&[text("Hello"), ContentPart::ImageUrl("a.png".to_string())]),
+            None
+        );
+    }
+
+    struct IndexedPrefixer;
+
+    impl VisionPromptPrefixer for IndexedPrefixer {
+        fn prefix_image(&self, image_index: usize, prompt: &str) -> String {
+            format!("<|image_{}|>{prompt}", image_index + 1)
+        }
+    }
+
+    #[test]
+    fn media_are_prefixed_in_order() {
+        let pending = [PendingMedia::Image(1), PendingMedia::Image(2)];
+        assert_eq!(
+            prefix_media(&IndexedPrefixer, &pending, "Compare these."),
+            "<|image_2|><|image_3|>Compare these."
+        );
+        assert_eq!(
+            prefix_media(&IndexedPrefixer, &pending[..1], ""),
+            "<|image_2|>"
+        );
+        assert_eq!(prefix_media(&IndexedPrefixer, &[], "Text"), "Text");
+    }
+
+    #[test]
+    fn existing_image_placeholders_are_detected() {
+        let image = ContentPart::ImageUrl("a.png".to_string());
+        let text = |text: &str| ContentPart::Text(text.to_string());
+
+        let manual = [image.clone(), text("<|image_2|>\nWhat is this?")];
+        assert!(has_image_placeholders(&IndexedPrefixer, &manual, 1));
+        // The placeholder of another image of the request does not count.
+        assert!(!has_image_placeholders(&IndexedPrefixer, &manual, 0));
+        assert!(!has_image_placeholders(
+            &IndexedPrefixer,
+            &[image.clone(), text("What is this?")],
+            0
+        ));
+        assert!(!has_image_placeholders(
+            &IndexedPrefixer,
+            &[text("<|image_1|>")],
+            0
+        ));
+    }
+
+    fn frame(value: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([value; 3])))
+    }
+
+    /// A clip of `n` frames at 10 fps, with frame `i` filled with `i`.
+    fn video(n: u8, sampling: VideoSampling) -> VideoInput {
+        VideoInput::from_frames((0..n).map(frame).collect(), 10.).with_sampling(sampling)
+    }
+
+    fn sampled(video: &VideoInput) -> Vec<u8> {
+        video
+            .sample_frames()
+            .iter()
+            .map(|f| f.get_pixel(0, 0)[0])
+            .collect()
+    }
+
+    #[test]
+    fn video_sampling_precedence() {
+        let sampling = |fps, num_frames, max_frames| VideoSampling {
+            fps,
+            num_frames,
+            max_frames,
+        };
+        // 1 s at 10 fps, sampled at 2 fps
+        assert_eq!(
+            sampled(&video(10, sampling(Some(2.), None, None))),
+            vec![0, 9]
+        );
+        // `num_frames` takes precedence over `fps`
+        assert_eq!(
+            sampled(&video(10, sampling(Some(2.), Some(5), None))),
+            vec![0, 2, 5, 7, 9]
+        );
+        // `max_frames` caps both
+        assert_eq!(
+            sampled(&video(10, sampling(Some(2.), Some(5), Some(3)))),
+            vec![0, 5, 9]
+        );
+        assert_eq!(
+            sampled(&video(10, sampling(Some(100.), None, Some(3)))),
+            vec![0, 5, 9]
+        );
+        // Without `fps` or `num_frames`, all frames are kept, but never more than the clip has
+        assert_eq!(
+            sampled(&video(4, sampling(None, None, None))),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(
+            sampled(&video(4, sampling(None, Some(100), None))),
+            vec![0, 1, 2, 3]
+        );
+        // At least one frame is kept
+        assert_eq!(sampled(&video(10, sampling(None, Some(0), None))), vec![0]);
+        assert_eq!(
+            sampled(&video(10, sampling(Some(0.01), None, None))),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn video_sampling_of_single_frame_and_empty_clips() {
+        assert_eq!(sampled(&video(1, VideoSampling::default())), vec![0]);
+        assert_eq!(
+            sampled(&video(
+                1,
+                VideoSampling {
+                    fps: None,
+                    num_frames: Some(8),
+                    max_frames: None,
+                }
+            )),
+            vec![0]
+        );
+        assert!(sampled(&video(0, VideoSampling::default())).is_empty());
+    }
+
+    #[test]
+    fn animation_frame_rate_from_delays() {
+        use image::{codecs::gif::GifEncoder, Delay, Frame, RgbaImage};
+
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut bytes);
+            let frames = (0..3u8).map(|i| {
+                Frame::from_parts(
+                    RgbaImage::from_pixel(2, 2, image::Rgba([i * 100, 0, 0, 255])),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(100, 1),
+                )
+            });
+            encoder.encode_frames(frames).unwrap();
+        }
+
+        let video = VideoInput::from_animation_bytes(&bytes).unwrap();
+        assert_eq!(video.frames.len(), 3);
+        assert!((video.fps - 10.).abs() < 1e-6, "{}", video.fps);
+
+        assert!(VideoInput::from_animation_bytes(&[]).is_err());
+        let mut jpeg = Vec::new();
+        frame(0)
+            .write_to(
+                &mut std::io::Cursor::new(&mut jpeg),
+                image::ImageFormat::Jpeg,
+            )
+            .unwrap();
+        assert!(VideoInput::from_animation_bytes(&jpeg).is_err());
+    }
+}
@@ -80,6 +80,10 @@ pub struct Choice {
     pub index: usize,
     pub message: ResponseMessage,
     pub logprobs: Option<Logprobs>,
+    /// The prompt and completion tokens, if requested with
+    /// [`crate::ConversationTokens::return_tokens`].
+    #[serde(skip)]
+    pub conversation_tokens: Option<Vec<u32>>,
 }
 
 generate_repr!(Choice);
@@ -93,6 +97,10 @@ pub struct ChunkChoice {
     pub index: usize,
     pub delta: Delta,
     pub logprobs: Option<ResponseLogprob>,
+    /// The prompt and completion tokens in the final chunk, if requested with
+    /// [`crate::ConversationTokens::return_tokens`].
+    #[serde(skip)]
+    pub conversation_tokens: Option<Vec<u32>>,
 }
 
 generate_repr!(ChunkChoice);
@@ -224,6 +232,139 @@ pub struct ImageGenerationResponse {
 
 generate_repr!(ImageGenerationResponse);
 
+#[cfg_attr(feature = "pyo3_macros", pyclass)]
+#[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
+#[derive(Debug, Clone, Serialize)]
+/// A timed span of a transcript. `start` and `end` are in seconds from the start of the audio.
+pub struct TranscriptionSegment {
+    pub id: usize,
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    pub tokens: Vec<u32>,
+}
+
+generate_repr!(TranscriptionSegment);
+
+#[cfg_attr(feature = "pyo3_macros", pyclass)]
+#[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
+#[derive(Debug, Clone, Serialize)]
+/// Transcription response. `language` is the name of the spoken (or detected) language and
+/// `duration` is the length of the audio in seconds.
+pub struct TranscriptionResponse {
+    pub task: String,
+    pub language: String,
+    pub duration: f64,
+    pub text: String,
+    pub segments: Vec<TranscriptionSegment>,
+}
+
+generate_repr!(TranscriptionResponse);
+
+#[cfg_attr(feature = "pyo3_macros", pyclass)]
+#[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
+#[derive(Debug, Clone, Serialize)]
+/// Token usage of an embedding, rerank or classification request.
+pub struct EmbeddingUsage {
+    pub prompt_tokens: usize,
+    pub total_tokens: usize,
+}
+
+generate_repr!(EmbeddingUsage);
+
+#[cfg_attr(feature = "pyo3_macros", pyclass)]
+#[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
+#[derive(Debug, Clone, Serialize)]
+/// The embedding of the input at `index`.
+pub struct EmbeddingData {
+    pub object: String,
+    pub embedding: Vec<f32>,
+    pub index: usize,
+}
+
+generate_repr!(EmbeddingData);
+
+#[cfg_attr(feature = "pyo3_macros", pyclass)]
+#[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
+#[derive(Debug, Clone, Serialize)]
+/// Embedding response, with one embedding per input in the input order.
+pub struct EmbeddingResponse {
+    pub object: String,
+    pub data: Vec<EmbeddingData>,
+    pub model: String,
+    pub usage: EmbeddingUsage,
+}
+
+generate_repr!(EmbeddingResponse);
+
+#[cfg_attr(feature = "pyo3_macros", pyclass)]
+#[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
+#[derive(Debug, Clone, Serialize)]
+pub struct RerankDocument {
+    pub text: String,
+}
+
+generate_repr!(RerankDocument);
+
+#[cfg_attr(feature = "pyo3_macros", pyclass)]
+#[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
+#[derive(Debug, Clone, Serialize)]
+/// The relevance of the document at `index` to the query, in `[0, 1]`.
+pub struct RerankResult {
+    pub index: usize,
+    pub relevance_score: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document: Option<RerankDocument>,
+}
+
+generate_repr!(RerankResult);
+
+#[cfg_attr(feature = "pyo3_macros", pyclass)]
+#[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
+#[derive(Debug, Clone, Serialize)]
+/// Rerank response, with the results sorted from most to least relevant.
+pub struct RerankResponse {
+    pub model: String,
+    pub results: Vec<RerankResult>,
+    pub usage: EmbeddingUsage,
+}
+
+generate_repr!(RerankResponse);
+
+#[cfg_attr(feature = "pyo3_macros", pyclass)]
+#[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
+#[derive(Debug, Clone, Serialize)]
+/// The classification of the input at `index`.
+/// - `label`: The most likely label.
+/// - `probs`: The probability of each label, in the order of the response `labels`. Labels are
+///     independent (sigmoid) for multi-label classifiers and single-output heads.
+/// - `logits`: The raw outputs of the classification head.
+/// - `reward`: The scalar score of a reward model, which has a single output.
+pub struct ClassificationResult {
+    pub index: usize,
+    pub label: String,
+    pub probs: Vec<f32>,
+    pub logits: Vec<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reward: Option<f32>,
+}
+
+generate_repr!(ClassificationResult);
+
+#[cfg_attr(feature = "pyo3_macros", pyclass)]
+#[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
+#[derive(Debug, Clone, Serialize)]
+/// Classification response, with one result per input in the input order.
+pub struct ClassificationResponse {
+    pub object: String,
+    pub model: String,
+    pub labels: Vec<String>,
+    pub data: Vec<ClassificationResult>,
+    pub usage: EmbeddingUsage,
+}
+
+generate_repr!(ClassificationResponse);
+
 /// The response enum contains 3 types of variants:
 /// - Error (-Error suffix)
 /// - Chat (no prefix)
@@ -241,6 +382,12 @@ pub enum Response {
     CompletionChunk(CompletionChunkResponse),
     // Image generation
     ImageGeneration(ImageGenerationResponse),
+    // Speech to text
+    Transcription(TranscriptionResponse),
+    // Embeddings, reranking and classification
+    Embedding(EmbeddingResponse),
+    Rerank(RerankResponse),
+    Classification(ClassificationResponse),
     // Raw
     Raw {
         logits_chunks: Vec<Tensor>,
@@ -258,6 +405,12 @@ pub enum ResponseOk {
     CompletionChunk(CompletionChunkResponse),
     // Image generation
     ImageGeneration(ImageGenerationResponse),
+    // Speech to text
+    Transcription(TranscriptionResponse),
+    // Embeddings, reranking and classification
+    Embedding(EmbeddingResponse),
+    Rerank(RerankResponse),
+    Classification(ClassificationResponse),
     // Raw
     Raw {
         logits_chunks: Vec<Tensor>,
@@ -325,6 +478,10 @@ impl Response {
                 Err(Box::new(ResponseErr::CompletionModelError(e, x)))
             }
             Self::ImageGeneration(x) => Ok(ResponseOk::ImageGeneration(x)),
+            Self::Transcription(x) => Ok(ResponseOk::Transcription(x)),
+            Self::Embedding(x) => Ok(ResponseOk::Embedding(x)),
+            Self::Rerank(x) => Ok(ResponseOk::Rerank(x)),
+            Self::Classification(x) => Ok(ResponseOk::Classification(x)),
             Self::Raw {
                 logits_chunks,
                 tokens,
@@ -17,7 +17,8 @@ pub trait FcfsBacker: Default {
     fn add(&mut self, item: Sequence);
     fn into_iter(self) -> impl Iterator<Item = Sequence>;
     fn len(&self) -> usize;
-    fn sort_ascending_ids(&mut self);
+    /// Sort by priority, then by ascending ids, so that low priority sequences come last.
+    fn sort_by_priority_and_id(&mut self);
 }
 
 impl FcfsBacker for VecDeque<Sequence> {
@@ -30,9 +31,9 @@ impl FcfsBacker for VecDeque<Sequence> {
     fn into_iter(self) -> impl Iterator<Item = Sequence> {
         <Self as IntoIterator>::into_iter(self)
     }
-    fn sort_ascending_ids(&mut self) {
+    fn sort_by_priority_and_id(&mut self) {
         let slice = self.make_contiguous();
-        slice.sort_by_key(|seq| *seq.id());
+        slice.sort_by_key(|seq| (seq.is_low_priority(), *seq.id()));
     }
     fn len(&self) -> usize {
         VecDeque::len(self)
@@ -72,7 +73,11 @@ pub trait BucketingManager<Backer: FcfsBacker> {
 // Bucket by that metric for images because if we are not a prompt, then this doesn't apply
 type BucketKey = (Option<Vec<String>>, usize, bool, usize);
 
-struct FixedBucketingManager;
+struct FixedBucketingManager {
+    /// The per-sequence cache has a constant size (recurrent models), so completion sequences of
+    /// different lengths can run in the same batch.
+    constant_state_size: bool,
+}
 
 impl<Backer: FcfsBacker> BucketingManager<Backer> for FixedBucketingManager {
     /// Move the seuqences into buckets, and run the ones with the shortest lengths.
@@ -88,7 +93,11 @@ impl<Backer: FcfsBacker> BucketingManager<Backer> for FixedBucketingManager {
         let mut seq_buckets: HashMap<BucketKey, Vec<Sequence>> = HashMap::new();
         let mut seq_priorities: HashMap<BucketKey, f64> = HashMap::new();
         for seq in running {
-            let len = seq.len();
+            let len = if self.constant_state_size && !seq.is_prompt() {
+                0
+            } else {
+                seq.len()
+            };
             match seq_buckets.get_mut(&(
                 seq.get_adapters(),
                 len,
@@ -183,9 +192,11 @@ pub struct DefaultScheduler<Backer: FcfsBacker> {
 }
 
 impl<Backer: FcfsBacker> DefaultScheduler<Backer> {
-    pub fn new(method: DefaultSchedulerMethod) -> Self {
+    pub fn new(method: DefaultSchedulerMethod, constant_state_size: bool) -> Self {
         let bucketing_manager: Box<dyn BucketingManager<_>> = match method {
-            DefaultSchedulerMethod::Fixed(_) => Box::new(FixedBucketingManager),
+            DefaultSchedulerMethod::Fixed(_) => Box::new(FixedBucketingManager {
+                constant_state_size,
+            }),
         };
         Self {
             running: Vec::new(),
@@ -254,8 +265,8 @@ impl<Backer: FcfsBacker> DefaultScheduler<Backer> {
             _ => {}
         }
 
-        // Sort the waiting seqs
-        waiting.sort_ascending_ids();
+        // Sort the waiting seqs, so that normal priority ones are added first
+        waiting.sort_by_priority_and_id();
 
         // If the waiting sequence will fit, add it. Otherwise remove it
         let mut new_waiting = Backer::new();
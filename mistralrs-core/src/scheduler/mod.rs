@@ -22,9 +22,13 @@ pub enum SchedulerConfig {
 }
 
 impl SchedulerConfig {
-    pub fn into_scheduler(self) -> Box<dyn Scheduler> {
+    /// `constant_state_size` is whether the model's per-sequence cache does not grow with the
+    /// sequence length, see [`crate::pipeline::EitherCache::is_constant_size`].
+    pub fn into_scheduler(self, constant_state_size: bool) -> Box<dyn Scheduler> {
         match self {
-            Self::DefaultScheduler { method } => Box::new(DefaultScheduler::new(method)),
+            Self::DefaultScheduler { method } => {
+                Box::new(DefaultScheduler::new(method, constant_state_size))
+            }
             Self::PagedAttentionMeta {
                 max_num_seqs,
                 config,
@@ -1,3 +1,12 @@
+use crate::{
+    embedding_models::EmbeddingInputs,
+    paged_attention::{BlockEngineSequence, LogicalTokenBlock},
+    pipeline::{DiffusionGenerationParams, KvCache, RecurrentLayerCache, TranscriptionParams},
+    response::CompletionChoice,
+    tools::ToolCallingMatcher,
+    AudioInput, CompletionChunkChoice, CompletionChunkResponse, CompletionResponse, ImageChoice,
+    ImageGenerationResponse, ImageGenerationResponseFormat, RequestPriority,
+};
 use crate::{
     get_mut_group,
     pipeline::LayerCaches,
@@ -5,14 +14,6 @@ use crate::{
     sampler::{Logprobs, Sampler},
     ChatCompletionResponse, Usage,
 };
-use crate::{
-    paged_attention::{BlockEngineSequence, LogicalTokenBlock},
-    pipeline::{DiffusionGenerationParams, KvCache},
-    response::CompletionChoice,
-    tools::ToolCallingMatcher,
-    CompletionChunkChoice, CompletionChunkResponse, CompletionResponse, ImageChoice,
-    ImageGenerationResponse, ImageGenerationResponseFormat,
-};
 use candle_core::Tensor;
 use std::{
     fmt::Display,
@@ -36,6 +37,8 @@ pub enum StopReason {
     },
     Canceled,
     GeneratedImage,
+    Transcribed,
+    Embedded,
 }
 
 impl Display for StopReason {
@@ -46,6 +49,8 @@ impl Display for StopReason {
             StopReason::StopTok(_) | StopReason::StopString { .. } => write!(f, "stop"),
             StopReason::Canceled => write!(f, "canceled"),
             StopReason::GeneratedImage => write!(f, "generated-image"),
+            StopReason::Transcribed => write!(f, "transcribed"),
+            StopReason::Embedded => write!(f, "embedded"),
         }
     }
 }
@@ -155,6 +160,7 @@ pub struct Sequence {
     prompt_len: usize,
     max_len: Option<usize>,
     timestamp: u128,
+    priority: RequestPriority,
     sampler: Arc<Sampler>,
     stop_tokens: Vec<u32>,
     stop_strings: Vec<String>,
@@ -171,6 +177,12 @@ pub struct Sequence {
     image_gen_response_format: Option<ImageGenerationResponseFormat>,
     diffusion_params: Option<DiffusionGenerationParams>,
 
+    // Speech to text
+    transcription_params: Option<TranscriptionParams>,
+
+    // Embedding and reranking
+    embedding_inputs: Option<EmbeddingInputs>,
+
     // Completion requests
     suffix: Option<String>,
     prefix: Option<String>,
@@ -187,6 +199,7 @@ pub struct Sequence {
     // Cache
     normal_cache: Vec<Option<KvCache>>,
     normal_draft_cache: Vec<Option<KvCache>>,
+    recurrent_cache: Vec<Option<RecurrentLayerCache>>,
     scaling_cache: Option<Tensor>,
     cache: LayerCaches,
     draft_cache: LayerCaches,
@@ -207,9 +220,13 @@ pub struct Sequence {
     pub recognizer: SequenceRecognizer,
     scheduling_urgency: usize, // The number of passes since scheduling
     input_images: Option<Vec<image::DynamicImage>>,
+    // Sampled frames of each video
+    input_videos: Option<Vec<Vec<image::DynamicImage>>>,
+    input_audios: Option<Vec<AudioInput>>,
     pub cached_pixel_values: Option<Tensor>,
     pub cached_img_thw: Option<Tensor>,
     pub cached_vid_thw: Option<Tensor>,
+    pub cached_vid_pixel_values: Option<Tensor>,
 
     // GPU things
     pub prompt_tok_per_sec: f32,
@@ -275,6 +292,8 @@ impl Sequence {
         prefix: Option<String>,
         adapters: Option<Vec<String>>,
         input_images: Option<Vec<image::DynamicImage>>,
+        input_videos: Option<Vec<Vec<image::DynamicImage>>>,
+        input_audios: Option<Vec<AudioInput>>,
         // Paged attention
         block_size: Option<usize>,
         //
@@ -282,6 +301,8 @@ impl Sequence {
         image_gen_response_format: Option<ImageGenerationResponseFormat>,
         sequence_stepping_type: SeqStepType,
         diffusion_params: Option<DiffusionGenerationParams>,
+        transcription_params: Option<TranscriptionParams>,
+        embedding_inputs: Option<EmbeddingInputs>,
         // Preallocated KV cache (k,v)
         seq_preallocated_cache: Option<(Tensor, Tensor)>,
         //
@@ -308,6 +329,7 @@ impl Sequence {
             state: RwLock::new(SequenceState::Waiting),
             normal_cache: vec![None; layers],
             normal_draft_cache: vec![None; layers],
+            recurrent_cache: vec![None; layers],
             cache: vec![None; layers],
             draft_cache: vec![None; layers],
             xlora_cache: if is_xlora {
@@ -342,19 +364,30 @@ impl Sequence {
             scheduling_urgency: 0,
             adapters,
             input_images,
+            input_videos,
+            input_audios,
             custom_metadata,
             tools,
             image_gen_response_format,
             sequence_stepping_type,
             diffusion_params,
+            transcription_params,
+            embedding_inputs,
             cached_pixel_values: None,
             cached_img_thw: None,
             cached_vid_thw: None,
+            cached_vid_pixel_values: None,
             return_raw_logits,
             token_offset: 0,
+            priority: RequestPriority::Normal,
         }
     }
 
+    pub fn with_priority(mut self, priority: RequestPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
     pub fn add_urgency(mut self) -> Self {
         self.scheduling_urgency += 1;
         self
@@ -513,6 +546,10 @@ impl Sequence {
         &mut self.normal_draft_cache
     }
 
+    pub fn recurrent_cache(&mut self) -> &mut Vec<Option<RecurrentLayerCache>> {
+        &mut self.recurrent_cache
+    }
+
     pub fn cache(&mut self) -> &mut Vec<Option<(Tensor, Tensor)>> {
         &mut self.cache
     }
@@ -665,6 +702,20 @@ impl Sequence {
         self.prompt_len
     }
 
+    /// The prompt and completion tokens, to continue the conversation later with the tokens which
+    /// were actually generated. A final EOS or stop token is dropped because the chat template
+    /// closes the turn itself.
+    pub fn conversation_tokens(&self) -> Vec<u32> {
+        let mut tokens = self.tokens.clone();
+        if matches!(
+            self.last_is_done,
+            Some(StopReason::Eos) | Some(StopReason::StopTok(_))
+        ) {
+            tokens.pop();
+        }
+        tokens
+    }
+
     pub fn stop_strings(&self) -> &[String] {
         &self.stop_strings
     }
@@ -702,6 +753,12 @@ impl Sequence {
         self.timestamp
     }
 
+    /// Whether the request has [`RequestPriority::Low`], so that the scheduler serves other
+    /// sequences first.
+    pub fn is_low_priority(&self) -> bool {
+        self.priority == RequestPriority::Low
+    }
+
     pub fn prompt_timestamp(&self) -> Option<u128> {
         self.prompt_timestamp
     }
@@ -786,6 +843,22 @@ impl Sequence {
         self.input_images.as_deref()
     }
 
+    pub fn clone_videos(&mut self) -> Option<Vec<Vec<image::DynamicImage>>> {
+        self.input_videos.clone()
+    }
+
+    pub fn videos(&self) -> Option<&[Vec<image::DynamicImage>]> {
+        self.input_videos.as_deref()
+    }
+
+    pub fn take_audios(&mut self) -> Option<Vec<AudioInput>> {
+        self.input_audios.take()
+    }
+
+    pub fn audios(&self) -> Option<&[AudioInput]> {
+        self.input_audios.as_deref()
+    }
+
     pub fn image_gen_response_format(&self) -> Option<ImageGenerationResponseFormat> {
         self.image_gen_response_format
     }
@@ -797,6 +870,14 @@ impl Sequence {
     pub fn get_diffusion_diffusion_params(&self) -> Option<DiffusionGenerationParams> {
         self.diffusion_params.clone()
     }
+
+    pub fn get_transcription_params(&self) -> Option<TranscriptionParams> {
+        self.transcription_params.clone()
+    }
+
+    pub(crate) fn get_embedding_inputs(&self) -> Option<EmbeddingInputs> {
+        self.embedding_inputs.clone()
+    }
 }
 
 pub struct SequenceGroup {
@@ -815,6 +896,8 @@ pub struct SequenceGroup {
     pub completion_streaming_chunks: Vec<CompletionChunkChoice>,
     pub is_streaming: bool,
     pub is_chat: bool,
+    /// Return the tokens of each sequence in its choice, see [`crate::ConversationTokens`].
+    pub return_conversation_tokens: bool,
 }
 
 impl SequenceGroup {
@@ -840,6 +923,7 @@ impl SequenceGroup {
             is_streaming,
             is_chat,
             best_of,
+            return_conversation_tokens: false,
         }
     }
 
@@ -0,0 +1,68 @@
+pub(crate) mod processor;
+pub(crate) mod whisper;
+
+use serde::{Deserialize, Serialize};
+
+macro_rules! generate_repr {
+    ($t:ident) => {
+        #[cfg(feature = "pyo3_macros")]
+        #[pyo3::pymethods]
+        impl $t {
+            fn __repr__(&self) -> String {
+                format!("{self:#?}")
+            }
+        }
+    };
+}
+
+#[cfg_attr(feature = "pyo3_macros", pyo3::pyclass(eq, eq_int))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Whether to transcribe speech in its own language or translate it to English.
+pub enum TranscriptionTask {
+    #[default]
+    Transcribe,
+    Translate,
+}
+
+impl std::fmt::Display for TranscriptionTask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Transcribe => write!(f, "transcribe"),
+            Self::Translate => write!(f, "translate"),
+        }
+    }
+}
+
+#[cfg_attr(feature = "pyo3_macros", pyo3::pyclass)]
+#[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
+#[derive(Debug, Clone)]
+/// Parameters of a transcription request.
+/// - `language`: Language of the speech, as an ISO-639-1 code (`en`) or name (`english`). It is
+///     detected from the first 30 seconds if not given.
+/// - `task`: Transcribe, or translate to English.
+/// - `timestamps`: Predict timestamp tokens, which split the transcript into timed segments.
+/// - `prompt`: Text which precedes the audio, used to guide spelling and style.
+/// - `temperature`: Sampling temperature. `0` decodes greedily.
+pub struct TranscriptionParams {
+    pub language: Option<String>,
+    pub task: TranscriptionTask,
+    pub timestamps: bool,
+    pub prompt: Option<String>,
+    pub temperature: f64,
+}
+
+generate_repr!(TranscriptionParams);
+
+impl Default for TranscriptionParams {
+    /// Detect the language, transcribe with timestamps and decode greedily.
+    fn default() -> Self {
+        Self {
+            language: None,
+            task: TranscriptionTask::Transcribe,
+            timestamps: true,
+            prompt: None,
+            temperature: 0.,
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::{any::Any, collections::HashMap, num::NonZeroUsize, sync::Arc};
+
+use anyhow::{Context, Result};
+use candle_core::Device;
+use indexmap::IndexMap;
+use tokenizers::Tokenizer;
+
+use crate::{
+    device_map::DeviceMapper,
+    pipeline::{
+        text_models_inputs_processor::PagedAttentionMeta, InputProcessorOutput, InputsProcessor,
+        InputsProcessorType, MessagesAction, Processor,
+    },
+    sequence::Sequence,
+    AudioInput, MessageContent, Pipeline,
+};
+
+use super::TranscriptionParams;
+
+pub struct SpeechProcessor;
+
+impl Processor for SpeechProcessor {
+    #[allow(clippy::too_many_arguments)]
+    fn process(
+        &self,
+        _pipeline: &dyn Pipeline,
+        _messages: Vec<IndexMap<String, MessageContent>>,
+        _add_generation_prompt: bool,
+        _continue_final_message: bool,
+        _add_special_tokens: bool,
+        _tools: Vec<crate::Tool>,
+        _chat_template_kwargs: Option<HashMap<String, serde_json::Value>>,
+    ) -> Result<(Vec<u32>, String)> {
+        anyhow::bail!(
+            "SpeechProcessor::process should not be used. It does not expect chat messages."
+        )
+    }
+    fn inputs_processor(&self) -> Arc<dyn InputsProcessor> {
+        Arc::new(SpeechInputsProcessor)
+    }
+    fn get_special_tokens(&self) -> &[&'static str] {
+        &[]
+    }
+    fn template_action(&self) -> MessagesAction {
+        // Just a default
+        MessagesAction::FlattenOnlyText
+    }
+}
+
+pub struct SpeechInputsProcessor;
+
+#[derive(Clone)]
+pub struct ModelInputs {
+    pub(crate) audios: Vec<AudioInput>,
+    pub(crate) params: Vec<TranscriptionParams>,
+}
+
+impl InputsProcessor for SpeechInputsProcessor {
+    fn get_type(&self) -> InputsProcessorType {
+        InputsProcessorType::Text
+    }
+
+    fn process_inputs(
+        &self,
+        _tokenizer: Option<Arc<Tokenizer>>,
+        input_seqs: &mut [&mut Sequence],
+        _is_prompt: bool,
+        _is_xlora: bool,
+        _device: &Device,
+        _no_kv_cache: bool,
+        _last_n_context_len: Option<(usize, usize)>,
+        _return_raw_logits: bool,
+        _other_config: Option<Arc<dyn Any>>,
+        _paged_attn_metadata: Option<PagedAttentionMeta<'_>>,
+        prompt_chunksize: Option<NonZeroUsize>,
+        _mapper: Option<&dyn DeviceMapper>,
+    ) -> Box<dyn Iterator<Item = Result<InputProcessorOutput>>> {
+        let mut make_value = if prompt_chunksize.is_some() {
+            return Box::new(std::iter::once(Err(anyhow::Error::msg(
+                "Prompt batching is unsupported for speech models",
+            ))));
+        } else {
+            || {
+                let mut audios = Vec::new();
+                let mut params = Vec::new();
+                for seq in input_seqs.iter() {
+                    audios.push(
+                        seq.audios()
+                            .and_then(|audios| audios.first())
+                            .context("Transcription audio must be present")?
+                            .clone(),
+                    );
+                    params.push(
+                        seq.get_transcription_params()
+                            .context("Transcription params must be present")?,
+                    );
+                }
+                Ok(InputProcessorOutput {
+                    inputs: Box::new(ModelInputs { audios, params }),
+                    seq_indices: (0..input_seqs.len()).collect::<Vec<_>>(),
+                })
+            }
+        };
+        Box::new(std::iter::once(make_value()))
+    }
+}
@@ -0,0 +1,217 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use candle_core::{DType, Device, Result, Tensor};
+use candle_nn::{Embedding, LayerNorm, Linear, Module};
+use mistralrs_quant::ShardedVarBuilder;
+
+use crate::layers::{embedding, layer_norm, linear, linear_no_bias, Activation, MatMul};
+
+use super::WhisperConfig;
+
+/// Multi-head attention with a KV cache. For cross attention, the keys and values are computed
+/// once from the encoder output and reused for every decoding step.
+struct Attention {
+    num_heads: usize,
+    head_dim: usize,
+    q_proj: Linear,
+    k_proj: Linear,
+    v_proj: Linear,
+    out_proj: Linear,
+    kv_cache: Option<(Tensor, Tensor)>,
+}
+
+impl Attention {
+    fn new(cfg: &WhisperConfig, vb: ShardedVarBuilder) -> Result<Self> {
+        let embed_dim = cfg.encoder.d_model;
+        let num_heads = cfg.decoder_attention_heads;
+        Ok(Self {
+            num_heads,
+            head_dim: embed_dim / num_heads,
+            q_proj: linear(embed_dim, embed_dim, vb.pp("q_proj"))?,
+            k_proj: linear_no_bias(embed_dim, embed_dim, vb.pp("k_proj"))?,
+            v_proj: linear(embed_dim, embed_dim, vb.pp("v_proj"))?,
+            out_proj: linear(embed_dim, embed_dim, vb.pp("out_proj"))?,
+            kv_cache: None,
+        })
+    }
+
+    fn split_heads(&self, xs: &Tensor) -> Result<Tensor> {
+        let (b_sz, seq_len, _) = xs.dims3()?;
+        xs.reshape((b_sz, seq_len, self.num_heads, self.head_dim))?
+            .transpose(1, 2)?
+            .contiguous()
+    }
+
+    /// Self attention if `xa` is `None`, otherwise cross attention over the encoder output `xa`.
+    fn forward(
+        &mut self,
+        xs: &Tensor,
+        xa: Option<&Tensor>,
+        mask: Option<&Tensor>,
+    ) -> Result<Tensor> {
+        let (b_sz, seq_len, embed_dim) = xs.dims3()?;
+        let q =
+            self.split_heads(&(self.q_proj.forward(xs)? * (self.head_dim as f64).powf(-0.5))?)?;
+
+        let (k, v) = match (xa, &self.kv_cache) {
+            (Some(_), Some((k, v))) => (k.clone(), v.clone()),
+            (Some(xa), None) => {
+                let k = self.split_heads(&self.k_proj.forward(xa)?)?;
+                let v = self.split_heads(&self.v_proj.forward(xa)?)?;
+                self.kv_cache = Some((k.clone(), v.clone()));
+                (k, v)
+            }
+            (None, cache) => {
+                let mut k = self.split_heads(&self.k_proj.forward(xs)?)?;
+                let mut v = self.split_heads(&self.v_proj.forward(xs)?)?;
+                if let Some((prev_k, prev_v)) = cache {
+                    k = Tensor::cat(&[prev_k, &k], 2)?;
+                    v = Tensor::cat(&[prev_v, &v], 2)?;
+                }
+                self.kv_cache = Some((k.clone(), v.clone()));
+                (k, v)
+            }
+        };
+
+        let mut attn_weights = MatMul.matmul(&q, &k.t()?.contiguous()?)?;
+        if let Some(mask) = mask {
+            attn_weights = attn_weights.broadcast_add(mask)?;
+        }
+        let attn_weights = candle_nn::ops::softmax_last_dim(&attn_weights)?;
+        MatMul
+            .matmul(&attn_weights, &v)?
+            .transpose(1, 2)?
+            .reshape((b_sz, seq_len, embed_dim))?
+            .apply(&self.out_proj)
+    }
+
+    fn reset(&mut self) {
+        self.kv_cache = None;
+    }
+}
+
+struct DecoderLayer {
+    self_attn: Attention,
+    self_attn_layer_norm: LayerNorm,
+    encoder_attn: Attention,
+    encoder_attn_layer_norm: LayerNorm,
+    fc1: Linear,
+    fc2: Linear,
+    final_layer_norm: LayerNorm,
+    activation: Activation,
+}
+
+impl DecoderLayer {
+    fn new(cfg: &WhisperConfig, vb: ShardedVarBuilder) -> Result<Self> {
+        let d_model = cfg.encoder.d_model;
+        Ok(Self {
+            self_attn: Attention::new(cfg, vb.pp("self_attn"))?,
+            self_attn_layer_norm: layer_norm(d_model, 1e-5, vb.pp("self_attn_layer_norm"))?,
+            encoder_attn: Attention::new(cfg, vb.pp("encoder_attn"))?,
+            encoder_attn_layer_norm: layer_norm(d_model, 1e-5, vb.pp("encoder_attn_layer_norm"))?,
+            fc1: linear(d_model, cfg.decoder_ffn_dim, vb.pp("fc1"))?,
+            fc2: linear(cfg.decoder_ffn_dim, d_model, vb.pp("fc2"))?,
+            final_layer_norm: layer_norm(d_model, 1e-5, vb.pp("final_layer_norm"))?,
+            activation: cfg.encoder.activation_function,
+        })
+    }
+
+    fn forward(&mut self, xs: &Tensor, xa: &Tensor, mask: Option<&Tensor>) -> Result<Tensor> {
+        let residual = xs;
+        let xs = self
+            .self_attn
+            .forward(&xs.apply(&self.self_attn_layer_norm)?, None, mask)?;
+        let xs = (xs + residual)?;
+
+        let residual = &xs;
+        let hidden_states =
+            self.encoder_attn
+                .forward(&xs.apply(&self.encoder_attn_layer_norm)?, Some(xa), None)?;
+        let xs = (hidden_states + residual)?;
+
+        let residual = &xs;
+        let hidden_states = xs
+            .apply(&self.final_layer_norm)?
+            .apply(&self.fc1)?
+            .apply(&self.activation)?
+            .apply(&self.fc2)?;
+        hidden_states + residual
+    }
+
+    fn reset(&mut self) {
+        self.self_attn.reset();
+        self.encoder_attn.reset();
+    }
+}
+
+/// Whisper text decoder. The output projection is tied to the token embeddings.
+pub(super) struct TextDecoder {
+    embed_tokens: Embedding,
+    embed_positions: Tensor,
+    layers: Vec<DecoderLayer>,
+    layer_norm: LayerNorm,
+}
+
+impl TextDecoder {
+    pub(super) fn new(cfg: &WhisperConfig, vb: ShardedVarBuilder) -> Result<Self> {
+        let d_model = cfg.encoder.d_model;
+        let embed_tokens = embedding(cfg.vocab_size, d_model, vb.pp("embed_tokens"))?;
+        let embed_positions =
+            embedding(cfg.max_target_positions, d_model, vb.pp("embed_positions"))?
+                .embeddings()
+                .clone();
+        let vb_l = vb.pp("layers");
+        let layers = (0..cfg.decoder_layers)
+            .map(|i| DecoderLayer::new(cfg, vb_l.pp(i)))
+            .collect::<Result<Vec<_>>>()?;
+        let layer_norm = layer_norm(d_model, 1e-5, vb.pp("layer_norm"))?;
+        Ok(Self {
+            embed_tokens,
+            embed_positions,
+            layers,
+            layer_norm,
+        })
+    }
+
+    fn causal_mask(seq_len: usize, dtype: DType, device: &Device) -> Result<Tensor> {
+        let mask: Vec<_> = (0..seq_len)
+            .flat_map(|i| (0..seq_len).map(move |j| if j > i { f32::NEG_INFINITY } else { 0. }))
+            .collect();
+        Tensor::from_slice(&mask, (seq_len, seq_len), device)?.to_dtype(dtype)
+    }
+
+    /// Run `tokens` (1, seq_len), which start at position `seqlen_offset`, attending to the
+    /// encoder output `xa`. Returns the f32 logits of the last position, (vocab_size,).
+    pub(super) fn forward(
+        &mut self,
+        tokens: &Tensor,
+        xa: &Tensor,
+        seqlen_offset: usize,
+    ) -> Result<Tensor> {
+        let seq_len = tokens.dim(1)?;
+        let mut xs = self
+            .embed_tokens
+            .forward(tokens)?
+            .broadcast_add(&self.embed_positions.narrow(0, seqlen_offset, seq_len)?)?;
+        let mask = if seq_len > 1 {
+            Some(Self::causal_mask(seq_len, xs.dtype(), xs.device())?)
+        } else {
+            None
+        };
+        for layer in &mut self.layers {
+            xs = layer.forward(&xs, xa, mask.as_ref())?;
+        }
+        let xs = xs.apply(&self.layer_norm)?.narrow(1, seq_len - 1, 1)?;
+        xs.broadcast_matmul(&self.embed_tokens.embeddings().t()?)?
+            .squeeze(0)?
+            .squeeze(0)?
+            .to_dtype(DType::F32)
+    }
+
+    /// Clear the self and cross attention caches, before decoding a new window.
+    pub(super) fn reset(&mut self) {
+        for layer in &mut self.layers {
+            layer.reset();
+        }
+    }
+}
@@ -0,0 +1,516 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use candle_core::{Device, Result, Tensor};
+use decoder::TextDecoder;
+use languages::LANGUAGES;
+use mistralrs_quant::ShardedVarBuilder;
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    SeedableRng,
+};
+use rand_isaac::Isaac64Rng;
+use tokenizers::Tokenizer;
+
+use crate::{
+    audio::{WhisperFeatureExtractor, N_FRAMES, N_SAMPLES, SAMPLE_RATE},
+    pipeline::SpeechModel,
+    vision_models::whisper::{WhisperEncoder, WhisperEncoderConfig},
+    AudioInput, TranscriptionResponse, TranscriptionSegment,
+};
+
+use super::{TranscriptionParams, TranscriptionTask};
+
+mod decoder;
+mod languages;
+
+/// Number of samples per timestamp token (20ms).
+const TIMESTAMP_SAMPLES: usize = SAMPLE_RATE as usize / 50;
+/// The first timestamp of a window may be at most 1 second.
+const MAX_INITIAL_TIMESTAMP_INDEX: u32 = 50;
+const SEED: u64 = 0;
+
+/// Whisper `config.json`. The encoder fields are shared with the audio encoder of multimodal
+/// models.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct WhisperConfig {
+    #[serde(flatten)]
+    pub encoder: WhisperEncoderConfig,
+    pub vocab_size: usize,
+    pub decoder_layers: usize,
+    pub decoder_attention_heads: usize,
+    pub decoder_ffn_dim: usize,
+    pub max_target_positions: usize,
+    #[serde(default)]
+    pub suppress_tokens: Vec<u32>,
+    #[serde(default)]
+    pub begin_suppress_tokens: Vec<u32>,
+}
+
+/// The token suppression lists moved to `generation_config.json` in newer checkpoints.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct WhisperGenerationConfig {
+    pub suppress_tokens: Option<Vec<u32>>,
+    pub begin_suppress_tokens: Option<Vec<u32>>,
+}
+
+struct SpecialTokens {
+    sot: u32,
+    eot: u32,
+    sot_prev: Option<u32>,
+    transcribe: Option<u32>,
+    translate: Option<u32>,
+    no_timestamps: u32,
+    timestamp_begin: u32,
+    /// `(token, code, name)` of each language in the vocabulary. Empty for English-only models.
+    languages: Vec<(u32, &'static str, &'static str)>,
+}
+
+impl SpecialTokens {
+    fn new(tokenizer: &Tokenizer) -> anyhow::Result<Self> {
+        let get = |token: &str| {
+            tokenizer
+                .token_to_id(token)
+                .with_context(|| format!("Whisper tokenizer is missing the `{token}` token."))
+        };
+        let no_timestamps = get("<|notimestamps|>")?;
+        Ok(Self {
+            sot: get("<|startoftranscript|>")?,
+            eot: get("<|endoftext|>")?,
+            sot_prev: tokenizer.token_to_id("<|startofprev|>"),
+            transcribe: tokenizer.token_to_id("<|transcribe|>"),
+            translate: tokenizer.token_to_id("<|translate|>"),
+            no_timestamps,
+            // Timestamp tokens `<|0.00|>`, `<|0.02|>`, ... follow `<|notimestamps|>`.
+            timestamp_begin: no_timestamps + 1,
+            languages: LANGUAGES
+                .iter()
+                .filter_map(|(code, name)| {
+                    tokenizer
+                        .token_to_id(&format!("<|{code}|>"))
+                        .map(|token| (token, *code, *name))
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Whisper speech to text model: the log-mel frontend, the audio encoder and a text decoder
+/// which cross attends to the encoded audio. Audio is transcribed in 30 second windows.
+pub struct WhisperModel {
+    encoder: WhisperEncoder,
+    decoder: TextDecoder,
+    feature_extractor: WhisperFeatureExtractor,
+    tokenizer: Arc<Tokenizer>,
+    special: SpecialTokens,
+    suppress_tokens: Vec<u32>,
+    begin_suppress_tokens: Vec<u32>,
+    max_target_positions: usize,
+    device: Device,
+    rng: Isaac64Rng,
+}
+
+impl WhisperModel {
+    pub fn new(
+        cfg: &WhisperConfig,
+        generation_cfg: Option<WhisperGenerationConfig>,
+        tokenizer: Arc<Tokenizer>,
+        vb: ShardedVarBuilder,
+        device: &Device,
+    ) -> anyhow::Result<Self> {
+        let encoder = WhisperEncoder::new(&cfg.encoder, vb.pp("model.encoder"))?;
+        let decoder = TextDecoder::new(cfg, vb.pp("model.decoder"))?;
+        let special = SpecialTokens::new(&tokenizer)?;
+
+        let generation_cfg = generation_cfg.unwrap_or_default();
+        let mut suppress_tokens = generation_cfg
+            .suppress_tokens
+            .unwrap_or_else(|| cfg.suppress_tokens.clone());
+        // These are never part of the transcript itself.
+        suppress_tokens.extend(
+            [
+                Some(special.sot),
+                special.sot_prev,
+                special.transcribe,
+                special.translate,
+            ]
+            .into_iter()
+            .flatten(),
+        );
+        let begin_suppress_tokens = generation_cfg
+            .begin_suppress_tokens
+            .unwrap_or_else(|| cfg.begin_suppress_tokens.clone());
+
+        Ok(Self {
+            encoder,
+            decoder,
+            feature_extractor: WhisperFeatureExtractor::new(cfg.encoder.num_mel_bins),
+            tokenizer,
+            special,
+            suppress_tokens,
+            begin_suppress_tokens,
+            max_target_positions: cfg.max_target_positions,
+            device: device.clone(),
+            rng: Isaac64Rng::seed_from_u64(SEED),
+        })
+    }
+
+    fn is_multilingual(&self) -> bool {
+        !self.special.languages.is_empty()
+    }
+
+    fn encode_text(&self, text: &str) -> Result<Vec<u32>> {
+        Ok(self
+            .tokenizer
+            .encode(text, false)
+            .map_err(candle_core::Error::msg)?
+            .get_ids()
+            .to_vec())
+    }
+
+    fn decode_text(&self, tokens: &[u32]) -> Result<String> {
+        let text_tokens = tokens
+            .iter()
+            .copied()
+            .filter(|t| *t < self.special.eot)
+            .collect::<Vec<_>>();
+        self.tokenizer
+            .decode(&text_tokens, true)
+            .map_err(candle_core::Error::msg)
+    }
+
+    /// Index into the known languages of a language code (`en`) or name (`english`).
+    fn find_language(&self, language: &str) -> Result<usize> {
+        let language = language.trim().to_lowercase();
+        self.special
+            .languages
+            .iter()
+            .position(|(_, code, name)| *code == language || *name == language)
+            .ok_or_else(|| {
+                candle_core::Error::Msg(format!(
+                    "Unsupported language `{language}` for this Whisper model."
+                ))
+            })
+    }
+
+    /// Encode up to 30 seconds of audio. Returns (1, n_audio_ctx, d_model).
+    fn encode_audio(&self, samples: &[f32]) -> Result<Tensor> {
+        let features = Tensor::from_vec(
+            self.feature_extractor.log_mel_spectrogram(samples),
+            (1, self.feature_extractor.n_mels(), N_FRAMES),
+            &self.device,
+        )?;
+        self.encoder.forward(&features)
+    }
+
+    /// Pick the most likely language token after `<|startoftranscript|>`.
+    fn detect_language(&mut self, audio_features: &Tensor) -> Result<usize> {
+        self.decoder.reset();
+        let tokens = Tensor::new(&[[self.special.sot]], &self.device)?;
+        let logits = self
+            .decoder
+            .forward(&tokens, audio_features, 0)?
+            .to_vec1::<f32>()?;
+        let (language, _) = self
+            .special
+            .languages
+            .iter()
+            .enumerate()
+            .map(|(i, (token, _, _))| (i, logits[*token as usize]))
+            .fold((0, f32::NEG_INFINITY), |best, cur| {
+                if cur.1 > best.1 {
+                    cur
+                } else {
+                    best
+                }
+            });
+        Ok(language)
+    }
+
+    /// The decoder prompt: previous text (if any), then the start of transcript, language, task
+    /// and timestamp mode tokens.
+    fn build_prompt(
+        &self,
+        context: &[u32],
+        language: Option<usize>,
+        params: &TranscriptionParams,
+    ) -> Vec<u32> {
+        let mut prompt = Vec::new();
+        if let (Some(sot_prev), false) = (self.special.sot_prev, context.is_empty()) {
+            let max_context = self.max_target_positions / 2 - 1;
+            prompt.push(sot_prev);
+            prompt.extend(&context[context.len().saturating_sub(max_context)..]);
+        }
+        prompt.push(self.special.sot);
+        if let Some(language) = language {
+            prompt.push(self.special.languages[language].0);
+            let task = match params.task {
+                TranscriptionTask::Transcribe => self.special.transcribe,
+                TranscriptionTask::Translate => self.special.translate,
+            };
+            prompt.extend(task);
+        }
+        if !params.timestamps {
+            prompt.push(self.special.no_timestamps);
+        }
+        prompt
+    }
+
+    /// Restrict which timestamp tokens may follow `sampled`, the tokens decoded so far in this
+    /// window: timestamps come in pairs (except at the start), never decrease, the first token is
+    /// a timestamp of at most 1 second, and a timestamp is forced when timestamps are more likely
+    /// than any single text token.
+    fn apply_timestamp_rules(&self, logits: &mut [f32], sampled: &[u32]) {
+        let ts_begin = self.special.timestamp_begin as usize;
+        logits[self.special.no_timestamps as usize] = f32::NEG_INFINITY;
+
+        let is_ts = |t: &u32| *t >= self.special.timestamp_begin;
+        let last_was_ts = sampled.last().is_some_and(is_ts);
+        let penultimate_was_ts = sampled.len() < 2 || is_ts(&sampled[sampled.len() - 2]);
+        if last_was_ts {
+            if penultimate_was_ts {
+                logits[ts_begin..].fill(f32::NEG_INFINITY);
+            } else {
+                logits[..self.special.eot as usize].fill(f32::NEG_INFINITY);
+            }
+        }
+
+        if let Some(last_ts) = sampled.iter().rev().find(|t| is_ts(*t)) {
+            let min_ts = if last_was_ts && !penultimate_was_ts {
+                *last_ts
+            } else {
+                last_ts + 1
+            };
+            logits[ts_begin..(min_ts as usize).min(logits.len())].fill(f32::NEG_INFINITY);
+        }
+
+        if sampled.is_empty() {
+            logits[..ts_begin].fill(f32::NEG_INFINITY);
+            let max_initial = ts_begin + MAX_INITIAL_TIMESTAMP_INDEX as usize + 1;
+            if max_initial < logits.len() {
+                logits[max_initial..].fill(f32::NEG_INFINITY);
+            }
+        }
+
+        let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let logsumexp = |xs: &[f32]| max + xs.iter().map(|x| (x - max).exp()).sum::<f32>().ln();
+        let all = logsumexp(logits);
+        let timestamp_logprob = logsumexp(&logits[ts_begin..]) - all;
+        let max_text_logprob = logits[..ts_begin]
+            .iter()
+            .copied()
+            .fold(f32::NEG_INFINITY, f32::max)
+            - all;
+        if timestamp_logprob > max_text_logprob {
+            logits[..ts_begin].fill(f32::NEG_INFINITY);
+        }
+    }
+
+    fn sample(&mut self, logits: &[f32], temperature: f64) -> Result<u32> {
+        if temperature <= 0. {
+            let (token, _) =
+                logits
+                    .iter()
+                    .enumerate()
+                    .fold((0, f32::NEG_INFINITY), |best, (i, x)| {
+                        if *x > best.1 {
+                            (i, *x)
+                        } else {
+                            best
+                        }
+                    });
+            return Ok(token as u32);
+        }
+        let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let weights = logits
+            .iter()
+            .map(|x| ((x - max) as f64 / temperature).exp())
+            .collect::<Vec<_>>();
+        let distr = WeightedIndex::new(&weights).map_err(candle_core::Error::msg)?;
+        Ok(distr.sample(&mut self.rng) as u32)
+    }
+
+    /// Decode the tokens of one window after `prompt`, excluding `<|endoftext|>`.
+    fn decode_window(
+        &mut self,
+        audio_features: &Tensor,
+        prompt: &[u32],
+        params: &TranscriptionParams,
+    ) -> Result<Vec<u32>> {
+        self.decoder.reset();
+        let sample_len =
+            (self.max_target_positions / 2).min(self.max_target_positions - prompt.len());
+
+        let mut tokens = prompt.to_vec();
+        for step in 0..sample_len {
+            let (input, seqlen_offset) = if step == 0 {
+                (&tokens[..], 0)
+            } else {
+                (&tokens[tokens.len() - 1..], tokens.len() - 1)
+            };
+            let input = Tensor::new(input, &self.device)?.unsqueeze(0)?;
+            let mut logits = self
+                .decoder
+                .forward(&input, audio_features, seqlen_offset)?
+                .to_vec1::<f32>()?;
+
+            for token in &self.suppress_tokens {
+                if let Some(logit) = logits.get_mut(*token as usize) {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+            if step == 0 {
+                for token in &self.begin_suppress_tokens {
+                    if let Some(logit) = logits.get_mut(*token as usize) {
+                        *logit = f32::NEG_INFINITY;
+                    }
+                }
+            }
+            if params.timestamps {
+                self.apply_timestamp_rules(&mut logits, &tokens[prompt.len()..]);
+            }
+
+            let next = self.sample(&logits, params.temperature)?;
+            if next == self.special.eot {
+                break;
+            }
+            tokens.push(next);
+        }
+        Ok(tokens[prompt.len()..].to_vec())
+    }
+
+    /// Split the tokens of a window into `(start, end, tokens)` segments, with times in seconds
+    /// from the start of the window. Also returns how many samples to advance: up to the last
+    /// complete segment if the window ended mid-segment, otherwise the whole window.
+    fn split_segments(
+        &self,
+        tokens: &[u32],
+        window_samples: usize,
+    ) -> (Vec<(f64, f64, Vec<u32>)>, usize) {
+        let ts_begin = self.special.timestamp_begin;
+        let is_ts = |t: u32| t >= ts_begin;
+        let to_secs = |t: u32| t.saturating_sub(ts_begin) as f64 * 0.02;
+
+        let n = tokens.len();
+        let single_timestamp_ending = n >= 2 && !is_ts(tokens[n - 2]) && is_ts(tokens[n - 1]);
+        let mut slices = (1..n)
+            .filter(|&i| is_ts(tokens[i - 1]) && is_ts(tokens[i]))
+            .collect::<Vec<_>>();
+
+        if slices.is_empty() {
+            let end = tokens
+                .iter()
+                .rev()
+                .find(|t| is_ts(**t) && **t != ts_begin)
+                .map(|t| to_secs(*t))
+                .unwrap_or(window_samples as f64 / SAMPLE_RATE as f64);
+            return (vec![(0., end, tokens.to_vec())], window_samples);
+        }
+
+        if single_timestamp_ending {
+            slices.push(n);
+        }
+        let mut segments = Vec::new();
+        let mut last_slice = 0;
+        for slice in slices {
+            let sliced = &tokens[last_slice..slice];
+            segments.push((
+                to_secs(sliced[0]),
+                to_secs(sliced[sliced.len() - 1]),
+                sliced.to_vec(),
+            ));
+            last_slice = slice;
+        }
+        let advance = if single_timestamp_ending {
+            window_samples
+        } else {
+            (tokens[last_slice - 1] - ts_begin) as usize * TIMESTAMP_SAMPLES
+        };
+        // Always make progress, even if the model only predicted `<|0.00|>`.
+        let advance = if advance == 0 {
+            window_samples
+        } else {
+            advance
+        };
+        (segments, advance)
+    }
+}
+
+impl SpeechModel for WhisperModel {
+    fn transcribe(
+        &mut self,
+        audio: &AudioInput,
+        params: &TranscriptionParams,
+    ) -> Result<TranscriptionResponse> {
+        let samples = audio.resample(SAMPLE_RATE).samples;
+        if samples.is_empty() {
+            candle_core::bail!("Received empty audio.");
+        }
+        let duration = samples.len() as f64 / SAMPLE_RATE as f64;
+
+        let mut language = match &params.language {
+            Some(language) if self.is_multilingual() => Some(self.find_language(language)?),
+            _ => None,
+        };
+        let mut context = match &params.prompt {
+            Some(prompt) => self.encode_text(&format!(" {}", prompt.trim()))?,
+            None => Vec::new(),
+        };
+
+        let mut segments = Vec::new();
+        let mut seek = 0;
+        while seek < samples.len() {
+            let window = &samples[seek..(seek + N_SAMPLES).min(samples.len())];
+            let audio_features = self.encode_audio(window)?;
+            if language.is_none() && self.is_multilingual() {
+                language = Some(self.detect_language(&audio_features)?);
+            }
+
+            let prompt = self.build_prompt(&context, language, params);
+            let tokens = self.decode_window(&audio_features, &prompt, params)?;
+
+            let time_offset = seek as f64 / SAMPLE_RATE as f64;
+            let (window_segments, advance) = self.split_segments(&tokens, window.len());
+            for (start, end, segment_tokens) in window_segments {
+                let text = self.decode_text(&segment_tokens)?;
+                if text.trim().is_empty() {
+                    continue;
+                }
+                segments.push(TranscriptionSegment {
+                    id: segments.len(),
+                    start: time_offset + start,
+                    end: (time_offset + end).min(duration),
+                    text,
+                    tokens: segment_tokens,
+                });
+            }
+
+            context.extend(tokens);
+            seek += advance;
+        }
+
+        Ok(TranscriptionResponse {
+            task: params.task.to_string(),
+            language: language
+                .map(|i| self.special.languages[i].2)
+                .unwrap_or("english")
+                .to_string(),
+            duration,
+            text: segments
+                .iter()
+                .map(|segment| segment.text.as_str())
+                .collect::<String>()
+                .trim()
+                .to_string(),
+            segments,
+        })
+    }
+    fn device(&self) -> &Device {
+        &self.device
+    }
+    fn max_seq_len(&self) -> usize {
+        self.max_target_positions
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use indexmap::IndexMap;
 
 use serde_json::Value;
 
@@ -25,7 +25,8 @@ pub enum ToolChoice {
 pub struct Function {
     pub description: Option<String>,
     pub name: String,
-    pub parameters: Option<HashMap<String, Value>>,
+    /// JSON schema of the parameters. Key order is preserved so the rendered prompt matches the request.
+    pub parameters: Option<IndexMap<String, Value>>,
 }
 
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
@@ -8,6 +8,11 @@ use serde::Deserialize;
 
 use crate::parse_isq_value;
 
+mod search;
+
+pub use search::TopologySearch;
+pub(crate) use search::{solve_topology, topology_yaml, IsqCandidateCost};
+
 const DEVICE_PATTERN: &str = r"^(cpu|cuda\[(\d+)\]|metal\[(\d+)\])$";
 
 #[derive(Deserialize)]
@@ -0,0 +1,211 @@
+use std::{fmt::Write, path::PathBuf};
+
+use mistralrs_quant::IsqType;
+
+use super::{LayerTopology, Topology};
+
+/// Search for a per-layer ISQ topology which fits in a memory budget.
+///
+/// Each decoder layer is quantized with every candidate ISQ type and the imatrix-weighted
+/// reconstruction error is measured. The cheapest assignment is then greedily upgraded, preferring
+/// the layers where the error improves most per byte, until the budget is exhausted.
+#[derive(Clone, Debug)]
+pub struct TopologySearch {
+    /// Budget for the decoder layer weights, in bytes.
+    pub budget_bytes: usize,
+    /// ISQ types which may be assigned to a layer.
+    pub candidates: Vec<IsqType>,
+    /// Where to write the resulting topology YAML file.
+    pub output: PathBuf,
+}
+
+impl TopologySearch {
+    pub fn new(budget_bytes: usize, candidates: Vec<IsqType>, output: PathBuf) -> Self {
+        Self {
+            budget_bytes,
+            candidates,
+            output,
+        }
+    }
+
+    /// Check that the candidates can be measured without calibration-specific data.
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        if self.candidates.is_empty() {
+            anyhow::bail!("Topology search requires at least one candidate ISQ type.");
+        }
+        if let Some(isq) = self
+            .candidates
+            .iter()
+            .find(|isq| matches!(isq, IsqType::GPTQ4 | IsqType::AWQ4))
+        {
+            anyhow::bail!("ISQ type {isq:?} cannot be used as a topology search candidate.");
+        }
+        Ok(())
+    }
+}
+
+/// Cost of quantizing one decoder layer with an ISQ type.
+#[derive(Clone, Debug)]
+pub struct IsqCandidateCost {
+    pub isq: IsqType,
+    /// Relative imatrix-weighted reconstruction error of the layer weights.
+    pub error: f64,
+    /// Size of the quantized layer weights.
+    pub bytes: usize,
+}
+
+/// Choose an ISQ type for each layer such that the total size fits in `budget_bytes`, minimizing the
+/// summed error. `layers` holds the cost of every candidate, per layer.
+pub(crate) fn solve_topology(
+    layers: &[Vec<IsqCandidateCost>],
+    budget_bytes: usize,
+) -> anyhow::Result<Vec<IsqType>> {
+    // Start with the smallest candidate for every layer
+    let mut chosen = Vec::with_capacity(layers.len());
+    for (i, costs) in layers.iter().enumerate() {
+        let Some((smallest, _)) = costs
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.bytes.cmp(&b.bytes).then(b.error.total_cmp(&a.error)))
+        else {
+            anyhow::bail!("Layer {i} has no topology search candidates.");
+        };
+        chosen.push(smallest);
+    }
+    let mut total = layers
+        .iter()
+        .zip(&chosen)
+        .map(|(costs, i)| costs[*i].bytes)
+        .sum::<usize>();
+    if total > budget_bytes {
+        anyhow::bail!(
+            "The smallest topology needs {:.2} GB, which exceeds the budget of {:.2} GB.",
+            total as f64 / 1e9,
+            budget_bytes as f64 / 1e9
+        );
+    }
+
+    // Repeatedly take the upgrade which removes the most error per added byte
+    loop {
+        let mut best: Option<(usize, usize, f64)> = None;
+        for (layer, costs) in layers.iter().enumerate() {
+            let current = &costs[chosen[layer]];
+            for (candidate, cost) in costs.iter().enumerate() {
+                if cost.error >= current.error || total - current.bytes + cost.bytes > budget_bytes
+                {
+                    continue;
+                }
+                let gain = (current.error - cost.error)
+                    / (cost.bytes.saturating_sub(current.bytes).max(1)) as f64;
+                if best.is_none_or(|(_, _, best_gain)| gain > best_gain) {
+                    best = Some((layer, candidate, gain));
+                }
+            }
+        }
+        let Some((layer, candidate, _)) = best else {
+            break;
+        };
+        total = total - layers[layer][chosen[layer]].bytes + layers[layer][candidate].bytes;
+        chosen[layer] = candidate;
+    }
+
+    Ok(layers
+        .iter()
+        .zip(chosen)
+        .map(|(costs, i)| costs[i].isq)
+        .collect())
+}
+
+/// Name of the ISQ type as accepted by a topology file.
+fn isq_name(isq: IsqType) -> String {
+    match isq {
+        IsqType::F8E4M3 => "FP8".to_string(),
+        other => format!("{other:?}"),
+    }
+}
+
+/// Serialize a per-layer ISQ assignment as a topology YAML file, merging consecutive layers with
+/// the same ISQ type into one range.
+pub(crate) fn topology_yaml(assignment: &[IsqType]) -> String {
+    let mut yaml = String::new();
+    let mut start = 0;
+    for group in assignment.chunk_by(|a, b| a == b) {
+        let end = start + group.len();
+        writeln!(yaml, "{start}-{end}:").unwrap();
+        writeln!(yaml, "  isq: {}", isq_name(group[0])).unwrap();
+        start = end;
+    }
+    yaml
+}
+
+impl From<&[IsqType]> for Topology {
+    fn from(assignment: &[IsqType]) -> Self {
+        Topology(
+            assignment
+                .iter()
+                .map(|isq| {
+                    Some(LayerTopology {
+                        isq: Some(*isq),
+                        device: None,
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mistralrs_quant::IsqType;
+
+    use super::{solve_topology, topology_yaml, IsqCandidateCost};
+    use crate::Topology;
+
+    fn costs(errors: [f64; 3]) -> Vec<IsqCandidateCost> {
+        [IsqType::Q4K, IsqType::Q6K, IsqType::Q8_0]
+            .into_iter()
+            .zip(errors)
+            .zip([4, 6, 8])
+            .map(|((isq, error), bytes)| IsqCandidateCost { isq, error, bytes })
+            .collect()
+    }
+
+    #[test]
+    fn upgrades_most_sensitive_layers() {
+        let layers = vec![
+            costs([0.01, 0.005, 0.001]),
+            costs([0.5, 0.1, 0.01]),
+            costs([0.02, 0.01, 0.002]),
+        ];
+        let assignment = solve_topology(&layers, 16).unwrap();
+        assert_eq!(assignment, vec![IsqType::Q4K, IsqType::Q8_0, IsqType::Q4K]);
+
+        let assignment = solve_topology(&layers, 24).unwrap();
+        assert_eq!(assignment, vec![IsqType::Q8_0; 3]);
+
+        assert!(solve_topology(&layers, 11).is_err());
+    }
+
+    #[test]
+    fn yaml_round_trips() {
+        let assignment = [
+            IsqType::Q4K,
+            IsqType::Q4K,
+            IsqType::Q8_0,
+            IsqType::F8E4M3,
+            IsqType::Q4K,
+        ];
+        let yaml = topology_yaml(&assignment);
+        assert_eq!(
+            yaml,
+            "0-2:\n  isq: Q4K\n2-3:\n  isq: Q8_0\n3-4:\n  isq: FP8\n4-5:\n  isq: Q4K\n"
+        );
+        let topology = Topology::from_str(&yaml).unwrap();
+        let parsed = topology
+            .0
+            .iter()
+            .map(|layer| layer.as_ref().unwrap().isq.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(parsed, assignment);
+    }
+}
@@ -29,6 +29,31 @@ impl<'a, R: std::io::Seek + std::io::Read> From<&Content<'a, R>> for ContentConf
     fn from(value: &Content<'a, R>) -> Self {
         let metadata = value.get_metadata();
         let arch = metadata["general.architecture"].to_string().unwrap();
+        if arch == "deepseek2" {
+            // MLA expands the latent KV to one key and value per attention head, and values are
+            // padded to the key size in the paged cache.
+            let num_attn_heads = metadata[&format!("{arch}.attention.head_count")]
+                .to_u64()
+                .unwrap() as usize;
+            let key_length = metadata
+                .get(&format!("{arch}.attention.key_length_mla"))
+                .unwrap_or_else(|| &metadata[&format!("{arch}.attention.key_length")])
+                .to_u64()
+                .unwrap() as usize;
+            return Self {
+                max_seq_len: metadata[&format!("{arch}.context_length")]
+                    .to_u64()
+                    .unwrap() as usize,
+                hidden_size: metadata[&format!("{arch}.embedding_length")]
+                    .to_u64()
+                    .unwrap() as usize,
+                num_attn_heads,
+                num_kv_heads: num_attn_heads,
+                num_layers: metadata[&format!("{arch}.block_count")].to_u64().unwrap() as usize,
+                key_length: Some(key_length),
+                value_length: Some(key_length),
+            };
+        }
         Self {
             max_seq_len: metadata[&format!("{arch}.context_length")]
                 .to_u64()
@@ -263,7 +288,10 @@ impl DeviceMappedModelLoader for GgufDeviceMapLoaderInner<'_, '_> {
         _weight_pack_factor: usize,
     ) -> Result<usize> {
         let size_in_bytes = match self.arch {
-            GGUFArchitecture::Llama => {
+            GGUFArchitecture::Llama
+            | GGUFArchitecture::Gemma
+            | GGUFArchitecture::Gemma2
+            | GGUFArchitecture::Deepseek2 => {
                 let token_embd = tensor_info_size_in_bytes!(
                     self.model.tensor_info("token_embd.weight")?,
                     DType::F32
@@ -312,7 +340,7 @@ impl DeviceMappedModelLoader for GgufDeviceMapLoaderInner<'_, '_> {
                 };
                 token_embd + output_norm + output
             }
-            GGUFArchitecture::Qwen2 => {
+            GGUFArchitecture::Qwen2 | GGUFArchitecture::Qwen2vl => {
                 let token_embd = tensor_info_size_in_bytes!(
                     self.model.tensor_info("token_embd.weight")?,
                     DType::F32
@@ -384,7 +412,7 @@ impl DeviceMappedModelLoader for GgufDeviceMapLoaderInner<'_, '_> {
                 let n_expert = self
                     .model
                     .get_metadata()
-                    .get("expert_count")
+                    .get(&format!("{}.expert_count", self.arch))
                     .map(|x| x.to_u64().unwrap() as usize)
                     .unwrap_or(0);
                 let moe_or_mlp = if n_expert <= 1 {
@@ -476,7 +504,7 @@ impl DeviceMappedModelLoader for GgufDeviceMapLoaderInner<'_, '_> {
 
                 attn_norm + ffn_norm + attn_qkv + attn_output + ffn_up + ffn_down
             }
-            GGUFArchitecture::Qwen2 => {
+            GGUFArchitecture::Qwen2 | GGUFArchitecture::Qwen2vl => {
                 let attn_norm = tensor_info_size_in_bytes!(
                     self.model.tensor_info("blk.0.attn_norm.weight")?,
                     DType::F32
@@ -563,6 +591,77 @@ impl DeviceMappedModelLoader for GgufDeviceMapLoaderInner<'_, '_> {
 
                 attn_norm + ffn_norm + attn_q + attn_k + attn_v + attn_output + ffn_up + ffn_down
             }
+            GGUFArchitecture::Gemma | GGUFArchitecture::Gemma2 => {
+                let mut norms = vec!["attn_norm", "ffn_norm"];
+                if matches!(self.arch, GGUFArchitecture::Gemma2) {
+                    norms.extend(["post_attention_norm", "post_ffw_norm"]);
+                }
+                let mut size = 0;
+                for norm in norms {
+                    size += tensor_info_size_in_bytes!(
+                        self.model.tensor_info(&format!("blk.0.{norm}.weight"))?,
+                        DType::F32
+                    );
+                }
+                for weight in [
+                    "attn_q",
+                    "attn_k",
+                    "attn_v",
+                    "attn_output",
+                    "ffn_gate",
+                    "ffn_up",
+                    "ffn_down",
+                ] {
+                    size += tensor_info_size_in_bytes!(self
+                        .model
+                        .tensor_info(&format!("blk.0.{weight}.weight"))?);
+                }
+                size
+            }
+            GGUFArchitecture::Deepseek2 => {
+                // Leading layers are dense and the rest are MoE, and the attention tensors depend on
+                // the conversion, so add up whatever each layer contains.
+                let mut sizes = Vec::new();
+                for layer_idx in 0..self.num_layers(config)? {
+                    let mut size = 0;
+                    for norm in ["attn_norm", "ffn_norm", "attn_q_a_norm", "attn_kv_a_norm"] {
+                        let name = format!("blk.{layer_idx}.{norm}.weight");
+                        if self.model.has_tensor(&name) {
+                            size += tensor_info_size_in_bytes!(
+                                self.model.tensor_info(&name)?,
+                                DType::F32
+                            );
+                        }
+                    }
+                    for weight in [
+                        "attn_q",
+                        "attn_q_a",
+                        "attn_q_b",
+                        "attn_kv_a_mqa",
+                        "attn_kv_b",
+                        "attn_k_b",
+                        "attn_v_b",
+                        "attn_output",
+                        "ffn_gate",
+                        "ffn_up",
+                        "ffn_down",
+                        "ffn_gate_inp",
+                        "ffn_gate_exps",
+                        "ffn_up_exps",
+                        "ffn_down_exps",
+                        "ffn_gate_shexp",
+                        "ffn_up_shexp",
+                        "ffn_down_shexp",
+                    ] {
+                        let name = format!("blk.{layer_idx}.{weight}.weight");
+                        if self.model.has_tensor(&name) {
+                            size += tensor_info_size_in_bytes!(self.model.tensor_info(&name)?);
+                        }
+                    }
+                    sizes.push(size);
+                }
+                return Ok(sizes);
+            }
             _ => unimplemented!(),
         };
         Ok(vec![size_in_bytes; self.num_layers(config)?])
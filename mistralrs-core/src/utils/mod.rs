@@ -117,6 +117,7 @@ macro_rules! handle_pipeline_forward_error {
                                 tool_calls: Vec::new(),
                             },
                             logprobs: None,
+                            conversation_tokens: None,
                         };
                         seq.add_choice_to_group(choice);
                     } else {
@@ -4,7 +4,7 @@ use std::{
 };
 
 use candle_core::{quantized::QMatMul, Tensor};
-use candle_nn::{Conv2d, Embedding, LayerNorm, Linear};
+use candle_nn::{Conv1d, Conv2d, Embedding, LayerNorm, Linear};
 use itertools::Itertools;
 use mistralrs_quant::QuantMethod;
 
@@ -55,6 +55,17 @@ impl ToTensors for Linear {
     }
 }
 
+impl ToTensors for Conv1d {
+    fn to_tensors(&self) -> HashMap<String, Tensor> {
+        let mut map = HashMap::new();
+        map.insert("weight".to_string(), self.weight().clone());
+        if let Some(bias) = self.bias() {
+            map.insert("bias".to_string(), bias.clone());
+        }
+        map
+    }
+}
+
 impl ToTensors for Conv2d {
     fn to_tensors(&self) -> HashMap<String, Tensor> {
         let mut map = HashMap::new();
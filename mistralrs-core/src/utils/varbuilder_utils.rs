@@ -35,6 +35,23 @@ impl TensorLoaderBackend for SafetensorBackend {
     }
 }
 
+/// The residual tensors of a self-contained UQFF file, skipping the serialized ISQ layers.
+struct UqffBackend(MmapedSafetensors);
+
+impl TensorLoaderBackend for UqffBackend {
+    fn get_names(&self) -> Vec<String> {
+        self.0
+            .tensors()
+            .into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| name.parse::<usize>().is_err())
+            .collect::<Vec<_>>()
+    }
+    fn load_name(&self, name: &str, device: &Device, dtype: Option<DType>) -> Result<Tensor> {
+        self.0.load(name, device, dtype)
+    }
+}
+
 struct PickleBackend(PthTensors);
 
 impl TensorLoaderBackend for PickleBackend {
@@ -237,6 +254,7 @@ trait LoadTensors {
             "safetensors" => Box::new(SafetensorBackend(unsafe {
                 MmapedSafetensors::new(path)?
             })),
+            "uqff" => Box::new(UqffBackend(unsafe { MmapedSafetensors::new(path)? })),
             "pth" | "pt" | "bin" => Box::new(PickleBackend(
                 candle_core::pickle::PthTensors::new(path, None)?
             )),
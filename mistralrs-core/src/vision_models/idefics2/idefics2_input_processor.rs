@@ -1,6 +1,6 @@
 #![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
 
-use std::{any::Any, num::NonZeroUsize, sync::Arc};
+use std::{any::Any, collections::HashMap, num::NonZeroUsize, sync::Arc};
 
 use candle_core::{Device, Result, Tensor};
 use image::{DynamicImage, GenericImageView};
@@ -59,20 +59,25 @@ impl Idefics2Processor {
 }
 
 impl Processor for Idefics2Processor {
+    #[allow(clippy::too_many_arguments)]
     fn process(
         &self,
         pipeline: &dyn Pipeline,
         messages: Vec<IndexMap<String, MessageContent>>,
         add_generation_prompt: bool,
+        continue_final_message: bool,
         add_special_tokens: bool,
         tools: Vec<Tool>,
+        chat_template_kwargs: Option<HashMap<String, serde_json::Value>>,
     ) -> anyhow::Result<(Vec<u32>, String)> {
         let mut prompt = apply_chat_template(
             pipeline,
             messages,
             add_generation_prompt,
+            continue_final_message,
             self.template_action(),
             tools,
+            chat_template_kwargs,
         )?;
 
         let mut image_str = format!(
@@ -203,7 +203,7 @@ impl Idefics3Model {
             // Modality proj and perceiver resampling
             let image_hidden_states = self.connector.forward(&image_hidden_states)?;
 
-            if self.text_model.cache().normal().0[0].current_seq_len() == 0 {
+            if self.text_model.cache().normal()?.0[0].current_seq_len() == 0 {
                 self.inputs_merger(
                     input_ids,
                     &self
@@ -128,17 +128,6 @@ impl Model {
         attention_mechanism: AttentionImplementation,
     ) -> Result<Self> {
         let device = normal_loading_metadata.real_device.clone();
-        let dtype = vb.dtype();
-        let clip_config = config.to_clip_config();
-        let mm_projector = MMProjector::new(&vb, config, &device)?;
-        let clip_vision_tower = ClipVisionTower::new(
-            vb.pp("vision_tower.vision_model")
-                .set_device(device.clone()),
-            config.vision_feature_layer,
-            &config.vision_feature_select_strategy,
-            &clip_config,
-        )?;
-
         let llm: Box<dyn LLaVALLM> = match config.text_config.model_type.as_str() {
             "llama" => {
                 let llama_config = config.to_llama_config();
@@ -166,6 +155,27 @@ impl Model {
                 bail!("Unsupported model type: {}", config.text_config.model_type);
             }
         };
+        Self::new_with_llm(config, vb, llm, device)
+    }
+
+    /// Load the vision tower and projector from `vb` around an already loaded language model, for
+    /// example a GGUF model paired with an `mmproj` file.
+    pub(crate) fn new_with_llm(
+        config: &Config,
+        vb: ShardedVarBuilder,
+        llm: Box<dyn LLaVALLM>,
+        device: Device,
+    ) -> Result<Self> {
+        let dtype = vb.dtype();
+        let clip_config = config.to_clip_config();
+        let mm_projector = MMProjector::new(&vb, config, &device)?;
+        let clip_vision_tower = ClipVisionTower::new(
+            vb.pp("vision_tower.vision_model")
+                .set_device(device.clone()),
+            config.vision_feature_layer,
+            &config.vision_feature_select_strategy,
+            &clip_config,
+        )?;
         Ok(Self {
             clip_vision_tower,
             mm_projector,
@@ -371,6 +381,6 @@ impl AnyMoeBaseModelMixin for Model {
         )
     }
     fn amoe_supported(&self) -> bool {
-        true
+        self.llm.amoe_supported()
     }
 }
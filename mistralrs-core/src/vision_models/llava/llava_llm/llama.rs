@@ -579,7 +579,7 @@ impl LLaVALLM for Llama {
         flash_params: &FlashParams,
     ) -> Result<Tensor> {
         let mut x = input_embed;
-        let mut cache = self.kv_cache.full().lock();
+        let mut cache = self.kv_cache.full()?.lock();
         let mask = CausalMasker.make_causal_mask_matrix(
             input_ids,
             metadata
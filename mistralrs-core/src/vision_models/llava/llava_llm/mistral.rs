@@ -568,7 +568,7 @@ impl Model {
         flash_params: &FlashParams,
     ) -> Result<Tensor> {
         let mut xs = input_embeds;
-        let mut cache = self.cache.full().lock();
+        let mut cache = self.cache.full()?.lock();
         let attention_mask = CausalMasker.make_sliding_window_causal_mask_matrix(
             input_ids,
             metadata
@@ -54,6 +54,8 @@ impl OrdinaryRoPE {
 }
 pub(crate) mod llama;
 pub(crate) mod mistral;
+pub(crate) mod quantized_llama;
 
 pub use llama::Llama;
 pub use mistral::Model as Mistral;
+pub(crate) use quantized_llama::QLlama;
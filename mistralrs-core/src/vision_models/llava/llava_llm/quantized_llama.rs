@@ -0,0 +1,143 @@
+//! GGUF LLaMA language model for LLaVA models loaded with an `mmproj` projector file.
+
+use std::sync::Arc;
+
+use candle_core::{DType, Device, Result, Tensor};
+use mistralrs_quant::QuantMethod;
+
+use crate::{
+    amoe::AnyMoeBaseModelMixin,
+    device_map::DeviceMapper,
+    models::quantized_llama::ModelWeights,
+    paged_attention::{ModelConfigLike, ModelConfigMetadata},
+    pipeline::{
+        text_models_inputs_processor::{FlashParams, PagedAttentionInputMetadata},
+        EitherCache, IsqModel, NormalModel,
+    },
+};
+
+use super::LLaVALLM;
+
+pub(crate) struct QLlama {
+    model: ModelWeights,
+    mapper: Box<dyn DeviceMapper + Send + Sync>,
+    cfg: ModelConfigMetadata,
+    embed_dtype: DType,
+}
+
+impl QLlama {
+    /// `embed_dtype` is the dtype of the image features which are merged with the text embeddings.
+    pub fn new(
+        model: ModelWeights,
+        mapper: Box<dyn DeviceMapper + Send + Sync>,
+        cfg: &dyn ModelConfigLike,
+        sliding_window: Option<usize>,
+        embed_dtype: DType,
+    ) -> Self {
+        Self {
+            model,
+            mapper,
+            cfg: ModelConfigMetadata {
+                max_seq_len: cfg.max_seq_len(),
+                num_layers: cfg.num_layers(),
+                hidden_size: cfg.hidden_size(),
+                num_kv_heads: cfg.num_kv_heads(),
+                num_attn_heads: cfg.num_attn_heads(),
+                sliding_window,
+                k_head_dim: cfg.k_head_dim(),
+                v_head_dim: cfg.v_head_dim(),
+            },
+            embed_dtype,
+        }
+    }
+}
+
+impl IsqModel for QLlama {
+    fn get_layers(
+        &mut self,
+    ) -> (
+        Vec<(&mut Arc<dyn QuantMethod>, Option<usize>)>,
+        &dyn DeviceMapper,
+    ) {
+        // The GGUF weights are already quantized.
+        (Vec::new(), &*self.mapper)
+    }
+
+    fn residual_tensors(&self) -> Vec<(String, Tensor)> {
+        Vec::new()
+    }
+}
+
+impl LLaVALLM for QLlama {
+    fn embed(&self, input_ids: &Tensor) -> Result<Tensor> {
+        self.model.embed(input_ids)?.to_dtype(self.embed_dtype)
+    }
+    fn forward_input_embed(
+        &self,
+        input_ids: &Tensor,
+        input_embed: Tensor,
+        seqlen_offsets: &[usize],
+        context_lens: Vec<(usize, usize)>,
+        metadata: Option<(Vec<(Tensor, Tensor)>, &PagedAttentionInputMetadata)>,
+        _flash_params: &FlashParams,
+    ) -> Result<Tensor> {
+        // The quantized model runs its residual stream in f32, like its own embeddings.
+        self.model.forward_input_embed(
+            input_ids,
+            input_embed.to_dtype(DType::F32)?,
+            seqlen_offsets,
+            context_lens,
+            metadata,
+        )
+    }
+}
+
+impl NormalModel for QLlama {
+    fn forward(
+        &self,
+        input_ids: &Tensor,
+        seqlen_offsets: &[usize],
+        context_lens: Vec<(usize, usize)>,
+        _position_ids: Vec<usize>,
+        metadata: Option<(Vec<(Tensor, Tensor)>, &PagedAttentionInputMetadata)>,
+        _flash_params: &FlashParams,
+    ) -> Result<Tensor> {
+        self.model
+            .forward(input_ids, seqlen_offsets, context_lens, metadata)
+    }
+    fn xlora_forward(
+        &self,
+        _input_ids: &Tensor,
+        _input_ids_full: &Tensor,
+        _seqlen_offsets: &[usize],
+        _seqlen_offsets_full: &[usize],
+        _no_kv_cache: bool,
+        _non_granular_state: &Option<crate::xlora_models::NonGranularState>,
+        _context_lens: Vec<(usize, usize)>,
+        _position_ids: Vec<usize>,
+        _flash_params: &FlashParams,
+        _flash_params_full: &FlashParams,
+    ) -> Result<Tensor> {
+        unimplemented!()
+    }
+    fn cache(&self) -> &EitherCache {
+        &self.model.cache
+    }
+    fn cache_mut(&mut self) -> &mut EitherCache {
+        &mut self.model.cache
+    }
+    fn device(&self) -> &Device {
+        &self.model.device
+    }
+    fn is_xlora(&self) -> bool {
+        false
+    }
+    fn max_seq_len(&self) -> usize {
+        self.model.max_seq_len
+    }
+    fn config(&self) -> &ModelConfigMetadata {
+        &self.cfg
+    }
+}
+
+impl AnyMoeBaseModelMixin for QLlama {}
@@ -0,0 +1,100 @@
+use candle_core::{Result, Tensor};
+use candle_nn::{Linear, Module};
+use mistralrs_quant::ShardedVarBuilder;
+
+use crate::{
+    layers::linear,
+    utils::unvarbuilder::UnVarBuilder,
+    vision_models::whisper::{WhisperEncoder, WhisperEncoderConfig},
+};
+
+/// Number of audio embeddings produced for `num_frames` log-mel frames: the encoder halves the
+/// frame rate, then the embeddings are average pooled by `pool_step`.
+pub(crate) fn num_audio_tokens(num_frames: usize, pool_step: usize) -> usize {
+    let encoder_len = num_frames.saturating_sub(1) / 2 + 1;
+    if encoder_len < pool_step {
+        0
+    } else {
+        (encoder_len - pool_step) / pool_step + 1
+    }
+}
+
+struct MultiModalProjector {
+    linear1: Linear,
+    linear2: Linear,
+}
+
+impl MultiModalProjector {
+    fn new(in_dim: usize, out_dim: usize, vb: ShardedVarBuilder) -> Result<Self> {
+        Ok(Self {
+            linear1: linear(in_dim, out_dim, vb.pp("linear1"))?,
+            linear2: linear(out_dim, out_dim, vb.pp("linear2"))?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        xs.apply(&self.linear1)?.relu()?.apply(&self.linear2)
+    }
+
+    fn residual_tensors(&self) -> Vec<(String, Tensor)> {
+        let uvb = UnVarBuilder::new();
+
+        uvb.pp("linear1").add(&self.linear1);
+        uvb.pp("linear2").add(&self.linear2);
+
+        uvb.to_safetensors()
+    }
+}
+
+/// The audio tower of MiniCPM-o: a Whisper encoder (`apm`), a projection into the LLM embedding
+/// space and average pooling over time.
+pub(crate) struct AudioEncoder {
+    apm: WhisperEncoder,
+    audio_projection_layer: MultiModalProjector,
+    pool_step: usize,
+}
+
+impl AudioEncoder {
+    pub(crate) fn new(
+        cfg: &WhisperEncoderConfig,
+        hidden_size: usize,
+        pool_step: usize,
+        vb: ShardedVarBuilder,
+    ) -> Result<Self> {
+        let apm = WhisperEncoder::new(cfg, vb.pp("apm"))?;
+        let audio_projection_layer = MultiModalProjector::new(
+            cfg.encoder_ffn_dim / 4,
+            hidden_size,
+            vb.pp("audio_projection_layer"),
+        )?;
+        Ok(Self {
+            apm,
+            audio_projection_layer,
+            pool_step,
+        })
+    }
+
+    /// `features`: (n_mels, frames) log-mel features of one clip of at most 30 seconds.
+    /// Returns (num_audio_tokens(frames), hidden_size).
+    pub(crate) fn forward(&self, features: &Tensor) -> Result<Tensor> {
+        let xs = self.apm.forward(&features.unsqueeze(0)?)?;
+        let xs = self.audio_projection_layer.forward(&xs)?.squeeze(0)?;
+
+        // AvgPool1d over time, dropping the remainder.
+        let (seq_len, hidden_size) = xs.dims2()?;
+        let pooled_len = seq_len / self.pool_step;
+        xs.narrow(0, 0, pooled_len * self.pool_step)?
+            .reshape((pooled_len, self.pool_step, hidden_size))?
+            .mean(1)
+    }
+
+    pub(crate) fn residual_tensors(&self) -> Vec<(String, Tensor)> {
+        let uvb = UnVarBuilder::new();
+
+        uvb.pp("apm").extend(self.apm.residual_tensors());
+        uvb.pp("audio_projection_layer")
+            .extend(self.audio_projection_layer.residual_tensors());
+
+        uvb.to_safetensors()
+    }
+}
@@ -1,4 +1,11 @@
-use crate::{models::qwen2, vision_models::siglip};
+use crate::{
+    models::qwen2,
+    serde_default_fn,
+    vision_models::{siglip, whisper},
+};
+
+serde_default_fn!(bool, init_audio, true);
+serde_default_fn!(usize, audio_pool_step, 2);
 
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct MiniCpmOConfig {
@@ -7,4 +14,16 @@ pub struct MiniCpmOConfig {
     pub vision_config: siglip::SiglipVisionConfig,
     pub vision_batch_size: usize,
     pub query_num: usize,
+    #[serde(default = "init_audio")]
+    pub init_audio: bool,
+    pub audio_config: Option<whisper::WhisperEncoderConfig>,
+    #[serde(default = "audio_pool_step")]
+    pub audio_pool_step: usize,
+}
+
+impl MiniCpmOConfig {
+    /// The audio encoder config, if this checkpoint includes the audio tower.
+    pub fn audio_config(&self) -> Option<&whisper::WhisperEncoderConfig> {
+        self.audio_config.as_ref().filter(|_| self.init_audio)
+    }
 }
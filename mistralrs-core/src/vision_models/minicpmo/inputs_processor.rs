@@ -2,7 +2,7 @@
 
 use std::{any::Any, num::NonZeroUsize, sync::Arc};
 
-use candle_core::{Device, IndexOp, Result, Tensor};
+use candle_core::{DType, Device, IndexOp, Result, Tensor};
 use image::{imageops::FilterType, DynamicImage, GenericImageView};
 use mistralrs_vision::{ApplyTransforms, Normalize, ToTensor, Transforms};
 use regex::Regex;
@@ -10,6 +10,7 @@ use tokenizers::Tokenizer;
 use tracing::warn;
 
 use crate::{
+    audio::{self, WhisperFeatureExtractor},
     device_map::DeviceMapper,
     pipeline::{
         text_models_inputs_processor::{
@@ -19,6 +20,7 @@ use crate::{
     },
     sequence::Sequence,
     vision_models::ModelInputs,
+    AudioInput,
 };
 
 use crate::vision_models::{
@@ -27,7 +29,7 @@ use crate::vision_models::{
     processor_config::ProcessorConfig,
 };
 
-use super::MiniCpmOSpecificArgs;
+use super::{audio::num_audio_tokens, MiniCpmOConfig, MiniCpmOSpecificArgs};
 
 const DEFAULT_MAX_SLICE_NUMS: usize = 9;
 const DEFAULT_SCALE_RESOLUTION: usize = 448;
@@ -42,23 +44,44 @@ const DEFAULT_SLICE_END_TOKEN: &str = "</slice>";
 const DEFAULT_UNK_TOKEN: &str = "<unk>";
 const DEFAULT_USE_IMAGE_ID: bool = false;
 const DEFAULT_SLICE_MODE: bool = true;
+const AUDIO_START_TOKEN: &str = "<|audio_start|>";
+const AUDIO_END_TOKEN: &str = "<|audio_end|>";
+
+/// Log-mel frontend and pooling step of the audio tower.
+#[derive(Clone)]
+struct AudioPreProcessor {
+    feature_extractor: Arc<WhisperFeatureExtractor>,
+    pool_step: usize,
+}
 
 pub struct MiniCpmOImageProcessor {
     config: PreProcessorConfig,
+    audio: Option<AudioPreProcessor>,
 }
 
 pub struct MiniCpmOProcessor {
     preprocessor_config: PreProcessorConfig,
+    audio: Option<AudioPreProcessor>,
 }
 
 impl MiniCpmOProcessor {
     pub fn new(
+        model_config: &str,
         _config: ProcessorConfig,
         preprocessor_config: PreProcessorConfig,
         _max_edge: Option<u32>,
     ) -> Self {
+        let model_config = serde_json::from_str::<MiniCpmOConfig>(model_config)
+            .expect("Failed to parse model config.");
+        let audio = model_config
+            .audio_config()
+            .map(|audio_cfg| AudioPreProcessor {
+                feature_extractor: Arc::new(WhisperFeatureExtractor::new(audio_cfg.num_mel_bins)),
+                pool_step: model_config.audio_pool_step,
+            });
         Self {
             preprocessor_config,
+            audio,
         }
     }
 }
@@ -67,6 +90,7 @@ impl Processor for MiniCpmOProcessor {
     fn inputs_processor(&self) -> Arc<dyn InputsProcessor> {
         Arc::new(MiniCpmOImageProcessor {
             config: self.preprocessor_config.clone(),
+            audio: self.audio.clone(),
         })
     }
 
@@ -77,12 +101,18 @@ impl Processor for MiniCpmOProcessor {
             DEFAULT_SLICE_START_TOKEN,
             DEFAULT_SLICE_END_TOKEN,
             DEFAULT_UNK_TOKEN,
+            AUDIO_START_TOKEN,
+            AUDIO_END_TOKEN,
         ]
     }
 
     fn template_action(&self) -> MessagesAction {
         MessagesAction::FlattenOnlyText
     }
+
+    fn supports_audios(&self) -> bool {
+        self.audio.is_some()
+    }
 }
 
 impl InputsProcessor for MiniCpmOImageProcessor {
@@ -174,216 +204,275 @@ impl InputsProcessor for MiniCpmOImageProcessor {
         let config = other_config.expect("Need a PreProcessorConfig config.");
         let config: &PreProcessorConfig = config.downcast_ref().expect("Downcast failed.");
 
-        let has_images = input_seqs
-            .iter()
-            .all(|seq| seq.images().is_some_and(|images| !images.is_empty()));
-
-        let (new_input, pixel_values_all, image_bound, tgt_sizes) = if has_images {
-            const IMAGE_TAG: &str = "(<image>./</image>)";
-            const IMAGE_PATTERN: &str = r"\(<image>./</image>\)";
-            const AUDIO_PATTERN: &str = r"\(<audio>./</audio>\)";
-
-            let image_pattern = Regex::new(IMAGE_PATTERN).unwrap();
-            let _audio_pattern = Regex::new(AUDIO_PATTERN).unwrap();
-            let split_pattern = Regex::new(&format!(r"({IMAGE_PATTERN}|{AUDIO_PATTERN})")).unwrap();
-
-            let mut pixel_values_accum = Vec::new();
-            let mut tgt_sizes_accum = Vec::new();
-            let mut input_ids_accum = Vec::new();
-            let mut image_bounds_accum = Vec::new();
-
-            for seq in input_seqs.iter_mut() {
-                let PreprocessedImages {
-                    pixel_values: _,
-                    pixel_attention_mask: _,
-                    image_sizes: _,
-                    num_img_tokens: _,
-                    aspect_ratio_ids: _,
-                    aspect_ratio_mask: _,
-                    num_tiles: _,
-                    image_grid_thw: _,
-                    video_grid_thw: _,
-                    rows: _,
-                    cols: _,
-                    pixel_values_list,
-                    tgt_sizes,
-                    image_sizes_all,
-                } = self
-                    .preprocess(
-                        seq.take_images()
-                            .expect("Need to have images by this point."),
-                        vec![],
-                        config,
-                        device,
-                        (usize::MAX, usize::MAX), // Don't use it here...
-                    )
-                    .expect("Preprocessing failed");
-                let pixel_values_list = pixel_values_list.unwrap();
-                let tgt_sizes = tgt_sizes.unwrap();
-                let image_sizes_all = image_sizes_all.unwrap();
-
-                let text = tokenizer
-                    .decode(seq.get_toks(), false)
-                    .expect("Detokenization failed!");
-
-                let mut text_chunks = {
-                    let mut results = Vec::new();
-                    let mut last_end = 0;
-
-                    for m in split_pattern.find_iter(&text) {
-                        // Anything between last_end and m.start() is unmatched
-                        if m.start() > last_end {
-                            results.push((false, &text[last_end..m.start()]));
-                        }
-                        results.push((true, m.as_str()));
-                        last_end = m.end();
-                    }
-                    // Handle the trailing unmatched part (if any)
-                    if last_end < text.len() {
-                        results.push((false, &text[last_end..]));
-                    }
+        let has_media = input_seqs.iter().all(|seq| {
+            seq.images().is_some_and(|images| !images.is_empty())
+                || seq.audios().is_some_and(|audios| !audios.is_empty())
+        });
 
-                    results
-                        .into_iter()
-                        .map(|(_, x)| x.to_string())
-                        .collect::<Vec<_>>()
-                };
+        let (new_input, pixel_values_all, image_bound, tgt_sizes, audio_features_all, audio_bound) =
+            if has_media {
+                const IMAGE_TAG: &str = "(<image>./</image>)";
+                const AUDIO_TAG: &str = "(<audio>./</audio>)";
+                const IMAGE_PATTERN: &str = r"\(<image>./</image>\)";
+                const AUDIO_PATTERN: &str = r"\(<audio>./</audio>\)";
+
+                let image_pattern = Regex::new(IMAGE_PATTERN).unwrap();
+                let audio_pattern = Regex::new(AUDIO_PATTERN).unwrap();
+                let split_pattern =
+                    Regex::new(&format!(r"({IMAGE_PATTERN}|{AUDIO_PATTERN})")).unwrap();
+
+                let mut pixel_values_accum = Vec::new();
+                let mut tgt_sizes_accum = Vec::new();
+                let mut input_ids_accum = Vec::new();
+                let mut image_bounds_accum = Vec::new();
+                let mut audio_features_accum = Vec::new();
+                let mut audio_bounds_accum = Vec::new();
+
+                for seq in input_seqs.iter_mut() {
+                    let images = seq.take_images().unwrap_or_default();
+                    let (pixel_values_list, tgt_sizes, image_sizes_all) = if images.is_empty() {
+                        (
+                            vec![],
+                            Tensor::zeros((0, 2), DType::U32, device).unwrap(),
+                            vec![],
+                        )
+                    } else {
+                        let PreprocessedImages {
+                            pixel_values: _,
+                            pixel_attention_mask: _,
+                            image_sizes: _,
+                            num_img_tokens: _,
+                            aspect_ratio_ids: _,
+                            aspect_ratio_mask: _,
+                            num_tiles: _,
+                            image_grid_thw: _,
+                            video_grid_thw: _,
+                            rows: _,
+                            cols: _,
+                            pixel_values_list,
+                            tgt_sizes,
+                            image_sizes_all,
+                        } = self
+                            .preprocess(
+                                images,
+                                vec![],
+                                config,
+                                device,
+                                (usize::MAX, usize::MAX), // Don't use it here...
+                            )
+                            .expect("Preprocessing failed");
+                        (
+                            pixel_values_list.unwrap(),
+                            tgt_sizes.unwrap(),
+                            image_sizes_all.unwrap(),
+                        )
+                    };
 
-                let image_tags = image_pattern.find_iter(&text).collect::<Vec<_>>();
+                    let (audio_features, audio_placeholders) = self
+                        .preprocess_audios(seq.take_audios().unwrap_or_default(), device)
+                        .expect("Audio preprocessing failed");
 
-                if !image_tags.is_empty() {
-                    assert_eq!(image_tags.len(), image_sizes_all.len());
-                }
+                    let text = tokenizer
+                        .decode(seq.get_toks(), false)
+                        .expect("Detokenization failed!");
 
-                let mut image_id = 0;
-                for chunk in &mut text_chunks {
-                    if chunk == IMAGE_TAG {
-                        *chunk =
-                            self.get_slice_image_placeholder(image_sizes_all[image_id], image_id);
-                        image_id += 1;
+                    let mut text_chunks = {
+                        let mut results = Vec::new();
+                        let mut last_end = 0;
+
+                        for m in split_pattern.find_iter(&text) {
+                            // Anything between last_end and m.start() is unmatched
+                            if m.start() > last_end {
+                                results.push((false, &text[last_end..m.start()]));
+                            }
+                            results.push((true, m.as_str()));
+                            last_end = m.end();
+                        }
+                        // Handle the trailing unmatched part (if any)
+                        if last_end < text.len() {
+                            results.push((false, &text[last_end..]));
+                        }
+
+                        results
+                            .into_iter()
+                            .map(|(_, x)| x.to_string())
+                            .collect::<Vec<_>>()
+                    };
+
+                    let image_tags = image_pattern.find_iter(&text).collect::<Vec<_>>();
+
+                    if !image_tags.is_empty() {
+                        assert_eq!(image_tags.len(), image_sizes_all.len());
+                    }
+                    assert_eq!(
+                        audio_pattern.find_iter(&text).count(),
+                        audio_placeholders.len()
+                    );
+
+                    let mut image_id = 0;
+                    let mut audio_id = 0;
+                    for chunk in &mut text_chunks {
+                        if chunk == IMAGE_TAG {
+                            *chunk = self
+                                .get_slice_image_placeholder(image_sizes_all[image_id], image_id);
+                            image_id += 1;
+                        } else if chunk == AUDIO_TAG {
+                            *chunk = audio_placeholders[audio_id].clone();
+                            audio_id += 1;
+                        }
                     }
-                }
 
-                let final_text = text_chunks.join("");
-                seq.set_initial_prompt(final_text.clone());
-
-                let (input_ids, image_bounds) = {
-                    let im_start_id = tokenizer
-                        .encode(
-                            self.config
-                                .im_start_token
-                                .clone()
-                                .unwrap_or(DEFAULT_IM_START_TOKEN.to_string()),
-                            true,
-                        )
-                        .unwrap()
-                        .get_ids()[0];
-                    let im_end_id = tokenizer
-                        .encode(
-                            self.config
-                                .im_end_token
-                                .clone()
-                                .unwrap_or(DEFAULT_IM_END_TOKEN.to_string()),
-                            true,
+                    let final_text = text_chunks.join("");
+                    seq.set_initial_prompt(final_text.clone());
+
+                    let (input_ids, image_bounds) = {
+                        let im_start_id = tokenizer
+                            .encode(
+                                self.config
+                                    .im_start_token
+                                    .clone()
+                                    .unwrap_or(DEFAULT_IM_START_TOKEN.to_string()),
+                                true,
+                            )
+                            .unwrap()
+                            .get_ids()[0];
+                        let im_end_id = tokenizer
+                            .encode(
+                                self.config
+                                    .im_end_token
+                                    .clone()
+                                    .unwrap_or(DEFAULT_IM_END_TOKEN.to_string()),
+                                true,
+                            )
+                            .unwrap()
+                            .get_ids()[0];
+                        let slice_start_id = tokenizer
+                            .encode(
+                                self.config
+                                    .slice_start_token
+                                    .clone()
+                                    .unwrap_or(DEFAULT_SLICE_START_TOKEN.to_string()),
+                                true,
+                            )
+                            .unwrap()
+                            .get_ids()[0];
+                        let slice_end_id = tokenizer
+                            .encode(
+                                self.config
+                                    .slice_end_token
+                                    .clone()
+                                    .unwrap_or(DEFAULT_SLICE_END_TOKEN.to_string()),
+                                true,
+                            )
+                            .unwrap()
+                            .get_ids()[0];
+
+                        let input_ids = tokenizer
+                            .encode(final_text, true)
+                            .unwrap()
+                            .get_ids()
+                            .to_vec();
+
+                        seq.set_toks(input_ids.clone());
+
+                        let image_start_idx = input_ids
+                            .iter()
+                            .enumerate()
+                            .filter_map(|(i, &id)| {
+                                if id == im_start_id || id == slice_start_id {
+                                    Some(i as u32 + 1)
+                                } else {
+                                    None
+                                }
+                            })
+                            .collect::<Vec<_>>();
+
+                        let image_end_idx = input_ids
+                            .iter()
+                            .enumerate()
+                            .filter_map(|(i, &id)| {
+                                if id == im_end_id || id == slice_end_id {
+                                    Some(i as u32)
+                                } else {
+                                    None
+                                }
+                            })
+                            .collect::<Vec<_>>();
+
+                        let valid_image_nums = image_start_idx.len().max(image_end_idx.len());
+
+                        let image_start_idx = Tensor::from_slice(
+                            &image_start_idx[..valid_image_nums],
+                            (valid_image_nums, 1),
+                            device,
                         )
-                        .unwrap()
-                        .get_ids()[0];
-                    let slice_start_id = tokenizer
-                        .encode(
-                            self.config
-                                .slice_start_token
-                                .clone()
-                                .unwrap_or(DEFAULT_SLICE_START_TOKEN.to_string()),
-                            true,
+                        .unwrap();
+                        let image_end_idx = Tensor::from_slice(
+                            &image_end_idx[..valid_image_nums],
+                            (valid_image_nums, 1),
+                            device,
                         )
-                        .unwrap()
-                        .get_ids()[0];
-                    let slice_end_id = tokenizer
-                        .encode(
-                            self.config
-                                .slice_end_token
-                                .clone()
-                                .unwrap_or(DEFAULT_SLICE_END_TOKEN.to_string()),
-                            true,
-                        )
-                        .unwrap()
-                        .get_ids()[0];
-
-                    let input_ids = tokenizer
-                        .encode(final_text, true)
-                        .unwrap()
-                        .get_ids()
-                        .to_vec();
-
-                    seq.set_toks(input_ids.clone());
-
-                    let image_start_idx = input_ids
-                        .iter()
-                        .enumerate()
-                        .filter_map(|(i, &id)| {
-                            if id == im_start_id || id == slice_start_id {
-                                Some(i as u32 + 1)
-                            } else {
-                                None
-                            }
-                        })
-                        .collect::<Vec<_>>();
-
-                    let image_end_idx = input_ids
-                        .iter()
-                        .enumerate()
-                        .filter_map(|(i, &id)| {
-                            if id == im_end_id || id == slice_end_id {
-                                Some(i as u32)
-                            } else {
-                                None
-                            }
-                        })
-                        .collect::<Vec<_>>();
-
-                    let valid_image_nums = image_start_idx.len().max(image_end_idx.len());
-
-                    let image_start_idx = Tensor::from_slice(
-                        &image_start_idx[..valid_image_nums],
-                        (valid_image_nums, 1),
-                        device,
-                    )
-                    .unwrap();
-                    let image_end_idx = Tensor::from_slice(
-                        &image_end_idx[..valid_image_nums],
-                        (valid_image_nums, 1),
-                        device,
-                    )
-                    .unwrap();
-
-                    let image_bounds = Tensor::cat(&[image_start_idx, image_end_idx], 1).unwrap();
-
-                    (input_ids, image_bounds)
-                };
-
-                pixel_values_accum.push(pixel_values_list);
-                tgt_sizes_accum.push(tgt_sizes);
-                input_ids_accum.push(input_ids);
-                image_bounds_accum.push(image_bounds);
-            }
+                        .unwrap();
+
+                        let image_bounds =
+                            Tensor::cat(&[image_start_idx, image_end_idx], 1).unwrap();
+
+                        (input_ids, image_bounds)
+                    };
+
+                    let audio_bounds = if audio_features.is_empty() {
+                        Tensor::zeros((0, 2), DType::U32, device).unwrap()
+                    } else {
+                        let audio_start_id =
+                            tokenizer.encode(AUDIO_START_TOKEN, true).unwrap().get_ids()[0];
+                        let audio_end_id =
+                            tokenizer.encode(AUDIO_END_TOKEN, true).unwrap().get_ids()[0];
+
+                        let audio_start_idx = input_ids
+                            .iter()
+                            .enumerate()
+                            .filter_map(|(i, &id)| (id == audio_start_id).then_some(i as u32 + 1));
+                        let audio_end_idx = input_ids
+                            .iter()
+                            .enumerate()
+                            .filter_map(|(i, &id)| (id == audio_end_id).then_some(i as u32));
+                        let audio_bounds = audio_start_idx
+                            .zip(audio_end_idx)
+                            .flat_map(|(start, end)| [start, end])
+                            .collect::<Vec<_>>();
+                        let num_audio_bounds = audio_bounds.len() / 2;
+                        Tensor::from_vec(audio_bounds, (num_audio_bounds, 2), device).unwrap()
+                    };
+
+                    pixel_values_accum.push(pixel_values_list);
+                    tgt_sizes_accum.push(tgt_sizes);
+                    input_ids_accum.push(input_ids);
+                    image_bounds_accum.push(image_bounds);
+                    audio_features_accum.push(audio_features);
+                    audio_bounds_accum.push(audio_bounds);
+                }
 
-            let mut all_ids_new = Vec::new();
-            let max_len = input_ids_accum.iter().map(|ids| ids.len()).max().unwrap();
-            for ids in input_ids_accum {
-                let pad = max_len - ids.len();
-                all_ids_new
-                    .push(Tensor::new([ids, vec![0; pad]].concat(), input.device()).unwrap());
-            }
+                let mut all_ids_new = Vec::new();
+                let max_len = input_ids_accum.iter().map(|ids| ids.len()).max().unwrap();
+                for ids in input_ids_accum {
+                    let pad = max_len - ids.len();
+                    all_ids_new
+                        .push(Tensor::new([ids, vec![0; pad]].concat(), input.device()).unwrap());
+                }
 
-            (
-                Some(Tensor::stack(&all_ids_new, 0).unwrap()),
-                Some(pixel_values_accum),
-                Some(image_bounds_accum),
-                Some(tgt_sizes_accum),
-            )
-        } else {
-            (None, None, None, None)
-        };
+                let has_images = pixel_values_accum.iter().any(|x| !x.is_empty());
+                let has_audios = audio_features_accum.iter().any(|x| !x.is_empty());
+                (
+                    Some(Tensor::stack(&all_ids_new, 0).unwrap()),
+                    has_images.then_some(pixel_values_accum),
+                    has_images.then_some(image_bounds_accum),
+                    has_images.then_some(tgt_sizes_accum),
+                    has_audios.then_some(audio_features_accum),
+                    has_audios.then_some(audio_bounds_accum),
+                )
+            } else {
+                (None, None, None, None, None, None)
+            };
 
         let input = match new_input {
             Some(new_input) => new_input,
@@ -394,6 +483,8 @@ impl InputsProcessor for MiniCpmOImageProcessor {
             pixel_values_all,
             tgt_sizes,
             image_bound,
+            audio_features_all,
+            audio_bound,
         };
 
         // Dummy pixel values - real ones are in model specific args
@@ -415,6 +506,60 @@ impl InputsProcessor for MiniCpmOImageProcessor {
 }
 
 impl MiniCpmOImageProcessor {
+    /// Compute the log-mel features of each 30 second chunk of each audio clip, and for each clip
+    /// the placeholder reserving one position per audio embedding. Every chunk is wrapped in its
+    /// own audio start and end tokens.
+    fn preprocess_audios(
+        &self,
+        audios: Vec<AudioInput>,
+        device: &Device,
+    ) -> anyhow::Result<(Vec<Tensor>, Vec<String>)> {
+        if audios.is_empty() {
+            return Ok((vec![], vec![]));
+        }
+        let Some(AudioPreProcessor {
+            feature_extractor,
+            pool_step,
+        }) = &self.audio
+        else {
+            anyhow::bail!("This MiniCPM-o checkpoint does not include the audio encoder.");
+        };
+        let unk_token = self
+            .config
+            .unk_token
+            .clone()
+            .unwrap_or(DEFAULT_UNK_TOKEN.to_string());
+
+        let mut features = Vec::new();
+        let mut placeholders = Vec::new();
+        for audio in audios {
+            let audio = audio.resample(audio::SAMPLE_RATE);
+            let mut placeholder = String::new();
+            for chunk in audio.samples.chunks(audio::N_SAMPLES) {
+                let num_frames = WhisperFeatureExtractor::num_frames(chunk.len());
+                let num_tokens = num_audio_tokens(num_frames, *pool_step);
+                if num_tokens == 0 {
+                    continue;
+                }
+                let mel = feature_extractor.log_mel_spectrogram(chunk);
+                features.push(
+                    Tensor::from_vec(mel, (feature_extractor.n_mels(), audio::N_FRAMES), device)?
+                        .narrow(1, 0, num_frames)?
+                        .contiguous()?,
+                );
+                placeholder.push_str(&format!(
+                    "{AUDIO_START_TOKEN}{}{AUDIO_END_TOKEN}",
+                    unk_token.repeat(num_tokens)
+                ));
+            }
+            if placeholder.is_empty() {
+                anyhow::bail!("Audio clip is too short to encode.");
+            }
+            placeholders.push(placeholder);
+        }
+        Ok((features, placeholders))
+    }
+
     fn get_sliced_grid(
         &self,
         (w, h): (usize, usize),
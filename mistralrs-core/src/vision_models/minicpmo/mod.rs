@@ -1,5 +1,6 @@
 use std::{any::Any, sync::Arc};
 
+use audio::AudioEncoder;
 use candle_core::{DType, Device, IndexOp, Result, Tensor, D};
 pub use config::MiniCpmOConfig;
 pub use inputs_processor::MiniCpmOProcessor;
@@ -22,6 +23,7 @@ use self::siglip::SiglipVisionTransformer;
 
 use super::siglip;
 
+mod audio;
 mod config;
 mod inputs_processor;
 mod resampler;
@@ -31,6 +33,7 @@ pub struct MiniCpmOModel {
     llm: qwen2::Model,
     vpm: SiglipVisionTransformer,
     resampler: Resampler,
+    audio: Option<AudioEncoder>,
 }
 
 impl MiniCpmOModel {
@@ -49,6 +52,30 @@ impl MiniCpmOModel {
             normal_loading_metadata,
             attention_mechanism,
         )?;
+        Self::new_with_llm(cfg, vb, llm, &real_device)
+    }
+
+    /// Load the vision encoder and resampler from an `mmproj` file, with the language model loaded
+    /// from GGUF. `mmproj` files do not include the audio encoder.
+    pub(crate) fn new_gguf(
+        cfg: &MiniCpmOConfig,
+        vb: ShardedVarBuilder,
+        llm: qwen2::Model,
+        real_device: &Device,
+    ) -> Result<Self> {
+        let cfg = MiniCpmOConfig {
+            init_audio: false,
+            ..cfg.clone()
+        };
+        Self::new_with_llm(&cfg, vb, llm, real_device)
+    }
+
+    fn new_with_llm(
+        cfg: &MiniCpmOConfig,
+        vb: ShardedVarBuilder,
+        llm: qwen2::Model,
+        real_device: &Device,
+    ) -> Result<Self> {
         let vpm = SiglipVisionTransformer::new(
             &cfg.vision_config,
             vb.pp("vpm").set_device(real_device.clone()),
@@ -62,14 +89,51 @@ impl MiniCpmOModel {
             None,
             vb.pp("resampler").set_device(real_device.clone()),
         )?;
+        let audio = cfg
+            .audio_config()
+            .map(|audio_cfg| {
+                AudioEncoder::new(
+                    audio_cfg,
+                    cfg.text_config.hidden_size,
+                    cfg.audio_pool_step,
+                    vb.set_device(real_device.clone()),
+                )
+            })
+            .transpose()?;
         Ok(Self {
             cfg: cfg.clone(),
             llm,
             vpm,
             resampler,
+            audio,
         })
     }
 
+    /// Overwrite the embeddings at the `bounds` (half-open position ranges) of one sequence with
+    /// `values`, in order.
+    fn replace_embeddings(
+        embeds: &Tensor,
+        bounds: &[Vec<u32>],
+        values: &Tensor,
+        device: &Device,
+    ) -> Result<Tensor> {
+        if bounds.is_empty() {
+            return Ok(embeds.clone());
+        }
+        let indices = bounds
+            .iter()
+            .map(|r| Tensor::arange(r[0], r[1], device))
+            .collect::<Result<Vec<_>>>()?;
+        let indices = Tensor::cat(&indices, 0)?
+            .reshape(((), 1))?
+            .repeat((1, embeds.dim(D::Minus1)?))?;
+        // Zero out the current data
+        let embeds_neg = embeds.gather(&indices, 0)?.neg()?;
+        let embeds = embeds.scatter_add(&indices, &embeds_neg, 0)?;
+        // Add the new data
+        embeds.scatter_add(&indices, &values.to_dtype(embeds.dtype())?, 0)
+    }
+
     fn get_vllm_embedding(
         &self,
         input_ids: &Tensor,
@@ -77,6 +141,8 @@ impl MiniCpmOModel {
         pixel_values_all: Option<Vec<Vec<Tensor>>>,
         tgt_sizes: Option<Vec<Tensor>>,
         image_bound: Option<Vec<Tensor>>,
+        audio_features_all: Option<Vec<Vec<Tensor>>>,
+        audio_bound: Option<Vec<Tensor>>,
     ) -> Result<Tensor> {
         let mut vllm_embedding = self.llm.get_input_embeddings(input_ids)?;
 
@@ -174,32 +240,45 @@ impl MiniCpmOModel {
             }
 
             let mut new_vllm_embedding = Vec::new();
-            for i in 0..input_ids.dim(0)? {
+            for (i, cur_image_bound) in image_bound_vec.iter().enumerate() {
+                let mut cur_vllm_emb = vllm_embedding.i(i)?;
                 if let Some(cur_vs_hs) = &vision_hidden_states[i] {
-                    let mut cur_vllm_emb = vllm_embedding.i(i)?;
-                    let cur_image_bound = &image_bound_vec[i];
-                    if !cur_image_bound.is_empty() {
-                        let mut image_indices = Vec::new();
-                        for r in cur_image_bound {
-                            image_indices.push(Tensor::arange(r[0], r[1], device)?);
-                        }
-                        let image_indices = Tensor::stack(&image_indices, 0)?;
-
-                        let indices = image_indices
-                            .reshape(((), 1))?
-                            .repeat((1, cur_vllm_emb.dim(D::Minus1)?))?;
-                        // Zero out the current data
-                        let cur_vllm_emb_neg = cur_vllm_emb.gather(&indices, 0)?.neg()?;
-                        cur_vllm_emb = cur_vllm_emb.scatter_add(&indices, &cur_vllm_emb_neg, 0)?;
-                        // Add the image data
-                        cur_vllm_emb = cur_vllm_emb.scatter_add(
-                            &indices,
-                            &cur_vs_hs.reshape(((), cur_vs_hs.dim(D::Minus1)?))?,
-                            0,
-                        )?;
-                        new_vllm_embedding.push(cur_vllm_emb);
-                    }
+                    cur_vllm_emb = Self::replace_embeddings(
+                        &cur_vllm_emb,
+                        cur_image_bound,
+                        &cur_vs_hs.reshape(((), cur_vs_hs.dim(D::Minus1)?))?,
+                        device,
+                    )?;
                 }
+                new_vllm_embedding.push(cur_vllm_emb);
+            }
+            vllm_embedding = Tensor::stack(&new_vllm_embedding, 0)?;
+        }
+
+        if let Some(audio_features_all) = audio_features_all {
+            let Some(audio) = &self.audio else {
+                candle_core::bail!("This MiniCPM-o checkpoint does not include the audio encoder.");
+            };
+            let audio_bound = audio_bound.expect("Need audio_bound");
+
+            let mut new_vllm_embedding = Vec::new();
+            for (i, (audio_features, audio_bound)) in
+                audio_features_all.iter().zip(audio_bound).enumerate()
+            {
+                let mut cur_vllm_emb = vllm_embedding.i(i)?;
+                if !audio_features.is_empty() {
+                    let audio_embeds = audio_features
+                        .iter()
+                        .map(|features| audio.forward(features))
+                        .collect::<Result<Vec<_>>>()?;
+                    cur_vllm_emb = Self::replace_embeddings(
+                        &cur_vllm_emb,
+                        &audio_bound.to_vec2::<u32>()?,
+                        &Tensor::cat(&audio_embeds, 0)?.to_device(device)?,
+                        device,
+                    )?;
+                }
+                new_vllm_embedding.push(cur_vllm_emb);
             }
             vllm_embedding = Tensor::stack(&new_vllm_embedding, 0)?;
         }
@@ -214,6 +293,8 @@ impl MiniCpmOModel {
         pixel_values_all: Option<Vec<Vec<Tensor>>>,
         tgt_sizes: Option<Vec<Tensor>>,
         image_bound: Option<Vec<Tensor>>,
+        audio_features_all: Option<Vec<Vec<Tensor>>>,
+        audio_bound: Option<Vec<Tensor>>,
         seqlen_offsets: &[usize],
         context_lens: Vec<(usize, usize)>,
         metadata: Option<(Vec<(Tensor, Tensor)>, &PagedAttentionInputMetadata)>,
@@ -225,6 +306,8 @@ impl MiniCpmOModel {
             pixel_values_all,
             tgt_sizes,
             image_bound,
+            audio_features_all,
+            audio_bound,
         )?;
 
         self.llm.forward_embed(
@@ -243,6 +326,8 @@ pub(crate) struct MiniCpmOSpecificArgs {
     pub(crate) pixel_values_all: Option<Vec<Vec<Tensor>>>,
     pub(crate) tgt_sizes: Option<Vec<Tensor>>,
     pub(crate) image_bound: Option<Vec<Tensor>>,
+    pub(crate) audio_features_all: Option<Vec<Vec<Tensor>>>,
+    pub(crate) audio_bound: Option<Vec<Tensor>>,
 }
 
 impl VisionModel for MiniCpmOModel {
@@ -279,6 +364,8 @@ impl VisionModel for MiniCpmOModel {
             pixel_values_all,
             tgt_sizes,
             image_bound,
+            audio_features_all,
+            audio_bound,
         } = *model_specific_args
             .downcast()
             .expect("Cannot downcast into `MiniCpmOSpecificArgs`");
@@ -287,6 +374,8 @@ impl VisionModel for MiniCpmOModel {
             pixel_values_all,
             tgt_sizes,
             image_bound,
+            audio_features_all,
+            audio_bound,
             seqlen_offsets,
             context_lens,
             metadata,
@@ -298,6 +387,8 @@ impl VisionModel for MiniCpmOModel {
             pixel_values_all: None,
             tgt_sizes: None,
             image_bound: None,
+            audio_features_all: None,
+            audio_bound: None,
         })
     }
 }
@@ -319,6 +410,9 @@ impl IsqModel for MiniCpmOModel {
         uvb.pp("vpm").extend(self.vpm.residual_tensors());
         uvb.pp("resampler")
             .extend(self.resampler.residual_tensors());
+        if let Some(audio) = &self.audio {
+            uvb.extend(audio.residual_tensors());
+        }
 
         uvb.to_safetensors()
     }
@@ -326,8 +420,11 @@ impl IsqModel for MiniCpmOModel {
     // NOTE: We ONLY calibrate the text bits of these models, so we should only track/return those parts!!
 
     /// This is used for imatrix generation internally. Begin stats tracking.
-    fn begin_track_stats(&mut self) -> anyhow::Result<()> {
-        self.llm.begin_track_stats()
+    fn begin_track_stats(
+        &mut self,
+        track_hessian: &dyn Fn(Option<usize>) -> bool,
+    ) -> anyhow::Result<()> {
+        self.llm.begin_track_stats(track_hessian)
     }
 
     /// End stats tracking and return the imatrix data
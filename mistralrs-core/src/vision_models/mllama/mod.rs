@@ -258,16 +258,14 @@ impl IsqModel for MLlamaModel {
     // NOTE: We ONLY calibrate the text bits of these models, so we should only track/return those parts!!
 
     /// This is used for imatrix generation internally. Begin stats tracking.
-    fn begin_track_stats(&mut self) -> anyhow::Result<()> {
-        let layers = self
-            .language_model
-            .get_layers()
-            .0
-            .into_iter()
-            .map(|(layer, _)| layer)
-            .collect::<Vec<_>>();
-        for layer in layers {
-            Arc::get_mut(layer).unwrap().begin_track_stats()?;
+    fn begin_track_stats(
+        &mut self,
+        track_hessian: &dyn Fn(Option<usize>) -> bool,
+    ) -> anyhow::Result<()> {
+        for (layer, layer_num) in self.language_model.get_layers().0 {
+            Arc::get_mut(layer)
+                .unwrap()
+                .begin_track_stats(track_hessian(layer_num))?;
         }
         Ok(())
     }
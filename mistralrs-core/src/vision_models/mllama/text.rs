@@ -682,7 +682,7 @@ impl MLlamaTextModel {
     ) -> Result<Tensor> {
         let mut hidden_states = self.embed_tokens.forward(input_ids)?;
 
-        let cache = &mut self.cache.normal().0;
+        let cache = &mut self.cache.normal()?.0;
         let self_mask = CausalMasker.make_causal_mask_matrix(
             input_ids,
             cache as &dyn PastKvLenCache,
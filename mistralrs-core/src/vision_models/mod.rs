@@ -20,6 +20,7 @@ pub(crate) use llava::llava_next_inputs_processor;
 pub(crate) mod idefics3;
 pub(crate) mod minicpmo;
 pub(crate) mod siglip;
+pub(crate) mod whisper;
 
 use crate::pipeline::text_models_inputs_processor::{FlashParams, PagedAttentionInputMetadata};
 
@@ -1091,7 +1091,7 @@ impl Model {
         } else {
             self.embed_tokens.forward(input_ids)?
         };
-        let cache = &mut self.cache.normal().0;
+        let cache = &mut self.cache.normal()?.0;
         let attention_mask = CausalMasker.make_sliding_window_causal_mask_matrix(
             input_ids,
             metadata
@@ -25,6 +25,13 @@ pub struct VisionConfig {
     pub temporal_patch_size: usize,
 }
 
+impl VisionConfig {
+    /// Hidden size of the encoder MLP.
+    pub fn intermediate_size(&self) -> usize {
+        (self.embed_dim as f64 * self.mlp_ratio) as usize
+    }
+}
+
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct MRopeScaling {
     pub mrope_section: Vec<usize>,
@@ -69,6 +69,10 @@ impl Processor for Qwen2VLProcessor {
     fn template_action(&self) -> MessagesAction {
         MessagesAction::FlattenOnlyText
     }
+
+    fn supports_videos(&self) -> bool {
+        true
+    }
 }
 
 fn replace_first_occurrence(text: &str, to_replace: &str, replacement: &str) -> String {
@@ -81,6 +85,16 @@ fn replace_first_occurrence(text: &str, to_replace: &str, replacement: &str) ->
     }
 }
 
+/// The frames are grouped into temporal patches, so repeat the last frame to fill the final
+/// patch.
+fn pad_to_temporal_patch(frames: &mut Vec<DynamicImage>, temporal_patch_size: usize) {
+    if let Some(last) = frames.last().cloned() {
+        while frames.len() % temporal_patch_size != 0 {
+            frames.push(last.clone());
+        }
+    }
+}
+
 fn find_sequences(nums: &[u32], needle: u32) -> Vec<(usize, usize)> {
     let mut sequences = Vec::new();
     let mut start = None;
@@ -211,13 +225,15 @@ impl InputsProcessor for Qwen2VLImageProcessor {
         let config = other_config.expect("Need a PreProcessorConfig config.");
         let config: &PreProcessorConfig = config.downcast_ref().expect("Downcast failed.");
 
-        let has_images = input_seqs
-            .iter()
-            .all(|seq| seq.images().is_some_and(|images| !images.is_empty()));
+        let has_media = input_seqs.iter().all(|seq| {
+            seq.images().is_some_and(|images| !images.is_empty())
+                || seq.videos().is_some_and(|videos| !videos.is_empty())
+        });
 
         let (
             new_input,
             pixel_values,
+            pixel_values_videos,
             image_grid_thw,
             video_grid_thw,
             continuous_img_pad,
@@ -225,8 +241,9 @@ impl InputsProcessor for Qwen2VLImageProcessor {
             input_ids_searching,
             image_nums,
             video_nums,
-        ) = if has_images {
+        ) = if has_media {
             let mut pixel_values_accum = Vec::new();
+            let mut pixel_values_videos_accum = Vec::new();
             let mut image_grid_thw_accum = Vec::new();
             let mut video_grid_thw_accum = Vec::new();
 
@@ -241,51 +258,97 @@ impl InputsProcessor for Qwen2VLImageProcessor {
                 .expect("Detokenization failed!");
 
             for seq in input_seqs.iter_mut() {
-                let (pixel_values, image_grid_thw, video_grid_thw) =
-                    if let Some(cached_pixel_values) = &seq.cached_pixel_values {
+                let (pixel_values, pixel_values_videos, image_grid_thw, video_grid_thw) =
+                    if seq.cached_pixel_values.is_some() || seq.cached_vid_pixel_values.is_some() {
                         (
-                            cached_pixel_values.clone(),
+                            seq.cached_pixel_values.clone(),
+                            seq.cached_vid_pixel_values.clone(),
                             seq.cached_img_thw.clone(),
                             seq.cached_vid_thw.clone(),
                         )
                     } else {
-                        let PreprocessedImages {
+                        let images = seq.clone_images().unwrap_or_default();
+                        let (pixel_values, image_grid_thw) = if images.is_empty() {
+                            (None, None)
+                        } else {
+                            let PreprocessedImages {
+                                pixel_values,
+                                image_grid_thw,
+                                ..
+                            } = self
+                                .preprocess(
+                                    images,
+                                    vec![],
+                                    config,
+                                    device,
+                                    (usize::MAX, usize::MAX), // Don't use it here...
+                                )
+                                .expect("Preprocessing failed");
+                            (Some(pixel_values), image_grid_thw)
+                        };
+
+                        let videos = seq.clone_videos().unwrap_or_default();
+                        let (pixel_values_videos, video_grid_thw) = if videos.is_empty() {
+                            (None, None)
+                        } else {
+                            let PreprocessedImages {
+                                pixel_values,
+                                video_grid_thw,
+                                ..
+                            } = self
+                                .preprocess(
+                                    vec![],
+                                    videos,
+                                    config,
+                                    device,
+                                    (usize::MAX, usize::MAX), // Don't use it here...
+                                )
+                                .expect("Preprocessing failed");
+                            (Some(pixel_values), video_grid_thw)
+                        };
+
+                        seq.cached_pixel_values = pixel_values.clone();
+                        seq.cached_vid_pixel_values = pixel_values_videos.clone();
+                        seq.cached_img_thw = image_grid_thw.clone();
+                        seq.cached_vid_thw = video_grid_thw.clone();
+                        (
                             pixel_values,
-                            pixel_attention_mask: _,
-                            image_sizes: _,
-                            num_img_tokens: _,
-                            aspect_ratio_ids: _,
-                            aspect_ratio_mask: _,
-                            num_tiles: _,
+                            pixel_values_videos,
                             image_grid_thw,
                             video_grid_thw,
-                            rows: _,
-                            cols: _,
-                            pixel_values_list: _,
-                            tgt_sizes: _,
-                            image_sizes_all: _,
-                        } = self
-                            .preprocess(
-                                seq.clone_images()
-                                    .expect("Need to have images by this point."),
-                                vec![],
-                                config,
-                                device,
-                                (usize::MAX, usize::MAX), // Don't use it here...
-                            )
-                            .expect("Preprocessing failed");
-
-                        seq.cached_pixel_values = Some(pixel_values.clone());
-                        seq.cached_img_thw = image_grid_thw.clone();
-                        seq.cached_vid_thw = video_grid_thw.clone();
-                        (pixel_values, image_grid_thw, video_grid_thw)
+                        )
                     };
 
-                pixel_values_accum.push(pixel_values.unsqueeze(0).unwrap());
+                pixel_values_accum.push(pixel_values.map(|pixels| pixels.unsqueeze(0).unwrap()));
+                pixel_values_videos_accum
+                    .push(pixel_values_videos.map(|pixels| pixels.unsqueeze(0).unwrap()));
                 image_grid_thw_accum.push(image_grid_thw); //.map(|img| img.unsqueeze(0).unwrap()));
                 video_grid_thw_accum.push(video_grid_thw); //.map(|vid| vid.unsqueeze(0).unwrap()));
             }
 
+            let pixel_values_accum = if pixel_values_accum.iter().any(|img| img.is_none()) {
+                None
+            } else {
+                Some(
+                    pixel_values_accum
+                        .into_iter()
+                        .map(|img| img.unwrap())
+                        .collect::<Vec<_>>(),
+                )
+            };
+
+            let pixel_values_videos_accum =
+                if pixel_values_videos_accum.iter().any(|vid| vid.is_none()) {
+                    None
+                } else {
+                    Some(
+                        pixel_values_videos_accum
+                            .into_iter()
+                            .map(|vid| vid.unwrap())
+                            .collect::<Vec<_>>(),
+                    )
+                };
+
             let image_grid_thw_accum = if image_grid_thw_accum.iter().any(|img| img.is_none()) {
                 None
             } else {
@@ -438,7 +501,8 @@ impl InputsProcessor for Qwen2VLImageProcessor {
 
             (
                 Some(Tensor::stack(&all_ids_new, 0).unwrap()),
-                Some(Tensor::cat(&pixel_values_accum, 0).unwrap()),
+                pixel_values_accum.map(|img| Tensor::cat(&img, 0).unwrap()),
+                pixel_values_videos_accum.map(|vid| Tensor::cat(&vid, 0).unwrap()),
                 image_grid_thw_accum.map(|img| Tensor::cat(&img, 0).unwrap()),
                 video_grid_thw_accum.map(|vid| Tensor::cat(&vid, 0).unwrap()),
                 all_continuous_img_pad,
@@ -453,6 +517,7 @@ impl InputsProcessor for Qwen2VLImageProcessor {
                 None,
                 None,
                 None,
+                None,
                 vec![],
                 vec![],
                 vec![vec![]; input_seqs.len()],
@@ -468,6 +533,7 @@ impl InputsProcessor for Qwen2VLImageProcessor {
         };
 
         let pixel_values = if is_prompt { pixel_values } else { None };
+        let pixel_values_videos = if is_prompt { pixel_values_videos } else { None };
 
         let seqlens = input_seqs
             .iter()
@@ -482,6 +548,7 @@ impl InputsProcessor for Qwen2VLImageProcessor {
             pixel_values,
             model_specific_args: Box::new(Qwen2VLVisionSpecificArgs {
                 input_ids_full,
+                pixel_values_videos,
                 image_grid_thw,
                 video_grid_thw,
                 seqlens,
@@ -502,6 +569,10 @@ impl InputsProcessor for Qwen2VLImageProcessor {
 }
 
 impl Qwen2VLImageProcessor {
+    // https://github.com/QwenLM/Qwen2-VL/blob/main/qwen-vl-utils/src/qwen_vl_utils/vision_process.py
+    const VIDEO_MAX_PIXELS: usize = 768 * 28 * 28;
+    const VIDEO_TOTAL_PIXELS: usize = 24576 * 28 * 28;
+
     fn smart_resize(
         &self,
         height: usize,
@@ -547,6 +618,7 @@ impl Qwen2VLImageProcessor {
         config: &PreProcessorConfig,
         device: &Device,
         (mut height, mut width): (u32, u32),
+        max_pixels: Option<usize>,
     ) -> candle_core::Result<(Tensor, (u32, u32, u32))> {
         let mut processed_images = Vec::new();
 
@@ -567,7 +639,10 @@ impl Qwen2VLImageProcessor {
                     config.patch_size.context("Require `patch_size`.")?
                         * config.merge_size.context("Require `merge_size`")?,
                     config.min_pixels.context("Require `min_pixels`")?,
-                    config.max_pixels.context("Require `max_pixels`")?,
+                    match max_pixels {
+                        Some(max_pixels) => max_pixels,
+                        None => config.max_pixels.context("Require `max_pixels`")?,
+                    },
                 )?;
                 height = resized_height as u32;
                 width = resized_width as u32;
@@ -672,7 +747,7 @@ impl ImagePreProcessor for Qwen2VLImageProcessor {
 
             for image in images {
                 let (patches, (t, h, w)) =
-                    self.preprocess_inner(vec![image], config, device, (height, width))?;
+                    self.preprocess_inner(vec![image], config, device, (height, width), None)?;
                 pixel_values.push(patches);
                 vision_grid_thw.push(Tensor::new(&[t, h, w], &Device::Cpu)?);
             }
@@ -697,6 +772,14 @@ impl ImagePreProcessor for Qwen2VLImageProcessor {
         }
 
         if !videos.is_empty() {
+            let temporal_patch_size = config
+                .temporal_patch_size
+                .context("Require `temporal_patch_size")?;
+
+            if videos.iter().any(|frames| frames.is_empty()) {
+                candle_core::bail!("Video contains no frames.");
+            }
+
             let mut height = 0;
             let mut width = 0;
             for image in &videos {
@@ -709,13 +792,26 @@ impl ImagePreProcessor for Qwen2VLImageProcessor {
                 }
             }
 
-            for images in videos {
-                let (patches, (t, h, w)) =
-                    self.preprocess_inner(images, config, device, (height, width))?;
+            for mut frames in videos {
+                pad_to_temporal_patch(&mut frames, temporal_patch_size);
+                // Spread the pixel budget of the whole clip over its frames.
+                let max_pixels = (Self::VIDEO_TOTAL_PIXELS / frames.len() * temporal_patch_size)
+                    .min(Self::VIDEO_MAX_PIXELS)
+                    .max(
+                        (config.min_pixels.context("Require `min_pixels`")? as f64 * 1.05) as usize,
+                    );
+                let (patches, (t, h, w)) = self.preprocess_inner(
+                    frames,
+                    config,
+                    device,
+                    (height, width),
+                    Some(max_pixels),
+                )?;
                 pixel_values.push(patches);
                 vision_grid_thw.push(Tensor::new(&[t, h, w], &Device::Cpu)?);
             }
-            let pixel_values = Tensor::stack(&pixel_values, 0)?;
+            // Videos may have different numbers of frames, so concatenate the patches.
+            let pixel_values = Tensor::cat(&pixel_values, 0)?;
             let vision_grid_thw = Tensor::stack(&vision_grid_thw, 0)?;
             return Ok(PreprocessedImages {
                 pixel_values,
@@ -737,3 +833,41 @@ impl ImagePreProcessor for Qwen2VLImageProcessor {
         unreachable!()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+
+    use super::pad_to_temporal_patch;
+
+    fn frame(value: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([value; 3])))
+    }
+
+    fn values(frames: &[DynamicImage]) -> Vec<u8> {
+        frames.iter().map(|f| f.get_pixel(0, 0)[0]).collect()
+    }
+
+    #[test]
+    fn frames_are_padded_with_the_last_frame() {
+        let mut frames = vec![frame(0), frame(1), frame(2)];
+        pad_to_temporal_patch(&mut frames, 2);
+        assert_eq!(values(&frames), vec![0, 1, 2, 2]);
+
+        let mut frames = vec![frame(0)];
+        pad_to_temporal_patch(&mut frames, 2);
+        assert_eq!(values(&frames), vec![0, 0]);
+
+        let mut frames = vec![frame(0), frame(1)];
+        pad_to_temporal_patch(&mut frames, 2);
+        assert_eq!(values(&frames), vec![0, 1]);
+
+        let mut frames = vec![frame(0), frame(1), frame(2), frame(3)];
+        pad_to_temporal_patch(&mut frames, 3);
+        assert_eq!(values(&frames), vec![0, 1, 2, 3, 3, 3]);
+
+        let mut frames = Vec::new();
+        pad_to_temporal_patch(&mut frames, 2);
+        assert!(frames.is_empty());
+    }
+}
@@ -10,6 +10,7 @@ use vision::Qwen2VLVisionModel;
 use crate::{
     amoe::AnyMoeBaseModelMixin,
     device_map::DeviceMapper,
+    gguf::Content,
     layers::CausalMasker,
     layers_masker::{masked_fill, PastKvLenCache},
     paged_attention::{AttentionImplementation, ModelConfigMetadata},
@@ -27,6 +28,29 @@ mod vision;
 pub(crate) use config::Config;
 pub(crate) use inputs_processor::Qwen2VLProcessor;
 
+/// Replace the runs of image or video pad tokens of each sequence, given by `(start, end)`, with
+/// the vision embeddings. The embeddings of all sequences are concatenated in order, like their
+/// pixel values.
+fn merge_vision_embeds(
+    mut xs: Tensor,
+    embeds: &Tensor,
+    pads: Vec<Vec<(usize, usize)>>,
+) -> Result<Tensor> {
+    let mut last_end = 0;
+    for (batch, batch_ids) in pads.into_iter().enumerate() {
+        for (start, end) in batch_ids {
+            xs = xs.slice_assign(
+                &[&batch, &(start..end), &..],
+                &embeds
+                    .i((last_end..last_end + (end - start), ..))?
+                    .unsqueeze(0)?,
+            )?;
+            last_end += end - start;
+        }
+    }
+    Ok(xs)
+}
+
 pub struct Qwen2VLModel {
     text: Qwen2VLTextModel,
     vision: Qwen2VLVisionModel,
@@ -47,12 +71,7 @@ impl Qwen2VLModel {
             // TODO!
             candle_core::bail!("Sliding window is unsupported for now!");
         }
-        let vision = Qwen2VLVisionModel::new(
-            &cfg.vision_config,
-            vb.pp("visual")
-                .set_device(normal_loading_metadata.real_device.clone())
-                .set_dtype(DType::F32),
-        )?;
+        let real_device = normal_loading_metadata.real_device.clone();
         let text = Qwen2VLTextModel::new(
             cfg,
             vb.clone(),
@@ -60,6 +79,34 @@ impl Qwen2VLModel {
             normal_loading_metadata,
             attention_mechanism,
         )?;
+        Self::new_with_text(cfg, vb, text, &real_device)
+    }
+
+    /// Load the vision encoder from an `mmproj` file, with the language model loaded from GGUF.
+    pub(crate) fn new_gguf<R: std::io::Seek + std::io::Read>(
+        cfg: &Config,
+        vb: ShardedVarBuilder,
+        ct: &mut Content<'_, R>,
+        mapper: Box<dyn DeviceMapper + Send + Sync>,
+        device: &Device,
+        dtype: DType,
+    ) -> Result<Self> {
+        let text = Qwen2VLTextModel::new_gguf(cfg, ct, mapper, device, dtype)?;
+        Self::new_with_text(cfg, vb, text, device)
+    }
+
+    fn new_with_text(
+        cfg: &Config,
+        vb: ShardedVarBuilder,
+        text: Qwen2VLTextModel,
+        real_device: &Device,
+    ) -> Result<Self> {
+        let vision = Qwen2VLVisionModel::new(
+            &cfg.vision_config,
+            vb.pp("visual")
+                .set_device(real_device.clone())
+                .set_dtype(DType::F32),
+        )?;
         Ok(Self {
             text,
             vision,
@@ -302,40 +349,21 @@ impl Qwen2VLModel {
                     )?
                     .to_dtype(self.text.dtype)?;
 
-                for (batch, batch_ids) in continuous_img_pad.into_iter().enumerate() {
-                    let mut last_end = 0;
-                    for (start, end) in batch_ids {
-                        xs = xs.slice_assign(
-                            &[&batch, &(start..end), &..],
-                            &image_embeds
-                                .i((last_end..last_end + (end - start), ..))?
-                                .unsqueeze(0)?,
-                        )?;
-                        last_end = end - start;
-                    }
-                }
+                xs = merge_vision_embeds(xs, &image_embeds, continuous_img_pad)?;
             }
 
             if let Some(pixel_values_videos) = pixel_values_videos {
-                let video_embeds = self.vision.forward(
-                    &pixel_values_videos,
-                    video_grid_thw
-                        .as_ref()
-                        .context("pixel_values_videos require video_grid_thw")?,
-                )?;
+                let video_embeds = self
+                    .vision
+                    .forward(
+                        &pixel_values_videos,
+                        video_grid_thw
+                            .as_ref()
+                            .context("pixel_values_videos require video_grid_thw")?,
+                    )?
+                    .to_dtype(self.text.dtype)?;
 
-                for (batch, batch_ids) in continuous_vid_pad.into_iter().enumerate() {
-                    let mut last_end = 0;
-                    for (start, end) in batch_ids {
-                        xs = xs.slice_assign(
-                            &[&batch, &(start..end), &..],
-                            &video_embeds
-                                .i((last_end..last_end + (end - start), ..))?
-                                .unsqueeze(0)?,
-                        )?;
-                        last_end = end - start;
-                    }
-                }
+                xs = merge_vision_embeds(xs, &video_embeds, continuous_vid_pad)?;
             }
 
             xs
@@ -406,8 +434,9 @@ impl Qwen2VLModel {
 
 pub(crate) struct Qwen2VLVisionSpecificArgs {
     input_ids_full: Tensor,
-    image_grid_thw: Option<Tensor>, // Some when pixel values are provided
-    video_grid_thw: Option<Tensor>, // Some when pixel values are provided
+    pixel_values_videos: Option<Tensor>, // Some when videos are provided
+    image_grid_thw: Option<Tensor>,      // Some when pixel values are provided
+    video_grid_thw: Option<Tensor>,      // Some when pixel values are provided
     seqlens: Vec<usize>,
     continuous_img_pad: Vec<Vec<(usize, usize)>>,
     continuous_vid_pad: Vec<Vec<(usize, usize)>>,
@@ -430,6 +459,7 @@ impl VisionModel for Qwen2VLModel {
     ) -> Result<Tensor> {
         let Qwen2VLVisionSpecificArgs {
             input_ids_full,
+            pixel_values_videos,
             image_grid_thw,
             video_grid_thw,
             seqlens,
@@ -441,19 +471,11 @@ impl VisionModel for Qwen2VLModel {
         } = *model_specific_args
             .downcast()
             .expect("Cannot downcast into `Qwen2VLVisionSpecificArgs`");
-        let (pixel_values, pixel_values_video) = match (&image_grid_thw, &video_grid_thw) {
-            (Some(_), None) => (pixel_values, None),
-            (None, Some(_)) => (None, pixel_values),
-            (None, None) => (None, None),
-            (Some(_), Some(_)) => {
-                candle_core::bail!("Images and videos cannot be provided together.")
-            }
-        };
         self.forward(
             input_ids,
             &input_ids_full,
             pixel_values,
-            pixel_values_video,
+            pixel_values_videos,
             image_grid_thw,
             video_grid_thw,
             seqlens,
@@ -489,6 +511,7 @@ impl VisionModel for Qwen2VLModel {
         assert_eq!(input_ids.dims()[0], 1);
         Box::new(Qwen2VLVisionSpecificArgs {
             input_ids_full: input_ids.clone(),
+            pixel_values_videos: None,
             image_grid_thw: None,
             video_grid_thw: None,
             seqlens: vec![input_ids.dims()[1]],
@@ -516,3 +539,29 @@ impl IsqModel for Qwen2VLModel {
 }
 
 impl AnyMoeBaseModelMixin for Qwen2VLModel {}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{Device, Result, Tensor};
+
+    use super::merge_vision_embeds;
+
+    #[test]
+    fn merges_several_images_per_sequence() -> Result<()> {
+        let dev = Device::Cpu;
+        // Two sequences of 6 tokens with a hidden size of 1. The first has three images of 1, 2
+        // and 1 tokens, the second one image of 2 tokens.
+        let xs = Tensor::zeros((2, 6, 1), candle_core::DType::F32, &dev)?;
+        let embeds = Tensor::new(&[[1f32], [2.], [3.], [4.], [5.], [6.]], &dev)?;
+        let pads = vec![vec![(0, 1), (2, 4), (5, 6)], vec![(1, 3)]];
+
+        let merged = merge_vision_embeds(xs, &embeds, pads)?
+            .squeeze(2)?
+            .to_vec2::<f32>()?;
+        assert_eq!(
+            merged,
+            vec![vec![1., 0., 2., 3., 0., 4.], vec![0., 5., 6., 0., 0., 0.]]
+        );
+        Ok(())
+    }
+}
@@ -2,6 +2,7 @@ use std::{collections::HashMap, sync::Arc};
 
 use candle_core::{DType, Device, Result, Tensor};
 use candle_nn::{Embedding, Module};
+use indicatif::MultiProgress;
 use mistralrs_quant::{
     ColumnParallelLayer, QuantMethod, ReplicatedLayer, RowParallelLayer, ShardedVarBuilder,
 };
@@ -9,6 +10,7 @@ use mistralrs_quant::{
 use crate::{
     attention::SdpaParams,
     device_map::DeviceMapper,
+    gguf::Content,
     layers::{self, Activation, F32RmsNorm, Qwen2VLRotaryEmbedding, Sdpa},
     paged_attention::{AttentionImplementation, ModelConfigMetadata},
     pipeline::{
@@ -63,6 +65,20 @@ impl Mlp {
         })
     }
 
+    fn new_gguf<R: std::io::Seek + std::io::Read>(
+        cfg: &Config,
+        ct: &mut Content<'_, R>,
+        prefix: &str,
+        device: &Device,
+    ) -> Result<Self> {
+        Ok(Self {
+            gate_proj: ct.linear(&format!("{prefix}.ffn_gate"), false, device)?,
+            up_proj: ct.linear(&format!("{prefix}.ffn_up"), false, device)?,
+            down_proj: ct.linear(&format!("{prefix}.ffn_down"), false, device)?,
+            act_fn: cfg.hidden_act,
+        })
+    }
+
     fn forward(&self, xs: &Tensor) -> Result<Tensor> {
         let original_dtype = xs.dtype();
         let mut xs = xs.clone();
@@ -162,6 +178,33 @@ impl Attention {
         })
     }
 
+    fn new_gguf<R: std::io::Seek + std::io::Read>(
+        rotary_emb: Arc<Qwen2VLRotaryEmbedding>,
+        cfg: &Config,
+        ct: &mut Content<'_, R>,
+        prefix: &str,
+        device: &Device,
+    ) -> Result<Self> {
+        let head_dim = cfg.hidden_size / cfg.num_attention_heads;
+        Ok(Self {
+            q_proj: ct.linear(&format!("{prefix}.attn_q"), true, device)?,
+            k_proj: ct.linear(&format!("{prefix}.attn_k"), true, device)?,
+            v_proj: ct.linear(&format!("{prefix}.attn_v"), true, device)?,
+            o_proj: ct.linear(&format!("{prefix}.attn_output"), false, device)?,
+            num_heads: cfg.num_attention_heads,
+            num_kv_heads: cfg.num_key_value_heads,
+            head_dim,
+            rotary_emb,
+            sdpa_params: SdpaParams {
+                n_kv_groups: cfg.num_attention_heads / cfg.num_key_value_heads,
+                use_flash_attn: false,
+                softcap: None,
+                softmax_scale: 1.0 / (head_dim as f32).sqrt(),
+                sliding_window: None,
+            },
+        })
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn forward(
         &self,
@@ -433,6 +476,102 @@ impl Qwen2VLTextModel {
         })
     }
 
+    /// Load the language model from the llama.cpp GGUF file (`qwen2vl` architecture). The shapes
+    /// come from `cfg`, the `config.json` of the original model.
+    pub fn new_gguf<R: std::io::Seek + std::io::Read>(
+        cfg: &Config,
+        ct: &mut Content<'_, R>,
+        mapper: Box<dyn DeviceMapper + Send + Sync>,
+        device: &Device,
+        dtype: DType,
+    ) -> Result<Self> {
+        if cfg.use_sliding_window {
+            candle_core::bail!("Sliding window is unsupported for now!");
+        }
+        let dequantize = |ct: &mut Content<'_, R>, name: &str, device: &Device| {
+            ct.tensor(name, device)?.dequantize(device)?.to_dtype(dtype)
+        };
+        let embed_tokens = Embedding::new(
+            dequantize(ct, "token_embd.weight", device)?,
+            cfg.hidden_size,
+        );
+        let head_dim = cfg.hidden_size / cfg.num_attention_heads;
+
+        let mut ropes = HashMap::new();
+        for layer_idx in 0..cfg.num_hidden_layers {
+            let device = mapper.device_for(layer_idx, false).unwrap_or(device);
+            ropes.insert(
+                device.location(),
+                Arc::new(Qwen2VLRotaryEmbedding::new(
+                    cfg.rope_theta as f32,
+                    head_dim,
+                    device,
+                    cfg.rope_scaling.mrope_section.clone(),
+                )?),
+            );
+        }
+
+        let mut layers = Vec::with_capacity(cfg.num_hidden_layers);
+        for layer_idx in NiceProgressBar::<_, 'b'>(
+            0..cfg.num_hidden_layers,
+            "Loading repeating layers",
+            &MultiProgress::new(),
+        ) {
+            let prefix = format!("blk.{layer_idx}");
+            let device = mapper.device_for(layer_idx, false).unwrap_or(device);
+            let rotary_emb = ropes
+                .get(&device.location())
+                .expect("No RoPE for device location!")
+                .clone();
+            layers.push(DecoderLayer {
+                self_attn: Attention::new_gguf(rotary_emb, cfg, ct, &prefix, device)?,
+                mlp: Mlp::new_gguf(cfg, ct, &prefix, device)?,
+                input_layernorm: F32RmsNorm::from_w(
+                    dequantize(ct, &format!("{prefix}.attn_norm.weight"), device)?,
+                    cfg.rms_norm_eps,
+                )?,
+                post_attention_layernorm: F32RmsNorm::from_w(
+                    dequantize(ct, &format!("{prefix}.ffn_norm.weight"), device)?,
+                    cfg.rms_norm_eps,
+                )?,
+            });
+        }
+        let norm = F32RmsNorm::from_w(
+            dequantize(ct, "output_norm.weight", device)?,
+            cfg.rms_norm_eps,
+        )?;
+        // Tied embeddings are not written to the file again.
+        let lm_head = if ct.has_tensor("output.weight") {
+            ct.linear("output", false, device)?
+        } else {
+            ct.linear("token_embd", false, device)?
+        };
+        Ok(Self {
+            embed_tokens,
+            norm,
+            layers,
+            lm_head,
+            cache: EitherCache::Normal(NormalCache::new(
+                cfg.num_hidden_layers,
+                cfg.max_position_embeddings,
+            )),
+            max_seq_len: cfg.max_position_embeddings,
+            cfg: ModelConfigMetadata {
+                max_seq_len: cfg.max_position_embeddings,
+                num_layers: cfg.num_hidden_layers,
+                hidden_size: cfg.hidden_size,
+                num_attn_heads: cfg.num_attention_heads,
+                num_kv_heads: cfg.num_key_value_heads,
+                sliding_window: cfg.sliding_window,
+                k_head_dim: head_dim,
+                v_head_dim: head_dim,
+            },
+            device: device.clone(),
+            dtype,
+            mapper,
+        })
+    }
+
     pub fn embed_tokens(&self, input_ids: &Tensor) -> Result<Tensor> {
         self.embed_tokens.forward(input_ids)
     }
@@ -445,7 +584,7 @@ impl Qwen2VLTextModel {
         context_lens: Vec<(usize, usize)>,
         flash_params: &FlashParams,
     ) -> Result<Tensor> {
-        let cache = &mut self.cache.normal().0;
+        let cache = &mut self.cache.normal()?.0;
         let cos_sin = self.layers[0]
             .self_attn
             .rotary_emb
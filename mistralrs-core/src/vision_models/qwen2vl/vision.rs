@@ -169,8 +169,12 @@ impl VisionBlock {
         let norm1 = layer_norm(cfg.embed_dim, 1e-6, vb.pp("norm1"))?;
         let norm2 = layer_norm(cfg.embed_dim, 1e-6, vb.pp("norm2"))?;
 
-        let mlp_hidden_dim = (cfg.embed_dim as f64 * cfg.mlp_ratio) as usize;
-        let mlp = VisionMlp::new(cfg.embed_dim, mlp_hidden_dim, cfg.hidden_act, vb.pp("mlp"))?;
+        let mlp = VisionMlp::new(
+            cfg.embed_dim,
+            cfg.intermediate_size(),
+            cfg.hidden_act,
+            vb.pp("mlp"),
+        )?;
         let attn = VisionAttention::new(cfg.embed_dim, cfg.num_heads, vb.pp("attn"))?;
 
         Ok(Self {
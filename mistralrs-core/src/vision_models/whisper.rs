@@ -0,0 +1,257 @@
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use candle_core::{Result, Tensor};
+use candle_nn::{Conv1d, Conv1dConfig, LayerNorm, Linear, Module};
+use mistralrs_quant::ShardedVarBuilder;
+
+use crate::{
+    layers::{conv1d, embedding, layer_norm, linear, linear_no_bias, Activation, MatMul},
+    serde_default_fn,
+    utils::unvarbuilder::UnVarBuilder,
+};
+
+serde_default_fn!(usize, d_model, 1280);
+serde_default_fn!(usize, encoder_layers, 32);
+serde_default_fn!(usize, encoder_attention_heads, 20);
+serde_default_fn!(usize, encoder_ffn_dim, 5120);
+serde_default_fn!(usize, num_mel_bins, 80);
+serde_default_fn!(usize, max_source_positions, 1500);
+serde_default_fn!(Activation, activation_function, Activation::Gelu);
+
+/// The encoder half of a Whisper `config.json`. Decoder fields are ignored.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct WhisperEncoderConfig {
+    #[serde(default = "d_model")]
+    pub d_model: usize,
+    #[serde(default = "encoder_layers")]
+    pub encoder_layers: usize,
+    #[serde(default = "encoder_attention_heads")]
+    pub encoder_attention_heads: usize,
+    #[serde(default = "encoder_ffn_dim")]
+    pub encoder_ffn_dim: usize,
+    #[serde(default = "num_mel_bins")]
+    pub num_mel_bins: usize,
+    #[serde(default = "max_source_positions")]
+    pub max_source_positions: usize,
+    #[serde(default = "activation_function")]
+    pub activation_function: Activation,
+}
+
+impl Default for WhisperEncoderConfig {
+    fn default() -> Self {
+        Self {
+            d_model: d_model(),
+            encoder_layers: encoder_layers(),
+            encoder_attention_heads: encoder_attention_heads(),
+            encoder_ffn_dim: encoder_ffn_dim(),
+            num_mel_bins: num_mel_bins(),
+            max_source_positions: max_source_positions(),
+            activation_function: activation_function(),
+        }
+    }
+}
+
+struct Attention {
+    num_heads: usize,
+    head_dim: usize,
+    q_proj: Linear,
+    k_proj: Linear,
+    v_proj: Linear,
+    out_proj: Linear,
+}
+
+impl Attention {
+    fn new(cfg: &WhisperEncoderConfig, vb: ShardedVarBuilder) -> Result<Self> {
+        let embed_dim = cfg.d_model;
+        let num_heads = cfg.encoder_attention_heads;
+        Ok(Self {
+            num_heads,
+            head_dim: embed_dim / num_heads,
+            q_proj: linear(embed_dim, embed_dim, vb.pp("q_proj"))?,
+            k_proj: linear_no_bias(embed_dim, embed_dim, vb.pp("k_proj"))?,
+            v_proj: linear(embed_dim, embed_dim, vb.pp("v_proj"))?,
+            out_proj: linear(embed_dim, embed_dim, vb.pp("out_proj"))?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let (b_sz, seq_len, embed_dim) = xs.dims3()?;
+        let shape = (b_sz, seq_len, self.num_heads, self.head_dim);
+
+        let q = (self.q_proj.forward(xs)? * (self.head_dim as f64).powf(-0.5))?
+            .reshape(shape)?
+            .transpose(1, 2)?
+            .contiguous()?;
+        let k = self
+            .k_proj
+            .forward(xs)?
+            .reshape(shape)?
+            .transpose(1, 2)?
+            .contiguous()?;
+        let v = self
+            .v_proj
+            .forward(xs)?
+            .reshape(shape)?
+            .transpose(1, 2)?
+            .contiguous()?;
+
+        let attn_weights = MatMul.matmul(&q, &k.t()?.contiguous()?)?;
+        let attn_weights = candle_nn::ops::softmax_last_dim(&attn_weights)?;
+        MatMul
+            .matmul(&attn_weights, &v)?
+            .transpose(1, 2)?
+            .reshape((b_sz, seq_len, embed_dim))?
+            .apply(&self.out_proj)
+    }
+
+    fn residual_tensors(&self) -> Vec<(String, Tensor)> {
+        let uvb = UnVarBuilder::new();
+
+        uvb.pp("q_proj").add(&self.q_proj);
+        uvb.pp("k_proj").add(&self.k_proj);
+        uvb.pp("v_proj").add(&self.v_proj);
+        uvb.pp("out_proj").add(&self.out_proj);
+
+        uvb.to_safetensors()
+    }
+}
+
+struct EncoderLayer {
+    self_attn: Attention,
+    self_attn_layer_norm: LayerNorm,
+    fc1: Linear,
+    fc2: Linear,
+    final_layer_norm: LayerNorm,
+    activation: Activation,
+}
+
+impl EncoderLayer {
+    fn new(cfg: &WhisperEncoderConfig, vb: ShardedVarBuilder) -> Result<Self> {
+        Ok(Self {
+            self_attn: Attention::new(cfg, vb.pp("self_attn"))?,
+            self_attn_layer_norm: layer_norm(cfg.d_model, 1e-5, vb.pp("self_attn_layer_norm"))?,
+            fc1: linear(cfg.d_model, cfg.encoder_ffn_dim, vb.pp("fc1"))?,
+            fc2: linear(cfg.encoder_ffn_dim, cfg.d_model, vb.pp("fc2"))?,
+            final_layer_norm: layer_norm(cfg.d_model, 1e-5, vb.pp("final_layer_norm"))?,
+            activation: cfg.activation_function,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let residual = xs;
+        let xs = self
+            .self_attn
+            .forward(&xs.apply(&self.self_attn_layer_norm)?)?;
+        let xs = (xs + residual)?;
+
+        let residual = &xs;
+        let hidden_states = xs
+            .apply(&self.final_layer_norm)?
+            .apply(&self.fc1)?
+            .apply(&self.activation)?
+            .apply(&self.fc2)?;
+        hidden_states + residual
+    }
+
+    fn residual_tensors(&self) -> Vec<(String, Tensor)> {
+        let uvb = UnVarBuilder::new();
+
+        uvb.pp("self_attn")
+            .extend(self.self_attn.residual_tensors());
+        uvb.pp("self_attn_layer_norm")
+            .add(&self.self_attn_layer_norm);
+        uvb.pp("fc1").add(&self.fc1);
+        uvb.pp("fc2").add(&self.fc2);
+        uvb.pp("final_layer_norm").add(&self.final_layer_norm);
+
+        uvb.to_safetensors()
+    }
+}
+
+/// Whisper audio encoder: two convolutions over the log-mel spectrogram, which halve the frame
+/// rate, followed by pre-norm transformer layers.
+pub struct WhisperEncoder {
+    conv1: Conv1d,
+    conv2: Conv1d,
+    embed_positions: Tensor,
+    layers: Vec<EncoderLayer>,
+    layer_norm: LayerNorm,
+}
+
+impl WhisperEncoder {
+    pub fn new(cfg: &WhisperEncoderConfig, vb: ShardedVarBuilder) -> Result<Self> {
+        let conv1 = conv1d(
+            cfg.num_mel_bins,
+            cfg.d_model,
+            3,
+            Conv1dConfig {
+                padding: 1,
+                ..Default::default()
+            },
+            vb.pp("conv1"),
+        )?;
+        let conv2 = conv1d(
+            cfg.d_model,
+            cfg.d_model,
+            3,
+            Conv1dConfig {
+                padding: 1,
+                stride: 2,
+                ..Default::default()
+            },
+            vb.pp("conv2"),
+        )?;
+        let embed_positions = embedding(
+            cfg.max_source_positions,
+            cfg.d_model,
+            vb.pp("embed_positions"),
+        )?
+        .embeddings()
+        .clone();
+        let vb_l = vb.pp("layers");
+        let layers = (0..cfg.encoder_layers)
+            .map(|i| EncoderLayer::new(cfg, vb_l.pp(i)))
+            .collect::<Result<Vec<_>>>()?;
+        let layer_norm = layer_norm(cfg.d_model, 1e-5, vb.pp("layer_norm"))?;
+        Ok(Self {
+            conv1,
+            conv2,
+            embed_positions,
+            layers,
+            layer_norm,
+        })
+    }
+
+    /// `input_features`: (bs, num_mel_bins, frames), with at most `2 * max_source_positions`
+    /// frames. Returns (bs, ceil(frames / 2), d_model).
+    pub fn forward(&self, input_features: &Tensor) -> Result<Tensor> {
+        let xs = input_features
+            .to_dtype(self.embed_positions.dtype())?
+            .apply(&self.conv1)?
+            .gelu_erf()?
+            .apply(&self.conv2)?
+            .gelu_erf()?
+            .transpose(1, 2)?;
+        let seq_len = xs.dim(1)?;
+        let mut xs = xs.broadcast_add(&self.embed_positions.narrow(0, 0, seq_len)?)?;
+        for layer in &self.layers {
+            xs = layer.forward(&xs)?;
+        }
+        xs.apply(&self.layer_norm)
+    }
+
+    pub fn residual_tensors(&self) -> Vec<(String, Tensor)> {
+        let uvb = UnVarBuilder::new();
+
+        uvb.pp("conv1").add(&self.conv1);
+        uvb.pp("conv2").add(&self.conv2);
+        uvb.pp("embed_positions")
+            .add_tensor("weight", self.embed_positions.clone());
+        uvb.pp("layer_norm").add(&self.layer_norm);
+        for (i, layer) in self.layers.iter().enumerate() {
+            uvb.pp("layers").pp(i).extend(layer.residual_tensors());
+        }
+
+        uvb.to_safetensors()
+    }
+}
@@ -598,15 +598,15 @@ impl XLoraModel {
         let mut cache = if is_full_pass {
             if no_kv_cache {
                 let mut new_cache = Vec::new();
-                for _ in 0..self.cache.full().xlora_lock().len() {
+                for _ in 0..self.cache.full()?.xlora_lock().len() {
                     new_cache.push(None);
                 }
 
-                self.cache.full().xlora_lock().clone_from(&new_cache);
+                self.cache.full()?.xlora_lock().clone_from(&new_cache);
             }
-            self.cache.full().xlora_lock()
+            self.cache.full()?.xlora_lock()
         } else {
-            self.cache.full().lock()
+            self.cache.full()?.lock()
         };
         let xs = self.embed_tokens.forward(input_ids)?;
         let attention_mask = CausalMasker.make_causal_mask_matrix(
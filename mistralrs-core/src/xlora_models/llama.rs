@@ -434,15 +434,15 @@ impl XLoraLlama {
         let mut cache = if is_full_pass {
             if no_kv_cache {
                 let mut new_cache = Vec::new();
-                for _ in 0..self.kv_cache.full().xlora_lock().len() {
+                for _ in 0..self.kv_cache.full()?.xlora_lock().len() {
                     new_cache.push(None);
                 }
 
-                self.kv_cache.full().xlora_lock().clone_from(&new_cache);
+                self.kv_cache.full()?.xlora_lock().clone_from(&new_cache);
             }
-            self.kv_cache.full().xlora_lock()
+            self.kv_cache.full()?.xlora_lock()
         } else {
-            self.kv_cache.full().lock()
+            self.kv_cache.full()?.lock()
         };
         let mask = CausalMasker.make_causal_mask_matrix(
             input_ids,
@@ -0,0 +1,41 @@
+#pragma once
+
+#include <stdint.h>
+#include <cuda_fp8.h>
+#include <cuda_fp16.h>
+
+namespace vllm {
+namespace fp8 {
+
+// Largest finite value of FP8 E4M3.
+constexpr float E4M3_MAX = 448.f;
+
+// The FP8 KV cache stores one power of two scale per token and head as a signed exponent.
+// The exponents live in two extra rows of the value cache: row `head_size` for the key and row
+// `head_size + 1` for the value.
+constexpr int NUM_SCALE_ROWS = 2;
+
+inline __device__ float e4m3_to_float(uint8_t x) {
+  const __half_raw h = __nv_cvt_fp8_to_halfraw(x, __NV_E4M3);
+  return __half2float(__half(h));
+}
+
+inline __device__ uint8_t float_to_e4m3(float x) {
+  return __nv_cvt_float_to_fp8(x, __NV_SATFINITE, __NV_E4M3);
+}
+
+// Exponent of the smallest power of two scale which maps `amax` into the E4M3 range.
+inline __device__ int8_t scale_exponent(float amax) {
+  if (!(amax > 0.f)) {
+    return 0;
+  }
+  const int e = static_cast<int>(ceilf(log2f(amax / E4M3_MAX)));
+  return static_cast<int8_t>(max(-64, min(64, e)));
+}
+
+inline __device__ float exponent_to_scale(uint8_t e) {
+  return ldexpf(1.f, static_cast<int8_t>(e));
+}
+
+} // namespace fp8
+} // namespace vllm
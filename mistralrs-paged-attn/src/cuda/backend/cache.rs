@@ -86,8 +86,13 @@ pub fn copy_blocks(
                 let ptr_value = *slice_value.slice(0..).device_ptr();
                 (ptr_key, ptr_value)
             }
+            (CudaStorageSlice::F8E4M3(slice_key), CudaStorageSlice::F8E4M3(slice_value)) => {
+                let ptr_key = *slice_key.slice(0..).device_ptr();
+                let ptr_value = *slice_value.slice(0..).device_ptr();
+                (ptr_key, ptr_value)
+            }
             _ => {
-                candle_core::bail!("only f32, f16 and bf16 input data type supported!",);
+                candle_core::bail!("only f32, f16, bf16 and f8e4m3 cache data types supported!",);
             }
         };
         key_cache_ptrs.push(key_ptr + key_offset);
@@ -126,9 +131,24 @@ pub fn copy_blocks(
         .product::<usize>()
         .try_into()
         .unwrap();
+    // Differs from the key blocks for the FP8 cache, which stores the scales in the value blocks.
+    let value_numel_per_block: u32 = value_caches
+        .first()
+        .unwrap()
+        .i(0)?
+        .shape()
+        .dims()
+        .iter()
+        .product::<usize>()
+        .try_into()
+        .unwrap();
     let launch_conf = LaunchConfig {
         grid_dim: (num_layers, num_pairs, 1u32),
-        block_dim: (numel_per_block.min(1024), 1u32, 1u32),
+        block_dim: (
+            numel_per_block.max(value_numel_per_block).min(1024),
+            1u32,
+            1u32,
+        ),
         shared_mem_bytes: 0,
     };
     let stream = dev.fork_default_stream().w()?;
@@ -151,6 +171,7 @@ pub fn copy_blocks(
                     value_cache_ptr,
                     block_mapping_ptr,
                     numel_per_block as i32,
+                    value_numel_per_block as i32,
                 ),
             )
             .w()?;
@@ -199,8 +220,13 @@ pub unsafe fn swap_blocks(
                     let ptr_dst = *slice_dst.slice(dst_layout.start_offset()..).device_ptr();
                     (ptr_src, ptr_dst)
                 }
+                (CudaStorageSlice::F8E4M3(slice_src), CudaStorageSlice::F8E4M3(slice_dst)) => {
+                    let ptr_src = *slice_src.slice(src_layout.start_offset()..).device_ptr();
+                    let ptr_dst = *slice_dst.slice(dst_layout.start_offset()..).device_ptr();
+                    (ptr_src, ptr_dst)
+                }
                 _ => {
-                    candle_core::bail!("only f32, f16 and bf16 input data type supported!")
+                    candle_core::bail!("only f32, f16, bf16 and f8e4m3 cache data types supported!")
                 }
             };
 
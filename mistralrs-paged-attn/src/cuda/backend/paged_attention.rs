@@ -1,10 +1,12 @@
 use crate::cuda::ffi;
 use crate::cuda::ffi::{paged_attention_v1, paged_attention_v2};
+use crate::FP8_KV_CACHE_SCALE_ROWS;
 use candle::backend::BackendStorage;
 use candle::cuda_backend::cudarc::driver::DevicePtr;
 use candle::cuda_backend::WrapErr;
 use candle::{CpuStorage, CudaStorage, DType, Layout, Result, Shape, Storage, Tensor};
 use candle_core as candle;
+use float8::F8E4M3;
 use half::{bf16, f16};
 use std::ffi::c_int;
 
@@ -35,6 +37,7 @@ impl PagedAttention {
             DType::F32 => 2,
             dtype => candle::bail!("dtype {dtype:?} is not supported"),
         };
+        let (cache_type, scale_rows) = cache_type(dtype, self.key_cache.dtype())?;
 
         let dev = q.device();
         let out_shape = q_l.shape().clone();
@@ -90,15 +93,16 @@ impl PagedAttention {
 
         // Get cuda slices for all tensors
         let q = q.as_cuda_slice::<T>()?;
-        let kc = kc.as_cuda_slice::<T>()?;
-        let vc = vc.as_cuda_slice::<T>()?;
+        let (kc_ptr, vc_ptr) = if cache_type == 1 {
+            cache_ptrs::<F8E4M3>(kc, kc_l, vc, vc_l)?
+        } else {
+            cache_ptrs::<T>(kc, kc_l, vc, vc_l)?
+        };
         let cl = cl.as_cuda_slice::<u32>()?; // Should be i32!
         let bt = bt.as_cuda_slice::<u32>()?; // Should be i32!
 
         // Get cuda views for all tensors
         let q = q.slice(q_l.start_offset()..);
-        let kc = kc.slice(kc_l.start_offset()..);
-        let vc = vc.slice(vc_l.start_offset()..);
         let cl = cl.slice(cl_l.start_offset()..);
         let bt = bt.slice(bt_l.start_offset()..);
 
@@ -146,7 +150,7 @@ impl PagedAttention {
             )
         }
 
-        if (num_blocks, num_kv_heads, head_size, block_size) != vc_l.shape().dims4()? {
+        if (num_blocks, num_kv_heads, head_size + scale_rows, block_size) != vc_l.shape().dims4()? {
             candle::bail!(
                 "shape mismatch key_cache {:?} and value_cache {:?}",
                 kc_l.shape(),
@@ -165,6 +169,8 @@ impl PagedAttention {
         let q_stride = q_l.stride()[0];
         let kv_block_stride = kc_l.stride()[0];
         let kv_head_stride = kc_l.stride()[1];
+        let v_block_stride = vc_l.stride()[0];
+        let v_head_stride = vc_l.stride()[1];
 
         let partition_size = 512;
         let max_num_partitions = self.max_context_len.div_ceil(partition_size);
@@ -176,8 +182,6 @@ impl PagedAttention {
 
         let out_ptr = *out.device_ptr() as *const core::ffi::c_void;
         let q_ptr = *q.device_ptr() as *const core::ffi::c_void;
-        let kc_ptr = *kc.device_ptr() as *const core::ffi::c_void;
-        let vc_ptr = *vc.device_ptr() as *const core::ffi::c_void;
         let bt_ptr = *bt.device_ptr() as *const core::ffi::c_int;
         let cl_ptr = *cl.device_ptr() as *const core::ffi::c_int;
 
@@ -203,7 +207,10 @@ impl PagedAttention {
                     q_stride as c_int,
                     kv_block_stride as c_int,
                     kv_head_stride as c_int,
+                    v_block_stride as c_int,
+                    v_head_stride as c_int,
                     internal_type,
+                    cache_type,
                 )
             }
         } else {
@@ -241,7 +248,10 @@ impl PagedAttention {
                     q_stride as c_int,
                     kv_block_stride as c_int,
                     kv_head_stride as c_int,
+                    v_block_stride as c_int,
+                    v_head_stride as c_int,
                     internal_type,
+                    cache_type,
                 )
             }
         }
@@ -251,6 +261,31 @@ impl PagedAttention {
     }
 }
 
+/// The cache type code passed to the kernels and the number of extra rows of the value cache.
+fn cache_type(dtype: DType, cache_dtype: DType) -> Result<(u32, usize)> {
+    match cache_dtype {
+        DType::F8E4M3 => Ok((1, FP8_KV_CACHE_SCALE_ROWS)),
+        cache_dtype if cache_dtype == dtype => Ok((0, 0)),
+        cache_dtype => {
+            candle::bail!("KV cache dtype {cache_dtype:?} is not supported for {dtype:?} inputs")
+        }
+    }
+}
+
+fn cache_ptrs<T: candle::cuda_backend::CudaDType>(
+    kc: &CudaStorage,
+    kc_l: &Layout,
+    vc: &CudaStorage,
+    vc_l: &Layout,
+) -> Result<(*const core::ffi::c_void, *const core::ffi::c_void)> {
+    let kc = kc.as_cuda_slice::<T>()?.slice(kc_l.start_offset()..);
+    let vc = vc.as_cuda_slice::<T>()?.slice(vc_l.start_offset()..);
+    Ok((
+        *kc.device_ptr() as *const core::ffi::c_void,
+        *vc.device_ptr() as *const core::ffi::c_void,
+    ))
+}
+
 impl candle::CustomOp1 for PagedAttention {
     fn name(&self) -> &'static str {
         "paged-attention"
@@ -282,6 +317,8 @@ impl candle::CustomOp1 for PagedAttention {
 /// * `key_cache` - Key cache paged tensor of shape `(num_blocks, num_heads_kv, head_size / x, block_size, x)`
 ///   with `x` being the size of an element in bytes.
 /// * `value_cache` - Value cache paged tensor of shape `(num_blocks, num_heads_kv, head_size, block_size)`.
+///   If the caches are FP8 E4M3, the value cache has [`FP8_KV_CACHE_SCALE_ROWS`] extra rows holding the
+///   scales of the keys and values.
 /// * `block_tables` - Padded table associating blocks to each sequence of shape `(num_sequences, max_context_len // block_size)`
 /// * `context_lens` - Tensor associating lengths to each sequence of shape `(num_sequences)`
 /// * `max_context_len` - Max of `context_len`
@@ -332,6 +369,7 @@ fn update_cache<
         DType::F32 => 2,
         dtype => candle::bail!("dtype {dtype:?} is not supported"),
     };
+    let (cache_type, scale_rows) = cache_type(dtype, key_cache.dtype())?;
 
     let (k, k_l) = key.storage_and_layout();
     let k = match &*k {
@@ -389,15 +427,16 @@ fn update_cache<
     // Get cuda slices for all tensors
     let k = k.as_cuda_slice::<T>()?;
     let v = v.as_cuda_slice::<T>()?;
-    let kc = kc.as_cuda_slice::<T>()?;
-    let vc = vc.as_cuda_slice::<T>()?;
+    let (kc_ptr, vc_ptr) = if cache_type == 1 {
+        cache_ptrs::<F8E4M3>(kc, kc_l, vc, vc_l)?
+    } else {
+        cache_ptrs::<T>(kc, kc_l, vc, vc_l)?
+    };
     let s = s.as_cuda_slice::<i64>()?;
 
     // Get cuda views for all tensors
     let k = k.slice(k_l.start_offset()..);
     let v = v.slice(v_l.start_offset()..);
-    let kc = kc.slice(kc_l.start_offset()..);
-    let vc = vc.slice(vc_l.start_offset()..);
     let s = s.slice(s_l.start_offset()..);
 
     let (num_tokens, num_heads, head_size) = k_l.shape().dims3()?;
@@ -414,7 +453,7 @@ fn update_cache<
         )
     }
 
-    if (num_blocks, num_heads, head_size, block_size) != vc_l.shape().dims4()? {
+    if (num_blocks, num_heads, head_size + scale_rows, block_size) != vc_l.shape().dims4()? {
         candle::bail!(
             "shape mismatch key_cache {:?} and value_cache {:?}",
             kc_l.shape(),
@@ -435,8 +474,6 @@ fn update_cache<
 
     let k_ptr = *k.device_ptr() as *const core::ffi::c_void;
     let v_ptr = *v.device_ptr() as *const core::ffi::c_void;
-    let kc_ptr = *kc.device_ptr() as *const core::ffi::c_void;
-    let vc_ptr = *vc.device_ptr() as *const core::ffi::c_void;
     let s_ptr = *s.device_ptr() as *const core::ffi::c_long;
 
     unsafe {
@@ -454,6 +491,7 @@ fn update_cache<
             key_stride,
             value_stride,
             internal_type,
+            cache_type,
         )
     }
     Ok(())
@@ -468,6 +506,8 @@ fn update_cache<
 /// * `key_cache` - Key cache paged tensor of shape `(num_blocks, num_heads, head_size / x, block_size, x)`
 ///   with `x` being the size of an element in bytes.
 /// * `value_cache` - Value cache paged tensor of shape `(num_blocks, num_heads, head_size, block_size)`.
+///   If the caches are FP8 E4M3, the keys and values are quantized with one scale per token and head,
+///   stored in [`FP8_KV_CACHE_SCALE_ROWS`] extra rows of the value cache.
 /// * `slot_mapping` - Mapping associating a slot to each token of shape `(num_tokens)`.
 pub fn reshape_and_cache(
     key: &Tensor,
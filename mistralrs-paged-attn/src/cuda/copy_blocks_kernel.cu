@@ -6,7 +6,8 @@ __device__ void copy_blocks_internal_kernel(
   int64_t* key_cache_ptrs,
   int64_t* value_cache_ptrs,
   const int64_t* __restrict__ block_mapping,
-  const int numel_per_block) {
+  const int numel_per_block,
+  const int value_numel_per_block) {
   const int layer_idx = blockIdx.x;
   const int pair_idx = blockIdx.y;
 
@@ -22,9 +23,12 @@ __device__ void copy_blocks_internal_kernel(
     int64_t dst_offset = dst_block_offset + i;
     key_cache[dst_offset] = key_cache[src_offset];
   }
-  for (int i = threadIdx.x; i < numel_per_block; i += blockDim.x) {
-    int64_t src_offset = src_block_offset + i;
-    int64_t dst_offset = dst_block_offset + i;
+  // The value blocks of the FP8 cache are larger, as they also hold the scales.
+  const int64_t src_value_block_offset = src_block_number * value_numel_per_block;
+  const int64_t dst_value_block_offset = dst_block_number * value_numel_per_block;
+  for (int i = threadIdx.x; i < value_numel_per_block; i += blockDim.x) {
+    int64_t src_offset = src_value_block_offset + i;
+    int64_t dst_offset = dst_value_block_offset + i;
     value_cache[dst_offset] = value_cache[src_offset];
   }
 }
@@ -33,36 +37,41 @@ __device__ void copy_blocks_internal_kernel(
 extern "C" __global__ void copy_blocks_kernel_u8(int64_t* key_cache_ptrs,
   int64_t* value_cache_ptrs,
   const int64_t* __restrict__ block_mapping,
-  const int numel_per_block) {
-  copy_blocks_internal_kernel<uint8_t>(key_cache_ptrs, value_cache_ptrs, block_mapping, numel_per_block);
+  const int numel_per_block,
+  const int value_numel_per_block) {
+  copy_blocks_internal_kernel<uint8_t>(key_cache_ptrs, value_cache_ptrs, block_mapping, numel_per_block, value_numel_per_block);
 }
 
 extern "C" __global__ void copy_blocks_kernel_u32(int64_t* key_cache_ptrs,
   int64_t* value_cache_ptrs,
   const int64_t* __restrict__ block_mapping,
-  const int numel_per_block) {
-  copy_blocks_internal_kernel<uint32_t>(key_cache_ptrs, value_cache_ptrs, block_mapping, numel_per_block);
+  const int numel_per_block,
+  const int value_numel_per_block) {
+  copy_blocks_internal_kernel<uint32_t>(key_cache_ptrs, value_cache_ptrs, block_mapping, numel_per_block, value_numel_per_block);
 }
 
 extern "C" __global__ void copy_blocks_kernel_i64(int64_t* key_cache_ptrs,
   int64_t* value_cache_ptrs,
   const int64_t* __restrict__ block_mapping,
-  const int numel_per_block) {
-  copy_blocks_internal_kernel<int64_t>(key_cache_ptrs, value_cache_ptrs, block_mapping, numel_per_block);
+  const int numel_per_block,
+  const int value_numel_per_block) {
+  copy_blocks_internal_kernel<int64_t>(key_cache_ptrs, value_cache_ptrs, block_mapping, numel_per_block, value_numel_per_block);
 }
 
 extern "C" __global__ void copy_blocks_kernel_f32(int64_t* key_cache_ptrs,
   int64_t* value_cache_ptrs,
   const int64_t* __restrict__ block_mapping,
-  const int numel_per_block) {
-  copy_blocks_internal_kernel<float>(key_cache_ptrs, value_cache_ptrs, block_mapping, numel_per_block);
+  const int numel_per_block,
+  const int value_numel_per_block) {
+  copy_blocks_internal_kernel<float>(key_cache_ptrs, value_cache_ptrs, block_mapping, numel_per_block, value_numel_per_block);
 }
 
 extern "C" __global__ void copy_blocks_kernel_f64(int64_t* key_cache_ptrs,
   int64_t* value_cache_ptrs,
   const int64_t* __restrict__ block_mapping,
-  const int numel_per_block) {
-  copy_blocks_internal_kernel<double>(key_cache_ptrs, value_cache_ptrs, block_mapping, numel_per_block);
+  const int numel_per_block,
+  const int value_numel_per_block) {
+  copy_blocks_internal_kernel<double>(key_cache_ptrs, value_cache_ptrs, block_mapping, numel_per_block, value_numel_per_block);
 }
 
 // f16, bf16 are special cases: We use a 16-bit integer to simulate the bit width. 
@@ -70,13 +79,23 @@ extern "C" __global__ void copy_blocks_kernel_f64(int64_t* key_cache_ptrs,
 extern "C" __global__ void copy_blocks_kernel_f16(int64_t* key_cache_ptrs,
   int64_t* value_cache_ptrs,
   const int64_t* __restrict__ block_mapping,
-  const int numel_per_block) {
-  copy_blocks_internal_kernel<int16_t>(key_cache_ptrs, value_cache_ptrs, block_mapping, numel_per_block);
+  const int numel_per_block,
+  const int value_numel_per_block) {
+  copy_blocks_internal_kernel<int16_t>(key_cache_ptrs, value_cache_ptrs, block_mapping, numel_per_block, value_numel_per_block);
 }
 
 extern "C" __global__ void copy_blocks_kernel_bf16(int64_t* key_cache_ptrs,
   int64_t* value_cache_ptrs,
   const int64_t* __restrict__ block_mapping,
-  const int numel_per_block) {
-  copy_blocks_internal_kernel<int16_t>(key_cache_ptrs, value_cache_ptrs, block_mapping, numel_per_block);
+  const int numel_per_block,
+  const int value_numel_per_block) {
+  copy_blocks_internal_kernel<int16_t>(key_cache_ptrs, value_cache_ptrs, block_mapping, numel_per_block, value_numel_per_block);
+}
+
+extern "C" __global__ void copy_blocks_kernel_f8_e4m3(int64_t* key_cache_ptrs,
+  int64_t* value_cache_ptrs,
+  const int64_t* __restrict__ block_mapping,
+  const int numel_per_block,
+  const int value_numel_per_block) {
+  copy_blocks_internal_kernel<uint8_t>(key_cache_ptrs, value_cache_ptrs, block_mapping, numel_per_block, value_numel_per_block);
 }
\ No newline at end of file
@@ -17,6 +17,7 @@ extern "C" {
         value_stride: c_int,
 
         dtype: u32,
+        cache_dtype: u32,
     );
 
     pub fn paged_attention_v1(
@@ -40,8 +41,11 @@ extern "C" {
         q_stride: c_int,
         kv_block_stride: c_int,
         kv_head_stride: c_int,
+        v_block_stride: c_int,
+        v_head_stride: c_int,
 
         dtype: u32,
+        cache_dtype: u32,
     );
 
     pub fn paged_attention_v2(
@@ -68,7 +72,10 @@ extern "C" {
         q_stride: c_int,
         kv_block_stride: c_int,
         kv_head_stride: c_int,
+        v_block_stride: c_int,
+        v_head_stride: c_int,
 
         dtype: u32,
+        cache_dtype: u32,
     );
 }
@@ -23,6 +23,7 @@
 
 #include "attention/attention_dtypes.h"
 #include "attention/attention_utils.cuh"
+#include "attention/dtype_fp8.cuh"
 
 #include <algorithm>
 
@@ -89,8 +90,12 @@ inline __device__ float fast_tanh(float x) {
 
 // TODO(woosuk): Merge the last two dimensions of the grid.
 // Grid: (num_heads, num_seqs, max_num_partitions).
+// If `IS_FP8_KV_CACHE`, the cache is FP8 E4M3 (`cache_t` is `uint8_t`) and the values have
+// `fp8::NUM_SCALE_ROWS` extra rows holding the scale exponents of each token.
 template<
   typename scalar_t,
+  typename cache_t,
+  bool IS_FP8_KV_CACHE,
   int HEAD_SIZE,
   int BLOCK_SIZE,
   int NUM_THREADS,
@@ -100,8 +105,8 @@ __device__ void paged_attention_kernel(
   float* __restrict__ max_logits,         // [num_seqs, num_heads, max_num_partitions]
   scalar_t* __restrict__ out,             // [num_seqs, num_heads, max_num_partitions, head_size]
   const scalar_t* __restrict__ q,         // [num_seqs, num_heads, head_size]
-  const scalar_t* __restrict__ k_cache,   // [num_blocks, num_kv_heads, head_size/x, block_size, x]
-  const scalar_t* __restrict__ v_cache,   // [num_blocks, num_kv_heads, head_size, block_size]
+  const cache_t* __restrict__ k_cache,    // [num_blocks, num_kv_heads, head_size/x, block_size, x]
+  const cache_t* __restrict__ v_cache,    // [num_blocks, num_kv_heads, head_size, block_size]
   const int num_kv_heads,                 // [num_heads]
   const float scale,
   const float softcapping,
@@ -111,7 +116,9 @@ __device__ void paged_attention_kernel(
   const float* __restrict__ alibi_slopes, // [num_heads]
   const int q_stride,
   const int kv_block_stride,
-  const int kv_head_stride) {
+  const int kv_head_stride,
+  const int v_block_stride,
+  const int v_head_stride) {
   const int seq_idx = blockIdx.y;
   const int partition_idx = blockIdx.z;
   const int max_num_partitions = gridDim.z;
@@ -189,7 +196,7 @@ __device__ void paged_attention_kernel(
 
   // x == THREAD_GROUP_SIZE * VEC_SIZE
   // Each thread group fetches x elements from the key at a time.
-  constexpr int x = 16 / sizeof(scalar_t);
+  constexpr int x = 16 / sizeof(cache_t);
   float qk_max = -FLT_MAX;
 
   // Iterate over the key blocks.
@@ -215,18 +222,34 @@ __device__ void paged_attention_kernel(
 
 #pragma unroll
       for (int j = 0; j < NUM_VECS_PER_THREAD; j++) {
-        const scalar_t* k_ptr = k_cache + physical_block_number * kv_block_stride
-                                        + kv_head_idx * kv_head_stride
-                                        + physical_block_offset * x;
+        const cache_t* k_ptr = k_cache + physical_block_number * kv_block_stride
+                                       + kv_head_idx * kv_head_stride
+                                       + physical_block_offset * x;
         const int vec_idx = thread_group_offset + j * THREAD_GROUP_SIZE;
         const int offset1 = (vec_idx * VEC_SIZE) / x;
         const int offset2 = (vec_idx * VEC_SIZE) % x;
-        k_vecs[j] = *reinterpret_cast<const K_vec*>(k_ptr + offset1 * BLOCK_SIZE * x + offset2);
+        if constexpr (IS_FP8_KV_CACHE) {
+          // The scale is applied to the dot product below.
+          const cache_t* k_src = k_ptr + offset1 * BLOCK_SIZE * x + offset2;
+          scalar_t* k_vec_ptr = reinterpret_cast<scalar_t*>(&k_vecs[j]);
+#pragma unroll
+          for (int e = 0; e < VEC_SIZE; e++) {
+            from_float(k_vec_ptr[e], fp8::e4m3_to_float(k_src[e]));
+          }
+        } else {
+          k_vecs[j] = *reinterpret_cast<const K_vec*>(k_ptr + offset1 * BLOCK_SIZE * x + offset2);
+        }
       }
 
       // Compute dot product.
       // This includes a reduction across the threads in the same thread group.
       float qk = scale * Qk_dot<scalar_t, THREAD_GROUP_SIZE>::dot(q_vecs[thread_group_offset], k_vecs);
+      if constexpr (IS_FP8_KV_CACHE) {
+        const cache_t* k_exps = v_cache + physical_block_number * v_block_stride
+                                        + kv_head_idx * v_head_stride
+                                        + HEAD_SIZE * BLOCK_SIZE;
+        qk *= fp8::exponent_to_scale(k_exps[physical_block_offset]);
+      }
       
       // Apply softcapping
       if (softcapping != 1.0) {
@@ -323,17 +346,40 @@ __device__ void paged_attention_kernel(
     const int64_t physical_block_number = static_cast<int64_t>(block_table[block_idx]);
     const int physical_block_offset = (lane % NUM_V_VECS_PER_ROW) * V_VEC_SIZE;
     const int token_idx = block_idx * BLOCK_SIZE + physical_block_offset;
+    const cache_t* v_ptr = v_cache + physical_block_number * v_block_stride
+                                   + kv_head_idx * v_head_stride;
     L_vec logits_vec;
-    from_float(logits_vec, *reinterpret_cast<Float_L_vec*>(logits + token_idx - start_token_idx));
+    if constexpr (IS_FP8_KV_CACHE) {
+      // Fold the value scale of each token into its probability.
+      const cache_t* v_exps = v_ptr + (HEAD_SIZE + 1) * BLOCK_SIZE + physical_block_offset;
+      Float_L_vec scaled_logits;
+      float* scaled_logits_ptr = reinterpret_cast<float*>(&scaled_logits);
+#pragma unroll
+      for (int j = 0; j < V_VEC_SIZE; j++) {
+        scaled_logits_ptr[j] = token_idx + j < context_len
+          ? logits[token_idx - start_token_idx + j] * fp8::exponent_to_scale(v_exps[j])
+          : 0.f;
+      }
+      from_float(logits_vec, scaled_logits);
+    } else {
+      from_float(logits_vec, *reinterpret_cast<Float_L_vec*>(logits + token_idx - start_token_idx));
+    }
 
-    const scalar_t* v_ptr = v_cache + physical_block_number * kv_block_stride
-                                    + kv_head_idx * kv_head_stride;
 #pragma unroll
     for (int i = 0; i < NUM_ROWS_PER_THREAD; i++) {
       const int row_idx = lane / NUM_V_VECS_PER_ROW + i * NUM_ROWS_PER_ITER;
       if (row_idx < HEAD_SIZE) {
         const int offset = row_idx * BLOCK_SIZE + physical_block_offset;
-        V_vec v_vec = *reinterpret_cast<const V_vec*>(v_ptr + offset);
+        V_vec v_vec;
+        if constexpr (IS_FP8_KV_CACHE) {
+          scalar_t* v_vec_ptr = reinterpret_cast<scalar_t*>(&v_vec);
+#pragma unroll
+          for (int j = 0; j < V_VEC_SIZE; j++) {
+            from_float(v_vec_ptr[j], fp8::e4m3_to_float(v_ptr[offset + j]));
+          }
+        } else {
+          v_vec = *reinterpret_cast<const V_vec*>(v_ptr + offset);
+        }
         if (block_idx == num_context_blocks - 1) {
           // NOTE(woosuk): When v_vec contains the tokens that are out of the context,
           // we should explicitly zero out the values since they may contain NaNs.
@@ -414,14 +460,16 @@ __device__ void paged_attention_kernel(
 // Grid: (num_heads, num_seqs, 1).
 template<
   typename scalar_t,
+  typename cache_t,
+  bool IS_FP8_KV_CACHE,
   int HEAD_SIZE,
   int BLOCK_SIZE,
   int NUM_THREADS>
 __global__ void paged_attention_v1_kernel(
   scalar_t* __restrict__ out,             // [num_seqs, num_heads, head_size]
   const scalar_t* __restrict__ q,         // [num_seqs, num_heads, head_size]
-  const scalar_t* __restrict__ k_cache,   // [num_blocks, num_kv_heads, head_size/x, block_size, x]
-  const scalar_t* __restrict__ v_cache,   // [num_blocks, num_kv_heads, head_size, block_size]
+  const cache_t* __restrict__ k_cache,    // [num_blocks, num_kv_heads, head_size/x, block_size, x]
+  const cache_t* __restrict__ v_cache,    // [num_blocks, num_kv_heads, head_size, block_size]
   const int num_kv_heads,                 // [num_heads]
   const float scale,
   const float softcapping,
@@ -431,16 +479,21 @@ __global__ void paged_attention_v1_kernel(
   const float* __restrict__ alibi_slopes, // [num_heads]
   const int q_stride,
   const int kv_block_stride,
-  const int kv_head_stride) {
-  paged_attention_kernel<scalar_t, HEAD_SIZE, BLOCK_SIZE, NUM_THREADS>(
+  const int kv_head_stride,
+  const int v_block_stride,
+  const int v_head_stride) {
+  paged_attention_kernel<scalar_t, cache_t, IS_FP8_KV_CACHE, HEAD_SIZE, BLOCK_SIZE, NUM_THREADS>(
     /* exp_sums */ nullptr, /* max_logits */ nullptr,
     out, q, k_cache, v_cache, num_kv_heads, scale, softcapping, block_tables, context_lens,
-    max_num_blocks_per_seq, alibi_slopes, q_stride, kv_block_stride, kv_head_stride);
+    max_num_blocks_per_seq, alibi_slopes, q_stride, kv_block_stride, kv_head_stride,
+    v_block_stride, v_head_stride);
 }
 
 // Grid: (num_heads, num_seqs, max_num_partitions).
 template<
   typename scalar_t,
+  typename cache_t,
+  bool IS_FP8_KV_CACHE,
   int HEAD_SIZE,
   int BLOCK_SIZE,
   int NUM_THREADS,
@@ -450,8 +503,8 @@ __global__ void paged_attention_v2_kernel(
   float* __restrict__ max_logits,         // [num_seqs, num_heads, max_num_partitions]
   scalar_t* __restrict__ tmp_out,         // [num_seqs, num_heads, max_num_partitions, head_size]
   const scalar_t* __restrict__ q,         // [num_seqs, num_heads, head_size]
-  const scalar_t* __restrict__ k_cache,   // [num_blocks, num_kv_heads, head_size/x, block_size, x]
-  const scalar_t* __restrict__ v_cache,   // [num_blocks, num_kv_heads, head_size, block_size]
+  const cache_t* __restrict__ k_cache,    // [num_blocks, num_kv_heads, head_size/x, block_size, x]
+  const cache_t* __restrict__ v_cache,    // [num_blocks, num_kv_heads, head_size, block_size]
   const int num_kv_heads,                 // [num_heads]
   const float scale,
   const float softcapping,
@@ -461,11 +514,13 @@ __global__ void paged_attention_v2_kernel(
   const float* __restrict__ alibi_slopes, // [num_heads]
   const int q_stride,
   const int kv_block_stride,
-  const int kv_head_stride) {
-  paged_attention_kernel<scalar_t, HEAD_SIZE, BLOCK_SIZE, NUM_THREADS, PARTITION_SIZE>(
+  const int kv_head_stride,
+  const int v_block_stride,
+  const int v_head_stride) {
+  paged_attention_kernel<scalar_t, cache_t, IS_FP8_KV_CACHE, HEAD_SIZE, BLOCK_SIZE, NUM_THREADS, PARTITION_SIZE>(
     exp_sums, max_logits, tmp_out, q, k_cache, v_cache, num_kv_heads, scale, softcapping,
     block_tables, context_lens, max_num_blocks_per_seq, alibi_slopes,
-    q_stride, kv_block_stride, kv_head_stride);
+    q_stride, kv_block_stride, kv_head_stride, v_block_stride, v_head_stride);
 }
 
 // Grid: (num_heads, num_seqs).
@@ -571,14 +626,16 @@ __global__ void paged_attention_v2_reduce_kernel(
 
 #define LAUNCH_PAGED_ATTENTION_V1(HEAD_SIZE)                                                  \
   VLLM_DevFuncAttribute_SET_MaxDynamicSharedMemorySize(                                       \
-    ((void*)vllm::paged_attention_v1_kernel<T, HEAD_SIZE, BLOCK_SIZE, NUM_THREADS>),          \
+    ((void*)vllm::paged_attention_v1_kernel<T, CACHE_T, IS_FP8_KV_CACHE, HEAD_SIZE,           \
+      BLOCK_SIZE, NUM_THREADS>),                                                              \
     shared_mem_size);                                                                         \
-  vllm::paged_attention_v1_kernel<T, HEAD_SIZE, BLOCK_SIZE, NUM_THREADS>                      \
+  vllm::paged_attention_v1_kernel<T, CACHE_T, IS_FP8_KV_CACHE, HEAD_SIZE, BLOCK_SIZE,         \
+    NUM_THREADS>                                                                              \
   <<<grid, block, shared_mem_size, stream>>>(                                                 \
     reinterpret_cast<T*>(out),                                                                \
     reinterpret_cast<T*>(query),                                                              \
-    reinterpret_cast<T*>(key_cache),                                                          \
-    reinterpret_cast<T*>(value_cache),                                                        \
+    reinterpret_cast<CACHE_T*>(key_cache),                                                    \
+    reinterpret_cast<CACHE_T*>(value_cache),                                                  \
     num_kv_heads,                                                                             \
     scale,                                                                                    \
     softcapping,                                                                              \
@@ -588,11 +645,15 @@ __global__ void paged_attention_v2_reduce_kernel(
     reinterpret_cast<float*>(alibi_slopes),                                                   \
     q_stride,                                                                                 \
     kv_block_stride,                                                                          \
-    kv_head_stride);
+    kv_head_stride,                                                                           \
+    v_block_stride,                                                                           \
+    v_head_stride);
 
 // TODO(woosuk): Tune NUM_THREADS.
 template<
   typename T,
+  typename CACHE_T,
+  bool IS_FP8_KV_CACHE,
   int BLOCK_SIZE,
   int NUM_THREADS = 128>
 void paged_attention_v1_launcher(
@@ -614,7 +675,9 @@ void paged_attention_v1_launcher(
   int max_num_blocks_per_seq,
   int q_stride,
   int kv_block_stride,
-  int kv_head_stride
+  int kv_head_stride,
+  int v_block_stride,
+  int v_head_stride
   ) {
 
   // int thread_group_size = MAX(WARP_SIZE / BLOCK_SIZE, 1);
@@ -663,8 +726,8 @@ void paged_attention_v1_launcher(
   }
 }
 
-#define CALL_V1_LAUNCHER(T, BLOCK_SIZE)                             \
-  paged_attention_v1_launcher<T, BLOCK_SIZE>(                       \
+#define CALL_V1_LAUNCHER(T, CACHE_T, IS_FP8_KV_CACHE, BLOCK_SIZE)    \
+  paged_attention_v1_launcher<T, CACHE_T, IS_FP8_KV_CACHE, BLOCK_SIZE>( \
     out,                                                            \
     query,                                                          \
     key_cache,                                                      \
@@ -682,20 +745,22 @@ void paged_attention_v1_launcher(
     max_num_blocks_per_seq,                                         \
     q_stride,                                                       \
     kv_block_stride,                                                \
-    kv_head_stride);
+    kv_head_stride,                                                 \
+    v_block_stride,                                                 \
+    v_head_stride);
 
 // NOTE(woosuk): To reduce the compilation time, we omitted block sizes
 // 1, 2, 4, 64, 128, 256.
-#define CALL_V1_LAUNCHER_BLOCK_SIZE(T)                              \
+#define CALL_V1_LAUNCHER_BLOCK_SIZE(T, CACHE_T, IS_FP8_KV_CACHE)     \
   switch (block_size) {                                             \
     case 8:                                                         \
-      CALL_V1_LAUNCHER(T, 8);                                       \
+      CALL_V1_LAUNCHER(T, CACHE_T, IS_FP8_KV_CACHE, 8);             \
       break;                                                        \
     case 16:                                                        \
-      CALL_V1_LAUNCHER(T, 16);                                      \
+      CALL_V1_LAUNCHER(T, CACHE_T, IS_FP8_KV_CACHE, 16);            \
       break;                                                        \
     case 32:                                                        \
-      CALL_V1_LAUNCHER(T, 32);                                      \
+      CALL_V1_LAUNCHER(T, CACHE_T, IS_FP8_KV_CACHE, 32);            \
       break;                                                        \
     default:                                                        \
       break;                                                        \
@@ -705,7 +770,7 @@ extern "C" void paged_attention_v1(
   void *out,             // [num_seqs, num_heads, head_size]
   void *query,           // [num_seqs, num_heads, head_size]
   void *key_cache,       // [num_blocks, num_heads, head_size/x, block_size, x]
-  void *value_cache,     // [num_blocks, num_heads, head_size, block_size]
+  void *value_cache,     // [num_blocks, num_heads, head_size (+ 2 for fp8), block_size]
   void *alibi_slopes, // [num_heads]
   int32_t num_kv_heads,
   float scale,
@@ -722,27 +787,39 @@ extern "C" void paged_attention_v1(
   int32_t q_stride,
   int32_t kv_block_stride,
   int32_t kv_head_stride,
+  int32_t v_block_stride,
+  int32_t v_head_stride,
 
-  uint32_t dtype      // 0 => f16; 1 => bf16; 2 => f32
+  uint32_t dtype,      // 0 => f16; 1 => bf16; 2 => f32
+  uint32_t cache_dtype // 0 => same as dtype; 1 => fp8 e4m3
   ) {
-  if (dtype == 2) {
-    CALL_V1_LAUNCHER_BLOCK_SIZE(float);
+  if (cache_dtype == 1) {
+    if (dtype == 2) {
+      CALL_V1_LAUNCHER_BLOCK_SIZE(float, uint8_t, true);
+    } else if (dtype == 0) {
+      CALL_V1_LAUNCHER_BLOCK_SIZE(uint16_t, uint8_t, true);
+    } else if (dtype == 1) {
+      CALL_V1_LAUNCHER_BLOCK_SIZE(__nv_bfloat16, uint8_t, true);
+    }
+  } else if (dtype == 2) {
+    CALL_V1_LAUNCHER_BLOCK_SIZE(float, float, false);
   } else if (dtype == 0) {
-    CALL_V1_LAUNCHER_BLOCK_SIZE(uint16_t);
+    CALL_V1_LAUNCHER_BLOCK_SIZE(uint16_t, uint16_t, false);
   } else if (dtype == 1) {
-    CALL_V1_LAUNCHER_BLOCK_SIZE(__nv_bfloat16);
+    CALL_V1_LAUNCHER_BLOCK_SIZE(__nv_bfloat16, __nv_bfloat16, false);
   }
 }
 
 #define LAUNCH_PAGED_ATTENTION_V2(HEAD_SIZE)                                                  \
-  vllm::paged_attention_v2_kernel<T, HEAD_SIZE, BLOCK_SIZE, NUM_THREADS, PARTITION_SIZE>      \
+  vllm::paged_attention_v2_kernel<T, CACHE_T, IS_FP8_KV_CACHE, HEAD_SIZE, BLOCK_SIZE,         \
+    NUM_THREADS, PARTITION_SIZE>                                                              \
   <<<grid, block, shared_mem_size, stream>>>(                                                 \
     exp_sums,                                                                                 \
     max_logits,                                                                               \
     tmp_out_ptr,                                                                              \
     reinterpret_cast<T*>(query),                                                              \
-    reinterpret_cast<T*>(key_cache),                                                          \
-    reinterpret_cast<T*>(value_cache),                                                        \
+    reinterpret_cast<CACHE_T*>(key_cache),                                                    \
+    reinterpret_cast<CACHE_T*>(value_cache),                                                  \
     num_kv_heads,                                                                             \
     scale,                                                                                    \
     softcapping,                                                                              \
@@ -752,7 +829,9 @@ extern "C" void paged_attention_v1(
     reinterpret_cast<float*>(alibi_slopes),                                                   \
     q_stride,                                                                                 \
     kv_block_stride,                                                                          \
-    kv_head_stride);                                                                          \
+    kv_head_stride,                                                                           \
+    v_block_stride,                                                                           \
+    v_head_stride);                                                                           \
   vllm::paged_attention_v2_reduce_kernel<T, HEAD_SIZE, NUM_THREADS, PARTITION_SIZE>           \
   <<<reduce_grid, block, reduce_shared_mem_size, stream>>>(                                   \
     reinterpret_cast<T*>(out),                                                                \
@@ -764,6 +843,8 @@ extern "C" void paged_attention_v1(
 
 template<
   typename T,
+  typename CACHE_T,
+  bool IS_FP8_KV_CACHE,
   int BLOCK_SIZE,
   int NUM_THREADS = 128,
   int PARTITION_SIZE = 512>
@@ -789,7 +870,9 @@ void paged_attention_v2_launcher(
   int max_num_blocks_per_seq,
   int q_stride,
   int kv_block_stride,
-  int kv_head_stride
+  int kv_head_stride,
+  int v_block_stride,
+  int v_head_stride
 
   ) {
   // int thread_group_size = MAX(WARP_SIZE / BLOCK_SIZE, 1);
@@ -842,8 +925,8 @@ void paged_attention_v2_launcher(
   }
 }
 
-#define CALL_V2_LAUNCHER(T, BLOCK_SIZE)                             \
-  paged_attention_v2_launcher<T, BLOCK_SIZE>(                       \
+#define CALL_V2_LAUNCHER(T, CACHE_T, IS_FP8_KV_CACHE, BLOCK_SIZE)    \
+  paged_attention_v2_launcher<T, CACHE_T, IS_FP8_KV_CACHE, BLOCK_SIZE>( \
     out,                                                            \
     exp_sums,                                                       \
     max_logits,                                                     \
@@ -864,20 +947,22 @@ void paged_attention_v2_launcher(
     max_num_blocks_per_seq,                                         \
     q_stride,                                                       \
     kv_block_stride,                                                \
-    kv_head_stride);
+    kv_head_stride,                                                 \
+    v_block_stride,                                                 \
+    v_head_stride);
 
 // NOTE(woosuk): To reduce the compilation time, we omitted block sizes
 // 1, 2, 4, 64, 128, 256.
-#define CALL_V2_LAUNCHER_BLOCK_SIZE(T)                              \
+#define CALL_V2_LAUNCHER_BLOCK_SIZE(T, CACHE_T, IS_FP8_KV_CACHE)     \
   switch (block_size) {                                             \
     case 8:                                                         \
-      CALL_V2_LAUNCHER(T, 8);                                       \
+      CALL_V2_LAUNCHER(T, CACHE_T, IS_FP8_KV_CACHE, 8);             \
       break;                                                        \
     case 16:                                                        \
-      CALL_V2_LAUNCHER(T, 16);                                      \
+      CALL_V2_LAUNCHER(T, CACHE_T, IS_FP8_KV_CACHE, 16);            \
       break;                                                        \
     case 32:                                                        \
-      CALL_V2_LAUNCHER(T, 32);                                      \
+      CALL_V2_LAUNCHER(T, CACHE_T, IS_FP8_KV_CACHE, 32);            \
       break;                                                        \
     default:                                                        \
       break;                                                        \
@@ -890,7 +975,7 @@ extern "C" void paged_attention_v2(
   void *tmp_out,         // [num_seqs, num_heads, max_num_partitions, head_size]
   void *query,           // [num_seqs, num_heads, head_size]
   void *key_cache,       // [num_blocks, num_heads, head_size/x, block_size, x]
-  void *value_cache,     // [num_blocks, num_heads, head_size, block_size]
+  void *value_cache,     // [num_blocks, num_heads, head_size (+ 2 for fp8), block_size]
   void *alibi_slopes, // [num_heads]
   int32_t num_kv_heads,
   float scale,
@@ -907,15 +992,26 @@ extern "C" void paged_attention_v2(
   int32_t q_stride,
   int32_t kv_block_stride,
   int32_t kv_head_stride,
+  int32_t v_block_stride,
+  int32_t v_head_stride,
 
-  uint32_t dtype      // 0 => f16; 1 => bf16; 2 => f32
+  uint32_t dtype,      // 0 => f16; 1 => bf16; 2 => f32
+  uint32_t cache_dtype // 0 => same as dtype; 1 => fp8 e4m3
   ) {
-  if (dtype == 2) {
-    CALL_V2_LAUNCHER_BLOCK_SIZE(float);
+  if (cache_dtype == 1) {
+    if (dtype == 2) {
+      CALL_V2_LAUNCHER_BLOCK_SIZE(float, uint8_t, true);
+    } else if (dtype == 0) {
+      CALL_V2_LAUNCHER_BLOCK_SIZE(uint16_t, uint8_t, true);
+    } else if (dtype == 1) {
+      CALL_V2_LAUNCHER_BLOCK_SIZE(__nv_bfloat16, uint8_t, true);
+    }
+  } else if (dtype == 2) {
+    CALL_V2_LAUNCHER_BLOCK_SIZE(float, float, false);
   } else if (dtype == 0) {
-    CALL_V2_LAUNCHER_BLOCK_SIZE(uint16_t);
+    CALL_V2_LAUNCHER_BLOCK_SIZE(uint16_t, uint16_t, false);
   } else if (dtype == 1) {
-    CALL_V2_LAUNCHER_BLOCK_SIZE(__nv_bfloat16);
+    CALL_V2_LAUNCHER_BLOCK_SIZE(__nv_bfloat16, __nv_bfloat16, false);
   }
 }
 
@@ -3,6 +3,8 @@
 #include <stdint.h>
 
 #include "cuda_compat.h"
+#include "attention/attention_dtypes.h"
+#include "attention/dtype_fp8.cuh"
 
 #include <algorithm>
 #include <cassert>
@@ -58,6 +60,87 @@ __global__ void reshape_and_cache_kernel(
   }
 }
 
+// FP8 E4M3 variant of `reshape_and_cache_kernel`. Each (token, head) of the key and the value is
+// scaled by its own power of two scale, the exponents of which are stored in the value cache.
+// Requires `2 * num_heads` ints of shared memory.
+template<typename scalar_t>
+__global__ void reshape_and_cache_fp8_kernel(
+  const scalar_t* __restrict__ key,           // [num_tokens, num_heads, head_size]
+  const scalar_t* __restrict__ value,         // [num_tokens, num_heads, head_size]
+  uint8_t* __restrict__ key_cache,            // [num_blocks, num_heads, head_size/x, block_size, x]
+  uint8_t* __restrict__ value_cache,          // [num_blocks, num_heads, head_size + 2, block_size]
+  const int64_t* __restrict__ slot_mapping,   // [num_tokens]
+  const int key_stride,
+  const int value_stride,
+  const int num_heads,
+  const int head_size,
+  const int block_size,
+  const int x) {
+  const int64_t token_idx = blockIdx.x;
+  const int64_t slot_idx = slot_mapping[token_idx];
+  if (slot_idx < 0) {
+    // Padding token that should be ignored.
+    return;
+  }
+
+  const int64_t block_idx = slot_idx / block_size;
+  const int64_t block_offset = slot_idx % block_size;
+  const int value_rows = head_size + fp8::NUM_SCALE_ROWS;
+
+  // Absolute maximum of each head of the key and value. Non negative floats compare like their bits.
+  extern __shared__ unsigned int amax_bits[];
+  for (int i = threadIdx.x; i < 2 * num_heads; i += blockDim.x) {
+    amax_bits[i] = 0;
+  }
+  __syncthreads();
+
+  const int n = num_heads * head_size;
+  for (int i = threadIdx.x; i < n; i += blockDim.x) {
+    const int head_idx = i / head_size;
+    const float k = fabsf(to_float(key[token_idx * key_stride + i]));
+    const float v = fabsf(to_float(value[token_idx * value_stride + i]));
+    atomicMax(&amax_bits[head_idx], __float_as_uint(k));
+    atomicMax(&amax_bits[num_heads + head_idx], __float_as_uint(v));
+  }
+  __syncthreads();
+
+  for (int head_idx = threadIdx.x; head_idx < num_heads; head_idx += blockDim.x) {
+    const int64_t tgt_scale_idx = block_idx * num_heads * value_rows * block_size
+                                  + head_idx * value_rows * block_size
+                                  + head_size * block_size
+                                  + block_offset;
+    value_cache[tgt_scale_idx] =
+      static_cast<uint8_t>(fp8::scale_exponent(__uint_as_float(amax_bits[head_idx])));
+    value_cache[tgt_scale_idx + block_size] =
+      static_cast<uint8_t>(fp8::scale_exponent(__uint_as_float(amax_bits[num_heads + head_idx])));
+  }
+
+  for (int i = threadIdx.x; i < n; i += blockDim.x) {
+    const int64_t src_key_idx = token_idx * key_stride + i;
+    const int64_t src_value_idx = token_idx * value_stride + i;
+
+    const int head_idx = i / head_size;
+    const int head_offset = i % head_size;
+    const int x_idx = head_offset / x;
+    const int x_offset = head_offset % x;
+
+    const int64_t tgt_key_idx = block_idx * num_heads * (head_size / x) * block_size * x
+                                + head_idx * (head_size / x) * block_size * x
+                                + x_idx * block_size * x
+                                + block_offset * x
+                                + x_offset;
+    const int64_t tgt_value_idx = block_idx * num_heads * value_rows * block_size
+                                  + head_idx * value_rows * block_size
+                                  + head_offset * block_size
+                                  + block_offset;
+    const float k_inv_scale = ldexpf(1.f, -fp8::scale_exponent(__uint_as_float(amax_bits[head_idx])));
+    const float v_inv_scale =
+      ldexpf(1.f, -fp8::scale_exponent(__uint_as_float(amax_bits[num_heads + head_idx])));
+    key_cache[tgt_key_idx] = fp8::float_to_e4m3(to_float(key[src_key_idx]) * k_inv_scale);
+    value_cache[tgt_value_idx] = fp8::float_to_e4m3(to_float(value[src_value_idx]) * v_inv_scale);
+  }
+}
+
 #define CALL_RESHAPE_AND_CACHE(T)                                     \
   vllm::reshape_and_cache_kernel<T><<<grid, block, 0, stream>>>(      \
     reinterpret_cast<T*>(key),                                        \
@@ -72,6 +155,20 @@ __global__ void reshape_and_cache_kernel(
     block_size,                                                       \
     x);
 
+#define CALL_RESHAPE_AND_CACHE_FP8(T)                                 \
+  vllm::reshape_and_cache_fp8_kernel<T>                               \
+  <<<grid, block, 2 * num_heads * sizeof(unsigned int), stream>>>(    \
+    reinterpret_cast<T*>(key),                                        \
+    reinterpret_cast<T*>(value),                                      \
+    reinterpret_cast<uint8_t*>(key_cache),                            \
+    reinterpret_cast<uint8_t*>(value_cache),                          \
+    slot_mapping,                                                     \
+    key_stride,                                                       \
+    value_stride,                                                     \
+    num_heads,                                                        \
+    head_size,                                                        \
+    block_size,                                                       \
+    x);
 
 } // namespace vllm
 
@@ -79,7 +176,7 @@ extern "C" void reshape_and_cache(
   void *key,              // [num_tokens, num_heads, head_size]
   void *value,            // [num_tokens, num_heads, head_size]
   void *key_cache,        // [num_blocks, num_heads, head_size/x, block_size, x]
-  void *value_cache,      // [num_blocks, num_heads, head_size, block_size]
+  void *value_cache,      // [num_blocks, num_heads, head_size (+ 2 for fp8), block_size]
   int64_t* slot_mapping,  // [num_tokens]
 
   int32_t num_tokens,
@@ -90,14 +187,23 @@ extern "C" void reshape_and_cache(
   int32_t key_stride,
   int32_t value_stride,
 
-  uint32_t dtype      // 0 => f16; 1 => bf16; 2 => f32
+  uint32_t dtype,      // 0 => f16; 1 => bf16; 2 => f32
+  uint32_t cache_dtype // 0 => same as dtype; 1 => fp8 e4m3
   )
 {
   dim3 grid(num_tokens);
   dim3 block(std::min(num_heads * head_size, 512));
   const cudaStream_t stream = 0;
 
-  if (dtype == 0){
+  if (cache_dtype == 1) {
+    if (dtype == 0){
+      CALL_RESHAPE_AND_CACHE_FP8(uint16_t);
+    } else if (dtype == 1) {
+      CALL_RESHAPE_AND_CACHE_FP8(__nv_bfloat16);
+    } else if (dtype == 2) {
+      CALL_RESHAPE_AND_CACHE_FP8(float);
+    }
+  } else if (dtype == 0){
     CALL_RESHAPE_AND_CACHE(uint16_t);
   } else if (dtype == 1) {
     CALL_RESHAPE_AND_CACHE(__nv_bfloat16);
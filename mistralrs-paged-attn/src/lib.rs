@@ -1,3 +1,8 @@
+/// Number of extra rows of the FP8 E4M3 value cache, of shape
+/// `(num_blocks, num_kv_heads, head_size + FP8_KV_CACHE_SCALE_ROWS, block_size)`. They hold the
+/// power of two scales of the keys and values of each token, as signed exponents.
+pub const FP8_KV_CACHE_SCALE_ROWS: usize = 2;
+
 #[cfg(all(feature = "cuda", target_family = "unix"))]
 mod cuda;
 #[cfg(all(feature = "cuda", target_family = "unix"))]
@@ -34,6 +34,12 @@ impl candle_core::CustomOp1 for PagedAttention {
             DType::F32 => PagedAttentionDType::F32,
             dtype => candle_core::bail!("dtype {dtype:?} is not supported"),
         };
+        if self.key_cache.dtype() != dtype {
+            candle_core::bail!(
+                "KV cache dtype {:?} is not supported on Metal for {dtype:?} inputs",
+                self.key_cache.dtype()
+            );
+        }
 
         let dev = q.device();
         let out_shape = q_l.shape().clone();
@@ -324,6 +330,12 @@ pub fn reshape_and_cache(
         DType::F32 => PagedAttentionDType::F32,
         dtype => candle_core::bail!("dtype {dtype:?} is not supported"),
     };
+    if key_cache.dtype() != dtype {
+        candle_core::bail!(
+            "KV cache dtype {:?} is not supported on Metal for {dtype:?} inputs",
+            key_cache.dtype()
+        );
+    }
 
     let (k, k_l) = key.storage_and_layout();
     let k = match &*k {
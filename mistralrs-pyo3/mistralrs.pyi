@@ -39,6 +39,8 @@ class ChatCompletionRequest:
     min_p: float | None = None
     tool_schemas: list[str] | None = None
     tool_choice: ToolChoice | None = None
+    chat_template_kwargs: str | None = None
+    continue_final_message: bool = False
 
 @dataclass
 class CompletionRequest:
@@ -81,6 +83,9 @@ class Architecture(Enum):
     Phi3_5MoE = "phi3.5moe"
     DeepseekV2 = "deepseekv2"
     DeepseekV3 = "deepseekv3"
+    Mamba = "mamba"
+    Mamba2 = "mamba2"
+    Jamba = "jamba"
 
 @dataclass
 class VisionArchitecture(Enum):
@@ -97,6 +102,21 @@ class VisionArchitecture(Enum):
 class DiffusionArchitecture(Enum):
     Flux = "flux"
     FluxOffloaded = "flux-offloaded"
+    StableDiffusion = "stable-diffusion"
+    StableDiffusionXl = "stable-diffusion-xl"
+
+@dataclass
+class SpeechArchitecture(Enum):
+    Whisper = "whisper"
+
+@dataclass
+class EmbeddingArchitecture(Enum):
+    Bert = "bert"
+    XLMRoberta = "xlmroberta"
+    ModernBert = "modernbert"
+    Llama = "llama"
+    Mistral = "mistral"
+    Qwen2 = "qwen2"
 
 @dataclass
 class IsqOrganization(Enum):
@@ -115,6 +135,11 @@ class ImageGenerationResponseFormat(Enum):
     Url = "url"
     B64Json = "b64json"
 
+@dataclass
+class TranscriptionTask(Enum):
+    Transcribe = "transcribe"
+    Translate = "translate"
+
 @dataclass
 class TextAutoMapParams:
     """
@@ -265,12 +290,35 @@ class Which(Enum):
         calibration_file: str | None = None
         imatrix: str | None = None
 
+    @dataclass
+    class VisionGGUF:
+        tok_model_id: str
+        quantized_model_id: str
+        quantized_filename: str | list[str]
+        mmproj_filename: str
+        arch: VisionArchitecture
+        topology: str | None = None
+        dtype: ModelDType = ModelDType.Auto
+        auto_map_params: VisionAutoMapParams | None = (None,)
+
     @dataclass
     class DiffusionPlain:
         model_id: str
         arch: DiffusionArchitecture
         dtype: ModelDType = ModelDType.Auto
 
+    @dataclass
+    class Speech:
+        model_id: str
+        arch: SpeechArchitecture
+        dtype: ModelDType = ModelDType.Auto
+
+    @dataclass
+    class Embedding:
+        model_id: str
+        arch: EmbeddingArchitecture
+        dtype: ModelDType = ModelDType.Auto
+
 class Runner:
     def __init__(
         self,
@@ -351,9 +399,73 @@ class Runner:
         response_format: ImageGenerationResponseFormat,
         height: int = 720,
         width: int = 1280,
+        n: int = 1,
+        num_steps: int | None = None,
+        guidance_scale: float | None = None,
+        seed: int | None = None,
+        negative_prompt: str | None = None,
+        true_cfg_scale: float = 4.0,
+        image: str | None = None,
+        strength: float = 0.6,
+        mask: str | None = None,
     ) -> ImageGenerationResponse:
         """
-        Generate an image.
+        Generate `n` images.
+
+        - `num_steps`: Number of denoising steps. Defaults to the model default.
+        - `guidance_scale`: Distilled guidance scale, for models with guidance distillation such as FLUX.1-dev. For Stable Diffusion, this is the classifier-free guidance scale.
+        - `seed`: Seed of the initial noise. The `n` images use consecutive seeds.
+        - `negative_prompt`: Steer the image away from this prompt with classifier-free guidance at `true_cfg_scale`.
+        - `image`: Image to start from (img2img), as a URL, path or base64 string. `strength` controls how much it is changed.
+        - `mask`: Inpainting mask for the `image`. White areas are regenerated and black areas are kept.
+        """
+
+    def transcribe(
+        self,
+        audio_url: str,
+        language: str | None = None,
+        task: TranscriptionTask = TranscriptionTask.Transcribe,
+        timestamps: bool = True,
+        prompt: str | None = None,
+        temperature: float = 0.0,
+        pcm16_sample_rate: int | None = None,
+    ) -> TranscriptionResponse:
+        """
+        Transcribe (or translate to English) the speech in a WAV file, given as a URL, local path or base64 string.
+        If `pcm16_sample_rate` is specified, the file is read as raw 16-bit mono PCM at that sample rate.
+        The language is detected if it is not specified.
+        """
+
+    def embed(
+        self,
+        inputs: list[str],
+        normalize: bool = True,
+        dimensions: int | None = None,
+    ) -> EmbeddingResponse:
+        """
+        Embed a list of texts with an embedding model, returning one embedding per input in order.
+        The embeddings are L2-normalized unless `normalize` is False, and truncated to the first `dimensions` values if specified.
+        """
+
+    def rerank(
+        self,
+        query: str,
+        documents: list[str],
+        top_n: int | None = None,
+        return_documents: bool = False,
+    ) -> RerankResponse:
+        """
+        Score the relevance of each document to the query with a reranker model.
+        The results are sorted from most to least relevant, keeping only the `top_n` best if specified.
+        """
+
+    def classify(
+        self,
+        inputs: list[str | list[dict[str, str]]],
+    ) -> ClassificationResponse:
+        """
+        Classify a list of texts or conversations with a sequence classification model, or score them with a reward model.
+        A conversation is a list of `{"role": ..., "content": ...}` messages, formatted with the chat template of the model.
         """
 
     def send_re_isq(self, dtype: str) -> CompletionResponse:
@@ -541,3 +653,69 @@ class ImageChoice:
 class ImageGenerationResponse:
     choices: list[ImageChoice]
     created: int
+
+@dataclass
+class TranscriptionSegment:
+    id: int
+    start: float
+    end: float
+    text: str
+    tokens: list[int]
+
+@dataclass
+class TranscriptionResponse:
+    task: str
+    language: str
+    duration: float
+    text: str
+    segments: list[TranscriptionSegment]
+
+@dataclass
+class EmbeddingUsage:
+    prompt_tokens: int
+    total_tokens: int
+
+@dataclass
+class EmbeddingData:
+    object: str
+    embedding: list[float]
+    index: int
+
+@dataclass
+class EmbeddingResponse:
+    object: str
+    data: list[EmbeddingData]
+    model: str
+    usage: EmbeddingUsage
+
+@dataclass
+class RerankDocument:
+    text: str
+
+@dataclass
+class RerankResult:
+    index: int
+    relevance_score: float
+    document: RerankDocument | None
+
+@dataclass
+class RerankResponse:
+    model: str
+    results: list[RerankResult]
+    usage: EmbeddingUsage
+
+@dataclass
+class ClassificationResult:
+    index: int
+    label: str
+    probs: list[float]
+    logits: list[float]
+    reward: float | None
+
+@dataclass
+class ClassificationResponse:
+    object: str
+    model: str
+    labels: list[str]
+    data: list[ClassificationResult]
+    usage: EmbeddingUsage
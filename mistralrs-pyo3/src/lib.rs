@@ -19,16 +19,21 @@ use util::{PyApiErr, PyApiResult};
 
 use candle_core::{Device, Result};
 use mistralrs_core::{
-    initialize_logging, paged_attn_supported, parse_isq_value, AnyMoeLoader, AutoDeviceMapParams,
-    ChatCompletionResponse, CompletionResponse, Constraint, DefaultSchedulerMethod,
-    DetokenizationRequest, DeviceLayerMapMetadata, DeviceMapMetadata, DeviceMapSetting,
-    DiffusionGenerationParams, DiffusionLoaderBuilder, DiffusionSpecificConfig, DrySamplingParams,
+    has_image_placeholders, initialize_logging, join_text_parts, paged_attn_supported,
+    parse_isq_value, prefix_media, AnyMoeLoader, AutoDeviceMapParams, ChatCompletionResponse,
+    ClassificationInput, ClassificationResponse, CompletionResponse, Constraint, ContentPart,
+    DefaultSchedulerMethod, DetokenizationRequest, DeviceLayerMapMetadata, DeviceMapMetadata,
+    DeviceMapSetting, DiffusionGenerationParams, DiffusionLoaderBuilder, DiffusionSpecificConfig,
+    DrySamplingParams, EmbeddingLoaderBuilder, EmbeddingParams, EmbeddingResponse,
     GGMLLoaderBuilder, GGMLSpecificConfig, GGUFLoaderBuilder, GGUFSpecificConfig,
-    ImageGenerationResponse, ImageGenerationResponseFormat, LlguidanceGrammar, Loader,
-    MemoryGpuConfig, MistralRs, MistralRsBuilder, NormalLoaderBuilder, NormalRequest,
-    NormalSpecificConfig, PagedAttentionConfig, Request as _Request, RequestMessage, Response,
-    ResponseOk, SamplingParams, SchedulerConfig, SpeculativeConfig, SpeculativeLoader, StopTokens,
-    TokenSource, TokenizationRequest, Tool, Topology, VisionLoaderBuilder, VisionSpecificConfig,
+    GGUFVisionLoaderBuilder, ImageGenerationResponse, ImageGenerationResponseFormat,
+    LlguidanceGrammar, Loader, MemoryGpuConfig, MistralRs, MistralRsBuilder, ModelCategory,
+    NormalLoaderBuilder, NormalRequest, NormalSpecificConfig, PagedAttentionConfig, PendingMedia,
+    Request as _Request, RequestMessage, RequestPriority, RerankParams, RerankResponse, Response,
+    ResponseOk, SamplingParams, SchedulerConfig, SpeculativeConfig, SpeculativeLoader,
+    SpeechLoaderBuilder, StopTokens, TokenSource, TokenizationRequest, Tool, Topology,
+    TranscriptionParams, TranscriptionResponse, TranscriptionTask, VisionLoaderBuilder,
+    VisionSpecificConfig,
 };
 use pyo3::prelude::*;
 use std::fs::File;
@@ -37,7 +42,10 @@ mod requests;
 mod stream;
 mod util;
 mod which;
-use which::{Architecture, DiffusionArchitecture, VisionArchitecture, Which};
+use which::{
+    Architecture, DiffusionArchitecture, EmbeddingArchitecture, SpeechArchitecture,
+    VisionArchitecture, Which,
+};
 
 static DEVICE: OnceLock<Result<Device>> = OnceLock::new();
 
@@ -378,6 +386,27 @@ fn parse_which(
             Some(model_id),
         )
         .build(arch.into()),
+        Which::VisionGGUF {
+            tok_model_id,
+            quantized_model_id,
+            quantized_filename,
+            mmproj_filename,
+            arch,
+            topology,
+            dtype: _,
+            auto_map_params: _,
+        } => GGUFVisionLoaderBuilder::new(
+            chat_template,
+            tok_model_id,
+            quantized_model_id,
+            quantized_filename.map_left(|f| vec![f]).into_inner(),
+            mmproj_filename,
+            GGUFSpecificConfig {
+                prompt_chunksize,
+                topology: Topology::from_option_path(topology)?,
+            },
+        )
+        .build(arch.into())?,
         Which::DiffusionPlain {
             model_id,
             arch,
@@ -386,6 +415,16 @@ fn parse_which(
             DiffusionLoaderBuilder::new(DiffusionSpecificConfig { use_flash_attn }, Some(model_id))
                 .build(arch.into())
         }
+        Which::Speech {
+            model_id,
+            arch,
+            dtype: _,
+        } => SpeechLoaderBuilder::new(Some(model_id)).build(arch.into()),
+        Which::Embedding {
+            model_id,
+            arch,
+            dtype: _,
+        } => EmbeddingLoaderBuilder::new(Some(model_id)).build(arch.into()),
     })
 }
 
@@ -476,7 +515,10 @@ impl Runner {
             | Which::GGML { .. }
             | Which::LoraGGML { .. }
             | Which::VisionPlain { .. }
-            | Which::DiffusionPlain { .. } => None,
+            | Which::VisionGGUF { .. }
+            | Which::DiffusionPlain { .. }
+            | Which::Speech { .. }
+            | Which::Embedding { .. } => None,
             Which::XLora {
                 tgt_non_granular_index,
                 ..
@@ -498,7 +540,10 @@ impl Runner {
             | Which::GGML { dtype, .. }
             | Which::LoraGGML { dtype, .. }
             | Which::VisionPlain { dtype, .. }
+            | Which::VisionGGUF { dtype, .. }
             | Which::DiffusionPlain { dtype, .. }
+            | Which::Speech { dtype, .. }
+            | Which::Embedding { dtype, .. }
             | Which::XLora { dtype, .. }
             | Which::XLoraGGUF { dtype, .. }
             | Which::XLoraGGML { dtype, .. } => dtype,
@@ -539,6 +584,9 @@ impl Runner {
                 .unwrap_or(AutoDeviceMapParams::default_text()),
             Which::VisionPlain {
                 auto_map_params, ..
+            }
+            | Which::VisionGGUF {
+                auto_map_params, ..
             } => auto_map_params
                 .clone()
                 .map(|p| AutoDeviceMapParams::Vision {
@@ -553,6 +601,7 @@ impl Runner {
                     "diffusion model doesn't support max_seq_len",
                 ))
             }
+            Which::Speech { .. } | Which::Embedding { .. } => AutoDeviceMapParams::default_text(),
         };
         let max_seqs = if tgt_non_granular_index.is_some() {
             1
@@ -774,6 +823,8 @@ impl Runner {
                 Either::Left(ref messages) => {
                     let mut messages_vec = Vec::new();
                     let mut image_urls = Vec::new();
+                    let mut video_parts = Vec::new();
+                    let mut audio_parts = Vec::new();
                     for message in messages {
                         match &message["content"] {
                             Either::Left(content) => {
@@ -791,102 +842,111 @@ impl Runner {
                                 );
                                 messages_vec.push(message_map);
                             }
-                            Either::Right(image_messages) => {
-                                if image_messages.len() != 2 {
-                                    return Err(PyApiErr::from(
-                                        "Expected 2 items for the content of a message with an image."
-                                    ));
+                            Either::Right(content_parts) => {
+                                let role = message["role"].as_ref().left().unwrap().clone();
+                                let mut message_map: IndexMap<
+                                    String,
+                                    Either<String, Vec<IndexMap<String, Value>>>,
+                                > = IndexMap::new();
+                                message_map.insert("role".to_string(), Either::Left(role.clone()));
+
+                                let parts = util::parse_content_parts(content_parts)?;
+                                if let Some(content) = join_text_parts(&parts) {
+                                    // Only text: this can be rendered by any chat template.
+                                    message_map
+                                        .insert("content".to_string(), Either::Left(content));
+                                    messages_vec.push(message_map);
+                                    continue;
                                 }
-                                if message["role"].as_ref().left().unwrap() != "user" {
+
+                                if role != "user" {
                                     return Err(PyApiErr::from(format!(
-                                        "Role for an image message must be `user`, but it is {}",
-                                        &message["role"].as_ref().left().unwrap()
+                                        "Role for an image, video or audio message must be `user`, but it is {role}"
                                     )));
                                 }
+                                let prefixer = match &self.runner.config().category {
+                                    ModelCategory::Vision {
+                                        has_conv2d: _,
+                                        prefixer,
+                                    } => prefixer.clone(),
+                                    ModelCategory::Text
+                                    | ModelCategory::Diffusion
+                                    | ModelCategory::Speech
+                                    | ModelCategory::Embedding
+                                    | ModelCategory::Reranker => return Err(PyApiErr::from(
+                                        "Image, video and audio messages require a vision model.",
+                                    )),
+                                };
 
-                                let mut items = Vec::new();
-                                for image_message in image_messages {
-                                    if image_message.len() != 2 {
-                                        return Err(PyApiErr::from("Expected 2 items for the sub-content of a message with an image.".to_string()));
-                                    }
-                                    if !image_message.contains_key("type") {
-                                        return Err(PyApiErr::from(
-                                            "Expected `type` key in input message.".to_string(),
-                                        ));
+                                // Each text part is prefixed with the placeholders of the images,
+                                // videos and audio clips which directly precede it so that the
+                                // placeholders keep their positions. Images are not prefixed if
+                                // the client already wrote their placeholders.
+                                let prefix_images =
+                                    !has_image_placeholders(&*prefixer, &parts, image_urls.len());
+                                let mut content_map: Vec<IndexMap<String, Value>> = Vec::new();
+                                let mut pending_media = Vec::new();
+                                for part in parts {
+                                    match part {
+                                        ContentPart::ImageUrl(url) => {
+                                            if prefix_images {
+                                                pending_media
+                                                    .push(PendingMedia::Image(image_urls.len()));
+                                            }
+                                            image_urls.push(url);
+                                            content_map.push(IndexMap::from([(
+                                                "type".to_string(),
+                                                Value::String("image".to_string()),
+                                            )]));
+                                        }
+                                        ContentPart::VideoUrl(video) => {
+                                            pending_media
+                                                .push(PendingMedia::Video(video_parts.len()));
+                                            video_parts.push(video);
+                                            content_map.push(IndexMap::from([(
+                                                "type".to_string(),
+                                                Value::String("video".to_string()),
+                                            )]));
+                                        }
+                                        ContentPart::Audio(audio) => {
+                                            pending_media
+                                                .push(PendingMedia::Audio(audio_parts.len()));
+                                            audio_parts.push(audio);
+                                            content_map.push(IndexMap::from([(
+                                                "type".to_string(),
+                                                Value::String("audio".to_string()),
+                                            )]));
+                                        }
+                                        ContentPart::Text(text) => {
+                                            let text =
+                                                prefix_media(&*prefixer, &pending_media, &text);
+                                            pending_media.clear();
+                                            content_map.push(IndexMap::from([
+                                                (
+                                                    "type".to_string(),
+                                                    Value::String("text".to_string()),
+                                                ),
+                                                ("text".to_string(), Value::String(text)),
+                                            ]));
+                                        }
                                     }
-                                    if image_message["type"].is_right() {
-                                        return Err(PyApiErr::from(
-                                            "Expected string value in `type`.".to_string(),
-                                        ));
-                                    }
-                                    items.push(image_message["type"].as_ref().unwrap_left().clone())
                                 }
-
-                                #[allow(clippy::type_complexity)]
-                                fn get_content_and_url(
-                                    text_idx: usize,
-                                    url_idx: usize,
-                                    image_messages: &[HashMap<
-                                        String,
-                                        Either<String, HashMap<String, String>>,
-                                    >],
-                                ) -> PyApiResult<(String, String)> {
-                                    if image_messages[text_idx]["text"].is_right() {
-                                        return Err(PyApiErr::from(
-                                            "Expected string value in `text`.".to_string(),
-                                        ));
-                                    }
-                                    let content = image_messages[text_idx]["text"]
-                                        .as_ref()
-                                        .unwrap_left()
-                                        .clone();
-                                    if image_messages[url_idx]["image_url"].is_left()
-                                        || !image_messages[url_idx]["image_url"]
-                                            .as_ref()
-                                            .unwrap_right()
-                                            .contains_key("url")
-                                    {
-                                        return Err(PyApiErr::from("Expected content of format {{`type`: `text`, `text`: ...}} and {{`type`: `url`, `image_url`: {{`url`: ...}}}}".to_string()));
-                                    }
-                                    let url = image_messages[url_idx]["image_url"]
-                                        .as_ref()
-                                        .unwrap_right()["url"]
-                                        .clone();
-                                    Ok((content, url))
+                                if !pending_media.is_empty() {
+                                    let text = prefix_media(&*prefixer, &pending_media, "");
+                                    content_map.push(IndexMap::from([
+                                        ("type".to_string(), Value::String("text".to_string())),
+                                        ("text".to_string(), Value::String(text)),
+                                    ]));
                                 }
-                                let mut message_map: IndexMap<
-                                    String,
-                                    Either<String, Vec<IndexMap<String, Value>>>,
-                                > = IndexMap::new();
-                                message_map.insert(
-                                    "role".to_string(),
-                                    Either::Left(message["role"].as_ref().left().unwrap().clone()),
-                                );
-                                let (content, url) = if items[0] == "text" {
-                                    get_content_and_url(0, 1, image_messages)?
-                                } else {
-                                    get_content_and_url(1, 0, image_messages)?
-                                };
-
-                                let mut content_map = Vec::new();
-                                let mut content_image_map = IndexMap::new();
-                                content_image_map
-                                    .insert("type".to_string(), Value::String("image".to_string()));
-                                content_map.push(content_image_map);
-                                let mut content_text_map = IndexMap::new();
-                                content_text_map
-                                    .insert("type".to_string(), Value::String("text".to_string()));
-                                content_text_map.insert("text".to_string(), Value::String(content));
-                                content_map.push(content_text_map);
 
                                 message_map
                                     .insert("content".to_string(), Either::Right(content_map));
                                 messages_vec.push(message_map);
-                                image_urls.push(url);
                             }
                         }
                     }
-                    if !image_urls.is_empty() {
+                    if !image_urls.is_empty() || !video_parts.is_empty() || !audio_parts.is_empty()
+                    {
                         let mut images = Vec::new();
                         for url in image_urls {
                             let url_unparsed = url.trim();
@@ -894,9 +954,19 @@ impl Runner {
                             let image = util::parse_image_url(url_unparsed)?;
                             images.push(image);
                         }
+                        let videos = video_parts
+                            .into_iter()
+                            .map(util::load_video)
+                            .collect::<PyApiResult<Vec<_>>>()?;
+                        let audios = audio_parts
+                            .into_iter()
+                            .map(util::load_audio)
+                            .collect::<PyApiResult<Vec<_>>>()?;
                         RequestMessage::VisionChat {
                             messages: messages_vec,
                             images,
+                            videos,
+                            audios,
                         }
                     } else {
                         RequestMessage::Chat(messages_vec)
@@ -930,6 +1000,12 @@ impl Runner {
                 None
             };
 
+            let chat_template_kwargs = if let Some(kwargs) = &request.chat_template_kwargs {
+                Some(serde_json::from_str(kwargs)?)
+            } else {
+                None
+            };
+
             let model_request = _Request::Normal(NormalRequest {
                 id: {
                     let l = NEXT_REQUEST_ID.lock().unwrap();
@@ -963,6 +1039,10 @@ impl Runner {
                 tools,
                 logits_processors: None,
                 return_raw_logits: false,
+                chat_template_kwargs,
+                continue_final_message: request.continue_final_message,
+                conversation: None,
+                priority: RequestPriority::Normal,
             });
 
             MistralRs::maybe_log_request(self.runner.clone(), format!("{request:?}"));
@@ -985,6 +1065,10 @@ impl Runner {
                     Response::CompletionModelError(_, _) => unreachable!(),
                     Response::CompletionChunk(_) => unreachable!(),
                     Response::ImageGeneration(_) => unreachable!(),
+                    Response::Transcription(_) => unreachable!(),
+                    Response::Embedding(_) => unreachable!(),
+                    Response::Rerank(_) => unreachable!(),
+                    Response::Classification(_) => unreachable!(),
                     Response::Raw { .. } => unreachable!(),
                 }
             }
@@ -1069,6 +1153,10 @@ impl Runner {
                 tools,
                 logits_processors: None,
                 return_raw_logits: false,
+                chat_template_kwargs: None,
+                continue_final_message: false,
+                conversation: None,
+                priority: RequestPriority::Normal,
             });
 
             MistralRs::maybe_log_request(self.runner.clone(), format!("{request:?}"));
@@ -1087,17 +1175,31 @@ impl Runner {
                 Response::ModelError(_, _) => unreachable!(),
                 Response::CompletionChunk(_) => unreachable!(),
                 Response::ImageGeneration(_) => unreachable!(),
+                Response::Transcription(_) => unreachable!(),
+                Response::Embedding(_) => unreachable!(),
+                Response::Rerank(_) => unreachable!(),
+                Response::Classification(_) => unreachable!(),
                 Response::Raw { .. } => unreachable!(),
             }
         })
     }
 
-    /// Generate an image.
+    /// Generate `n` images. Give an `image` (URL, path or base64 string) to start from it
+    /// (img2img), and a `mask` to only regenerate its white areas (inpainting).
     #[pyo3(signature = (
         prompt,
         response_format,
         height = 720,
         width = 1280,
+        n = 1,
+        num_steps = None,
+        guidance_scale = None,
+        seed = None,
+        negative_prompt = None,
+        true_cfg_scale = 4.0,
+        image = None,
+        strength = 0.6,
+        mask = None,
     ))]
     fn generate_image(
         &self,
@@ -1105,17 +1207,41 @@ impl Runner {
         response_format: ImageGenerationResponseFormat,
         height: usize,
         width: usize,
+        n: usize,
+        num_steps: Option<usize>,
+        guidance_scale: Option<f64>,
+        seed: Option<u64>,
+        negative_prompt: Option<String>,
+        true_cfg_scale: f64,
+        image: Option<String>,
+        strength: f64,
+        mask: Option<String>,
     ) -> PyApiResult<ImageGenerationResponse> {
         let (tx, mut rx) = channel(1);
 
+        let generation_params = DiffusionGenerationParams {
+            height,
+            width,
+            num_steps,
+            guidance_scale,
+            seed,
+            negative_prompt,
+            true_cfg_scale,
+            init_image: image.as_deref().map(util::parse_image_url).transpose()?,
+            strength,
+            mask_image: mask.as_deref().map(util::parse_image_url).transpose()?,
+        };
         let request = _Request::Normal(NormalRequest {
             id: 0,
             messages: RequestMessage::ImageGeneration {
                 prompt: prompt.to_string(),
                 format: response_format,
-                generation_params: DiffusionGenerationParams { height, width },
+                generation_params,
+            },
+            sampling_params: SamplingParams {
+                n_choices: n,
+                ..SamplingParams::deterministic()
             },
-            sampling_params: SamplingParams::deterministic(),
             response: tx,
             return_logprobs: false,
             is_streaming: false,
@@ -1126,6 +1252,10 @@ impl Runner {
             tools: None,
             logits_processors: None,
             return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
         });
 
         let sender = self.runner.get_sender()?;
@@ -1142,6 +1272,238 @@ impl Runner {
         Ok(response)
     }
 
+    /// Transcribe (or translate to English) the speech in a WAV file. The file may be a URL,
+    /// local path or base64 string; raw 16-bit mono PCM is read if `pcm16_sample_rate` is given.
+    #[pyo3(signature = (
+        audio_url,
+        language = None,
+        task = TranscriptionTask::Transcribe,
+        timestamps = true,
+        prompt = None,
+        temperature = 0.,
+        pcm16_sample_rate = None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn transcribe(
+        &self,
+        audio_url: String,
+        language: Option<String>,
+        task: TranscriptionTask,
+        timestamps: bool,
+        prompt: Option<String>,
+        temperature: f64,
+        pcm16_sample_rate: Option<u32>,
+    ) -> PyApiResult<TranscriptionResponse> {
+        let (tx, mut rx) = channel(1);
+
+        let request = _Request::Normal(NormalRequest {
+            id: 0,
+            messages: RequestMessage::Transcription {
+                audio: util::parse_audio_url(&audio_url, pcm16_sample_rate)?,
+                params: TranscriptionParams {
+                    language,
+                    task,
+                    timestamps,
+                    prompt,
+                    temperature,
+                },
+            },
+            sampling_params: SamplingParams::deterministic(),
+            response: tx,
+            return_logprobs: false,
+            is_streaming: false,
+            suffix: None,
+            constraint: Constraint::None,
+            adapters: None,
+            tool_choice: None,
+            tools: None,
+            logits_processors: None,
+            return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
+        });
+
+        let sender = self.runner.get_sender()?;
+        sender.blocking_send(request).unwrap();
+
+        let ResponseOk::Transcription(response) = rx
+            .blocking_recv()
+            .context("Channel was erroneously closed!")?
+            .as_result()?
+        else {
+            return Err(PyApiErr::from("Got unexpected response type."));
+        };
+
+        Ok(response)
+    }
+
+    /// Embed a list of texts with an embedding model. The embeddings are L2-normalized unless
+    /// `normalize` is false, and truncated to the first `dimensions` values if it is given.
+    #[pyo3(signature = (inputs, normalize = true, dimensions = None))]
+    fn embed(
+        &self,
+        inputs: Vec<String>,
+        normalize: bool,
+        dimensions: Option<usize>,
+    ) -> PyApiResult<EmbeddingResponse> {
+        let (tx, mut rx) = channel(1);
+
+        let request = _Request::Normal(NormalRequest {
+            id: 0,
+            messages: RequestMessage::Embedding {
+                inputs,
+                params: EmbeddingParams {
+                    normalize,
+                    dimensions,
+                },
+            },
+            sampling_params: SamplingParams::deterministic(),
+            response: tx,
+            return_logprobs: false,
+            is_streaming: false,
+            suffix: None,
+            constraint: Constraint::None,
+            adapters: None,
+            tool_choice: None,
+            tools: None,
+            logits_processors: None,
+            return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
+        });
+
+        let sender = self.runner.get_sender()?;
+        sender.blocking_send(request).unwrap();
+
+        let ResponseOk::Embedding(response) = rx
+            .blocking_recv()
+            .context("Channel was erroneously closed!")?
+            .as_result()?
+        else {
+            return Err(PyApiErr::from("Got unexpected response type."));
+        };
+
+        Ok(response)
+    }
+
+    /// Score the relevance of each document to the query with a reranker model. The results are
+    /// sorted from most to least relevant.
+    #[pyo3(signature = (query, documents, top_n = None, return_documents = false))]
+    fn rerank(
+        &self,
+        query: String,
+        documents: Vec<String>,
+        top_n: Option<usize>,
+        return_documents: bool,
+    ) -> PyApiResult<RerankResponse> {
+        let (tx, mut rx) = channel(1);
+
+        let request = _Request::Normal(NormalRequest {
+            id: 0,
+            messages: RequestMessage::Rerank {
+                query,
+                documents,
+                params: RerankParams {
+                    top_n,
+                    return_documents,
+                },
+            },
+            sampling_params: SamplingParams::deterministic(),
+            response: tx,
+            return_logprobs: false,
+            is_streaming: false,
+            suffix: None,
+            constraint: Constraint::None,
+            adapters: None,
+            tool_choice: None,
+            tools: None,
+            logits_processors: None,
+            return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
+        });
+
+        let sender = self.runner.get_sender()?;
+        sender.blocking_send(request).unwrap();
+
+        let ResponseOk::Rerank(response) = rx
+            .blocking_recv()
+            .context("Channel was erroneously closed!")?
+            .as_result()?
+        else {
+            return Err(PyApiErr::from("Got unexpected response type."));
+        };
+
+        Ok(response)
+    }
+
+    /// Classify a batch of texts or conversations with a sequence classification model, or score
+    /// them with a reward model. A conversation is a list of `{"role": ..., "content": ...}`
+    /// messages, formatted with the chat template of the model.
+    fn classify(
+        &self,
+        inputs: Vec<Either<String, Vec<HashMap<String, String>>>>,
+    ) -> PyApiResult<ClassificationResponse> {
+        let (tx, mut rx) = channel(1);
+
+        let inputs = inputs
+            .into_iter()
+            .map(|input| match input {
+                Either::Left(text) => ClassificationInput::Text(text),
+                Either::Right(messages) => ClassificationInput::Conversation(
+                    messages
+                        .into_iter()
+                        .map(|message| {
+                            message
+                                .into_iter()
+                                .map(|(k, v)| (k, Either::Left(v)))
+                                .collect::<IndexMap<_, _>>()
+                        })
+                        .collect(),
+                ),
+            })
+            .collect();
+
+        let request = _Request::Normal(NormalRequest {
+            id: 0,
+            messages: RequestMessage::Classification { inputs },
+            sampling_params: SamplingParams::deterministic(),
+            response: tx,
+            return_logprobs: false,
+            is_streaming: false,
+            suffix: None,
+            constraint: Constraint::None,
+            adapters: None,
+            tool_choice: None,
+            tools: None,
+            logits_processors: None,
+            return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
+        });
+
+        let sender = self.runner.get_sender()?;
+        sender.blocking_send(request).unwrap();
+
+        let ResponseOk::Classification(response) = rx
+            .blocking_recv()
+            .context("Channel was erroneously closed!")?
+            .as_result()?
+        else {
+            return Err(PyApiErr::from("Got unexpected response type."));
+        };
+
+        Ok(response)
+    }
+
     /// Send a request to re-ISQ the model. If the model was loaded as GGUF or GGML
     /// then nothing will happen.
     fn send_re_isq(&self, dtype: String) -> PyApiResult<()> {
@@ -1166,6 +1528,7 @@ impl Runner {
         let request = _Request::Tokenize(TokenizationRequest {
             text: Either::Right(text),
             tools: None,
+            chat_template_kwargs: None,
             add_generation_prompt: true,
             add_special_tokens,
             response: tx,
@@ -1206,6 +1569,8 @@ fn mistralrs(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<Architecture>()?;
     m.add_class::<VisionArchitecture>()?;
     m.add_class::<DiffusionArchitecture>()?;
+    m.add_class::<SpeechArchitecture>()?;
+    m.add_class::<EmbeddingArchitecture>()?;
     m.add_class::<AnyMoeConfig>()?;
     m.add_class::<AnyMoeExpertType>()?;
     m.add_class::<ToolChoice>()?;
@@ -1224,5 +1589,16 @@ fn mistralrs(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<mistralrs_core::TopLogprob>()?;
     m.add_class::<mistralrs_core::ModelDType>()?;
     m.add_class::<mistralrs_core::ImageGenerationResponseFormat>()?;
+    m.add_class::<mistralrs_core::TranscriptionTask>()?;
+    m.add_class::<mistralrs_core::TranscriptionSegment>()?;
+    m.add_class::<mistralrs_core::TranscriptionResponse>()?;
+    m.add_class::<mistralrs_core::EmbeddingData>()?;
+    m.add_class::<mistralrs_core::EmbeddingUsage>()?;
+    m.add_class::<mistralrs_core::EmbeddingResponse>()?;
+    m.add_class::<mistralrs_core::RerankDocument>()?;
+    m.add_class::<mistralrs_core::RerankResult>()?;
+    m.add_class::<mistralrs_core::RerankResponse>()?;
+    m.add_class::<mistralrs_core::ClassificationResult>()?;
+    m.add_class::<mistralrs_core::ClassificationResponse>()?;
     Ok(())
 }
@@ -5,9 +5,27 @@ use pyo3::{
     exceptions::PyTypeError,
     pyclass, pymethods,
     types::{PyAnyMethods, PyList, PyString},
-    Py, PyAny, PyErr, PyResult, Python,
+    FromPyObject, Py, PyAny, PyErr, PyResult, Python,
 };
 
+#[derive(Debug, FromPyObject)]
+/// A value in the dict of a content part, such as `{"url": ...}` or `{"frames": [...], "fps": 2}`.
+pub enum ContentPartValue {
+    Str(String),
+    Number(f64),
+    List(Vec<String>),
+}
+
+impl From<&ContentPartValue> for serde_json::Value {
+    fn from(value: &ContentPartValue) -> Self {
+        match value {
+            ContentPartValue::Str(s) => Self::String(s.clone()),
+            ContentPartValue::Number(n) => Self::from(*n),
+            ContentPartValue::List(l) => Self::from(l.clone()),
+        }
+    }
+}
+
 #[pyclass(eq, eq_int)]
 #[derive(PartialEq, Debug, Clone)]
 pub enum ToolChoice {
@@ -138,7 +156,10 @@ pub struct ChatCompletionRequest {
         Vec<
             HashMap<
                 String,
-                Either<String, Vec<HashMap<String, Either<String, HashMap<String, String>>>>>,
+                Either<
+                    String,
+                    Vec<HashMap<String, Either<String, HashMap<String, ContentPartValue>>>>,
+                >,
             >,
         >,
         String,
@@ -166,6 +187,8 @@ pub struct ChatCompletionRequest {
     pub(crate) dry_base: Option<f32>,
     pub(crate) dry_allowed_length: Option<usize>,
     pub(crate) dry_sequence_breakers: Option<Vec<String>>,
+    pub(crate) chat_template_kwargs: Option<String>,
+    pub(crate) continue_final_message: bool,
 }
 
 #[pymethods]
@@ -196,6 +219,8 @@ impl ChatCompletionRequest {
         dry_base=None,
         dry_allowed_length=None,
         dry_sequence_breakers=None,
+        chat_template_kwargs=None,
+        continue_final_message=false,
     ))]
     fn new(
         messages: Py<PyAny>,
@@ -222,6 +247,8 @@ impl ChatCompletionRequest {
         dry_base: Option<f32>,
         dry_allowed_length: Option<usize>,
         dry_sequence_breakers: Option<Vec<String>>,
+        chat_template_kwargs: Option<String>,
+        continue_final_message: bool,
     ) -> PyResult<Self> {
         let messages = Python::with_gil(|py| {
             if let Ok(messages) = messages.bind(py).downcast_exact::<PyList>() {
@@ -231,7 +258,7 @@ impl ChatCompletionRequest {
                         String,
                         Either<
                             String,
-                            Vec<HashMap<String, Either<String, HashMap<String, String>>>>,
+                            Vec<HashMap<String, Either<String, HashMap<String, ContentPartValue>>>>,
                         >,
                     >>()?);
                 }
@@ -242,7 +269,12 @@ impl ChatCompletionRequest {
                                 String,
                                 Either<
                                     String,
-                                    Vec<HashMap<String, Either<String, HashMap<String, String>>>>,
+                                    Vec<
+                                        HashMap<
+                                            String,
+                                            Either<String, HashMap<String, ContentPartValue>>,
+                                        >,
+                                    >,
                                 >,
                             >,
                         >,
@@ -259,7 +291,12 @@ impl ChatCompletionRequest {
                                 String,
                                 Either<
                                     String,
-                                    Vec<HashMap<String, Either<String, HashMap<String, String>>>>,
+                                    Vec<
+                                        HashMap<
+                                            String,
+                                            Either<String, HashMap<String, ContentPartValue>>,
+                                        >,
+                                    >,
                                 >,
                             >,
                         >,
@@ -296,6 +333,8 @@ impl ChatCompletionRequest {
             dry_allowed_length,
             dry_base,
             dry_sequence_breakers,
+            chat_template_kwargs,
+            continue_final_message,
         })
     }
 }
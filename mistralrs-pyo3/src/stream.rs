@@ -40,6 +40,10 @@ impl ChatCompletionStreamer {
                 Response::CompletionModelError(_, _) => unreachable!(),
                 Response::CompletionChunk(_) => unreachable!(),
                 Response::ImageGeneration(_) => unreachable!(),
+                Response::Transcription(_) => unreachable!(),
+                Response::Embedding(_) => unreachable!(),
+                Response::Rerank(_) => unreachable!(),
+                Response::Classification(_) => unreachable!(),
                 Response::Raw { .. } => unreachable!(),
             },
             None => Some(Err(PyValueError::new_err(
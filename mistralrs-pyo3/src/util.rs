@@ -1,11 +1,16 @@
 use std::{
+    collections::HashMap,
     fs::{self, File},
     io::Read,
 };
 
+use either::Either;
 use image::DynamicImage;
-use mistralrs_core::ResponseErr;
+use mistralrs_core::{AudioInput, AudioPart, ContentPart, ResponseErr, VideoInput, VideoPart};
 use pyo3::{exceptions::PyValueError, PyErr};
+use serde_json::Value;
+
+use crate::requests::ContentPartValue;
 
 pub(crate) struct PyApiErr(pub(crate) PyErr);
 pub(crate) type PyApiResult<T> = Result<T, PyApiErr>;
@@ -84,7 +89,8 @@ impl From<Box<ResponseErr>> for PyApiErr {
     }
 }
 
-pub(crate) fn parse_image_url(url_unparsed: &str) -> PyApiResult<DynamicImage> {
+/// Read the bytes of an http(s) URL, a local file path, a `data:` URL or raw base64 data.
+fn read_url_bytes(url_unparsed: &str) -> PyApiResult<Vec<u8>> {
     let url = if let Ok(url) = url::Url::parse(url_unparsed) {
         url
     } else if File::open(url_unparsed).is_ok() {
@@ -129,5 +135,73 @@ pub(crate) fn parse_image_url(url_unparsed: &str) -> PyApiResult<DynamicImage> {
         )));
     };
 
-    image::load_from_memory(&bytes).map_err(|e| PyApiErr::from(format!("{e}")))
+    Ok(bytes)
+}
+
+pub(crate) fn parse_image_url(url_unparsed: &str) -> PyApiResult<DynamicImage> {
+    image::load_from_memory(&read_url_bytes(url_unparsed)?)
+        .map_err(|e| PyApiErr::from(format!("{e}")))
+}
+
+/// Load an animated GIF, PNG or WebP clip as a video.
+pub(crate) fn parse_video_url(url_unparsed: &str) -> PyApiResult<VideoInput> {
+    VideoInput::from_animation_bytes(&read_url_bytes(url_unparsed)?)
+        .map_err(|e| PyApiErr::from(format!("{e}")))
+}
+
+/// Load a WAV file, or raw 16-bit mono PCM at `pcm16_sample_rate` if it is given.
+pub(crate) fn parse_audio_url(
+    url_unparsed: &str,
+    pcm16_sample_rate: Option<u32>,
+) -> PyApiResult<AudioInput> {
+    let bytes = read_url_bytes(url_unparsed)?;
+    match pcm16_sample_rate {
+        Some(sample_rate) => AudioInput::from_pcm16_bytes(&bytes, sample_rate, 1),
+        None => AudioInput::from_wav_bytes(&bytes),
+    }
+    .map_err(|e| PyApiErr::from(format!("{e}")))
+}
+
+/// Load a video content part: an animated clip or a list of frame URLs.
+pub(crate) fn load_video(video: VideoPart) -> PyApiResult<VideoInput> {
+    let loaded = match &video.source {
+        Either::Left(url) => Either::Left(parse_video_url(url.trim())?),
+        Either::Right(frame_urls) => Either::Right(
+            frame_urls
+                .iter()
+                .map(|url| parse_image_url(url.trim()))
+                .collect::<PyApiResult<Vec<_>>>()?,
+        ),
+    };
+    Ok(video.into_video(loaded))
+}
+
+/// Load an audio content part: a WAV file, or raw 16-bit mono PCM.
+pub(crate) fn load_audio(audio: AudioPart) -> PyApiResult<AudioInput> {
+    parse_audio_url(audio.source.trim(), audio.pcm16_sample_rate)
+}
+
+/// Parse the content parts of a message, preserving their order.
+#[allow(clippy::type_complexity)]
+pub(crate) fn parse_content_parts(
+    content_parts: &[HashMap<String, Either<String, HashMap<String, ContentPartValue>>>],
+) -> PyApiResult<Vec<ContentPart>> {
+    let content_parts = content_parts
+        .iter()
+        .map(|content_part| {
+            content_part
+                .iter()
+                .map(|(key, value)| {
+                    let value = value.as_ref().map_right(|object| {
+                        object
+                            .iter()
+                            .map(|(key, value)| (key.clone(), Value::from(value)))
+                            .collect::<HashMap<_, _>>()
+                    });
+                    (key.clone(), value.map_left(Clone::clone))
+                })
+                .collect::<HashMap<_, _>>()
+        })
+        .collect::<Vec<_>>();
+    Ok(mistralrs_core::parse_content_parts(&content_parts)?)
 }
@@ -2,7 +2,8 @@ use std::path::PathBuf;
 
 use either::Either;
 use mistralrs_core::{
-    AutoDeviceMapParams, DiffusionLoaderType, ModelDType, NormalLoaderType, VisionLoaderType,
+    AutoDeviceMapParams, DiffusionLoaderType, EmbeddingLoaderType, ModelDType, NormalLoaderType,
+    SpeechLoaderType, VisionLoaderType,
 };
 use pyo3::{pyclass, pymethods};
 
@@ -21,6 +22,9 @@ pub enum Architecture {
     Phi3_5MoE,
     DeepseekV2,
     DeepseekV3,
+    Mamba,
+    Mamba2,
+    Jamba,
 }
 
 impl From<Architecture> for NormalLoaderType {
@@ -38,6 +42,9 @@ impl From<Architecture> for NormalLoaderType {
             Architecture::Phi3_5MoE => Self::Phi3_5MoE,
             Architecture::DeepseekV2 => Self::DeepSeekV2,
             Architecture::DeepseekV3 => Self::DeepSeekV3,
+            Architecture::Mamba => Self::Mamba,
+            Architecture::Mamba2 => Self::Mamba2,
+            Architecture::Jamba => Self::Jamba,
         }
     }
 }
@@ -75,6 +82,8 @@ impl From<VisionArchitecture> for VisionLoaderType {
 pub enum DiffusionArchitecture {
     Flux,
     FluxOffloaded,
+    StableDiffusion,
+    StableDiffusionXl,
 }
 
 impl From<DiffusionArchitecture> for DiffusionLoaderType {
@@ -82,6 +91,46 @@ impl From<DiffusionArchitecture> for DiffusionLoaderType {
         match value {
             DiffusionArchitecture::Flux => DiffusionLoaderType::Flux,
             DiffusionArchitecture::FluxOffloaded => DiffusionLoaderType::FluxOffloaded,
+            DiffusionArchitecture::StableDiffusion => DiffusionLoaderType::StableDiffusion,
+            DiffusionArchitecture::StableDiffusionXl => DiffusionLoaderType::StableDiffusionXl,
+        }
+    }
+}
+
+#[pyclass(eq, eq_int)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpeechArchitecture {
+    Whisper,
+}
+
+impl From<SpeechArchitecture> for SpeechLoaderType {
+    fn from(value: SpeechArchitecture) -> Self {
+        match value {
+            SpeechArchitecture::Whisper => SpeechLoaderType::Whisper,
+        }
+    }
+}
+
+#[pyclass(eq, eq_int)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmbeddingArchitecture {
+    Bert,
+    XLMRoberta,
+    ModernBert,
+    Llama,
+    Mistral,
+    Qwen2,
+}
+
+impl From<EmbeddingArchitecture> for EmbeddingLoaderType {
+    fn from(value: EmbeddingArchitecture) -> Self {
+        match value {
+            EmbeddingArchitecture::Bert => EmbeddingLoaderType::Bert,
+            EmbeddingArchitecture::XLMRoberta => EmbeddingLoaderType::XLMRoberta,
+            EmbeddingArchitecture::ModernBert => EmbeddingLoaderType::ModernBert,
+            EmbeddingArchitecture::Llama => EmbeddingLoaderType::Llama,
+            EmbeddingArchitecture::Mistral => EmbeddingLoaderType::Mistral,
+            EmbeddingArchitecture::Qwen2 => EmbeddingLoaderType::Qwen2,
         }
     }
 }
@@ -404,6 +453,27 @@ pub enum Which {
         auto_map_params: Option<VisionAutoMapParams>,
     },
 
+    #[pyo3(constructor = (
+        tok_model_id,
+        quantized_model_id,
+        quantized_filename,
+        mmproj_filename,
+        arch,
+        topology = None,
+        dtype = ModelDType::Auto,
+        auto_map_params = None,
+    ))]
+    VisionGGUF {
+        tok_model_id: String,
+        quantized_model_id: String,
+        quantized_filename: Either<String, Vec<String>>,
+        mmproj_filename: String,
+        arch: VisionArchitecture,
+        topology: Option<String>,
+        dtype: ModelDType,
+        auto_map_params: Option<VisionAutoMapParams>,
+    },
+
     #[pyo3(constructor = (
         model_id,
         arch,
@@ -414,4 +484,26 @@ pub enum Which {
         arch: DiffusionArchitecture,
         dtype: ModelDType,
     },
+
+    #[pyo3(constructor = (
+        model_id,
+        arch,
+        dtype = ModelDType::Auto,
+    ))]
+    Speech {
+        model_id: String,
+        arch: SpeechArchitecture,
+        dtype: ModelDType,
+    },
+
+    #[pyo3(constructor = (
+        model_id,
+        arch,
+        dtype = ModelDType::Auto,
+    ))]
+    Embedding {
+        model_id: String,
+        arch: EmbeddingArchitecture,
+        dtype: ModelDType,
+    },
 }
@@ -0,0 +1,190 @@
+// Int8 matmul for LLM.int8: out = A @ B^T with int32 accumulation.
+// A is (m, k) and B is (n, k), both row major.
+
+#include <cuda_runtime.h>
+#include <stdint.h>
+
+// Each block computes a BM x BN tile of the output with 16 x 16 threads, so every thread computes
+// 4 x 4 outputs. The k dimension is loaded BK bytes at a time, as packed words of 4 int8 values
+// which are multiplied with __dp4a.
+#define BM 64
+#define BN 64
+#define BK 32
+#define BKW (BK / 4)
+#define THREADS 16
+#define PER_THREAD (BM / THREADS)
+
+// Four consecutive int8 values of `row` from `kk`, zero past `k`, packed into one word.
+__device__ __forceinline__ int load_packed(const int8_t *row, const int kk,
+                                           const int k, const bool aligned) {
+  if (aligned && kk < k) {
+    return *reinterpret_cast<const int *>(row + kk);
+  }
+  int packed = 0;
+#pragma unroll
+  for (int i = 0; i < 4; i++) {
+    const int v = kk + i < k ? (uint8_t)row[kk + i] : 0;
+    packed |= v << (8 * i);
+  }
+  return packed;
+}
+
+__device__ __forceinline__ int dot4(const int a, const int b, const int acc) {
+#if __CUDA_ARCH__ >= 610
+  return __dp4a(a, b, acc);
+#else
+  const char4 x = *reinterpret_cast<const char4 *>(&a);
+  const char4 y = *reinterpret_cast<const char4 *>(&b);
+  return acc + x.x * y.x + x.y * y.y + x.z * y.z + x.w * y.w;
+#endif
+}
+
+__global__ void kInt8MatmulI32(const int8_t *__restrict__ A,
+                               const int8_t *__restrict__ B,
+                               int32_t *__restrict__ out, const int m,
+                               const int n, const int k, const bool aligned) {
+  // Padded so that the threads of a warp read different banks of b_tile.
+  __shared__ int a_tile[BM][BKW + 1];
+  __shared__ int b_tile[BN][BKW + 1];
+
+  const int tx = threadIdx.x;
+  const int ty = threadIdx.y;
+  const int tid = ty * THREADS + tx;
+  const int row0 = blockIdx.y * BM;
+  const int col0 = blockIdx.x * BN;
+
+  int acc[PER_THREAD][PER_THREAD] = {};
+
+  for (int t = 0; t < k; t += BK) {
+    for (int i = tid; i < BM * BKW; i += THREADS * THREADS) {
+      const int r = i / BKW;
+      const int w = i % BKW;
+      const int kk = t + 4 * w;
+      a_tile[r][w] = row0 + r < m
+                         ? load_packed(A + (size_t)(row0 + r) * k, kk, k, aligned)
+                         : 0;
+      b_tile[r][w] = col0 + r < n
+                         ? load_packed(B + (size_t)(col0 + r) * k, kk, k, aligned)
+                         : 0;
+    }
+    __syncthreads();
+
+#pragma unroll
+    for (int w = 0; w < BKW; w++) {
+      int a[PER_THREAD];
+      int b[PER_THREAD];
+#pragma unroll
+      for (int i = 0; i < PER_THREAD; i++) {
+        a[i] = a_tile[ty + THREADS * i][w];
+        b[i] = b_tile[tx + THREADS * i][w];
+      }
+#pragma unroll
+      for (int i = 0; i < PER_THREAD; i++) {
+#pragma unroll
+        for (int j = 0; j < PER_THREAD; j++) {
+          acc[i][j] = dot4(a[i], b[j], acc[i][j]);
+        }
+      }
+    }
+    __syncthreads();
+  }
+
+#pragma unroll
+  for (int i = 0; i < PER_THREAD; i++) {
+    const int row = row0 + ty + THREADS * i;
+#pragma unroll
+    for (int j = 0; j < PER_THREAD; j++) {
+      const int col = col0 + tx + THREADS * j;
+      if (row < m && col < n) {
+        out[(size_t)row * n + col] = acc[i][j];
+      }
+    }
+  }
+}
+
+extern "C" void int8_matmul_i32(const int8_t *A, const int8_t *B, int32_t *out,
+                                const int m, const int n, const int k,
+                                cudaStream_t stream) {
+  // Rows can be read as whole words if they all start on a word boundary.
+  const bool aligned = k % 4 == 0 && (uintptr_t)A % 4 == 0 &&
+                       (uintptr_t)B % 4 == 0;
+  dim3 threads(THREADS, THREADS);
+  dim3 blocks((n + BN - 1) / BN, (m + BM - 1) / BM);
+  kInt8MatmulI32<<<blocks, threads, 0, stream>>>(A, B, out, m, n, k, aligned);
+}
+
+#define OUTLIER_THREADS 1024
+
+// Write the columns whose absmax is at least `threshold` to `idx`, in increasing order, and their
+// number to `count`. Runs as a single block: each thread scans a contiguous range of columns.
+__global__ void kOutlierColumns(const float *__restrict__ col_absmax,
+                                const float threshold, const int k,
+                                int32_t *__restrict__ idx,
+                                int32_t *__restrict__ count) {
+  __shared__ int counts[OUTLIER_THREADS];
+
+  const int per_thread = (k + OUTLIER_THREADS - 1) / OUTLIER_THREADS;
+  const int start = min((int)threadIdx.x * per_thread, k);
+  const int end = min(start + per_thread, k);
+  int own = 0;
+  for (int c = start; c < end; c++) {
+    own += col_absmax[c] >= threshold;
+  }
+  counts[threadIdx.x] = own;
+  __syncthreads();
+
+  // Inclusive scan of the counts
+  for (int offset = 1; offset < OUTLIER_THREADS; offset <<= 1) {
+    const int v = threadIdx.x >= offset ? counts[threadIdx.x - offset] : 0;
+    __syncthreads();
+    counts[threadIdx.x] += v;
+    __syncthreads();
+  }
+
+  int pos = counts[threadIdx.x] - own;
+  for (int c = start; c < end; c++) {
+    if (col_absmax[c] >= threshold) {
+      idx[pos++] = c;
+    }
+  }
+  if (threadIdx.x == OUTLIER_THREADS - 1) {
+    *count = counts[threadIdx.x];
+  }
+}
+
+// out[r, j] = sum over the outlier columns c of x[r, c] * B[j, c].
+__global__ void kOutlierMatmulF32(const float *__restrict__ x,
+                                  const int8_t *__restrict__ B,
+                                  const int32_t *__restrict__ idx,
+                                  const int32_t *__restrict__ count,
+                                  float *__restrict__ out, const int m,
+                                  const int n, const int k) {
+  const int row = blockIdx.y * blockDim.y + threadIdx.y;
+  const int col = blockIdx.x * blockDim.x + threadIdx.x;
+  if (row >= m || col >= n) {
+    return;
+  }
+  const int n_outliers = *count;
+  float acc = 0.f;
+  for (int t = 0; t < n_outliers; t++) {
+    const int c = idx[t];
+    acc += x[(size_t)row * k + c] * (float)B[(size_t)col * k + c];
+  }
+  out[(size_t)row * n + col] = acc;
+}
+
+// The product of the outlier columns of x (m, k), those whose absmax is at least `threshold`, with
+// the same columns of the int8 B (n, k). `idx` (k) and `count` (1) are scratch buffers.
+extern "C" void int8_outlier_matmul_f32(const float *x, const int8_t *B,
+                                        const float *col_absmax,
+                                        const float threshold, int32_t *idx,
+                                        int32_t *count, float *out,
+                                        const int m, const int n, const int k,
+                                        cudaStream_t stream) {
+  kOutlierColumns<<<1, OUTLIER_THREADS, 0, stream>>>(col_absmax, threshold, k,
+                                                     idx, count);
+  dim3 threads(THREADS, THREADS);
+  dim3 blocks((n + THREADS - 1) / THREADS, (m + THREADS - 1) / THREADS);
+  kOutlierMatmulF32<<<blocks, threads, 0, stream>>>(x, B, idx, count, out, m,
+                                                    n, k);
+}
@@ -0,0 +1,763 @@
+use std::{
+    borrow::Cow,
+    io::Cursor,
+    num::NonZeroUsize,
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use candle_core::{DType, Device, Result, Tensor, D};
+use candle_nn::Linear;
+
+use crate::{
+    utils::{deserialize_tensor, serialize_tensor, version_is_compatible, UQFF_VERSION},
+    DummyLayer, IsqType, MatMul, QuantMethod, QuantMethodConfig, QuantizedConfig, QuantizedSerde,
+    QuantizedSerdeType, ShardedVarBuilder, UnquantLinear,
+};
+
+mod quantize;
+
+const AWQ_PACK_FACTOR: usize = 8;
+
+/// Number of input rows of the weight dequantized at once in the forward pass, rounded up to a
+/// multiple of the group size.
+const AWQ_FORWARD_CHUNK_ROWS: usize = 512;
+
+/// Nibble of a packed AWQ GEMM `i32` which holds output column `i % 8`. AutoAWQ packs the columns
+/// in the order `[0, 2, 4, 6, 1, 3, 5, 7]`.
+const AWQ_REVERSE_ORDER: [usize; AWQ_PACK_FACTOR] = [0, 4, 1, 5, 2, 6, 3, 7];
+
+/// AWQ 4 bit quantization.
+///
+/// The `gemm` and `gemv` checkpoint versions of AutoAWQ only differ in how the values are packed.
+/// Both are repacked at load time into [`QuantMethodConfig::Awq`], which is also the UQFF layout,
+/// and the layer then keeps the values unpacked, one byte each, so that nothing is unpacked in
+/// the forward pass. There is no fused AWQ kernel: the forward pass dequantizes the weight with
+/// tensor ops, so this runs on all devices. It is dequantized a few groups of input rows at a
+/// time, so only a slice of the full precision weight exists at once.
+///
+/// Layers quantized by AWQ ISQ also hold the inverse of the activation-aware scales, which is
+/// applied to the input dimension of the dequantized weight.
+#[derive(Debug)]
+pub struct AwqLayer {
+    /// `(in_dim, out_dim)` u8 values.
+    qweight: Tensor,
+    /// `(in_dim / group_size, out_dim)` u8 zeros.
+    qzeros: Tensor,
+    scales: Tensor,
+    input_scales: Option<Tensor>,
+    bias: Option<Tensor>,
+    group_size: usize,
+}
+
+fn to_i32_vec(t: &Tensor) -> Result<Vec<i32>> {
+    t.to_device(&Device::Cpu)?
+        .to_dtype(DType::I32)?
+        .flatten_all()?
+        .to_vec1::<i32>()
+}
+
+/// Unpack `(rows, cols / 8)` AWQ GEMM words into `(rows, cols)` 4 bit values.
+fn unpack_gemm(words: &[i32], rows: usize, cols: usize) -> Vec<u8> {
+    let n_words = cols / AWQ_PACK_FACTOR;
+    let mut values = vec![0u8; rows * cols];
+    for r in 0..rows {
+        for c in 0..cols {
+            let word = words[r * n_words + c / AWQ_PACK_FACTOR] as u32;
+            let shift = 4 * AWQ_REVERSE_ORDER[c % AWQ_PACK_FACTOR];
+            values[r * cols + c] = ((word >> shift) & 0xF) as u8;
+        }
+    }
+    values
+}
+
+/// Unpack `(rows, n_words)` AWQ GEMV words, packed in order, into `(rows, cols)` 4 bit values.
+fn unpack_gemv(words: &[i32], rows: usize, n_words: usize, cols: usize) -> Vec<u8> {
+    let mut values = vec![0u8; rows * cols];
+    for r in 0..rows {
+        for c in 0..cols {
+            let word = words[r * n_words + c / AWQ_PACK_FACTOR] as u32;
+            let shift = 4 * (c % AWQ_PACK_FACTOR);
+            values[r * cols + c] = ((word >> shift) & 0xF) as u8;
+        }
+    }
+    values
+}
+
+fn transpose(values: &[u8], rows: usize, cols: usize) -> Vec<u8> {
+    let mut out = vec![0u8; values.len()];
+    for r in 0..rows {
+        for c in 0..cols {
+            out[c * rows + r] = values[r * cols + c];
+        }
+    }
+    out
+}
+
+/// Width of the GEMV `qzeros`, which AutoAWQ pads for its kernels.
+fn gemv_zeros_width(in_dim: usize, group_size: usize) -> usize {
+    let multiplier = match group_size {
+        32 => 4,
+        64 => 2,
+        _ => 1,
+    };
+    let width = (in_dim / group_size).div_ceil(AWQ_PACK_FACTOR);
+    width.div_ceil(multiplier) * multiplier
+}
+
+/// Pack `(rows, cols)` 4 bit values into a `(rows, cols / 2)` u8 tensor, low nibble first.
+fn pack_nibbles(values: &[u8], rows: usize, cols: usize, device: &Device) -> Result<Tensor> {
+    let packed = values
+        .chunks_exact(2)
+        .map(|pair| pair[0] | (pair[1] << 4))
+        .collect::<Vec<_>>();
+    Tensor::from_vec(packed, (rows, cols / 2), device)
+}
+
+/// Inverse of [`pack_nibbles`]: `(rows, cols / 2)` u8 to `(rows, cols)` u8, on the same device.
+fn unpack_nibbles(packed: &Tensor) -> Result<Tensor> {
+    let (rows, half_cols) = packed.dims2()?;
+    let values = packed
+        .to_device(&Device::Cpu)?
+        .flatten_all()?
+        .to_vec1::<u8>()?
+        .into_iter()
+        .flat_map(|byte| [byte & 0xF, byte >> 4])
+        .collect::<Vec<_>>();
+    Tensor::from_vec(values, (rows, half_cols * 2), packed.device())
+}
+
+/// [`pack_nibbles`] for a `(rows, cols)` u8 tensor.
+fn pack_nibbles_tensor(values: &Tensor) -> Result<Tensor> {
+    let (rows, cols) = values.dims2()?;
+    let data = values
+        .to_device(&Device::Cpu)?
+        .flatten_all()?
+        .to_vec1::<u8>()?;
+    pack_nibbles(&data, rows, cols, values.device())
+}
+
+impl AwqLayer {
+    /// Dequantize the input rows `start..start + len` into an f32 weight of shape
+    /// `(len, out_dim)`. Both must be multiples of the group size.
+    fn dequantize_rows_t(&self, start: usize, len: usize) -> Result<Tensor> {
+        let (first_group, n_groups) = (start / self.group_size, len / self.group_size);
+        let q = self.qweight.narrow(0, start, len)?.to_dtype(DType::F32)?;
+        let z = self
+            .qzeros
+            .narrow(0, first_group, n_groups)?
+            .to_dtype(DType::F32)?;
+        let scales = self.scales.narrow(0, first_group, n_groups)?;
+        let out_dim = q.dim(1)?;
+        let w = q
+            .reshape((n_groups, self.group_size, out_dim))?
+            .broadcast_sub(&z.unsqueeze(1)?)?
+            .broadcast_mul(&scales.to_dtype(DType::F32)?.unsqueeze(1)?)?
+            .reshape((len, out_dim))?;
+        match &self.input_scales {
+            Some(input_scales) => w.broadcast_mul(
+                &input_scales
+                    .narrow(0, start, len)?
+                    .to_dtype(DType::F32)?
+                    .unsqueeze(1)?,
+            ),
+            None => Ok(w),
+        }
+    }
+
+    /// Dequantize into a weight of shape `(in_dim, out_dim)`, in the dtype of the scales.
+    fn dequantize_t(&self) -> Result<Tensor> {
+        self.dequantize_rows_t(0, self.qweight.dim(0)?)?
+            .to_dtype(self.scales.dtype())
+    }
+
+    /// Factor by which the weight size is reduced over the given dtype.
+    pub fn pack_factor(dtype: DType) -> usize {
+        dtype.size_in_bytes() * 8 / 4
+    }
+}
+
+impl QuantMethod for AwqLayer {
+    fn new(method: QuantMethodConfig) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        match method {
+            QuantMethodConfig::Awq {
+                qweight,
+                qzeros,
+                scales,
+                bias,
+                group_size,
+            } => {
+                let (in_dim, half_out_dim) = qweight.dims2()?;
+                if group_size == 0
+                    || in_dim % group_size != 0
+                    || qzeros.dims2()? != (in_dim / group_size, half_out_dim)
+                    || scales.dims2()? != (in_dim / group_size, half_out_dim * 2)
+                {
+                    candle_core::bail!(
+                        "AWQ tensors have mismatched shapes: qweight {:?}, qzeros {:?}, scales {:?} with group size {group_size}.",
+                        qweight.dims(),
+                        qzeros.dims(),
+                        scales.dims()
+                    );
+                }
+                Ok(Self {
+                    qweight: unpack_nibbles(&qweight)?,
+                    qzeros: unpack_nibbles(&qzeros)?,
+                    scales,
+                    input_scales: None,
+                    bias,
+                    group_size,
+                })
+            }
+            QuantMethodConfig::Gguf { .. }
+            | QuantMethodConfig::Gptq { .. }
+            | QuantMethodConfig::Unquantized(_)
+            | QuantMethodConfig::Hqq { .. }
+            | QuantMethodConfig::Dummy
+            | QuantMethodConfig::FP8 { .. }
+            | QuantMethodConfig::Bnb { .. }
+            | QuantMethodConfig::BnbInt8 { .. }
+            | QuantMethodConfig::BlockwiseFP8 { .. } => unreachable!(),
+        }
+    }
+
+    fn dequantize_w(&self) -> Result<Tensor> {
+        self.dequantize_t()?.t()?.contiguous()
+    }
+
+    fn forward(&self, a: &Tensor) -> Result<Tensor> {
+        let in_dim = self.qweight.dim(0)?;
+        let chunk_rows = AWQ_FORWARD_CHUNK_ROWS.div_ceil(self.group_size) * self.group_size;
+        // Accumulate the partial products in f32.
+        let mut res: Option<Tensor> = None;
+        for start in (0..in_dim).step_by(chunk_rows) {
+            let len = chunk_rows.min(in_dim - start);
+            let w = self.dequantize_rows_t(start, len)?.to_dtype(a.dtype())?;
+            let w = match *a.dims() {
+                [b1, b2, _, _] => w.broadcast_left((b1, b2))?,
+                [bsize, _, _] => w.broadcast_left(bsize)?,
+                _ => w,
+            };
+            let a = a.narrow(D::Minus1, start, len)?.contiguous()?;
+            let partial = MatMul.matmul(&a, &w)?.to_dtype(DType::F32)?;
+            res = Some(match res {
+                Some(res) => (res + partial)?,
+                None => partial,
+            });
+        }
+        let res = res
+            .expect("AWQ layers have at least one input row")
+            .to_dtype(a.dtype())?;
+        if let Some(ref bias) = self.bias {
+            res.broadcast_add(bias)
+        } else {
+            Ok(res)
+        }
+    }
+
+    fn quantized_act_type(&self) -> Option<DType> {
+        Some(self.scales.dtype())
+    }
+
+    fn add_delta_w(&self, _delta: &Tensor) -> Result<Arc<dyn QuantMethod>> {
+        candle_core::bail!("AWQ quantization does not support adding weight delta.")
+    }
+
+    fn dtype_and_device(&self) -> (DType, Device) {
+        (self.scales.dtype(), self.scales.device().clone())
+    }
+
+    fn apply_isq(
+        self: Arc<Self>,
+        dtype: Option<IsqType>,
+        device: Device,
+        n_quantized: &AtomicUsize,
+        imatrix_weight: Option<Vec<f32>>,
+    ) -> Result<Arc<dyn QuantMethod>> {
+        match dtype {
+            // Requantize from the dequantized weight.
+            Some(_) => {
+                let unquant = UnquantLinear::new(QuantMethodConfig::Unquantized(Linear::new(
+                    self.dequantize_w()?,
+                    self.bias.clone(),
+                )))?;
+                Arc::new(unquant).apply_isq(dtype, device, n_quantized, imatrix_weight)
+            }
+            None => {
+                let bias = match &self.bias {
+                    Some(b) => Some(b.to_device(&device)?),
+                    None => None,
+                };
+                let input_scales = match &self.input_scales {
+                    Some(s) => Some(s.to_device(&device)?),
+                    None => None,
+                };
+                Ok(Arc::new(Self {
+                    qweight: self.qweight.to_device(&device)?,
+                    qzeros: self.qzeros.to_device(&device)?,
+                    scales: self.scales.to_device(&device)?,
+                    input_scales,
+                    bias,
+                    group_size: self.group_size,
+                }))
+            }
+        }
+    }
+
+    fn get_max_isq_cpu_threads(&self, _dtype: IsqType) -> Option<NonZeroUsize> {
+        None
+    }
+}
+
+// Serialization structure:
+//
+// -----------------------
+// UQFF version, u32, little endian
+// -----------------------
+// ISQ type (4 for AWQ), u8, little endian
+// -----------------------
+// Whether bias data is included, u8 boolean
+// -----------------------
+// Whether input scales are included, u8 boolean
+// -----------------------
+// Packed weight tensor data generated by `serialize_tensor`, two values per byte (see
+// `QuantMethodConfig::Awq`). Refer to its docs for layout.
+// -----------------------
+// Packed zeros tensor data generated by `serialize_tensor`, two values per byte. Refer to its docs
+// for layout.
+// -----------------------
+// Scales tensor data generated by `serialize_tensor`. Refer to its docs for layout.
+// -----------------------
+// Group size, u32, little endian
+// -----------------------
+// [OPTIONAL] Input scales tensor data generated by `serialize_tensor`. Refer to its docs for layout.
+// -----------------------
+// [OPTIONAL] Bias tensor data generated by `serialize_tensor`. Refer to its docs for layout.
+// -----------------------
+
+impl QuantizedSerde for AwqLayer {
+    fn isq_serde_supported(&self) -> bool {
+        true
+    }
+    fn name(&self) -> &'static str {
+        "awq"
+    }
+    fn serialize(&self) -> Result<Cow<[u8]>> {
+        let mut buffer = Vec::new();
+
+        // Version is always first!
+        buffer.extend(&UQFF_VERSION.to_le_bytes());
+
+        // ISQ type for AWQ is 4
+        buffer.push(QuantizedSerdeType::Awq as u8);
+
+        // Has bias
+        buffer.push(self.bias.is_some() as u8);
+
+        // Has input scales
+        buffer.push(self.input_scales.is_some() as u8);
+
+        serialize_tensor(&mut buffer, &pack_nibbles_tensor(&self.qweight)?)?;
+        serialize_tensor(&mut buffer, &pack_nibbles_tensor(&self.qzeros)?)?;
+        serialize_tensor(&mut buffer, &self.scales)?;
+
+        buffer.extend(&(self.group_size as u32).to_le_bytes());
+
+        if let Some(input_scales) = &self.input_scales {
+            serialize_tensor(&mut buffer, input_scales)?;
+        }
+
+        if let Some(bias) = &self.bias {
+            // Bias
+            serialize_tensor(&mut buffer, bias)?;
+        }
+
+        Ok(Cow::from(buffer))
+    }
+
+    fn deserialize(data: Cow<[u8]>, device: &Device) -> Result<Arc<dyn QuantMethod>>
+    where
+        Self: Sized,
+    {
+        let mut buffer = Cursor::new(data);
+
+        let version = buffer.read_u32::<LittleEndian>()?;
+        if let Err(e) = version_is_compatible(version) {
+            return Err(candle_core::Error::wrap(e));
+        }
+
+        let isq_type = buffer.read_u8()? as usize;
+        if isq_type != QuantizedSerdeType::Awq as usize {
+            candle_core::bail!(
+                "ISQ type ({isq_type}) doesn't match expected type {}",
+                QuantizedSerdeType::Awq as usize
+            );
+        }
+
+        let has_bias = buffer.read_u8()? != 0;
+        let has_input_scales = buffer.read_u8()? != 0;
+
+        let qweight = deserialize_tensor(&mut buffer, device)?;
+        let qzeros = deserialize_tensor(&mut buffer, device)?;
+        let scales = deserialize_tensor(&mut buffer, device)?;
+
+        let group_size = buffer.read_u32::<LittleEndian>()? as usize;
+
+        let input_scales = if has_input_scales {
+            Some(deserialize_tensor(&mut buffer, device)?)
+        } else {
+            None
+        };
+
+        let bias = if has_bias {
+            Some(deserialize_tensor(&mut buffer, device)?)
+        } else {
+            None
+        };
+
+        let mut layer = Self::new(QuantMethodConfig::Awq {
+            qweight,
+            qzeros,
+            scales,
+            bias,
+            group_size,
+        })?;
+        layer.input_scales = input_scales;
+        Ok(Arc::new(layer))
+    }
+}
+
+pub fn awq_linear(
+    in_dim: usize,
+    out_dim: usize,
+    config: &QuantizedConfig,
+    vb: ShardedVarBuilder,
+) -> Result<Arc<dyn QuantMethod>> {
+    // Handle the case where the layer is dummy (no tensors)
+    if !(vb.contains_tensor("qweight")
+        && vb.contains_tensor("qzeros")
+        && vb.contains_tensor("scales"))
+    {
+        let layer = <DummyLayer as QuantMethod>::new(QuantMethodConfig::Dummy)?;
+        return Ok(Arc::new(layer) as Arc<dyn QuantMethod>);
+    }
+
+    let bits = config.bits.unwrap_or(4);
+    if bits != 4 {
+        candle_core::bail!("AWQ only supports 4 bits, got {bits}.");
+    }
+    let group_size = config
+        .group_size
+        .expect("AWQ requires group size in config");
+    let n_groups = in_dim / group_size;
+
+    let version = config
+        .version
+        .as_deref()
+        .unwrap_or("gemm")
+        .to_ascii_lowercase();
+    let (q, z, scales) = match version.as_str() {
+        "gemm" => {
+            let qweight = vb.get_with_hints_dtype(
+                (in_dim, out_dim / AWQ_PACK_FACTOR),
+                "qweight",
+                Default::default(),
+                DType::I32,
+            )?;
+            let qzeros = vb.get_with_hints_dtype(
+                (n_groups, out_dim / AWQ_PACK_FACTOR),
+                "qzeros",
+                Default::default(),
+                DType::I32,
+            )?;
+            let scales = vb.get_with_hints_dtype(
+                (n_groups, out_dim),
+                "scales",
+                Default::default(),
+                DType::F16,
+            )?;
+            (
+                unpack_gemm(&to_i32_vec(&qweight)?, in_dim, out_dim),
+                unpack_gemm(&to_i32_vec(&qzeros)?, n_groups, out_dim),
+                scales,
+            )
+        }
+        "gemv" => {
+            // GEMV checkpoints are packed along the input dimension, with padded zeros and scales.
+            let zeros_width = gemv_zeros_width(in_dim, group_size);
+            let qweight = vb.get_with_hints_dtype(
+                (out_dim, in_dim / AWQ_PACK_FACTOR),
+                "qweight",
+                Default::default(),
+                DType::I32,
+            )?;
+            let qzeros = vb.get_with_hints_dtype(
+                (out_dim, zeros_width),
+                "qzeros",
+                Default::default(),
+                DType::I32,
+            )?;
+            let scales = vb.get_with_hints_dtype(
+                (out_dim, zeros_width * AWQ_PACK_FACTOR),
+                "scales",
+                Default::default(),
+                DType::F16,
+            )?;
+            let q = unpack_gemv(
+                &to_i32_vec(&qweight)?,
+                out_dim,
+                in_dim / AWQ_PACK_FACTOR,
+                in_dim,
+            );
+            let z = unpack_gemv(&to_i32_vec(&qzeros)?, out_dim, zeros_width, n_groups);
+            (
+                transpose(&q, out_dim, in_dim),
+                transpose(&z, out_dim, n_groups),
+                scales.narrow(1, 0, n_groups)?.t()?.contiguous()?,
+            )
+        }
+        other => {
+            candle_core::bail!("Unsupported AWQ version `{other}`, expected `gemm` or `gemv`.")
+        }
+    };
+    let bias = if vb.contains_tensor("bias") {
+        Some(vb.get_with_hints_dtype((out_dim,), "bias", Default::default(), DType::F16)?)
+    } else {
+        None
+    };
+
+    let device = scales.device().clone();
+    let config = QuantMethodConfig::Awq {
+        qweight: pack_nibbles(&q, in_dim, out_dim, &device)?,
+        qzeros: pack_nibbles(&z, n_groups, out_dim, &device)?,
+        scales,
+        bias,
+        group_size,
+    };
+    Ok(Arc::new(AwqLayer::new(config)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, collections::HashMap, sync::Arc};
+
+    use candle_core::{DType, Device, Result, Tensor};
+
+    use super::{awq_linear, gemv_zeros_width, AwqLayer, AWQ_PACK_FACTOR};
+    use crate::{QuantMethod, QuantizedConfig, QuantizedSerde, ShardedSafeTensors};
+
+    // Large enough for the forward pass to dequantize the weight in several chunks.
+    const IN_DIM: usize = 1088;
+    const OUT_DIM: usize = 16;
+    const GROUP_SIZE: usize = 32;
+    const N_GROUPS: usize = IN_DIM / GROUP_SIZE;
+
+    /// Known 4 bit weights `(IN_DIM, OUT_DIM)`, zeros and scales `(N_GROUPS, OUT_DIM)`.
+    struct Values {
+        q: Vec<u8>,
+        z: Vec<u8>,
+        s: Vec<f32>,
+    }
+
+    impl Values {
+        fn new() -> Self {
+            Self {
+                q: (0..IN_DIM * OUT_DIM).map(|i| (i * 7 % 16) as u8).collect(),
+                z: (0..N_GROUPS * OUT_DIM)
+                    .map(|i| (i * 5 % 16) as u8)
+                    .collect(),
+                s: (0..N_GROUPS * OUT_DIM)
+                    .map(|i| half::f16::from_f32(0.01 * (i % 50 + 1) as f32).to_f32())
+                    .collect(),
+            }
+        }
+
+        /// The `(OUT_DIM, IN_DIM)` weight, `(q - z) * s`.
+        fn weight(&self) -> Vec<Vec<f32>> {
+            (0..OUT_DIM)
+                .map(|n| {
+                    (0..IN_DIM)
+                        .map(|k| {
+                            let g = k / GROUP_SIZE;
+                            (self.q[k * OUT_DIM + n] as f32 - self.z[g * OUT_DIM + n] as f32)
+                                * self.s[g * OUT_DIM + n]
+                        })
+                        .collect()
+                })
+                .collect()
+        }
+    }
+
+    /// Pack `(rows, cols)` values into AWQ GEMM words, as AutoAWQ does.
+    fn pack_gemm(values: &[u8], rows: usize, cols: usize) -> Vec<i32> {
+        const ORDER: [usize; AWQ_PACK_FACTOR] = [0, 2, 4, 6, 1, 3, 5, 7];
+        let n_words = cols / AWQ_PACK_FACTOR;
+        let mut words = vec![0u32; rows * n_words];
+        for r in 0..rows {
+            for w in 0..n_words {
+                for (i, col) in ORDER.iter().enumerate() {
+                    let v = values[r * cols + w * AWQ_PACK_FACTOR + col] as u32;
+                    words[r * n_words + w] |= v << (4 * i);
+                }
+            }
+        }
+        words.into_iter().map(|w| w as i32).collect()
+    }
+
+    /// Pack the transpose of `(rows, cols)` values into `(cols, n_words)` AWQ GEMV words, in
+    /// order along `rows` and padded with zeros.
+    fn pack_gemv_t(values: &[u8], rows: usize, cols: usize, n_words: usize) -> Vec<i32> {
+        let mut words = vec![0u32; cols * n_words];
+        for c in 0..cols {
+            for r in 0..rows {
+                let v = values[r * cols + c] as u32;
+                words[c * n_words + r / AWQ_PACK_FACTOR] |= v << (4 * (r % AWQ_PACK_FACTOR));
+            }
+        }
+        words.into_iter().map(|w| w as i32).collect()
+    }
+
+    fn load(version: &str, tensors: HashMap<String, Tensor>) -> Result<Arc<dyn QuantMethod>> {
+        let dev = Device::Cpu;
+        let config = QuantizedConfig {
+            bits: Some(4),
+            group_size: Some(GROUP_SIZE),
+            version: Some(version.to_string()),
+            ..Default::default()
+        };
+        let vb = ShardedSafeTensors::wrap(Box::new(tensors), DType::F32, dev);
+        awq_linear(IN_DIM, OUT_DIM, &config, vb)
+    }
+
+    fn gemm_layer(values: &Values) -> Result<Arc<dyn QuantMethod>> {
+        let dev = Device::Cpu;
+        let n_words = OUT_DIM / AWQ_PACK_FACTOR;
+        let tensors = HashMap::from([
+            (
+                "qweight".to_string(),
+                Tensor::from_vec(
+                    pack_gemm(&values.q, IN_DIM, OUT_DIM),
+                    (IN_DIM, n_words),
+                    &dev,
+                )?,
+            ),
+            (
+                "qzeros".to_string(),
+                Tensor::from_vec(
+                    pack_gemm(&values.z, N_GROUPS, OUT_DIM),
+                    (N_GROUPS, n_words),
+                    &dev,
+                )?,
+            ),
+            (
+                "scales".to_string(),
+                Tensor::from_vec(values.s.clone(), (N_GROUPS, OUT_DIM), &dev)?,
+            ),
+        ]);
+        load("gemm", tensors)
+    }
+
+    fn gemv_layer(values: &Values) -> Result<Arc<dyn QuantMethod>> {
+        let dev = Device::Cpu;
+        let zeros_width = gemv_zeros_width(IN_DIM, GROUP_SIZE);
+        // The scales are transposed and padded like the zeros.
+        let mut scales = vec![0f32; OUT_DIM * zeros_width * AWQ_PACK_FACTOR];
+        for g in 0..N_GROUPS {
+            for n in 0..OUT_DIM {
+                scales[n * zeros_width * AWQ_PACK_FACTOR + g] = values.s[g * OUT_DIM + n];
+            }
+        }
+        let tensors = HashMap::from([
+            (
+                "qweight".to_string(),
+                Tensor::from_vec(
+                    pack_gemv_t(&values.q, IN_DIM, OUT_DIM, IN_DIM / AWQ_PACK_FACTOR),
+                    (OUT_DIM, IN_DIM / AWQ_PACK_FACTOR),
+                    &dev,
+                )?,
+            ),
+            (
+                "qzeros".to_string(),
+                Tensor::from_vec(
+                    pack_gemv_t(&values.z, N_GROUPS, OUT_DIM, zeros_width),
+                    (OUT_DIM, zeros_width),
+                    &dev,
+                )?,
+            ),
+            (
+                "scales".to_string(),
+                Tensor::from_vec(scales, (OUT_DIM, zeros_width * AWQ_PACK_FACTOR), &dev)?,
+            ),
+        ]);
+        load("gemv", tensors)
+    }
+
+    fn assert_close(actual: &[Vec<f32>], expected: &[Vec<f32>]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().flatten().zip(expected.iter().flatten()) {
+            assert!((a - e).abs() < 1e-4, "{a} != {e}");
+        }
+    }
+
+    /// The forward pass is `x @ w.T`.
+    fn check_forward(layer: &dyn QuantMethod, weight: &[Vec<f32>]) -> Result<()> {
+        let x = (Tensor::arange(0f32, IN_DIM as f32, &Device::Cpu)? / IN_DIM as f64)?
+            .reshape((1, IN_DIM))?;
+        let y = layer.forward(&x)?.to_vec2::<f32>()?;
+        let x = x.flatten_all()?.to_vec1::<f32>()?;
+        let expected = weight
+            .iter()
+            .map(|row| row.iter().zip(&x).map(|(w, x)| w * x).sum())
+            .collect::<Vec<f32>>();
+        for (a, e) in y[0].iter().zip(&expected) {
+            assert!((a - e).abs() < 1e-3 * e.abs().max(1.), "{a} != {e}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_gemm_layout() -> Result<()> {
+        let values = Values::new();
+        let layer = gemm_layer(&values)?;
+        assert_close(
+            &layer
+                .dequantize_w()?
+                .to_dtype(DType::F32)?
+                .to_vec2::<f32>()?,
+            &values.weight(),
+        );
+        check_forward(&*layer, &values.weight())
+    }
+
+    #[test]
+    fn test_gemv_layout() -> Result<()> {
+        let values = Values::new();
+        let layer = gemv_layer(&values)?;
+        assert_close(
+            &layer
+                .dequantize_w()?
+                .to_dtype(DType::F32)?
+                .to_vec2::<f32>()?,
+            &values.weight(),
+        );
+        check_forward(&*layer, &values.weight())
+    }
+
+    #[test]
+    fn test_serde_roundtrip() -> Result<()> {
+        let values = Values::new();
+        let data = gemm_layer(&values)?.serialize()?;
+        let layer = AwqLayer::deserialize(Cow::from(data.to_vec()), &Device::Cpu)?;
+        assert_close(
+            &layer
+                .dequantize_w()?
+                .to_dtype(DType::F32)?
+                .to_vec2::<f32>()?,
+            &values.weight(),
+        );
+        check_forward(&*layer, &values.weight())
+    }
+}
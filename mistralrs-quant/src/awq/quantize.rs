@@ -0,0 +1,156 @@
+use candle_core::{DType, Device, Result, Tensor};
+
+use super::AwqLayer;
+
+/// Group sizes tried for AWQ ISQ, in order of preference. 128 is the AutoAWQ default.
+const AWQ_ISQ_GROUP_SIZES: [usize; 3] = [128, 64, 32];
+/// Number of exponents tried in the search for the activation-aware scales.
+const AWQ_GRID_SIZE: usize = 20;
+
+/// Round to nearest asymmetric 4 bit quantization of `w` of shape `(out_dim, in_dim)`, with groups
+/// along the input dimension, as done by AutoAWQ. Returns the quantized values `(out_dim, in_dim)`
+/// and the zeros and scales `(out_dim, n_groups)`.
+fn quantize_groups(w: &Tensor, group_size: usize) -> Result<(Tensor, Tensor, Tensor)> {
+    let (out_dim, in_dim) = w.dims2()?;
+    let w = w.reshape((out_dim, in_dim / group_size, group_size))?;
+    let max = w.max_keepdim(2)?;
+    let min = w.min_keepdim(2)?;
+    let scales = ((max - &min)?.clamp(1e-5, f32::MAX)? / 15.)?;
+    let zeros = (min / &scales)?.round()?.neg()?.clamp(0., 15.)?;
+    let q = w
+        .broadcast_div(&scales)?
+        .round()?
+        .broadcast_add(&zeros)?
+        .clamp(0., 15.)?;
+    Ok((
+        q.reshape((out_dim, in_dim))?,
+        zeros.squeeze(2)?,
+        scales.squeeze(2)?,
+    ))
+}
+
+fn dequantize_groups(q: &Tensor, zeros: &Tensor, scales: &Tensor) -> Result<Tensor> {
+    let (out_dim, in_dim) = q.dims2()?;
+    let n_groups = zeros.dim(1)?;
+    q.reshape((out_dim, n_groups, in_dim / n_groups))?
+        .broadcast_sub(&zeros.unsqueeze(2)?)?
+        .broadcast_mul(&scales.unsqueeze(2)?)?
+        .reshape((out_dim, in_dim))
+}
+
+impl AwqLayer {
+    /// Quantize `w` of shape `(out_dim, in_dim)` with AWQ, given the mean squared activation of
+    /// each input channel (as in an imatrix).
+    ///
+    /// The weight is scaled by `s = sqrt(imatrix)^alpha` along the input dimension before
+    /// quantization, so that the channels with large activations are quantized more precisely.
+    /// `alpha` is chosen to minimize the activation weighted quantization error.
+    ///
+    /// <https://arxiv.org/abs/2306.00978>
+    pub fn quantize(
+        w: &Tensor,
+        bias: Option<Tensor>,
+        imatrix: &[f32],
+        device: &Device,
+    ) -> Result<Self> {
+        let (out_dim, in_dim) = w.dims2()?;
+        if imatrix.len() != in_dim {
+            candle_core::bail!(
+                "Expected {in_dim} imatrix values for AWQ, got {}.",
+                imatrix.len()
+            );
+        }
+        if out_dim % 2 != 0 {
+            candle_core::bail!("AWQ ISQ requires an even output dimension, got {out_dim}.");
+        }
+        let Some(group_size) = AWQ_ISQ_GROUP_SIZES.into_iter().find(|g| in_dim % g == 0) else {
+            candle_core::bail!(
+                "AWQ ISQ requires the input dimension to be divisible by 32, got {in_dim}."
+            );
+        };
+
+        let dtype = w.dtype();
+        let w = w.to_device(&Device::Cpu)?.to_dtype(DType::F32)?;
+        let act = Tensor::from_slice(imatrix, (1, in_dim), &Device::Cpu)?.relu()?;
+        let x_mag = act.sqrt()?;
+
+        let mut best: Option<(f32, Tensor)> = None;
+        for i in 0..AWQ_GRID_SIZE {
+            let alpha = i as f64 / AWQ_GRID_SIZE as f64;
+            let s = x_mag.powf(alpha)?.clamp(1e-4, f32::MAX)?;
+            let s_flat = s.flatten_all()?;
+            let norm = s_flat.max(0)?.to_scalar::<f32>()? * s_flat.min(0)?.to_scalar::<f32>()?;
+            let s = (s / norm.sqrt() as f64)?;
+
+            let (q, zeros, scales) = quantize_groups(&w.broadcast_mul(&s)?, group_size)?;
+            let w_q = dequantize_groups(&q, &zeros, &scales)?.broadcast_div(&s)?;
+            let err = (w_q - &w)?
+                .sqr()?
+                .broadcast_mul(&act)?
+                .sum_all()?
+                .to_scalar::<f32>()?;
+            if best.as_ref().is_none_or(|(best_err, _)| err < *best_err) {
+                best = Some((err, s));
+            }
+        }
+        let (_, s) = best.expect("AWQ grid is not empty");
+
+        let (q, zeros, scales) = quantize_groups(&w.broadcast_mul(&s)?, group_size)?;
+        let to_u8 = |t: Tensor| -> Result<Tensor> {
+            t.t()?.contiguous()?.to_dtype(DType::U8)?.to_device(device)
+        };
+        let bias = match bias {
+            Some(b) => Some(b.to_device(device)?),
+            None => None,
+        };
+        Ok(Self {
+            qweight: to_u8(q)?,
+            qzeros: to_u8(zeros)?,
+            scales: scales.t()?.to_dtype(dtype)?.to_device(device)?,
+            input_scales: Some(s.recip()?.squeeze(0)?.to_dtype(dtype)?.to_device(device)?),
+            bias,
+            group_size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{DType, Device, Result, Tensor};
+
+    use super::{dequantize_groups, quantize_groups};
+    use crate::{AwqLayer, QuantMethod};
+
+    #[test]
+    fn test_awq_beats_round_to_nearest() -> Result<()> {
+        let dev = Device::Cpu;
+        // A few input channels with much larger activations, as in LLMs.
+        let mut imatrix = vec![1f32; 256];
+        for x in imatrix.iter_mut().step_by(37) {
+            *x = 400.;
+        }
+        let w = Tensor::randn(0f32, 1., (64, 256), &dev)?;
+        let act = Tensor::from_slice(&imatrix, (1, 256), &dev)?;
+        let weighted_err = |w_q: &Tensor| -> Result<f32> {
+            (w_q - &w)?
+                .sqr()?
+                .broadcast_mul(&act)?
+                .sum_all()?
+                .to_scalar::<f32>()
+        };
+
+        let layer = AwqLayer::quantize(&w, None, &imatrix, &dev)?;
+        assert_eq!(layer.group_size, 128);
+        let awq_err = weighted_err(&layer.dequantize_w()?)?;
+
+        let (q, zeros, scales) = quantize_groups(&w, 128)?;
+        let rtn_err = weighted_err(&dequantize_groups(&q, &zeros, &scales)?)?;
+        assert!(awq_err < rtn_err, "AWQ {awq_err} >= RTN {rtn_err}");
+
+        let x = Tensor::randn(0f32, 1., (3, 256), &dev)?;
+        let y = layer.forward(&x)?;
+        assert_eq!(y.dims(), &[3, 64]);
+        assert_eq!(y.dtype(), DType::F32);
+        Ok(())
+    }
+}
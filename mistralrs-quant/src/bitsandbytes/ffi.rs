@@ -3,6 +3,30 @@ use half::{bf16, f16};
 
 #[allow(dead_code)]
 extern "C" {
+    pub(crate) fn int8_matmul_i32(
+        a: *const i8,
+        b: *const i8,
+        out: *mut i32,
+        m: i32,
+        n: i32,
+        k: i32,
+        stream: CUstream,
+    );
+
+    pub(crate) fn int8_outlier_matmul_f32(
+        x: *const f32,
+        b: *const i8,
+        col_absmax: *const f32,
+        threshold: f32,
+        idx: *mut i32,
+        count: *mut i32,
+        out: *mut f32,
+        m: i32,
+        n: i32,
+        k: i32,
+        stream: CUstream,
+    );
+
     pub(crate) fn dequantize_blockwise_f32_int8(
         code: *const f32,
         a: *const u8,
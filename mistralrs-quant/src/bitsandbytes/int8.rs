@@ -0,0 +1,379 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use candle_core::{DType, Device, Result, Tensor, D};
+use candle_nn::Linear;
+
+use crate::{
+    IsqType, QuantMethod, QuantMethodConfig, QuantizedSerde, ShardedVarBuilder, UnquantLinear,
+};
+
+use super::op;
+
+/// Default `llm_int8_threshold` of bitsandbytes.
+pub(crate) const DEFAULT_LLM_INT8_THRESHOLD: f32 = 6.0;
+
+/// A bitsandbytes LLM.int8 layer.
+///
+/// The weight is quantized to int8 with one absmax scale per output row (`SCB`). In the forward
+/// pass, input features (columns) with a magnitude of at least `threshold` in any row are outliers:
+/// they are multiplied in f32 with the dequantized weight columns. All other
+/// features are quantized to int8 per row and multiplied with the int8 weight, accumulating in
+/// int32.
+#[derive(Debug)]
+pub struct BnbInt8Linear {
+    /// Int8 weight of shape `(out_dim, in_dim)`, stored as the bits of a `U8` tensor.
+    weight: Tensor,
+    /// Absmax of each row of the weight, of shape `(out_dim,)`.
+    scb: Tensor,
+    bias: Option<Tensor>,
+    threshold: f32,
+    dtype: DType,
+}
+
+impl BnbInt8Linear {
+    pub fn linear_b(
+        in_dim: usize,
+        out_dim: usize,
+        bias: bool,
+        threshold: f32,
+        vb: ShardedVarBuilder,
+    ) -> Result<Self> {
+        let weight = vb.get_unchecked_dtype("weight", DType::U8)?;
+        if weight.dims() != [out_dim, in_dim] {
+            candle_core::bail!(
+                "Expected LLM.int8 weight of shape ({out_dim}, {in_dim}), got {:?}",
+                weight.dims()
+            );
+        }
+        if vb.contains_tensor("weight_format")
+            && vb
+                .get_unchecked_dtype("weight_format", DType::U8)?
+                .flatten_all()?
+                .to_vec1::<u8>()?
+                .first()
+                .is_some_and(|format| *format != 0)
+        {
+            candle_core::bail!("Only the row-major LLM.int8 weight format is supported.");
+        }
+        let scb = vb.get_with_hints_dtype((out_dim,), "SCB", Default::default(), DType::F32)?;
+        let bias = if bias {
+            Some(vb.get((out_dim,), "bias")?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            weight,
+            scb,
+            bias,
+            threshold,
+            dtype: vb.dtype(),
+        })
+    }
+
+    /// Interpret int8 values stored in a `U8` tensor as f32.
+    fn int8_to_f32(xs: &Tensor) -> Result<Tensor> {
+        let xs = xs.to_dtype(DType::F32)?;
+        let negative = xs.ge(128f64)?;
+        negative.where_cond(&(&xs - 256f64)?, &xs)
+    }
+
+    /// Round f32 values in `[-127, 127]` to int8, stored as the bits of a `U8` tensor.
+    fn f32_to_int8(xs: &Tensor) -> Result<Tensor> {
+        let xs = xs.round()?;
+        let negative = xs.lt(0f64)?;
+        negative
+            .where_cond(&(&xs + 256f64)?, &xs)?
+            .to_dtype(DType::U8)
+    }
+
+    /// Dequantize weight columns: `weight * SCB / 127`.
+    fn dequantize_weight(&self, weight: &Tensor) -> Result<Tensor> {
+        Self::int8_to_f32(weight)?.broadcast_mul(&(self.scb.unsqueeze(1)? / 127.)?)
+    }
+
+    fn forward_int8(&self, xs: &Tensor) -> Result<Tensor> {
+        let m = xs.dim(0)?;
+        let mut xs = xs.to_dtype(DType::F32)?;
+
+        let outlier_out = if self.threshold > 0. {
+            // Multiply the outlier features with the dequantized weight columns in f32...
+            let col_absmax = xs.abs()?.max(0)?;
+            let outlier_out =
+                op::int8_outlier_matmul(&xs, &self.weight, &col_absmax, self.threshold)?
+                    .broadcast_mul(&(self.scb.unsqueeze(0)? / 127.)?)?;
+
+            // ...and remove them from the int8 part. The outliers never leave the device.
+            let mask = col_absmax.lt(self.threshold as f64)?.to_dtype(DType::F32)?;
+            xs = xs.broadcast_mul(&mask)?;
+            Some(outlier_out)
+        } else {
+            None
+        };
+
+        // Row-wise absmax quantization of the activations
+        let row_absmax = xs.abs()?.max_keepdim(1)?.clamp(1e-8, f32::INFINITY)?;
+        let xq = Self::f32_to_int8(&(xs.broadcast_div(&row_absmax)? * 127.)?)?;
+        let acc = op::int8_matmul(&xq, &self.weight)?.to_dtype(DType::F32)?;
+
+        let scale = row_absmax.broadcast_mul(&(self.scb.unsqueeze(0)? / (127. * 127.))?)?;
+        let out = (acc * scale)?;
+        let out = match outlier_out {
+            Some(outlier_out) => (out + outlier_out)?,
+            None => out,
+        };
+        debug_assert_eq!(out.dims(), [m, self.scb.dim(0)?]);
+        Ok(out)
+    }
+}
+
+impl QuantMethod for BnbInt8Linear {
+    fn new(method: QuantMethodConfig) -> candle_core::Result<Self>
+    where
+        Self: Sized,
+    {
+        match method {
+            QuantMethodConfig::Gguf { .. }
+            | QuantMethodConfig::Gptq { .. }
+            | QuantMethodConfig::Hqq { .. }
+            | QuantMethodConfig::Dummy
+            | QuantMethodConfig::Awq { .. }
+            | QuantMethodConfig::Unquantized(_)
+            | QuantMethodConfig::FP8 { .. }
+            | QuantMethodConfig::Bnb { .. }
+            | QuantMethodConfig::BlockwiseFP8 { .. } => unreachable!(),
+            QuantMethodConfig::BnbInt8 {
+                weight,
+                scb,
+                bias,
+                threshold,
+                dtype,
+            } => Ok(Self {
+                weight,
+                scb,
+                bias,
+                threshold,
+                dtype,
+            }),
+        }
+    }
+
+    fn dequantize_w(&self) -> Result<Tensor> {
+        self.dequantize_weight(&self.weight)?.to_dtype(self.dtype)
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let original_dtype = xs.dtype();
+        let mut out_dims = xs.dims().to_vec();
+        *out_dims.last_mut().unwrap() = self.scb.dim(0)?;
+
+        let xs = xs.flatten_to(D::Minus2)?;
+        let res = if xs.device().is_metal() {
+            // No int8 matmul kernel, compute with the dequantized weight
+            xs.to_dtype(DType::F32)?
+                .matmul(&self.dequantize_weight(&self.weight)?.t()?)?
+        } else {
+            self.forward_int8(&xs)?
+        };
+        let res = res.reshape(out_dims)?.to_dtype(original_dtype)?;
+        if let Some(bias) = &self.bias {
+            res.broadcast_add(&bias.to_dtype(original_dtype)?)
+        } else {
+            Ok(res)
+        }
+    }
+
+    fn quantized_act_type(&self) -> Option<DType> {
+        None
+    }
+
+    fn add_delta_w(&self, _delta: &Tensor) -> Result<Arc<dyn QuantMethod>> {
+        candle_core::bail!("LLM.int8 quantization does not support adding weight delta.")
+    }
+
+    fn dtype_and_device(&self) -> (DType, Device) {
+        (self.dtype, self.weight.device().clone())
+    }
+
+    fn apply_isq(
+        self: Arc<Self>,
+        dtype: Option<IsqType>,
+        device: Device,
+        n_quantized: &AtomicUsize,
+        imatrix_weight: Option<Vec<f32>>,
+    ) -> Result<Arc<dyn QuantMethod>> {
+        if dtype.is_none() {
+            return Ok(self);
+        }
+        let unquant = UnquantLinear::new(QuantMethodConfig::Unquantized(Linear::new(
+            self.dequantize_w()?,
+            self.bias.clone(),
+        )))?;
+        Arc::new(unquant).apply_isq(dtype, device, n_quantized, imatrix_weight)
+    }
+
+    fn get_max_isq_cpu_threads(&self, _dtype: IsqType) -> Option<NonZeroUsize> {
+        None
+    }
+}
+
+impl QuantizedSerde for BnbInt8Linear {
+    fn name(&self) -> &'static str {
+        "bnb-int8-linear"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{DType, Device, Result, Tensor};
+
+    use super::BnbInt8Linear;
+    use crate::QuantMethod;
+
+    fn layer(weight: &Tensor, threshold: f32) -> Result<BnbInt8Linear> {
+        let scb = weight.abs()?.max(1)?;
+        let wq = BnbInt8Linear::f32_to_int8(&(weight.broadcast_div(&scb.unsqueeze(1)?)? * 127.)?)?;
+        Ok(BnbInt8Linear {
+            weight: wq,
+            scb,
+            bias: None,
+            threshold,
+            dtype: DType::F32,
+        })
+    }
+
+    /// LLM.int8 computed directly: the outlier features in f32, all others quantized to int8 per
+    /// row and multiplied exactly.
+    fn reference(layer: &BnbInt8Linear, xs: &[Vec<f32>]) -> Result<Vec<Vec<f32>>> {
+        let wq = layer.weight.to_vec2::<u8>()?;
+        let scb = layer.scb.to_vec1::<f32>()?;
+        let k = xs[0].len();
+        let outlier = (0..k)
+            .map(|c| {
+                layer.threshold > 0.
+                    && xs.iter().map(|row| row[c].abs()).fold(0f32, f32::max) >= layer.threshold
+            })
+            .collect::<Vec<_>>();
+        Ok(xs
+            .iter()
+            .map(|row| {
+                let absmax = (0..k)
+                    .filter(|c| !outlier[*c])
+                    .map(|c| row[c].abs())
+                    .fold(0f32, f32::max)
+                    .max(1e-8);
+                let xq = (0..k)
+                    .map(|c| {
+                        if outlier[c] {
+                            0
+                        } else {
+                            (row[c] / absmax * 127.).round() as i32
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                wq.iter()
+                    .zip(&scb)
+                    .map(|(w, scb)| {
+                        let acc = (0..k).map(|c| xq[c] * w[c] as i8 as i32).sum::<i32>();
+                        let outliers = (0..k)
+                            .filter(|c| outlier[*c])
+                            .map(|c| row[c] * w[c] as i8 as f32)
+                            .sum::<f32>();
+                        acc as f32 * (absmax * (scb / (127. * 127.))) + outliers * (scb / 127.)
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    #[test]
+    fn int8_matmul_matches_reference() -> Result<()> {
+        let dev = Device::Cpu;
+        let weight = Tensor::randn(0f32, 1., (7, 40), &dev)?;
+        // Features 3 and 17 are outliers, 25 only exceeds the lower threshold.
+        let mut xs = Tensor::randn(0f32, 1., (5, 40), &dev)?.to_vec2::<f32>()?;
+        xs[1][3] = 20.;
+        xs[4][17] = -12.;
+        xs[2][25] = 4.5;
+        let xs_t = Tensor::new(xs.clone(), &dev)?;
+
+        for threshold in [0., 4., 6., 100.] {
+            let layer = layer(&weight, threshold)?;
+            let out = layer.forward(&xs_t)?.to_vec2::<f32>()?;
+            let expected = reference(&layer, &xs)?;
+            for (out, expected) in out.iter().flatten().zip(expected.iter().flatten()) {
+                assert!(
+                    (out - expected).abs() <= 1e-5 * expected.abs().max(1.),
+                    "threshold {threshold}: {out} vs {expected}"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn int8_matmul_handles_outliers() -> Result<()> {
+        let dev = Device::Cpu;
+        let weight = Tensor::new(
+            &[
+                [0.5f32, -0.25, 0.125, -1.0],
+                [-0.75, 0.5, 1.0, 0.25],
+                [0.1, 0.2, -0.3, 0.4],
+            ],
+            &dev,
+        )?;
+        // The third feature is an outlier
+        let xs = Tensor::new(&[[1.0f32, -2.0, 20.0, 0.5], [0.25, 1.5, -10.0, -1.0]], &dev)?;
+        let expected = xs.matmul(&weight.t()?)?;
+
+        // Keeping the outlier out of the int8 part keeps the error small
+        let out = layer(&weight, 6.)?.forward(&xs)?;
+        let err = (out - &expected)?.abs()?.flatten_all()?.max(0)?;
+        assert!(err.to_scalar::<f32>()? < 0.05);
+
+        // Dequantization recovers the weight up to int8 rounding
+        let dequant = layer(&weight, 6.)?.dequantize_w()?;
+        let err = (dequant - weight)?.abs()?.flatten_all()?.max(0)?;
+        assert!(err.to_scalar::<f32>()? < 0.01);
+        Ok(())
+    }
+
+    #[cfg(feature = "cuda")]
+    #[test]
+    fn int8_matmul_cuda_matches_cpu() -> Result<()> {
+        use crate::bitsandbytes::op;
+
+        let cpu = Device::Cpu;
+        let cuda = Device::new_cuda(0)?;
+        // Unaligned and aligned inner dimensions, and partial tiles
+        for (m, n, k) in [(1, 70, 131), (37, 70, 131), (64, 128, 256), (130, 65, 4100)] {
+            let to_int8 =
+                |xs: &Tensor| BnbInt8Linear::f32_to_int8(&(xs * 127.)?.clamp(-127., 127.)?);
+            let a = to_int8(&Tensor::randn(0f32, 1., (m, k), &cpu)?)?;
+            let b = to_int8(&Tensor::randn(0f32, 1., (n, k), &cpu)?)?;
+            let expected = op::int8_matmul(&a, &b)?.to_vec2::<i32>()?;
+            let out = op::int8_matmul(&a.to_device(&cuda)?, &b.to_device(&cuda)?)?;
+            assert_eq!(out.to_vec2::<i32>()?, expected, "({m}, {n}, {k})");
+
+            let x = Tensor::randn(0f32, 4., (m, k), &cpu)?;
+            let col_absmax = x.abs()?.max(0)?;
+            let expected = op::int8_outlier_matmul(&x, &b, &col_absmax, 10.)?;
+            let out = op::int8_outlier_matmul(
+                &x.to_device(&cuda)?,
+                &b.to_device(&cuda)?,
+                &col_absmax.to_device(&cuda)?,
+                10.,
+            )?;
+            let err = (out.to_device(&cpu)? - expected)?
+                .abs()?
+                .flatten_all()?
+                .max(0)?
+                .to_scalar::<f32>()?;
+            assert!(err < 1e-3, "({m}, {n}, {k}): {err}");
+        }
+        Ok(())
+    }
+}
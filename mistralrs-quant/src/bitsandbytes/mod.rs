@@ -5,15 +5,22 @@ use std::{
 };
 
 use candle_core::{Context, DType, Device, Result, Shape, Tensor};
+use candle_nn::Linear;
 use serde::Deserialize;
 
-use crate::{IsqType, QuantMethod, QuantMethodConfig, QuantizedSerde, ShardedVarBuilder};
+use crate::{
+    DummyLayer, IsqType, QuantMethod, QuantMethodConfig, QuantizedConfig, QuantizedSerde,
+    ShardedVarBuilder, UnquantLinear,
+};
 
 #[cfg(feature = "cuda")]
 mod ffi;
 
+mod int8;
 mod op;
 
+pub use int8::BnbInt8Linear;
+
 const SUPPORTED_BLOCKSIZE: [usize; 7] = [2048, 4096, 1024, 512, 256, 128, 64];
 
 #[derive(Debug, Deserialize, Clone, Copy)]
@@ -86,7 +93,7 @@ impl BnbLinear {
         if !vb_w.contains_tensor("quant_state.bitsandbytes__nf4")
             && !vb_w.contains_tensor("quant_state.bitsandbytes__fp4")
         {
-            candle_core::bail!("`BnbLinear` expects either `...__nf4` or `...__fp4` tensors, this means the layer is not 4bit.");
+            candle_core::bail!("`BnbLinear` expects either `...__nf4` or `...__fp4` tensors, this means the layer is not 4bit. 8-bit layers are loaded by `BnbInt8Linear`.");
         }
 
         let bias = if bias {
@@ -198,6 +205,55 @@ impl BnbLinear {
     }
 }
 
+/// Load a bitsandbytes layer: LLM.int8 if the weight has `SCB` row scales, NF4/FP4 otherwise.
+/// Layers matching `llm_int8_skip_modules` are loaded unquantized.
+pub fn bnb_linear_b(
+    in_dim: usize,
+    out_dim: usize,
+    bias: bool,
+    config: &QuantizedConfig,
+    vb: ShardedVarBuilder,
+) -> Result<Arc<dyn QuantMethod>> {
+    // Handle the case where the layer is dummy (no tensors)
+    if !vb.contains_tensor("weight") {
+        let layer = <DummyLayer as QuantMethod>::new(QuantMethodConfig::Dummy)?;
+        return Ok(Arc::new(layer) as Arc<dyn QuantMethod>);
+    }
+
+    let prefix = vb.prefix();
+    let skipped = config
+        .llm_int8_skip_modules
+        .as_ref()
+        .is_some_and(|modules| {
+            modules
+                .iter()
+                .any(|module| prefix.contains(module.as_str()))
+        });
+    if skipped {
+        let weight = vb.get((out_dim, in_dim), "weight")?;
+        let bias = if bias {
+            Some(vb.get((out_dim,), "bias")?)
+        } else {
+            None
+        };
+        let layer = <UnquantLinear as QuantMethod>::new(QuantMethodConfig::Unquantized(
+            Linear::new(weight, bias),
+        ))?;
+        return Ok(Arc::new(layer) as Arc<dyn QuantMethod>);
+    }
+
+    if vb.contains_tensor("SCB") {
+        let threshold = config
+            .llm_int8_threshold
+            .unwrap_or(int8::DEFAULT_LLM_INT8_THRESHOLD);
+        Ok(Arc::new(BnbInt8Linear::linear_b(
+            in_dim, out_dim, bias, threshold, vb,
+        )?))
+    } else {
+        Ok(Arc::new(BnbLinear::linear_b(in_dim, out_dim, bias, vb)?))
+    }
+}
+
 impl QuantMethod for BnbLinear {
     fn new(method: QuantMethodConfig) -> candle_core::Result<Self>
     where
@@ -208,8 +264,10 @@ impl QuantMethod for BnbLinear {
             | QuantMethodConfig::Gptq { .. }
             | QuantMethodConfig::Hqq { .. }
             | QuantMethodConfig::Dummy
+            | QuantMethodConfig::Awq { .. }
             | QuantMethodConfig::Unquantized(_)
             | QuantMethodConfig::FP8 { .. }
+            | QuantMethodConfig::BnbInt8 { .. }
             | QuantMethodConfig::BlockwiseFP8 { .. } => unreachable!(),
             QuantMethodConfig::Bnb {
                 weight,
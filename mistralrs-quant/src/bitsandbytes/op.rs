@@ -9,7 +9,11 @@ use candle_core::cuda::{
 };
 
 use candle_core::{
-    backend::BackendStorage, CpuStorage, CustomOp3, Result, Shape, Tensor, WithDType,
+    backend::BackendStorage, CpuStorage, CustomOp2, CustomOp3, Result, Shape, Tensor, WithDType,
+};
+use rayon::{
+    iter::{IndexedParallelIterator, ParallelIterator},
+    slice::ParallelSliceMut,
 };
 
 #[cfg(feature = "cuda")]
@@ -507,3 +511,218 @@ pub fn dequantize(
         },
     )
 }
+
+/// `a @ b^T` for int8 matrices stored as the bits of `U8` tensors, accumulating into `I32`.
+struct Int8MatMulOp;
+
+impl CustomOp2 for Int8MatMulOp {
+    fn name(&self) -> &'static str {
+        "int8-matmul-bnb"
+    }
+
+    fn cpu_fwd(
+        &self,
+        a_s: &CpuStorage,
+        a_l: &candle_core::Layout,
+        b_s: &CpuStorage,
+        b_l: &candle_core::Layout,
+    ) -> Result<(CpuStorage, Shape)> {
+        if !(a_l.is_contiguous() && b_l.is_contiguous()) {
+            candle_core::bail!("All inputs must be contiguous");
+        }
+        let (m, k) = a_l.shape().dims2()?;
+        let (n, b_k) = b_l.shape().dims2()?;
+        if k != b_k {
+            candle_core::bail!("Int8 matmul inner dimensions do not match: {k} and {b_k}");
+        }
+        let (CpuStorage::U8(a), CpuStorage::U8(b)) = (a_s, b_s) else {
+            candle_core::bail!("Int8 matmul expects u8 inputs holding int8 values");
+        };
+        let a = &a[a_l.start_offset()..a_l.start_offset() + m * k];
+        let b = &b[b_l.start_offset()..b_l.start_offset() + n * k];
+
+        let mut out = vec![0i32; m * n];
+        out.par_chunks_mut(n).enumerate().for_each(|(i, row)| {
+            let a_row = &a[i * k..(i + 1) * k];
+            for (j, out) in row.iter_mut().enumerate() {
+                let b_row = &b[j * k..(j + 1) * k];
+                *out = a_row
+                    .iter()
+                    .zip(b_row)
+                    .map(|(&x, &y)| x as i8 as i32 * y as i8 as i32)
+                    .sum();
+            }
+        });
+        Ok((CpuStorage::I32(out), Shape::from_dims(&[m, n])))
+    }
+
+    #[cfg(feature = "cuda")]
+    fn cuda_fwd(
+        &self,
+        a_s: &candle_core::CudaStorage,
+        a_l: &candle_core::Layout,
+        b_s: &candle_core::CudaStorage,
+        b_l: &candle_core::Layout,
+    ) -> Result<(candle_core::CudaStorage, Shape)> {
+        use candle_core::cuda::{cudarc::driver::DevicePtr, WrapErr};
+
+        if !(a_l.is_contiguous() && b_l.is_contiguous()) {
+            candle_core::bail!("All inputs must be contiguous");
+        }
+        let (m, k) = a_l.shape().dims2()?;
+        let (n, b_k) = b_l.shape().dims2()?;
+        if k != b_k {
+            candle_core::bail!("Int8 matmul inner dimensions do not match: {k} and {b_k}");
+        }
+        let a = a_s.as_cuda_slice::<u8>()?.slice(a_l.start_offset()..);
+        let b = b_s.as_cuda_slice::<u8>()?.slice(b_l.start_offset()..);
+        let dev = a_s.device().clone();
+
+        let out = unsafe { dev.alloc::<i32>(m * n).w()? };
+        unsafe {
+            ffi::int8_matmul_i32(
+                (*a.device_ptr()) as *const _,
+                (*b.device_ptr()) as *const _,
+                (*out.device_ptr()) as *mut _,
+                m as i32,
+                n as i32,
+                k as i32,
+                *dev.cu_stream(),
+            )
+        };
+
+        Ok((
+            candle_core::CudaStorage::wrap_cuda_slice(out, dev),
+            Shape::from_dims(&[m, n]),
+        ))
+    }
+}
+
+/// Multiply `a` of shape `(m, k)` by `b^T`, with `b` of shape `(n, k)`. Both hold int8 values in
+/// `U8` tensors; the result is the exact `I32` accumulation of shape `(m, n)`.
+pub fn int8_matmul(a: &Tensor, b: &Tensor) -> Result<Tensor> {
+    a.contiguous()?.apply_op2(&b.contiguous()?, Int8MatMulOp)
+}
+
+/// The product of the outlier features of `x` with the same features of the int8 `b`: the columns
+/// whose absmax is at least `threshold`.
+struct Int8OutlierMatMulOp {
+    threshold: f32,
+}
+
+impl CustomOp3 for Int8OutlierMatMulOp {
+    fn name(&self) -> &'static str {
+        "int8-outlier-matmul-bnb"
+    }
+
+    fn cpu_fwd(
+        &self,
+        x_s: &CpuStorage,
+        x_l: &candle_core::Layout,
+        b_s: &CpuStorage,
+        b_l: &candle_core::Layout,
+        absmax_s: &CpuStorage,
+        absmax_l: &candle_core::Layout,
+    ) -> Result<(CpuStorage, Shape)> {
+        if !(x_l.is_contiguous() && b_l.is_contiguous() && absmax_l.is_contiguous()) {
+            candle_core::bail!("All inputs must be contiguous");
+        }
+        let (m, k) = x_l.shape().dims2()?;
+        let (n, b_k) = b_l.shape().dims2()?;
+        if k != b_k || absmax_l.shape().dims1()? != k {
+            candle_core::bail!("Int8 outlier matmul inner dimensions do not match");
+        }
+        let (CpuStorage::F32(x), CpuStorage::U8(b), CpuStorage::F32(absmax)) = (x_s, b_s, absmax_s)
+        else {
+            candle_core::bail!("Int8 outlier matmul expects f32, u8 and f32 inputs");
+        };
+        let x = &x[x_l.start_offset()..x_l.start_offset() + m * k];
+        let b = &b[b_l.start_offset()..b_l.start_offset() + n * k];
+        let absmax = &absmax[absmax_l.start_offset()..absmax_l.start_offset() + k];
+
+        let outliers = (0..k)
+            .filter(|c| absmax[*c] >= self.threshold)
+            .collect::<Vec<_>>();
+        let mut out = vec![0f32; m * n];
+        out.par_chunks_mut(n).enumerate().for_each(|(i, row)| {
+            let x_row = &x[i * k..(i + 1) * k];
+            for (j, out) in row.iter_mut().enumerate() {
+                let b_row = &b[j * k..(j + 1) * k];
+                *out = outliers
+                    .iter()
+                    .map(|&c| x_row[c] * b_row[c] as i8 as f32)
+                    .sum();
+            }
+        });
+        Ok((CpuStorage::F32(out), Shape::from_dims(&[m, n])))
+    }
+
+    #[cfg(feature = "cuda")]
+    fn cuda_fwd(
+        &self,
+        x_s: &candle_core::CudaStorage,
+        x_l: &candle_core::Layout,
+        b_s: &candle_core::CudaStorage,
+        b_l: &candle_core::Layout,
+        absmax_s: &candle_core::CudaStorage,
+        absmax_l: &candle_core::Layout,
+    ) -> Result<(candle_core::CudaStorage, Shape)> {
+        use candle_core::cuda::{cudarc::driver::DevicePtr, WrapErr};
+
+        if !(x_l.is_contiguous() && b_l.is_contiguous() && absmax_l.is_contiguous()) {
+            candle_core::bail!("All inputs must be contiguous");
+        }
+        let (m, k) = x_l.shape().dims2()?;
+        let (n, b_k) = b_l.shape().dims2()?;
+        if k != b_k || absmax_l.shape().dims1()? != k {
+            candle_core::bail!("Int8 outlier matmul inner dimensions do not match");
+        }
+        let x = x_s.as_cuda_slice::<f32>()?.slice(x_l.start_offset()..);
+        let b = b_s.as_cuda_slice::<u8>()?.slice(b_l.start_offset()..);
+        let absmax = absmax_s
+            .as_cuda_slice::<f32>()?
+            .slice(absmax_l.start_offset()..);
+        let dev = x_s.device().clone();
+
+        // The outlier columns are found on the device, there is no copy to the host.
+        let idx = unsafe { dev.alloc::<i32>(k).w()? };
+        let count = unsafe { dev.alloc::<i32>(1).w()? };
+        let out = unsafe { dev.alloc::<f32>(m * n).w()? };
+        unsafe {
+            ffi::int8_outlier_matmul_f32(
+                (*x.device_ptr()) as *const _,
+                (*b.device_ptr()) as *const _,
+                (*absmax.device_ptr()) as *const _,
+                self.threshold,
+                (*idx.device_ptr()) as *mut _,
+                (*count.device_ptr()) as *mut _,
+                (*out.device_ptr()) as *mut _,
+                m as i32,
+                n as i32,
+                k as i32,
+                *dev.cu_stream(),
+            )
+        };
+
+        Ok((
+            candle_core::CudaStorage::wrap_cuda_slice(out, dev),
+            Shape::from_dims(&[m, n]),
+        ))
+    }
+}
+
+/// Multiply the outlier features of `x` (`(m, k)`, `F32`), those whose absmax `col_absmax`
+/// (`(k,)`) is at least `threshold`, by the same features of `b^T`, with `b` of shape `(n, k)`
+/// holding int8 values in a `U8` tensor. The result is `F32` of shape `(m, n)`.
+pub fn int8_outlier_matmul(
+    x: &Tensor,
+    b: &Tensor,
+    col_absmax: &Tensor,
+    threshold: f32,
+) -> Result<Tensor> {
+    x.contiguous()?.apply_op3(
+        &b.contiguous()?,
+        &col_absmax.contiguous()?,
+        Int8OutlierMatMulOp { threshold },
+    )
+}
@@ -14,8 +14,9 @@ mod ffi;
 use crate::{
     generate_isq, generate_isq_imatrix,
     hqq::{ISQ_HQQ_DEFAULT_OPT_STEPS, ISQ_HQQ_GROUP_SIZE},
-    DummyLayer, FP8Linear, GgufMatMul, HqqAxis, HqqBits, HqqConfig, HqqLayer, IsqType, QuantMethod,
-    QuantMethodConfig, QuantizedConfig, QuantizedSerde, Shard, ShardedVarBuilder, UnquantLinear,
+    AwqLayer, DummyLayer, FP8Linear, GgufMatMul, HqqAxis, HqqBits, HqqConfig, HqqLayer, IsqType,
+    QuantMethod, QuantMethodConfig, QuantizedConfig, QuantizedSerde, Shard, ShardedVarBuilder,
+    UnquantLinear,
 };
 
 #[derive(Debug)]
@@ -37,8 +38,10 @@ impl QuantMethod for BlockwiseFP8Linear {
             | QuantMethodConfig::Gptq { .. }
             | QuantMethodConfig::Hqq { .. }
             | QuantMethodConfig::Dummy
+            | QuantMethodConfig::Awq { .. }
             | QuantMethodConfig::Unquantized(_)
             | QuantMethodConfig::Bnb { .. }
+            | QuantMethodConfig::BnbInt8 { .. }
             | QuantMethodConfig::FP8 { .. } => unreachable!(),
             QuantMethodConfig::BlockwiseFP8 {
                 weight,
@@ -98,8 +101,7 @@ impl QuantMethod for BlockwiseFP8Linear {
             self.dequant_dtype,
         )?;
         match dtype {
-            /*Some(IsqType::HQQ1 | IsqType::HQQ2 | IsqType::HQQ3 | */
-            Some(IsqType::HQQ4 | IsqType::HQQ8) => {
+            Some(IsqType::HQQ1 | IsqType::HQQ2 | IsqType::HQQ3 | IsqType::HQQ4 | IsqType::HQQ8) => {
                 if imatrix_weight.is_some() {
                     // TODO just warn?
                     candle_core::bail!("HQQ does not support imatrix.");
@@ -109,9 +111,9 @@ impl QuantMethod for BlockwiseFP8Linear {
                 let bits = match dtype.unwrap() {
                     IsqType::HQQ8 => HqqBits::Eight,
                     IsqType::HQQ4 => HqqBits::Four,
-                    // IsqType::HQQ3 => HqqBits::Three,
-                    // IsqType::HQQ2 => HqqBits::Two,
-                    // IsqType::HQQ1 => HqqBits::One,
+                    IsqType::HQQ3 => HqqBits::Three,
+                    IsqType::HQQ2 => HqqBits::Two,
+                    IsqType::HQQ1 => HqqBits::One,
                     _ => unreachable!(),
                 };
                 let cfg = HqqConfig {
@@ -160,6 +162,25 @@ impl QuantMethod for BlockwiseFP8Linear {
                         .map(|b| b.to_dtype(DType::F32).unwrap().to_device(&device).unwrap()),
                 })?))
             }
+            Some(IsqType::GPTQ4) => {
+                candle_core::bail!(
+                    "GPTQ ISQ requires a Hessian, which blockwise FP8 layers do not collect."
+                )
+            }
+            Some(IsqType::AWQ4) => {
+                let Some(imatrix_weight) = imatrix_weight else {
+                    candle_core::bail!(
+                        "AWQ ISQ requires activations collected from a calibration file or an imatrix."
+                    );
+                };
+                n_quantized.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                Ok(Arc::new(AwqLayer::quantize(
+                    &weight,
+                    self.bias.clone(),
+                    &imatrix_weight,
+                    &device,
+                )?))
+            }
             Some(IsqType::F8E4M3) => {
                 if imatrix_weight.is_some() {
                     // TODO just warn?
@@ -195,7 +216,7 @@ impl QuantMethod for BlockwiseFP8Linear {
 
     fn get_max_isq_cpu_threads(&self, dtype: IsqType) -> Option<NonZeroUsize> {
         match dtype {
-            IsqType::F8E4M3 => None,
+            IsqType::F8E4M3 | IsqType::GPTQ4 | IsqType::AWQ4 => None,
             IsqType::Q2K
             | IsqType::Q3K
             | IsqType::Q4K
@@ -208,6 +229,9 @@ impl QuantMethod for BlockwiseFP8Linear {
             | IsqType::Q8K
             | IsqType::Q8_0
             | IsqType::Q8_1
+            | IsqType::HQQ1
+            | IsqType::HQQ2
+            | IsqType::HQQ3
             | IsqType::HQQ4
             | IsqType::HQQ8 => None,
         }
@@ -1,12 +1,12 @@
 use std::sync::Arc;
 
-use candle_core::{Context, Result, Tensor};
+use candle_core::{quantized::QTensor, Context, Result, Tensor};
 use candle_nn::Linear;
 
 use crate::{
-    blockwise_fp8::blockwise_fp8_linear_b, distributed, gptq::gptq_linear, BnbLinear, DummyLayer,
-    QuantMethod, QuantMethodConfig, QuantMethodType, QuantizedConfig, QuantizedSerde, Shard,
-    ShardedVarBuilder, UnquantLinear,
+    awq::awq_linear, blockwise_fp8::blockwise_fp8_linear_b, distributed, gptq::gptq_linear,
+    BnbLinear, DummyLayer, QuantMethod, QuantMethodConfig, QuantMethodType, QuantizedConfig,
+    QuantizedSerde, Shard, ShardedVarBuilder, UnquantLinear,
 };
 
 use super::Comm;
@@ -43,14 +43,14 @@ impl RowParallelLayer {
         let shard = shard(1, rank, world_size);
 
         let weight = if let Some(quant_conf) = &config {
-            // GPTQ and BNB do not support tensor parallelism
+            // GPTQ, AWQ and BNB do not support tensor parallelism
             if matches!(
                 quant_conf.quant_method,
-                QuantMethodType::Bitsandbytes | QuantMethodType::Gptq
+                QuantMethodType::Bitsandbytes | QuantMethodType::Gptq | QuantMethodType::Awq
             ) && comm.world_size() != 1
             {
                 candle_core::bail!(
-                    "GPTQ and BNB quantization types to not support tensor parallelism, but got a world size of {}",
+                    "GPTQ, AWQ and BNB quantization types to not support tensor parallelism, but got a world size of {}",
                     comm.world_size()
                 );
             }
@@ -60,6 +60,10 @@ impl RowParallelLayer {
                     let gpt_layer = gptq_linear(in_dim, out_dim, quant_conf, vb.clone())?;
                     return Ok(gpt_layer);
                 }
+                QuantMethodType::Awq => {
+                    let awq_layer = awq_linear(in_dim, out_dim, quant_conf, vb.clone())?;
+                    return Ok(awq_layer);
+                }
                 QuantMethodType::Bitsandbytes => {
                     let bnb_layer =
                         Arc::new(BnbLinear::linear_b(in_dim, out_dim, bias, vb.clone())?) as Arc<_>;
@@ -134,10 +138,16 @@ impl QuantMethod for RowParallelLayer {
         self.weight.dtype_and_device()
     }
 
-    fn begin_track_stats(&mut self) -> Result<()> {
+    fn begin_track_stats(&mut self, track_hessian: bool) -> Result<()> {
+        Arc::get_mut(&mut self.weight)
+            .context("Failed to get &mut to weight")?
+            .begin_track_stats(track_hessian)
+    }
+
+    fn quantize_tracked_hessian(&mut self) -> Result<()> {
         Arc::get_mut(&mut self.weight)
             .context("Failed to get &mut to weight")?
-            .begin_track_stats()
+            .quantize_tracked_hessian()
     }
 
     fn end_track_stats(&self) -> Result<Tensor> {
@@ -152,6 +162,12 @@ impl QuantMethod for RowParallelLayer {
         self.weight.unquant_weight_bias()
     }
 
+    fn gguf_weight_bias(&self) -> Option<(Arc<QTensor>, Option<Tensor>)> {
+        self.weight
+            .gguf_weight_bias()
+            .map(|(w, b)| (w, b.or_else(|| self.bias.clone())))
+    }
+
     fn get_max_isq_cpu_threads(&self, dtype: crate::IsqType) -> Option<std::num::NonZeroUsize> {
         self.weight.get_max_isq_cpu_threads(dtype)
     }
@@ -207,14 +223,14 @@ impl ColumnParallelLayer {
         vb: ShardedVarBuilder,
     ) -> Result<Arc<dyn QuantMethod>> {
         let weight = if let Some(quant_conf) = &config {
-            // GPTQ and BNB do not support tensor parallelism
+            // GPTQ, AWQ and BNB do not support tensor parallelism
             if matches!(
                 quant_conf.quant_method,
-                QuantMethodType::Bitsandbytes | QuantMethodType::Gptq
+                QuantMethodType::Bitsandbytes | QuantMethodType::Gptq | QuantMethodType::Awq
             ) && comm.world_size() != 1
             {
                 candle_core::bail!(
-                    "GPTQ and BNB quantization types to not support tensor parallelism, but got a world size of {}",
+                    "GPTQ, AWQ and BNB quantization types to not support tensor parallelism, but got a world size of {}",
                     comm.world_size()
                 );
             }
@@ -224,6 +240,10 @@ impl ColumnParallelLayer {
                     let gpt_layer = gptq_linear(in_dim, out_dim, quant_conf, vb.clone())?;
                     return Ok(gpt_layer);
                 }
+                QuantMethodType::Awq => {
+                    let awq_layer = awq_linear(in_dim, out_dim, quant_conf, vb.clone())?;
+                    return Ok(awq_layer);
+                }
                 QuantMethodType::Bitsandbytes => {
                     let bnb_layer =
                         Arc::new(BnbLinear::linear_b(in_dim, out_dim, bias, vb.clone())?) as Arc<_>;
@@ -308,10 +328,16 @@ impl QuantMethod for ColumnParallelLayer {
         self.weight.dtype_and_device()
     }
 
-    fn begin_track_stats(&mut self) -> Result<()> {
+    fn begin_track_stats(&mut self, track_hessian: bool) -> Result<()> {
+        Arc::get_mut(&mut self.weight)
+            .context("Failed to get &mut to weight")?
+            .begin_track_stats(track_hessian)
+    }
+
+    fn quantize_tracked_hessian(&mut self) -> Result<()> {
         Arc::get_mut(&mut self.weight)
             .context("Failed to get &mut to weight")?
-            .begin_track_stats()
+            .quantize_tracked_hessian()
     }
 
     fn end_track_stats(&self) -> Result<Tensor> {
@@ -326,6 +352,12 @@ impl QuantMethod for ColumnParallelLayer {
         self.weight.unquant_weight_bias()
     }
 
+    fn gguf_weight_bias(&self) -> Option<(Arc<QTensor>, Option<Tensor>)> {
+        self.weight
+            .gguf_weight_bias()
+            .map(|(w, b)| (w, b.or_else(|| self.bias.clone())))
+    }
+
     fn get_max_isq_cpu_threads(&self, dtype: crate::IsqType) -> Option<std::num::NonZeroUsize> {
         self.weight.get_max_isq_cpu_threads(dtype)
     }
@@ -386,6 +418,7 @@ impl ReplicatedLayer {
         let layer = if let Some(quant_conf) = &config {
             match quant_conf.quant_method {
                 QuantMethodType::Gptq => gptq_linear(in_dim, out_dim, quant_conf, vb.clone())?,
+                QuantMethodType::Awq => awq_linear(in_dim, out_dim, quant_conf, vb.clone())?,
                 QuantMethodType::Bitsandbytes => {
                     Arc::new(BnbLinear::linear_b(in_dim, out_dim, bias, vb.clone())?) as Arc<_>
                 }
@@ -447,10 +480,16 @@ impl QuantMethod for ReplicatedLayer {
         self.0.dtype_and_device()
     }
 
-    fn begin_track_stats(&mut self) -> Result<()> {
+    fn begin_track_stats(&mut self, track_hessian: bool) -> Result<()> {
+        Arc::get_mut(&mut self.0)
+            .context("Failed to get &mut to weight")?
+            .begin_track_stats(track_hessian)
+    }
+
+    fn quantize_tracked_hessian(&mut self) -> Result<()> {
         Arc::get_mut(&mut self.0)
             .context("Failed to get &mut to weight")?
-            .begin_track_stats()
+            .quantize_tracked_hessian()
     }
 
     fn end_track_stats(&self) -> Result<Tensor> {
@@ -465,6 +504,10 @@ impl QuantMethod for ReplicatedLayer {
         self.0.unquant_weight_bias()
     }
 
+    fn gguf_weight_bias(&self) -> Option<(Arc<QTensor>, Option<Tensor>)> {
+        self.0.gguf_weight_bias()
+    }
+
     fn get_max_isq_cpu_threads(&self, dtype: crate::IsqType) -> Option<std::num::NonZeroUsize> {
         self.0.get_max_isq_cpu_threads(dtype)
     }
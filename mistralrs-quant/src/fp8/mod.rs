@@ -18,7 +18,7 @@ use crate::{
         deserialize_tensor, read_dtype, serialize_tensor, version_is_compatible, write_dtype,
         UQFF_VERSION,
     },
-    IsqType, QuantMethod, QuantMethodConfig, QuantizedSerde, QuantizedSerdeType,
+    IsqType, QuantMethod, QuantMethodConfig, QuantizedSerde, QuantizedSerdeType, UnquantLinear,
 };
 
 #[derive(Debug)]
@@ -41,8 +41,10 @@ impl QuantMethod for FP8Linear {
             | QuantMethodConfig::Gptq { .. }
             | QuantMethodConfig::Hqq { .. }
             | QuantMethodConfig::Dummy
+            | QuantMethodConfig::Awq { .. }
             | QuantMethodConfig::Unquantized(_)
             | QuantMethodConfig::Bnb { .. }
+            | QuantMethodConfig::BnbInt8 { .. }
             | QuantMethodConfig::BlockwiseFP8 { .. } => unreachable!(),
             QuantMethodConfig::FP8 { lin, dtype } => {
                 let QuantizationResult {
@@ -149,17 +151,39 @@ impl QuantMethod for FP8Linear {
 
     fn apply_isq(
         self: Arc<Self>,
-        _dtype: Option<IsqType>,
-        _device: Device,
-        _n_quantized: &AtomicUsize,
-        _imatrix_weight: Option<Vec<f32>>,
+        dtype: Option<IsqType>,
+        device: Device,
+        n_quantized: &AtomicUsize,
+        imatrix_weight: Option<Vec<f32>>,
     ) -> Result<Arc<dyn QuantMethod>> {
-        todo!()
+        match dtype {
+            // Requantize from the dequantized weight.
+            Some(_) => {
+                let unquant = UnquantLinear::new(QuantMethodConfig::Unquantized(
+                    self.dequantize(DType::F32)?,
+                ))?;
+                Arc::new(unquant).apply_isq(dtype, device, n_quantized, imatrix_weight)
+            }
+            // Only move the layer to the device.
+            None => {
+                let bias = match self.lin.bias() {
+                    Some(b) => Some(b.to_device(&device)?),
+                    None => None,
+                };
+                Ok(Arc::new(Self {
+                    lin: Linear::new(self.lin.weight().to_device(&device)?, bias),
+                    dequant_w_scale: self.dequant_w_scale.to_device(&device)?,
+                    dequant_x_scale: self.dequant_x_scale.to_device(&device)?,
+                    quant_scale: self.quant_scale.to_device(&device)?,
+                    dtype: self.dtype,
+                }))
+            }
+        }
     }
 
     fn get_max_isq_cpu_threads(&self, dtype: IsqType) -> Option<NonZeroUsize> {
         match dtype {
-            IsqType::F8E4M3 => None,
+            IsqType::F8E4M3 | IsqType::GPTQ4 | IsqType::AWQ4 => None,
             IsqType::Q2K
             | IsqType::Q3K
             | IsqType::Q4K
@@ -172,6 +196,9 @@ impl QuantMethod for FP8Linear {
             | IsqType::Q8K
             | IsqType::Q8_0
             | IsqType::Q8_1
+            | IsqType::HQQ1
+            | IsqType::HQQ2
+            | IsqType::HQQ3
             | IsqType::HQQ4
             | IsqType::HQQ8 => None,
         }
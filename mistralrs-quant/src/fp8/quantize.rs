@@ -106,6 +106,33 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_apply_isq_without_type_keeps_weight() -> Result<()> {
+        use std::sync::{atomic::AtomicUsize, Arc};
+
+        use candle_nn::Linear;
+
+        use crate::{QuantMethod, QuantMethodConfig};
+
+        let dev = Device::Cpu;
+        let w = Tensor::rand(0f32, 1f32, (8, 32), &dev)?;
+        let layer = Arc::new(FP8Linear::new(QuantMethodConfig::FP8 {
+            lin: Linear::new(w, None),
+            dtype: DType::F8E4M3,
+        })?);
+        let expected = layer.dequantize_w()?;
+
+        let n_quantized = AtomicUsize::new(0);
+        let moved = layer.apply_isq(None, dev.clone(), &n_quantized, None)?;
+        let diff = (expected - moved.dequantize_w()?)?
+            .abs()?
+            .max_all()?
+            .to_scalar::<f32>()?;
+        assert_eq!(diff, 0.);
+        assert_eq!(n_quantized.load(std::sync::atomic::Ordering::Relaxed), 0);
+        Ok(())
+    }
+
     #[test]
     #[cfg(feature = "cuda")]
     fn test_cublaslt_matmul() -> Result<()> {
@@ -38,8 +38,10 @@ impl QuantMethod for GgufMatMul {
             | QuantMethodConfig::Unquantized(_)
             | QuantMethodConfig::Hqq { .. }
             | QuantMethodConfig::Dummy
+            | QuantMethodConfig::Awq { .. }
             | QuantMethodConfig::FP8 { .. }
             | QuantMethodConfig::Bnb { .. }
+            | QuantMethodConfig::BnbInt8 { .. }
             | QuantMethodConfig::BlockwiseFP8 { .. } => unreachable!(),
         }
     }
@@ -149,6 +151,13 @@ impl QuantMethod for GgufMatMul {
     fn get_max_isq_cpu_threads(&self, _dtype: IsqType) -> Option<NonZeroUsize> {
         None
     }
+
+    fn gguf_weight_bias(&self) -> Option<(Arc<QTensor>, Option<Tensor>)> {
+        match &self.w {
+            QMatMul::QTensor(qw) => Some((qw.clone(), self.b.clone())),
+            QMatMul::Tensor(_) | QMatMul::TensorF16(_) => None,
+        }
+    }
 }
 
 // Serialization structure:
@@ -1,15 +1,265 @@
 use crate::{
-    DummyLayer, IsqType, QuantMethod, QuantMethodConfig, QuantizedConfig, QuantizedSerde,
-    ShardedVarBuilder,
+    DummyLayer, GgufMatMul, IsqType, QuantMethod, QuantMethodConfig, QuantizedConfig,
+    QuantizedSerde, ShardedVarBuilder,
+};
+use candle_core::{
+    quantized::{ggml_file::qtensor_from_ggml, GgmlDType, QMatMul},
+    DType, Device, Result, Tensor, D,
+};
+use half::f16;
+use rayon::{
+    iter::{IndexedParallelIterator, ParallelIterator},
+    slice::ParallelSliceMut,
 };
-use candle_core::{DType, Device, Result, Tensor};
 use std::{
     num::NonZeroUsize,
     sync::{atomic::AtomicUsize, Arc},
 };
 
+/// Number of weights in a Q4_1 or Q8_0 block.
+const BLOCK_SIZE: usize = 32;
+/// Size in bytes of a Q4_1 block: the scale, the minimum and 16 bytes of packed values.
+const Q4_1_BLOCK_BYTES: usize = 2 + 2 + BLOCK_SIZE / 2;
+/// Size in bytes of a Q8_0 block: the scale and 32 signed values.
+const Q8_0_BLOCK_BYTES: usize = 2 + BLOCK_SIZE;
+
+/// GPTQ on the CPU and Metal.
+///
+/// There are no packed GPTQ kernels for these devices, so at load time the GPTQ values are
+/// written into GGML blocks which have CPU and Metal quantized matmul kernels. The input rows are
+/// first sorted by group (`g_idx`), and the activations are permuted to match in the forward
+/// pass, so that every block of 32 weights belongs to a single group. Each block then keeps the
+/// GPTQ scale and zero of its group:
+/// - 2, 3 and 4 bits: Q4_1 with the scale `s` and the minimum `-s * z`. 2 and 3 bit weights take
+///   as much memory as 4 bit ones.
+/// - 8 bits: Q8_0 with the scale `s` and the values `q - z`, if they all fit in an `i8`, as with
+///   symmetric checkpoints.
+///
+/// The weights are then the GPTQ weights, up to the rounding of the Q4_1 minimum to f16. If the
+/// groups are not made of whole blocks, or 8 bit values do not fit, the dequantized weight is
+/// kept unquantized instead.
 #[derive(Debug)]
-pub struct GptqLayer;
+pub struct GptqLayer {
+    inner: GgufMatMul,
+    /// Order of the input rows of `inner`, applied to the last dimension of the activations.
+    perm: Option<Tensor>,
+    /// Inverse of `perm`, to restore the input order of the weight.
+    inv_perm: Option<Tensor>,
+}
+
+/// Read the `bits` wide value at `idx` of a little endian bit stream packed into `i32`s.
+/// Values may span two words, as with 3 bit GPTQ.
+fn unpack(word: impl Fn(usize) -> i32, idx: usize, bits: usize) -> u32 {
+    let bit = idx * bits;
+    let (word_idx, shift) = (bit / 32, bit % 32);
+    let mut v = (word(word_idx) as u32) >> shift;
+    if shift + bits > 32 {
+        v |= (word(word_idx + 1) as u32) << (32 - shift);
+    }
+    v & ((1u32 << bits) - 1)
+}
+
+/// A GPTQ weight unpacked on the CPU. The weight is `(q - zero) * scale`, with the zero and
+/// scale of the group of each input row.
+struct UnpackedGptq {
+    out_dim: usize,
+    in_dim: usize,
+    /// `(out_dim, in_dim)`.
+    q: Vec<u8>,
+    /// `(n_groups, out_dim)`, with the GPTQ v1 offset of one added back.
+    zeros: Vec<u32>,
+    /// `(n_groups, out_dim)`.
+    scales: Vec<f32>,
+    /// `(in_dim,)`, the group of each input row.
+    g_idx: Vec<usize>,
+}
+
+impl UnpackedGptq {
+    /// - `q_weight`: `(in_dim * bits / 32, out_dim)`, packed along the input dimension.
+    /// - `qzeros`: `(n_groups, out_dim * bits / 32)`, packed along the output dimension.
+    /// - `scales`: `(n_groups, out_dim)`.
+    /// - `g_idx`: `(in_dim,)`, the group of each input row. Without it, rows are grouped in order.
+    fn new(
+        q_weight: &Tensor,
+        qzeros: &Tensor,
+        scales: &Tensor,
+        g_idx: Option<&Tensor>,
+        bits: usize,
+    ) -> Result<Self> {
+        if !matches!(bits, 2 | 3 | 4 | 8) {
+            candle_core::bail!("GPTQ bits must be one of 2, 3, 4, or 8, got {bits}.");
+        }
+        let (packed_rows, out_dim) = q_weight.dims2()?;
+        let in_dim = packed_rows * 32 / bits;
+        let (n_groups, _) = scales.dims2()?;
+
+        let q_weight = q_weight
+            .to_device(&Device::Cpu)?
+            .to_dtype(DType::I32)?
+            .flatten_all()?
+            .to_vec1::<i32>()?;
+        let qzeros = qzeros.to_device(&Device::Cpu)?.to_dtype(DType::I32)?;
+        let zeros_cols = qzeros.dim(1)?;
+        let qzeros = qzeros.flatten_all()?.to_vec1::<i32>()?;
+        let scales = scales
+            .to_device(&Device::Cpu)?
+            .to_dtype(DType::F32)?
+            .flatten_all()?
+            .to_vec1::<f32>()?;
+        let g_idx = match g_idx {
+            Some(g_idx) => g_idx
+                .to_device(&Device::Cpu)?
+                .to_dtype(DType::I32)?
+                .to_vec1::<i32>()?
+                .into_iter()
+                .map(|g| g as usize)
+                .collect::<Vec<_>>(),
+            None => {
+                let group_size = in_dim / n_groups;
+                (0..in_dim).map(|k| k / group_size).collect()
+            }
+        };
+        if g_idx.len() != in_dim {
+            candle_core::bail!(
+                "GPTQ `g_idx` has {} entries, expected {in_dim}.",
+                g_idx.len()
+            );
+        }
+        if let Some(g) = g_idx.iter().find(|g| **g >= n_groups) {
+            candle_core::bail!("GPTQ `g_idx` entry {g} is out of range for {n_groups} groups.");
+        }
+
+        let mut q = vec![0u8; out_dim * in_dim];
+        q.par_chunks_mut(in_dim).enumerate().for_each(|(n, row)| {
+            for (k, q) in row.iter_mut().enumerate() {
+                *q = unpack(|r| q_weight[r * out_dim + n], k, bits) as u8;
+            }
+        });
+        // Zeros are stored minus one (the GPTQ v1 format), as the CUDA kernels expect.
+        let zeros = (0..n_groups * out_dim)
+            .map(|i| unpack(|c| qzeros[i / out_dim * zeros_cols + c], i % out_dim, bits) + 1)
+            .collect();
+        Ok(Self {
+            out_dim,
+            in_dim,
+            q,
+            zeros,
+            scales,
+            g_idx,
+        })
+    }
+
+    /// The f32 weight of shape `(out_dim, in_dim)`.
+    fn dequantize(&self) -> Result<Tensor> {
+        let Self {
+            out_dim,
+            in_dim,
+            q,
+            zeros,
+            scales,
+            g_idx,
+        } = self;
+        let mut w = vec![0f32; out_dim * in_dim];
+        w.par_chunks_mut(*in_dim).enumerate().for_each(|(n, row)| {
+            for (k, w) in row.iter_mut().enumerate() {
+                let i = g_idx[k] * out_dim + n;
+                *w = (q[n * in_dim + k] as f32 - zeros[i] as f32) * scales[i];
+            }
+        });
+        Tensor::from_vec(w, (*out_dim, *in_dim), &Device::Cpu)
+    }
+
+    /// Input rows sorted by group, or `None` if the groups are not made of whole blocks.
+    fn block_order(&self) -> Option<Vec<usize>> {
+        if self.in_dim % BLOCK_SIZE != 0 {
+            return None;
+        }
+        let mut order = (0..self.in_dim).collect::<Vec<_>>();
+        order.sort_by_key(|k| self.g_idx[*k]);
+        order
+            .chunks_exact(BLOCK_SIZE)
+            .all(|block| block.iter().all(|k| self.g_idx[*k] == self.g_idx[block[0]]))
+            .then_some(order)
+    }
+
+    /// Write the weight as GGML blocks of `dtype` with the input rows in `order`, where every
+    /// block belongs to a single group. Returns `None` if a value cannot be represented.
+    fn to_blocks(&self, order: &[usize], dtype: GgmlDType) -> Option<Vec<u8>> {
+        let Self {
+            out_dim,
+            in_dim,
+            q,
+            zeros,
+            scales,
+            g_idx,
+        } = self;
+        let block_bytes = match dtype {
+            GgmlDType::Q4_1 => Q4_1_BLOCK_BYTES,
+            GgmlDType::Q8_0 => Q8_0_BLOCK_BYTES,
+            _ => unreachable!(),
+        };
+        let row_bytes = in_dim / BLOCK_SIZE * block_bytes;
+        let mut data = vec![0u8; out_dim * row_bytes];
+        let fits = data
+            .par_chunks_mut(row_bytes)
+            .enumerate()
+            .map(|(n, row)| {
+                for (block, out) in order
+                    .chunks_exact(BLOCK_SIZE)
+                    .zip(row.chunks_exact_mut(block_bytes))
+                {
+                    let i = g_idx[block[0]] * out_dim + n;
+                    let (zero, scale) = (zeros[i], scales[i]);
+                    let values = block.iter().map(|k| q[n * in_dim + k]);
+                    out[0..2].copy_from_slice(&f16::from_f32(scale).to_le_bytes());
+                    match dtype {
+                        GgmlDType::Q4_1 => {
+                            let min = f16::from_f32(-(zero as f32) * scale);
+                            out[2..4].copy_from_slice(&min.to_le_bytes());
+                            let values = values.collect::<Vec<_>>();
+                            for j in 0..BLOCK_SIZE / 2 {
+                                out[4 + j] = values[j] | (values[j + BLOCK_SIZE / 2] << 4);
+                            }
+                        }
+                        _ => {
+                            for (out, v) in out[2..].iter_mut().zip(values) {
+                                let Ok(v) = i8::try_from(v as i32 - zero as i32) else {
+                                    return false;
+                                };
+                                *out = v as u8;
+                            }
+                        }
+                    }
+                }
+                true
+            })
+            .reduce(|| true, |a, b| a && b);
+        fits.then_some(data)
+    }
+}
+
+impl GptqLayer {
+    /// The layer as a [`GgufMatMul`] over the inputs in their original order.
+    fn unpermuted(&self) -> Result<GgufMatMul> {
+        Ok(match &self.perm {
+            Some(_) => GgufMatMul {
+                w: QMatMul::Tensor(self.dequantize_w()?),
+                b: self.inner.b.clone(),
+            },
+            None => GgufMatMul {
+                w: self.inner.w.clone(),
+                b: self.inner.b.clone(),
+            },
+        })
+    }
+
+    fn permute_input(&self, a: &Tensor) -> Result<Tensor> {
+        match &self.perm {
+            Some(perm) => a.index_select(perm, D::Minus1),
+            None => Ok(a.clone()),
+        }
+    }
+}
 
 impl QuantMethod for GptqLayer {
     fn new(method: QuantMethodConfig) -> Result<Self>
@@ -17,13 +267,89 @@ impl QuantMethod for GptqLayer {
         Self: Sized,
     {
         match method {
-            QuantMethodConfig::Gptq { .. } => candle_core::bail!("GPTQ is only supported on CUDA."),
+            QuantMethodConfig::Gptq {
+                bits,
+                use_exllama: _,
+                q_weight,
+                gptq_qzeros,
+                gptq_scales,
+                g_idx,
+                bias,
+                workspace: _,
+                is_marlin,
+            } => {
+                if is_marlin {
+                    candle_core::bail!("Marlin GPTQ checkpoints are only supported on CUDA.");
+                }
+                let Some(qzeros) = gptq_qzeros else {
+                    candle_core::bail!("GPTQ on the CPU requires `qzeros`.");
+                };
+                let device = q_weight.device().clone();
+                let unpacked = UnpackedGptq::new(
+                    &q_weight,
+                    &qzeros,
+                    &gptq_scales,
+                    g_idx.as_ref(),
+                    bits as usize,
+                )?;
+
+                let ggml_dtype = if bits == 8 {
+                    GgmlDType::Q8_0
+                } else {
+                    GgmlDType::Q4_1
+                };
+                let blocks = unpacked.block_order().and_then(|order| {
+                    let data = unpacked.to_blocks(&order, ggml_dtype)?;
+                    Some((order, data))
+                });
+                let (w, perm, inv_perm) = match blocks {
+                    Some((order, data)) => {
+                        let w = qtensor_from_ggml(
+                            ggml_dtype,
+                            &data,
+                            vec![unpacked.out_dim, unpacked.in_dim],
+                            &device,
+                        )?;
+                        let (perm, inv_perm) = if order.iter().enumerate().all(|(i, k)| i == *k) {
+                            (None, None)
+                        } else {
+                            let mut inv_perm = vec![0u32; order.len()];
+                            for (i, k) in order.iter().enumerate() {
+                                inv_perm[*k] = i as u32;
+                            }
+                            let perm = order.into_iter().map(|k| k as u32).collect::<Vec<_>>();
+                            (
+                                Some(Tensor::new(perm, &device)?),
+                                Some(Tensor::new(inv_perm, &device)?),
+                            )
+                        };
+                        (QMatMul::QTensor(Arc::new(w)), perm, inv_perm)
+                    }
+                    // Not representable with these blocks, so keep the dequantized weight.
+                    None => (
+                        QMatMul::Tensor(unpacked.dequantize()?.to_device(&device)?),
+                        None,
+                        None,
+                    ),
+                };
+                let b = match bias {
+                    Some(b) => Some(b.to_dtype(DType::F32)?),
+                    None => None,
+                };
+                Ok(Self {
+                    inner: GgufMatMul { w, b },
+                    perm,
+                    inv_perm,
+                })
+            }
             QuantMethodConfig::Gguf { .. }
             | QuantMethodConfig::Unquantized(_)
             | QuantMethodConfig::Hqq { .. }
             | QuantMethodConfig::Dummy
+            | QuantMethodConfig::Awq { .. }
             | QuantMethodConfig::FP8 { .. }
             | QuantMethodConfig::Bnb { .. }
+            | QuantMethodConfig::BnbInt8 { .. }
             | QuantMethodConfig::BlockwiseFP8 { .. } => {
                 unreachable!()
             }
@@ -31,37 +357,45 @@ impl QuantMethod for GptqLayer {
     }
 
     fn dequantize_w(&self) -> Result<Tensor> {
-        todo!()
+        let w = self.inner.dequantize_w()?;
+        match &self.inv_perm {
+            Some(inv_perm) => w.index_select(inv_perm, 1),
+            None => Ok(w),
+        }
+    }
+
+    fn forward(&self, a: &Tensor) -> Result<Tensor> {
+        self.inner.forward(&self.permute_input(a)?)
     }
 
-    fn forward(&self, _a: &Tensor) -> Result<Tensor> {
-        todo!()
+    fn forward_via_half(&self, a: &Tensor) -> Result<Tensor> {
+        self.inner.forward_via_half(&self.permute_input(a)?)
     }
 
     fn quantized_act_type(&self) -> Option<DType> {
-        todo!()
+        self.inner.quantized_act_type()
     }
 
-    fn add_delta_w(&self, _delta: &Tensor) -> Result<Arc<dyn QuantMethod>> {
-        todo!()
+    fn add_delta_w(&self, delta: &Tensor) -> Result<Arc<dyn QuantMethod>> {
+        self.unpermuted()?.add_delta_w(delta)
     }
 
     fn dtype_and_device(&self) -> (DType, candle_core::Device) {
-        todo!()
+        self.inner.dtype_and_device()
     }
 
     fn apply_isq(
         self: Arc<Self>,
-        _dtype: Option<IsqType>,
-        _device: Device,
-        _n_quantized: &AtomicUsize,
-        _imatrix_weight: Option<Vec<f32>>,
+        dtype: Option<IsqType>,
+        device: Device,
+        n_quantized: &AtomicUsize,
+        imatrix_weight: Option<Vec<f32>>,
     ) -> Result<Arc<dyn QuantMethod>> {
-        todo!()
+        Arc::new(self.unpermuted()?).apply_isq(dtype, device, n_quantized, imatrix_weight)
     }
 
-    fn get_max_isq_cpu_threads(&self, _dtype: IsqType) -> Option<NonZeroUsize> {
-        todo!()
+    fn get_max_isq_cpu_threads(&self, dtype: IsqType) -> Option<NonZeroUsize> {
+        self.inner.get_max_isq_cpu_threads(dtype)
     }
 }
 
@@ -71,12 +405,6 @@ impl QuantizedSerde for GptqLayer {
     }
 }
 
-macro_rules! pack_factor {
-    ($bits:expr) => {
-        32 / $bits
-    };
-}
-
 pub fn gptq_linear(
     in_dim: usize,
     out_dim: usize,
@@ -86,7 +414,6 @@ pub fn gptq_linear(
     // Handle the case where the layer is dummy (no tensors)
     if !(vb.contains_tensor("qweight")
         && vb.contains_tensor("qzeros")
-        && vb.contains_tensor("g_idx")
         && vb.contains_tensor("scales"))
     {
         let layer = <DummyLayer as QuantMethod>::new(QuantMethodConfig::Dummy)?;
@@ -94,8 +421,16 @@ pub fn gptq_linear(
     }
 
     let bits = config.bits.expect("GPTQ requires bits in config");
+    if config
+        .checkpoint_format
+        .as_ref()
+        .is_some_and(|fmt| fmt == "marlin")
+    {
+        candle_core::bail!("Marlin GPTQ checkpoints are only supported on CUDA.");
+    }
+    // Values are packed as a bit stream, so 3 bit weights do not divide evenly into words.
     let qweight = vb.get_with_hints_dtype(
-        (in_dim / pack_factor!(bits), out_dim),
+        (in_dim * bits / 32, out_dim),
         "qweight",
         Default::default(),
         DType::I32,
@@ -105,12 +440,17 @@ pub fn gptq_linear(
             .group_size
             .expect("GPTQ requires group size in config");
     let qzeros = vb.get_with_hints_dtype(
-        (scale_and_zero_size, out_dim / pack_factor!(bits)),
+        (scale_and_zero_size, out_dim * bits / 32),
         "qzeros",
         Default::default(),
         DType::I32,
     )?;
-    let g_idx = vb.get_with_hints_dtype((in_dim,), "g_idx", Default::default(), DType::I32)?;
+    // Act-order checkpoints permute the groups with `g_idx`. Without it, rows are grouped in order.
+    let g_idx = if vb.contains_tensor("g_idx") {
+        Some(vb.get_with_hints_dtype((in_dim,), "g_idx", Default::default(), DType::I32)?)
+    } else {
+        None
+    };
     let scales = vb.get_with_hints_dtype(
         (scale_and_zero_size, out_dim),
         "scales",
@@ -129,10 +469,214 @@ pub fn gptq_linear(
         q_weight: qweight,
         gptq_qzeros: Some(qzeros),
         gptq_scales: scales,
-        g_idx: Some(g_idx),
+        g_idx,
         bias,
         workspace: None,
         is_marlin: false,
     };
     Ok(Arc::new(GptqLayer::new(config)?))
 }
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{quantized::QMatMul, DType, Device, Result, Tensor};
+
+    use super::{GptqLayer, UnpackedGptq};
+    use crate::{QuantMethod, QuantMethodConfig};
+
+    /// Pack `values` (each `bits` wide) into a little endian stream of `i32`s.
+    fn pack(values: &[u32], bits: usize) -> Vec<i32> {
+        let mut words = vec![0u32; (values.len() * bits).div_ceil(32)];
+        for (i, v) in values.iter().enumerate() {
+            let bit = i * bits;
+            words[bit / 32] |= v << (bit % 32);
+            if bit % 32 + bits > 32 {
+                words[bit / 32 + 1] |= v >> (32 - bit % 32);
+            }
+        }
+        words.into_iter().map(|w| w as i32).collect()
+    }
+
+    /// A GPTQ checkpoint with act-order groups: rows alternate between the groups.
+    struct Checkpoint {
+        bits: usize,
+        in_dim: usize,
+        out_dim: usize,
+        n_groups: usize,
+        /// `(in_dim, out_dim)`.
+        q: Vec<u32>,
+        /// `(n_groups, out_dim)`, as stored (minus one).
+        z: Vec<u32>,
+        /// `(n_groups, out_dim)`, representable in f16.
+        s: Vec<f32>,
+        g_idx: Vec<i32>,
+    }
+
+    impl Checkpoint {
+        /// `symmetric` stores the zero of the middle of the range, as symmetric checkpoints do.
+        fn new(
+            bits: usize,
+            in_dim: usize,
+            out_dim: usize,
+            n_groups: usize,
+            symmetric: bool,
+        ) -> Self {
+            let max = (1u32 << bits) - 1;
+            let q = (0..in_dim * out_dim)
+                .map(|i| (i as u32 * 7) % (max + 1))
+                .collect::<Vec<_>>();
+            let z = (0..n_groups * out_dim)
+                .map(|i| {
+                    if symmetric {
+                        (max + 1) / 2 - 1
+                    } else {
+                        (i as u32 * 3) % max
+                    }
+                })
+                .collect::<Vec<_>>();
+            let s = (0..n_groups * out_dim)
+                .map(|i| half::f16::from_f32(0.01 * (i % 13 + 1) as f32).to_f32())
+                .collect::<Vec<_>>();
+            let g_idx = (0..in_dim)
+                .map(|k| (k % n_groups) as i32)
+                .collect::<Vec<_>>();
+            Self {
+                bits,
+                in_dim,
+                out_dim,
+                n_groups,
+                q,
+                z,
+                s,
+                g_idx,
+            }
+        }
+
+        /// `(q_weight, qzeros, scales, g_idx)` in the checkpoint layout.
+        fn tensors(&self, dev: &Device) -> Result<(Tensor, Tensor, Tensor, Tensor)> {
+            let Self {
+                bits,
+                in_dim,
+                out_dim,
+                n_groups,
+                ..
+            } = *self;
+            // `q` is packed along the input dimension for each column.
+            let mut q_weight = vec![0i32; in_dim * bits / 32 * out_dim];
+            for n in 0..out_dim {
+                let column = (0..in_dim)
+                    .map(|k| self.q[k * out_dim + n])
+                    .collect::<Vec<_>>();
+                for (r, word) in pack(&column, bits).into_iter().enumerate() {
+                    q_weight[r * out_dim + n] = word;
+                }
+            }
+            let qzeros = (0..n_groups)
+                .flat_map(|g| pack(&self.z[g * out_dim..(g + 1) * out_dim], bits))
+                .collect::<Vec<_>>();
+            Ok((
+                Tensor::from_vec(q_weight, (in_dim * bits / 32, out_dim), dev)?,
+                Tensor::from_vec(qzeros, (n_groups, out_dim * bits / 32), dev)?,
+                Tensor::from_vec(self.s.clone(), (n_groups, out_dim), dev)?.to_dtype(DType::F16)?,
+                Tensor::from_vec(self.g_idx.clone(), (in_dim,), dev)?,
+            ))
+        }
+
+        /// The GPTQ dequantization of weight `(n, k)`.
+        fn weight(&self, n: usize, k: usize) -> f32 {
+            let i = self.g_idx[k] as usize * self.out_dim + n;
+            (self.q[k * self.out_dim + n] as f32 - (self.z[i] + 1) as f32) * self.s[i]
+        }
+
+        fn layer(&self, dev: &Device) -> Result<GptqLayer> {
+            let (q_weight, qzeros, scales, g_idx) = self.tensors(dev)?;
+            GptqLayer::new(QuantMethodConfig::Gptq {
+                bits: self.bits as i32,
+                use_exllama: false,
+                q_weight,
+                gptq_qzeros: Some(qzeros),
+                gptq_scales: scales,
+                g_idx: Some(g_idx),
+                bias: None,
+                workspace: None,
+                is_marlin: false,
+            })
+        }
+    }
+
+    #[test]
+    fn test_dequantize_gptq_act_order() -> Result<()> {
+        let dev = Device::Cpu;
+        for bits in [2, 3, 4, 8] {
+            let ckpt = Checkpoint::new(bits, 32, 32, 2, false);
+            let (q_weight, qzeros, scales, g_idx) = ckpt.tensors(&dev)?;
+            let w = UnpackedGptq::new(&q_weight, &qzeros, &scales, Some(&g_idx), bits)?
+                .dequantize()?
+                .to_vec2::<f32>()?;
+
+            for n in 0..ckpt.out_dim {
+                for k in 0..ckpt.in_dim {
+                    let expected = ckpt.weight(n, k);
+                    assert!(
+                        (w[n][k] - expected).abs() < 1e-4,
+                        "bits {bits}, ({n}, {k}): {} != {expected}",
+                        w[n][k]
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_gptq_layer_matches_gptq_dequantization() -> Result<()> {
+        let dev = Device::Cpu;
+        // Act-order groups of 32 rows, so that without sorting every block would mix all groups.
+        let (in_dim, out_dim, n_groups) = (128, 64, 4);
+        for (bits, symmetric) in [(2, false), (3, false), (4, false), (8, true), (8, false)] {
+            let ckpt = Checkpoint::new(bits, in_dim, out_dim, n_groups, symmetric);
+            let layer = ckpt.layer(&dev)?;
+            // Asymmetric 8 bit values do not fit in Q8_0, so they are kept dequantized.
+            assert_eq!(
+                matches!(layer.inner.w, QMatMul::QTensor(_)),
+                bits != 8 || symmetric,
+                "bits {bits}"
+            );
+
+            // The only errors are the rounding of the Q4_1 minimum `-s * z` to f16, and of the
+            // weight itself as `dequantize_w` goes through f16.
+            let w = layer.dequantize_w()?.to_vec2::<f32>()?;
+            for n in 0..out_dim {
+                for k in 0..in_dim {
+                    let i = ckpt.g_idx[k] as usize * out_dim + n;
+                    let expected = ckpt.weight(n, k);
+                    let bound =
+                        (ckpt.s[i] * (ckpt.z[i] + 1) as f32 + expected.abs()) / 1024. + 1e-6;
+                    assert!(
+                        (w[n][k] - expected).abs() <= bound,
+                        "bits {bits}, ({n}, {k}): {} != {expected}",
+                        w[n][k]
+                    );
+                }
+            }
+
+            // The forward pass permutes the activations to the sorted rows. The quantized matmul
+            // also quantizes the activations, hence the relative bound.
+            let expected = (0..out_dim * in_dim)
+                .map(|i| ckpt.weight(i / in_dim, i % in_dim))
+                .collect::<Vec<_>>();
+            let expected = Tensor::from_vec(expected, (out_dim, in_dim), &dev)?;
+            let x = Tensor::randn(0f32, 1., (3, in_dim), &dev)?;
+            let y_ref = x.matmul(&expected.t()?)?;
+            let y = layer.forward(&x)?;
+            let err = (y - &y_ref)?.sqr()?.sum_all()?.sqrt()?.to_scalar::<f32>()?;
+            let norm = y_ref.sqr()?.sum_all()?.sqrt()?.to_scalar::<f32>()?;
+            assert!(
+                err / norm < 1e-2,
+                "bits {bits}: relative error {}",
+                err / norm
+            );
+        }
+        Ok(())
+    }
+}
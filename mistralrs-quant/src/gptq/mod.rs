@@ -8,8 +8,10 @@ mod gptq_cuda;
 mod marlin_backend;
 #[cfg(feature = "cuda")]
 mod marlin_ffi;
+mod quantize;
 
 #[cfg(not(feature = "cuda"))]
 pub use gptq_cpu::{gptq_linear, GptqLayer};
 #[cfg(feature = "cuda")]
 pub use gptq_cuda::{gptq_linear, GptqLayer};
+pub(crate) use quantize::gptq_quantize;
@@ -0,0 +1,302 @@
+use candle_core::{
+    quantized::{ggml_file::qtensor_from_ggml, GgmlDType, QTensor},
+    DType, Device, Result, Tensor,
+};
+use half::f16;
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+use rayon::slice::ParallelSliceMut;
+
+/// GPTQ ISQ writes Q4_1 blocks: 32 weights sharing an f16 scale and minimum. This is exactly
+/// asymmetric 4 bit GPTQ with a group size of 32.
+const GROUP_SIZE: usize = 32;
+/// Size in bytes of a Q4_1 block: the scale, the minimum and 16 bytes of packed values.
+const Q4_1_BLOCK_BYTES: usize = 2 + 2 + GROUP_SIZE / 2;
+/// Number of columns quantized between the lazy batch updates of the remaining weight.
+const GPTQ_BLOCK_SIZE: usize = 128;
+/// Dampening added to the diagonal of the Hessian, relative to its mean.
+const GPTQ_PERCDAMP: f64 = 0.01;
+/// Size below which the Cholesky factorization and triangular inverse are computed on the CPU.
+const LINALG_BASE_SIZE: usize = 64;
+
+fn to_f64_vec(t: &Tensor) -> Result<Vec<f64>> {
+    t.to_device(&Device::Cpu)?
+        .to_dtype(DType::F64)?
+        .flatten_all()?
+        .to_vec1::<f64>()
+}
+
+fn from_f64_vec(data: Vec<f64>, n: usize, device: &Device) -> Result<Tensor> {
+    let data = data.into_iter().map(|x| x as f32).collect::<Vec<_>>();
+    Tensor::from_vec(data, (n, n), device)
+}
+
+fn cholesky_small(a: &[f64], n: usize) -> Result<Vec<f64>> {
+    let mut l = vec![0f64; n * n];
+    for j in 0..n {
+        let mut d = a[j * n + j];
+        for k in 0..j {
+            d -= l[j * n + k] * l[j * n + k];
+        }
+        if d <= 0. || !d.is_finite() {
+            candle_core::bail!(
+                "The calibration Hessian is not positive definite. Try a larger calibration file."
+            );
+        }
+        let d = d.sqrt();
+        l[j * n + j] = d;
+        for i in j + 1..n {
+            let mut x = a[i * n + j];
+            for k in 0..j {
+                x -= l[i * n + k] * l[j * n + k];
+            }
+            l[i * n + j] = x / d;
+        }
+    }
+    Ok(l)
+}
+
+fn invert_lower_small(l: &[f64], n: usize) -> Vec<f64> {
+    let mut inv = vec![0f64; n * n];
+    for j in 0..n {
+        inv[j * n + j] = 1. / l[j * n + j];
+        for i in j + 1..n {
+            let mut x = 0.;
+            for k in j..i {
+                x -= l[i * n + k] * inv[k * n + j];
+            }
+            inv[i * n + j] = x / l[i * n + i];
+        }
+    }
+    inv
+}
+
+fn block(a: &Tensor, row: usize, col: usize, rows: usize, cols: usize) -> Result<Tensor> {
+    a.narrow(0, row, rows)?.narrow(1, col, cols)?.contiguous()
+}
+
+fn assemble_lower(l11: Tensor, l21: Tensor, l22: Tensor) -> Result<Tensor> {
+    let (h, n) = (l11.dim(0)?, l11.dim(0)? + l22.dim(0)?);
+    let zeros = Tensor::zeros((h, n - h), l11.dtype(), l11.device())?;
+    let top = Tensor::cat(&[l11, zeros], 1)?;
+    let bottom = Tensor::cat(&[l21, l22], 1)?;
+    Tensor::cat(&[top, bottom], 0)
+}
+
+/// Lower triangular `L` with `a = L L^T`. Recursive, so that most of the work is in matmuls.
+fn cholesky(a: &Tensor) -> Result<Tensor> {
+    let n = a.dim(0)?;
+    if n <= LINALG_BASE_SIZE {
+        return from_f64_vec(cholesky_small(&to_f64_vec(a)?, n)?, n, a.device());
+    }
+    let h = n / 2;
+    let l11 = cholesky(&block(a, 0, 0, h, h)?)?;
+    let l21 = block(a, h, 0, n - h, h)?.matmul(&invert_lower(&l11)?.t()?)?;
+    let a22 = (block(a, h, h, n - h, n - h)? - l21.matmul(&l21.t()?)?)?;
+    let l22 = cholesky(&a22)?;
+    assemble_lower(l11, l21, l22)
+}
+
+/// Inverse of the lower triangular `l`.
+fn invert_lower(l: &Tensor) -> Result<Tensor> {
+    let n = l.dim(0)?;
+    if n <= LINALG_BASE_SIZE {
+        return from_f64_vec(invert_lower_small(&to_f64_vec(l)?, n), n, l.device());
+    }
+    let h = n / 2;
+    let i11 = invert_lower(&block(l, 0, 0, h, h)?)?;
+    let i22 = invert_lower(&block(l, h, h, n - h, n - h)?)?;
+    let i21 = i22
+        .matmul(&block(l, h, 0, n - h, h)?.matmul(&i11)?)?
+        .neg()?;
+    assemble_lower(i11, i21, i22)
+}
+
+/// Reverse the order of the rows and columns.
+fn reverse(a: &Tensor) -> Result<Tensor> {
+    let n = a.dim(0)?;
+    let idx = Tensor::from_vec((0..n as u32).rev().collect::<Vec<_>>(), n, a.device())?;
+    a.index_select(&idx, 0)?.index_select(&idx, 1)
+}
+
+/// Upper triangular `U` with `U^T U = h^-1`.
+///
+/// With `P` the reversal permutation, the Cholesky factor `C` of `P h P` gives `h = V V^T` with
+/// the upper triangular `V = P C P`, so `U = V^-1 = P C^-1 P`.
+fn inverse_hessian_factor(h: &Tensor) -> Result<Tensor> {
+    let c = cholesky(&reverse(h)?)?;
+    reverse(&invert_lower(&c)?)
+}
+
+/// Quantize one row of a block of columns with GPTQ, group by group. `w` holds the weights of
+/// the row, which are updated in place to compensate for the error of the previous columns, and
+/// the error of each column is written to `err` for the update of the following blocks.
+fn quantize_block_row(w: &mut [f32], u: &[f32], err: &mut [f32], out: &mut [u8]) {
+    let bs = w.len();
+    for (g, out) in out.chunks_exact_mut(Q4_1_BLOCK_BYTES).enumerate() {
+        let start = g * GROUP_SIZE;
+        let group = &w[start..start + GROUP_SIZE];
+        let min = group.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = group.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let d = f16::from_f32((max - min) / 15.);
+        let m = f16::from_f32(min);
+        let (df, mf) = (d.to_f32(), m.to_f32());
+        let inv_d = if df != 0. { 1. / df } else { 0. };
+
+        let mut q = [0u8; GROUP_SIZE];
+        for (k, q) in q.iter_mut().enumerate() {
+            let i = start + k;
+            let qi = ((w[i] - mf) * inv_d).round().clamp(0., 15.);
+            *q = qi as u8;
+            let e = (w[i] - (qi * df + mf)) / u[i * bs + i];
+            err[i] = e;
+            for j in i + 1..bs {
+                w[j] -= e * u[i * bs + j];
+            }
+        }
+
+        out[0..2].copy_from_slice(&d.to_le_bytes());
+        out[2..4].copy_from_slice(&m.to_le_bytes());
+        for j in 0..GROUP_SIZE / 2 {
+            out[4 + j] = q[j] | (q[j + GROUP_SIZE / 2] << 4);
+        }
+    }
+}
+
+/// Quantize the weight `w` of shape `(out_dim, in_dim)` with GPTQ, given the Hessian
+/// `2 X^T X / n` of the layer inputs `X`. The result is a Q4_1 tensor on `device`.
+///
+/// The matmuls of the algorithm run on `device`, the quantization of each block runs on the CPU.
+///
+/// <https://arxiv.org/abs/2210.17323>
+pub(crate) fn gptq_quantize(w: &Tensor, hessian: &Tensor, device: &Device) -> Result<QTensor> {
+    let (out_dim, in_dim) = w.dims2()?;
+    if in_dim % GROUP_SIZE != 0 {
+        candle_core::bail!(
+            "GPTQ ISQ requires the input dimension to be divisible by {GROUP_SIZE}, got {in_dim}."
+        );
+    }
+    if hessian.dims2()? != (in_dim, in_dim) {
+        candle_core::bail!(
+            "Expected a Hessian of shape ({in_dim}, {in_dim}), got {:?}.",
+            hessian.dims()
+        );
+    }
+
+    // Inputs which are always zero do not affect the output: make their Hessian entry 1 and
+    // their weight 0. Then dampen the diagonal for numerical stability.
+    let mut h = hessian
+        .to_device(&Device::Cpu)?
+        .to_dtype(DType::F32)?
+        .flatten_all()?
+        .to_vec1::<f32>()?;
+    let mut alive = vec![1f32; in_dim];
+    for (i, alive) in alive.iter_mut().enumerate() {
+        if h[i * in_dim + i] == 0. {
+            h[i * in_dim + i] = 1.;
+            *alive = 0.;
+        }
+    }
+    let damp =
+        GPTQ_PERCDAMP * (0..in_dim).map(|i| h[i * in_dim + i] as f64).sum::<f64>() / in_dim as f64;
+    for i in 0..in_dim {
+        h[i * in_dim + i] += damp as f32;
+    }
+    let h = Tensor::from_vec(h, (in_dim, in_dim), device)?;
+    let u = inverse_hessian_factor(&h)?;
+
+    let alive = Tensor::from_vec(alive, (1, in_dim), device)?;
+    let mut w_rest = w
+        .to_device(device)?
+        .to_dtype(DType::F32)?
+        .broadcast_mul(&alive)?;
+
+    let row_bytes = in_dim / GROUP_SIZE * Q4_1_BLOCK_BYTES;
+    let mut data = vec![0u8; out_dim * row_bytes];
+    for i1 in (0..in_dim).step_by(GPTQ_BLOCK_SIZE) {
+        let bs = GPTQ_BLOCK_SIZE.min(in_dim - i1);
+        let w1 = w_rest
+            .narrow(1, 0, bs)?
+            .to_device(&Device::Cpu)?
+            .to_vec2::<f32>()?;
+        let u1 = block(&u, i1, i1, bs, bs)?
+            .to_device(&Device::Cpu)?
+            .flatten_all()?
+            .to_vec1::<f32>()?;
+
+        // Rows are independent of each other given the inverse Hessian factor.
+        let mut err = vec![0f32; out_dim * bs];
+        let first_block = i1 / GROUP_SIZE * Q4_1_BLOCK_BYTES;
+        data.par_chunks_mut(row_bytes)
+            .zip(w1.into_par_iter())
+            .zip(err.par_chunks_mut(bs))
+            .for_each(|((out, mut w), err)| {
+                let out = &mut out[first_block..first_block + bs / GROUP_SIZE * Q4_1_BLOCK_BYTES];
+                quantize_block_row(&mut w, &u1, err, out);
+            });
+
+        // Lazy batch update of the remaining columns with the error of this block.
+        let rest = in_dim - i1 - bs;
+        if rest > 0 {
+            let err = Tensor::from_vec(err, (out_dim, bs), device)?;
+            let u12 = block(&u, i1, i1 + bs, bs, rest)?;
+            w_rest = (w_rest.narrow(1, bs, rest)? - err.matmul(&u12)?)?;
+        }
+    }
+
+    qtensor_from_ggml(GgmlDType::Q4_1, &data, vec![out_dim, in_dim], device)
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{quantized::GgmlDType, DType, Device, Result, Tensor, D};
+
+    use super::{gptq_quantize, inverse_hessian_factor};
+
+    #[test]
+    fn test_inverse_hessian_factor() -> Result<()> {
+        let dev = Device::Cpu;
+        let x = Tensor::randn(0f32, 1., (512, 160), &dev)?;
+        let h = (x.t()?.matmul(&x)? + Tensor::eye(160, DType::F32, &dev)?)?;
+        let u = inverse_hessian_factor(&h)?;
+
+        // U is upper triangular with U^T U = H^-1, so H U^T U = I.
+        for (i, row) in u.to_vec2::<f32>()?.iter().enumerate() {
+            assert!(row[..i].iter().all(|x| *x == 0.));
+        }
+        let id = h.matmul(&u.t()?.matmul(&u)?)?;
+        let err = (id - Tensor::eye(160, DType::F32, &dev)?)?
+            .abs()?
+            .max_keepdim(0)?
+            .max(D::Minus1)?
+            .to_vec1::<f32>()?[0];
+        assert!(err < 1e-3, "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_gptq_beats_round_to_nearest() -> Result<()> {
+        let dev = Device::Cpu;
+        // Correlated inputs, which is where GPTQ's error compensation helps.
+        let base = Tensor::randn(0f32, 1., (1024, 32), &dev)?;
+        let mix = Tensor::randn(0f32, 1., (32, 256), &dev)?;
+        let x = (base.matmul(&mix)? + Tensor::randn(0f32, 0.1, (1024, 256), &dev)?)?;
+        let w = Tensor::randn(0f32, 1., (64, 256), &dev)?;
+        let h = (x.t()?.matmul(&x)? * (2. / 1024.))?;
+
+        let gptq = gptq_quantize(&w, &h, &dev)?;
+        assert_eq!(gptq.dtype(), GgmlDType::Q4_1);
+        let rtn = candle_core::quantized::QTensor::quantize(&w, GgmlDType::Q4_1)?;
+
+        let y = x.matmul(&w.t()?)?;
+        let err = |q: &candle_core::quantized::QTensor| -> Result<f32> {
+            let w_q = q.dequantize(&dev)?;
+            (x.matmul(&w_q.t()?)? - &y)?
+                .sqr()?
+                .mean_all()?
+                .to_scalar::<f32>()
+        };
+        let (gptq_err, rtn_err) = (err(&gptq)?, err(&rtn)?);
+        assert!(gptq_err < rtn_err, "GPTQ {gptq_err} >= RTN {rtn_err}");
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use byteorder::{LittleEndian, ReadBytesExt};
 use candle_core::{DType, Device, Result, Shape, Tensor};
+use candle_nn::Linear;
 
 #[cfg(feature = "cuda")]
 use candle_core::{
@@ -22,6 +23,7 @@ use crate::{
         BitWiseOp, LeftshiftOp, UQFF_VERSION,
     },
     IsqType, MatMul, QuantMethod, QuantMethodConfig, QuantizedSerde, QuantizedSerdeType,
+    UnquantLinear,
 };
 
 #[cfg(feature = "cuda")]
@@ -263,6 +265,7 @@ impl HqqLayer {
             3 => self
                 .w_q
                 .apply_op3_no_bwd(&self.scales, &self.zeros, &Dequant3Bit { h, w })?
+                .narrow(self.cfg.axis as usize, 0, self.cfg.group_size.into())?
                 .reshape(&self.w_shape),
             2 => self
                 .w_q
@@ -527,8 +530,10 @@ impl QuantMethod for HqqLayer {
             | QuantMethodConfig::Unquantized(_)
             | QuantMethodConfig::Gptq { .. }
             | QuantMethodConfig::Dummy
+            | QuantMethodConfig::Awq { .. }
             | QuantMethodConfig::FP8 { .. }
             | QuantMethodConfig::Bnb { .. }
+            | QuantMethodConfig::BnbInt8 { .. }
             | QuantMethodConfig::BlockwiseFP8 { .. } => {
                 unreachable!()
             }
@@ -594,20 +599,29 @@ impl QuantMethod for HqqLayer {
         n_quantized: &AtomicUsize,
         imatrix_weight: Option<Vec<f32>>,
     ) -> Result<Arc<dyn QuantMethod>> {
+        let bits = match dtype {
+            Some(IsqType::HQQ8) => HqqBits::Eight,
+            Some(IsqType::HQQ4) => HqqBits::Four,
+            Some(IsqType::HQQ3) => HqqBits::Three,
+            Some(IsqType::HQQ2) => HqqBits::Two,
+            Some(IsqType::HQQ1) => HqqBits::One,
+            // Requantize from the dequantized weight.
+            Some(_) => {
+                let unquant = UnquantLinear::new(QuantMethodConfig::Unquantized(Linear::new(
+                    self.dequantize()?,
+                    self.bias.clone(),
+                )))?;
+                return Arc::new(unquant).apply_isq(dtype, device, n_quantized, imatrix_weight);
+            }
+            None => candle_core::bail!("Expected a HQQ ISQ type."),
+        };
+
         if imatrix_weight.is_some() {
             // TODO just warn?
             candle_core::bail!("HQQ does not support imatrix.");
         }
 
         n_quantized.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        let bits = match dtype {
-            Some(IsqType::HQQ8) => HqqBits::Eight,
-            Some(IsqType::HQQ4) => HqqBits::Four,
-            // Some(IsqType::HQQ3) => HqqBits::Three,
-            // Some(IsqType::HQQ2) => HqqBits::Two,
-            // Some(IsqType::HQQ1) => HqqBits::One,
-            _ => candle_core::bail!("Expected a HQQ ISQ type."),
-        };
         let cfg = HqqConfig {
             bits,
             group_size: ISQ_HQQ_GROUP_SIZE.try_into()?,
@@ -825,9 +839,9 @@ impl HqqLayer {
         match bits {
             HqqBits::Eight => Ok(IsqType::HQQ8),
             HqqBits::Four => Ok(IsqType::HQQ4),
-            HqqBits::One | HqqBits::Two | HqqBits::Three => {
-                candle_core::bail!("cannot convert hqq bits to isq type")
-            }
+            HqqBits::Three => Ok(IsqType::HQQ3),
+            HqqBits::Two => Ok(IsqType::HQQ2),
+            HqqBits::One => Ok(IsqType::HQQ1),
         }
     }
 }
@@ -121,4 +121,63 @@ mod test {
         // dbg!(&(&dequant - &data)?.abs()?.mean_all()?);
         Ok(())
     }
+
+    #[test]
+    fn test_quantize_dequantize_hqq_low_bits_cpu() -> Result<()> {
+        use candle_core::DType;
+
+        use crate::{HqqAxis, HqqBits, HqqConfig, HqqLayer};
+
+        let dev = Device::Cpu;
+        // With axis 0 and a group size of 64, each column is a group.
+        let (group_size, n_groups) = (64, 32);
+        let data = Tensor::rand(0f32, 1f32, (group_size, n_groups), &dev)?;
+        let min = data.min_keepdim(0)?.to_vec2::<f32>()?.remove(0);
+        let max = data.max_keepdim(0)?.to_vec2::<f32>()?.remove(0);
+        let values = data.to_vec2::<f32>()?;
+        for (bits, packed_rows, packed_dtype) in [
+            (HqqBits::One, group_size / 8, DType::U8),
+            (HqqBits::Two, group_size / 4, DType::U8),
+            // 10 values per i32, so the 64 rows are padded to 70 and narrowed back.
+            (HqqBits::Three, group_size.div_ceil(10), DType::I32),
+            (HqqBits::Four, group_size / 2, DType::U8),
+            (HqqBits::Eight, group_size, DType::U8),
+        ] {
+            let hqq = HqqLayer::quantize(
+                &data,
+                &dev,
+                HqqConfig {
+                    bits,
+                    group_size: group_size.try_into()?,
+                    axis: HqqAxis::Zero,
+                    // Without the optimization, the grid of each group spans its min and max.
+                    optimization_steps: Some(0),
+                    round_zeros: false,
+                    channel_wise: true,
+                },
+            )?;
+            assert_eq!(hqq.w_q.dims(), &[packed_rows, n_groups], "{bits:?}");
+            assert_eq!(hqq.w_q.dtype(), packed_dtype, "{bits:?}");
+            assert_eq!(hqq.scales.dims(), &[1, n_groups], "{bits:?}");
+            assert_eq!(hqq.zeros.dims(), &[1, n_groups], "{bits:?}");
+
+            let dequant = hqq.dequantize()?;
+            assert_eq!(dequant.dims(), data.dims());
+
+            // Each value is rounded to the nearest point of its group's grid.
+            let dequant = dequant.to_vec2::<f32>()?;
+            for g in 0..n_groups {
+                let step = (max[g] - min[g]) / (2f32.powi(bits as i32) - 1.);
+                for k in 0..group_size {
+                    let err = (dequant[k][g] - values[k][g]).abs();
+                    assert!(
+                        err <= step / 2. + 1e-5,
+                        "{bits:?}, ({k}, {g}): error {err} > half a step {}",
+                        step / 2.
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
 }
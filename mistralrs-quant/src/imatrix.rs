@@ -3,18 +3,65 @@ use std::{
     fs,
     io::Cursor,
     path::Path,
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock, Weak},
 };
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use candle_core::{Context, DType, Device, Result, Tensor, D};
+use candle_core::{Context, DType, Device, Result, Tensor, TensorId, D};
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
+/// `X^T X` of one input, accumulated on the device of the layers it feeds. Layers which are fed the
+/// same input tensor, such as the q, k and v projections, share one accumulator.
+#[derive(Debug)]
+struct HessianAccum {
+    n_rows: usize,
+    accum: Tensor,
+    /// The input added last. A layer fed the same tensor as a sibling does not add it again.
+    last_input: Option<TensorId>,
+}
+
+type SharedHessian = Arc<Mutex<HessianAccum>>;
+
+/// Accumulators by the first input they were created for, so that the other layers fed that input
+/// find them. The entries are weak and are dropped with the layers.
+static HESSIANS: Lazy<Mutex<HashMap<TensorId, Weak<Mutex<HessianAccum>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug)]
+enum HessianStats {
+    /// No input was seen yet.
+    Unbound {
+        in_dim: usize,
+        device: Device,
+    },
+    Bound(SharedHessian),
+}
+
+impl HessianStats {
+    /// The accumulator for `inp`: the one of a layer which was fed the same tensor, or a new one.
+    fn bind(in_dim: usize, device: &Device, inp: TensorId) -> Result<SharedHessian> {
+        let mut hessians = HESSIANS.lock().unwrap();
+        if let Some(shared) = hessians.get(&inp).and_then(Weak::upgrade) {
+            return Ok(shared);
+        }
+        hessians.retain(|_, shared| shared.strong_count() > 0);
+        let shared = Arc::new(Mutex::new(HessianAccum {
+            n_rows: 0,
+            accum: Tensor::zeros((in_dim, in_dim), DType::F32, device)?,
+            last_input: None,
+        }));
+        hessians.insert(inp, Arc::downgrade(&shared));
+        Ok(shared)
+    }
+}
+
 #[derive(Debug)]
 struct ImatrixLayerStats_ {
     row_counts: usize,
     ncalls: usize,
     row_accum: Tensor,
+    hessian: Option<HessianStats>,
 }
 
 #[derive(Debug, Clone)]
@@ -26,6 +73,23 @@ impl ImatrixLayerStats {
             row_counts: 0,
             ncalls: 0,
             row_accum: Tensor::zeros((w.dim(1)?,), DType::F32, device)?,
+            hessian: None,
+        })))))
+    }
+
+    /// Like [`ImatrixLayerStats::new`], but also accumulate the full `(in_dim, in_dim)` Hessian of
+    /// the layer inputs on `device`, as needed by GPTQ. This is `in_dim * in_dim * 4` bytes, shared
+    /// between the layers which are fed the same input tensor.
+    pub fn new_with_hessian(w: &Tensor, device: &Device) -> Result<Self> {
+        let in_dim = w.dim(1)?;
+        Ok(Self(Arc::new(RwLock::new(Some(ImatrixLayerStats_ {
+            row_counts: 0,
+            ncalls: 0,
+            row_accum: Tensor::zeros((in_dim,), DType::F32, device)?,
+            hessian: Some(HessianStats::Unbound {
+                in_dim,
+                device: device.clone(),
+            }),
         })))))
     }
 
@@ -33,10 +97,28 @@ impl ImatrixLayerStats {
         let mut handle = self.0.write().unwrap();
         let this = handle.as_mut().context("Layer stats were dinitialized!")?;
 
+        let inp_id = inp.id();
         let inp = inp.reshape(((), inp.dim(D::Minus1)?))?;
         this.ncalls += 1;
         this.row_counts += inp.dim(D::Minus1)?;
-        this.row_accum = (&this.row_accum + inp.to_dtype(DType::F32)?.sqr()?.sum(0)?)?;
+        let inp = inp.to_dtype(DType::F32)?;
+        this.row_accum = (&this.row_accum + inp.sqr()?.sum(0)?)?;
+        if let Some(hessian) = &mut this.hessian {
+            let shared = match hessian {
+                HessianStats::Bound(shared) => shared.clone(),
+                HessianStats::Unbound { in_dim, device } => {
+                    let shared = HessianStats::bind(*in_dim, device, inp_id)?;
+                    *hessian = HessianStats::Bound(shared.clone());
+                    shared
+                }
+            };
+            let mut shared = shared.lock().unwrap();
+            if shared.last_input != Some(inp_id) {
+                shared.n_rows += inp.dim(0)?;
+                shared.accum = (&shared.accum + inp.t()?.matmul(&inp)?)?;
+                shared.last_input = Some(inp_id);
+            }
+        }
         Ok(())
     }
 
@@ -46,6 +128,30 @@ impl ImatrixLayerStats {
         (&this.row_accum / this.row_counts as f64)? * this.ncalls as f64
     }
 
+    /// The Hessian `2 X^T X / n` of the layer inputs, if it is tracked.
+    pub fn compute_hessian(&self) -> Result<Option<Tensor>> {
+        let handle = self.0.read().unwrap();
+        let this = handle.as_ref().context("Layer stats were dinitialized!")?;
+        match &this.hessian {
+            Some(HessianStats::Bound(shared)) => {
+                let shared = shared.lock().unwrap();
+                Ok(Some((&shared.accum * (2. / shared.n_rows as f64))?))
+            }
+            Some(HessianStats::Unbound { .. }) => {
+                candle_core::bail!("No inputs were seen while tracking the Hessian.")
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Stop tracking the Hessian and free it once no other layer shares it.
+    pub fn clear_hessian(&self) -> Result<()> {
+        let mut handle = self.0.write().unwrap();
+        let this = handle.as_mut().context("Layer stats were dinitialized!")?;
+        this.hessian = None;
+        Ok(())
+    }
+
     pub fn clear(&self) -> Result<()> {
         let mut handle = self.0.write().unwrap();
         *handle = None;
@@ -53,7 +159,7 @@ impl ImatrixLayerStats {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CollectedImatrixData(pub HashMap<usize, Option<Vec<f32>>>);
 
 impl CollectedImatrixData {
@@ -116,3 +222,62 @@ impl CollectedImatrixData {
         Ok(Self(entries))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{DType, Device, Result, Tensor};
+
+    use super::ImatrixLayerStats;
+
+    fn max_diff(a: &Tensor, b: &Tensor) -> Result<f32> {
+        (a - b)?.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()
+    }
+
+    #[test]
+    fn layers_fed_the_same_input_share_the_hessian() -> Result<()> {
+        let dev = Device::Cpu;
+        let w = Tensor::zeros((8, 16), DType::F32, &dev)?;
+        let q = ImatrixLayerStats::new_with_hessian(&w, &dev)?;
+        let k = ImatrixLayerStats::new_with_hessian(&w, &dev)?;
+        let o = ImatrixLayerStats::new_with_hessian(&w, &dev)?;
+
+        let x1 = Tensor::randn(0f32, 1., (1, 5, 16), &dev)?;
+        let x2 = Tensor::randn(0f32, 1., (1, 3, 16), &dev)?;
+        let y = Tensor::randn(0f32, 1., (1, 4, 16), &dev)?;
+        for x in [&x1, &x2] {
+            q.process(x)?;
+            k.process(x)?;
+            o.process(&y)?;
+        }
+
+        let x = Tensor::cat(&[x1.squeeze(0)?, x2.squeeze(0)?], 0)?;
+        let expected = (x.t()?.matmul(&x)? * (2. / 8.))?;
+        let h_q = q.compute_hessian()?.unwrap();
+        let h_k = k.compute_hessian()?.unwrap();
+        assert!(max_diff(&h_q, &expected)? < 1e-4);
+        assert!(max_diff(&h_k, &expected)? < 1e-4);
+
+        let y = y.squeeze(0)?;
+        let expected = (y.t()?.matmul(&y)? * (2. / 4.))?;
+        assert!(max_diff(&o.compute_hessian()?.unwrap(), &expected)? < 1e-4);
+
+        // Clearing one layer's Hessian leaves the shared one to the other layer.
+        q.clear_hessian()?;
+        assert!(q.compute_hessian()?.is_none());
+        assert!(max_diff(&k.compute_hessian()?.unwrap(), &h_k)? < 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn hessian_needs_inputs() -> Result<()> {
+        let dev = Device::Cpu;
+        let w = Tensor::zeros((8, 16), DType::F32, &dev)?;
+        assert!(ImatrixLayerStats::new_with_hessian(&w, &dev)?
+            .compute_hessian()
+            .is_err());
+        assert!(ImatrixLayerStats::new(&w, &dev)?
+            .compute_hessian()?
+            .is_none());
+        Ok(())
+    }
+}
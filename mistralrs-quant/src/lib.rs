@@ -17,6 +17,7 @@ use candle_core::{
 #[cfg(feature = "metal")]
 mod metal_kernels;
 
+mod awq;
 mod bitsandbytes;
 mod blockwise_fp8;
 mod cublaslt;
@@ -31,10 +32,12 @@ pub mod safetensors;
 mod unquantized;
 mod utils;
 
+use awq::awq_linear;
 use gptq::gptq_linear;
 pub use safetensors::{Shard, ShardedSafeTensors, ShardedVarBuilder};
 
-pub use bitsandbytes::{BnbLinear, BnbQuantParmas, BnbQuantType};
+pub use awq::AwqLayer;
+pub use bitsandbytes::{bnb_linear_b, BnbInt8Linear, BnbLinear, BnbQuantParmas, BnbQuantType};
 pub use distributed::{
     layers::{
         compute_kv_shard, compute_n_kv_groups, ColumnParallelLayer, ReplicatedLayer,
@@ -50,7 +53,13 @@ pub use gptq::GptqLayer;
 pub use hqq::{HqqAxis, HqqBits, HqqConfig, HqqLayer};
 pub use imatrix::{CollectedImatrixData, ImatrixLayerStats};
 pub use unquantized::UnquantLinear;
-pub use utils::UQFF_QUANT_TYPE_OFFSET;
+pub use utils::{
+    deserialize_uqff_layer, inspect_uqff, inspect_uqff_embedded, is_self_contained_uqff,
+    merge_uqff, read_uqff_files, requantize_uqff, split_uqff, uqff_version_string, write_uqff,
+    UqffChecksum, UqffContents, UqffEmbeddedInfo, UqffLayerInfo, UqffTensor,
+    UQFF_CHECKSUM_METADATA_PREFIX, UQFF_FILE_METADATA_PREFIX, UQFF_QUANT_TYPE_OFFSET,
+    UQFF_VERSION_METADATA_KEY,
+};
 
 use candle_nn::{Linear, Module};
 use serde::{Deserialize, Serialize};
@@ -61,6 +70,8 @@ pub enum QuantMethodType {
     Fp8,
     #[serde(rename = "gptq")]
     Gptq,
+    #[serde(rename = "awq")]
+    Awq,
     #[serde(rename = "unreachable")]
     Unreachable,
     #[default]
@@ -72,6 +83,7 @@ impl Display for QuantMethodType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Gptq => write!(f, "gptq"),
+            Self::Awq => write!(f, "awq"),
             Self::Fp8 => write!(f, "fp8"),
             Self::Bitsandbytes => write!(f, "bnb"),
             Self::Unreachable => write!(f, "unreachable",),
@@ -81,13 +93,21 @@ impl Display for QuantMethodType {
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct QuantizedConfig {
-    // GPTQ
+    // GPTQ, AWQ
     pub bits: Option<usize>,
     pub group_size: Option<usize>,
     pub checkpoint_format: Option<String>,
 
+    // AWQ: `gemm` (default) or `gemv` packing
+    pub version: Option<String>,
+
     // BNB
     pub bnb_4bit_quant_type: Option<String>,
+    /// LLM.int8 outlier threshold, 6.0 if not specified. Input features with a magnitude of at
+    /// least this are multiplied in higher precision. Disabled if 0.
+    pub llm_int8_threshold: Option<f32>,
+    /// Modules which are not quantized, matched against the layer path.
+    pub llm_int8_skip_modules: Option<Vec<String>>,
 
     // FP8
     pub weight_block_size: Option<Vec<usize>>,
@@ -122,6 +142,16 @@ pub enum QuantMethodConfig {
         workspace: Option<Tensor>,
         is_marlin: bool,
     },
+    /// AWQ 4 bit weights, repacked at load time: two values per byte (low nibble first) along the
+    /// output dimension. `qweight` is `(in_dim, out_dim / 2)`, `qzeros` is
+    /// `(in_dim / group_size, out_dim / 2)` and `scales` is `(in_dim / group_size, out_dim)`.
+    Awq {
+        qweight: Tensor,
+        qzeros: Tensor,
+        scales: Tensor,
+        bias: Option<Tensor>,
+        group_size: usize,
+    },
     Gguf {
         q_weight: Arc<QTensor>,
         b: Option<Tensor>,
@@ -148,6 +178,15 @@ pub enum QuantMethodConfig {
         params: BnbQuantParmas,
         quant_ty: BnbQuantType,
     },
+    /// LLM.int8 weight of shape `(out_dim, in_dim)` holding int8 values in a `U8` tensor, with the
+    /// absmax of each row in `scb`.
+    BnbInt8 {
+        weight: Tensor,
+        scb: Tensor,
+        bias: Option<Tensor>,
+        threshold: f32,
+        dtype: DType,
+    },
     BlockwiseFP8 {
         weight: Tensor,
         weight_scale_inv: Tensor,
@@ -253,10 +292,14 @@ pub enum IsqType {
     Q8K,
     HQQ8,
     HQQ4,
-    // HQQ3,
-    // HQQ2,
-    // HQQ1,
+    HQQ3,
+    HQQ2,
+    HQQ1,
     F8E4M3,
+    /// GPTQ with the Hessian collected from a calibration file, stored as Q4_1.
+    GPTQ4,
+    /// AWQ with the activations collected from a calibration file or an imatrix.
+    AWQ4,
 }
 
 impl IsqType {
@@ -301,9 +344,16 @@ impl IsqType {
                 (dtype.size_in_bytes() * GgmlDType::Q8K.block_size()) / GgmlDType::Q8K.type_size()
             }
             // Estimates
-            Self::HQQ4 => 4,
             Self::HQQ8 => 2,
+            Self::HQQ4 => 4,
+            Self::HQQ3 => 5,
+            Self::HQQ2 => 8,
+            Self::HQQ1 => 16,
             Self::F8E4M3 => 2,
+            Self::GPTQ4 => {
+                (dtype.size_in_bytes() * GgmlDType::Q4_1.block_size()) / GgmlDType::Q4_1.type_size()
+            }
+            Self::AWQ4 => AwqLayer::pack_factor(dtype),
         }
     }
 }
@@ -342,7 +392,7 @@ impl TryFrom<IsqType> for GgmlDType {
                     | GgmlDType::Q5K
                     | GgmlDType::Q6K
             ) {
-                candle_core::bail!("GGML ISQ type on CUDA must be one of `Q4_0`, `Q4_1`, `Q5_0`, `Q5_1`, `Q8_0`, `Q2K`, `Q3K`, `Q4K`, `Q5K`, `Q6K`, `HQQ8`, `HQQ4`")
+                candle_core::bail!("GGML ISQ type on CUDA must be one of `Q4_0`, `Q4_1`, `Q5_0`, `Q5_1`, `Q8_0`, `Q2K`, `Q3K`, `Q4K`, `Q5K`, `Q6K`, `HQQ8`, `HQQ4`, `HQQ3`, `HQQ2`, `HQQ1`")
             }
         }
         Ok(tp)
@@ -373,11 +423,13 @@ impl TryFrom<GgmlDType> for IsqType {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum QuantizedSerdeType {
     Gguf = 0,
     Unquant = 1,
     Hqq = 2,
     Fp8 = 3,
+    Awq = 4,
 }
 
 impl TryFrom<usize> for QuantizedSerdeType {
@@ -388,6 +440,7 @@ impl TryFrom<usize> for QuantizedSerdeType {
             1 => Ok(Self::Unquant),
             2 => Ok(Self::Hqq),
             3 => Ok(Self::Fp8),
+            4 => Ok(Self::Awq),
             other => candle_core::bail!("QuantizedSerdeType {other} is invalid."),
         }
     }
@@ -466,11 +519,24 @@ pub trait QuantMethod: Send + Sync + Debug + QuantizedSerde {
         None
     }
 
-    /// Begin tracking stats into an ImatrixLayerStats
-    fn begin_track_stats(&mut self) -> Result<()> {
+    /// If the weight is a GGUF quantized tensor, the weight and the bias. Used to export GGUF files.
+    fn gguf_weight_bias(&self) -> Option<(Arc<QTensor>, Option<Tensor>)> {
+        None
+    }
+
+    /// Begin tracking stats into an ImatrixLayerStats. If `track_hessian`, the full Hessian of the
+    /// inputs is tracked too, for GPTQ ISQ.
+    fn begin_track_stats(&mut self, _track_hessian: bool) -> Result<()> {
         candle_core::bail!("`{}` does not support tracking stats.", self.name())
     }
 
+    /// Quantize the weight with GPTQ using the Hessian tracked since
+    /// [`QuantMethod::begin_track_stats`], then free the Hessian. The result is used when GPTQ ISQ
+    /// is applied. Does nothing if no Hessian is tracked.
+    fn quantize_tracked_hessian(&mut self) -> Result<()> {
+        Ok(())
+    }
+
     /// End tracking stats into an ImatrixLayerStats. Returns the computed imatrix.
     fn end_track_stats(&self) -> Result<Tensor> {
         candle_core::bail!("`{}` does not support tracking stats.", self.name())
@@ -492,12 +558,11 @@ pub fn linear_no_bias(
     let layer = if let Some(quant_conf) = &config {
         match quant_conf.quant_method {
             QuantMethodType::Gptq => gptq_linear(in_dim, out_dim, quant_conf, vb)?,
+            QuantMethodType::Awq => awq_linear(in_dim, out_dim, quant_conf, vb)?,
             QuantMethodType::Fp8 => {
                 blockwise_fp8_linear_b(in_dim, out_dim, quant_conf, false, Default::default(), vb)?
             }
-            QuantMethodType::Bitsandbytes => {
-                Arc::new(BnbLinear::linear_b(in_dim, out_dim, false, vb)?) as Arc<_>
-            }
+            QuantMethodType::Bitsandbytes => bnb_linear_b(in_dim, out_dim, false, quant_conf, vb)?,
             QuantMethodType::Unreachable => unreachable!(),
         }
     } else {
@@ -526,12 +591,11 @@ pub fn linear(
     let layer = if let Some(quant_conf) = &config {
         match quant_conf.quant_method {
             QuantMethodType::Gptq => gptq_linear(in_dim, out_dim, quant_conf, vb)?,
+            QuantMethodType::Awq => awq_linear(in_dim, out_dim, quant_conf, vb)?,
             QuantMethodType::Fp8 => {
                 blockwise_fp8_linear_b(in_dim, out_dim, quant_conf, true, Default::default(), vb)?
             }
-            QuantMethodType::Bitsandbytes => {
-                Arc::new(BnbLinear::linear_b(in_dim, out_dim, true, vb)?) as Arc<_>
-            }
+            QuantMethodType::Bitsandbytes => bnb_linear_b(in_dim, out_dim, true, quant_conf, vb)?,
             QuantMethodType::Unreachable => unreachable!(),
         }
     } else {
@@ -94,6 +94,8 @@ fn convert(
 ) -> Result<Tensor> {
     match (view.dtype(), cast_dtype) {
         (st::Dtype::U8, _) => convert_::<u8>(view, device),
+        // There is no int8 dtype: keep the bits, to be reinterpreted by the quantization method
+        (st::Dtype::I8, _) => convert_::<u8>(view, device),
         (st::Dtype::U16, _) => {
             let conv = |x| Ok(u32::from(x));
             convert_with_cast_::<u16, u32, _>(view, device, conv)
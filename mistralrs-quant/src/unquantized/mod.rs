@@ -6,16 +6,20 @@ use std::{
 };
 
 use byteorder::{LittleEndian, ReadBytesExt};
-use candle_core::{quantized::GgmlDType, DType, Device, DeviceLocation, Result, Shape, Tensor, D};
+use candle_core::{
+    quantized::{ggml_file::qtensor_from_ggml, GgmlDType, QTensor},
+    DType, Device, DeviceLocation, Result, Shape, Tensor, D,
+};
 use candle_nn::Linear;
 
 use crate::{
     cublaslt::{maybe_init_cublas_lt_wrapper, CUBLASLT_HANDLE},
     generate_isq, generate_isq_imatrix,
+    gptq::gptq_quantize,
     hqq::{HqqAxis, HqqBits, HqqConfig, HqqLayer, ISQ_HQQ_DEFAULT_OPT_STEPS, ISQ_HQQ_GROUP_SIZE},
     utils::{deserialize_tensor, serialize_tensor, version_is_compatible, UQFF_VERSION},
-    FP8Linear, GgufMatMul, ImatrixLayerStats, IsqType, MatMul, QuantMethod, QuantMethodConfig,
-    QuantizedSerde, QuantizedSerdeType,
+    AwqLayer, FP8Linear, GgufMatMul, ImatrixLayerStats, IsqType, MatMul, QuantMethod,
+    QuantMethodConfig, QuantizedSerde, QuantizedSerdeType,
 };
 
 #[derive(Debug)]
@@ -23,6 +27,8 @@ pub struct UnquantLinear {
     w: Tensor,
     b: Option<Tensor>,
     stats: Option<ImatrixLayerStats>,
+    /// The weight quantized with GPTQ from the tracked Hessian, to be used when applying GPTQ ISQ.
+    gptq: Option<Arc<QTensor>>,
 }
 
 impl QuantMethod for UnquantLinear {
@@ -35,13 +41,16 @@ impl QuantMethod for UnquantLinear {
             | QuantMethodConfig::Gptq { .. }
             | QuantMethodConfig::Hqq { .. }
             | QuantMethodConfig::Dummy
+            | QuantMethodConfig::Awq { .. }
             | QuantMethodConfig::FP8 { .. }
             | QuantMethodConfig::Bnb { .. }
+            | QuantMethodConfig::BnbInt8 { .. }
             | QuantMethodConfig::BlockwiseFP8 { .. } => unreachable!(),
             QuantMethodConfig::Unquantized(l) => Ok(Self {
                 w: l.weight().clone(),
                 b: l.bias().cloned(),
                 stats: None,
+                gptq: None,
             }),
         }
     }
@@ -133,6 +142,7 @@ impl QuantMethod for UnquantLinear {
             w: (&self.w + delta)?,
             b: self.b.clone(),
             stats: self.stats.clone(),
+            gptq: None,
         }))
     }
 
@@ -148,8 +158,7 @@ impl QuantMethod for UnquantLinear {
         imatrix_weight: Option<Vec<f32>>,
     ) -> Result<Arc<dyn QuantMethod>> {
         match dtype {
-            /*Some(IsqType::HQQ1 | IsqType::HQQ2 | IsqType::HQQ3 | */
-            Some(IsqType::HQQ4 | IsqType::HQQ8) => {
+            Some(IsqType::HQQ1 | IsqType::HQQ2 | IsqType::HQQ3 | IsqType::HQQ4 | IsqType::HQQ8) => {
                 if imatrix_weight.is_some() {
                     // TODO just warn?
                     candle_core::bail!("HQQ does not support imatrix.");
@@ -159,9 +168,9 @@ impl QuantMethod for UnquantLinear {
                 let bits = match dtype.unwrap() {
                     IsqType::HQQ8 => HqqBits::Eight,
                     IsqType::HQQ4 => HqqBits::Four,
-                    // IsqType::HQQ3 => HqqBits::Three,
-                    // IsqType::HQQ2 => HqqBits::Two,
-                    // IsqType::HQQ1 => HqqBits::One,
+                    IsqType::HQQ3 => HqqBits::Three,
+                    IsqType::HQQ2 => HqqBits::Two,
+                    IsqType::HQQ1 => HqqBits::One,
                     _ => unreachable!(),
                 };
                 let cfg = HqqConfig {
@@ -210,6 +219,45 @@ impl QuantMethod for UnquantLinear {
                         .map(|b| b.to_dtype(DType::F32).unwrap().to_device(&device).unwrap()),
                 })?))
             }
+            Some(IsqType::GPTQ4) => {
+                let Some(q_weight) = &self.gptq else {
+                    candle_core::bail!(
+                        "GPTQ ISQ requires the Hessian collected from a calibration file."
+                    );
+                };
+                n_quantized.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let q_weight = if q_weight.device().same_device(&device) {
+                    q_weight.clone()
+                } else {
+                    Arc::new(qtensor_from_ggml(
+                        q_weight.dtype(),
+                        &q_weight.data()?,
+                        q_weight.shape().dims().to_vec(),
+                        &device,
+                    )?)
+                };
+                Ok(Arc::new(GgufMatMul::new(QuantMethodConfig::Gguf {
+                    q_weight,
+                    b: self
+                        .b
+                        .as_ref()
+                        .map(|b| b.to_dtype(DType::F32).unwrap().to_device(&device).unwrap()),
+                })?))
+            }
+            Some(IsqType::AWQ4) => {
+                let Some(imatrix_weight) = imatrix_weight else {
+                    candle_core::bail!(
+                        "AWQ ISQ requires activations collected from a calibration file or an imatrix."
+                    );
+                };
+                n_quantized.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                Ok(Arc::new(AwqLayer::quantize(
+                    &self.w,
+                    self.b.clone(),
+                    &imatrix_weight,
+                    &device,
+                )?))
+            }
             Some(IsqType::F8E4M3) => {
                 if imatrix_weight.is_some() {
                     // TODO just warn?
@@ -245,12 +293,12 @@ impl QuantMethod for UnquantLinear {
 
     fn get_max_isq_cpu_threads(&self, dtype: IsqType) -> Option<NonZeroUsize> {
         match dtype {
-            /*IsqType::HQQ1 | IsqType::HQQ2 | IsqType::HQQ3 | */
-            IsqType::HQQ4 | IsqType::HQQ8 => {
+            IsqType::HQQ1 | IsqType::HQQ2 | IsqType::HQQ3 | IsqType::HQQ4 | IsqType::HQQ8 => {
                 // Use 1 because our HQQ quantizes on the GPU
                 Some(1.try_into().unwrap())
             }
-            IsqType::F8E4M3 => None,
+            // GPTQ already ran while calibrating
+            IsqType::F8E4M3 | IsqType::GPTQ4 | IsqType::AWQ4 => None,
             IsqType::Q2K
             | IsqType::Q3K
             | IsqType::Q4K
@@ -270,8 +318,23 @@ impl QuantMethod for UnquantLinear {
         Some((self.w.clone(), self.b.clone()))
     }
 
-    fn begin_track_stats(&mut self) -> Result<()> {
-        self.stats = Some(ImatrixLayerStats::new(&self.w, self.w.device())?);
+    fn begin_track_stats(&mut self, track_hessian: bool) -> Result<()> {
+        self.stats = Some(if track_hessian {
+            ImatrixLayerStats::new_with_hessian(&self.w, self.w.device())?
+        } else {
+            ImatrixLayerStats::new(&self.w, self.w.device())?
+        });
+        Ok(())
+    }
+
+    fn quantize_tracked_hessian(&mut self) -> Result<()> {
+        let Some(stats) = &self.stats else {
+            return Ok(());
+        };
+        if let Some(hessian) = stats.compute_hessian()? {
+            stats.clear_hessian()?;
+            self.gptq = Some(Arc::new(gptq_quantize(&self.w, &hessian, self.w.device())?));
+        }
         Ok(())
     }
 
@@ -360,6 +423,11 @@ impl QuantizedSerde for UnquantLinear {
             None
         };
 
-        Ok(Arc::new(Self { w, b, stats: None }))
+        Ok(Arc::new(Self {
+            w,
+            b,
+            stats: None,
+            gptq: None,
+        }))
     }
 }
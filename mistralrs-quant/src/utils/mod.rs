@@ -4,6 +4,7 @@ pub(crate) mod isq;
 mod ops;
 
 mod uqff;
+mod uqff_file;
 
 pub use ops::{BitWiseOp, LeftshiftOp};
 pub use uqff::UQFF_QUANT_TYPE_OFFSET;
@@ -11,6 +12,12 @@ pub(crate) use uqff::{
     deserialize_tensor, fake_deserialize_tensor, read_dtype, serialize_tensor,
     version_is_compatible, write_dtype, UQFF_VERSION,
 };
+pub use uqff_file::{
+    deserialize_uqff_layer, inspect_uqff, inspect_uqff_embedded, is_self_contained_uqff,
+    merge_uqff, read_uqff_files, requantize_uqff, split_uqff, uqff_version_string, write_uqff,
+    UqffChecksum, UqffContents, UqffEmbeddedInfo, UqffLayerInfo, UqffTensor,
+    UQFF_CHECKSUM_METADATA_PREFIX, UQFF_FILE_METADATA_PREFIX, UQFF_VERSION_METADATA_KEY,
+};
 
 #[cfg(feature = "cuda")]
 use candle_core::{
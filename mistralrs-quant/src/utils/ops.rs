@@ -72,7 +72,18 @@ impl CustomOp2 for BitWiseOr {
             CpuStorage::U32(_) => Err(Error::UnsupportedDTypeForOp(DType::U32, "bitwise-or")),
             CpuStorage::I64(_) => Err(Error::UnsupportedDTypeForOp(DType::I64, "bitwise-or")),
             CpuStorage::I32(vs1) => {
-                let vs2 = &s2.as_slice::<i32>().unwrap();
+                let vs1 = match l1.contiguous_offsets() {
+                    Some((start, end)) => &vs1[start..end],
+                    None => candle_core::bail!("Input tensor s1 must be contiguous"),
+                };
+                let vs2 = s2.as_slice::<i32>()?;
+                let vs2 = match l2.contiguous_offsets() {
+                    Some((start, end)) => &vs2[start..end],
+                    None => candle_core::bail!("Input tensor s2 must be contiguous"),
+                };
+                if vs1.len() != vs2.len() {
+                    candle_core::bail!("Input tensors must have the same number of elements");
+                };
                 let result = self.bitwise(vs1, vs2);
                 let result = CpuStorage::I32(result);
                 Ok((result, l1.shape().clone()))
@@ -233,8 +244,8 @@ impl CustomOp2 for BitWiseOr {
             s1.dtype(),
             s1.buffer(),
             s2.buffer(),
-            l1.start_offset(),
-            l2.start_offset(),
+            l1.start_offset() * s1.dtype().size_in_bytes(),
+            l2.start_offset() * s2.dtype().size_in_bytes(),
             out_shape.elem_count(),
             &output,
         )
@@ -275,12 +286,12 @@ impl CustomOp1 for Leftshift {
     }
 
     fn cpu_fwd(&self, s1: &CpuStorage, l1: &Layout) -> Result<(CpuStorage, Shape)> {
-        if !l1.is_contiguous() {
+        let Some((start, end)) = l1.contiguous_offsets() else {
             candle_core::bail!("Input tensor s1 must be contiguous");
-        }
+        };
         match s1 {
             CpuStorage::U8(vs1) => {
-                let result = self.leftshift(vs1);
+                let result = self.leftshift(&vs1[start..end]);
                 let result = CpuStorage::U8(result);
                 Ok((result, l1.shape().clone()))
             }
@@ -288,7 +299,7 @@ impl CustomOp1 for Leftshift {
             CpuStorage::U32(_) => Err(Error::UnsupportedDTypeForOp(DType::U32, "leftshifr")),
             CpuStorage::I64(_) => Err(Error::UnsupportedDTypeForOp(DType::I64, "leftshifr")),
             CpuStorage::I32(vs1) => {
-                let result = self.leftshift(vs1);
+                let result = self.leftshift(&vs1[start..end]);
                 let result = CpuStorage::I32(result);
                 Ok((result, l1.shape().clone()))
             }
@@ -403,7 +414,7 @@ impl CustomOp1 for Leftshift {
             &crate::metal_kernels::Kernels::new(),
             s1.dtype(),
             s1.buffer(),
-            l1.start_offset(),
+            l1.start_offset() * s1.dtype().size_in_bytes(),
             self.0 as u32,
             out_shape.elem_count(),
             &output,
@@ -491,6 +502,27 @@ mod tests {
         assert_eq!(c, [[4, 8], [12, 16], [20, 24]]);
     }
 
+    #[test]
+    fn test_bitwise_or_and_leftshift_narrowed_cpu() {
+        use crate::utils::ops::{BitWiseOp, LeftshiftOp};
+        use candle_core::Tensor;
+        let device = candle_core::Device::Cpu;
+        let a = Tensor::from_vec(vec![1u8, 2, 3, 4, 5, 6], (3, 2), &device).unwrap();
+        let b = a.narrow(0, 1, 2).unwrap();
+        let c = b.leftshift(2).unwrap().to_vec2::<u8>().unwrap();
+        assert_eq!(c, [[12, 16], [20, 24]]);
+
+        let a = Tensor::from_vec(vec![1i32, 2, 4, 8, 16, 32], (3, 2), &device).unwrap();
+        let c = a
+            .narrow(0, 0, 1)
+            .unwrap()
+            .bitwise_or(&a.narrow(0, 2, 1).unwrap())
+            .unwrap()
+            .to_vec2::<i32>()
+            .unwrap();
+        assert_eq!(c, [[17, 34]]);
+    }
+
     #[cfg(feature = "metal")]
     #[test]
     fn test_leftshift_metal() {
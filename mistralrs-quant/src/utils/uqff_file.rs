@@ -0,0 +1,731 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs::File,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use candle_core::{Device, Result};
+use safetensors::{tensor::TensorView, Dtype, SafeTensors};
+use sha2::{Digest, Sha256};
+
+use super::uqff::{read_dtype, version_is_compatible, UQFF_VERSION};
+use crate::{
+    AwqLayer, FP8Linear, GgufMatMul, HqqLayer, IsqType, QuantMethod, QuantizedSerde,
+    QuantizedSerdeType, UnquantLinear,
+};
+
+/// Metadata key of a UQFF file holding the UQFF version it was written with.
+pub const UQFF_VERSION_METADATA_KEY: &str = "uqff_version";
+/// Prefix of the metadata keys of a UQFF file holding the SHA-256 checksum of each layer, residual
+/// tensor and embedded file.
+pub const UQFF_CHECKSUM_METADATA_PREFIX: &str = "sha256.";
+/// Prefix of the metadata keys of a self-contained UQFF file holding an embedded file.
+pub const UQFF_FILE_METADATA_PREFIX: &str = "file.";
+
+/// Format a UQFF version as `MAJOR.MINOR.PATCH`.
+pub fn uqff_version_string(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        (version >> (8 * 2)) & 0xff,
+        (version >> 8) & 0xff,
+        version & 0xff
+    )
+}
+
+fn checksum(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Result of checking a layer against the checksum stored in the UQFF file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UqffChecksum {
+    Valid,
+    Invalid,
+    /// Files written before checksums were added have none.
+    Missing,
+}
+
+/// Description of one serialized layer of a UQFF file, read from its header only.
+#[derive(Clone, Debug)]
+pub struct UqffLayerInfo {
+    pub name: String,
+    pub version: u32,
+    pub serde_type: QuantizedSerdeType,
+    /// `None` for unquantized layers.
+    pub isq_type: Option<IsqType>,
+    /// Shape of the weight as stored. For AWQ, this is the packed weight.
+    pub shape: Vec<usize>,
+    pub has_bias: bool,
+    pub size_in_bytes: usize,
+    pub checksum: UqffChecksum,
+}
+
+/// Read the shape of a tensor written by `serialize_tensor` and skip over its data.
+fn read_tensor_shape(buffer: &mut Cursor<&[u8]>) -> Result<Vec<usize>> {
+    let data_len = buffer.read_u32::<LittleEndian>()? as usize;
+    let _dtype = read_dtype(buffer)?;
+    let shape = read_shape(buffer)?;
+    buffer.set_position(buffer.position() + data_len as u64);
+    Ok(shape)
+}
+
+fn read_shape(buffer: &mut Cursor<&[u8]>) -> Result<Vec<usize>> {
+    let n_dims = buffer.read_u32::<LittleEndian>()? as usize;
+    let mut dims = Vec::with_capacity(n_dims);
+    for _ in 0..n_dims {
+        dims.push(buffer.read_u32::<LittleEndian>()? as usize)
+    }
+    Ok(dims)
+}
+
+impl UqffLayerInfo {
+    fn read(name: String, data: &[u8], checksum: UqffChecksum) -> Result<Self> {
+        let mut buffer = Cursor::new(data);
+
+        let version = buffer.read_u32::<LittleEndian>()?;
+        version_is_compatible(version)?;
+        let serde_type = QuantizedSerdeType::try_from(buffer.read_u8()? as usize)?;
+
+        let (isq_type, shape, has_bias) = match serde_type {
+            QuantizedSerdeType::Gguf => {
+                let _data_len = buffer.read_u32::<LittleEndian>()?;
+                let has_bias = buffer.read_u8()? != 0;
+                let _dtype = buffer.read_u32::<LittleEndian>()?;
+                let shape = read_shape(&mut buffer)?;
+                let isq_type = GgufMatMul::get_isq_type_from_uqff(Cow::Borrowed(data))?;
+                (Some(isq_type), shape, has_bias)
+            }
+            QuantizedSerdeType::Unquant => {
+                let has_bias = buffer.read_u8()? != 0;
+                (None, read_tensor_shape(&mut buffer)?, has_bias)
+            }
+            QuantizedSerdeType::Hqq => {
+                let has_bias = buffer.read_u8()? != 0;
+                for _ in 0..3 {
+                    read_tensor_shape(&mut buffer)?;
+                }
+                let shape = read_shape(&mut buffer)?;
+                let isq_type = HqqLayer::get_isq_type_from_uqff(Cow::Borrowed(data))?;
+                (Some(isq_type), shape, has_bias)
+            }
+            QuantizedSerdeType::Fp8 => {
+                let has_bias = buffer.read_u8()? != 0;
+                (
+                    Some(IsqType::F8E4M3),
+                    read_tensor_shape(&mut buffer)?,
+                    has_bias,
+                )
+            }
+            QuantizedSerdeType::Awq => {
+                let has_bias = buffer.read_u8()? != 0;
+                let _has_input_scales = buffer.read_u8()? != 0;
+                (
+                    Some(IsqType::AWQ4),
+                    read_tensor_shape(&mut buffer)?,
+                    has_bias,
+                )
+            }
+        };
+
+        Ok(Self {
+            name,
+            version,
+            serde_type,
+            isq_type,
+            shape,
+            has_bias,
+            size_in_bytes: data.len(),
+            checksum,
+        })
+    }
+}
+
+/// Order layer names numerically, as written by ISQ.
+fn sort_layer_names<T>(layers: &mut [(String, T)]) {
+    layers.sort_by(
+        |(a, _), (b, _)| match (a.parse::<usize>(), b.parse::<usize>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            _ => a.cmp(b),
+        },
+    );
+}
+
+/// A tensor stored in a UQFF file next to the layers.
+pub struct UqffTensor<'a> {
+    pub dtype: Dtype,
+    pub shape: Vec<usize>,
+    pub data: Cow<'a, [u8]>,
+}
+
+/// Contents of a UQFF file.
+#[derive(Default)]
+pub struct UqffContents<'a> {
+    /// Serialized ISQ layers, named by their index.
+    pub layers: Vec<(String, Cow<'a, [u8]>)>,
+    /// Tensors of the model which are not ISQ layers. Only self-contained UQFF files have these.
+    pub residual: Vec<(String, UqffTensor<'a>)>,
+    /// Embedded files, such as `config.json` and `tokenizer.json`, with their contents. Only
+    /// self-contained UQFF files have these.
+    pub files: Vec<(String, String)>,
+}
+
+/// A memory-mapped UQFF file.
+struct UqffFile {
+    mmap: memmap2::Mmap,
+}
+
+impl UqffFile {
+    fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path.as_ref())?;
+        let mmap = unsafe { memmap2::MmapOptions::new().map(&file)? };
+        Ok(Self { mmap })
+    }
+
+    fn metadata(&self) -> Result<HashMap<String, String>> {
+        let (_, metadata) =
+            SafeTensors::read_metadata(&self.mmap).map_err(candle_core::Error::wrap)?;
+        Ok(metadata.metadata().clone().unwrap_or_default())
+    }
+
+    /// The layers in order, the residual tensors and the embedded files.
+    fn contents(&self) -> Result<UqffContents<'_>> {
+        let tensors = SafeTensors::deserialize(&self.mmap).map_err(candle_core::Error::wrap)?;
+        let mut contents = UqffContents::default();
+        for (name, view) in tensors.tensors() {
+            if name.parse::<usize>().is_ok() {
+                contents.layers.push((name, Cow::Borrowed(view.data())));
+            } else {
+                let tensor = UqffTensor {
+                    dtype: view.dtype(),
+                    shape: view.shape().to_vec(),
+                    data: Cow::Borrowed(view.data()),
+                };
+                contents.residual.push((name, tensor));
+            }
+        }
+        sort_layer_names(&mut contents.layers);
+        contents.residual.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut files = self
+            .metadata()?
+            .into_iter()
+            .filter_map(|(key, value)| {
+                key.strip_prefix(UQFF_FILE_METADATA_PREFIX)
+                    .map(|name| (name.to_string(), value))
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        contents.files = files;
+        Ok(contents)
+    }
+}
+
+fn check(metadata: &HashMap<String, String>, key: &str, data: &[u8]) -> UqffChecksum {
+    match metadata.get(&format!("{UQFF_CHECKSUM_METADATA_PREFIX}{key}")) {
+        Some(expected) if *expected == checksum(data) => UqffChecksum::Valid,
+        Some(_) => UqffChecksum::Invalid,
+        None => UqffChecksum::Missing,
+    }
+}
+
+/// Read the header of each layer of a UQFF file, checking version compatibility and checksums.
+pub fn inspect_uqff<P: AsRef<Path>>(path: P) -> Result<Vec<UqffLayerInfo>> {
+    let file = UqffFile::open(path)?;
+    let metadata = file.metadata()?;
+    file.contents()?
+        .layers
+        .into_iter()
+        .map(|(name, data)| {
+            let checksum = check(&metadata, &name, &data);
+            UqffLayerInfo::read(name, &data, checksum)
+        })
+        .collect()
+}
+
+/// Checksums of the residual tensors and embedded files of a self-contained UQFF file.
+#[derive(Clone, Debug, Default)]
+pub struct UqffEmbeddedInfo {
+    pub residual: Vec<(String, UqffChecksum)>,
+    pub files: Vec<(String, UqffChecksum)>,
+}
+
+/// Check the residual tensors and embedded files of a UQFF file against their checksums.
+pub fn inspect_uqff_embedded<P: AsRef<Path>>(path: P) -> Result<UqffEmbeddedInfo> {
+    let file = UqffFile::open(path)?;
+    let metadata = file.metadata()?;
+    let contents = file.contents()?;
+    Ok(UqffEmbeddedInfo {
+        residual: contents
+            .residual
+            .iter()
+            .map(|(name, tensor)| (name.clone(), check(&metadata, name, &tensor.data)))
+            .collect(),
+        files: contents
+            .files
+            .iter()
+            .map(|(name, data)| {
+                let key = format!("{UQFF_FILE_METADATA_PREFIX}{name}");
+                (name.clone(), check(&metadata, &key, data.as_bytes()))
+            })
+            .collect(),
+    })
+}
+
+/// Whether the UQFF file at `path` embeds the files needed to load it without the original model.
+pub fn is_self_contained_uqff<P: AsRef<Path>>(path: P) -> bool {
+    UqffFile::open(path)
+        .and_then(|file| file.metadata())
+        .is_ok_and(|metadata| {
+            metadata.contains_key(&format!("{UQFF_FILE_METADATA_PREFIX}config.json"))
+        })
+}
+
+/// Read the files embedded in a self-contained UQFF file, failing if any of them or any residual
+/// tensor does not match its checksum.
+pub fn read_uqff_files<P: AsRef<Path>>(path: P) -> Result<Vec<(String, String)>> {
+    let file = UqffFile::open(path)?;
+    let metadata = file.metadata()?;
+    let UqffContents {
+        residual, files, ..
+    } = file.contents()?;
+    for (name, tensor) in &residual {
+        if check(&metadata, name, &tensor.data) == UqffChecksum::Invalid {
+            candle_core::bail!("Residual tensor `{name}` does not match its checksum.");
+        }
+    }
+    for (name, data) in &files {
+        let key = format!("{UQFF_FILE_METADATA_PREFIX}{name}");
+        if check(&metadata, &key, data.as_bytes()) == UqffChecksum::Invalid {
+            candle_core::bail!("Embedded file `{name}` does not match its checksum.");
+        }
+    }
+    Ok(files)
+}
+
+/// Deserialize one layer of a UQFF file.
+pub fn deserialize_uqff_layer(data: &[u8], device: &Device) -> Result<Arc<dyn QuantMethod>> {
+    // NOTE(EricLBuehler): isq type is ALWAYS byte 4 (5th) of the tensor.
+    let isq_type = data[super::UQFF_QUANT_TYPE_OFFSET];
+    match QuantizedSerdeType::try_from(isq_type as usize)? {
+        QuantizedSerdeType::Gguf => GgufMatMul::deserialize(Cow::from(data), device),
+        QuantizedSerdeType::Unquant => UnquantLinear::deserialize(Cow::from(data), device),
+        QuantizedSerdeType::Hqq => HqqLayer::deserialize(Cow::from(data), device),
+        QuantizedSerdeType::Fp8 => FP8Linear::deserialize(Cow::from(data), device),
+        QuantizedSerdeType::Awq => AwqLayer::deserialize(Cow::from(data), device),
+    }
+}
+
+/// Write the contents of a UQFF file, storing the UQFF version and the checksum of each layer,
+/// residual tensor and embedded file in the metadata.
+pub fn write_uqff<P: AsRef<Path>>(contents: &UqffContents<'_>, path: P) -> Result<()> {
+    let mut metadata = HashMap::new();
+    metadata.insert(
+        UQFF_VERSION_METADATA_KEY.to_string(),
+        uqff_version_string(UQFF_VERSION),
+    );
+    let mut views = Vec::with_capacity(contents.layers.len() + contents.residual.len());
+    for (name, data) in &contents.layers {
+        metadata.insert(
+            format!("{UQFF_CHECKSUM_METADATA_PREFIX}{name}"),
+            checksum(data),
+        );
+        let view =
+            TensorView::new(Dtype::U8, vec![data.len()], data).map_err(candle_core::Error::wrap)?;
+        views.push((name.clone(), view));
+    }
+    for (name, tensor) in &contents.residual {
+        if name.parse::<usize>().is_ok() {
+            candle_core::bail!("Residual tensor name `{name}` collides with a UQFF layer name.");
+        }
+        metadata.insert(
+            format!("{UQFF_CHECKSUM_METADATA_PREFIX}{name}"),
+            checksum(&tensor.data),
+        );
+        let view = TensorView::new(tensor.dtype, tensor.shape.clone(), &tensor.data)
+            .map_err(candle_core::Error::wrap)?;
+        views.push((name.clone(), view));
+    }
+    for (name, data) in &contents.files {
+        let key = format!("{UQFF_FILE_METADATA_PREFIX}{name}");
+        metadata.insert(
+            format!("{UQFF_CHECKSUM_METADATA_PREFIX}{key}"),
+            checksum(data.as_bytes()),
+        );
+        metadata.insert(key, data.clone());
+    }
+    safetensors::serialize_to_file(views, &Some(metadata), path.as_ref())
+        .map_err(candle_core::Error::wrap)
+}
+
+/// Re-quantize the layers of a UQFF file selected by `select` to `isq`, keeping the others. The
+/// layers are quantized on `device`. Returns the number of re-quantized layers.
+pub fn requantize_uqff<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    isq: IsqType,
+    device: &Device,
+    select: impl Fn(&str) -> bool,
+) -> Result<usize> {
+    let file = UqffFile::open(input)?;
+    let mut contents = file.contents()?;
+    let n_quantized = AtomicUsize::new(0);
+    for (name, data) in &mut contents.layers {
+        if select(name) {
+            let layer = deserialize_uqff_layer(data, device)?.apply_isq(
+                Some(isq),
+                device.clone(),
+                &n_quantized,
+                None,
+            )?;
+            if !layer.isq_serde_supported() {
+                candle_core::bail!(
+                    "Layer {name} cannot be serialized after quantizing to {isq:?}."
+                );
+            }
+            *data = Cow::Owned(layer.serialize()?.into_owned());
+        }
+    }
+    write_uqff(&contents, output)?;
+    Ok(n_quantized.load(std::sync::atomic::Ordering::Relaxed))
+}
+
+/// Split a UQFF file into shards of at most `max_shard_bytes` of layers (unless a single layer is
+/// larger), named `<stem>-00001-of-0000N.uqff` next to the input. The first shard keeps the residual
+/// tensors and embedded files. The layer names are kept, so the shards can be merged back with
+/// [`merge_uqff`].
+pub fn split_uqff<P: AsRef<Path>>(input: P, max_shard_bytes: usize) -> Result<Vec<PathBuf>> {
+    let input = input.as_ref();
+    let file = UqffFile::open(input)?;
+    let UqffContents {
+        layers,
+        residual,
+        files,
+    } = file.contents()?;
+
+    let mut shards = vec![UqffContents {
+        layers: Vec::new(),
+        residual,
+        files,
+    }];
+    let mut shard_bytes = 0;
+    for (name, data) in layers {
+        if shard_bytes + data.len() > max_shard_bytes && shard_bytes > 0 {
+            shards.push(UqffContents::default());
+            shard_bytes = 0;
+        }
+        shard_bytes += data.len();
+        shards.last_mut().unwrap().layers.push((name, data));
+    }
+
+    let stem = input
+        .file_stem()
+        .ok_or(candle_core::Error::msg("UQFF path must have a file name."))?
+        .to_string_lossy();
+    let n_shards = shards.len();
+    let mut paths = Vec::with_capacity(n_shards);
+    for (i, shard) in shards.iter().enumerate() {
+        let path = input.with_file_name(format!("{stem}-{:05}-of-{n_shards:05}.uqff", i + 1));
+        write_uqff(shard, &path)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Merge UQFF shards into one file. The shards must not share layer or residual tensor names.
+pub fn merge_uqff<P: AsRef<Path>, Q: AsRef<Path>>(inputs: &[P], output: Q) -> Result<()> {
+    let files = inputs
+        .iter()
+        .map(UqffFile::open)
+        .collect::<Result<Vec<_>>>()?;
+    let mut merged = UqffContents::default();
+    for file in &files {
+        let contents = file.contents()?;
+        merged.layers.extend(contents.layers);
+        merged.residual.extend(contents.residual);
+        for (name, data) in contents.files {
+            if !merged.files.iter().any(|(other, _)| *other == name) {
+                merged.files.push((name, data));
+            }
+        }
+    }
+    sort_layer_names(&mut merged.layers);
+    if let Some(pair) = merged.layers.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        candle_core::bail!(
+            "Layer {} is present in more than one UQFF shard.",
+            pair[0].0
+        );
+    }
+    merged.residual.sort_by(|(a, _), (b, _)| a.cmp(b));
+    if let Some(pair) = merged
+        .residual
+        .windows(2)
+        .find(|pair| pair[0].0 == pair[1].0)
+    {
+        candle_core::bail!(
+            "Residual tensor {} is present in more than one UQFF shard.",
+            pair[0].0
+        );
+    }
+    write_uqff(&merged, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        borrow::Cow,
+        path::{Path, PathBuf},
+    };
+
+    use safetensors::{Dtype, SafeTensors};
+
+    use candle_core::{Device, Tensor};
+    use candle_nn::Linear;
+
+    use super::{
+        inspect_uqff, inspect_uqff_embedded, is_self_contained_uqff, merge_uqff, read_uqff_files,
+        requantize_uqff, split_uqff, write_uqff, UqffChecksum, UqffContents, UqffTensor,
+    };
+    use crate::{
+        IsqType, QuantMethod, QuantMethodConfig, QuantizedSerde, QuantizedSerdeType, UnquantLinear,
+    };
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mistralrs-uqff-test-{}-{name}", std::process::id()))
+    }
+
+    fn self_contained_contents() -> UqffContents<'static> {
+        UqffContents {
+            layers: vec![
+                ("0".to_string(), Cow::Owned(vec![1u8, 2, 3, 4])),
+                ("1".to_string(), Cow::Owned(vec![5u8, 6, 7])),
+            ],
+            residual: vec![(
+                "model.norm.weight".to_string(),
+                UqffTensor {
+                    dtype: Dtype::F32,
+                    shape: vec![2],
+                    data: Cow::Owned([1f32, 2.].iter().flat_map(|x| x.to_le_bytes()).collect()),
+                },
+            )],
+            files: vec![
+                ("config.json".to_string(), "hello config".to_string()),
+                ("tokenizer.json".to_string(), "hello tokenizer".to_string()),
+            ],
+        }
+    }
+
+    /// Overwrite the bytes of `path` at the first occurrence of `from`.
+    fn corrupt(path: &Path, from: &[u8], to: &[u8]) {
+        let mut bytes = std::fs::read(path).unwrap();
+        let pos = bytes
+            .windows(from.len())
+            .position(|w| w == from)
+            .expect("pattern not found");
+        bytes[pos..pos + to.len()].copy_from_slice(to);
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn self_contained_round_trip() {
+        let path = temp_path("round-trip.uqff");
+        write_uqff(&self_contained_contents(), &path).unwrap();
+
+        assert!(is_self_contained_uqff(&path));
+        let files = read_uqff_files(&path).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                ("config.json".to_string(), "hello config".to_string()),
+                ("tokenizer.json".to_string(), "hello tokenizer".to_string()),
+            ]
+        );
+        let embedded = inspect_uqff_embedded(&path).unwrap();
+        assert_eq!(
+            embedded.residual,
+            vec![("model.norm.weight".to_string(), UqffChecksum::Valid)]
+        );
+        assert!(embedded
+            .files
+            .iter()
+            .all(|(_, checksum)| *checksum == UqffChecksum::Valid));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn corrupted_embedded_file_is_detected() {
+        let path = temp_path("corrupted-file.uqff");
+        write_uqff(&self_contained_contents(), &path).unwrap();
+        corrupt(&path, b"hello config", b"jello config");
+
+        let err = read_uqff_files(&path).unwrap_err().to_string();
+        assert!(err.contains("config.json"), "{err}");
+        let embedded = inspect_uqff_embedded(&path).unwrap();
+        assert!(embedded
+            .files
+            .contains(&("config.json".to_string(), UqffChecksum::Invalid)));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn corrupted_residual_tensor_is_detected() {
+        let path = temp_path("corrupted-residual.uqff");
+        write_uqff(&self_contained_contents(), &path).unwrap();
+
+        // Flip a byte of the residual tensor data
+        let mut bytes = std::fs::read(&path).unwrap();
+        let (header_len, metadata) = SafeTensors::read_metadata(&bytes).unwrap();
+        let (start, _) = metadata.info("model.norm.weight").unwrap().data_offsets;
+        bytes[8 + header_len + start] ^= 0xff;
+        std::fs::write(&path, bytes).unwrap();
+
+        let err = read_uqff_files(&path).unwrap_err().to_string();
+        assert!(err.contains("model.norm.weight"), "{err}");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn plain_uqff_is_not_self_contained() {
+        let path = temp_path("plain.uqff");
+        let contents = UqffContents {
+            layers: vec![("0".to_string(), Cow::Owned(vec![1u8, 2, 3, 4]))],
+            ..Default::default()
+        };
+        write_uqff(&contents, &path).unwrap();
+
+        assert!(!is_self_contained_uqff(&path));
+        assert!(read_uqff_files(&path).unwrap().is_empty());
+        assert!(!is_self_contained_uqff(temp_path("missing.uqff")));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// A UQFF file of `n` unquantized (4, 32) layers.
+    fn unquant_contents(n: usize) -> UqffContents<'static> {
+        let layers = (0..n)
+            .map(|i| {
+                let w = Tensor::arange(0f32, 128., &Device::Cpu)
+                    .unwrap()
+                    .affine(1. / 128., i as f64)
+                    .unwrap()
+                    .reshape((4, 32))
+                    .unwrap();
+                let layer =
+                    UnquantLinear::new(QuantMethodConfig::Unquantized(Linear::new(w, None)))
+                        .unwrap();
+                (
+                    i.to_string(),
+                    Cow::Owned(layer.serialize().unwrap().into_owned()),
+                )
+            })
+            .collect();
+        UqffContents {
+            layers,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn layer_checksums_are_written_and_checked() {
+        let path = temp_path("checksums.uqff");
+        write_uqff(&unquant_contents(2), &path).unwrap();
+
+        let layers = inspect_uqff(&path).unwrap();
+        assert_eq!(layers.len(), 2);
+        for layer in &layers {
+            assert_eq!(layer.checksum, UqffChecksum::Valid);
+            assert_eq!(layer.serde_type, QuantizedSerdeType::Unquant);
+            assert_eq!(layer.shape, vec![4, 32]);
+        }
+
+        // Flip a byte of the data of layer 1
+        let mut bytes = std::fs::read(&path).unwrap();
+        let (header_len, metadata) = SafeTensors::read_metadata(&bytes).unwrap();
+        let (_, end) = metadata.info("1").unwrap().data_offsets;
+        bytes[8 + header_len + end - 1] ^= 0xff;
+        std::fs::write(&path, bytes).unwrap();
+
+        let checksums = inspect_uqff(&path)
+            .unwrap()
+            .into_iter()
+            .map(|layer| layer.checksum)
+            .collect::<Vec<_>>();
+        assert_eq!(checksums, vec![UqffChecksum::Valid, UqffChecksum::Invalid]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn split_and_merge_round_trip() {
+        let path = temp_path("split.uqff");
+        let contents = unquant_contents(3);
+        let layer_size = contents.layers[0].1.len();
+        write_uqff(&contents, &path).unwrap();
+
+        let shards = split_uqff(&path, layer_size).unwrap();
+        assert_eq!(shards.len(), 3);
+        assert!(shards[2]
+            .to_string_lossy()
+            .ends_with("-00003-of-00003.uqff"));
+
+        // Merge out of order, the layers are sorted again
+        let merged = temp_path("merged.uqff");
+        merge_uqff(&[&shards[2], &shards[0], &shards[1]], &merged).unwrap();
+        let names = inspect_uqff(&merged)
+            .unwrap()
+            .into_iter()
+            .map(|layer| (layer.name, layer.checksum))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                ("0".to_string(), UqffChecksum::Valid),
+                ("1".to_string(), UqffChecksum::Valid),
+                ("2".to_string(), UqffChecksum::Valid),
+            ]
+        );
+
+        let err = merge_uqff(&[&shards[0], &shards[0]], temp_path("duplicate.uqff"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("more than one UQFF shard"), "{err}");
+
+        for shard in shards {
+            std::fs::remove_file(shard).unwrap();
+        }
+        std::fs::remove_file(merged).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn requantize_selected_layers() {
+        let path = temp_path("requantize.uqff");
+        let output = temp_path("requantize-q8_0.uqff");
+        write_uqff(&unquant_contents(2), &path).unwrap();
+
+        let n_quantized = requantize_uqff(&path, &output, IsqType::Q8_0, &Device::Cpu, |name| {
+            name == "1"
+        })
+        .unwrap();
+        assert_eq!(n_quantized, 1);
+
+        let layers = inspect_uqff(&output).unwrap();
+        assert_eq!(layers[0].serde_type, QuantizedSerdeType::Unquant);
+        assert_eq!(layers[0].isq_type, None);
+        assert_eq!(layers[1].serde_type, QuantizedSerdeType::Gguf);
+        assert_eq!(layers[1].isq_type, Some(IsqType::Q8_0));
+        assert_eq!(layers[1].shape, vec![4, 32]);
+        assert!(layers
+            .iter()
+            .all(|layer| layer.checksum == UqffChecksum::Valid));
+
+        std::fs::remove_file(output).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,1011 @@
+//! OpenAI compatible batch API (`/v1/files` and `/v1/batches`) and the batch mode of the CLI.
+//!
+//! A batch input file is a JSONL file where each line is a request:
+//! `{"custom_id": "...", "method": "POST", "url": "/v1/chat/completions", "body": {...}}`.
+//! Each line of the results is `{"id": "...", "custom_id": "...", "response": {...}, "error": ...}`.
+
+use std::{
+    collections::HashMap,
+    fs,
+    future::Future,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    body::Bytes,
+    extract::{Json, Multipart, Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response as HttpResponse},
+    Extension,
+};
+use futures::StreamExt;
+use indexmap::IndexMap;
+use mistralrs_core::{MistralRs, Request, RequestPriority, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    fs::File,
+    io::{AsyncWrite, AsyncWriteExt, BufWriter},
+    sync::mpsc::channel,
+};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    chat_completion, completions,
+    openai::{ChatCompletionRequest, CompletionRequest},
+};
+
+const CHAT_COMPLETIONS_URL: &str = "/v1/chat/completions";
+const COMPLETIONS_URL: &str = "/v1/completions";
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time travel has occurred!")
+        .as_secs()
+}
+
+fn new_id(prefix: &str) -> String {
+    format!("{prefix}{}", uuid::Uuid::new_v4().simple())
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FileObject {
+    pub id: String,
+    pub object: &'static str,
+    pub bytes: usize,
+    pub created_at: u64,
+    pub filename: String,
+    pub purpose: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Validating,
+    InProgress,
+    Finalizing,
+    Completed,
+    Failed,
+    Cancelling,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, ToSchema)]
+pub struct RequestCounts {
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Batch {
+    pub id: String,
+    pub object: &'static str,
+    pub endpoint: String,
+    pub errors: Option<Value>,
+    pub input_file_id: String,
+    pub completion_window: String,
+    pub status: BatchStatus,
+    pub output_file_id: Option<String>,
+    pub error_file_id: Option<String>,
+    pub created_at: u64,
+    pub in_progress_at: Option<u64>,
+    pub finalizing_at: Option<u64>,
+    pub completed_at: Option<u64>,
+    pub failed_at: Option<u64>,
+    pub cancelling_at: Option<u64>,
+    pub cancelled_at: Option<u64>,
+    pub request_counts: RequestCounts,
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CreateBatchRequest {
+    #[schema(example = "file-abc123")]
+    pub input_file_id: String,
+    #[schema(example = "/v1/chat/completions")]
+    pub endpoint: String,
+    #[schema(example = "24h")]
+    pub completion_window: String,
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchList {
+    pub object: &'static str,
+    pub data: Vec<Batch>,
+    pub has_more: bool,
+}
+
+#[derive(Deserialize)]
+struct BatchRequestLine {
+    custom_id: String,
+    method: Option<String>,
+    url: String,
+    body: Value,
+}
+
+#[derive(Serialize)]
+struct BatchResponseBody {
+    status_code: u16,
+    request_id: String,
+    body: Value,
+}
+
+#[derive(Serialize)]
+struct BatchLineError {
+    code: String,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct BatchResponseLine {
+    id: String,
+    custom_id: String,
+    response: Option<BatchResponseBody>,
+    error: Option<BatchLineError>,
+}
+
+impl BatchResponseLine {
+    fn error(custom_id: String, code: &str, message: impl ToString) -> Self {
+        Self {
+            id: new_id("batch_req_"),
+            custom_id,
+            response: None,
+            error: Some(BatchLineError {
+                code: code.to_string(),
+                message: message.to_string(),
+            }),
+        }
+    }
+}
+
+/// Send one request to the engine and wait for the whole response.
+async fn run_request(state: Arc<MistralRs>, url: String, body: Value) -> Result<Value> {
+    let (tx, mut rx) = channel(10_000);
+    let request = match url.as_str() {
+        CHAT_COMPLETIONS_URL => {
+            let mut oairequest: ChatCompletionRequest = serde_json::from_value(body)?;
+            if oairequest.previous_response_id.is_some() || oairequest.store {
+                anyhow::bail!("Batch requests do not support `previous_response_id` or `store`.");
+            }
+            oairequest.stream = Some(false);
+            chat_completion::parse_request(oairequest, None, state.clone(), tx)
+                .await?
+                .0
+        }
+        COMPLETIONS_URL => {
+            let mut oairequest: CompletionRequest = serde_json::from_value(body)?;
+            if oairequest.logprobs.is_some() {
+                anyhow::bail!("Completion requests do not support logprobs.");
+            }
+            oairequest.stream = Some(false);
+            completions::parse_request(oairequest, state.clone(), tx)?.0
+        }
+        other => anyhow::bail!(
+            "Unsupported url `{other}`, expected `{CHAT_COMPLETIONS_URL}` or `{COMPLETIONS_URL}`."
+        ),
+    };
+    let request = match request {
+        Request::Normal(mut request) => {
+            request.priority = RequestPriority::Low;
+            Request::Normal(request)
+        }
+        request => request,
+    };
+    state.get_sender()?.send(request).await?;
+
+    let response = rx
+        .recv()
+        .await
+        .context("No response received from the model.")?;
+    match response {
+        Response::Done(response) => {
+            MistralRs::maybe_log_response(state, &response);
+            Ok(serde_json::to_value(response)?)
+        }
+        Response::CompletionDone(response) => {
+            MistralRs::maybe_log_response(state, &response);
+            Ok(serde_json::to_value(response)?)
+        }
+        Response::ModelError(msg, _) | Response::CompletionModelError(msg, _) => {
+            anyhow::bail!(msg)
+        }
+        Response::InternalError(e) | Response::ValidationError(e) => anyhow::bail!(e.to_string()),
+        Response::Chunk(_)
+        | Response::CompletionChunk(_)
+        | Response::ImageGeneration(_)
+        | Response::Transcription(_)
+        | Response::Embedding(_)
+        | Response::Rerank(_)
+        | Response::Classification(_)
+        | Response::Raw { .. } => unreachable!(),
+    }
+}
+
+/// Run one line of a batch input file with `run`, which sends a request body to a url. If
+/// `endpoint` is given, the line must target it.
+async fn run_line<F, Fut>(run: &F, line: String, endpoint: Option<&str>) -> BatchResponseLine
+where
+    F: Fn(String, Value) -> Fut,
+    Fut: Future<Output = Result<Value>>,
+{
+    let line: BatchRequestLine = match serde_json::from_str(&line) {
+        Ok(line) => line,
+        Err(e) => {
+            return BatchResponseLine::error(String::new(), "invalid_request", e);
+        }
+    };
+    if line.method.as_ref().is_some_and(|method| method != "POST") {
+        return BatchResponseLine::error(
+            line.custom_id,
+            "invalid_method",
+            format!(
+                "The method `{}` is not supported, expected `POST`.",
+                line.method.unwrap_or_default()
+            ),
+        );
+    }
+    if endpoint.is_some_and(|endpoint| endpoint != line.url) {
+        return BatchResponseLine::error(
+            line.custom_id,
+            "invalid_url",
+            format!(
+                "The url `{}` does not match the endpoint of the batch.",
+                line.url
+            ),
+        );
+    }
+    match run(line.url, line.body).await {
+        Ok(body) => BatchResponseLine {
+            id: new_id("batch_req_"),
+            custom_id: line.custom_id,
+            response: Some(BatchResponseBody {
+                status_code: 200,
+                request_id: body["id"].as_str().unwrap_or_default().to_string(),
+                body,
+            }),
+            error: None,
+        },
+        Err(e) => BatchResponseLine::error(line.custom_id, "request_failed", e),
+    }
+}
+
+/// Run a batch input file. Results are written in the order of the input. Failed requests go to
+/// `errors`, or to `output` if it is not given.
+///
+/// Batch requests are sent with [`RequestPriority::Low`], so that the scheduler serves
+/// interactive requests first, and only `concurrency` of them are in the engine at once. No new
+/// requests are started once `cancel` is set.
+#[allow(clippy::too_many_arguments)]
+pub async fn run_batch(
+    state: Arc<MistralRs>,
+    input: &std::path::Path,
+    output: &mut (dyn AsyncWrite + Unpin + Send),
+    errors: Option<&mut (dyn AsyncWrite + Unpin + Send)>,
+    endpoint: Option<&str>,
+    concurrency: usize,
+    cancel: Arc<AtomicBool>,
+    progress: impl FnMut(RequestCounts) + Send,
+) -> Result<RequestCounts> {
+    let run = |url: String, body: Value| run_request(state.clone(), url, body);
+    run_batch_with(
+        &run,
+        input,
+        output,
+        errors,
+        endpoint,
+        concurrency,
+        cancel,
+        progress,
+    )
+    .await
+}
+
+/// [`run_batch`], with `run` sending each request.
+#[allow(clippy::too_many_arguments)]
+async fn run_batch_with<F, Fut>(
+    run: &F,
+    input: &std::path::Path,
+    output: &mut (dyn AsyncWrite + Unpin + Send),
+    mut errors: Option<&mut (dyn AsyncWrite + Unpin + Send)>,
+    endpoint: Option<&str>,
+    concurrency: usize,
+    cancel: Arc<AtomicBool>,
+    mut progress: impl FnMut(RequestCounts) + Send,
+) -> Result<RequestCounts>
+where
+    F: Fn(String, Value) -> Fut,
+    Fut: Future<Output = Result<Value>>,
+{
+    let lines = tokio::fs::read_to_string(input)
+        .await?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+
+    let mut counts = RequestCounts {
+        total: lines.len(),
+        ..Default::default()
+    };
+    progress(counts);
+
+    let mut results = futures::stream::iter(lines)
+        .map(|line| {
+            let cancel = cancel.clone();
+            async move {
+                if cancel.load(Ordering::SeqCst) {
+                    None
+                } else {
+                    Some(run_line(run, line, endpoint).await)
+                }
+            }
+        })
+        .buffered(concurrency.max(1));
+
+    while let Some(result) = results.next().await {
+        let Some(result) = result else {
+            continue;
+        };
+        let is_error = result.error.is_some();
+        let writer = match errors.as_deref_mut() {
+            Some(errors) if is_error => errors,
+            _ => &mut *output,
+        };
+        let mut line = serde_json::to_vec(&result)?;
+        line.push(b'\n');
+        writer.write_all(&line).await?;
+        if is_error {
+            counts.failed += 1;
+        } else {
+            counts.completed += 1;
+        }
+        progress(counts);
+    }
+    output.flush().await?;
+    if let Some(errors) = errors {
+        errors.flush().await?;
+    }
+    Ok(counts)
+}
+
+/// Run a batch input file from the command line, writing all results to `output`.
+pub async fn batch_mode(
+    state: Arc<MistralRs>,
+    input: PathBuf,
+    output: PathBuf,
+    concurrency: usize,
+) -> Result<()> {
+    let mut writer = BufWriter::new(
+        File::create(&output)
+            .await
+            .with_context(|| format!("Failed to create batch output {}", output.display()))?,
+    );
+    info!(
+        "Running batch {} with {concurrency} concurrent requests.",
+        input.display()
+    );
+    let mut last_logged = 0;
+    let counts = run_batch(
+        state,
+        &input,
+        &mut writer,
+        None,
+        None,
+        concurrency,
+        Arc::new(AtomicBool::new(false)),
+        |counts| {
+            let done = counts.completed + counts.failed;
+            // Log about every 5%.
+            if done == counts.total || done >= last_logged + (counts.total / 20).max(1) {
+                last_logged = done;
+                info!(
+                    "Batch progress: {done}/{} requests, {} failed.",
+                    counts.total, counts.failed
+                );
+            }
+        },
+    )
+    .await?;
+    info!(
+        "Batch finished: {} completed, {} failed. Results written to {}.",
+        counts.completed,
+        counts.failed,
+        output.display()
+    );
+    Ok(())
+}
+
+struct BatchJob {
+    batch: Batch,
+    cancel: Arc<AtomicBool>,
+}
+
+/// Uploaded files and batches of the server. Files are stored in `dir`.
+pub struct BatchState {
+    dir: PathBuf,
+    concurrency: usize,
+    files: Mutex<IndexMap<String, FileObject>>,
+    batches: Mutex<IndexMap<String, BatchJob>>,
+}
+
+impl BatchState {
+    pub fn new(dir: PathBuf, concurrency: usize) -> Result<Self> {
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create batch directory {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            concurrency,
+            files: Mutex::new(IndexMap::new()),
+            batches: Mutex::new(IndexMap::new()),
+        })
+    }
+
+    fn file_path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    fn add_file(&self, filename: String, purpose: String, bytes: usize) -> FileObject {
+        let file = FileObject {
+            id: new_id("file-"),
+            object: "file",
+            bytes,
+            created_at: now(),
+            filename,
+            purpose,
+        };
+        self.files
+            .lock()
+            .unwrap()
+            .insert(file.id.clone(), file.clone());
+        file
+    }
+
+    fn update_batch(&self, id: &str, f: impl FnOnce(&mut Batch)) {
+        if let Some(job) = self.batches.lock().unwrap().get_mut(id) {
+            f(&mut job.batch);
+        }
+    }
+
+    /// Run a batch in the background and record its results as files.
+    async fn run(self: Arc<Self>, state: Arc<MistralRs>, id: String, cancel: Arc<AtomicBool>) {
+        let (input_file_id, endpoint) = {
+            let batches = self.batches.lock().unwrap();
+            let batch = &batches[&id].batch;
+            (batch.input_file_id.clone(), batch.endpoint.clone())
+        };
+        // The batch may already be cancelling, which is kept until it is cancelled.
+        self.update_batch(&id, |batch| {
+            if batch.status == BatchStatus::Validating {
+                batch.status = BatchStatus::InProgress;
+                batch.in_progress_at = Some(now());
+            }
+        });
+
+        let output_id = new_id("file-");
+        let error_id = new_id("file-");
+        let result = async {
+            let mut output = BufWriter::new(File::create(self.file_path(&output_id)).await?);
+            let mut errors = BufWriter::new(File::create(self.file_path(&error_id)).await?);
+            let counts = run_batch(
+                state,
+                &self.file_path(&input_file_id),
+                &mut output,
+                Some(&mut errors as &mut (dyn AsyncWrite + Unpin + Send)),
+                Some(&endpoint),
+                self.concurrency,
+                cancel.clone(),
+                |counts| self.update_batch(&id, |batch| batch.request_counts = counts),
+            )
+            .await?;
+            anyhow::Ok(counts)
+        }
+        .await;
+
+        match result {
+            Ok(counts) => {
+                self.update_batch(&id, |batch| {
+                    if batch.status == BatchStatus::InProgress {
+                        batch.status = BatchStatus::Finalizing;
+                        batch.finalizing_at = Some(now());
+                    }
+                });
+                let filename = format!("{id}_output.jsonl");
+                let output_bytes = tokio::fs::metadata(self.file_path(&output_id))
+                    .await
+                    .map_or(0, |m| m.len());
+                let error_bytes = tokio::fs::metadata(self.file_path(&error_id))
+                    .await
+                    .map_or(0, |m| m.len());
+                let output_file = self.add_file(filename, "batch_output".to_string(), 0);
+                let _ =
+                    tokio::fs::rename(self.file_path(&output_id), self.file_path(&output_file.id))
+                        .await;
+                self.set_file_size(&output_file.id, output_bytes as usize);
+                let error_file = if counts.failed > 0 {
+                    let error_file =
+                        self.add_file(format!("{id}_error.jsonl"), "batch_output".to_string(), 0);
+                    let _ = tokio::fs::rename(
+                        self.file_path(&error_id),
+                        self.file_path(&error_file.id),
+                    )
+                    .await;
+                    self.set_file_size(&error_file.id, error_bytes as usize);
+                    Some(error_file.id)
+                } else {
+                    let _ = tokio::fs::remove_file(self.file_path(&error_id)).await;
+                    None
+                };
+                self.update_batch(&id, |batch| {
+                    batch.output_file_id = Some(output_file.id);
+                    batch.error_file_id = error_file;
+                    if cancel.load(Ordering::SeqCst) {
+                        batch.status = BatchStatus::Cancelled;
+                        batch.cancelled_at = Some(now());
+                    } else {
+                        batch.status = BatchStatus::Completed;
+                        batch.completed_at = Some(now());
+                    }
+                });
+            }
+            Err(e) => {
+                warn!("Batch {id} failed: {e}");
+                let _ = tokio::fs::remove_file(self.file_path(&output_id)).await;
+                let _ = tokio::fs::remove_file(self.file_path(&error_id)).await;
+                self.update_batch(&id, |batch| {
+                    batch.status = BatchStatus::Failed;
+                    batch.failed_at = Some(now());
+                    batch.errors = Some(serde_json::json!({
+                        "object": "list",
+                        "data": [{"code": "batch_failed", "message": e.to_string()}],
+                    }));
+                });
+            }
+        }
+    }
+
+    fn set_file_size(&self, id: &str, bytes: usize) {
+        if let Some(file) = self.files.lock().unwrap().get_mut(id) {
+            file.bytes = bytes;
+        }
+    }
+}
+
+fn error_response(code: StatusCode, message: impl ToString) -> HttpResponse {
+    (
+        code,
+        Json(serde_json::json!({ "message": message.to_string() })),
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    post,
+    tag = "Mistral.rs",
+    path = "/v1/files",
+    responses((status = 200, description = "Upload a file, such as a batch input file", body = FileObject))
+)]
+pub async fn create_file(
+    Extension(batches): Extension<Arc<BatchState>>,
+    mut multipart: Multipart,
+) -> HttpResponse {
+    let mut purpose = None;
+    let mut upload = None;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+        };
+        match field.name() {
+            Some("purpose") => match field.text().await {
+                Ok(text) => purpose = Some(text),
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+            },
+            Some("file") => {
+                let filename = field.file_name().unwrap_or("file.jsonl").to_string();
+                match field.bytes().await {
+                    Ok(bytes) => upload = Some((filename, bytes)),
+                    Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+                }
+            }
+            _ => {}
+        }
+    }
+    let Some((filename, bytes)) = upload else {
+        return error_response(StatusCode::BAD_REQUEST, "Expected a `file` field.");
+    };
+    let purpose = purpose.unwrap_or_else(|| "batch".to_string());
+    let file = batches.add_file(filename, purpose, bytes.len());
+    if let Err(e) = tokio::fs::write(batches.file_path(&file.id), &bytes).await {
+        batches.files.lock().unwrap().shift_remove(&file.id);
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, e);
+    }
+    Json(file).into_response()
+}
+
+#[utoipa::path(
+    get,
+    tag = "Mistral.rs",
+    path = "/v1/files/{file_id}",
+    params(("file_id" = String, Path, description = "ID of the file")),
+    responses((status = 200, description = "File information", body = FileObject))
+)]
+pub async fn get_file(
+    Extension(batches): Extension<Arc<BatchState>>,
+    Path(file_id): Path<String>,
+) -> HttpResponse {
+    match batches.files.lock().unwrap().get(&file_id) {
+        Some(file) => Json(file.clone()).into_response(),
+        None => error_response(StatusCode::NOT_FOUND, format!("No file `{file_id}`.")),
+    }
+}
+
+#[utoipa::path(
+    get,
+    tag = "Mistral.rs",
+    path = "/v1/files/{file_id}/content",
+    params(("file_id" = String, Path, description = "ID of the file")),
+    responses((status = 200, description = "File content, such as the results of a batch"))
+)]
+pub async fn get_file_content(
+    Extension(batches): Extension<Arc<BatchState>>,
+    Path(file_id): Path<String>,
+) -> HttpResponse {
+    if !batches.files.lock().unwrap().contains_key(&file_id) {
+        return error_response(StatusCode::NOT_FOUND, format!("No file `{file_id}`."));
+    }
+    match tokio::fs::read(batches.file_path(&file_id)).await {
+        Ok(content) => (
+            [(header::CONTENT_TYPE, "application/jsonl")],
+            Bytes::from(content),
+        )
+            .into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+#[utoipa::path(
+    post,
+    tag = "Mistral.rs",
+    path = "/v1/batches",
+    request_body = CreateBatchRequest,
+    responses((status = 200, description = "Create and start a batch", body = Batch))
+)]
+pub async fn create_batch(
+    State(state): State<Arc<MistralRs>>,
+    Extension(batches): Extension<Arc<BatchState>>,
+    Json(request): Json<CreateBatchRequest>,
+) -> HttpResponse {
+    if !batches
+        .files
+        .lock()
+        .unwrap()
+        .contains_key(&request.input_file_id)
+    {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            format!("No file `{}`.", request.input_file_id),
+        );
+    }
+    if request.endpoint != CHAT_COMPLETIONS_URL && request.endpoint != COMPLETIONS_URL {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Unsupported endpoint `{}`, expected `{CHAT_COMPLETIONS_URL}` or `{COMPLETIONS_URL}`.",
+                request.endpoint
+            ),
+        );
+    }
+
+    let batch = Batch {
+        id: new_id("batch_"),
+        object: "batch",
+        endpoint: request.endpoint,
+        errors: None,
+        input_file_id: request.input_file_id,
+        completion_window: request.completion_window,
+        status: BatchStatus::Validating,
+        output_file_id: None,
+        error_file_id: None,
+        created_at: now(),
+        in_progress_at: None,
+        finalizing_at: None,
+        completed_at: None,
+        failed_at: None,
+        cancelling_at: None,
+        cancelled_at: None,
+        request_counts: RequestCounts::default(),
+        metadata: request.metadata,
+    };
+    let cancel = Arc::new(AtomicBool::new(false));
+    batches.batches.lock().unwrap().insert(
+        batch.id.clone(),
+        BatchJob {
+            batch: batch.clone(),
+            cancel: cancel.clone(),
+        },
+    );
+    tokio::spawn(batches.clone().run(state, batch.id.clone(), cancel));
+    Json(batch).into_response()
+}
+
+#[utoipa::path(
+    get,
+    tag = "Mistral.rs",
+    path = "/v1/batches/{batch_id}",
+    params(("batch_id" = String, Path, description = "ID of the batch")),
+    responses((status = 200, description = "Batch status and progress", body = Batch))
+)]
+pub async fn get_batch(
+    Extension(batches): Extension<Arc<BatchState>>,
+    Path(batch_id): Path<String>,
+) -> HttpResponse {
+    match batches.batches.lock().unwrap().get(&batch_id) {
+        Some(job) => Json(job.batch.clone()).into_response(),
+        None => error_response(StatusCode::NOT_FOUND, format!("No batch `{batch_id}`.")),
+    }
+}
+
+#[utoipa::path(
+    get,
+    tag = "Mistral.rs",
+    path = "/v1/batches",
+    responses((status = 200, description = "List the batches", body = BatchList))
+)]
+pub async fn list_batches(Extension(batches): Extension<Arc<BatchState>>) -> Json<BatchList> {
+    // Most recent first, as in OpenAI.
+    let data = batches
+        .batches
+        .lock()
+        .unwrap()
+        .values()
+        .rev()
+        .map(|job| job.batch.clone())
+        .collect();
+    Json(BatchList {
+        object: "list",
+        data,
+        has_more: false,
+    })
+}
+
+#[utoipa::path(
+    post,
+    tag = "Mistral.rs",
+    path = "/v1/batches/{batch_id}/cancel",
+    params(("batch_id" = String, Path, description = "ID of the batch")),
+    responses((status = 200, description = "Cancel a batch. Requests which already started will finish.", body = Batch))
+)]
+pub async fn cancel_batch(
+    Extension(batches): Extension<Arc<BatchState>>,
+    Path(batch_id): Path<String>,
+) -> HttpResponse {
+    let mut jobs = batches.batches.lock().unwrap();
+    let Some(job) = jobs.get_mut(&batch_id) else {
+        return error_response(StatusCode::NOT_FOUND, format!("No batch `{batch_id}`."));
+    };
+    if matches!(
+        job.batch.status,
+        BatchStatus::Validating | BatchStatus::InProgress
+    ) {
+        job.cancel.store(true, Ordering::SeqCst);
+        job.batch.status = BatchStatus::Cancelling;
+        job.batch.cancelling_at = Some(now());
+    }
+    Json(job.batch.clone()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use serde_json::{json, Value};
+    use tokio::io::AsyncWrite;
+
+    use super::{
+        run_batch_with, BatchResponseLine, BatchStatus, RequestCounts, CHAT_COMPLETIONS_URL,
+        COMPLETIONS_URL,
+    };
+
+    fn write_input(name: &str, lines: &[String]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "mistralrs-batch-{name}-{}.jsonl",
+            uuid::Uuid::new_v4().simple()
+        ));
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        path
+    }
+
+    fn request(i: usize, url: &str) -> String {
+        json!({
+            "custom_id": format!("request-{i}"),
+            "method": "POST",
+            "url": url,
+            "body": {"index": i},
+        })
+        .to_string()
+    }
+
+    fn parse(output: &[u8]) -> Vec<Value> {
+        String::from_utf8(output.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn custom_ids(lines: &[Value]) -> Vec<&str> {
+        lines
+            .iter()
+            .map(|line| line["custom_id"].as_str().unwrap())
+            .collect()
+    }
+
+    /// Run a batch with requests which answer with their index, after a delay.
+    async fn run(
+        name: &str,
+        lines: &[String],
+        endpoint: Option<&str>,
+        concurrency: usize,
+        cancel_after: Option<usize>,
+    ) -> (Vec<Value>, Vec<Value>, RequestCounts) {
+        let input = write_input(name, lines);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let started = Arc::new(AtomicUsize::new(0));
+        let runner = {
+            let cancel = cancel.clone();
+            move |_url: String, body: Value| {
+                let cancel = cancel.clone();
+                let started = started.clone();
+                async move {
+                    let n = started.fetch_add(1, Ordering::SeqCst) + 1;
+                    if cancel_after == Some(n) {
+                        cancel.store(true, Ordering::SeqCst);
+                    }
+                    let index = body["index"].as_u64().unwrap();
+                    if index == 3 {
+                        anyhow::bail!("Request 3 failed.");
+                    }
+                    // Later requests finish first.
+                    tokio::time::sleep(Duration::from_millis(50 - 5 * index)).await;
+                    Ok(json!({"id": format!("chatcmpl-{index}")}))
+                }
+            }
+        };
+        let (mut output, mut errors) = (Vec::new(), Vec::new());
+        let mut last_counts = RequestCounts::default();
+        let counts = run_batch_with(
+            &runner,
+            &input,
+            &mut output,
+            Some(&mut errors as &mut (dyn AsyncWrite + Unpin + Send)),
+            endpoint,
+            concurrency,
+            cancel,
+            |counts| last_counts = counts,
+        )
+        .await
+        .unwrap();
+        std::fs::remove_file(input).unwrap();
+        assert_eq!(counts.completed, last_counts.completed);
+        assert_eq!(counts.failed, last_counts.failed);
+        (parse(&output), parse(&errors), counts)
+    }
+
+    #[tokio::test]
+    async fn test_results_are_in_input_order() {
+        let lines = (0..8)
+            .map(|i| request(i, CHAT_COMPLETIONS_URL))
+            .collect::<Vec<_>>();
+        let (output, errors, counts) = run("order", &lines, None, 4, None).await;
+
+        assert_eq!(
+            custom_ids(&output),
+            vec![
+                "request-0",
+                "request-1",
+                "request-2",
+                "request-4",
+                "request-5",
+                "request-6",
+                "request-7"
+            ]
+        );
+        for line in &output {
+            let index = &line["custom_id"].as_str().unwrap()["request-".len()..];
+            assert_eq!(line["response"]["status_code"], 200);
+            assert_eq!(line["response"]["request_id"], format!("chatcmpl-{index}"));
+            assert!(line["error"].is_null());
+        }
+        assert_eq!(custom_ids(&errors), vec!["request-3"]);
+        assert_eq!(errors[0]["error"]["code"], "request_failed");
+        assert_eq!((counts.total, counts.completed, counts.failed), (8, 7, 1));
+    }
+
+    #[tokio::test]
+    async fn test_lines_must_match_the_endpoint() {
+        let lines = vec![
+            request(0, CHAT_COMPLETIONS_URL),
+            request(1, COMPLETIONS_URL),
+            "not json".to_string(),
+            request(2, CHAT_COMPLETIONS_URL),
+        ];
+        let (output, errors, counts) =
+            run("endpoint", &lines, Some(CHAT_COMPLETIONS_URL), 2, None).await;
+
+        assert_eq!(custom_ids(&output), vec!["request-0", "request-2"]);
+        assert_eq!(custom_ids(&errors), vec!["request-1", ""]);
+        assert_eq!(errors[0]["error"]["code"], "invalid_url");
+        assert_eq!(errors[1]["error"]["code"], "invalid_request");
+        assert_eq!((counts.total, counts.completed, counts.failed), (4, 2, 2));
+    }
+
+    #[tokio::test]
+    async fn test_lines_must_use_post() {
+        let mut get = serde_json::from_str::<Value>(&request(1, CHAT_COMPLETIONS_URL)).unwrap();
+        get["method"] = json!("GET");
+        let mut no_method =
+            serde_json::from_str::<Value>(&request(2, CHAT_COMPLETIONS_URL)).unwrap();
+        no_method.as_object_mut().unwrap().remove("method");
+        let lines = vec![
+            request(0, CHAT_COMPLETIONS_URL),
+            get.to_string(),
+            no_method.to_string(),
+        ];
+        let (output, errors, counts) = run("method", &lines, None, 2, None).await;
+
+        assert_eq!(custom_ids(&output), vec!["request-0", "request-2"]);
+        assert_eq!(custom_ids(&errors), vec!["request-1"]);
+        assert_eq!(errors[0]["error"]["code"], "invalid_method");
+        assert_eq!((counts.total, counts.completed, counts.failed), (3, 2, 1));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_new_requests() {
+        let lines = (0..8)
+            .map(|i| request(i, CHAT_COMPLETIONS_URL))
+            .collect::<Vec<_>>();
+        // Cancelled while the second request runs: it finishes, the others never start.
+        let (output, errors, counts) = run("cancel", &lines, None, 1, Some(2)).await;
+
+        assert_eq!(custom_ids(&output), vec!["request-0", "request-1"]);
+        assert!(errors.is_empty());
+        assert_eq!((counts.total, counts.completed, counts.failed), (8, 2, 0));
+    }
+
+    #[test]
+    fn test_batch_status_serialization() {
+        assert_eq!(
+            serde_json::to_string(&BatchStatus::InProgress).unwrap(),
+            "\"in_progress\""
+        );
+    }
+
+    #[test]
+    fn test_error_line() {
+        let line = BatchResponseLine::error("request-1".to_string(), "request_failed", "oops");
+        let value = serde_json::to_value(line).unwrap();
+        assert!(value["id"].as_str().unwrap().starts_with("batch_req_"));
+        assert_eq!(value["custom_id"], "request-1");
+        assert!(value["response"].is_null());
+        assert_eq!(value["error"]["code"], "request_failed");
+        assert_eq!(value["error"]["message"], "oops");
+    }
+}
@@ -0,0 +1,246 @@
+//! Inspect, validate and convert UQFF files without loading a model.
+
+use std::{collections::HashSet, path::PathBuf};
+
+use anyhow::Result;
+use candle_core::Device;
+use clap::{Parser, Subcommand};
+use mistralrs_core::{initialize_logging, parse_isq_value, IsqType};
+use mistralrs_quant::{
+    inspect_uqff, inspect_uqff_embedded, merge_uqff, requantize_uqff, split_uqff,
+    uqff_version_string, UqffChecksum,
+};
+use tracing::info;
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the layers of a UQFF file with their quantization type, shape and size.
+    List { file: PathBuf },
+
+    /// Check the version compatibility and the checksum of every layer of a UQFF file, and of the
+    /// residual tensors and files embedded in a self-contained UQFF file.
+    Verify { file: PathBuf },
+
+    /// Re-quantize the layers of a UQFF file to another ISQ type.
+    Requant {
+        input: PathBuf,
+        output: PathBuf,
+
+        /// ISQ type to re-quantize to.
+        #[arg(long, value_parser = parse_isq_value)]
+        isq: IsqType,
+
+        /// Layers to re-quantize, as comma-separated indices or `START-END` ranges (end exclusive),
+        /// for example `0-8,12`. All layers are re-quantized if this is not specified.
+        #[arg(long)]
+        layers: Option<String>,
+
+        /// Quantize on the CPU.
+        #[arg(long)]
+        cpu: bool,
+    },
+
+    /// Split a UQFF file into shards, written next to it.
+    Split {
+        file: PathBuf,
+
+        /// Maximum size of a shard in GB.
+        #[arg(long, default_value_t = 5.0)]
+        max_shard_size: f64,
+    },
+
+    /// Merge UQFF shards into one file.
+    Merge {
+        /// Merged UQFF file to write.
+        #[arg(short, long)]
+        output: PathBuf,
+
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+    },
+}
+
+/// Parse a layer selection such as `0-8,12` into layer names.
+fn parse_layers(layers: &str) -> Result<HashSet<String>> {
+    let mut selected = HashSet::new();
+    for segment in layers.split(',').map(str::trim) {
+        if let Some((start, end)) = segment.split_once('-') {
+            let (start, end) = (start.parse::<usize>()?, end.parse::<usize>()?);
+            if end <= start {
+                anyhow::bail!("Layer range end must be > start, got {end} <= {start}");
+            }
+            selected.extend((start..end).map(|layer| layer.to_string()));
+        } else {
+            selected.insert(segment.parse::<usize>()?.to_string());
+        }
+    }
+    Ok(selected)
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    initialize_logging();
+
+    match args.command {
+        Command::List { file } => {
+            let layers = inspect_uqff(&file)?;
+            println!(
+                "{:>6}  {:<8}  {:<8}  {:<20}  {:<5}  {:>12}  {:<8}",
+                "LAYER", "VERSION", "QUANT", "SHAPE", "BIAS", "SIZE (MB)", "CHECKSUM"
+            );
+            for layer in &layers {
+                let quant = layer
+                    .isq_type
+                    .map(|isq| format!("{isq:?}"))
+                    .unwrap_or(format!("{:?}", layer.serde_type));
+                println!(
+                    "{:>6}  {:<8}  {:<8}  {:<20}  {:<5}  {:>12.2}  {:<8}",
+                    layer.name,
+                    uqff_version_string(layer.version),
+                    quant,
+                    format!("{:?}", layer.shape),
+                    layer.has_bias,
+                    layer.size_in_bytes as f64 / 1e6,
+                    format!("{:?}", layer.checksum),
+                );
+            }
+            let total = layers
+                .iter()
+                .map(|layer| layer.size_in_bytes)
+                .sum::<usize>();
+            println!(
+                "{} layers, {:.2} GB total.",
+                layers.len(),
+                total as f64 / 1e9
+            );
+            let embedded = inspect_uqff_embedded(&file)?;
+            if !embedded.files.is_empty() {
+                let files = embedded
+                    .files
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>();
+                println!(
+                    "Self-contained: {} residual tensors, embedded files: {}.",
+                    embedded.residual.len(),
+                    files.join(", ")
+                );
+            }
+        }
+        Command::Verify { file } => {
+            // Reading the headers checks version compatibility
+            let layers = inspect_uqff(&file)?;
+            let embedded = inspect_uqff_embedded(&file)?;
+            let checksums = layers
+                .iter()
+                .map(|layer| (layer.name.as_str(), layer.checksum))
+                .chain(
+                    embedded
+                        .residual
+                        .iter()
+                        .chain(&embedded.files)
+                        .map(|(name, checksum)| (name.as_str(), *checksum)),
+                )
+                .collect::<Vec<_>>();
+            let invalid = checksums
+                .iter()
+                .filter(|(_, checksum)| *checksum == UqffChecksum::Invalid)
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>();
+            let missing = checksums
+                .iter()
+                .filter(|(_, checksum)| *checksum == UqffChecksum::Missing)
+                .count();
+            if !invalid.is_empty() {
+                anyhow::bail!(
+                    "Checksum mismatch for {} entries: {}",
+                    invalid.len(),
+                    invalid.join(", ")
+                );
+            }
+            if missing > 0 {
+                info!("{missing} layer(s) have no checksum, this file was written by an older version.");
+            }
+            info!(
+                "`{}` is valid: {} layers are compatible with this version.",
+                file.display(),
+                layers.len()
+            );
+        }
+        Command::Requant {
+            input,
+            output,
+            isq,
+            layers,
+            cpu,
+        } => {
+            #[cfg(feature = "metal")]
+            let device = if cpu {
+                Device::Cpu
+            } else {
+                Device::new_metal(0)?
+            };
+            #[cfg(not(feature = "metal"))]
+            let device = if cpu {
+                Device::Cpu
+            } else {
+                Device::cuda_if_available(0)?
+            };
+
+            let selected = layers.as_deref().map(parse_layers).transpose()?;
+            let n_quantized = requantize_uqff(&input, &output, isq, &device, |name| {
+                selected
+                    .as_ref()
+                    .is_none_or(|selected| selected.contains(name))
+            })?;
+            info!(
+                "Re-quantized {n_quantized} layers into {isq:?}, wrote `{}`.",
+                output.display()
+            );
+        }
+        Command::Split {
+            file,
+            max_shard_size,
+        } => {
+            let shards = split_uqff(&file, (max_shard_size * 1e9) as usize)?;
+            for shard in &shards {
+                info!("Wrote `{}`.", shard.display());
+            }
+        }
+        Command::Merge { output, inputs } => {
+            merge_uqff(&inputs, &output)?;
+            info!(
+                "Merged {} shards into `{}`.",
+                inputs.len(),
+                output.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_layers;
+
+    #[test]
+    fn parses_layer_selection() {
+        let mut layers = parse_layers("0-3, 7")
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>();
+        layers.sort();
+        assert_eq!(layers, vec!["0", "1", "2", "7"]);
+
+        assert!(parse_layers("3-3").is_err());
+        assert!(parse_layers("a").is_err());
+    }
+}
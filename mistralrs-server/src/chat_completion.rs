@@ -1,6 +1,5 @@
 use serde_json::Value;
 use std::{
-    collections::HashMap,
     env,
     error::Error,
     ops::Deref,
@@ -12,7 +11,8 @@ use std::{
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 
 use crate::{
-    openai::{ChatCompletionRequest, Grammar, MessageInnerContent, StopTokens},
+    conversation_store::ConversationStore,
+    openai::{ChatCompletionRequest, Grammar, Message, StopTokens},
     util,
 };
 use anyhow::{Context as _, Result};
@@ -23,14 +23,19 @@ use axum::{
         sse::{Event, KeepAlive},
         IntoResponse, Sse,
     },
+    Extension,
 };
 use either::Either;
 use indexmap::IndexMap;
 use mistralrs_core::{
-    ChatCompletionResponse, Constraint, DrySamplingParams, MistralRs, NormalRequest, Request,
-    RequestMessage, Response, SamplingParams, StopTokens as InternalStopTokens,
+    has_image_placeholders, join_text_parts, parse_content_parts, prefix_media, AudioInput,
+    AudioPart, ChatCompletionResponse, Constraint, ContentPart, ConversationTokens,
+    DrySamplingParams, MistralRs, ModelCategory, NormalRequest, PendingMedia, Request,
+    RequestMessage, RequestPriority, Response, SamplingParams, StopTokens as InternalStopTokens,
+    VideoInput, VideoPart,
 };
 use serde::Serialize;
+use tracing::warn;
 
 #[derive(Debug)]
 struct ModelErrorMessage(String);
@@ -45,6 +50,9 @@ pub struct Streamer {
     rx: Receiver<Response>,
     is_done: bool,
     state: Arc<MistralRs>,
+    conversation: Option<PendingConversation>,
+    reply: String,
+    tokens: Vec<u32>,
 }
 
 impl futures::Stream for Streamer {
@@ -71,9 +79,27 @@ impl futures::Stream for Streamer {
                     MistralRs::maybe_log_error(self.state.clone(), &*e);
                     Poll::Ready(Some(Ok(Event::default().data(e.to_string()))))
                 }
-                Response::Chunk(response) => {
+                Response::Chunk(mut response) => {
+                    if let Some(conversation) = &self.conversation {
+                        response.id = conversation.id.clone();
+                        if let Some(choice) =
+                            response.choices.iter().find(|choice| choice.index == 0)
+                        {
+                            if let Some(content) = &choice.delta.content {
+                                self.reply.push_str(content);
+                            }
+                            if let Some(tokens) = &choice.conversation_tokens {
+                                self.tokens.clone_from(tokens);
+                            }
+                        }
+                    }
                     if response.choices.iter().all(|x| x.finish_reason.is_some()) {
                         self.is_done = true;
+                        if let Some(conversation) = self.conversation.take() {
+                            let reply = std::mem::take(&mut self.reply);
+                            let tokens = std::mem::take(&mut self.tokens);
+                            conversation.finish(reply, tokens);
+                        }
                     }
                     MistralRs::maybe_log_response(self.state.clone(), &response);
                     Poll::Ready(Some(Event::default().json_data(response)))
@@ -83,6 +109,10 @@ impl futures::Stream for Streamer {
                 Response::CompletionModelError(_, _) => unreachable!(),
                 Response::CompletionChunk(_) => unreachable!(),
                 Response::ImageGeneration(_) => unreachable!(),
+                Response::Transcription(_) => unreachable!(),
+                Response::Embedding(_) => unreachable!(),
+                Response::Rerank(_) => unreachable!(),
+                Response::Classification(_) => unreachable!(),
                 Response::Raw { .. } => unreachable!(),
             },
             Poll::Pending | Poll::Ready(None) => Poll::Pending,
@@ -154,8 +184,127 @@ impl IntoResponse for ChatCompletionResponder {
     }
 }
 
-async fn parse_request(
+/// A conversation which will be stored once the reply is complete.
+struct PendingConversation {
+    store: Arc<ConversationStore>,
+    id: String,
+    messages: Vec<Message>,
+    continue_final_message: bool,
+}
+
+impl PendingConversation {
+    /// Store the conversation with the assistant reply and the tokens of the exchange, under the
+    /// ID of the response.
+    fn finish(mut self, reply: String, tokens: Vec<u32>) {
+        match self.messages.last_mut() {
+            Some(last)
+                if self.continue_final_message
+                    && last.role == "assistant"
+                    && last.content.is_left() =>
+            {
+                let content = format!("{}{reply}", last.content.as_ref().left().unwrap());
+                last.content = content.into();
+            }
+            _ => self.messages.push(Message {
+                content: reply.into(),
+                role: "assistant".to_string(),
+                name: None,
+            }),
+        }
+        if let Err(e) = self.store.insert(self.id, self.messages, tokens) {
+            warn!("Failed to store conversation: {e}");
+        }
+    }
+}
+
+/// Prepend the history of `previous_response_id` to the request messages. This also returns the
+/// stored tokens of the history for the engine and, if the conversation should be stored, where to
+/// store it.
+fn resolve_conversation(
+    mut oairequest: ChatCompletionRequest,
+    store: Option<&Arc<ConversationStore>>,
+) -> Result<(
+    ChatCompletionRequest,
+    Option<ConversationTokens>,
+    Option<PendingConversation>,
+)> {
+    if oairequest.previous_response_id.is_none() && !oairequest.store {
+        return Ok((oairequest, None, None));
+    }
+    let Some(store) = store else {
+        anyhow::bail!("`previous_response_id` and `store` require the server to be started with `--conversation-store`.");
+    };
+    if oairequest.store && oairequest.n_choices != 1 {
+        anyhow::bail!(
+            "`store` only supports a single choice, but `n` is {}.",
+            oairequest.n_choices
+        );
+    }
+    let (mut messages, prefix_tokens) = match &oairequest.previous_response_id {
+        Some(id) => {
+            let conversation = store
+                .get(id)
+                .with_context(|| format!("Unknown `previous_response_id` `{id}`."))?;
+            (conversation.messages, conversation.tokens)
+        }
+        None => (Vec::new(), Vec::new()),
+    };
+    let tokens = ConversationTokens {
+        prefix_messages: messages.len(),
+        prefix_tokens,
+        return_tokens: oairequest.store,
+    };
+    match oairequest.messages {
+        Either::Left(new_messages) => messages.extend(new_messages),
+        Either::Right(prompt) => messages.push(Message {
+            content: prompt.into(),
+            role: "user".to_string(),
+            name: None,
+        }),
+    }
+    oairequest.messages = Either::Left(messages.clone());
+    // Continuing a conversation only stores the new turn if requested.
+    let conversation = oairequest.store.then(|| PendingConversation {
+        store: store.clone(),
+        id: ConversationStore::new_id(),
+        messages,
+        continue_final_message: oairequest.continue_final_message,
+    });
+    Ok((oairequest, Some(tokens), conversation))
+}
+
+/// Load a video content part: an animated clip or a list of frame URLs.
+async fn load_video(video: VideoPart) -> Result<VideoInput> {
+    let loaded = match &video.source {
+        Either::Left(url_unparsed) => Either::Left(
+            util::parse_video_url(url_unparsed)
+                .await
+                .with_context(|| format!("Failed to parse video resource: {}", url_unparsed))?,
+        ),
+        Either::Right(frame_urls) => {
+            let mut frames = Vec::new();
+            for url_unparsed in frame_urls {
+                let frame = util::parse_image_url(url_unparsed)
+                    .await
+                    .with_context(|| format!("Failed to parse video frame: {}", url_unparsed))?;
+                frames.push(frame);
+            }
+            Either::Right(frames)
+        }
+    };
+    Ok(video.into_video(loaded))
+}
+
+/// Load an audio content part: a WAV file, or raw 16-bit mono PCM.
+async fn load_audio(audio: AudioPart) -> Result<AudioInput> {
+    util::parse_audio_url(&audio.source, audio.pcm16_sample_rate)
+        .await
+        .with_context(|| format!("Failed to parse audio resource: {}", audio.source))
+}
+
+pub(crate) async fn parse_request(
     oairequest: ChatCompletionRequest,
+    conversation: Option<ConversationTokens>,
     state: Arc<MistralRs>,
     tx: Sender<Response>,
 ) -> Result<(Request, bool)> {
@@ -171,6 +320,8 @@ async fn parse_request(
         Either::Left(req_messages) => {
             let mut messages = Vec::new();
             let mut image_urls = Vec::new();
+            let mut video_parts = Vec::new();
+            let mut audio_parts = Vec::new();
             for message in req_messages {
                 match message.content.deref() {
                     Either::Left(content) => {
@@ -183,87 +334,103 @@ async fn parse_request(
                             .insert("content".to_string(), Either::Left(content.to_string()));
                         messages.push(message_map);
                     }
-                    Either::Right(image_messages) => {
-                        if image_messages.len() != 2 {
-                            anyhow::bail!(
-                                "Expected 2 items for the content of a message with an image."
-                            );
+                    Either::Right(content_parts) => {
+                        let mut message_map: IndexMap<
+                            String,
+                            Either<String, Vec<IndexMap<String, Value>>>,
+                        > = IndexMap::new();
+                        message_map.insert("role".to_string(), Either::Left(message.role.clone()));
+
+                        let parts = parse_content_parts(content_parts)?;
+                        if let Some(content) = join_text_parts(&parts) {
+                            // Only text: this can be rendered by any chat template.
+                            message_map.insert("content".to_string(), Either::Left(content));
+                            messages.push(message_map);
+                            continue;
                         }
+
                         if message.role != "user" {
                             anyhow::bail!(
-                                "Role for an image message must be `user`, but it is {}",
+                                "Role for an image, video or audio message must be `user`, but it is {}",
                                 message.role
                             );
                         }
-
-                        let mut items = Vec::new();
-                        for image_message in image_messages {
-                            if image_message.len() != 2 {
-                                anyhow::bail!("Expected 2 items for the sub-content of a message with an image.");
-                            }
-                            if !image_message.contains_key("type") {
-                                anyhow::bail!("Expected `type` key in input message.");
-                            }
-                            if image_message["type"].is_right() {
-                                anyhow::bail!("Expected string value in `type`.");
-                            }
-                            items.push(image_message["type"].as_ref().unwrap_left().clone())
-                        }
-
-                        fn get_content_and_url(
-                            text_idx: usize,
-                            url_idx: usize,
-                            image_messages: &[HashMap<String, MessageInnerContent>],
-                        ) -> Result<(String, String)> {
-                            if image_messages[text_idx]["text"].is_right() {
-                                anyhow::bail!("Expected string value in `text`.");
+                        let prefixer = match &state.config().category {
+                            ModelCategory::Vision {
+                                has_conv2d: _,
+                                prefixer,
+                            } => prefixer.clone(),
+                            ModelCategory::Text
+                            | ModelCategory::Diffusion
+                            | ModelCategory::Speech
+                            | ModelCategory::Embedding
+                            | ModelCategory::Reranker => {
+                                anyhow::bail!(
+                                    "Image, video and audio messages require a vision model."
+                                )
                             }
-                            let content = image_messages[text_idx]["text"]
-                                .as_ref()
-                                .unwrap_left()
-                                .clone();
-                            if image_messages[url_idx]["image_url"].is_left()
-                                || !image_messages[url_idx]["image_url"]
-                                    .as_ref()
-                                    .unwrap_right()
-                                    .contains_key("url")
-                            {
-                                anyhow::bail!("Expected content of format {{`type`: `text`, `text`: ...}} and {{`type`: `url`, `image_url`: {{`url`: ...}}}}")
-                            }
-                            let url = image_messages[url_idx]["image_url"].as_ref().unwrap_right()
-                                ["url"]
-                                .clone();
-                            Ok((content, url))
-                        }
-                        let mut message_map: IndexMap<
-                            String,
-                            Either<String, Vec<IndexMap<String, Value>>>,
-                        > = IndexMap::new();
-                        message_map.insert("role".to_string(), Either::Left(message.role));
-                        let (content, url) = if items[0] == "text" {
-                            get_content_and_url(0, 1, image_messages)?
-                        } else {
-                            get_content_and_url(1, 0, image_messages)?
                         };
 
+                        // Each text part is prefixed with the placeholders of the images, videos
+                        // and audio clips which directly precede it so that the placeholders keep
+                        // their positions. Images are not prefixed if the client already wrote
+                        // their placeholders.
+                        let prefix_images =
+                            !has_image_placeholders(&*prefixer, &parts, image_urls.len());
                         let mut content_map: Vec<IndexMap<String, Value>> = Vec::new();
-                        let mut content_image_map = IndexMap::new();
-                        content_image_map
-                            .insert("type".to_string(), Value::String("image".to_string()));
-                        content_map.push(content_image_map);
-                        let mut content_text_map = IndexMap::new();
-                        content_text_map
-                            .insert("type".to_string(), Value::String("text".to_string()));
-                        content_text_map.insert("text".to_string(), Value::String(content));
-                        content_map.push(content_text_map);
+                        let mut pending_media = Vec::new();
+                        for part in parts {
+                            match part {
+                                ContentPart::ImageUrl(url) => {
+                                    if prefix_images {
+                                        pending_media.push(PendingMedia::Image(image_urls.len()));
+                                    }
+                                    image_urls.push(url);
+                                    content_map.push(IndexMap::from([(
+                                        "type".to_string(),
+                                        Value::String("image".to_string()),
+                                    )]));
+                                }
+                                ContentPart::VideoUrl(video) => {
+                                    pending_media.push(PendingMedia::Video(video_parts.len()));
+                                    video_parts.push(video);
+                                    content_map.push(IndexMap::from([(
+                                        "type".to_string(),
+                                        Value::String("video".to_string()),
+                                    )]));
+                                }
+                                ContentPart::Audio(audio) => {
+                                    pending_media.push(PendingMedia::Audio(audio_parts.len()));
+                                    audio_parts.push(audio);
+                                    content_map.push(IndexMap::from([(
+                                        "type".to_string(),
+                                        Value::String("audio".to_string()),
+                                    )]));
+                                }
+                                ContentPart::Text(text) => {
+                                    let text = prefix_media(&*prefixer, &pending_media, &text);
+                                    pending_media.clear();
+                                    content_map.push(IndexMap::from([
+                                        ("type".to_string(), Value::String("text".to_string())),
+                                        ("text".to_string(), Value::String(text)),
+                                    ]));
+                                }
+                            }
+                        }
+                        if !pending_media.is_empty() {
+                            let text = prefix_media(&*prefixer, &pending_media, "");
+                            content_map.push(IndexMap::from([
+                                ("type".to_string(), Value::String("text".to_string())),
+                                ("text".to_string(), Value::String(text)),
+                            ]));
+                        }
 
                         message_map.insert("content".to_string(), Either::Right(content_map));
                         messages.push(message_map);
-                        image_urls.push(url);
                     }
                 }
             }
-            if !image_urls.is_empty() {
+            if !image_urls.is_empty() || !video_parts.is_empty() || !audio_parts.is_empty() {
                 let mut images = Vec::new();
                 for url_unparsed in image_urls {
                     let image = util::parse_image_url(&url_unparsed)
@@ -274,7 +441,20 @@ async fn parse_request(
 
                     images.push(image);
                 }
-                RequestMessage::VisionChat { messages, images }
+                let mut videos = Vec::new();
+                for video in video_parts {
+                    videos.push(load_video(video).await?);
+                }
+                let mut audios = Vec::new();
+                for audio in audio_parts {
+                    audios.push(load_audio(audio).await?);
+                }
+                RequestMessage::VisionChat {
+                    messages,
+                    images,
+                    videos,
+                    audios,
+                }
             } else {
                 RequestMessage::Chat(messages)
             }
@@ -336,6 +516,10 @@ async fn parse_request(
             tools: oairequest.tools,
             logits_processors: None,
             return_raw_logits: false,
+            chat_template_kwargs: oairequest.chat_template_kwargs,
+            continue_final_message: oairequest.continue_final_message,
+            conversation,
+            priority: RequestPriority::Normal,
         }),
         is_streaming,
     ))
@@ -350,17 +534,28 @@ async fn parse_request(
 )]
 pub async fn chatcompletions(
     State(state): State<Arc<MistralRs>>,
+    Extension(conversation_store): Extension<Option<Arc<ConversationStore>>>,
     Json(oairequest): Json<ChatCompletionRequest>,
 ) -> ChatCompletionResponder {
     let (tx, mut rx) = channel(10_000);
-    let (request, is_streaming) = match parse_request(oairequest, state.clone(), tx).await {
-        Ok(x) => x,
-        Err(e) => {
-            let e = anyhow::Error::msg(e.to_string());
-            MistralRs::maybe_log_error(state, &*e);
-            return ChatCompletionResponder::InternalError(e.into());
-        }
-    };
+    let (oairequest, conversation_tokens, conversation) =
+        match resolve_conversation(oairequest, conversation_store.as_ref()) {
+            Ok(x) => x,
+            Err(e) => {
+                let e = anyhow::Error::msg(e.to_string());
+                MistralRs::maybe_log_error(state, &*e);
+                return ChatCompletionResponder::ValidationError(e.into());
+            }
+        };
+    let (request, is_streaming) =
+        match parse_request(oairequest, conversation_tokens, state.clone(), tx).await {
+            Ok(x) => x,
+            Err(e) => {
+                let e = anyhow::Error::msg(e.to_string());
+                MistralRs::maybe_log_error(state, &*e);
+                return ChatCompletionResponder::InternalError(e.into());
+            }
+        };
     let sender = state.get_sender().unwrap();
 
     if let Err(e) = sender.send(request).await {
@@ -374,6 +569,9 @@ pub async fn chatcompletions(
             rx,
             is_done: false,
             state,
+            conversation,
+            reply: String::new(),
+            tokens: Vec::new(),
         };
 
         let keep_alive_interval = env::var("KEEP_ALIVE_INTERVAL")
@@ -404,7 +602,21 @@ pub async fn chatcompletions(
                 ChatCompletionResponder::ModelError(msg, response)
             }
             Response::ValidationError(e) => ChatCompletionResponder::ValidationError(e),
-            Response::Done(response) => {
+            Response::Done(mut response) => {
+                if let Some(conversation) = conversation {
+                    response.id = conversation.id.clone();
+                    let (reply, tokens) = response
+                        .choices
+                        .first()
+                        .map(|choice| {
+                            (
+                                choice.message.content.clone().unwrap_or_default(),
+                                choice.conversation_tokens.clone().unwrap_or_default(),
+                            )
+                        })
+                        .unwrap_or_default();
+                    conversation.finish(reply, tokens);
+                }
                 MistralRs::maybe_log_response(state, &response);
                 ChatCompletionResponder::Json(response)
             }
@@ -413,7 +625,77 @@ pub async fn chatcompletions(
             Response::CompletionModelError(_, _) => unreachable!(),
             Response::CompletionChunk(_) => unreachable!(),
             Response::ImageGeneration(_) => unreachable!(),
+            Response::Transcription(_) => unreachable!(),
+            Response::Embedding(_) => unreachable!(),
+            Response::Rerank(_) => unreachable!(),
+            Response::Classification(_) => unreachable!(),
             Response::Raw { .. } => unreachable!(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::resolve_conversation;
+    use crate::{
+        conversation_store::ConversationStore,
+        openai::{ChatCompletionRequest, Message},
+    };
+
+    fn request(store: bool, n: usize) -> ChatCompletionRequest {
+        serde_json::from_value(json!({
+            "model": "",
+            "messages": [{"role": "user", "content": "What is my name?"}],
+            "previous_response_id": "resp_a",
+            "store": store,
+            "n": n,
+        }))
+        .unwrap()
+    }
+
+    fn store() -> Arc<ConversationStore> {
+        let store = Arc::new(ConversationStore::new(4, None).unwrap());
+        store
+            .insert(
+                "resp_a".to_string(),
+                vec![Message {
+                    content: "My name is Alice.".to_string().into(),
+                    role: "user".to_string(),
+                    name: None,
+                }],
+                vec![1, 2, 3],
+            )
+            .unwrap();
+        store
+    }
+
+    #[test]
+    fn test_previous_response_is_only_stored_with_store() {
+        let store = store();
+
+        let (oairequest, tokens, conversation) =
+            resolve_conversation(request(false, 1), Some(&store)).unwrap();
+        assert!(conversation.is_none());
+        assert_eq!(oairequest.messages.left().unwrap().len(), 2);
+        let tokens = tokens.unwrap();
+        assert_eq!(tokens.prefix_tokens, vec![1, 2, 3]);
+        assert_eq!(tokens.prefix_messages, 1);
+        assert!(!tokens.return_tokens);
+
+        let (_, tokens, conversation) =
+            resolve_conversation(request(true, 1), Some(&store)).unwrap();
+        assert_eq!(conversation.unwrap().messages.len(), 2);
+        assert!(tokens.unwrap().return_tokens);
+    }
+
+    #[test]
+    fn test_store_rejects_multiple_choices() {
+        let store = store();
+        assert!(resolve_conversation(request(true, 2), Some(&store)).is_err());
+        assert!(resolve_conversation(request(false, 2), Some(&store)).is_ok());
+    }
+}
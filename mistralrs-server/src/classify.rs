@@ -0,0 +1,184 @@
+use anyhow::Result;
+use either::Either;
+use indexmap::IndexMap;
+use std::{error::Error, ops::Deref, sync::Arc};
+use tokio::sync::mpsc::{channel, Sender};
+
+use crate::openai::{ClassificationInput, ClassificationInputs, ClassificationRequest, Message};
+use axum::{
+    extract::{Json, State},
+    http::{self, StatusCode},
+    response::IntoResponse,
+};
+use mistralrs_core::{
+    ClassificationResponse, Constraint, MessageContent, MistralRs, NormalRequest, Request,
+    RequestMessage, RequestPriority, Response, SamplingParams,
+};
+use serde::Serialize;
+
+pub enum ClassificationResponder {
+    Json(ClassificationResponse),
+    InternalError(Box<dyn Error>),
+    ValidationError(Box<dyn Error>),
+}
+
+trait ErrorToResponse: Serialize {
+    fn to_response(&self, code: StatusCode) -> axum::response::Response {
+        let mut r = Json(self).into_response();
+        *r.status_mut() = code;
+        r
+    }
+}
+
+#[derive(Serialize)]
+struct JsonError {
+    message: String,
+}
+
+impl JsonError {
+    fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+impl ErrorToResponse for JsonError {}
+
+impl IntoResponse for ClassificationResponder {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            ClassificationResponder::Json(s) => Json(s).into_response(),
+            ClassificationResponder::InternalError(e) => {
+                JsonError::new(e.to_string()).to_response(http::StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            ClassificationResponder::ValidationError(e) => {
+                JsonError::new(e.to_string()).to_response(http::StatusCode::UNPROCESSABLE_ENTITY)
+            }
+        }
+    }
+}
+
+/// Conversations to classify must only contain text.
+fn parse_conversation(messages: Vec<Message>) -> Result<Vec<IndexMap<String, MessageContent>>> {
+    messages
+        .into_iter()
+        .map(|message| {
+            let Either::Left(content) = message.content.deref() else {
+                anyhow::bail!("Messages to classify must have string content.");
+            };
+            let mut message_map: IndexMap<String, MessageContent> = IndexMap::new();
+            message_map.insert("role".to_string(), Either::Left(message.role));
+            message_map.insert("content".to_string(), Either::Left(content.clone()));
+            Ok(message_map)
+        })
+        .collect()
+}
+
+fn parse_request(
+    oairequest: ClassificationRequest,
+    state: Arc<MistralRs>,
+    tx: Sender<Response>,
+) -> Result<Request> {
+    let repr = serde_json::to_string(&oairequest).expect("Serialization of request failed.");
+    MistralRs::maybe_log_request(state.clone(), repr);
+
+    let inputs = match oairequest.input {
+        ClassificationInputs::Multi(inputs) => inputs,
+        ClassificationInputs::Single(input) => vec![input],
+    };
+    if inputs.is_empty() {
+        anyhow::bail!("`input` must contain at least one input.");
+    }
+    let inputs = inputs
+        .into_iter()
+        .map(|input| match input {
+            ClassificationInput::Text(text) => Ok(mistralrs_core::ClassificationInput::Text(text)),
+            ClassificationInput::Conversation(messages) => Ok(
+                mistralrs_core::ClassificationInput::Conversation(parse_conversation(messages)?),
+            ),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Request::Normal(NormalRequest {
+        id: state.next_request_id(),
+        messages: RequestMessage::Classification { inputs },
+        sampling_params: SamplingParams::deterministic(),
+        response: tx,
+        return_logprobs: false,
+        is_streaming: false,
+        suffix: None,
+        constraint: Constraint::None,
+        adapters: None,
+        tool_choice: None,
+        tools: None,
+        logits_processors: None,
+        return_raw_logits: false,
+        chat_template_kwargs: None,
+        continue_final_message: false,
+        conversation: None,
+        priority: RequestPriority::Normal,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    tag = "Mistral.rs",
+    path = "/v1/classify",
+    request_body = ClassificationRequest,
+    responses((status = 200, description = "Classify texts or conversations, or score them with a reward model"))
+)]
+pub async fn classify(
+    State(state): State<Arc<MistralRs>>,
+    Json(oairequest): Json<ClassificationRequest>,
+) -> ClassificationResponder {
+    let (tx, mut rx) = channel(10_000);
+
+    let request = match parse_request(oairequest, state.clone(), tx) {
+        Ok(x) => x,
+        Err(e) => {
+            MistralRs::maybe_log_error(state, &*e);
+            return ClassificationResponder::ValidationError(e.into());
+        }
+    };
+    let sender = state.get_sender().unwrap();
+
+    if let Err(e) = sender.send(request).await {
+        let e = anyhow::Error::msg(e.to_string());
+        MistralRs::maybe_log_error(state, &*e);
+        return ClassificationResponder::InternalError(e.into());
+    }
+
+    let response = match rx.recv().await {
+        Some(response) => response,
+        None => {
+            let e = anyhow::Error::msg("No response received from the model.");
+            MistralRs::maybe_log_error(state, &*e);
+            return ClassificationResponder::InternalError(e.into());
+        }
+    };
+
+    match response {
+        Response::InternalError(e) => {
+            MistralRs::maybe_log_error(state, &*e);
+            ClassificationResponder::InternalError(e)
+        }
+        Response::ValidationError(e) => ClassificationResponder::ValidationError(e),
+        Response::Classification(response) => {
+            MistralRs::maybe_log_response(state, &response);
+            ClassificationResponder::Json(response)
+        }
+        Response::CompletionModelError(m, _) => {
+            let e = anyhow::Error::msg(m.to_string());
+            MistralRs::maybe_log_error(state, &*e);
+            ClassificationResponder::InternalError(e.into())
+        }
+        Response::CompletionDone(_) => unreachable!(),
+        Response::CompletionChunk(_) => unreachable!(),
+        Response::Chunk(_) => unreachable!(),
+        Response::Done(_) => unreachable!(),
+        Response::ModelError(_, _) => unreachable!(),
+        Response::ImageGeneration(_) => unreachable!(),
+        Response::Transcription(_) => unreachable!(),
+        Response::Embedding(_) => unreachable!(),
+        Response::Rerank(_) => unreachable!(),
+        Response::Raw { .. } => unreachable!(),
+    }
+}
@@ -20,7 +20,7 @@ use axum::{
 };
 use mistralrs_core::{
     CompletionResponse, Constraint, DrySamplingParams, MistralRs, NormalRequest, Request,
-    RequestMessage, Response, SamplingParams, StopTokens as InternalStopTokens,
+    RequestMessage, RequestPriority, Response, SamplingParams, StopTokens as InternalStopTokens,
 };
 use serde::Serialize;
 use tracing::warn;
@@ -75,6 +75,10 @@ impl futures::Stream for Streamer {
                 Response::CompletionDone(_) => unreachable!(),
                 Response::Chunk(_) => unreachable!(),
                 Response::ImageGeneration(_) => unreachable!(),
+                Response::Transcription(_) => unreachable!(),
+                Response::Embedding(_) => unreachable!(),
+                Response::Rerank(_) => unreachable!(),
+                Response::Classification(_) => unreachable!(),
                 Response::ModelError(_, _) => unreachable!(),
                 Response::Raw { .. } => unreachable!(),
             },
@@ -145,7 +149,7 @@ impl IntoResponse for CompletionResponder {
     }
 }
 
-fn parse_request(
+pub(crate) fn parse_request(
     oairequest: CompletionRequest,
     state: Arc<MistralRs>,
     tx: Sender<Response>,
@@ -213,6 +217,10 @@ fn parse_request(
             tools: oairequest.tools,
             logits_processors: None,
             return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
         }),
         is_streaming,
     ))
@@ -297,6 +305,10 @@ pub async fn completions(
             Response::Done(_) => unreachable!(),
             Response::ModelError(_, _) => unreachable!(),
             Response::ImageGeneration(_) => unreachable!(),
+            Response::Transcription(_) => unreachable!(),
+            Response::Embedding(_) => unreachable!(),
+            Response::Rerank(_) => unreachable!(),
+            Response::Classification(_) => unreachable!(),
             Response::Raw { .. } => unreachable!(),
         }
     }
@@ -0,0 +1,198 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::openai::Message;
+
+/// A conversation stored after a chat completion. `messages` holds the whole history, including
+/// the assistant reply, and `tokens` the prompt and completion tokens of the reply as the engine
+/// generated them. A continuation uses them in place of re-tokenizing the history so that the
+/// prefix cacher can reuse their KV cache. `tokens` may be empty, in which case the history is
+/// tokenized again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredConversation {
+    pub id: String,
+    pub created: u64,
+    pub messages: Vec<Message>,
+    #[serde(default)]
+    pub tokens: Vec<u32>,
+}
+
+/// Server-side store of conversations, so that a chat completion request can continue a previous
+/// response with `previous_response_id` instead of re-sending the history.
+///
+/// The most recent conversations are kept in memory. If a directory is given, every conversation
+/// is also written there as `<id>.json` and is loaded from it when not in memory, so conversations
+/// survive restarts.
+pub struct ConversationStore {
+    conversations: Mutex<IndexMap<String, StoredConversation>>,
+    max_in_memory: usize,
+    persist_dir: Option<PathBuf>,
+}
+
+impl ConversationStore {
+    pub fn new(max_in_memory: usize, persist_dir: Option<PathBuf>) -> Result<Self> {
+        if let Some(dir) = &persist_dir {
+            fs::create_dir_all(dir).with_context(|| {
+                format!(
+                    "Failed to create conversation store directory {}",
+                    dir.display()
+                )
+            })?;
+        }
+        Ok(Self {
+            conversations: Mutex::new(IndexMap::new()),
+            max_in_memory: max_in_memory.max(1),
+            persist_dir,
+        })
+    }
+
+    /// Create a new, unique response ID.
+    pub fn new_id() -> String {
+        format!("resp_{}", uuid::Uuid::new_v4().simple())
+    }
+
+    fn path(&self, id: &str) -> Option<PathBuf> {
+        // IDs come from requests, so only accept the ones we generate before touching the disk.
+        let valid = id.strip_prefix("resp_").is_some_and(|rest| {
+            !rest.is_empty() && rest.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+        match &self.persist_dir {
+            Some(dir) if valid => Some(dir.join(format!("{id}.json"))),
+            _ => None,
+        }
+    }
+
+    /// Get a stored conversation.
+    pub fn get(&self, id: &str) -> Option<StoredConversation> {
+        if let Some(conversation) = self.conversations.lock().unwrap().get(id) {
+            return Some(conversation.clone());
+        }
+        let path = self.path(id)?;
+        let data = fs::read_to_string(&path).ok()?;
+        match serde_json::from_str::<StoredConversation>(&data) {
+            Ok(conversation) => {
+                self.insert_in_memory(conversation.clone());
+                Some(conversation)
+            }
+            Err(e) => {
+                warn!("Failed to read stored conversation {}: {e}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Store a conversation and the tokens of its last exchange under `id`, replacing any existing
+    /// one.
+    pub fn insert(&self, id: String, messages: Vec<Message>, tokens: Vec<u32>) -> Result<()> {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time travel has occurred!")
+            .as_secs();
+        let conversation = StoredConversation {
+            id: id.clone(),
+            created,
+            messages,
+            tokens,
+        };
+        if let Some(path) = self.path(&id) {
+            fs::write(&path, serde_json::to_vec(&conversation)?).with_context(|| {
+                format!("Failed to write stored conversation {}", path.display())
+            })?;
+        }
+        self.insert_in_memory(conversation);
+        Ok(())
+    }
+
+    fn insert_in_memory(&self, conversation: StoredConversation) {
+        let mut conversations = self.conversations.lock().unwrap();
+        conversations.shift_remove(&conversation.id);
+        conversations.insert(conversation.id.clone(), conversation);
+        // Drop the oldest ones. Persisted conversations can still be loaded from disk.
+        while conversations.len() > self.max_in_memory {
+            conversations.shift_remove_index(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConversationStore;
+    use crate::openai::Message;
+
+    fn message(role: &str, content: &str) -> Message {
+        Message {
+            content: content.to_string().into(),
+            role: role.to_string(),
+            name: None,
+        }
+    }
+
+    #[test]
+    fn test_evicts_oldest_in_memory() {
+        let store = ConversationStore::new(2, None).unwrap();
+        for id in ["resp_a", "resp_b", "resp_c"] {
+            store
+                .insert(id.to_string(), vec![message("user", id)], Vec::new())
+                .unwrap();
+        }
+        assert!(store.get("resp_a").is_none());
+        assert!(store.get("resp_b").is_some());
+        assert!(store.get("resp_c").is_some());
+    }
+
+    #[test]
+    fn test_persists_to_disk() {
+        let dir =
+            std::env::temp_dir().join(format!("conversations-{}", ConversationStore::new_id()));
+        let store = ConversationStore::new(1, Some(dir.clone())).unwrap();
+        let id = ConversationStore::new_id();
+        store
+            .insert(
+                id.clone(),
+                vec![message("user", "Hello"), message("assistant", "Hi!")],
+                vec![1, 15043, 6324, 29991],
+            )
+            .unwrap();
+
+        // A new store, as after a restart.
+        let store = ConversationStore::new(1, Some(dir.clone())).unwrap();
+        let conversation = store.get(&id).unwrap();
+        assert_eq!(conversation.messages.len(), 2);
+        assert_eq!(conversation.messages[1].role, "assistant");
+        assert_eq!(conversation.tokens, vec![1, 15043, 6324, 29991]);
+        assert!(store.get("resp_missing").is_none());
+        assert!(store.get("../resp_escape").is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_loads_conversations_without_tokens() {
+        let dir =
+            std::env::temp_dir().join(format!("conversations-{}", ConversationStore::new_id()));
+        let store = ConversationStore::new(1, Some(dir.clone())).unwrap();
+        let id = ConversationStore::new_id();
+        std::fs::write(
+            dir.join(format!("{id}.json")),
+            format!(
+                r#"{{"id": "{id}", "created": 0, "messages": [{{"role": "user", "content": "Hi"}}]}}"#
+            ),
+        )
+        .unwrap();
+
+        let conversation = store.get(&id).unwrap();
+        assert_eq!(conversation.messages.len(), 1);
+        assert!(conversation.tokens.is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
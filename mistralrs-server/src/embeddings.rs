@@ -0,0 +1,242 @@
+use anyhow::Result;
+use std::{error::Error, sync::Arc};
+use tokio::sync::mpsc::{channel, Sender};
+
+use crate::openai::{EmbeddingEncodingFormat, EmbeddingInput, EmbeddingRequest};
+use axum::{
+    extract::{Json, State},
+    http::{self, StatusCode},
+    response::IntoResponse,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mistralrs_core::{
+    Constraint, EmbeddingParams, EmbeddingResponse, EmbeddingUsage, MistralRs, NormalRequest,
+    Request, RequestMessage, RequestPriority, Response, SamplingParams,
+};
+use serde::Serialize;
+
+pub enum EmbeddingResponder {
+    Json(EmbeddingResponse),
+    Base64(Base64EmbeddingResponse),
+    InternalError(Box<dyn Error>),
+    ValidationError(Box<dyn Error>),
+}
+
+trait ErrorToResponse: Serialize {
+    fn to_response(&self, code: StatusCode) -> axum::response::Response {
+        let mut r = Json(self).into_response();
+        *r.status_mut() = code;
+        r
+    }
+}
+
+#[derive(Serialize)]
+struct JsonError {
+    message: String,
+}
+
+impl JsonError {
+    fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+impl ErrorToResponse for JsonError {}
+
+#[derive(Serialize)]
+pub struct Base64EmbeddingData {
+    object: String,
+    embedding: String,
+    index: usize,
+}
+
+/// An embedding response with `encoding_format: "base64"`.
+#[derive(Serialize)]
+pub struct Base64EmbeddingResponse {
+    object: String,
+    data: Vec<Base64EmbeddingData>,
+    model: String,
+    usage: EmbeddingUsage,
+}
+
+impl From<EmbeddingResponse> for Base64EmbeddingResponse {
+    fn from(response: EmbeddingResponse) -> Self {
+        Self {
+            object: response.object,
+            data: response
+                .data
+                .into_iter()
+                .map(|data| Base64EmbeddingData {
+                    object: data.object,
+                    embedding: STANDARD.encode(
+                        data.embedding
+                            .iter()
+                            .flat_map(|x| x.to_le_bytes())
+                            .collect::<Vec<_>>(),
+                    ),
+                    index: data.index,
+                })
+                .collect(),
+            model: response.model,
+            usage: response.usage,
+        }
+    }
+}
+
+impl IntoResponse for EmbeddingResponder {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            EmbeddingResponder::Json(s) => Json(s).into_response(),
+            EmbeddingResponder::Base64(s) => Json(s).into_response(),
+            EmbeddingResponder::InternalError(e) => {
+                JsonError::new(e.to_string()).to_response(http::StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            EmbeddingResponder::ValidationError(e) => {
+                JsonError::new(e.to_string()).to_response(http::StatusCode::UNPROCESSABLE_ENTITY)
+            }
+        }
+    }
+}
+
+fn parse_request(
+    oairequest: EmbeddingRequest,
+    state: Arc<MistralRs>,
+    tx: Sender<Response>,
+) -> Result<Request> {
+    let repr = serde_json::to_string(&oairequest).expect("Serialization of request failed.");
+    MistralRs::maybe_log_request(state.clone(), repr);
+
+    let inputs = match oairequest.input {
+        EmbeddingInput::Single(input) => vec![input],
+        EmbeddingInput::Multi(inputs) => inputs,
+    };
+    if inputs.is_empty() {
+        anyhow::bail!("`input` must contain at least one string.");
+    }
+
+    Ok(Request::Normal(NormalRequest {
+        id: state.next_request_id(),
+        messages: RequestMessage::Embedding {
+            inputs,
+            params: EmbeddingParams {
+                normalize: oairequest.normalize,
+                dimensions: oairequest.dimensions,
+            },
+        },
+        sampling_params: SamplingParams::deterministic(),
+        response: tx,
+        return_logprobs: false,
+        is_streaming: false,
+        suffix: None,
+        constraint: Constraint::None,
+        adapters: None,
+        tool_choice: None,
+        tools: None,
+        logits_processors: None,
+        return_raw_logits: false,
+        chat_template_kwargs: None,
+        continue_final_message: false,
+        conversation: None,
+        priority: RequestPriority::Normal,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    tag = "Mistral.rs",
+    path = "/v1/embeddings",
+    request_body = EmbeddingRequest,
+    responses((status = 200, description = "Embeddings"))
+)]
+pub async fn embeddings(
+    State(state): State<Arc<MistralRs>>,
+    Json(oairequest): Json<EmbeddingRequest>,
+) -> EmbeddingResponder {
+    let (tx, mut rx) = channel(10_000);
+    let encoding_format = oairequest.encoding_format;
+
+    let request = match parse_request(oairequest, state.clone(), tx) {
+        Ok(x) => x,
+        Err(e) => {
+            MistralRs::maybe_log_error(state, &*e);
+            return EmbeddingResponder::ValidationError(e.into());
+        }
+    };
+    let sender = state.get_sender().unwrap();
+
+    if let Err(e) = sender.send(request).await {
+        let e = anyhow::Error::msg(e.to_string());
+        MistralRs::maybe_log_error(state, &*e);
+        return EmbeddingResponder::InternalError(e.into());
+    }
+
+    let response = match rx.recv().await {
+        Some(response) => response,
+        None => {
+            let e = anyhow::Error::msg("No response received from the model.");
+            MistralRs::maybe_log_error(state, &*e);
+            return EmbeddingResponder::InternalError(e.into());
+        }
+    };
+
+    match response {
+        Response::InternalError(e) => {
+            MistralRs::maybe_log_error(state, &*e);
+            EmbeddingResponder::InternalError(e)
+        }
+        Response::ValidationError(e) => EmbeddingResponder::ValidationError(e),
+        Response::Embedding(response) => {
+            MistralRs::maybe_log_response(state, &response);
+            match encoding_format {
+                EmbeddingEncodingFormat::Float => EmbeddingResponder::Json(response),
+                EmbeddingEncodingFormat::Base64 => EmbeddingResponder::Base64(response.into()),
+            }
+        }
+        Response::CompletionModelError(m, _) => {
+            let e = anyhow::Error::msg(m.to_string());
+            MistralRs::maybe_log_error(state, &*e);
+            EmbeddingResponder::InternalError(e.into())
+        }
+        Response::CompletionDone(_) => unreachable!(),
+        Response::CompletionChunk(_) => unreachable!(),
+        Response::Chunk(_) => unreachable!(),
+        Response::Done(_) => unreachable!(),
+        Response::ModelError(_, _) => unreachable!(),
+        Response::ImageGeneration(_) => unreachable!(),
+        Response::Transcription(_) => unreachable!(),
+        Response::Rerank(_) => unreachable!(),
+        Response::Classification(_) => unreachable!(),
+        Response::Raw { .. } => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use mistralrs_core::{EmbeddingData, EmbeddingResponse, EmbeddingUsage};
+
+    use super::Base64EmbeddingResponse;
+
+    #[test]
+    fn base64_embeddings_are_little_endian_f32() {
+        let response = EmbeddingResponse {
+            object: "list".to_string(),
+            data: vec![EmbeddingData {
+                object: "embedding".to_string(),
+                embedding: vec![1.0, -0.5],
+                index: 0,
+            }],
+            model: "default".to_string(),
+            usage: EmbeddingUsage {
+                prompt_tokens: 2,
+                total_tokens: 2,
+            },
+        };
+        let encoded = Base64EmbeddingResponse::from(response);
+        let bytes = STANDARD.decode(&encoded.data[0].embedding).unwrap();
+        let values = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![1.0, -0.5]);
+    }
+}
@@ -1,16 +1,17 @@
 use anyhow::Result;
 use std::{error::Error, sync::Arc};
-use tokio::sync::mpsc::{channel, Sender};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
 
-use crate::openai::ImageGenerationRequest;
+use crate::{openai::ImageGenerationRequest, util::parse_image_url};
 use axum::{
-    extract::{Json, State},
+    extract::{Json, Multipart, State},
     http::{self, StatusCode},
     response::IntoResponse,
 };
+use image::{DynamicImage, GrayImage, Luma};
 use mistralrs_core::{
-    Constraint, DiffusionGenerationParams, ImageGenerationResponse, MistralRs, NormalRequest,
-    Request, RequestMessage, Response, SamplingParams,
+    Constraint, DiffusionGenerationParams, ImageGenerationResponse, ImageGenerationResponseFormat,
+    MistralRs, NormalRequest, Request, RequestMessage, RequestPriority, Response, SamplingParams,
 };
 use serde::Serialize;
 
@@ -54,7 +55,7 @@ impl IntoResponse for ImageGenerationResponder {
     }
 }
 
-fn parse_request(
+async fn parse_request(
     oairequest: ImageGenerationRequest,
     state: Arc<MistralRs>,
     tx: Sender<Response>,
@@ -62,17 +63,51 @@ fn parse_request(
     let repr = serde_json::to_string(&oairequest).expect("Serialization of request failed.");
     MistralRs::maybe_log_request(state.clone(), repr);
 
-    Ok(Request::Normal(NormalRequest {
-        id: state.next_request_id(),
+    let init_image = match &oairequest.image {
+        Some(url) => Some(parse_image_url(url).await?),
+        None => None,
+    };
+
+    Ok(image_request(
+        state.next_request_id(),
+        oairequest.prompt,
+        oairequest.response_format,
+        oairequest.n_choices,
+        DiffusionGenerationParams {
+            height: oairequest.height,
+            width: oairequest.width,
+            num_steps: oairequest.num_steps,
+            guidance_scale: oairequest.guidance_scale,
+            seed: oairequest.seed,
+            negative_prompt: oairequest.negative_prompt,
+            true_cfg_scale: oairequest.true_cfg_scale,
+            init_image,
+            strength: oairequest.strength,
+            mask_image: None,
+        },
+        tx,
+    ))
+}
+
+fn image_request(
+    id: usize,
+    prompt: String,
+    format: ImageGenerationResponseFormat,
+    n_choices: usize,
+    generation_params: DiffusionGenerationParams,
+    tx: Sender<Response>,
+) -> Request {
+    Request::Normal(NormalRequest {
+        id,
         messages: RequestMessage::ImageGeneration {
-            prompt: oairequest.prompt,
-            format: oairequest.response_format,
-            generation_params: DiffusionGenerationParams {
-                height: oairequest.height,
-                width: oairequest.width,
-            },
+            prompt,
+            format,
+            generation_params,
+        },
+        sampling_params: SamplingParams {
+            n_choices,
+            ..SamplingParams::deterministic()
         },
-        sampling_params: SamplingParams::deterministic(),
         response: tx,
         return_logprobs: false,
         is_streaming: false,
@@ -83,29 +118,121 @@ fn parse_request(
         tools: None,
         logits_processors: None,
         return_raw_logits: false,
+        chat_template_kwargs: None,
+        continue_final_message: false,
+        conversation: None,
+        priority: RequestPriority::Normal,
+    })
+}
+
+/// OpenAI edit masks mark the area to edit as transparent. Masks without an alpha channel are used
+/// as is, where white marks the area to edit.
+fn mask_from_image(mask: DynamicImage) -> DynamicImage {
+    if !mask.color().has_alpha() {
+        return mask;
+    }
+    let rgba = mask.to_rgba8();
+    DynamicImage::ImageLuma8(GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        Luma([255 - rgba.get_pixel(x, y)[3]])
     }))
 }
 
-#[utoipa::path(
-    post,
-    tag = "Mistral.rs",
-    path = "/v1/images/generations",
-    request_body = ImageGenerationRequest,
-    responses((status = 200, description = "Image generation"))
-)]
+fn parse_response_format(format: &str) -> Result<ImageGenerationResponseFormat> {
+    match format {
+        "url" | "Url" => Ok(ImageGenerationResponseFormat::Url),
+        "b64_json" | "B64Json" => Ok(ImageGenerationResponseFormat::B64Json),
+        other => {
+            anyhow::bail!("Unknown response format `{other}`. Possible formats: `url`, `b64_json`.")
+        }
+    }
+}
 
-pub async fn image_generation(
-    State(state): State<Arc<MistralRs>>,
-    Json(oairequest): Json<ImageGenerationRequest>,
-) -> ImageGenerationResponder {
-    let (tx, mut rx) = channel(10_000);
+/// Parse a `size` such as `1024x768` into `(height, width)`.
+fn parse_size(size: &str) -> Result<(usize, usize)> {
+    let Some((width, height)) = size.split_once('x') else {
+        anyhow::bail!("Expected a size of the form `<width>x<height>`, got `{size}`.");
+    };
+    Ok((height.trim().parse()?, width.trim().parse()?))
+}
+
+/// Parse a multipart image edit request. The `image` is edited according to the `prompt`, only in
+/// the area of the `mask` if one is given.
+async fn parse_edit_multipart(
+    mut multipart: Multipart,
+    state: Arc<MistralRs>,
+    tx: Sender<Response>,
+) -> Result<Request> {
+    let mut image = None;
+    let mut mask = None;
+    let mut prompt = None;
+    let mut size = None;
+    let mut n_choices = 1;
+    let mut format = ImageGenerationResponseFormat::Url;
+    let mut strength = None;
+    let mut params = DiffusionGenerationParams::default();
+    while let Some(field) = multipart.next_field().await? {
+        match field.name() {
+            Some("image" | "image[]") => {
+                image = Some(image::load_from_memory(&field.bytes().await?)?)
+            }
+            Some("mask") => mask = Some(image::load_from_memory(&field.bytes().await?)?),
+            Some("prompt") => prompt = Some(field.text().await?),
+            Some("n") => n_choices = field.text().await?.trim().parse()?,
+            Some("size") => size = Some(parse_size(&field.text().await?)?),
+            Some("response_format") => format = parse_response_format(field.text().await?.trim())?,
+            Some("num_steps") => params.num_steps = Some(field.text().await?.trim().parse()?),
+            Some("guidance_scale") => {
+                params.guidance_scale = Some(field.text().await?.trim().parse()?)
+            }
+            Some("seed") => params.seed = Some(field.text().await?.trim().parse()?),
+            Some("negative_prompt") => params.negative_prompt = Some(field.text().await?),
+            Some("true_cfg_scale") => params.true_cfg_scale = field.text().await?.trim().parse()?,
+            Some("strength") => strength = Some(field.text().await?.trim().parse()?),
+            // `model` is accepted for compatibility, the loaded model is always used.
+            _ => {}
+        }
+    }
+    let Some(image) = image else {
+        anyhow::bail!("Expected an `image` field containing the image to edit.");
+    };
+    let Some(prompt) = prompt else {
+        anyhow::bail!("Expected a `prompt` field.");
+    };
 
-    let request = match parse_request(oairequest, state.clone(), tx) {
+    let repr = format!(
+        "{{\"prompt\":{prompt:?},\"n\":{n_choices},\"params\":\"{params:?}\",\"has_mask\":{}}}",
+        mask.is_some()
+    );
+    MistralRs::maybe_log_request(state.clone(), repr);
+
+    // Default to the size of the image being edited.
+    (params.height, params.width) =
+        size.unwrap_or((image.height() as usize, image.width() as usize));
+    // The masked area is fully regenerated by default, like an OpenAI edit.
+    params.strength = strength.unwrap_or(if mask.is_some() { 1.0 } else { params.strength });
+    params.init_image = Some(image);
+    params.mask_image = mask.map(mask_from_image);
+
+    Ok(image_request(
+        state.next_request_id(),
+        prompt,
+        format,
+        n_choices,
+        params,
+        tx,
+    ))
+}
+
+async fn run_image_generation(
+    state: Arc<MistralRs>,
+    request: Result<Request>,
+    mut rx: Receiver<Response>,
+) -> ImageGenerationResponder {
+    let request = match request {
         Ok(x) => x,
         Err(e) => {
-            let e = anyhow::Error::msg(e.to_string());
             MistralRs::maybe_log_error(state, &*e);
-            return ImageGenerationResponder::InternalError(e.into());
+            return ImageGenerationResponder::ValidationError(e.into());
         }
     };
     let sender = state.get_sender().unwrap();
@@ -146,5 +273,63 @@ pub async fn image_generation(
         Response::Done(_) => unreachable!(),
         Response::ModelError(_, _) => unreachable!(),
         Response::Raw { .. } => unreachable!(),
+        Response::Transcription(_) => unreachable!(),
+        Response::Embedding(_) => unreachable!(),
+        Response::Rerank(_) => unreachable!(),
+        Response::Classification(_) => unreachable!(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    tag = "Mistral.rs",
+    path = "/v1/images/generations",
+    request_body = ImageGenerationRequest,
+    responses((status = 200, description = "Image generation"))
+)]
+
+pub async fn image_generation(
+    State(state): State<Arc<MistralRs>>,
+    Json(oairequest): Json<ImageGenerationRequest>,
+) -> ImageGenerationResponder {
+    let (tx, rx) = channel(10_000);
+    let request = parse_request(oairequest, state.clone(), tx).await;
+    run_image_generation(state, request, rx).await
+}
+
+#[utoipa::path(
+    post,
+    tag = "Mistral.rs",
+    path = "/v1/images/edits",
+    responses((status = 200, description = "Edit or inpaint an image"))
+)]
+pub async fn image_edit(
+    State(state): State<Arc<MistralRs>>,
+    multipart: Multipart,
+) -> ImageGenerationResponder {
+    let (tx, rx) = channel(10_000);
+    let request = parse_edit_multipart(multipart, state.clone(), tx).await;
+    run_image_generation(state, request, rx).await
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+    use super::{mask_from_image, parse_size};
+
+    #[test]
+    fn edit_size() {
+        assert_eq!(parse_size("1024x768").unwrap(), (768, 1024));
+        assert!(parse_size("1024").is_err());
+    }
+
+    #[test]
+    fn transparent_mask_area_is_edited() {
+        let mut mask = RgbaImage::from_pixel(2, 1, Rgba([0, 0, 0, 255]));
+        mask.put_pixel(1, 0, Rgba([0, 0, 0, 0]));
+        let mask = mask_from_image(DynamicImage::ImageRgba8(mask));
+        assert_eq!(mask.get_pixel(0, 0)[0], 0);
+        assert_eq!(mask.get_pixel(1, 0)[0], 255);
     }
 }
@@ -3,7 +3,8 @@ use indexmap::IndexMap;
 use mistralrs_core::{
     ChunkChoice, Constraint, Delta, DiffusionGenerationParams, DrySamplingParams,
     ImageGenerationResponseFormat, MessageContent, MistralRs, ModelCategory, NormalRequest,
-    Request, RequestMessage, Response, ResponseOk, SamplingParams, TERMINATE_ALL_NEXT_STEP,
+    Request, RequestMessage, RequestPriority, Response, ResponseOk, SamplingParams,
+    TranscriptionParams, TERMINATE_ALL_NEXT_STEP,
 };
 use once_cell::sync::Lazy;
 use regex::Regex;
@@ -34,6 +35,10 @@ pub async fn interactive_mode(mistralrs: Arc<MistralRs>, throughput: bool) {
         ModelCategory::Text => text_interactive_mode(mistralrs, throughput).await,
         ModelCategory::Vision { .. } => vision_interactive_mode(mistralrs, throughput).await,
         ModelCategory::Diffusion => diffusion_interactive_mode(mistralrs).await,
+        ModelCategory::Speech => speech_interactive_mode(mistralrs).await,
+        ModelCategory::Embedding | ModelCategory::Reranker => {
+            error!("Embedding and reranker models do not support interactive mode, use `--port`.")
+        }
     }
 }
 
@@ -73,6 +78,14 @@ Commands:
 - `\exit`: Quit interactive mode.
 "#;
 
+const SPEECH_INTERACTIVE_HELP: &str = r#"
+Welcome to interactive mode! Because this model is a speech to text model, you can enter the URL or local path of a WAV file and the model will transcribe it.
+
+Commands:
+- `\help`: Display this message.
+- `\exit`: Quit interactive mode.
+"#;
+
 const HELP_CMD: &str = "\\help";
 const EXIT_CMD: &str = "\\exit";
 const SYSTEM_CMD: &str = "\\system";
@@ -177,6 +190,10 @@ async fn text_interactive_mode(mistralrs: Arc<MistralRs>, throughput: bool) {
             tools: None,
             logits_processors: None,
             return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
         });
         sender.send(req).await.unwrap();
 
@@ -226,6 +243,10 @@ async fn text_interactive_mode(mistralrs: Arc<MistralRs>, throughput: bool) {
                 Response::CompletionModelError(_, _) => unreachable!(),
                 Response::CompletionChunk(_) => unreachable!(),
                 Response::ImageGeneration(_) => unreachable!(),
+                Response::Transcription(_) => unreachable!(),
+                Response::Embedding(_) => unreachable!(),
+                Response::Rerank(_) => unreachable!(),
+                Response::Classification(_) => unreachable!(),
                 Response::Raw { .. } => unreachable!(),
             }
         }
@@ -275,7 +296,11 @@ async fn vision_interactive_mode(mistralrs: Arc<MistralRs>, throughput: bool) {
     let mut images = Vec::new();
 
     let prefixer = match &mistralrs.config().category {
-        ModelCategory::Text | ModelCategory::Diffusion => {
+        ModelCategory::Text
+        | ModelCategory::Diffusion
+        | ModelCategory::Speech
+        | ModelCategory::Embedding
+        | ModelCategory::Reranker => {
             panic!("`add_image_message` expects a vision model.")
         }
         ModelCategory::Vision {
@@ -390,6 +415,8 @@ async fn vision_interactive_mode(mistralrs: Arc<MistralRs>, throughput: bool) {
 
         let request_messages = RequestMessage::VisionChat {
             images: images.clone(),
+            videos: vec![],
+            audios: vec![],
             messages: messages.clone(),
         };
 
@@ -408,6 +435,10 @@ async fn vision_interactive_mode(mistralrs: Arc<MistralRs>, throughput: bool) {
             tools: None,
             logits_processors: None,
             return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
         });
         sender.send(req).await.unwrap();
 
@@ -457,6 +488,10 @@ async fn vision_interactive_mode(mistralrs: Arc<MistralRs>, throughput: bool) {
                 Response::CompletionModelError(_, _) => unreachable!(),
                 Response::CompletionChunk(_) => unreachable!(),
                 Response::ImageGeneration(_) => unreachable!(),
+                Response::Transcription(_) => unreachable!(),
+                Response::Embedding(_) => unreachable!(),
+                Response::Rerank(_) => unreachable!(),
+                Response::Classification(_) => unreachable!(),
                 Response::Raw { .. } => unreachable!(),
             }
         }
@@ -541,6 +576,10 @@ async fn diffusion_interactive_mode(mistralrs: Arc<MistralRs>) {
             tools: None,
             logits_processors: None,
             return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
         });
 
         let start = Instant::now();
@@ -656,3 +695,115 @@ mod tests {
         );
     }
 }
+
+async fn speech_interactive_mode(mistralrs: Arc<MistralRs>) {
+    let sender = mistralrs.get_sender().unwrap();
+
+    let transcription_params = TranscriptionParams::default();
+
+    info!("Starting interactive loop with transcription params: {transcription_params:?}");
+    println!(
+        "{}{SPEECH_INTERACTIVE_HELP}{}",
+        "=".repeat(20),
+        "=".repeat(20)
+    );
+
+    // Set the handler to process exit
+    *CTRLC_HANDLER.lock().unwrap() = &exit_handler;
+
+    ctrlc::set_handler(move || CTRLC_HANDLER.lock().unwrap()())
+        .expect("Failed to set CTRL-C handler for interactive mode");
+
+    loop {
+        // Set the handler to process exit
+        *CTRLC_HANDLER.lock().unwrap() = &exit_handler;
+
+        let mut prompt = String::new();
+        print!("> ");
+        io::stdout().flush().unwrap();
+        io::stdin()
+            .read_line(&mut prompt)
+            .expect("Failed to get input");
+
+        let url = match prompt.as_str().trim() {
+            "" => continue,
+            HELP_CMD => {
+                println!(
+                    "{}{SPEECH_INTERACTIVE_HELP}{}",
+                    "=".repeat(20),
+                    "=".repeat(20)
+                );
+                continue;
+            }
+            EXIT_CMD => {
+                break;
+            }
+            url => url.to_string(),
+        };
+
+        let audio = match util::parse_audio_url(&url, None).await {
+            Ok(audio) => audio,
+            Err(e) => {
+                error!("Failed to load audio `{url}`: {e}");
+                continue;
+            }
+        };
+        let audio_secs = audio.duration_secs();
+
+        // Set the handler to terminate all seqs, so allowing cancelling running
+        *CTRLC_HANDLER.lock().unwrap() = &terminate_handler;
+
+        let (tx, mut rx) = channel(10_000);
+        let req = Request::Normal(NormalRequest {
+            id: 0,
+            messages: RequestMessage::Transcription {
+                audio,
+                params: transcription_params.clone(),
+            },
+            sampling_params: SamplingParams::deterministic(),
+            response: tx,
+            return_logprobs: false,
+            is_streaming: false,
+            suffix: None,
+            constraint: Constraint::None,
+            adapters: None,
+            tool_choice: None,
+            tools: None,
+            logits_processors: None,
+            return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
+        });
+
+        let start = Instant::now();
+        sender.send(req).await.unwrap();
+
+        let response = match rx.recv().await.unwrap().as_result() {
+            Ok(ResponseOk::Transcription(response)) => response,
+            Ok(_) => panic!("Got unexpected response type."),
+            Err(e) => {
+                error!("Got a transcription error: {e}");
+                continue;
+            }
+        };
+        let end = Instant::now();
+
+        let duration = end.duration_since(start).as_secs_f64();
+        let realtime_factor = audio_secs / duration;
+
+        for segment in &response.segments {
+            println!(
+                "[{:>7.2}s -> {:>7.2}s]{}",
+                segment.start, segment.end, segment.text
+            );
+        }
+        println!(
+            "Detected language: {}. Took {duration:.2}s ({realtime_factor:.2}x real time).",
+            response.language
+        );
+
+        println!();
+    }
+}
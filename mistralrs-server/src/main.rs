@@ -3,36 +3,54 @@ use axum::{
     extract::{DefaultBodyLimit, Json, State},
     http::{self, Method},
     routing::{get, post},
-    Router,
+    Extension, Router,
 };
 use candle_core::Device;
 use clap::Parser;
 use mistralrs_core::{
     get_auto_device_map_params, get_model_dtype, get_tgt_non_granular_index, initialize_logging,
     paged_attn_supported, parse_isq_value, DefaultSchedulerMethod, DeviceLayerMapMetadata,
-    DeviceMapMetadata, DeviceMapSetting, IsqType, Loader, LoaderBuilder, MemoryGpuConfig,
-    MistralRs, MistralRsBuilder, ModelSelected, PagedAttentionConfig, Request, SchedulerConfig,
-    TokenSource,
+    DeviceMapMetadata, DeviceMapSetting, IsqType, KvCacheType, Loader, LoaderBuilder,
+    MemoryGpuConfig, MistralRs, MistralRsBuilder, ModelSelected, PagedAttentionConfig, Request,
+    SchedulerConfig, TokenSource, TopologySearch,
 };
 use openai::{
-    ChatCompletionRequest, CompletionRequest, ImageGenerationRequest, Message, ModelObjects,
-    StopTokens,
+    ChatCompletionRequest, ClassificationInput, ClassificationInputs, ClassificationRequest,
+    CompletionRequest, EmbeddingEncodingFormat, EmbeddingInput, EmbeddingRequest,
+    ImageGenerationRequest, Message, ModelObjects, RerankRequest, StopTokens,
 };
 use serde::{Deserialize, Serialize};
-use std::{num::NonZeroUsize, sync::Arc};
+use std::{num::NonZeroUsize, path::PathBuf, sync::Arc};
 
+mod batch;
 mod chat_completion;
+mod classify;
 mod completions;
+mod conversation_store;
+mod embeddings;
 mod image_generation;
 mod interactive_mode;
 mod openai;
+mod rerank;
+mod transcription;
 mod util;
 
 use crate::openai::ModelObject;
 use crate::{
+    batch::{
+        __path_cancel_batch, __path_create_batch, __path_create_file, __path_get_batch,
+        __path_get_file, __path_get_file_content, __path_list_batches, batch_mode, cancel_batch,
+        create_batch, create_file, get_batch, get_file, get_file_content, list_batches, Batch,
+        BatchList, BatchState, BatchStatus, CreateBatchRequest, FileObject, RequestCounts,
+    },
     chat_completion::{__path_chatcompletions, chatcompletions},
+    classify::classify,
     completions::completions,
-    image_generation::image_generation,
+    conversation_store::ConversationStore,
+    embeddings::embeddings,
+    image_generation::{image_edit, image_generation},
+    rerank::rerank,
+    transcription::{transcription, translation},
 };
 
 use interactive_mode::interactive_mode;
@@ -148,6 +166,34 @@ struct Args {
     #[arg(long = "paged-attn", default_value_t = false)]
     paged_attn: bool,
 
+    /// Storage type of the KV cache: `auto` (activation dtype) or `f8e4m3`.
+    /// `f8e4m3` requires PagedAttention on CUDA.
+    #[arg(long = "kv-cache-type", default_value_t = KvCacheType::Auto)]
+    kv_cache_type: KvCacheType,
+
+    /// Search for a per-layer ISQ topology whose decoder layers fit in this many GB, using the
+    /// calibration file to measure the sensitivity of each layer. Requires a calibration file.
+    #[arg(long = "search-topology-budget")]
+    search_topology_budget: Option<f64>,
+
+    /// Comma-separated ISQ types the topology search may assign to a layer.
+    #[arg(
+        long = "search-topology-isq",
+        value_parser = parse_isq_value,
+        value_delimiter = ',',
+        default_value = "q4k,q6k,q8_0"
+    )]
+    search_topology_isq: Vec<IsqType>,
+
+    /// Where to write the topology found by the topology search.
+    #[arg(long = "search-topology-out", default_value = "topology.yml")]
+    search_topology_out: String,
+
+    /// Embed the residual tensors, config and tokenizer in the UQFF file written with `--write-uqff`,
+    /// so that it can be loaded offline from that single file with `--from-uqff`.
+    #[arg(long = "self-contained-uqff", default_value_t = false)]
+    self_contained_uqff: bool,
+
     /// Enable server throughput logging, supported in the server and with interactive mode
     #[arg(long = "throughput", default_value_t = false)]
     throughput_log: bool,
@@ -159,6 +205,37 @@ struct Args {
     /// Use CPU only
     #[arg(long)]
     cpu: bool,
+
+    /// Store conversations on the server so that chat completion requests can continue them with
+    /// `previous_response_id` instead of re-sending the history.
+    #[arg(long = "conversation-store", default_value_t = false)]
+    conversation_store: bool,
+
+    /// Directory to persist stored conversations to, so they survive restarts. Implies `--conversation-store`.
+    #[arg(long = "conversation-store-dir")]
+    conversation_store_dir: Option<String>,
+
+    /// Maximum number of stored conversations to keep in memory. The oldest ones are dropped first,
+    /// but may still be loaded from `--conversation-store-dir`.
+    #[arg(long = "conversation-store-size", default_value_t = 1024)]
+    conversation_store_size: usize,
+
+    /// Run the requests of this batch input file (OpenAI batch JSONL format) and exit instead of serving.
+    #[arg(long = "batch-input", requires = "batch_output")]
+    batch_input: Option<String>,
+
+    /// File to write the results of `--batch-input` to, one JSON line per request in input order.
+    #[arg(long = "batch-output", requires = "batch_input")]
+    batch_output: Option<String>,
+
+    /// Maximum number of requests of a batch to run at once. Batch requests also have a low priority, so the
+    /// scheduler serves interactive requests first.
+    #[arg(long = "batch-concurrency", default_value_t = 4)]
+    batch_concurrency: usize,
+
+    /// Directory to store files uploaded to `/v1/files` and batch results in. Defaults to a temporary directory.
+    #[arg(long = "batch-dir")]
+    batch_dir: Option<String>,
 }
 
 #[utoipa::path(
@@ -237,12 +314,16 @@ async fn re_isq(
     Ok(repr)
 }
 
-fn get_router(state: Arc<MistralRs>) -> Router {
+fn get_router(
+    state: Arc<MistralRs>,
+    conversation_store: Option<Arc<ConversationStore>>,
+    batch_state: Arc<BatchState>,
+) -> Router {
     #[derive(OpenApi)]
     #[openapi(
-        paths(models, health, chatcompletions),
+        paths(models, health, chatcompletions, create_file, get_file, get_file_content, create_batch, get_batch, list_batches, cancel_batch),
         components(
-            schemas(ModelObjects, ModelObject, ChatCompletionRequest, CompletionRequest, ImageGenerationRequest, StopTokens, Message)),
+            schemas(ModelObjects, ModelObject, ChatCompletionRequest, CompletionRequest, ImageGenerationRequest, EmbeddingRequest, EmbeddingInput, EmbeddingEncodingFormat, RerankRequest, ClassificationRequest, ClassificationInputs, ClassificationInput, StopTokens, Message, FileObject, Batch, BatchStatus, RequestCounts, CreateBatchRequest, BatchList)),
         tags(
             (name = "Mistral.rs", description = "Mistral.rs API")
         ),
@@ -273,8 +354,22 @@ fn get_router(state: Arc<MistralRs>) -> Router {
         .route("/activate_adapters", post(activate_adapters))
         .route("/re_isq", post(re_isq))
         .route("/v1/images/generations", post(image_generation))
+        .route("/v1/images/edits", post(image_edit))
+        .route("/v1/audio/transcriptions", post(transcription))
+        .route("/v1/audio/translations", post(translation))
+        .route("/v1/embeddings", post(embeddings))
+        .route("/v1/rerank", post(rerank))
+        .route("/v1/classify", post(classify))
+        .route("/v1/files", post(create_file))
+        .route("/v1/files/:file_id", get(get_file))
+        .route("/v1/files/:file_id/content", get(get_file_content))
+        .route("/v1/batches", post(create_batch).get(list_batches))
+        .route("/v1/batches/:batch_id", get(get_batch))
+        .route("/v1/batches/:batch_id/cancel", post(cancel_batch))
         .layer(cors_layer)
         .layer(DefaultBodyLimit::max(N_INPUT_SIZE * MB_TO_B))
+        .layer(Extension(conversation_store))
+        .layer(Extension(batch_state))
         .with_state(state)
 }
 
@@ -283,7 +378,7 @@ async fn main() -> Result<()> {
     let mut args = Args::parse();
     initialize_logging();
 
-    let setting_server = if !args.interactive_mode {
+    let setting_server = if !args.interactive_mode && args.batch_input.is_none() {
         let port = args.port.expect("Interactive mode was not specified, so expected port to be specified. Perhaps you forgot `-i` or `--port`?");
         let ip = args.serve_ip.unwrap_or_else(|| "0.0.0.0".to_string());
 
@@ -312,11 +407,22 @@ async fn main() -> Result<()> {
         None => None,
     };
 
+    let topology_search = args.search_topology_budget.map(|budget| {
+        TopologySearch::new(
+            (budget * 1e9) as usize,
+            args.search_topology_isq.clone(),
+            args.search_topology_out.clone().into(),
+        )
+    });
+
     let loader: Box<dyn Loader> = LoaderBuilder::new(args.model)
         .with_no_kv_cache(args.no_kv_cache)
         .with_chat_template(args.chat_template)
         .with_use_flash_attn(use_flash_attn)
         .with_prompt_chunksize(prompt_chunksize)
+        .with_kv_cache_type(args.kv_cache_type)
+        .with_topology_search(topology_search)
+        .with_self_contained_uqff(args.self_contained_uqff)
         .build()?;
 
     #[cfg(feature = "metal")]
@@ -500,7 +606,34 @@ async fn main() -> Result<()> {
     };
     let mistralrs = builder.build();
 
-    let app = get_router(mistralrs);
+    if let (Some(input), Some(output)) = (args.batch_input, args.batch_output) {
+        batch_mode(
+            mistralrs,
+            PathBuf::from(input),
+            PathBuf::from(output),
+            args.batch_concurrency,
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let conversation_store = if args.conversation_store || args.conversation_store_dir.is_some() {
+        Some(Arc::new(ConversationStore::new(
+            args.conversation_store_size,
+            args.conversation_store_dir.map(PathBuf::from),
+        )?))
+    } else {
+        None
+    };
+
+    let batch_state = Arc::new(BatchState::new(
+        args.batch_dir
+            .map(PathBuf::from)
+            .unwrap_or_else(|| std::env::temp_dir().join("mistralrs-batches")),
+        args.batch_concurrency,
+    )?);
+
+    let app = get_router(mistralrs, conversation_store, batch_state);
     if let Some((listener, ip, port)) = setting_server {
         info!("Serving on http://{ip}:{}.", port);
         axum::serve(listener, app).await?;
@@ -1,21 +1,28 @@
 use either::Either;
 use mistralrs_core::{ImageGenerationResponseFormat, LlguidanceGrammar, Tool, ToolChoice};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, ops::Deref};
+use serde_json::Value;
+use std::{borrow::Borrow, collections::HashMap, ops::Deref};
 use utoipa::ToSchema;
 
 #[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct MessageInnerContent(
-    #[serde(with = "either::serde_untagged")] Either<String, HashMap<String, String>>,
+    #[serde(with = "either::serde_untagged")] Either<String, HashMap<String, Value>>,
 );
 
 impl Deref for MessageInnerContent {
-    type Target = Either<String, HashMap<String, String>>;
+    type Target = Either<String, HashMap<String, Value>>;
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 
+impl Borrow<Either<String, HashMap<String, Value>>> for MessageInnerContent {
+    fn borrow(&self) -> &Either<String, HashMap<String, Value>> {
+        &self.0
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct MessageContent(
     #[serde(with = "either::serde_untagged")]
@@ -29,6 +36,12 @@ impl Deref for MessageContent {
     }
 }
 
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        Self(Either::Left(text))
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct Message {
     pub content: MessageContent,
@@ -59,10 +72,22 @@ fn default_1280usize() -> usize {
     1280
 }
 
+fn default_true() -> bool {
+    true
+}
+
 fn default_model() -> String {
     "default".to_string()
 }
 
+fn default_true_cfg_scale() -> f64 {
+    4.0
+}
+
+fn default_strength() -> f64 {
+    0.6
+}
+
 fn default_response_format() -> ImageGenerationResponseFormat {
     ImageGenerationResponseFormat::Url
 }
@@ -136,6 +161,16 @@ pub struct ChatCompletionRequest {
     pub dry_allowed_length: Option<usize>,
     #[schema(example = json!(Option::None::<String>))]
     pub dry_sequence_breakers: Option<Vec<String>>,
+    #[schema(example = json!(Option::None::<HashMap<String, serde_json::Value>>))]
+    pub chat_template_kwargs: Option<HashMap<String, serde_json::Value>>,
+    #[serde(default = "default_false")]
+    #[schema(example = false)]
+    pub continue_final_message: bool,
+    #[schema(example = json!(Option::None::<String>))]
+    pub previous_response_id: Option<String>,
+    #[serde(default = "default_false")]
+    #[schema(example = false)]
+    pub store: bool,
 }
 
 #[derive(Debug, Serialize, ToSchema)]
@@ -234,4 +269,101 @@ pub struct ImageGenerationRequest {
     #[serde(default = "default_1280usize")]
     #[schema(example = 1280)]
     pub width: usize,
+    /// Number of denoising steps. Defaults to the model default.
+    #[schema(example = json!(Option::None::<usize>))]
+    pub num_steps: Option<usize>,
+    /// Distilled guidance scale, for models with guidance distillation such as FLUX.1-dev.
+    /// For Stable Diffusion, this is the classifier-free guidance scale.
+    #[schema(example = json!(Option::None::<f64>))]
+    pub guidance_scale: Option<f64>,
+    /// Seed of the initial noise. The `n` images use consecutive seeds.
+    #[schema(example = json!(Option::None::<u64>))]
+    pub seed: Option<u64>,
+    /// Steer the image away from this prompt with classifier-free guidance.
+    #[schema(example = json!(Option::None::<String>))]
+    pub negative_prompt: Option<String>,
+    #[serde(default = "default_true_cfg_scale")]
+    #[schema(example = 4.0)]
+    pub true_cfg_scale: f64,
+    /// Image to start from (img2img), as a URL, path or base64 encoded string.
+    #[schema(example = json!(Option::None::<String>))]
+    pub image: Option<String>,
+    /// How much the `image` is changed, from 0 (unchanged) to 1 (fully regenerated).
+    #[serde(default = "default_strength")]
+    #[schema(example = 0.6)]
+    pub strength: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    Multi(Vec<String>),
+    Single(String),
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, ToSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingEncodingFormat {
+    #[default]
+    Float,
+    /// Little-endian f32 values, base64 encoded.
+    Base64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct EmbeddingRequest {
+    #[schema(example = "mistral")]
+    #[serde(default = "default_model")]
+    pub model: String,
+    #[schema(example = json!(["The food was delicious and the waiter..."]))]
+    pub input: EmbeddingInput,
+    #[serde(default)]
+    pub encoding_format: EmbeddingEncodingFormat,
+    #[schema(example = json!(Option::None::<usize>))]
+    pub dimensions: Option<usize>,
+    #[serde(rename = "user")]
+    pub _user: Option<String>,
+
+    // mistral.rs additional
+    #[serde(default = "default_true")]
+    pub normalize: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct RerankRequest {
+    #[schema(example = "mistral")]
+    #[serde(default = "default_model")]
+    pub model: String,
+    #[schema(example = "What is the capital of France?")]
+    pub query: String,
+    #[schema(example = json!(["Paris is the capital of France.", "Berlin is in Germany."]))]
+    pub documents: Vec<String>,
+    #[schema(example = json!(Option::None::<usize>))]
+    pub top_n: Option<usize>,
+    #[serde(default = "default_false")]
+    pub return_documents: bool,
+}
+
+/// A text, or a conversation which is formatted with the chat template of the model.
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum ClassificationInput {
+    Text(String),
+    Conversation(Vec<Message>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum ClassificationInputs {
+    Multi(Vec<ClassificationInput>),
+    Single(ClassificationInput),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct ClassificationRequest {
+    #[schema(example = "mistral")]
+    #[serde(default = "default_model")]
+    pub model: String,
+    #[schema(example = json!(["I love this movie!", "The service was terribly slow."]))]
+    pub input: ClassificationInputs,
 }
@@ -0,0 +1,160 @@
+use anyhow::Result;
+use std::{error::Error, sync::Arc};
+use tokio::sync::mpsc::{channel, Sender};
+
+use crate::openai::RerankRequest;
+use axum::{
+    extract::{Json, State},
+    http::{self, StatusCode},
+    response::IntoResponse,
+};
+use mistralrs_core::{
+    Constraint, MistralRs, NormalRequest, Request, RequestMessage, RequestPriority, RerankParams,
+    RerankResponse, Response, SamplingParams,
+};
+use serde::Serialize;
+
+pub enum RerankResponder {
+    Json(RerankResponse),
+    InternalError(Box<dyn Error>),
+    ValidationError(Box<dyn Error>),
+}
+
+trait ErrorToResponse: Serialize {
+    fn to_response(&self, code: StatusCode) -> axum::response::Response {
+        let mut r = Json(self).into_response();
+        *r.status_mut() = code;
+        r
+    }
+}
+
+#[derive(Serialize)]
+struct JsonError {
+    message: String,
+}
+
+impl JsonError {
+    fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+impl ErrorToResponse for JsonError {}
+
+impl IntoResponse for RerankResponder {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            RerankResponder::Json(s) => Json(s).into_response(),
+            RerankResponder::InternalError(e) => {
+                JsonError::new(e.to_string()).to_response(http::StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            RerankResponder::ValidationError(e) => {
+                JsonError::new(e.to_string()).to_response(http::StatusCode::UNPROCESSABLE_ENTITY)
+            }
+        }
+    }
+}
+
+fn parse_request(
+    oairequest: RerankRequest,
+    state: Arc<MistralRs>,
+    tx: Sender<Response>,
+) -> Result<Request> {
+    let repr = serde_json::to_string(&oairequest).expect("Serialization of request failed.");
+    MistralRs::maybe_log_request(state.clone(), repr);
+
+    if oairequest.documents.is_empty() {
+        anyhow::bail!("`documents` must contain at least one document.");
+    }
+
+    Ok(Request::Normal(NormalRequest {
+        id: state.next_request_id(),
+        messages: RequestMessage::Rerank {
+            query: oairequest.query,
+            documents: oairequest.documents,
+            params: RerankParams {
+                top_n: oairequest.top_n,
+                return_documents: oairequest.return_documents,
+            },
+        },
+        sampling_params: SamplingParams::deterministic(),
+        response: tx,
+        return_logprobs: false,
+        is_streaming: false,
+        suffix: None,
+        constraint: Constraint::None,
+        adapters: None,
+        tool_choice: None,
+        tools: None,
+        logits_processors: None,
+        return_raw_logits: false,
+        chat_template_kwargs: None,
+        continue_final_message: false,
+        conversation: None,
+        priority: RequestPriority::Normal,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    tag = "Mistral.rs",
+    path = "/v1/rerank",
+    request_body = RerankRequest,
+    responses((status = 200, description = "Rerank documents by relevance to a query"))
+)]
+pub async fn rerank(
+    State(state): State<Arc<MistralRs>>,
+    Json(oairequest): Json<RerankRequest>,
+) -> RerankResponder {
+    let (tx, mut rx) = channel(10_000);
+
+    let request = match parse_request(oairequest, state.clone(), tx) {
+        Ok(x) => x,
+        Err(e) => {
+            MistralRs::maybe_log_error(state, &*e);
+            return RerankResponder::ValidationError(e.into());
+        }
+    };
+    let sender = state.get_sender().unwrap();
+
+    if let Err(e) = sender.send(request).await {
+        let e = anyhow::Error::msg(e.to_string());
+        MistralRs::maybe_log_error(state, &*e);
+        return RerankResponder::InternalError(e.into());
+    }
+
+    let response = match rx.recv().await {
+        Some(response) => response,
+        None => {
+            let e = anyhow::Error::msg("No response received from the model.");
+            MistralRs::maybe_log_error(state, &*e);
+            return RerankResponder::InternalError(e.into());
+        }
+    };
+
+    match response {
+        Response::InternalError(e) => {
+            MistralRs::maybe_log_error(state, &*e);
+            RerankResponder::InternalError(e)
+        }
+        Response::ValidationError(e) => RerankResponder::ValidationError(e),
+        Response::Rerank(response) => {
+            MistralRs::maybe_log_response(state, &response);
+            RerankResponder::Json(response)
+        }
+        Response::CompletionModelError(m, _) => {
+            let e = anyhow::Error::msg(m.to_string());
+            MistralRs::maybe_log_error(state, &*e);
+            RerankResponder::InternalError(e.into())
+        }
+        Response::CompletionDone(_) => unreachable!(),
+        Response::CompletionChunk(_) => unreachable!(),
+        Response::Chunk(_) => unreachable!(),
+        Response::Done(_) => unreachable!(),
+        Response::ModelError(_, _) => unreachable!(),
+        Response::ImageGeneration(_) => unreachable!(),
+        Response::Transcription(_) => unreachable!(),
+        Response::Embedding(_) => unreachable!(),
+        Response::Classification(_) => unreachable!(),
+        Response::Raw { .. } => unreachable!(),
+    }
+}
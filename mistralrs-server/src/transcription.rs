@@ -0,0 +1,337 @@
+use anyhow::Result;
+use std::{error::Error, str::FromStr, sync::Arc};
+use tokio::sync::mpsc::{channel, Sender};
+
+use axum::{
+    extract::{Json, Multipart, State},
+    http::{self, header, StatusCode},
+    response::IntoResponse,
+};
+use mistralrs_core::{
+    AudioInput, Constraint, MistralRs, NormalRequest, Request, RequestMessage, RequestPriority,
+    Response, SamplingParams, TranscriptionParams, TranscriptionResponse, TranscriptionTask,
+};
+use serde::Serialize;
+
+/// The `response_format` of an OpenAI transcription request.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum TranscriptionResponseFormat {
+    #[default]
+    Json,
+    Text,
+    VerboseJson,
+    Srt,
+    Vtt,
+}
+
+impl FromStr for TranscriptionResponseFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "text" => Ok(Self::Text),
+            "verbose_json" => Ok(Self::VerboseJson),
+            "srt" => Ok(Self::Srt),
+            "vtt" => Ok(Self::Vtt),
+            a => Err(format!(
+                "Unknown response format `{a}`. Possible formats: `json`, `text`, `verbose_json`, `srt`, `vtt`."
+            )),
+        }
+    }
+}
+
+pub enum TranscriptionResponder {
+    Json(TranscriptionResponse),
+    VerboseJson(TranscriptionResponse),
+    Text(String),
+    Subtitles(&'static str, String),
+    InternalError(Box<dyn Error>),
+    ValidationError(Box<dyn Error>),
+}
+
+trait ErrorToResponse: Serialize {
+    fn to_response(&self, code: StatusCode) -> axum::response::Response {
+        let mut r = Json(self).into_response();
+        *r.status_mut() = code;
+        r
+    }
+}
+
+#[derive(Serialize)]
+struct JsonError {
+    message: String,
+}
+
+impl JsonError {
+    fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+impl ErrorToResponse for JsonError {}
+
+#[derive(Serialize)]
+struct TextOnly {
+    text: String,
+}
+
+impl IntoResponse for TranscriptionResponder {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            TranscriptionResponder::Json(s) => Json(TextOnly { text: s.text }).into_response(),
+            TranscriptionResponder::VerboseJson(s) => Json(s).into_response(),
+            TranscriptionResponder::Text(s) => s.into_response(),
+            TranscriptionResponder::Subtitles(content_type, s) => {
+                ([(header::CONTENT_TYPE, content_type)], s).into_response()
+            }
+            TranscriptionResponder::InternalError(e) => {
+                JsonError::new(e.to_string()).to_response(http::StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            TranscriptionResponder::ValidationError(e) => {
+                JsonError::new(e.to_string()).to_response(http::StatusCode::UNPROCESSABLE_ENTITY)
+            }
+        }
+    }
+}
+
+/// Format seconds as `HH:MM:SS<sep>mmm`, as used by SRT (`,`) and WebVTT (`.`).
+fn format_timestamp(secs: f64, sep: char) -> String {
+    let millis = (secs.max(0.) * 1000.).round() as u64;
+    let (hours, millis) = (millis / 3_600_000, millis % 3_600_000);
+    let (minutes, millis) = (millis / 60_000, millis % 60_000);
+    let (seconds, millis) = (millis / 1000, millis % 1000);
+    format!("{hours:02}:{minutes:02}:{seconds:02}{sep}{millis:03}")
+}
+
+fn to_srt(response: &TranscriptionResponse) -> String {
+    response
+        .segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            format!(
+                "{}\n{} --> {}\n{}\n\n",
+                i + 1,
+                format_timestamp(segment.start, ','),
+                format_timestamp(segment.end, ','),
+                segment.text.trim()
+            )
+        })
+        .collect()
+}
+
+fn to_vtt(response: &TranscriptionResponse) -> String {
+    let mut vtt = "WEBVTT\n\n".to_string();
+    for segment in &response.segments {
+        vtt.push_str(&format!(
+            "{} --> {}\n{}\n\n",
+            format_timestamp(segment.start, '.'),
+            format_timestamp(segment.end, '.'),
+            segment.text.trim()
+        ));
+    }
+    vtt
+}
+
+/// The fields of a multipart transcription or translation request.
+struct TranscriptionRequest {
+    audio: AudioInput,
+    params: TranscriptionParams,
+    format: TranscriptionResponseFormat,
+}
+
+async fn parse_multipart(
+    mut multipart: Multipart,
+    task: TranscriptionTask,
+) -> Result<TranscriptionRequest> {
+    let mut audio = None;
+    let mut params = TranscriptionParams {
+        task,
+        ..Default::default()
+    };
+    let mut format = TranscriptionResponseFormat::default();
+    while let Some(field) = multipart.next_field().await? {
+        match field.name() {
+            Some("file") => audio = Some(AudioInput::from_wav_bytes(&field.bytes().await?)?),
+            Some("language") if task == TranscriptionTask::Transcribe => {
+                params.language = Some(field.text().await?)
+            }
+            Some("prompt") => params.prompt = Some(field.text().await?),
+            Some("temperature") => params.temperature = field.text().await?.trim().parse()?,
+            Some("response_format") => {
+                format = field.text().await?.parse().map_err(anyhow::Error::msg)?
+            }
+            Some("timestamp_granularities[]" | "timestamp_granularities") => {
+                let granularity = field.text().await?;
+                if granularity != "segment" {
+                    anyhow::bail!(
+                        "Unsupported timestamp granularity `{granularity}`, only `segment` is supported."
+                    );
+                }
+            }
+            // `model` is accepted for compatibility, the loaded model is always used.
+            _ => {}
+        }
+    }
+    let Some(audio) = audio else {
+        anyhow::bail!("Expected a `file` field containing a WAV file.");
+    };
+    // Timestamps are only needed to split the transcript into timed segments.
+    params.timestamps = matches!(
+        format,
+        TranscriptionResponseFormat::VerboseJson
+            | TranscriptionResponseFormat::Srt
+            | TranscriptionResponseFormat::Vtt
+    );
+    Ok(TranscriptionRequest {
+        audio,
+        params,
+        format,
+    })
+}
+
+fn parse_request(
+    request: TranscriptionRequest,
+    state: Arc<MistralRs>,
+    tx: Sender<Response>,
+) -> Request {
+    let repr = format!(
+        "{{\"params\":\"{:?}\",\"audio_secs\":{}}}",
+        request.params,
+        request.audio.duration_secs()
+    );
+    MistralRs::maybe_log_request(state.clone(), repr);
+
+    Request::Normal(NormalRequest {
+        id: state.next_request_id(),
+        messages: RequestMessage::Transcription {
+            audio: request.audio,
+            params: request.params,
+        },
+        sampling_params: SamplingParams::deterministic(),
+        response: tx,
+        return_logprobs: false,
+        is_streaming: false,
+        suffix: None,
+        constraint: Constraint::None,
+        adapters: None,
+        tool_choice: None,
+        tools: None,
+        logits_processors: None,
+        return_raw_logits: false,
+        chat_template_kwargs: None,
+        continue_final_message: false,
+        conversation: None,
+        priority: RequestPriority::Normal,
+    })
+}
+
+async fn run_transcription(
+    state: Arc<MistralRs>,
+    multipart: Multipart,
+    task: TranscriptionTask,
+) -> TranscriptionResponder {
+    let request = match parse_multipart(multipart, task).await {
+        Ok(x) => x,
+        Err(e) => {
+            MistralRs::maybe_log_error(state, &*e);
+            return TranscriptionResponder::ValidationError(e.into());
+        }
+    };
+    let format = request.format;
+
+    let (tx, mut rx) = channel(10_000);
+    let request = parse_request(request, state.clone(), tx);
+    let sender = state.get_sender().unwrap();
+
+    if let Err(e) = sender.send(request).await {
+        let e = anyhow::Error::msg(e.to_string());
+        MistralRs::maybe_log_error(state, &*e);
+        return TranscriptionResponder::InternalError(e.into());
+    }
+
+    let response = match rx.recv().await {
+        Some(response) => response,
+        None => {
+            let e = anyhow::Error::msg("No response received from the model.");
+            MistralRs::maybe_log_error(state, &*e);
+            return TranscriptionResponder::InternalError(e.into());
+        }
+    };
+
+    match response {
+        Response::InternalError(e) => {
+            MistralRs::maybe_log_error(state, &*e);
+            TranscriptionResponder::InternalError(e)
+        }
+        Response::ValidationError(e) => TranscriptionResponder::ValidationError(e),
+        Response::Transcription(response) => {
+            MistralRs::maybe_log_response(state, &response);
+            match format {
+                TranscriptionResponseFormat::Json => TranscriptionResponder::Json(response),
+                TranscriptionResponseFormat::VerboseJson => {
+                    TranscriptionResponder::VerboseJson(response)
+                }
+                TranscriptionResponseFormat::Text => TranscriptionResponder::Text(response.text),
+                TranscriptionResponseFormat::Srt => {
+                    TranscriptionResponder::Subtitles("application/x-subrip", to_srt(&response))
+                }
+                TranscriptionResponseFormat::Vtt => {
+                    TranscriptionResponder::Subtitles("text/vtt", to_vtt(&response))
+                }
+            }
+        }
+        Response::CompletionModelError(m, _) => {
+            let e = anyhow::Error::msg(m.to_string());
+            MistralRs::maybe_log_error(state, &*e);
+            TranscriptionResponder::InternalError(e.into())
+        }
+        Response::CompletionDone(_) => unreachable!(),
+        Response::CompletionChunk(_) => unreachable!(),
+        Response::Chunk(_) => unreachable!(),
+        Response::Done(_) => unreachable!(),
+        Response::ModelError(_, _) => unreachable!(),
+        Response::ImageGeneration(_) => unreachable!(),
+        Response::Raw { .. } => unreachable!(),
+        Response::Embedding(_) => unreachable!(),
+        Response::Rerank(_) => unreachable!(),
+        Response::Classification(_) => unreachable!(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    tag = "Mistral.rs",
+    path = "/v1/audio/transcriptions",
+    responses((status = 200, description = "Transcribe a WAV file"))
+)]
+pub async fn transcription(
+    State(state): State<Arc<MistralRs>>,
+    multipart: Multipart,
+) -> TranscriptionResponder {
+    run_transcription(state, multipart, TranscriptionTask::Transcribe).await
+}
+
+#[utoipa::path(
+    post,
+    tag = "Mistral.rs",
+    path = "/v1/audio/translations",
+    responses((status = 200, description = "Translate the speech in a WAV file to English"))
+)]
+pub async fn translation(
+    State(state): State<Arc<MistralRs>>,
+    multipart: Multipart,
+) -> TranscriptionResponder {
+    run_transcription(state, multipart, TranscriptionTask::Translate).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_timestamp;
+
+    #[test]
+    fn subtitle_timestamps() {
+        assert_eq!(format_timestamp(0., ','), "00:00:00,000");
+        assert_eq!(format_timestamp(3725.5, ','), "01:02:05,500");
+        assert_eq!(format_timestamp(61.0004, '.'), "00:01:01.000");
+    }
+}
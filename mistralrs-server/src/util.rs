@@ -1,10 +1,12 @@
 use image::DynamicImage;
+use mistralrs_core::{AudioInput, VideoInput};
 use tokio::{
     fs::{self, File},
     io::AsyncReadExt,
 };
 
-pub async fn parse_image_url(url_unparsed: &str) -> Result<DynamicImage, anyhow::Error> {
+/// Read the bytes of an http(s) URL, a local file path, a `data:` URL or raw base64 data.
+async fn read_url_bytes(url_unparsed: &str) -> Result<Vec<u8>, anyhow::Error> {
     let url = if let Ok(url) = url::Url::parse(url_unparsed) {
         url
     } else if File::open(url_unparsed).await.is_ok() {
@@ -43,7 +45,30 @@ pub async fn parse_image_url(url_unparsed: &str) -> Result<DynamicImage, anyhow:
         anyhow::bail!("Unsupported URL scheme: {}", url.scheme());
     };
 
-    Ok(image::load_from_memory(&bytes)?)
+    Ok(bytes)
+}
+
+pub async fn parse_image_url(url_unparsed: &str) -> Result<DynamicImage, anyhow::Error> {
+    Ok(image::load_from_memory(
+        &read_url_bytes(url_unparsed).await?,
+    )?)
+}
+
+/// Load an animated GIF, PNG or WebP clip as a video.
+pub async fn parse_video_url(url_unparsed: &str) -> Result<VideoInput, anyhow::Error> {
+    VideoInput::from_animation_bytes(&read_url_bytes(url_unparsed).await?)
+}
+
+/// Load a WAV file, or raw 16-bit mono PCM at `pcm16_sample_rate` if it is given.
+pub async fn parse_audio_url(
+    url_unparsed: &str,
+    pcm16_sample_rate: Option<u32>,
+) -> Result<AudioInput, anyhow::Error> {
+    let bytes = read_url_bytes(url_unparsed).await?;
+    match pcm16_sample_rate {
+        Some(sample_rate) => AudioInput::from_pcm16_bytes(&bytes, sample_rate, 1),
+        None => AudioInput::from_wav_bytes(&bytes),
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,53 @@
+use anyhow::Result;
+use mistralrs::{
+    ClassificationInput, EmbeddingLoaderType, EmbeddingModelBuilder, TextMessageRole, TextMessages,
+};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let classifier = EmbeddingModelBuilder::new(
+        "cardiffnlp/twitter-roberta-base-sentiment-latest",
+        EmbeddingLoaderType::XLMRoberta,
+    )
+    .with_logging()
+    .build()
+    .await?;
+
+    let response = classifier
+        .classify(vec![
+            ClassificationInput::Text("I love this movie!".to_string()),
+            ClassificationInput::Text("The service was terribly slow.".to_string()),
+        ])
+        .await?;
+    for result in response.data {
+        println!("{}: {:?}", result.label, result.probs);
+    }
+
+    // A reward model scores whole conversations, which are formatted with its chat template.
+    let reward_model = EmbeddingModelBuilder::new(
+        "Skywork/Skywork-Reward-Llama-3.1-8B-v0.2",
+        EmbeddingLoaderType::Llama,
+    )
+    .with_logging()
+    .build()
+    .await?;
+
+    let question = "What is 2 + 2?";
+    let response = reward_model
+        .classify(vec![
+            TextMessages::new()
+                .add_message(TextMessageRole::User, question)
+                .add_message(TextMessageRole::Assistant, "2 + 2 = 4.")
+                .into(),
+            TextMessages::new()
+                .add_message(TextMessageRole::User, question)
+                .add_message(TextMessageRole::Assistant, "2 + 2 = 5.")
+                .into(),
+        ])
+        .await?;
+    for result in response.data {
+        println!("Reward of answer {}: {:?}", result.index, result.reward);
+    }
+
+    Ok(())
+}
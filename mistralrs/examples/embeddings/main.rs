@@ -0,0 +1,58 @@
+use anyhow::Result;
+use mistralrs::{EmbeddingLoaderType, EmbeddingModelBuilder, EmbeddingParams, RerankParams};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let query = "How do I make my model run faster?";
+    let documents = vec![
+        "Quantizing the weights reduces memory bandwidth and speeds up decoding.".to_string(),
+        "The Eiffel Tower is in Paris.".to_string(),
+        "PagedAttention allows larger batches by managing the KV cache in blocks.".to_string(),
+    ];
+
+    let embedder = EmbeddingModelBuilder::new("BAAI/bge-small-en-v1.5", EmbeddingLoaderType::Bert)
+        .with_logging()
+        .build()
+        .await?;
+
+    let mut inputs = vec![query.to_string()];
+    inputs.extend(documents.iter().cloned());
+    let response = embedder.embed(inputs, EmbeddingParams::default()).await?;
+
+    // The embeddings are normalized, so the dot product is the cosine similarity.
+    let query_embedding = &response.data[0].embedding;
+    for (document, data) in documents.iter().zip(&response.data[1..]) {
+        let similarity: f32 = query_embedding
+            .iter()
+            .zip(&data.embedding)
+            .map(|(a, b)| a * b)
+            .sum();
+        println!("{similarity:.3}: {document}");
+    }
+
+    let reranker =
+        EmbeddingModelBuilder::new("BAAI/bge-reranker-base", EmbeddingLoaderType::XLMRoberta)
+            .with_logging()
+            .build()
+            .await?;
+
+    let response = reranker
+        .rerank(
+            query,
+            documents,
+            RerankParams {
+                top_n: Some(2),
+                return_documents: true,
+            },
+        )
+        .await?;
+    for result in response.results {
+        println!(
+            "{:.3}: {}",
+            result.relevance_score,
+            result.document.map(|d| d.text).unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
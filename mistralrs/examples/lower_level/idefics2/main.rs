@@ -73,6 +73,8 @@ fn main() -> anyhow::Result<()> {
     let request = Request::Normal(NormalRequest {
         messages: RequestMessage::VisionChat {
             images: vec![image],
+            videos: vec![],
+            audios: vec![],
             messages: vec![IndexMap::from([
                 ("role".to_string(), Either::Left("user".to_string())),
                 (
@@ -5,7 +5,7 @@ use clap::Parser;
 use either::Either;
 use mistralrs::{
     cross_entropy_loss, parse_isq_value, Constraint, DType, Device, MistralRs, NormalRequest,
-    Request, ResponseOk, SamplingParams, Tensor, TextModelBuilder,
+    Request, RequestPriority, ResponseOk, SamplingParams, Tensor, TextModelBuilder,
 };
 use tokio::sync::mpsc::channel;
 
@@ -50,6 +50,10 @@ async fn process_chunk(runner: &MistralRs, chunk: Vec<u32>) -> anyhow::Result<(T
         tool_choice: None,
         logits_processors: None,
         return_raw_logits: true,
+        chat_template_kwargs: None,
+        continue_final_message: false,
+        conversation: None,
+        priority: RequestPriority::Normal,
     });
 
     runner.get_sender()?.send(request).await?;
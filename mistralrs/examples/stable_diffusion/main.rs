@@ -0,0 +1,44 @@
+use std::time::Instant;
+
+use anyhow::Result;
+use mistralrs::{
+    DiffusionGenerationParams, DiffusionLoaderType, DiffusionModelBuilder,
+    ImageGenerationResponseFormat,
+};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let model = DiffusionModelBuilder::new(
+        "stable-diffusion-v1-5/stable-diffusion-v1-5",
+        DiffusionLoaderType::StableDiffusion,
+    )
+    .with_logging()
+    .build()
+    .await?;
+
+    let start = Instant::now();
+
+    let response = model
+        .generate_image(
+            "A vibrant sunset in the mountains, 4k, high quality.".to_string(),
+            ImageGenerationResponseFormat::Url,
+            DiffusionGenerationParams {
+                height: 512,
+                width: 512,
+                num_steps: Some(25),
+                seed: Some(42),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+    let finished = Instant::now();
+
+    println!(
+        "Done! Took {} s. Image saved at: {}",
+        finished.duration_since(start).as_secs_f32(),
+        response.data[0].url.as_ref().unwrap()
+    );
+
+    Ok(())
+}
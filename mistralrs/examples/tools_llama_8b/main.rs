@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use indexmap::IndexMap;
 
 use anyhow::Result;
 use mistralrs::{
@@ -25,7 +25,7 @@ async fn main() -> Result<()> {
         .build()
         .await?;
 
-    let parameters: HashMap<String, Value> = serde_json::from_value(json!({
+    let parameters: IndexMap<String, Value> = serde_json::from_value(json!({
         "type": "object",
         "properties": {
             "place": {
@@ -0,0 +1,40 @@
+use std::time::Instant;
+
+use anyhow::Result;
+use mistralrs::{AudioInput, SpeechLoaderType, SpeechModelBuilder, TranscriptionParams};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let model = SpeechModelBuilder::new("openai/whisper-large-v3-turbo", SpeechLoaderType::Whisper)
+        .with_logging()
+        .build()
+        .await?;
+
+    let bytes = reqwest::get("https://github.com/ggerganov/whisper.cpp/raw/master/samples/jfk.wav")
+        .await?
+        .bytes()
+        .await?;
+    let audio = AudioInput::from_wav_bytes(&bytes)?;
+
+    let start = Instant::now();
+
+    let response = model
+        .transcribe(audio, TranscriptionParams::default())
+        .await?;
+
+    let finished = Instant::now();
+
+    for segment in &response.segments {
+        println!(
+            "[{:.2}s -> {:.2}s]{}",
+            segment.start, segment.end, segment.text
+        );
+    }
+    println!(
+        "Detected language: {}. Took {} s.",
+        response.language,
+        finished.duration_since(start).as_secs_f32(),
+    );
+
+    Ok(())
+}
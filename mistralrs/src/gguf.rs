@@ -24,6 +24,7 @@ pub struct GgufModelBuilder {
     pub(crate) paged_attn_cfg: Option<PagedAttentionConfig>,
     pub(crate) max_num_seqs: usize,
     pub(crate) no_kv_cache: bool,
+    pub(crate) kv_cache_type: KvCacheType,
     pub(crate) with_logging: bool,
     pub(crate) prefix_cache_n: Option<usize>,
 }
@@ -47,6 +48,7 @@ impl GgufModelBuilder {
             paged_attn_cfg: None,
             max_num_seqs: 32,
             no_kv_cache: false,
+            kv_cache_type: KvCacheType::Auto,
             prefix_cache_n: Some(16),
             with_logging: false,
             topology: None,
@@ -133,6 +135,13 @@ impl GgufModelBuilder {
         self
     }
 
+    /// Set the storage type of the KV cache. `f8e4m3` requires PagedAttention on CUDA, loading
+    /// fails otherwise.
+    pub fn with_kv_cache_type(mut self, kv_cache_type: KvCacheType) -> Self {
+        self.kv_cache_type = kv_cache_type;
+        self
+    }
+
     /// Set the number of sequences to hold in the prefix cache. Set to `None` to disable the prefix cacher.
     pub fn with_prefix_cache_n(mut self, n_seqs: Option<usize>) -> Self {
         self.prefix_cache_n = n_seqs;
@@ -168,6 +177,7 @@ impl GgufModelBuilder {
             self.files,
             config,
         )
+        .with_kv_cache_type(self.kv_cache_type)
         .build();
 
         // Load, into a Pipeline
@@ -8,6 +8,7 @@
 //! - [`GgufLoraModelBuilder`]
 //! - [`GgufXLoraModelBuilder`]
 //! - [`VisionModelBuilder`]
+//! - [`GgufVisionModelBuilder`]
 //! - [`AnyMoeModelBuilder`]
 //!
 //! Check out the [`v0_4_api`] module for concise documentation of this, newer API.
@@ -101,13 +102,16 @@
 
 mod anymoe;
 mod diffusion_model;
+mod embedding_model;
 mod gguf;
 mod gguf_lora_model;
+mod gguf_vision_model;
 mod gguf_xlora_model;
 mod lora_model;
 mod messages;
 mod model;
 mod speculative;
+mod speech_model;
 mod text_model;
 mod vision_model;
 mod xlora_model;
@@ -116,8 +120,10 @@ mod xlora_model;
 pub mod v0_4_api {
     pub use super::anymoe::AnyMoeModelBuilder;
     pub use super::diffusion_model::DiffusionModelBuilder;
+    pub use super::embedding_model::EmbeddingModelBuilder;
     pub use super::gguf::GgufModelBuilder;
     pub use super::gguf_lora_model::GgufLoraModelBuilder;
+    pub use super::gguf_vision_model::GgufVisionModelBuilder;
     pub use super::gguf_xlora_model::GgufXLoraModelBuilder;
     pub use super::lora_model::LoraModelBuilder;
     pub use super::messages::{
@@ -125,6 +131,7 @@ pub mod v0_4_api {
     };
     pub use super::model::{best_device, Model};
     pub use super::speculative::TextSpeculativeBuilder;
+    pub use super::speech_model::SpeechModelBuilder;
     pub use super::text_model::{PagedAttentionMetaBuilder, TextModelBuilder};
     pub use super::vision_model::VisionModelBuilder;
     pub use super::xlora_model::XLoraModelBuilder;
@@ -16,6 +16,15 @@ pub trait RequestLike {
     fn take_constraint(&mut self) -> Constraint;
     fn take_tools(&mut self) -> Option<(Vec<Tool>, ToolChoice)>;
     fn take_sampling_params(&mut self) -> SamplingParams;
+    /// Extra variables for the chat template. None by default.
+    fn take_chat_template_kwargs(&mut self) -> Option<HashMap<String, Value>> {
+        None
+    }
+    /// Whether to continue the final assistant message instead of starting a new one. False by
+    /// default.
+    fn continue_final_message(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -32,6 +41,12 @@ impl From<TextMessages> for Vec<IndexMap<String, MessageContent>> {
     }
 }
 
+impl From<TextMessages> for ClassificationInput {
+    fn from(value: TextMessages) -> Self {
+        Self::Conversation(value.0)
+    }
+}
+
 /// A chat message role.
 pub enum TextMessageRole {
     User,
@@ -108,7 +123,7 @@ impl RequestLike for TextMessages {
 }
 
 #[derive(Debug, Clone, PartialEq)]
-/// Text (chat) messages with images.
+/// Text (chat) messages with images, videos and audio.
 ///
 /// No constraints, logits processors, logprobs, tools, or adapters.
 ///
@@ -116,6 +131,8 @@ impl RequestLike for TextMessages {
 pub struct VisionMessages {
     messages: Vec<IndexMap<String, MessageContent>>,
     images: Vec<DynamicImage>,
+    videos: Vec<VideoInput>,
+    audios: Vec<AudioInput>,
 }
 
 impl Default for VisionMessages {
@@ -128,6 +145,8 @@ impl VisionMessages {
     pub fn new() -> Self {
         Self {
             images: Vec::new(),
+            videos: Vec::new(),
+            audios: Vec::new(),
             messages: Vec::new(),
         }
     }
@@ -233,7 +252,11 @@ impl VisionMessages {
         model: &Model,
     ) -> anyhow::Result<Self> {
         let prefixer = match &model.config().category {
-            ModelCategory::Text | ModelCategory::Diffusion => {
+            ModelCategory::Text
+            | ModelCategory::Diffusion
+            | ModelCategory::Speech
+            | ModelCategory::Embedding
+            | ModelCategory::Reranker => {
                 anyhow::bail!("`add_image_message` expects a vision model.")
             }
             ModelCategory::Vision {
@@ -263,9 +286,97 @@ impl VisionMessages {
         Ok(self)
     }
 
+    /// Add a message with a video. Frames are sampled according to [`VideoInput::sampling`].
+    pub fn add_video_message(
+        mut self,
+        role: TextMessageRole,
+        text: impl ToString,
+        video: VideoInput,
+        model: &Model,
+    ) -> anyhow::Result<Self> {
+        let prefixer = match &model.config().category {
+            ModelCategory::Text
+            | ModelCategory::Diffusion
+            | ModelCategory::Speech
+            | ModelCategory::Embedding
+            | ModelCategory::Reranker => {
+                anyhow::bail!("`add_video_message` expects a vision model.")
+            }
+            ModelCategory::Vision {
+                has_conv2d: _,
+                prefixer,
+            } => prefixer,
+        };
+        self.videos.push(video);
+        self.messages.push(IndexMap::from([
+            ("role".to_string(), Either::Left(role.to_string())),
+            (
+                "content".to_string(),
+                Either::Right(vec![
+                    IndexMap::from([("type".to_string(), Value::String("video".to_string()))]),
+                    IndexMap::from([
+                        ("type".to_string(), Value::String("text".to_string())),
+                        (
+                            "text".to_string(),
+                            Value::String(
+                                prefixer.prefix_video(self.videos.len() - 1, &text.to_string()),
+                            ),
+                        ),
+                    ]),
+                ]),
+            ),
+        ]));
+        Ok(self)
+    }
+
+    /// Add a message with an audio clip.
+    pub fn add_audio_message(
+        mut self,
+        role: TextMessageRole,
+        text: impl ToString,
+        audio: AudioInput,
+        model: &Model,
+    ) -> anyhow::Result<Self> {
+        let prefixer = match &model.config().category {
+            ModelCategory::Text
+            | ModelCategory::Diffusion
+            | ModelCategory::Speech
+            | ModelCategory::Embedding
+            | ModelCategory::Reranker => {
+                anyhow::bail!("`add_audio_message` expects a vision model.")
+            }
+            ModelCategory::Vision {
+                has_conv2d: _,
+                prefixer,
+            } => prefixer,
+        };
+        self.audios.push(audio);
+        self.messages.push(IndexMap::from([
+            ("role".to_string(), Either::Left(role.to_string())),
+            (
+                "content".to_string(),
+                Either::Right(vec![
+                    IndexMap::from([("type".to_string(), Value::String("audio".to_string()))]),
+                    IndexMap::from([
+                        ("type".to_string(), Value::String("text".to_string())),
+                        (
+                            "text".to_string(),
+                            Value::String(
+                                prefixer.prefix_audio(self.audios.len() - 1, &text.to_string()),
+                            ),
+                        ),
+                    ]),
+                ]),
+            ),
+        ]));
+        Ok(self)
+    }
+
     pub fn clear(mut self) -> Self {
         self.messages.clear();
         self.images.clear();
+        self.videos.clear();
+        self.audios.clear();
 
         self
     }
@@ -280,8 +391,14 @@ impl RequestLike for VisionMessages {
         std::mem::swap(&mut other_messages, &mut self.messages);
         let mut other_images = Vec::new();
         std::mem::swap(&mut other_images, &mut self.images);
+        let mut other_videos = Vec::new();
+        std::mem::swap(&mut other_videos, &mut self.videos);
+        let mut other_audios = Vec::new();
+        std::mem::swap(&mut other_audios, &mut self.audios);
         RequestMessage::VisionChat {
             images: other_images,
+            videos: other_videos,
+            audios: other_audios,
             messages: other_messages,
         }
     }
@@ -317,6 +434,8 @@ impl RequestLike for VisionMessages {
 pub struct RequestBuilder {
     messages: Vec<IndexMap<String, MessageContent>>,
     images: Vec<DynamicImage>,
+    videos: Vec<VideoInput>,
+    audios: Vec<AudioInput>,
     logits_processors: Vec<Arc<dyn CustomLogitsProcessor>>,
     adapters: Vec<String>,
     return_logprobs: bool,
@@ -324,6 +443,8 @@ pub struct RequestBuilder {
     tools: Vec<Tool>,
     tool_choice: ToolChoice,
     sampling_params: SamplingParams,
+    chat_template_kwargs: HashMap<String, Value>,
+    continue_final_message: bool,
 }
 
 impl Default for RequestBuilder {
@@ -337,6 +458,8 @@ impl From<TextMessages> for RequestBuilder {
         Self {
             messages: value.0,
             images: Vec::new(),
+            videos: Vec::new(),
+            audios: Vec::new(),
             logits_processors: Vec::new(),
             adapters: Vec::new(),
             return_logprobs: false,
@@ -344,6 +467,8 @@ impl From<TextMessages> for RequestBuilder {
             tools: Vec::new(),
             tool_choice: ToolChoice::Auto,
             sampling_params: SamplingParams::deterministic(),
+            chat_template_kwargs: HashMap::new(),
+            continue_final_message: false,
         }
     }
 }
@@ -353,6 +478,8 @@ impl From<VisionMessages> for RequestBuilder {
         Self {
             messages: value.messages,
             images: value.images,
+            videos: value.videos,
+            audios: value.audios,
             logits_processors: Vec::new(),
             adapters: Vec::new(),
             return_logprobs: false,
@@ -360,6 +487,8 @@ impl From<VisionMessages> for RequestBuilder {
             tools: Vec::new(),
             tool_choice: ToolChoice::Auto,
             sampling_params: SamplingParams::deterministic(),
+            chat_template_kwargs: HashMap::new(),
+            continue_final_message: false,
         }
     }
 }
@@ -369,6 +498,8 @@ impl RequestBuilder {
         Self {
             messages: Vec::new(),
             images: Vec::new(),
+            videos: Vec::new(),
+            audios: Vec::new(),
             logits_processors: Vec::new(),
             adapters: Vec::new(),
             return_logprobs: false,
@@ -376,6 +507,8 @@ impl RequestBuilder {
             tools: Vec::new(),
             tool_choice: ToolChoice::Auto,
             sampling_params: SamplingParams::deterministic(),
+            chat_template_kwargs: HashMap::new(),
+            continue_final_message: false,
         }
     }
 
@@ -454,6 +587,34 @@ impl RequestBuilder {
         self
     }
 
+    pub fn add_video_message(
+        mut self,
+        role: TextMessageRole,
+        text: impl ToString,
+        video: VideoInput,
+    ) -> Self {
+        self.messages.push(IndexMap::from([
+            ("role".to_string(), Either::Left(role.to_string())),
+            ("content".to_string(), Either::Left(text.to_string())),
+        ]));
+        self.videos.push(video);
+        self
+    }
+
+    pub fn add_audio_message(
+        mut self,
+        role: TextMessageRole,
+        text: impl ToString,
+        audio: AudioInput,
+    ) -> Self {
+        self.messages.push(IndexMap::from([
+            ("role".to_string(), Either::Left(role.to_string())),
+            ("content".to_string(), Either::Left(text.to_string())),
+        ]));
+        self.audios.push(audio);
+        self
+    }
+
     pub fn add_logits_processor(mut self, processor: Arc<dyn CustomLogitsProcessor>) -> Self {
         self.logits_processors.push(processor);
         self
@@ -560,6 +721,19 @@ impl RequestBuilder {
         self.sampling_params.dry_params = Some(dry_params);
         self
     }
+
+    /// Set an extra variable for the chat template, such as `enable_thinking`.
+    pub fn set_chat_template_kwarg(mut self, key: impl ToString, value: Value) -> Self {
+        self.chat_template_kwargs.insert(key.to_string(), value);
+        self
+    }
+
+    /// Continue the final assistant message instead of starting a new one. This can be used to
+    /// prefill the start of the response. Only the continuation is returned.
+    pub fn set_continue_final_message(mut self, continue_final_message: bool) -> Self {
+        self.continue_final_message = continue_final_message;
+        self
+    }
 }
 
 impl RequestLike for RequestBuilder {
@@ -568,7 +742,7 @@ impl RequestLike for RequestBuilder {
     }
 
     fn take_messages(&mut self) -> RequestMessage {
-        if self.images.is_empty() {
+        if self.images.is_empty() && self.videos.is_empty() && self.audios.is_empty() {
             let mut other = Vec::new();
             std::mem::swap(&mut other, &mut self.messages);
             RequestMessage::Chat(other)
@@ -577,8 +751,14 @@ impl RequestLike for RequestBuilder {
             std::mem::swap(&mut other_messages, &mut self.messages);
             let mut other_images = Vec::new();
             std::mem::swap(&mut other_images, &mut self.images);
+            let mut other_videos = Vec::new();
+            std::mem::swap(&mut other_videos, &mut self.videos);
+            let mut other_audios = Vec::new();
+            std::mem::swap(&mut other_audios, &mut self.audios);
             RequestMessage::VisionChat {
                 images: other_images,
+                videos: other_videos,
+                audios: other_audios,
                 messages: other_messages,
             }
         }
@@ -631,4 +811,18 @@ impl RequestLike for RequestBuilder {
         std::mem::swap(&mut other, &mut self.sampling_params);
         other
     }
+
+    fn take_chat_template_kwargs(&mut self) -> Option<HashMap<String, Value>> {
+        if self.chat_template_kwargs.is_empty() {
+            None
+        } else {
+            let mut other = HashMap::new();
+            std::mem::swap(&mut other, &mut self.chat_template_kwargs);
+            Some(other)
+        }
+    }
+
+    fn continue_final_message(&self) -> bool {
+        self.continue_final_message
+    }
 }
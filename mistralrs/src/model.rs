@@ -89,6 +89,10 @@ impl Model {
             tool_choice,
             logits_processors: request.take_logits_processors(),
             return_raw_logits: false,
+            chat_template_kwargs: request.take_chat_template_kwargs(),
+            continue_final_message: request.continue_final_message(),
+            conversation: None,
+            priority: RequestPriority::Normal,
         });
 
         self.runner.get_sender()?.send(request).await?;
@@ -124,6 +128,10 @@ impl Model {
             tool_choice,
             logits_processors: request.take_logits_processors(),
             return_raw_logits: false,
+            chat_template_kwargs: request.take_chat_template_kwargs(),
+            continue_final_message: request.continue_final_message(),
+            conversation: None,
+            priority: RequestPriority::Normal,
         });
 
         self.runner.get_sender()?.send(request).await?;
@@ -168,6 +176,10 @@ impl Model {
             tool_choice,
             logits_processors: request.take_logits_processors(),
             return_raw_logits: true,
+            chat_template_kwargs: request.take_chat_template_kwargs(),
+            continue_final_message: request.continue_final_message(),
+            conversation: None,
+            priority: RequestPriority::Normal,
         });
 
         self.runner.get_sender()?.send(request).await?;
@@ -192,6 +204,18 @@ impl Model {
         prompt: impl ToString,
         response_format: ImageGenerationResponseFormat,
         generation_params: DiffusionGenerationParams,
+    ) -> anyhow::Result<ImageGenerationResponse> {
+        self.generate_images(prompt, response_format, generation_params, 1)
+            .await
+    }
+
+    /// Generate `n` images from one prompt. With a seed, the images use consecutive seeds.
+    pub async fn generate_images(
+        &self,
+        prompt: impl ToString,
+        response_format: ImageGenerationResponseFormat,
+        generation_params: DiffusionGenerationParams,
+        n: usize,
     ) -> anyhow::Result<ImageGenerationResponse> {
         let (tx, mut rx) = channel(1);
 
@@ -202,7 +226,10 @@ impl Model {
                 format: response_format,
                 generation_params,
             },
-            sampling_params: SamplingParams::deterministic(),
+            sampling_params: SamplingParams {
+                n_choices: n,
+                ..SamplingParams::deterministic()
+            },
             response: tx,
             return_logprobs: false,
             is_streaming: false,
@@ -213,6 +240,10 @@ impl Model {
             tools: None,
             logits_processors: None,
             return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
         });
 
         self.runner.get_sender()?.send(request).await?;
@@ -229,6 +260,180 @@ impl Model {
         Ok(response)
     }
 
+    /// Transcribe (or translate to English) the speech in an audio clip.
+    pub async fn transcribe(
+        &self,
+        audio: AudioInput,
+        params: TranscriptionParams,
+    ) -> anyhow::Result<TranscriptionResponse> {
+        let (tx, mut rx) = channel(1);
+
+        let request = Request::Normal(NormalRequest {
+            id: 0,
+            messages: RequestMessage::Transcription { audio, params },
+            sampling_params: SamplingParams::deterministic(),
+            response: tx,
+            return_logprobs: false,
+            is_streaming: false,
+            suffix: None,
+            constraint: Constraint::None,
+            adapters: None,
+            tool_choice: None,
+            tools: None,
+            logits_processors: None,
+            return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
+        });
+
+        self.runner.get_sender()?.send(request).await?;
+
+        let ResponseOk::Transcription(response) = rx
+            .recv()
+            .await
+            .context("Channel was erroneously closed!")?
+            .as_result()?
+        else {
+            anyhow::bail!("Got unexpected response type.")
+        };
+
+        Ok(response)
+    }
+
+    /// Embed a batch of texts with an embedding model.
+    pub async fn embed(
+        &self,
+        inputs: Vec<String>,
+        params: EmbeddingParams,
+    ) -> anyhow::Result<EmbeddingResponse> {
+        let (tx, mut rx) = channel(1);
+
+        let request = Request::Normal(NormalRequest {
+            id: 0,
+            messages: RequestMessage::Embedding { inputs, params },
+            sampling_params: SamplingParams::deterministic(),
+            response: tx,
+            return_logprobs: false,
+            is_streaming: false,
+            suffix: None,
+            constraint: Constraint::None,
+            adapters: None,
+            tool_choice: None,
+            tools: None,
+            logits_processors: None,
+            return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
+        });
+
+        self.runner.get_sender()?.send(request).await?;
+
+        let ResponseOk::Embedding(response) = rx
+            .recv()
+            .await
+            .context("Channel was erroneously closed!")?
+            .as_result()?
+        else {
+            anyhow::bail!("Got unexpected response type.")
+        };
+
+        Ok(response)
+    }
+
+    /// Score the relevance of each document to the query with a reranker model.
+    pub async fn rerank(
+        &self,
+        query: impl ToString,
+        documents: Vec<String>,
+        params: RerankParams,
+    ) -> anyhow::Result<RerankResponse> {
+        let (tx, mut rx) = channel(1);
+
+        let request = Request::Normal(NormalRequest {
+            id: 0,
+            messages: RequestMessage::Rerank {
+                query: query.to_string(),
+                documents,
+                params,
+            },
+            sampling_params: SamplingParams::deterministic(),
+            response: tx,
+            return_logprobs: false,
+            is_streaming: false,
+            suffix: None,
+            constraint: Constraint::None,
+            adapters: None,
+            tool_choice: None,
+            tools: None,
+            logits_processors: None,
+            return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
+        });
+
+        self.runner.get_sender()?.send(request).await?;
+
+        let ResponseOk::Rerank(response) = rx
+            .recv()
+            .await
+            .context("Channel was erroneously closed!")?
+            .as_result()?
+        else {
+            anyhow::bail!("Got unexpected response type.")
+        };
+
+        Ok(response)
+    }
+
+    /// Classify a batch of texts or conversations with a sequence classification model, or score
+    /// them with a reward model. Conversations ([`TextMessages`] convert into them) are formatted
+    /// with the chat template of the model.
+    pub async fn classify(
+        &self,
+        inputs: Vec<ClassificationInput>,
+    ) -> anyhow::Result<ClassificationResponse> {
+        let (tx, mut rx) = channel(1);
+
+        let request = Request::Normal(NormalRequest {
+            id: 0,
+            messages: RequestMessage::Classification { inputs },
+            sampling_params: SamplingParams::deterministic(),
+            response: tx,
+            return_logprobs: false,
+            is_streaming: false,
+            suffix: None,
+            constraint: Constraint::None,
+            adapters: None,
+            tool_choice: None,
+            tools: None,
+            logits_processors: None,
+            return_raw_logits: false,
+            chat_template_kwargs: None,
+            continue_final_message: false,
+            conversation: None,
+            priority: RequestPriority::Normal,
+        });
+
+        self.runner.get_sender()?.send(request).await?;
+
+        let ResponseOk::Classification(response) = rx
+            .recv()
+            .await
+            .context("Channel was erroneously closed!")?
+            .as_result()?
+        else {
+            anyhow::bail!("Got unexpected response type.")
+        };
+
+        Ok(response)
+    }
+
     /// Activate certain adapters on the model, they will be used for requests which do not specify unique adapters.
     pub async fn activate_adapters<A: ToString>(&self, adapters: Vec<A>) -> anyhow::Result<()> {
         let request = Request::ActivateAdapters(
@@ -261,6 +466,7 @@ impl Model {
         let request = Request::Tokenize(TokenizationRequest {
             text: text.map_left(Into::into),
             tools,
+            chat_template_kwargs: None,
             add_special_tokens,
             add_generation_prompt,
             response: tx,
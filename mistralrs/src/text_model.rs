@@ -14,6 +14,8 @@ pub struct TextModelBuilder {
     pub(crate) from_uqff: Option<PathBuf>,
     pub(crate) imatrix: Option<PathBuf>,
     pub(crate) calibration_file: Option<PathBuf>,
+    pub(crate) self_contained_uqff: bool,
+    pub(crate) topology_search: Option<TopologySearch>,
     pub(crate) chat_template: Option<String>,
     pub(crate) tokenizer_json: Option<String>,
     pub(crate) device_mapping: Option<DeviceMapSetting>,
@@ -32,6 +34,7 @@ pub struct TextModelBuilder {
     pub(crate) paged_attn_cfg: Option<PagedAttentionConfig>,
     pub(crate) max_num_seqs: usize,
     pub(crate) no_kv_cache: bool,
+    pub(crate) kv_cache_type: KvCacheType,
     pub(crate) with_logging: bool,
     pub(crate) prefix_cache_n: Option<usize>,
 }
@@ -96,11 +99,14 @@ impl TextModelBuilder {
             paged_attn_cfg: None,
             max_num_seqs: 32,
             no_kv_cache: false,
+            kv_cache_type: KvCacheType::Auto,
             prefix_cache_n: Some(16),
             with_logging: false,
             device_mapping: None,
             imatrix: None,
             calibration_file: None,
+            topology_search: None,
+            self_contained_uqff: false,
         }
     }
 
@@ -183,6 +189,13 @@ impl TextModelBuilder {
         self
     }
 
+    /// Search for a per-layer ISQ topology which fits in a memory budget, using the calibration
+    /// file to measure the sensitivity of each layer. The topology is written out and applied.
+    pub fn with_topology_search(mut self, topology_search: TopologySearch) -> Self {
+        self.topology_search = Some(topology_search);
+        self
+    }
+
     /// Enable PagedAttention. Configure PagedAttention with a [`PagedAttentionConfig`] object, which
     /// can be created with sensible values with a [`PagedAttentionMetaBuilder`].
     ///
@@ -211,6 +224,13 @@ impl TextModelBuilder {
         self
     }
 
+    /// Set the storage type of the KV cache. `f8e4m3` requires PagedAttention on CUDA, loading
+    /// fails otherwise.
+    pub fn with_kv_cache_type(mut self, kv_cache_type: KvCacheType) -> Self {
+        self.kv_cache_type = kv_cache_type;
+        self
+    }
+
     /// Set the number of sequences to hold in the prefix cache. Set to `None` to disable the prefix cacher.
     pub fn with_prefix_cache_n(mut self, n_seqs: Option<usize>) -> Self {
         self.prefix_cache_n = n_seqs;
@@ -243,11 +263,21 @@ impl TextModelBuilder {
     /// - `tokenizer.json`
     /// - `config.json`
     /// - And others
+    ///
+    /// If the extension is `.gguf`, a single llama.cpp compatible GGUF file is written instead.
     pub fn write_uqff(mut self, path: PathBuf) -> Self {
         self.write_uqff = Some(path);
         self
     }
 
+    /// Embed the residual tensors, config and tokenizer in the UQFF file written with
+    /// [`Self::write_uqff`] instead of writing them next to it. The UQFF file can then be loaded
+    /// offline from its path alone with [`Self::from_uqff`].
+    pub fn with_self_contained_uqff(mut self) -> Self {
+        self.self_contained_uqff = true;
+        self
+    }
+
     pub async fn build(self) -> anyhow::Result<Model> {
         let config = NormalSpecificConfig {
             use_flash_attn: self.use_flash_attn,
@@ -271,6 +301,9 @@ impl TextModelBuilder {
             Some(self.model_id),
         )
         .with_no_kv_cache(self.no_kv_cache)
+        .with_kv_cache_type(self.kv_cache_type)
+        .with_self_contained_uqff(self.self_contained_uqff)
+        .with_topology_search(self.topology_search)
         .build(self.loader_type)?;
 
         // Load, into a Pipeline
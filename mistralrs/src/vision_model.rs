@@ -13,6 +13,7 @@ pub struct VisionModelBuilder {
     pub(crate) write_uqff: Option<PathBuf>,
     pub(crate) from_uqff: Option<PathBuf>,
     pub(crate) calibration_file: Option<PathBuf>,
+    pub(crate) self_contained_uqff: bool,
     pub(crate) imatrix: Option<PathBuf>,
     pub(crate) chat_template: Option<String>,
     pub(crate) tokenizer_json: Option<String>,
@@ -30,6 +31,7 @@ pub struct VisionModelBuilder {
 
     // Other things
     pub(crate) max_num_seqs: usize,
+    pub(crate) kv_cache_type: KvCacheType,
     pub(crate) with_logging: bool,
 }
 
@@ -56,9 +58,11 @@ impl VisionModelBuilder {
             hf_revision: None,
             isq: None,
             max_num_seqs: 32,
+            kv_cache_type: KvCacheType::Auto,
             with_logging: false,
             device_mapping: None,
             calibration_file: None,
+            self_contained_uqff: false,
             imatrix: None,
         }
     }
@@ -129,6 +133,13 @@ impl VisionModelBuilder {
         self
     }
 
+    /// Set the storage type of the KV cache. `f8e4m3` requires PagedAttention on CUDA, loading
+    /// fails otherwise.
+    pub fn with_kv_cache_type(mut self, kv_cache_type: KvCacheType) -> Self {
+        self.kv_cache_type = kv_cache_type;
+        self
+    }
+
     /// Enable logging.
     pub fn with_logging(mut self) -> Self {
         self.with_logging = true;
@@ -167,6 +178,14 @@ impl VisionModelBuilder {
         self
     }
 
+    /// Embed the residual tensors, config and tokenizer in the UQFF file written with
+    /// [`Self::write_uqff`] instead of writing them next to it. The UQFF file can then be loaded
+    /// offline from its path alone with [`Self::from_uqff`].
+    pub fn with_self_contained_uqff(mut self) -> Self {
+        self.self_contained_uqff = true;
+        self
+    }
+
     pub async fn build(self) -> anyhow::Result<Model> {
         let config = VisionSpecificConfig {
             use_flash_attn: self.use_flash_attn,
@@ -189,6 +208,8 @@ impl VisionModelBuilder {
             self.tokenizer_json,
             Some(self.model_id),
         )
+        .with_kv_cache_type(self.kv_cache_type)
+        .with_self_contained_uqff(self.self_contained_uqff)
         .build(self.loader_type);
 
         // Load, into a Pipeline